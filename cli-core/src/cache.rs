@@ -0,0 +1,950 @@
+use std::cmp;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroU32;
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use ahash::AHashMap as HashMap;
+use ahash::AHashSet as HashSet;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nwind::BinaryData;
+use nwind::proc_maps::Region;
+use string_interner::Symbol;
+
+use crate::data::{
+    Allocation,
+    AllocationChain,
+    AllocationFlags,
+    AllocationId,
+    BacktraceId,
+    CodePointer,
+    Data,
+    DataId,
+    Deallocation,
+    GroupStatistics,
+    Mallopt,
+    MalloptKind,
+    MapFlags,
+    Marker,
+    MemoryMap,
+    MemoryProtect,
+    MemoryRemap,
+    MemoryUnmap,
+    MmapOperation,
+    Module,
+    OperationId,
+    ProtectionFlags,
+    RemapFlags,
+    StringId,
+    StringInterner,
+    Timestamp
+};
+use crate::frame::Frame;
+use crate::loader::SymbolicationContext;
+use crate::vecvec::DenseVecVec;
+use crate::mapped_vec::{MappedVec, MappedVecMut};
+
+const MAGIC: &[u8; 8] = b"BHINDEX\0";
+
+/// Has to be bumped every time the format of the cache changes, or when the loader starts producing different data.
+const CACHE_VERSION: u32 = 6;
+
+/// How much of the beginning and of the end of the data file is hashed to detect whether it has changed.
+const FINGERPRINT_LENGTH: u64 = 1024 * 1024;
+
+/// The subdirectory of the cache directory in which the binaries are kept; every binary is only stored
+/// once, no matter how many data files were profiled with it.
+const BINARIES_DIR: &str = "binaries";
+
+/// The 64-bit FNV-1a hash.
+///
+/// Unlike the `DefaultHasher` this is guaranteed to always give the same results,
+/// so the caches written by one build can still be found by another.
+struct Fnv( u64 );
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv( 0xcbf29ce484222325 )
+    }
+}
+
+impl Hasher for Fnv {
+    fn write( &mut self, bytes: &[u8] ) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul( 0x100000001b3 );
+        }
+    }
+
+    fn finish( &self ) -> u64 {
+        self.0
+    }
+}
+
+fn hash_bytes( bytes: &[u8] ) -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.write( bytes );
+    hasher.finish()
+}
+
+/// Hashes the path, the size and the modification time of the given debug symbols,
+/// or of every file within them if they're a directory, so that the cache is
+/// thrown away when any of them is rebuilt.
+fn hash_debug_symbols( hasher: &mut Fnv, path: &Path ) {
+    hasher.write_u8( 0 );
+    hasher.write( path.as_os_str().as_bytes() );
+
+    let metadata = match fs::metadata( path ) {
+        Ok( metadata ) => metadata,
+        Err( _ ) => return
+    };
+
+    if metadata.is_dir() {
+        let mut entries: Vec< _ > = match fs::read_dir( path ) {
+            Ok( entries ) => entries.filter_map( |entry| entry.ok() ).map( |entry| entry.path() ).collect(),
+            Err( _ ) => return
+        };
+
+        entries.sort();
+        for entry in entries {
+            // Don't follow the symlinks to directories, in case they'd loop back.
+            let is_symlink = fs::symlink_metadata( &entry ).map( |metadata| metadata.file_type().is_symlink() ).unwrap_or( false );
+            if is_symlink && entry.is_dir() {
+                continue;
+            }
+
+            hash_debug_symbols( hasher, &entry );
+        }
+    } else {
+        let modified = metadata.modified().ok().and_then( |modified| modified.duration_since( UNIX_EPOCH ).ok() ).unwrap_or_default();
+        hasher.write_u64( metadata.len() );
+        hasher.write_u64( modified.as_secs() );
+        hasher.write_u32( modified.subsec_nanos() );
+    }
+}
+
+/// Identifies the exact data file from which the cache was built.
+#[derive(PartialEq, Debug)]
+struct CacheKey {
+    size: u64,
+    modified_secs: u64,
+    modified_nsecs: u32,
+    fingerprint: u64
+}
+
+impl CacheKey {
+    fn new( path: &Path, debug_symbols: &[PathBuf] ) -> io::Result< Self > {
+        let mut fp = File::open( path )?;
+        let metadata = fp.metadata()?;
+        let modified = metadata.modified()?.duration_since( UNIX_EPOCH ).unwrap_or_default();
+        let size = metadata.len();
+
+        let mut hasher = Fnv::new();
+        let mut buffer = Vec::new();
+        (&mut fp).take( FINGERPRINT_LENGTH ).read_to_end( &mut buffer )?;
+        fp.seek( SeekFrom::Start( size.saturating_sub( FINGERPRINT_LENGTH ) ) )?;
+        fp.take( FINGERPRINT_LENGTH ).read_to_end( &mut buffer )?;
+        hasher.write( &buffer );
+
+        // The debug symbols affect how the data is symbolicated.
+        for path in debug_symbols {
+            hash_debug_symbols( &mut hasher, path );
+        }
+
+        Ok( CacheKey {
+            size,
+            modified_secs: modified.as_secs(),
+            modified_nsecs: modified.subsec_nanos(),
+            fingerprint: hasher.finish()
+        })
+    }
+}
+
+trait Encode {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () >;
+}
+
+trait Decode: Sized {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self >;
+}
+
+fn invalid_data( message: &str ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidData, message )
+}
+
+macro_rules! impl_for_primitive {
+    ($type:ty, $write:ident, $read:ident) => {
+        impl Encode for $type {
+            fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+                fp.$write::< LittleEndian >( *self )
+            }
+        }
+
+        impl Decode for $type {
+            fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+                fp.$read::< LittleEndian >()
+            }
+        }
+    }
+}
+
+impl_for_primitive!( u32, write_u32, read_u32 );
+impl_for_primitive!( u64, write_u64, read_u64 );
+impl_for_primitive!( i32, write_i32, read_i32 );
+
+/// Implements the encoding for a struct by encoding each of the given fields in order.
+macro_rules! impl_for_struct {
+    ($type:ident { $($field:ident),+ }) => {
+        impl Encode for $type {
+            fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+                $( self.$field.encode( fp )?; )+
+                Ok(())
+            }
+        }
+
+        impl Decode for $type {
+            fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+                Ok( $type {
+                    $( $field: Decode::decode( fp )?, )+
+                })
+            }
+        }
+    }
+}
+
+/// Implements the encoding for a type which is losslessly convertible to and from a primitive.
+macro_rules! impl_through {
+    ($type:ty, $primitive:ty, |$encode_value:ident| $encode:expr, |$decode_value:ident| $decode:expr) => {
+        impl Encode for $type {
+            fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+                let $encode_value = self;
+                let value: $primitive = $encode;
+                value.encode( fp )
+            }
+        }
+
+        impl Decode for $type {
+            fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+                let $decode_value: $primitive = Decode::decode( fp )?;
+                $decode
+            }
+        }
+    }
+}
+
+impl_through!( bool, u32, |value| *value as u32, |value| Ok( value != 0 ) );
+impl_through!( usize, u64, |value| *value as u64, |value| Ok( value as usize ) );
+impl_through!( Timestamp, u64, |value| value.as_usecs(), |value| Ok( Timestamp::from_usecs( value ) ) );
+impl_through!( AllocationId, u64, |value| value.raw(), |value| Ok( AllocationId::new( value ) ) );
+impl_through!( BacktraceId, u32, |value| value.raw(), |value| Ok( BacktraceId::new( value ) ) );
+impl_through!( CodePointer, u64, |value| value.raw(), |value| Ok( CodePointer::new( value ) ) );
+impl_through!( OperationId, u64, |value| value.raw(), |value| Ok( OperationId::from_raw( value ) ) );
+impl_through!( StringId, u64, |value| value.to_usize() as u64, |value| Ok( StringId::from_usize( value as usize ) ) );
+impl_through!( NonZeroU32, u32, |value| value.get(), |value| NonZeroU32::new( value ).ok_or_else( || invalid_data( "unexpected zero" ) ) );
+impl_through!( MalloptKind, i32, |value| value.raw(), |value| Ok( value.into() ) );
+impl_through!( ProtectionFlags, u32, |value| value.0, |value| Ok( ProtectionFlags( value ) ) );
+impl_through!( MapFlags, u32, |value| value.0, |value| Ok( MapFlags( value ) ) );
+impl_through!( RemapFlags, u32, |value| value.0, |value| Ok( RemapFlags( value ) ) );
+impl_through!( AllocationFlags, u32, |value| value.bits() as u32, |value| Ok( AllocationFlags::from_bits_truncate( value as u8 ) ) );
+impl_through!( DataId, String, |value| format!( "{}", value ), |value| value.parse().map_err( |_| invalid_data( "invalid data ID" ) ) );
+
+impl Encode for [u8] {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        (self.len() as u64).encode( fp )?;
+        fp.write_all( self )
+    }
+}
+
+impl Encode for String {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        self.as_bytes().encode( fp )
+    }
+}
+
+fn decode_bytes< R: Read >( fp: &mut R ) -> io::Result< Vec< u8 > > {
+    let length = u64::decode( fp )?;
+    let mut bytes = Vec::new();
+    fp.take( length ).read_to_end( &mut bytes )?;
+    if bytes.len() as u64 != length {
+        return Err( io::ErrorKind::UnexpectedEof.into() );
+    }
+
+    Ok( bytes )
+}
+
+impl Decode for String {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        String::from_utf8( decode_bytes( fp )? ).map_err( |_| invalid_data( "invalid string" ) )
+    }
+}
+
+impl< T: Encode > Encode for Vec< T > {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        (self.len() as u64).encode( fp )?;
+        for item in self {
+            item.encode( fp )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl< T: Decode > Decode for Vec< T > {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        let length = u64::decode( fp )? as usize;
+
+        // The length could be garbage, so don't trust it too much.
+        let mut output = Vec::with_capacity( cmp::min( length, 1024 * 1024 ) );
+        for _ in 0..length {
+            output.push( T::decode( fp )? );
+        }
+
+        Ok( output )
+    }
+}
+
+// Encoded just like a `Vec`.
+impl< T: Encode > Encode for MappedVec< T > {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        (self.len() as u64).encode( fp )?;
+        for item in self.iter() {
+            item.encode( fp )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl< T: Decode > Decode for MappedVec< T > {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        Vec::decode( fp ).map( MappedVec::from )
+    }
+}
+
+impl< T: Encode > Encode for Option< T > {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        match *self {
+            Some( ref value ) => {
+                true.encode( fp )?;
+                value.encode( fp )
+            },
+            None => false.encode( fp )
+        }
+    }
+}
+
+impl< T: Decode > Decode for Option< T > {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        if bool::decode( fp )? {
+            Ok( Some( T::decode( fp )? ) )
+        } else {
+            Ok( None )
+        }
+    }
+}
+
+impl< A: Encode, B: Encode > Encode for (A, B) {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        self.0.encode( fp )?;
+        self.1.encode( fp )
+    }
+}
+
+impl< A: Decode, B: Decode > Decode for (A, B) {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        Ok( (A::decode( fp )?, B::decode( fp )?) )
+    }
+}
+
+impl_through!( Range< u64 >, (u64, u64), |value| (value.start, value.end), |value| Ok( value.0..value.1 ) );
+
+impl_for_struct!( CacheKey { size, modified_secs, modified_nsecs, fingerprint } );
+impl_for_struct!( Allocation {
+    pointer, timestamp, thread, size, backtrace, deallocation, reallocation, reallocated_from,
+    first_allocation_in_chain, position_in_chain, flags, extra_usable_space, marker, preceding_free_space
+});
+impl_for_struct!( Deallocation { timestamp, thread, backtrace } );
+impl_for_struct!( AllocationChain { first, last, length } );
+impl_for_struct!( GroupStatistics {
+    first_allocation, last_allocation, alloc_count, alloc_size, free_count, free_size, min_size, max_size, max_total_usage_first_seen_at
+});
+impl_for_struct!( Mallopt { timestamp, backtrace, thread, kind, value, result } );
+impl_for_struct!( Marker { timestamp, backtrace, thread, value } );
+impl_for_struct!( MemoryMap {
+    timestamp, pointer, length, backtrace, requested_address, mmap_protection, mmap_flags, file_descriptor, thread, offset, file_path
+});
+impl_for_struct!( MemoryUnmap { timestamp, pointer, length, backtrace, thread } );
+impl_for_struct!( MemoryRemap { timestamp, old_pointer, old_length, new_pointer, new_length, backtrace, remap_flags, thread } );
+impl_for_struct!( MemoryProtect { timestamp, pointer, length, backtrace, protection, thread } );
+impl_for_struct!( Module { path, address_range, build_id } );
+impl_for_struct!( Region { start, end, is_read, is_write, is_executable, is_shared, file_offset, major, minor, inode, name } );
+
+impl Encode for MmapOperation {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        match *self {
+            MmapOperation::Mmap( ref map ) => {
+                0_u32.encode( fp )?;
+                map.encode( fp )
+            },
+            MmapOperation::Munmap( ref unmap ) => {
+                1_u32.encode( fp )?;
+                unmap.encode( fp )
+            },
+            MmapOperation::Mremap( ref remap ) => {
+                2_u32.encode( fp )?;
+                remap.encode( fp )
+            },
+            MmapOperation::Mprotect( ref protect ) => {
+                3_u32.encode( fp )?;
+                protect.encode( fp )
+            }
+        }
+    }
+}
+
+impl Decode for MmapOperation {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        match u32::decode( fp )? {
+            0 => Ok( MmapOperation::Mmap( Decode::decode( fp )? ) ),
+            1 => Ok( MmapOperation::Munmap( Decode::decode( fp )? ) ),
+            2 => Ok( MmapOperation::Mremap( Decode::decode( fp )? ) ),
+            3 => Ok( MmapOperation::Mprotect( Decode::decode( fp )? ) ),
+            _ => Err( invalid_data( "invalid memory map operation" ) )
+        }
+    }
+}
+
+impl Encode for Frame {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        self.address().encode( fp )?;
+        self.count().encode( fp )?;
+        self.is_inline().encode( fp )?;
+        self.library().encode( fp )?;
+        self.function().encode( fp )?;
+        self.raw_function().encode( fp )?;
+        self.source().encode( fp )?;
+        self.line().encode( fp )?;
+        self.column().encode( fp )
+    }
+}
+
+impl Decode for Frame {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        let mut frame = Frame::new_unknown( Decode::decode( fp )? );
+        frame.increment_count( Decode::decode( fp )? );
+        frame.set_is_inline( Decode::decode( fp )? );
+        if let Some( library ) = Decode::decode( fp )? {
+            frame.set_library( library );
+        }
+        if let Some( function ) = Decode::decode( fp )? {
+            frame.set_function( function );
+        }
+        if let Some( raw_function ) = Decode::decode( fp )? {
+            frame.set_raw_function( raw_function );
+        }
+        if let Some( source ) = Decode::decode( fp )? {
+            frame.set_source( source );
+        }
+        if let Some( line ) = Decode::decode( fp )? {
+            frame.set_line( line );
+        }
+        if let Some( column ) = Decode::decode( fp )? {
+            frame.set_column( column );
+        }
+
+        Ok( frame )
+    }
+}
+
+impl Encode for StringInterner {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        (self.len() as u64).encode( fp )?;
+        for (_, string) in self.iter() {
+            string.as_bytes().encode( fp )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Decode for StringInterner {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        // The strings are interned in the same order, so they get the same IDs.
+        let strings: Vec< String > = Decode::decode( fp )?;
+        let mut interner = StringInterner::new();
+        for string in strings {
+            interner.get_or_intern( string );
+        }
+
+        Ok( interner )
+    }
+}
+
+impl< T: Encode > Encode for DenseVecVec< T > {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        (self.len() as u64).encode( fp )?;
+        for index in 0..self.len() {
+            (self.get( index ).len() as u64).encode( fp )?;
+            for item in self.get( index ) {
+                item.encode( fp )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl< T: Decode > Decode for DenseVecVec< T > {
+    fn decode< R: Read >( fp: &mut R ) -> io::Result< Self > {
+        let mut output = DenseVecVec::new();
+        for _ in 0..u64::decode( fp )? {
+            let items: Vec< T > = Decode::decode( fp )?;
+            output.push( items );
+        }
+
+        Ok( output )
+    }
+}
+
+impl Encode for Data {
+    fn encode< W: Write >( &self, fp: &mut W ) -> io::Result< () > {
+        self.id.encode( fp )?;
+        self.initial_timestamp.encode( fp )?;
+        self.last_timestamp.encode( fp )?;
+        self.executable.encode( fp )?;
+        self.architecture.encode( fp )?;
+        self.pid.encode( fp )?;
+        self.cmdline.encode( fp )?;
+        self.has_wall_clock.encode( fp )?;
+        self.pointer_size.encode( fp )?;
+        self.interner.encode( fp )?;
+        self.operations.encode( fp )?;
+        self.allocations.encode( fp )?;
+        self.sorted_by_timestamp.encode( fp )?;
+        self.sorted_by_address.encode( fp )?;
+        self.sorted_by_size.encode( fp )?;
+        self.frames.encode( fp )?;
+        self.backtraces.encode( fp )?;
+        self.backtraces_storage.encode( fp )?;
+        self.allocations_by_backtrace.encode( fp )?;
+        self.total_allocated.encode( fp )?;
+        self.total_allocated_count.encode( fp )?;
+        self.total_freed.encode( fp )?;
+        self.total_freed_count.encode( fp )?;
+        self.mallopts.encode( fp )?;
+        self.markers.encode( fp )?;
+        self.mmap_operations.encode( fp )?;
+        self.maximum_backtrace_depth.encode( fp )?;
+        self.group_stats.encode( fp )?;
+
+        let mut chains: Vec< _ > = self.chains.iter().map( |(&id, chain)| (id, chain.clone()) ).collect();
+        chains.sort_by_key( |&(id, _)| id );
+        chains.encode( fp )?;
+
+        // The binaries are stored separately; see `write_cache`.
+        self.symbolication.regions.encode( fp )?;
+        self.modules.encode( fp )?;
+        self.sources.encode( fp )
+    }
+}
+
+/// Decodes a `MappedVec` straight into a memory mapped file in the given directory, if any,
+/// so that it never has to be fully in memory.
+fn decode_mapped_vec< T: Decode + Copy, R: Read >( fp: &mut R, directory: Option< &Path > ) -> io::Result< MappedVec< T > > {
+    let directory = match directory {
+        Some( directory ) => directory,
+        None => return Decode::decode( fp )
+    };
+
+    let mut output = MappedVecMut::new_on_disk( directory )?;
+    for _ in 0..u64::decode( fp )? {
+        output.push( T::decode( fp )? );
+    }
+
+    Ok( output.into() )
+}
+
+impl Data {
+    /// Decodes the data, keeping the allocations in a memory mapped file in `allocations_directory` if it's given.
+    fn decode< R: Read >( fp: &mut R, allocations_directory: Option< &Path > ) -> io::Result< Self > {
+        Ok( Data {
+            id: Decode::decode( fp )?,
+            initial_timestamp: Decode::decode( fp )?,
+            last_timestamp: Decode::decode( fp )?,
+            executable: Decode::decode( fp )?,
+            architecture: Decode::decode( fp )?,
+            pid: Decode::decode( fp )?,
+            cmdline: Decode::decode( fp )?,
+            has_wall_clock: Decode::decode( fp )?,
+            pointer_size: Decode::decode( fp )?,
+            interner: Decode::decode( fp )?,
+            operations: Decode::decode( fp )?,
+            allocations: decode_mapped_vec( fp, allocations_directory )?,
+            sorted_by_timestamp: Decode::decode( fp )?,
+            sorted_by_address: Decode::decode( fp )?,
+            sorted_by_size: Decode::decode( fp )?,
+            frames: Decode::decode( fp )?,
+            backtraces: Decode::decode( fp )?,
+            backtraces_storage: Decode::decode( fp )?,
+            allocations_by_backtrace: Decode::decode( fp )?,
+            total_allocated: Decode::decode( fp )?,
+            total_allocated_count: Decode::decode( fp )?,
+            total_freed: Decode::decode( fp )?,
+            total_freed_count: Decode::decode( fp )?,
+            mallopts: Decode::decode( fp )?,
+            markers: Decode::decode( fp )?,
+            mmap_operations: Decode::decode( fp )?,
+            maximum_backtrace_depth: Decode::decode( fp )?,
+            group_stats: Decode::decode( fp )?,
+            chains: {
+                let chains: Vec< (AllocationId, AllocationChain) > = Decode::decode( fp )?;
+                chains.into_iter().collect()
+            },
+            symbolication: SymbolicationContext {
+                regions: Decode::decode( fp )?,
+                binaries: HashMap::new()
+            },
+            modules: Decode::decode( fp )?,
+            sources: Decode::decode( fp )?
+        })
+    }
+}
+
+fn cache_path( cache_dir: &Path, path: &Path ) -> PathBuf {
+    let path = path.canonicalize().unwrap_or_else( |_| path.to_owned() );
+    let name = path.file_name().map( |name| name.to_string_lossy().into_owned() ).unwrap_or_default();
+    cache_dir.join( format!( "{}-{:016x}.cache", name, hash_bytes( path.as_os_str().as_bytes() ) ) )
+}
+
+fn binary_path( cache_dir: &Path, hash: u64 ) -> PathBuf {
+    cache_dir.join( BINARIES_DIR ).join( format!( "{:016x}", hash ) )
+}
+
+/// The names of the binaries used by a cache, along with the hashes under which they're stored.
+type CachedBinaries = Vec< (String, u64) >;
+
+/// Reads everything in front of the data itself, which is also all that's needed to evict the cache.
+fn read_header< R: Read >( fp: &mut R ) -> io::Result< Option< (CacheKey, CachedBinaries) > > {
+    let mut magic = [0; 8];
+    fp.read_exact( &mut magic )?;
+    if &magic != MAGIC || u32::decode( fp )? != CACHE_VERSION {
+        return Ok( None );
+    }
+
+    let key = CacheKey::decode( fp )?;
+    let binaries = Decode::decode( fp )?;
+    Ok( Some( (key, binaries) ) )
+}
+
+/// Marks the file as just used, so that it's evicted last.
+fn touch( path: &Path ) -> io::Result< () > {
+    let fp = File::open( path )?;
+    let result = unsafe { libc::futimens( fp.as_raw_fd(), std::ptr::null() ) };
+    if result != 0 {
+        return Err( io::Error::last_os_error() );
+    }
+
+    Ok(())
+}
+
+/// Returns `None` if the cache doesn't exist, or if it's stale.
+fn read_cache( cache_dir: &Path, cache_path: &Path, key: &CacheKey, allocations_directory: Option< &Path > ) -> io::Result< Option< Data > > {
+    let fp = match File::open( cache_path ) {
+        Ok( fp ) => fp,
+        Err( ref error ) if error.kind() == io::ErrorKind::NotFound => return Ok( None ),
+        Err( error ) => return Err( error )
+    };
+
+    let mut fp = BufReader::new( fp );
+    let binaries = match read_header( &mut fp )? {
+        Some( (ref cached_key, binaries) ) if cached_key == key => binaries,
+        _ => return Ok( None )
+    };
+
+    let mut data = Data::decode( &mut fp, allocations_directory )?;
+    for (name, hash) in binaries {
+        let bytes = match fs::read( binary_path( cache_dir, hash ) ) {
+            Ok( bytes ) => bytes,
+            Err( ref error ) if error.kind() == io::ErrorKind::NotFound => return Ok( None ),
+            Err( error ) => return Err( error )
+        };
+
+        let binary = BinaryData::load_from_owned_bytes( &name, bytes )?;
+        data.symbolication.binaries.insert( name, Arc::new( binary ) );
+    }
+
+    Ok( Some( data ) )
+}
+
+/// Writes the file through a temporary file so that a half written file is never picked up.
+fn write_atomically( path: &Path, callback: impl FnOnce( &mut BufWriter< File > ) -> io::Result< () > ) -> io::Result< () > {
+    let mut tmp_path = path.to_owned().into_os_string();
+    tmp_path.push( ".tmp" );
+
+    let mut fp = BufWriter::new( File::create( &tmp_path )? );
+    callback( &mut fp )?;
+    fp.into_inner().map_err( |error| error.into_error() )?.sync_all()?;
+
+    fs::rename( &tmp_path, path )
+}
+
+fn write_cache( cache_dir: &Path, cache_path: &Path, key: &CacheKey, data: &Data ) -> io::Result< () > {
+    fs::create_dir_all( cache_dir.join( BINARIES_DIR ) )?;
+
+    let mut binaries = Vec::new();
+    for (name, binary) in &data.symbolication.binaries {
+        let hash = hash_bytes( binary.as_bytes() );
+        let path = binary_path( cache_dir, hash );
+        if !path.exists() {
+            write_atomically( &path, |fp| fp.write_all( binary.as_bytes() ) )?;
+        }
+
+        binaries.push( (name.clone(), hash) );
+    }
+
+    write_atomically( cache_path, |fp| {
+        fp.write_all( MAGIC )?;
+        CACHE_VERSION.encode( fp )?;
+        key.encode( fp )?;
+        binaries.encode( fp )?;
+        data.encode( fp )
+    })
+}
+
+/// Removes the least recently used caches until the cache directory takes at most `size_limit` bytes,
+/// along with the binaries which aren't used by any of the remaining caches.
+///
+/// The cache at `keep` is never removed. The caches which can't be read or which were made
+/// by a different version of the profiler are always removed.
+fn evict( cache_dir: &Path, size_limit: u64, keep: &Path ) -> io::Result< () > {
+    let mut binary_sizes = HashMap::new();
+    if let Ok( entries ) = fs::read_dir( cache_dir.join( BINARIES_DIR ) ) {
+        for entry in entries {
+            let entry = entry?;
+            let hash = entry.file_name().to_str().and_then( |name| u64::from_str_radix( name, 16 ).ok() );
+            if let Some( hash ) = hash {
+                binary_sizes.insert( hash, entry.metadata()?.len() );
+            }
+        }
+    }
+
+    let mut caches = Vec::new();
+    for entry in fs::read_dir( cache_dir )? {
+        let path = entry?.path();
+        if path.extension().map( |extension| extension != "cache" ).unwrap_or( true ) {
+            continue;
+        }
+
+        let metadata = fs::metadata( &path )?;
+        let header = File::open( &path ).and_then( |fp| read_header( &mut BufReader::new( fp ) ) ).ok().and_then( |header| header );
+        let binaries = match header {
+            Some( (_, binaries) ) => binaries,
+            None => {
+                debug!( "Removing an unusable cache: {:?}", path );
+                fs::remove_file( &path )?;
+                continue;
+            }
+        };
+
+        let used_at = metadata.modified().unwrap_or( SystemTime::UNIX_EPOCH );
+        let is_kept = path == keep;
+        caches.push( (is_kept, used_at, metadata.len(), binaries, path) );
+    }
+
+    // The most recently used go first.
+    caches.sort_by_key( |&(is_kept, used_at, ..)| cmp::Reverse( (is_kept, used_at) ) );
+
+    let mut total_size = 0;
+    let mut used_binaries = HashSet::new();
+    for (is_kept, _, size, binaries, path) in caches {
+        let mut new_binaries: Vec< u64 > = binaries.iter().map( |&(_, hash)| hash ).filter( |hash| !used_binaries.contains( hash ) ).collect();
+        new_binaries.sort_unstable();
+        new_binaries.dedup();
+
+        let size = size + new_binaries.iter().map( |hash| binary_sizes.get( hash ).cloned().unwrap_or( 0 ) ).sum::< u64 >();
+        if !is_kept && total_size + size > size_limit {
+            info!( "Removing {:?} from the cache", path );
+            fs::remove_file( &path )?;
+            continue;
+        }
+
+        total_size += size;
+        used_binaries.extend( new_binaries );
+    }
+
+    for hash in binary_sizes.keys() {
+        if !used_binaries.contains( hash ) {
+            fs::remove_file( binary_path( cache_dir, *hash ) )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads a data file through a cache kept in `cache_dir`.
+///
+/// If there's a cache for this exact data file it's loaded instead, which is much faster than
+/// loading the data file itself; otherwise the data file is loaded with `load` and the cache is
+/// written for the next time, after which the least recently used caches are removed until
+/// the cache directory takes at most `size_limit` bytes. A cache which is stale or broken is silently ignored.
+///
+/// If `allocations_directory` is given then the allocations loaded from the cache are kept in a memory mapped file there.
+pub fn load_with_cache< F >( cache_dir: &Path, size_limit: u64, path: &Path, debug_symbols: &[PathBuf], allocations_directory: Option< &Path >, load: F ) -> io::Result< Data >
+    where F: FnOnce() -> io::Result< Data >
+{
+    let key = match CacheKey::new( path, debug_symbols ) {
+        Ok( key ) => key,
+        Err( _ ) => return load()
+    };
+
+    let cache_path = cache_path( cache_dir, path );
+    let start_timestamp = Instant::now();
+    match read_cache( cache_dir, &cache_path, &key, allocations_directory ) {
+        Ok( Some( data ) ) => {
+            let elapsed = start_timestamp.elapsed();
+            info!( "Loaded {:?} from the cache in {}s {:03}", path, elapsed.as_secs(), elapsed.subsec_millis() );
+            if let Err( error ) = touch( &cache_path ) {
+                debug!( "Failed to update the modification time of {:?}: {}", cache_path, error );
+            }
+
+            return Ok( data );
+        },
+        Ok( None ) => {},
+        Err( error ) => {
+            debug!( "Ignoring the cache at {:?}: {}", cache_path, error );
+        }
+    }
+
+    let data = load()?;
+    if let Err( error ) = write_cache( cache_dir, &cache_path, &key, &data ) {
+        warn!( "Failed to write the cache to {:?}: {}", cache_path, error );
+    }
+
+    if let Err( error ) = evict( cache_dir, size_limit, &cache_path ) {
+        warn!( "Failed to remove the old caches from {:?}: {}", cache_dir, error );
+    }
+
+    Ok( data )
+}
+
+#[test]
+fn test_load_with_cache() {
+    use common::event::{AllocBody, Event, HeaderBody};
+    use common::speedy::Writable;
+    use crate::loader::Loader;
+
+    let directory = std::env::temp_dir().join( format!( "bytehound-cache-test-{}", std::process::id() ) );
+    let cache_dir = directory.join( "cache" );
+    let path = directory.join( "data.dat" );
+    fs::create_dir_all( &directory ).unwrap();
+
+    let write_data = |sizes: &[u64]| {
+        let mut fp = common::lz4_stream::Lz4Writer::new( Vec::new() );
+        let header = HeaderBody {
+            id: DataId::new( 1, 2 ),
+            initial_timestamp: Timestamp::from_secs( 1 ),
+            timestamp: Timestamp::from_secs( 1 ),
+            wall_clock_secs: 0,
+            wall_clock_nsecs: 0,
+            pid: 1,
+            cmdline: Vec::new(),
+            executable: b"test".to_vec(),
+            arch: "x86_64".into(),
+            flags: 0,
+            pointer_size: 8
+        };
+
+        Event::Header( header ).write_to_stream( &mut fp ).unwrap();
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() }.write_to_stream( &mut fp ).unwrap();
+        for (index, &size) in sizes.iter().enumerate() {
+            let allocation = AllocBody { pointer: 0x10000 * (index as u64 + 1), size, backtrace: 1, thread: 1, flags: 0, extra_usable_space: 0, preceding_free_space: 0 };
+            Event::Alloc { timestamp: Timestamp::from_secs( index as u64 + 2 ), allocation }.write_to_stream( &mut fp ).unwrap();
+        }
+        Event::Free { timestamp: Timestamp::from_secs( 10 ), pointer: 0x10000, backtrace: 1, thread: 1 }.write_to_stream( &mut fp ).unwrap();
+        fs::write( &path, fp.into_inner().unwrap() ).unwrap();
+    };
+
+    let load_count = std::cell::Cell::new( 0 );
+    let load = || {
+        load_with_cache( &cache_dir, u64::MAX, &path, &[], None, || {
+            load_count.set( load_count.get() + 1 );
+            Loader::load_from_stream_without_debug_info( File::open( &path )? )
+        }).unwrap()
+    };
+
+    let summary = |data: &Data| -> Vec< _ > {
+        data.unsorted_allocations().map( |allocation| (allocation.size, allocation.timestamp, allocation.deallocation.is_some()) ).collect()
+    };
+
+    write_data( &[ 10, 20 ] );
+    let loaded = load();
+    let cached = load();
+    assert_eq!( load_count.get(), 1 );
+    assert_eq!( summary( &cached ), summary( &loaded ) );
+    assert_eq!( summary( &cached ).len(), 2 );
+    assert_eq!( cached.id(), loaded.id() );
+    assert_eq!( cached.get_frame_ids( BacktraceId::new( 0 ) ).len(), loaded.get_frame_ids( BacktraceId::new( 0 ) ).len() );
+    assert_eq!( cached.last_timestamp(), loaded.last_timestamp() );
+    assert_eq!( cached.alloc_sorted_by_size( None, None ), loaded.alloc_sorted_by_size( None, None ) );
+
+    // The cache is ignored once the data file changes.
+    write_data( &[ 10, 20, 30 ] );
+    assert_eq!( summary( &load() ).len(), 3 );
+    assert_eq!( load_count.get(), 2 );
+
+    // A broken cache is ignored too.
+    let cache_file = cache_path( &cache_dir, &path );
+    let contents = fs::read( &cache_file ).unwrap();
+    fs::write( &cache_file, &contents[ ..contents.len() / 2 ] ).unwrap();
+    assert_eq!( summary( &load() ).len(), 3 );
+    assert_eq!( load_count.get(), 3 );
+    assert_eq!( summary( &load() ).len(), 3 );
+    assert_eq!( load_count.get(), 3 );
+
+    // Once the size limit is exceeded only the most recently written cache is kept,
+    // and the caches which can't be used are removed.
+    let old_cache_path = cache_dir.join( "old.cache" );
+    fs::write( &old_cache_path, b"BHINDEX\0\x01\0\0\0" ).unwrap();
+    let other_path = directory.join( "other.dat" );
+    fs::copy( &path, &other_path ).unwrap();
+    load_with_cache( &cache_dir, 1, &other_path, &[], None, || Loader::load_from_stream_without_debug_info( File::open( &other_path )? ) ).unwrap();
+    assert!( !cache_file.exists() );
+    assert!( cache_path( &cache_dir, &other_path ).exists() );
+    assert!( !old_cache_path.exists() );
+
+    let _ = fs::remove_dir_all( &directory );
+}
+
+#[test]
+fn test_cache_key_depends_on_the_debug_symbols() {
+    let directory = std::env::temp_dir().join( format!( "bytehound-cache-key-test-{}", std::process::id() ) );
+    let symbols_dir = directory.join( "symbols" );
+    let path = directory.join( "data.dat" );
+    fs::create_dir_all( &symbols_dir ).unwrap();
+    fs::write( &path, b"data" ).unwrap();
+    fs::write( symbols_dir.join( "libfoo.so.debug" ), b"old" ).unwrap();
+
+    let symbols = vec![ symbols_dir.clone() ];
+    let key = CacheKey::new( &path, &symbols ).unwrap();
+    assert_eq!( CacheKey::new( &path, &symbols ).unwrap(), key );
+    assert_ne!( CacheKey::new( &path, &[] ).unwrap(), key );
+
+    // The symbols were rebuilt at the same path.
+    fs::write( symbols_dir.join( "libfoo.so.debug" ), b"newer" ).unwrap();
+    assert_ne!( CacheKey::new( &path, &symbols ).unwrap(), key );
+
+    let _ = fs::remove_dir_all( &directory );
+}
+
+#[test]
+fn test_fnv() {
+    assert_eq!( hash_bytes( b"" ), 0xcbf29ce484222325 );
+    assert_eq!( hash_bytes( b"a" ), 0xaf63dc4c8601ec8c );
+    assert_eq!( hash_bytes( b"foobar" ), 0x85944171f73967e8 );
+}
@@ -1,5 +1,7 @@
 use std::fmt;
+use std::io;
 use std::ops::Range;
+use std::path::Path;
 use std::num::NonZeroU32;
 use std::cmp::Ordering;
 use std::borrow::{Borrow, Cow};
@@ -13,6 +15,8 @@ use crate::tree::Tree;
 use crate::tree_printer::dump_tree;
 use crate::frame::Frame;
 use crate::vecvec::DenseVecVec;
+use crate::mapped_vec::MappedVec;
+use crate::loader::SymbolicationContext;
 use crate::util::{ReadableSize, table_to_string};
 
 pub use common::{Timestamp};
@@ -89,6 +93,16 @@ impl OperationId {
     pub fn id( &self ) -> AllocationId {
         AllocationId( self.0 & !(3 << 62) )
     }
+
+    #[inline]
+    pub(crate) fn raw( &self ) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub(crate) fn from_raw( raw: u64 ) -> Self {
+        OperationId( raw )
+    }
 }
 
 #[test]
@@ -113,16 +127,23 @@ fn test_operation_id() {
     assert_eq!( id.id(), max );
 }
 
+#[derive(Clone)]
 pub struct Data {
     pub(crate) id: DataId,
     pub(crate) initial_timestamp: Timestamp,
     pub(crate) last_timestamp: Timestamp,
     pub(crate) executable: String,
     pub(crate) architecture: String,
+    /// The PID of the profiled process, if it's known.
+    pub(crate) pid: Option< u32 >,
+    /// The command line of the profiled process; empty if it wasn't recorded.
+    pub(crate) cmdline: Vec< String >,
+    /// Whether the timestamps are on the wall clock, i.e. whether we know when the profiling actually started.
+    pub(crate) has_wall_clock: bool,
     pub(crate) pointer_size: u64,
     pub(crate) interner: StringInterner,
     pub(crate) operations: Vec< OperationId >,
-    pub(crate) allocations: Vec< Allocation >,
+    pub(crate) allocations: MappedVec< Allocation >,
     pub(crate) sorted_by_timestamp: Vec< AllocationId >,
     pub(crate) sorted_by_address: Vec< AllocationId >,
     pub(crate) sorted_by_size: Vec< AllocationId >,
@@ -135,10 +156,15 @@ pub struct Data {
     pub(crate) total_freed: u64,
     pub(crate) total_freed_count: u64,
     pub(crate) mallopts: Vec< Mallopt >,
+    pub(crate) markers: Vec< Marker >,
     pub(crate) mmap_operations: Vec< MmapOperation >,
     pub(crate) maximum_backtrace_depth: u32,
     pub(crate) group_stats: Vec< GroupStatistics >,
-    pub(crate) chains: HashMap< AllocationId, AllocationChain >
+    pub(crate) chains: HashMap< AllocationId, AllocationChain >,
+    pub(crate) symbolication: SymbolicationContext,
+    pub(crate) modules: Vec< Module >,
+    /// The data from which this one was merged, if any.
+    pub(crate) sources: Vec< DataId >
 }
 
 pub type DataPointer = u64;
@@ -207,6 +233,8 @@ bitflags! {
         const IS_PREV_IN_USE    = 1 << 0;
         const IS_MMAPED         = 1 << 1;
         const IN_NON_MAIN_ARENA = 1 << 2;
+        /// The allocation was made before, or freed after, the range of the data which was loaded.
+        const IS_PARTIALLY_OBSERVED = 1 << 3;
         const IS_JEMALLOC       = 1 << 5;
         const IS_SHARED_PTR     = 1 << 6;
         const IS_CALLOC         = 1 << 7;
@@ -220,7 +248,7 @@ pub struct AllocationChain {
     pub length: u32
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct Allocation {
     pub pointer: DataPointer,
     pub timestamp: Timestamp,
@@ -238,7 +266,7 @@ pub struct Allocation {
     pub preceding_free_space: u32
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GroupStatistics {
     pub first_allocation: Timestamp,
     pub last_allocation: Timestamp,
@@ -310,7 +338,7 @@ macro_rules! enum_primitive {
 }
 
 enum_primitive! {
-    #[derive(Debug)]
+    #[derive(Copy, Clone, Debug)]
     pub enum MalloptKind {
         Other( i32 ),
         TrimThreshold   = -1,
@@ -324,7 +352,23 @@ enum_primitive! {
     }
 }
 
-#[derive(Debug)]
+impl MalloptKind {
+    pub fn name( &self ) -> Option< &'static str > {
+        match *self {
+            MalloptKind::TrimThreshold  => Some( "M_TRIM_THRESHOLD" ),
+            MalloptKind::TopPad         => Some( "M_TOP_PAD" ),
+            MalloptKind::MmapThreshold  => Some( "M_MMAP_THRESHOLD" ),
+            MalloptKind::MmapMax        => Some( "M_MMAP_MAX" ),
+            MalloptKind::CheckAction    => Some( "M_CHECK_ACTION" ),
+            MalloptKind::Perturb        => Some( "M_PERTURB" ),
+            MalloptKind::ArenaTest      => Some( "M_ARENA_TEST" ),
+            MalloptKind::ArenaMax       => Some( "M_ARENA_MAX" ),
+            MalloptKind::Other( _ )     => None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Mallopt {
     pub timestamp: Timestamp,
     pub backtrace: BacktraceId,
@@ -334,6 +378,15 @@ pub struct Mallopt {
     pub result: i32
 }
 
+#[derive(Clone, Debug)]
+pub struct Marker {
+    pub timestamp: Timestamp,
+    /// Older data files don't record the backtrace of markers.
+    pub backtrace: Option< BacktraceId >,
+    pub thread: ThreadId,
+    pub value: u32
+}
+
 impl Allocation {
     #[inline]
     pub fn was_deallocated( &self ) -> bool {
@@ -365,6 +418,11 @@ impl Allocation {
         self.flags.contains( AllocationFlags::IS_MMAPED )
     }
 
+    #[inline]
+    pub fn is_partially_observed( &self ) -> bool {
+        self.flags.contains( AllocationFlags::IS_PARTIALLY_OBSERVED )
+    }
+
     #[inline]
     pub fn usable_size( &self ) -> u64 {
         self.size + self.extra_usable_space as u64
@@ -377,7 +435,7 @@ impl Allocation {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct Deallocation {
     pub timestamp: Timestamp,
     pub thread: ThreadId,
@@ -403,7 +461,17 @@ pub enum Operation< 'a > {
     }
 }
 
-#[derive(Debug)]
+/// An object file mapped into the address space of the profiled process.
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub path: String,
+    /// Covers every mapping of this module.
+    pub address_range: Range< u64 >,
+    /// The build ID as a lowercase hex string.
+    pub build_id: Option< String >
+}
+
+#[derive(Clone, Debug)]
 pub struct MemoryMap {
     pub timestamp: Timestamp,
     pub pointer: DataPointer,
@@ -414,10 +482,12 @@ pub struct MemoryMap {
     pub mmap_flags: MapFlags,
     pub file_descriptor: u32,
     pub thread: ThreadId,
-    pub offset: u64
+    pub offset: u64,
+    /// The path of the mapped file, if it's a file-backed map and we were able to figure it out.
+    pub file_path: Option< StringId >
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MemoryUnmap {
     pub timestamp: Timestamp,
     pub pointer: DataPointer,
@@ -426,10 +496,63 @@ pub struct MemoryUnmap {
     pub thread: ThreadId
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+pub struct MemoryRemap {
+    pub timestamp: Timestamp,
+    pub old_pointer: DataPointer,
+    pub old_length: u64,
+    pub new_pointer: DataPointer,
+    pub new_length: u64,
+    pub backtrace: BacktraceId,
+    pub remap_flags: RemapFlags,
+    pub thread: ThreadId
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryProtect {
+    pub timestamp: Timestamp,
+    pub pointer: DataPointer,
+    pub length: u64,
+    pub backtrace: BacktraceId,
+    pub protection: ProtectionFlags,
+    pub thread: ThreadId
+}
+
+#[derive(Clone, Debug)]
 pub enum MmapOperation {
     Mmap( MemoryMap ),
-    Munmap( MemoryUnmap )
+    Munmap( MemoryUnmap ),
+    Mremap( MemoryRemap ),
+    Mprotect( MemoryProtect )
+}
+
+impl MmapOperation {
+    pub fn timestamp( &self ) -> Timestamp {
+        match *self {
+            MmapOperation::Mmap( ref map ) => map.timestamp,
+            MmapOperation::Munmap( ref unmap ) => unmap.timestamp,
+            MmapOperation::Mremap( ref remap ) => remap.timestamp,
+            MmapOperation::Mprotect( ref protect ) => protect.timestamp
+        }
+    }
+
+    pub fn backtrace( &self ) -> BacktraceId {
+        match *self {
+            MmapOperation::Mmap( ref map ) => map.backtrace,
+            MmapOperation::Munmap( ref unmap ) => unmap.backtrace,
+            MmapOperation::Mremap( ref remap ) => remap.backtrace,
+            MmapOperation::Mprotect( ref protect ) => protect.backtrace
+        }
+    }
+
+    pub fn thread( &self ) -> ThreadId {
+        match *self {
+            MmapOperation::Mmap( ref map ) => map.thread,
+            MmapOperation::Munmap( ref unmap ) => unmap.thread,
+            MmapOperation::Mremap( ref remap ) => remap.thread,
+            MmapOperation::Mprotect( ref protect ) => protect.thread
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -486,6 +609,24 @@ impl MapFlags {
     }
 }
 
+#[derive(Copy, Clone, Debug)]
+pub struct RemapFlags( pub(crate) u32 );
+
+impl RemapFlags {
+    pub fn is_may_move( &self ) -> bool {
+        self.0 & 0x1 != 0
+    }
+
+    pub fn is_fixed( &self ) -> bool {
+        self.0 & 0x2 != 0
+    }
+
+    /// The old range is left mapped, and only its pages are moved to the new range.
+    pub fn is_dont_unmap( &self ) -> bool {
+        self.0 & 0x4 != 0
+    }
+}
+
 #[derive(Debug)]
 pub struct CountAndSize {
     pub count: u64,
@@ -698,6 +839,49 @@ impl Data {
         self.total_freed_count
     }
 
+    /// Returns a rough estimate of how much memory the data occupies.
+    ///
+    /// Only the biggest tables are taken into account. The binaries used for symbolication
+    /// are counted in full even though they can be shared with other data.
+    pub fn memory_usage_estimate( &self ) -> u64 {
+        fn size_of< T >( vec: &Vec< T > ) -> usize {
+            vec.capacity() * std::mem::size_of::< T >()
+        }
+
+        let strings: usize = self.interner.iter_values()
+            .map( |string| string.len() + std::mem::size_of::< Box< str > >() + std::mem::size_of::< (usize, StringId) >() )
+            .sum();
+
+        let binaries: usize = self.symbolication.binaries.values()
+            .map( |binary| binary.as_bytes().len() )
+            .sum();
+
+        let size =
+            size_of( &self.operations ) +
+            self.allocations.memory_usage() +
+            size_of( &self.sorted_by_timestamp ) +
+            size_of( &self.sorted_by_address ) +
+            size_of( &self.sorted_by_size ) +
+            size_of( &self.frames ) +
+            size_of( &self.backtraces ) +
+            size_of( &self.backtraces_storage ) +
+            size_of( &self.mallopts ) +
+            size_of( &self.markers ) +
+            size_of( &self.mmap_operations ) +
+            size_of( &self.group_stats ) +
+            self.allocations_by_backtrace.memory_usage() +
+            strings +
+            binaries;
+
+        size as u64
+    }
+
+    /// Moves the allocations into a memory mapped file in the given directory, so that they
+    /// don't have to be resident in memory all the time; everything else stays in memory.
+    pub fn move_allocations_to_disk( &mut self, directory: &Path ) -> io::Result< () > {
+        self.allocations.move_to_disk( directory )
+    }
+
     #[inline]
     pub fn initial_timestamp( &self ) -> Timestamp {
         self.initial_timestamp
@@ -723,11 +907,41 @@ impl Data {
         &self.architecture
     }
 
+    /// Returns the PID of the profiled process, or `None` if it's unknown, e.g. for data merged from different processes.
+    #[inline]
+    pub fn pid( &self ) -> Option< u32 > {
+        self.pid
+    }
+
+    /// Returns the command line arguments of the profiled process, or nothing if they weren't recorded.
+    #[inline]
+    pub fn cmdline( &self ) -> &[String] {
+        &self.cmdline
+    }
+
+    /// Returns the wall clock time at which the profiling started, or `None` if it wasn't recorded.
+    ///
+    /// If it's known then every other timestamp is also on the wall clock.
+    #[inline]
+    pub fn wall_clock_start( &self ) -> Option< Timestamp > {
+        if self.has_wall_clock {
+            Some( self.initial_timestamp )
+        } else {
+            None
+        }
+    }
+
     #[inline]
     pub fn id( &self ) -> DataId {
         self.id
     }
 
+    /// Returns the IDs of the data from which this one was merged, or nothing if it wasn't merged.
+    #[inline]
+    pub fn sources( &self ) -> &[DataId] {
+        &self.sources
+    }
+
     #[inline]
     pub fn unique_backtrace_count( &self ) -> usize {
         self.backtraces.len()
@@ -769,6 +983,15 @@ impl Data {
         &self.allocations[ id.raw() as usize ]
     }
 
+    /// Returns the id of the allocation with the given raw id, or `None` if there's no such allocation.
+    pub fn get_allocation_id( &self, raw: u64 ) -> Option< AllocationId > {
+        if raw < self.allocations.len() as u64 {
+            Some( AllocationId::new( raw ) )
+        } else {
+            None
+        }
+    }
+
     pub fn get_allocations_by_backtrace( &self, id: BacktraceId ) -> impl SliceLikeIterator< Item = (AllocationId, &Allocation) > {
         self.allocations_by_backtrace.get( id.raw() as _ ).iter().map( move |&allocation_id| (allocation_id, &self.allocations[ allocation_id.raw() as usize ]) )
     }
@@ -837,20 +1060,35 @@ impl Data {
                 continue;
             }
 
-            tree.add_allocation( &allocation, allocation_id, self.get_backtrace( allocation.backtrace ).map( |(frame_id, frame)| {
-                let key = match (frame.source(), frame.line(), frame.function().or( frame.raw_function() )) {
-                    (Some( source ), Some( line ), _) => SourceKey::Location( source, line ),
-                    (_, _, Some( function )) => SourceKey::Function( function ),
-                    _ => SourceKey::Address( frame.address() )
-                };
+            tree.add_allocation( allocation, allocation_id, self.get_backtrace_by_source( allocation.backtrace ) );
+        }
 
-                (key, frame_id)
-            }));
+        tree
+    }
+
+    /// Same as `tree_by_source`, except it takes arbitrary `(backtrace, timestamp, size)` records
+    /// instead of allocations, e.g. to build a tree of the memory maps.
+    pub fn tree_by_source_of_sizes( &self, records: impl Iterator< Item = (BacktraceId, Timestamp, u64) > ) -> Tree< SourceKey, FrameId > {
+        let mut tree = Tree::new();
+        for (backtrace, timestamp, size) in records {
+            tree.add( timestamp, size, self.get_backtrace_by_source( backtrace ) );
         }
 
         tree
     }
 
+    fn get_backtrace_by_source( &self, backtrace: BacktraceId ) -> impl Iterator< Item = (SourceKey, FrameId) > + '_ {
+        self.get_backtrace( backtrace ).map( |(frame_id, frame)| {
+            let key = match (frame.source(), frame.line(), frame.function().or( frame.raw_function() )) {
+                (Some( source ), Some( line ), _) => SourceKey::Location( source, line ),
+                (_, _, Some( function )) => SourceKey::Function( function ),
+                _ => SourceKey::Address( frame.address() )
+            };
+
+            (key, frame_id)
+        })
+    }
+
     pub fn dump_tree( &self, tree: &Tree< SourceKey, FrameId > ) -> Vec< Vec< String > > {
         dump_tree( &tree, self.initial_timestamp, |&frame_id| {
             let frame = &self.frames[ frame_id ];
@@ -871,10 +1109,29 @@ impl Data {
         })
     }
 
+    /// Returns every loaded module, sorted by its address.
+    pub fn modules( &self ) -> &[Module] {
+        &self.modules
+    }
+
+    pub fn module_for_address( &self, address: u64 ) -> Option< &Module > {
+        let index = self.modules.partition_point( |module| module.address_range.start <= address );
+        let module = self.modules[ ..index ].last()?;
+        if module.address_range.contains( &address ) {
+            Some( module )
+        } else {
+            None
+        }
+    }
+
     pub fn mallopts( &self ) -> &[Mallopt] {
         &self.mallopts
     }
 
+    pub fn markers( &self ) -> &[Marker] {
+        &self.markers
+    }
+
     pub fn mmap_operations( &self ) -> &[MmapOperation] {
         &self.mmap_operations
     }
@@ -0,0 +1,78 @@
+use std::borrow::Cow;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum DemangleStyle {
+    /// Fully demangled names, including Rust hashes and C++ parameter lists.
+    Full,
+    /// Demangled names without Rust hashes and crate disambiguators, and without C++ parameter lists.
+    Short,
+    /// Raw, mangled names.
+    None
+}
+
+/// Demangles a raw symbol name according to the given style.
+///
+/// Symbols which aren't mangled (e.g. plain C functions) are returned unchanged.
+pub fn demangle_symbol( raw: &str, style: DemangleStyle ) -> Cow< str > {
+    if style == DemangleStyle::None {
+        return raw.into();
+    }
+
+    // Legacy Rust symbols are also valid Itanium symbols, so this has to be checked first.
+    if let Ok( symbol ) = rustc_demangle::try_demangle( raw ) {
+        return match style {
+            DemangleStyle::Short => format!( "{:#}", symbol ).into(),
+            _ => format!( "{}", symbol ).into()
+        };
+    }
+
+    if raw.starts_with( "_Z" ) {
+        if let Ok( symbol ) = cpp_demangle::Symbol::new( raw.as_bytes() ) {
+            let options = cpp_demangle::DemangleOptions {
+                no_params: style == DemangleStyle::Short
+            };
+
+            if let Ok( demangled ) = symbol.demangle( &options ) {
+                return demangled.into();
+            }
+        }
+    }
+
+    raw.into()
+}
+
+#[test]
+fn test_demangle_rust_legacy() {
+    let raw = "_ZN4core3ptr13drop_in_place17h1234567890abcdefE";
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Full ), "core::ptr::drop_in_place::h1234567890abcdef" );
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Short ), "core::ptr::drop_in_place" );
+    assert_eq!( demangle_symbol( raw, DemangleStyle::None ), raw );
+}
+
+#[test]
+fn test_demangle_rust_v0() {
+    let raw = "_RNvCs1234_7mycrate3foo";
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Full ), "mycrate[3c1c0]::foo" );
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Short ), "mycrate::foo" );
+    assert_eq!( demangle_symbol( raw, DemangleStyle::None ), raw );
+}
+
+#[test]
+fn test_demangle_cpp() {
+    let raw = "_ZN9wikipedia7article6formatEv";
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Full ), "wikipedia::article::format()" );
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Short ), "wikipedia::article::format" );
+    assert_eq!( demangle_symbol( raw, DemangleStyle::None ), raw );
+
+    let raw = "_Z3fooiPKc";
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Full ), "foo(int, char const*)" );
+    assert_eq!( demangle_symbol( raw, DemangleStyle::Short ), "foo" );
+}
+
+#[test]
+fn test_demangle_c() {
+    for &style in &[DemangleStyle::Full, DemangleStyle::Short, DemangleStyle::None] {
+        assert_eq!( demangle_symbol( "malloc", style ), "malloc" );
+        assert_eq!( demangle_symbol( "__libc_start_main", style ), "__libc_start_main" );
+    }
+}
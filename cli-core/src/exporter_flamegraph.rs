@@ -6,10 +6,12 @@ use inferno::flamegraph;
 use super::{
     Allocation,
     AllocationId,
-    Data
+    BacktraceId,
+    Data,
+    Timestamp
 };
 
-use crate::exporter_flamegraph_pl::dump_collation;
+use crate::exporter_flamegraph_pl::{dump_collation, dump_collation_of_sizes, FlamegraphOptions};
 use crate::io_adapter::IoAdapter;
 
 pub fn lines_to_svg( lines: Vec< String >, output: impl fmt::Write ) {
@@ -34,12 +36,27 @@ pub fn lines_to_svg( lines: Vec< String >, output: impl fmt::Write ) {
     let _ = flamegraph::from_lines( &mut options, lines.iter().map( |line| line.as_str() ), IoAdapter::new( output ) );
 }
 
-pub fn export_as_flamegraph< T, F >( data: &Data, output: T, filter: F )
+pub fn export_as_flamegraph< T, F >( data: &Data, output: T, options: &FlamegraphOptions, filter: F )
     where T: fmt::Write,
           F: Fn( AllocationId, &Allocation ) -> bool
 {
     let mut lines = Vec::new();
-    dump_collation( data, filter, |line| {
+    dump_collation( data, options, filter, |line| {
+        lines.push( line.to_owned() );
+        let result: Result< (), () > = Ok(());
+        result
+    }).unwrap();
+
+    lines.sort_unstable();
+
+    lines_to_svg( lines, output )
+}
+
+pub fn export_sizes_as_flamegraph< T >( data: &Data, output: T, options: &FlamegraphOptions, records: impl Iterator< Item = (BacktraceId, Timestamp, u64) > )
+    where T: fmt::Write
+{
+    let mut lines = Vec::new();
+    dump_collation_of_sizes( data, records, options, |line| {
         lines.push( line.to_owned() );
         let result: Result< (), () > = Ok(());
         result
@@ -1,18 +1,37 @@
 use super::{
     Allocation,
     AllocationId,
+    BacktraceId,
     Data,
     Frame,
     FrameId,
     NodeId,
+    Timestamp,
     Tree
 };
 
+use crate::demangle::{demangle_symbol, DemangleStyle};
+use crate::trim::trimmed_frame_count;
+
 use std::fmt::{self, Write};
 
+use ahash::AHashMap as HashMap;
+use regex::Regex;
+
+#[derive(Clone, Default, Debug)]
+pub struct FlamegraphOptions {
+    /// Demangles the raw symbol names with the given style instead of using the names resolved by the loader.
+    pub demangle: Option< DemangleStyle >,
+    /// Trims every frame outward of the first frame whose function matches this regex.
+    pub trim_below: Option< Regex >,
+    /// Trims every frame outward of the runtime's entry point.
+    pub trim_runtime_prefix: bool
+}
+
 fn dump_collation_impl< O: FnMut( &str ) -> Result< (), E >, K: PartialEq + Clone, E >(
     data: &Data,
     tree: &Tree< K, &Frame >,
+    options: &FlamegraphOptions,
     node_id: NodeId,
     stack: &mut Vec< String >,
     cache: &mut Vec< String >,
@@ -23,9 +42,12 @@ fn dump_collation_impl< O: FnMut( &str ) -> Result< (), E >, K: PartialEq + Clon
     if let Some( value ) = node.value() {
         let mut buffer = cache.pop().unwrap_or( String::new() );
         let library = value.library().map( |id| data.interner().resolve( id ).unwrap() ).unwrap_or( "???" );
-        if let Some( function ) = value.function().map( |id| data.interner().resolve( id ).unwrap() ) {
+        let raw_function = value.raw_function().map( |id| data.interner().resolve( id ).unwrap() );
+        if let (Some( style ), Some( raw_function )) = (options.demangle, raw_function) {
+            write!( &mut buffer, "{} [{}]", demangle_symbol( raw_function, style ), library ).unwrap();
+        } else if let Some( function ) = value.function().map( |id| data.interner().resolve( id ).unwrap() ) {
             write!( &mut buffer, "{} [{}]", function, library ).unwrap();
-        } else if let Some( function ) = value.raw_function().map( |id| data.interner().resolve( id ).unwrap() ) {
+        } else if let Some( function ) = raw_function {
             write!( &mut buffer, "{} [{}]", function, library ).unwrap();
         } else {
             write!( &mut buffer, "0x{:016X} [{}]", value.address().raw(), library ).unwrap();
@@ -44,7 +66,7 @@ fn dump_collation_impl< O: FnMut( &str ) -> Result< (), E >, K: PartialEq + Clon
     }
 
     for &(_, child_id) in tree.get_node( node_id ).children.iter() {
-        dump_collation_impl( data, tree, child_id, stack, cache, output )?;
+        dump_collation_impl( data, tree, options, child_id, stack, cache, output )?;
     }
 
     if !node.is_root() {
@@ -59,27 +81,69 @@ fn dump_collation_impl< O: FnMut( &str ) -> Result< (), E >, K: PartialEq + Clon
 pub fn dump_collation_from_iter< 'a, O, E >(
     data: &Data,
     allocations: impl Iterator< Item = (AllocationId, &'a Allocation) >,
+    options: &FlamegraphOptions,
     mut output: O
 ) -> Result< (), E >
     where O: FnMut( &str ) -> Result< (), E >
 {
     let mut tree: Tree< FrameId, &Frame > = Tree::new();
+    let mut trimmed_count_cache = HashMap::new();
     for (allocation_id, allocation) in allocations {
-        tree.add_allocation( allocation, allocation_id, data.get_backtrace( allocation.backtrace ) );
+        let backtrace = data.get_backtrace( allocation.backtrace );
+        let trimmed_count = *trimmed_count_cache.entry( allocation.backtrace ).or_insert_with( || {
+            trimmed_frame_count( data, backtrace.clone().map( |(_, frame)| frame ), options.trim_below.as_ref(), options.trim_runtime_prefix )
+        });
+
+        tree.add_allocation( allocation, allocation_id, backtrace.skip( trimmed_count ) );
     }
 
-    dump_collation_impl( data, &tree, 0, &mut Vec::new(), &mut Vec::new(), &mut output )
+    dump_collation_impl( data, &tree, options, 0, &mut Vec::new(), &mut Vec::new(), &mut output )
 }
 
-pub fn dump_collation< F, O, E >( data: &Data, filter: F, output: O ) -> Result< (), E >
+/// Same as `dump_collation_from_iter`, except it takes arbitrary `(backtrace, timestamp, size)` records
+/// instead of allocations, e.g. to build a flamegraph of the memory maps.
+pub fn dump_collation_of_sizes< O, E >(
+    data: &Data,
+    records: impl Iterator< Item = (BacktraceId, Timestamp, u64) >,
+    options: &FlamegraphOptions,
+    mut output: O
+) -> Result< (), E >
+    where O: FnMut( &str ) -> Result< (), E >
+{
+    let mut tree: Tree< FrameId, &Frame > = Tree::new();
+    let mut trimmed_count_cache = HashMap::new();
+    for (backtrace_id, timestamp, size) in records {
+        let backtrace = data.get_backtrace( backtrace_id );
+        let trimmed_count = *trimmed_count_cache.entry( backtrace_id ).or_insert_with( || {
+            trimmed_frame_count( data, backtrace.clone().map( |(_, frame)| frame ), options.trim_below.as_ref(), options.trim_runtime_prefix )
+        });
+
+        tree.add( timestamp, size, backtrace.skip( trimmed_count ) );
+    }
+
+    dump_collation_impl( data, &tree, options, 0, &mut Vec::new(), &mut Vec::new(), &mut output )
+}
+
+pub fn dump_collation< F, O, E >( data: &Data, options: &FlamegraphOptions, filter: F, output: O ) -> Result< (), E >
     where F: Fn( AllocationId, &Allocation ) -> bool,
           O: FnMut( &str ) -> Result< (), E >
 {
-    dump_collation_from_iter( data, data.allocations_with_id().filter( |(id, allocation)| filter( *id, allocation ) ), output )
+    dump_collation_from_iter( data, data.allocations_with_id().filter( |(id, allocation)| filter( *id, allocation ) ), options, output )
 }
 
-pub fn export_as_flamegraph_pl< T: fmt::Write, F: Fn( AllocationId, &Allocation ) -> bool >( data: &Data, mut output: T, filter: F ) -> fmt::Result {
-    dump_collation( data, filter, |line| {
+pub fn export_as_flamegraph_pl< T: fmt::Write, F: Fn( AllocationId, &Allocation ) -> bool >( data: &Data, mut output: T, options: &FlamegraphOptions, filter: F ) -> fmt::Result {
+    dump_collation( data, options, filter, |line| {
+        writeln!( &mut output, "{}", line )
+    })
+}
+
+pub fn export_sizes_as_flamegraph_pl< T: fmt::Write >(
+    data: &Data,
+    mut output: T,
+    options: &FlamegraphOptions,
+    records: impl Iterator< Item = (BacktraceId, Timestamp, u64) >
+) -> fmt::Result {
+    dump_collation_of_sizes( data, records, options, |line| {
         writeln!( &mut output, "{}", line )
     })
 }
@@ -17,6 +17,8 @@ mod tree;
 mod tree_printer;
 mod reader;
 mod loader;
+mod merge;
+mod cache;
 mod postprocessor;
 mod squeeze;
 mod frame;
@@ -27,20 +29,26 @@ mod exporter_heaptrack;
 mod exporter_flamegraph;
 mod exporter_flamegraph_pl;
 mod vecvec;
+mod mapped_vec;
 mod threaded_lz4_stream;
 mod repack;
 mod timeline;
+mod demangle;
+mod trim;
+mod strip_template;
 pub mod script;
 mod script_virtual;
 
-pub use crate::data::{Data, DataId, CodePointer, DataPointer, BacktraceId, Timestamp, Operation, OperationId, StringId, Allocation, AllocationId, FrameId, Mallopt, MalloptKind, MmapOperation, MemoryMap, MemoryUnmap, CountAndSize};
-pub use crate::loader::Loader;
+pub use crate::data::{Data, DataId, CodePointer, DataPointer, BacktraceId, Timestamp, Operation, OperationId, StringId, Allocation, AllocationId, FrameId, Mallopt, MalloptKind, Marker, MmapOperation, MemoryMap, MemoryUnmap, MemoryRemap, MemoryProtect, CountAndSize, Module};
+pub use crate::loader::{Loader, LoadOptions, LoadPhase, LoadProgress, LoadRange, SymbolUpdates};
+pub use crate::merge::MergeError;
+pub use crate::cache::load_with_cache;
 pub use crate::tree::{Tree, Node, NodeId};
 pub use crate::frame::Frame;
 pub use crate::exporter_replay::export_as_replay;
 pub use crate::exporter_heaptrack::export_as_heaptrack;
-pub use crate::exporter_flamegraph_pl::export_as_flamegraph_pl;
-pub use crate::exporter_flamegraph::export_as_flamegraph;
+pub use crate::exporter_flamegraph_pl::{export_as_flamegraph_pl, export_sizes_as_flamegraph_pl, FlamegraphOptions};
+pub use crate::exporter_flamegraph::{export_as_flamegraph, export_sizes_as_flamegraph};
 pub use crate::vecvec::VecVec;
 pub use crate::util::table_to_string;
 pub use crate::postprocessor::{Anonymize, postprocess};
@@ -48,7 +56,10 @@ pub use crate::squeeze::squeeze_data;
 pub use crate::reader::parse_events;
 pub use crate::repack::repack;
 pub use crate::script::run_script;
-pub use crate::timeline::build_timeline;
+pub use crate::timeline::{build_timeline, build_timeline_with_resolution, TimelinePoint};
+pub use crate::demangle::{demangle_symbol, DemangleStyle};
+pub use crate::trim::{innermost_non_allocator_frame, trimmed_frame_count};
+pub use crate::strip_template::strip_template;
 
 pub use common::event;
 
@@ -1,11 +1,15 @@
 use std::mem;
 use std::cell::RefCell;
 use std::ops::{Deref, Range};
+use std::str::FromStr;
 use std::io::{self, Read};
 use std::borrow::Cow;
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::ffi::OsStr;
+use std::path::PathBuf;
 use std::cmp;
 
 use std::collections::hash_map;
@@ -15,6 +19,7 @@ use byteorder::{BigEndian, LittleEndian, ByteOrder};
 use nwind::{arch, BinaryData, AddressSpace, IAddressSpace, DebugInfoIndex};
 use nwind::proc_maps::Region;
 use nwind::proc_maps::parse as parse_maps;
+use parking_lot::Mutex;
 use rayon::prelude::*;
 
 use common::event::{
@@ -43,12 +48,17 @@ use crate::data::{
     FrameId,
     GroupStatistics,
     Mallopt,
+    Marker,
     MemoryMap,
+    MemoryProtect,
+    MemoryRemap,
     MemoryUnmap,
     MmapOperation,
+    Module,
     OperationId,
     ProtectionFlags,
     MapFlags,
+    RemapFlags,
     ThreadId,
     Timestamp,
     StringInterner,
@@ -56,6 +66,7 @@ use crate::data::{
 };
 use crate::vecvec::DenseVecVec;
 use crate::reader::parse_events;
+use crate::mapped_vec::MappedVecMut;
 
 #[derive(Clone, PartialEq, Eq, Default, Debug, Hash)]
 pub struct AddressMapping {
@@ -82,6 +93,154 @@ fn test_clean_symbol() {
     );
 }
 
+/// The stage of loading a data file which is currently in progress.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LoadPhase {
+    /// The events are being read, and the backtraces deduplicated.
+    Reading,
+    /// The allocations are being sorted.
+    Sorting,
+    /// The indexes of the allocations are being built.
+    Indexing
+}
+
+impl LoadPhase {
+    pub fn name( self ) -> &'static str {
+        match self {
+            LoadPhase::Reading => "reading",
+            LoadPhase::Sorting => "sorting",
+            LoadPhase::Indexing => "indexing"
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct LoadProgress {
+    pub phase: LoadPhase,
+    /// How many bytes were read from the stream so far.
+    pub bytes_read: u64
+}
+
+/// Limits the loading to the allocations which were alive during the given time range.
+///
+/// Both ends are relative to the start of the profiling; the start is inclusive and the end is exclusive.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct LoadRange {
+    pub from: Option< Duration >,
+    pub to: Option< Duration >
+}
+
+fn parse_duration( value: &str ) -> Result< Duration, String > {
+    let value = value.trim();
+    let (number, multiplier) = if let Some( number ) = value.strip_suffix( "ms" ) {
+        (number, 0.001)
+    } else if let Some( number ) = value.strip_suffix( 's' ) {
+        (number, 1.0)
+    } else if let Some( number ) = value.strip_suffix( 'm' ) {
+        (number, 60.0)
+    } else if let Some( number ) = value.strip_suffix( 'h' ) {
+        (number, 3600.0)
+    } else {
+        (value, 1.0)
+    };
+
+    let number: f64 = number.parse().map_err( |_| format!( "invalid duration: '{}'", value ) )?;
+    if !number.is_finite() || number < 0.0 {
+        return Err( format!( "invalid duration: '{}'", value ) );
+    }
+
+    Ok( Duration::from_secs_f64( number * multiplier ) )
+}
+
+impl FromStr for LoadRange {
+    type Err = String;
+
+    /// Parses a range in the `<from>..<to>` format, where either end can be omitted, e.g. `10m..20m`, `..90s` or `3600..`.
+    ///
+    /// The durations are in seconds unless they have one of the `ms`, `s`, `m` or `h` suffixes.
+    fn from_str( value: &str ) -> Result< Self, Self::Err > {
+        let index = value.find( ".." ).ok_or_else( || format!( "invalid range: '{}'; expected '<from>..<to>'", value ) )?;
+        let (from, to) = (&value[ ..index ], &value[ index + 2.. ]);
+        let from = if from.trim().is_empty() { None } else { Some( parse_duration( from )? ) };
+        let to = if to.trim().is_empty() { None } else { Some( parse_duration( to )? ) };
+        if let (Some( from ), Some( to )) = (from, to) {
+            if from >= to {
+                return Err( format!( "invalid range: '{}'; its start must be before its end", value ) );
+            }
+        }
+
+        Ok( LoadRange { from, to } )
+    }
+}
+
+#[test]
+fn test_parse_load_range() {
+    let range = |from: Option< u64 >, to: Option< u64 >| LoadRange { from: from.map( Duration::from_millis ), to: to.map( Duration::from_millis ) };
+    assert_eq!( "10..20".parse(), Ok( range( Some( 10000 ), Some( 20000 ) ) ) );
+    assert_eq!( "10m..1.5h".parse(), Ok( range( Some( 600000 ), Some( 5400000 ) ) ) );
+    assert_eq!( "500ms..2s".parse(), Ok( range( Some( 500 ), Some( 2000 ) ) ) );
+    assert_eq!( "..90s".parse(), Ok( range( None, Some( 90000 ) ) ) );
+    assert_eq!( "3600..".parse(), Ok( range( Some( 3600000 ), None ) ) );
+    assert!( "10".parse::< LoadRange >().is_err() );
+    assert!( "20..10".parse::< LoadRange >().is_err() );
+    assert!( "-1..10".parse::< LoadRange >().is_err() );
+    assert!( "1x..10".parse::< LoadRange >().is_err() );
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct LoadOptions {
+    pub range: Option< LoadRange >,
+    /// A directory in which to create a memory mapped file into which the allocations are written
+    /// as they're loaded, instead of keeping them in memory.
+    pub allocations_directory: Option< PathBuf >
+}
+
+/// Where the events which are being processed are in relation to the `LoadRange`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum RangeState {
+    Before,
+    Inside,
+    After
+}
+
+/// An allocation made before the `LoadRange` which might still be alive once it starts.
+struct PendingAllocation {
+    order: u64,
+    id: event::AllocationId,
+    timestamp: Timestamp,
+    pointer: DataPointer,
+    size: u64,
+    backtrace: BacktraceId,
+    thread: ThreadId,
+    flags: u32,
+    extra_usable_space: u32,
+    preceding_free_space: u64,
+    marker: u32
+}
+
+/// How many events are processed between each progress report.
+const PROGRESS_INTERVAL: usize = 4096;
+
+/// How many times longer than it took to build the last snapshot of a live stream to wait before
+/// building the next one, so that the snapshots of a big stream don't take up all of the time.
+const LIVE_SNAPSHOT_SLOWDOWN: u32 = 4;
+
+/// Counts how many bytes were read from the underlying stream.
+///
+/// The events are parsed on a different thread than the one which reads them, hence the atomic.
+struct CountingReader< R > {
+    inner: R,
+    bytes_read: Arc< AtomicU64 >
+}
+
+impl< R: Read > Read for CountingReader< R > {
+    fn read( &mut self, buffer: &mut [u8] ) -> io::Result< usize > {
+        let count = self.inner.read( buffer )?;
+        self.bytes_read.fetch_add( count as u64, Ordering::Relaxed );
+        Ok( count )
+    }
+}
+
 pub struct Loader {
     id: DataId,
     header: HeaderBody,
@@ -97,7 +256,7 @@ pub struct Loader {
     backtrace_remappings: HashMap< u64, BacktraceId >,
     group_stats: Vec< GroupStatistics >,
     operations: Vec< (Timestamp, OperationId) >,
-    allocations: Vec< Allocation >,
+    allocations: MappedVecMut< Allocation >,
     allocation_map: HashMap< (u64, u64), AllocationId >,
     allocation_range_map: RangeMap< AllocationId >,
     allocation_range_map_dirty: bool,
@@ -115,13 +274,22 @@ pub struct Loader {
     symbol_new_range: Range< u64 >,
     marker: u32,
     mallopts: Vec< Mallopt >,
+    markers: Vec< Marker >,
     timestamp_to_wall_clock: u64,
+    has_wall_clock: bool,
     is_little_endian: bool,
     mmap_operations: Vec< MmapOperation >,
+    /// The indexes of the file-backed maps in `mmap_operations` whose paths weren't resolved yet.
+    unresolved_mmap_paths: Vec< usize >,
     maximum_backtrace_depth: u32,
     previous_backtrace_on_thread: HashMap< u32, Vec< u64 > >,
     string_id_map: HashMap< u32, StringId >,
-    last_timestamp: Timestamp
+    last_timestamp: Timestamp,
+    /// The range of the raw timestamps of the events which are loaded, if limited.
+    range: Option< Range< Timestamp > >,
+    range_state: RangeState,
+    pending_allocations: HashMap< (u64, u64), PendingAllocation >,
+    pending_allocation_counter: u64
 }
 
 fn address_to_frame< F: FnMut( Frame ) >( address_space: &dyn IAddressSpace, interner: &mut StringInterner, address: u64, mut callback: F ) {
@@ -186,6 +354,21 @@ fn get_basename( path: &str ) -> &str {
     &path[ path.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ]
 }
 
+/// Splits the command line, as read from `/proc/self/cmdline`, into its arguments.
+fn parse_cmdline( cmdline: &[u8] ) -> Vec< String > {
+    cmdline.split( |&byte| byte == 0 )
+        .filter( |argument| !argument.is_empty() )
+        .map( |argument| String::from_utf8_lossy( argument ).into_owned() )
+        .collect()
+}
+
+#[test]
+fn test_parse_cmdline() {
+    assert_eq!( parse_cmdline( b"./foo\0--bar\0baz\0" ), vec![ "./foo", "--bar", "baz" ] );
+    assert_eq!( parse_cmdline( b"./foo" ), vec![ "./foo" ] );
+    assert!( parse_cmdline( b"" ).is_empty() );
+}
+
 fn into_key( id: event::AllocationId, pointer: DataPointer ) -> (u64, u64) {
     if !id.is_invalid() && !id.is_untracked() {
         (id.thread, id.allocation)
@@ -194,15 +377,302 @@ fn into_key( id: event::AllocationId, pointer: DataPointer ) -> (u64, u64) {
     }
 }
 
+fn new_address_space( architecture: &str ) -> Box< dyn IAddressSpace > {
+    match architecture {
+        "arm" => Box::new( AddressSpace::< arch::arm::Arch >::new() ),
+        "x86_64" => Box::new( AddressSpace::< arch::amd64::Arch >::new() ),
+        "mips64" => Box::new( AddressSpace::< arch::mips64::Arch >::new() ),
+        "aarch64" => Box::new( AddressSpace::< arch::aarch64::Arch >::new() ),
+        _ => panic!( "Unknown architecture: {}", architecture )
+    }
+}
+
+fn reload_address_space(
+    address_space: &mut dyn IAddressSpace,
+    regions: Vec< Region >,
+    binaries: &HashMap< String, Arc< BinaryData > >,
+    debug_info_index: &mut DebugInfoIndex
+) {
+    address_space.reload( regions, &mut |region, handle| {
+        handle.should_load_frame_descriptions( false );
+
+        let basename = get_basename( &region.name );
+        let debug_binary_data = if let Some( binary_data ) = binaries.get( &region.name ).cloned() {
+            let debug_binary_data = debug_info_index.get( &basename, binary_data.debuglink(), binary_data.build_id() );
+            handle.set_binary( binary_data );
+            debug_binary_data
+        } else {
+            debug_info_index.get( &basename, None, None )
+        };
+
+        if let Some( debug_binary_data ) = debug_binary_data {
+            handle.set_debug_binary( debug_binary_data.clone() );
+        }
+    });
+}
+
+/// Merges the address ranges of every mapping with the same name; the result is sorted by the address.
+///
+/// Anonymous mappings and pseudo-files like `[heap]` are skipped.
+fn merge_module_ranges< 'a >( mappings: impl Iterator< Item = (&'a str, Range< u64 >) > ) -> Vec< (&'a str, Range< u64 >) > {
+    let mut ranges: HashMap< &str, Range< u64 > > = HashMap::new();
+    for (name, range) in mappings {
+        if name.is_empty() || name.starts_with( '[' ) {
+            continue;
+        }
+
+        let entry = ranges.entry( name ).or_insert( range.clone() );
+        entry.start = cmp::min( entry.start, range.start );
+        entry.end = cmp::max( entry.end, range.end );
+    }
+
+    let mut ranges: Vec< _ > = ranges.into_iter().collect();
+    ranges.sort_by_key( |(_, range)| (range.start, range.end) );
+    ranges
+}
+
+fn build_modules( regions: &[Region], binaries: &HashMap< String, Arc< BinaryData > > ) -> Vec< Module > {
+    let mappings = regions.iter().map( |region| (region.name.as_str(), region.start..region.end) );
+    merge_module_ranges( mappings ).into_iter().map( |(name, address_range)| {
+        let build_id = binaries.get( name ).and_then( |binary_data| binary_data.build_id() ).map( |build_id| {
+            build_id.iter().map( |byte| format!( "{:02x}", byte ) ).collect()
+        });
+
+        Module {
+            path: name.to_owned(),
+            address_range,
+            build_id
+        }
+    }).collect()
+}
+
+#[test]
+fn test_merge_module_ranges() {
+    let mappings = vec![
+        ("/usr/lib/libc.so.6", 0x7000..0x8000),
+        ("/usr/bin/app", 0x1000..0x2000),
+        ("", 0x3000..0x4000),
+        ("[heap]", 0x4000..0x5000),
+        ("/usr/bin/app", 0x2000..0x3000),
+        ("/usr/lib/libc.so.6", 0x6000..0x7000),
+        ("[vdso]", 0x9000..0xa000)
+    ];
+
+    assert_eq!( merge_module_ranges( mappings.into_iter() ), vec![
+        ("/usr/bin/app", 0x1000..0x3000),
+        ("/usr/lib/libc.so.6", 0x6000..0x8000)
+    ]);
+}
+
+/// Returns the path of the file which is mapped at the start of the given map, if it's still mapped there.
+fn file_path_of_mapping< 'a >( maps: &'a RangeMap< Region >, mmap: &MemoryMap ) -> Option< &'a str > {
+    let (range, region) = maps.get( mmap.pointer )?;
+    if !region.name.starts_with( '/' ) || region.file_offset + (mmap.pointer - range.start) != mmap.offset {
+        return None;
+    }
+
+    Some( &region.name )
+}
+
+#[test]
+fn test_file_path_of_mapping() {
+    let region = |start: u64, end: u64, file_offset: u64, name: &str| {
+        let region = Region {
+            start,
+            end,
+            is_read: true,
+            is_write: false,
+            is_executable: false,
+            is_shared: false,
+            file_offset,
+            major: 0,
+            minor: 0,
+            inode: 1,
+            name: name.to_owned()
+        };
+        (start..end, region)
+    };
+
+    let maps = RangeMap::from_vec( vec![
+        region( 0x1000, 0x3000, 0, "/usr/lib/libfoo.so" ),
+        region( 0x3000, 0x4000, 0x2000, "/usr/lib/libfoo.so" ),
+        region( 0x5000, 0x6000, 0, "" ),
+        region( 0x7000, 0x8000, 0, "[heap]" )
+    ]);
+
+    let mmap = |pointer: u64, offset: u64| MemoryMap {
+        timestamp: Timestamp::min(),
+        pointer,
+        length: 0x1000,
+        backtrace: BacktraceId::new( 0 ),
+        requested_address: 0,
+        mmap_protection: ProtectionFlags( 0 ),
+        mmap_flags: MapFlags( 0 ),
+        file_descriptor: 3,
+        thread: 1,
+        offset,
+        file_path: None
+    };
+
+    assert_eq!( file_path_of_mapping( &maps, &mmap( 0x1000, 0 ) ), Some( "/usr/lib/libfoo.so" ) );
+    assert_eq!( file_path_of_mapping( &maps, &mmap( 0x3000, 0x2000 ) ), Some( "/usr/lib/libfoo.so" ) );
+
+    // Something else is mapped there now.
+    assert_eq!( file_path_of_mapping( &maps, &mmap( 0x2000, 0 ) ), None );
+    assert_eq!( file_path_of_mapping( &maps, &mmap( 0x5000, 0 ) ), None );
+    assert_eq!( file_path_of_mapping( &maps, &mmap( 0x7000, 0 ) ), None );
+    assert_eq!( file_path_of_mapping( &maps, &mmap( 0x9000, 0 ) ), None );
+}
+
+/// Everything which is necessary to symbolicate addresses after the data was loaded.
+#[derive(Clone)]
+pub(crate) struct SymbolicationContext {
+    pub(crate) regions: Vec< Region >,
+    pub(crate) binaries: HashMap< String, Arc< BinaryData > >
+}
+
+impl SymbolicationContext {
+    /// Combines the contexts of multiple data files; the regions which are present in more than one of them are only kept once.
+    pub(crate) fn merge< 'a, I: IntoIterator< Item = &'a SymbolicationContext > >( contexts: I ) -> Self {
+        let mut regions: Vec< Region > = Vec::new();
+        let mut binaries = HashMap::new();
+        for context in contexts {
+            for region in &context.regions {
+                let is_duplicate = regions.iter().any( |existing| {
+                    existing.start == region.start &&
+                    existing.end == region.end &&
+                    existing.file_offset == region.file_offset &&
+                    existing.name == region.name
+                });
+
+                if !is_duplicate {
+                    regions.push( region.clone() );
+                }
+            }
+
+            for (name, binary) in &context.binaries {
+                binaries.entry( name.clone() ).or_insert_with( || binary.clone() );
+            }
+        }
+
+        SymbolicationContext { regions, binaries }
+    }
+
+    pub(crate) fn modules( &self ) -> Vec< Module > {
+        build_modules( &self.regions, &self.binaries )
+    }
+}
+
+struct ResolvedSymbols {
+    library: Option< String >,
+    function: Option< String >,
+    raw_function: Option< String >,
+    source: Option< String >,
+    line: Option< u32 >,
+    column: Option< u32 >
+}
+
+/// Symbols resolved for frames which were missing them; can be applied with `Data::apply_symbol_updates`.
+pub struct SymbolUpdates {
+    data_id: DataId,
+    frames: Vec< (FrameId, ResolvedSymbols) >
+}
+
+impl SymbolUpdates {
+    pub fn len( &self ) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl Data {
+    /// Tries to symbolicate every frame which has neither a function name nor a source location.
+    ///
+    /// This can be slow, so it doesn't modify the data; the results should be applied with `apply_symbol_updates`.
+    pub fn symbolicate_missing_frames< D: AsRef< OsStr >, I: IntoIterator< Item = D > >( &self, debug_symbols: I ) -> SymbolUpdates {
+        let mut debug_info_index = DebugInfoIndex::new();
+        for path in debug_symbols {
+            debug_info_index.add( path.as_ref() );
+        }
+
+        let mut address_space = new_address_space( &self.architecture );
+        let context = &self.symbolication;
+        reload_address_space( &mut *address_space, context.regions.clone(), &context.binaries, &mut debug_info_index );
+
+        let mut updates = Vec::new();
+        for (frame_id, frame) in self.frames.iter().enumerate() {
+            if frame.function().is_some() || frame.source().is_some() {
+                continue;
+            }
+
+            // The last frame is the one of the function which actually contains this address;
+            // the ones before it, if any, are the ones which were inlined into it.
+            let mut resolved = None;
+            address_space.decode_symbol_while( frame.address().raw(), &mut |frame| {
+                resolved = Some( ResolvedSymbols {
+                    library: frame.library.take().map( |library| get_basename( &library ).to_owned() ),
+                    function: frame.demangled_name.take().map( |name| clean_symbol( name ).into_owned() ),
+                    raw_function: frame.name.take().map( |name| name.into_owned() ),
+                    source: frame.file.take(),
+                    line: frame.line.take().map( |value| value as _ ),
+                    column: frame.column.take().map( |value| value as _ )
+                });
+                true
+            });
+
+            if let Some( resolved ) = resolved {
+                if resolved.function.is_some() || resolved.source.is_some() {
+                    updates.push( (frame_id, resolved) );
+                }
+            }
+        }
+
+        SymbolUpdates {
+            data_id: self.id,
+            frames: updates
+        }
+    }
+
+    /// Applies the symbols resolved by `symbolicate_missing_frames`; returns the number of frames which gained symbols.
+    pub fn apply_symbol_updates( &mut self, updates: SymbolUpdates ) -> usize {
+        assert_eq!( updates.data_id, self.id );
+
+        let count = updates.frames.len();
+        for (frame_id, resolved) in updates.frames {
+            let interner = &mut self.interner;
+            let frame = &mut self.frames[ frame_id ];
+            if let Some( library ) = resolved.library {
+                if frame.library().is_none() {
+                    frame.set_library( interner.get_or_intern( library ) );
+                }
+            }
+            if let Some( function ) = resolved.function {
+                frame.set_function( interner.get_or_intern( function ) );
+            }
+            if let Some( raw_function ) = resolved.raw_function {
+                frame.set_raw_function( interner.get_or_intern( raw_function ) );
+            }
+            if let Some( source ) = resolved.source {
+                frame.set_source( interner.get_or_intern( source ) );
+            }
+            if let Some( line ) = resolved.line {
+                frame.set_line( line );
+            }
+            if let Some( column ) = resolved.column {
+                frame.set_column( column );
+            }
+        }
+
+        count
+    }
+}
+
 impl Loader {
     pub fn new( header: HeaderBody, debug_info_index: DebugInfoIndex ) -> Self {
-        let address_space: Box< dyn IAddressSpace > = match &*header.arch {
-            "arm" => Box::new( AddressSpace::< arch::arm::Arch >::new() ),
-            "x86_64" => Box::new( AddressSpace::< arch::amd64::Arch >::new() ),
-            "mips64" => Box::new( AddressSpace::< arch::mips64::Arch >::new() ),
-            "aarch64" => Box::new( AddressSpace::< arch::aarch64::Arch >::new() ),
-            _ => panic!( "Unknown architecture: {}", header.arch )
-        };
+        let address_space = new_address_space( &header.arch );
 
         let flags = header.flags;
         let timestamp = header.timestamp;
@@ -224,7 +694,7 @@ impl Loader {
             backtrace_remappings: Default::default(),
             group_stats: Default::default(),
             operations: Vec::with_capacity( 100000 ),
-            allocations: Vec::with_capacity( 100000 ),
+            allocations: Vec::with_capacity( 100000 ).into(),
             allocation_map: Default::default(),
             allocation_range_map: RangeMap::new(),
             allocation_range_map_dirty: true,
@@ -242,21 +712,90 @@ impl Loader {
             symbol_new_range: -1_i64 as u64..0,
             marker: 0,
             mallopts: Default::default(),
+            markers: Default::default(),
             timestamp_to_wall_clock: 0,
+            has_wall_clock: false,
             is_little_endian: (flags & HEADER_FLAG_IS_LITTLE_ENDIAN) != 0,
             mmap_operations: Default::default(),
+            unresolved_mmap_paths: Default::default(),
             maximum_backtrace_depth: 0,
             previous_backtrace_on_thread: Default::default(),
             string_id_map: Default::default(),
-            last_timestamp: Timestamp::min()
+            last_timestamp: Timestamp::min(),
+            range: None,
+            range_state: RangeState::Inside,
+            pending_allocations: Default::default(),
+            pending_allocation_counter: 0
         };
 
         loader.update_timestamp_to_wall_clock( timestamp, wall_clock_secs, wall_clock_nsecs );
         loader
     }
 
+    /// Only loads the allocations which were alive at some point during the given range.
+    ///
+    /// The allocations made before the range which are still alive when it starts are included, and so are
+    /// the deallocations of the allocations from the range which happen after it; since only a part of
+    /// the lifetime of those allocations is within the range they're flagged as partially observed.
+    /// Any other allocations, as well as the memory maps, from outside of the range are skipped.
+    pub fn set_range( &mut self, range: LoadRange ) {
+        let initial_timestamp = self.header.initial_timestamp.as_usecs();
+        let from = range.from.map( |from| Timestamp::from_usecs( initial_timestamp + from.as_micros() as u64 ) ).unwrap_or( Timestamp::min() );
+        let to = range.to.map( |to| Timestamp::from_usecs( initial_timestamp + to.as_micros() as u64 ) ).unwrap_or( Timestamp::max() );
+        self.range = Some( from..to );
+        self.range_state = RangeState::Before;
+    }
+
+    /// Updates where we are in relation to the range based on the raw timestamp of an event.
+    ///
+    /// The events aren't necessarily perfectly ordered by their timestamps, so this never goes back.
+    fn update_range_state( &mut self, timestamp: Timestamp ) {
+        let range = match self.range {
+            Some( ref range ) => range.clone(),
+            None => return
+        };
+
+        if self.range_state == RangeState::Before && timestamp >= range.start {
+            self.range_state = RangeState::Inside;
+            self.flush_pending_allocations();
+        }
+
+        if self.range_state == RangeState::Inside && timestamp >= range.end {
+            self.range_state = RangeState::After;
+        }
+    }
+
+    fn flush_pending_allocations( &mut self ) {
+        let mut pending: Vec< _ > = self.pending_allocations.drain().map( |(_, allocation)| allocation ).collect();
+        pending.sort_by_key( |allocation| allocation.order );
+
+        let marker = self.marker;
+        for allocation in pending {
+            let count = self.allocations.len();
+            self.marker = allocation.marker;
+            self.handle_alloc(
+                allocation.id,
+                allocation.timestamp,
+                allocation.pointer,
+                allocation.size,
+                allocation.backtrace,
+                allocation.thread,
+                allocation.flags,
+                allocation.extra_usable_space,
+                allocation.preceding_free_space
+            );
+
+            if self.allocations.len() > count {
+                self.allocations.last_mut().unwrap().flags |= AllocationFlags::IS_PARTIALLY_OBSERVED;
+            }
+        }
+
+        self.marker = marker;
+    }
+
     fn update_timestamp_to_wall_clock( &mut self, timestamp: Timestamp, wall_clock_secs: u64, wall_clock_nsecs: u64 ) {
         self.timestamp_to_wall_clock = Timestamp::from_timespec( wall_clock_secs, wall_clock_nsecs ).as_usecs().wrapping_sub( timestamp.as_usecs() );
+        self.has_wall_clock |= wall_clock_secs != 0 || wall_clock_nsecs != 0;
     }
 
     pub fn load_from_stream_without_debug_info< F: Read + Send + 'static >( fp: F ) -> Result< Data, io::Error > {
@@ -267,9 +806,33 @@ impl Loader {
     }
 
     pub fn load_from_stream< F: Read + Send + 'static, D: AsRef< OsStr >, I: IntoIterator< Item = D > >( fp: F, debug_symbols: I ) -> Result< Data, io::Error > {
+        Loader::load_from_stream_with_progress( fp, debug_symbols, &LoadOptions::default(), |_| {} )
+    }
+
+    /// Loads the data while periodically reporting the progress through the given callback.
+    ///
+    /// The number of bytes read is counted before the data is decompressed.
+    pub fn load_from_stream_with_progress< F, D, I, P >( fp: F, debug_symbols: I, options: &LoadOptions, mut on_progress: P ) -> Result< Data, io::Error >
+        where F: Read + Send + 'static,
+              D: AsRef< OsStr >,
+              I: IntoIterator< Item = D >,
+              P: FnMut( LoadProgress )
+    {
         debug!( "Starting to load data..." );
 
         let start_timestamp = Instant::now();
+        let bytes_read = Arc::new( AtomicU64::new( 0 ) );
+        let fp = CountingReader {
+            inner: fp,
+            bytes_read: bytes_read.clone()
+        };
+
+        let mut report = |phase| on_progress( LoadProgress {
+            phase,
+            bytes_read: bytes_read.load( Ordering::Relaxed )
+        });
+
+        report( LoadPhase::Reading );
         let (header, event_stream) = parse_events( fp )?;
 
         let mut debug_info_index = DebugInfoIndex::new();
@@ -278,18 +841,114 @@ impl Loader {
         }
 
         let mut loader = Loader::new( header, debug_info_index );
+        if let Some( range ) = options.range {
+            loader.set_range( range );
+        }
 
-        for event in event_stream {
+        if let Some( ref directory ) = options.allocations_directory {
+            loader.allocations = MappedVecMut::new_on_disk( directory )?;
+        }
+
+        for (index, event) in event_stream.enumerate() {
             let event = event?;
             loader.process( event );
+
+            if index % PROGRESS_INTERVAL == 0 {
+                report( LoadPhase::Reading );
+            }
         }
 
-        let output = loader.finalize();
+        let output = loader.finalize_with_progress( &mut report );
         let elapsed = start_timestamp.elapsed();
         info!( "Loaded data in {}s {:03}", elapsed.as_secs(), elapsed.subsec_millis() );
         Ok( output )
     }
 
+    /// Loads the data from a stream which is still being written to, e.g. one received from a running process,
+    /// passing a snapshot of everything which was loaded so far to the given callback every `snapshot_interval`.
+    ///
+    /// The first snapshot is taken right after the header is read; the rest are taken on a separate thread,
+    /// so the events keep on being processed while a snapshot is being built, and the longer it takes
+    /// to build one the less often they're taken. If the callback returns an error the loading
+    /// is aborted. If the stream is cut off then the data which was received up to that point is returned,
+    /// since that's how the stream of a process which was killed ends.
+    pub fn load_from_live_stream< F, D, I, S >( fp: F, debug_symbols: I, snapshot_interval: Duration, mut on_snapshot: S ) -> Result< Data, io::Error >
+        where F: Read + Send + 'static,
+              D: AsRef< OsStr >,
+              I: IntoIterator< Item = D >,
+              S: FnMut( Data ) -> Result< (), io::Error > + Send + 'static
+    {
+        let (header, event_stream) = parse_events( fp )?;
+
+        let mut debug_info_index = DebugInfoIndex::new();
+        for path in debug_symbols {
+            debug_info_index.add( path.as_ref() );
+        }
+
+        let loader = Loader::new( header, debug_info_index );
+        let id = loader.id;
+        on_snapshot( loader.snapshot() )?;
+
+        let loader = Arc::new( Mutex::new( (loader, false) ) );
+        let aborted = Arc::new( AtomicBool::new( false ) );
+        let (stop_tx, stop_rx) = mpsc::channel::< () >();
+        let snapshot_thread = {
+            let loader = loader.clone();
+            let aborted = aborted.clone();
+            thread::spawn( move || -> Result< (), io::Error > {
+                let mut wait = snapshot_interval;
+                while let Err( mpsc::RecvTimeoutError::Timeout ) = stop_rx.recv_timeout( wait ) {
+                    let started = Instant::now();
+                    let snapshot = {
+                        let mut guard = loader.lock();
+                        let (ref loader, ref mut is_modified) = *guard;
+                        if !mem::replace( is_modified, false ) {
+                            continue;
+                        }
+
+                        loader.snapshot_loader()
+                    };
+
+                    if let Err( error ) = on_snapshot( snapshot.finalize() ) {
+                        aborted.store( true, Ordering::Relaxed );
+                        return Err( error );
+                    }
+
+                    wait = cmp::max( snapshot_interval, started.elapsed() * LIVE_SNAPSHOT_SLOWDOWN );
+                }
+
+                Ok(())
+            })
+        };
+
+        for event in event_stream {
+            let event = match event {
+                Ok( event ) => event,
+                Err( error ) => {
+                    warn!( "The live stream of {} was cut off: {}", id, error );
+                    break;
+                }
+            };
+
+            let mut guard = loader.lock();
+            guard.0.process( event );
+            guard.1 = true;
+            if aborted.load( Ordering::Relaxed ) {
+                break;
+            }
+        }
+
+        mem::drop( stop_tx );
+        snapshot_thread.join().map_err( |_| io::Error::new( io::ErrorKind::Other, "the snapshot thread panicked" ) )??;
+
+        let (loader, _) = match Arc::try_unwrap( loader ) {
+            Ok( loader ) => loader.into_inner(),
+            Err( _ ) => unreachable!()
+        };
+
+        Ok( loader.finalize() )
+    }
+
     fn shift_timestamp( &self, timestamp: Timestamp ) -> Timestamp {
         Timestamp::from_usecs( timestamp.as_usecs().wrapping_add( self.timestamp_to_wall_clock ) )
     }
@@ -339,6 +998,20 @@ impl Loader {
         extra_usable_space: u32,
         preceding_free_space: u64
     ) {
+        match self.range_state {
+            RangeState::Before => {
+                let order = self.pending_allocation_counter;
+                self.pending_allocation_counter += 1;
+                self.pending_allocations.insert( into_key( id, pointer ), PendingAllocation {
+                    order, id, timestamp, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space,
+                    marker: self.marker
+                });
+                return;
+            },
+            RangeState::After => return,
+            RangeState::Inside => {}
+        }
+
         self.last_timestamp = std::cmp::max( self.last_timestamp, timestamp );
 
         let flags = self.parse_flags( backtrace, flags );
@@ -399,18 +1072,35 @@ impl Loader {
         backtrace: Option< BacktraceId >,
         thread: ThreadId
     ) {
-        self.last_timestamp = std::cmp::max( self.last_timestamp, timestamp );
-
         let key = into_key( id, pointer );
+        if self.range_state == RangeState::Before {
+            self.pending_allocations.remove( &key );
+            return;
+        }
+
+        if self.range_state == RangeState::Inside {
+            self.last_timestamp = std::cmp::max( self.last_timestamp, timestamp );
+        }
+
         let allocation_id = match self.allocation_map.remove( &key ) {
             Some( id ) => id,
             None => {
-                debug!( "Unknown deallocation of 0x{:016X} at backtrace = {:?}", pointer, backtrace );
+                if self.range_state == RangeState::Inside {
+                    debug!( "Unknown deallocation of 0x{:016X} at backtrace = {:?}", pointer, backtrace );
+                }
                 return;
             }
         };
 
+        if self.range_state == RangeState::After {
+            self.last_timestamp = std::cmp::max( self.last_timestamp, timestamp );
+        }
+
         let allocation = &mut self.allocations[ allocation_id.raw() as usize ];
+        if self.range_state == RangeState::After {
+            allocation.flags |= AllocationFlags::IS_PARTIALLY_OBSERVED;
+        }
+
         allocation.deallocation = Some( Deallocation { timestamp, thread, backtrace } );
         self.total_freed += allocation.size;
         self.total_freed_count += 1;
@@ -441,6 +1131,21 @@ impl Loader {
         extra_usable_space: u32,
         preceding_free_space: u64
     ) {
+        match self.range_state {
+            RangeState::Before => {
+                // The chain is lost here, but that's fine since we'll only see a part of it anyway.
+                if self.pending_allocations.remove( &into_key( id, old_pointer ) ).is_some() {
+                    self.handle_alloc( id, timestamp, new_pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space );
+                }
+                return;
+            },
+            RangeState::After => {
+                self.handle_free( id, timestamp, old_pointer, Some( backtrace ), thread );
+                return;
+            },
+            RangeState::Inside => {}
+        }
+
         self.last_timestamp = std::cmp::max( self.last_timestamp, timestamp );
 
         let old_key = into_key( id, old_pointer );
@@ -556,23 +1261,31 @@ impl Loader {
         }
 
         self.address_space_needs_reloading = false;
-        let binaries = &self.binaries;
-        let debug_info_index = &mut self.debug_info_index;
         let regions: Vec< Region > = self.maps.values().cloned().collect();
-        self.address_space.reload( regions, &mut |region, handle| {
-            handle.should_load_frame_descriptions( false );
-
-            let basename = get_basename( &region.name );
-            let debug_binary_data = if let Some( binary_data ) = binaries.get( &region.name ).cloned() {
-                let debug_binary_data = debug_info_index.get( &basename, binary_data.debuglink(), binary_data.build_id() );
-                handle.set_binary( binary_data );
-                debug_binary_data
-            } else {
-                debug_info_index.get( &basename, None, None )
+        reload_address_space( &mut *self.address_space, regions, &self.binaries, &mut self.debug_info_index );
+    }
+
+    /// Resolves the paths of the file-backed maps using the current `/proc/self/maps`.
+    ///
+    /// We don't know which file a given file descriptor refers to, so we can only
+    /// resolve the maps which are still around when the next snapshot of the maps is taken,
+    /// e.g. the ones created while loading a library.
+    fn resolve_mmap_paths( &mut self ) {
+        let maps = &self.maps;
+        let interner = self.interner.get_mut();
+        let mmap_operations = &mut self.mmap_operations;
+        self.unresolved_mmap_paths.retain( |&index| {
+            let mmap = match mmap_operations[ index ] {
+                MmapOperation::Mmap( ref mut mmap ) => mmap,
+                _ => unreachable!()
             };
 
-            if let Some( debug_binary_data ) = debug_binary_data {
-                handle.set_debug_binary( debug_binary_data.clone() );
+            match file_path_of_mapping( maps, mmap ) {
+                Some( path ) => {
+                    mmap.file_path = Some( interner.get_or_intern( path ) );
+                    false
+                },
+                None => true
             }
         });
     }
@@ -761,6 +1474,22 @@ impl Loader {
     }
 
     pub fn process( &mut self, event: Event ) {
+        match event {
+            Event::Alloc { timestamp, .. } |
+            Event::AllocEx { timestamp, .. } |
+            Event::Realloc { timestamp, .. } |
+            Event::ReallocEx { timestamp, .. } |
+            Event::Free { timestamp, .. } |
+            Event::FreeEx { timestamp, .. } |
+            Event::MemoryMap { timestamp, .. } |
+            Event::MemoryUnmap { timestamp, .. } |
+            Event::MemoryRemap { timestamp, .. } |
+            Event::MemoryProtect { timestamp, .. } => {
+                self.update_range_state( timestamp );
+            },
+            _ => {}
+        }
+
         match event {
             Event::Header( header ) => {
                 assert_eq!( header.id, self.header.id );
@@ -791,6 +1520,7 @@ impl Loader {
                 }
 
                 self.maps = RangeMap::from_vec( maps );
+                self.resolve_mmap_paths();
                 let binaries: Vec< _ > = self.binaries.values().cloned().collect();
                 for binary_data in binaries {
                     self.scan_for_symbols( &binary_data );
@@ -897,10 +1627,16 @@ impl Loader {
                     mmap_flags: MapFlags( mmap_flags ),
                     file_descriptor,
                     thread,
-                    offset
+                    offset,
+                    file_path: None
                 };
 
-                self.mmap_operations.push( MmapOperation::Mmap( mmap ) );
+                if self.range_state == RangeState::Inside {
+                    if !mmap.mmap_flags.is_anonymous() {
+                        self.unresolved_mmap_paths.push( self.mmap_operations.len() );
+                    }
+                    self.mmap_operations.push( MmapOperation::Mmap( mmap ) );
+                }
             },
             Event::MemoryUnmap { timestamp, pointer, length, backtrace, thread } => {
                 let timestamp = self.shift_timestamp( timestamp );
@@ -913,7 +1649,43 @@ impl Loader {
                     thread
                 };
 
-                self.mmap_operations.push( MmapOperation::Munmap( munmap ) );
+                if self.range_state == RangeState::Inside {
+                    self.mmap_operations.push( MmapOperation::Munmap( munmap ) );
+                }
+            },
+            Event::MemoryRemap { timestamp, old_pointer, old_length, new_pointer, new_length, backtrace, remap_flags, thread } => {
+                let timestamp = self.shift_timestamp( timestamp );
+                let backtrace = self.lookup_backtrace( backtrace ).unwrap();
+                let remap = MemoryRemap {
+                    timestamp,
+                    old_pointer,
+                    old_length,
+                    new_pointer,
+                    new_length,
+                    backtrace,
+                    remap_flags: RemapFlags( remap_flags ),
+                    thread
+                };
+
+                if self.range_state == RangeState::Inside {
+                    self.mmap_operations.push( MmapOperation::Mremap( remap ) );
+                }
+            },
+            Event::MemoryProtect { timestamp, pointer, length, backtrace, protection, thread } => {
+                let timestamp = self.shift_timestamp( timestamp );
+                let backtrace = self.lookup_backtrace( backtrace ).unwrap();
+                let protect = MemoryProtect {
+                    timestamp,
+                    pointer,
+                    length,
+                    backtrace,
+                    protection: ProtectionFlags( protection ),
+                    thread
+                };
+
+                if self.range_state == RangeState::Inside {
+                    self.mmap_operations.push( MmapOperation::Mprotect( protect ) );
+                }
             },
             Event::Mallopt { timestamp, backtrace, thread, param, value, result } => {
                 let timestamp = self.shift_timestamp( timestamp );
@@ -936,6 +1708,29 @@ impl Loader {
             },
             Event::Marker { value } => {
                 self.marker = value;
+                // Old-style markers don't have a timestamp, so just use the last one we've seen.
+                let timestamp = std::cmp::max( self.last_timestamp, self.shift_timestamp( self.header.initial_timestamp ) );
+                self.markers.push( Marker {
+                    timestamp,
+                    backtrace: None,
+                    thread: 0,
+                    value
+                });
+            },
+            Event::MarkerEx { timestamp, backtrace, thread, value } => {
+                let timestamp = self.shift_timestamp( timestamp );
+                let backtrace_id = self.lookup_backtrace( backtrace );
+                if backtrace_id.is_none() {
+                    warn!( "Marker {} has an unknown backtrace: {}", value, backtrace );
+                }
+
+                self.marker = value;
+                self.markers.push( Marker {
+                    timestamp,
+                    backtrace: backtrace_id,
+                    thread,
+                    value
+                });
             },
             Event::MemoryDump { address, length, data } => {
                 if true {
@@ -987,7 +1782,47 @@ impl Loader {
         }
     }
 
-    pub fn finalize( mut self ) -> Data {
+    pub fn finalize( self ) -> Data {
+        self.finalize_with_progress( &mut |_| {} )
+    }
+
+    /// Builds the `Data` out of the events which were processed so far without finishing the loading,
+    /// so that the data which is still being received can already be looked at.
+    ///
+    /// This copies everything, so it shouldn't be called too often.
+    pub fn snapshot( &self ) -> Data {
+        self.snapshot_loader().finalize()
+    }
+
+    fn snapshot_loader( &self ) -> Loader {
+        let mut loader = Loader::new( self.header.clone(), DebugInfoIndex::new() );
+        loader.interner = self.interner.clone();
+        loader.binaries = self.binaries.clone();
+        loader.maps = self.maps.clone();
+        loader.backtraces = self.backtraces.clone();
+        loader.backtraces_storage = self.backtraces_storage.clone();
+        loader.group_stats = self.group_stats.clone();
+        loader.operations = self.operations.clone();
+        loader.allocations = self.allocations.clone();
+        loader.allocations_by_backtrace = self.allocations_by_backtrace.clone();
+        loader.frames = self.frames.clone();
+        loader.total_allocated = self.total_allocated;
+        loader.total_allocated_count = self.total_allocated_count;
+        loader.total_freed = self.total_freed;
+        loader.total_freed_count = self.total_freed_count;
+        loader.mallopts = self.mallopts.clone();
+        loader.markers = self.markers.clone();
+        loader.timestamp_to_wall_clock = self.timestamp_to_wall_clock;
+        loader.has_wall_clock = self.has_wall_clock;
+        loader.mmap_operations = self.mmap_operations.clone();
+        loader.maximum_backtrace_depth = self.maximum_backtrace_depth;
+        loader.last_timestamp = self.last_timestamp;
+        loader
+    }
+
+    fn finalize_with_progress( mut self, report: &mut dyn FnMut( LoadPhase ) ) -> Data {
+        report( LoadPhase::Sorting );
+
         let mut chains = HashMap::new();
         for index in 0..self.allocations.len() {
             let mut allocation = &self.allocations[ index ];
@@ -1054,6 +1889,8 @@ impl Loader {
         self.operations.par_sort_by_key( |(timestamp, _)| *timestamp );
         let operations: Vec< _ > = self.operations.into_iter().map( |(_, op)| op ).collect();
 
+        report( LoadPhase::Indexing );
+
         let mut current_total_usage_by_backtrace = Vec::new();
         current_total_usage_by_backtrace.resize( self.backtraces.len(), 0 );
 
@@ -1086,6 +1923,7 @@ impl Loader {
         self.backtraces.shrink_to_fit();
         self.backtraces_storage.shrink_to_fit();
         self.mallopts.shrink_to_fit();
+        self.markers.shrink_to_fit();
         self.mmap_operations.shrink_to_fit();
         self.group_stats.shrink_to_fit();
 
@@ -1109,15 +1947,20 @@ impl Loader {
 
         let last_timestamp = self.group_stats.iter().map( |stats| stats.last_allocation ).max().unwrap_or( initial_timestamp );
         let last_timestamp = std::cmp::max( self.last_timestamp, last_timestamp );
+        let regions: Vec< _ > = self.maps.values().cloned().collect();
+        let modules = build_modules( &regions, &self.binaries );
         Data {
             id: self.id,
             initial_timestamp,
             last_timestamp,
             executable: String::from_utf8_lossy( &self.header.executable ).into_owned(),
             architecture: self.header.arch,
+            pid: Some( self.header.pid ).filter( |&pid| pid != 0 ),
+            cmdline: parse_cmdline( &self.header.cmdline ),
+            has_wall_clock: self.has_wall_clock,
             pointer_size: self.header.pointer_size as _,
             interner: self.interner.into_inner(),
-            allocations: self.allocations,
+            allocations: self.allocations.into(),
             sorted_by_timestamp,
             sorted_by_address,
             sorted_by_size,
@@ -1131,10 +1974,17 @@ impl Loader {
             total_freed: self.total_freed,
             total_freed_count: self.total_freed_count,
             mallopts: self.mallopts,
+            markers: self.markers,
             mmap_operations: self.mmap_operations,
             maximum_backtrace_depth: self.maximum_backtrace_depth,
             group_stats: self.group_stats,
-            chains
+            chains,
+            modules,
+            symbolication: SymbolicationContext {
+                regions,
+                binaries: self.binaries
+            },
+            sources: Vec::new()
         }
     }
 }
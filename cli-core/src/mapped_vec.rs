@@ -0,0 +1,306 @@
+use std::cmp;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new( 0 );
+
+/// How many bytes a `MappedVecMut` grows by at least.
+const MINIMUM_GROWTH: usize = 16 * 1024 * 1024;
+
+/// Creates a new file in the given directory which is deleted right away,
+/// so it's only kept around for as long as it's open.
+fn create_anonymous_file( directory: &Path ) -> io::Result< File > {
+    let filename = format!( "bytehound-{}-{}.tmp", std::process::id(), COUNTER.fetch_add( 1, Ordering::SeqCst ) );
+    let path = directory.join( filename );
+    let fp = OpenOptions::new().read( true ).write( true ).create_new( true ).open( &path )?;
+    fs::remove_file( &path )?;
+    Ok( fp )
+}
+
+/// Drops the pages of the map which are resident; since the map is backed by a file
+/// they'll be paged back in when they're accessed.
+fn release_resident_pages( map: &[u8] ) {
+    unsafe {
+        libc::madvise( map.as_ptr() as *mut libc::c_void, map.len(), libc::MADV_DONTNEED );
+    }
+}
+
+/// A read-only array which is either kept in memory, or in a memory mapped file
+/// from where it can be paged in and out by the kernel as needed.
+///
+/// Cloning a mapped array is cheap since the map itself is shared.
+pub enum MappedVec< T > {
+    InMemory( Vec< T > ),
+    Mapped {
+        map: Arc< memmap::Mmap >,
+        length: usize,
+        marker: PhantomData< T >
+    }
+}
+
+impl< T > Default for MappedVec< T > {
+    fn default() -> Self {
+        MappedVec::InMemory( Vec::new() )
+    }
+}
+
+impl< T: Clone > Clone for MappedVec< T > {
+    fn clone( &self ) -> Self {
+        match *self {
+            MappedVec::InMemory( ref vec ) => MappedVec::InMemory( vec.clone() ),
+            MappedVec::Mapped { ref map, length, .. } => MappedVec::Mapped {
+                map: map.clone(),
+                length,
+                marker: PhantomData
+            }
+        }
+    }
+}
+
+impl< T > From< Vec< T > > for MappedVec< T > {
+    fn from( vec: Vec< T > ) -> Self {
+        MappedVec::InMemory( vec )
+    }
+}
+
+impl< T > Deref for MappedVec< T > {
+    type Target = [T];
+
+    #[inline]
+    fn deref( &self ) -> &[T] {
+        match *self {
+            MappedVec::InMemory( ref vec ) => vec,
+            MappedVec::Mapped { ref map, length, .. } => unsafe {
+                slice::from_raw_parts( map.as_ptr() as *const T, length )
+            }
+        }
+    }
+}
+
+impl< T: Copy > MappedVec< T > {
+    /// Moves the contents out of memory and into a file created in the given directory.
+    ///
+    /// The file is deleted right away, so it's only kept around for as long as it's mapped.
+    /// Since it's only ever read by the same process which wrote it there's no need
+    /// for any serialization; the elements are simply copied as-is.
+    pub fn move_to_disk( &mut self, directory: &Path ) -> io::Result< () > {
+        let vec = match *self {
+            MappedVec::InMemory( ref vec ) if !vec.is_empty() && mem::size_of::< T >() != 0 => vec,
+            _ => return Ok(())
+        };
+
+        let fp = create_anonymous_file( directory )?;
+        let size = vec.len() * mem::size_of::< T >();
+        fp.set_len( size as u64 )?;
+
+        let mut map = unsafe { memmap::MmapMut::map_mut( &fp ) }?;
+        unsafe {
+            ptr::copy_nonoverlapping( vec.as_ptr(), map.as_mut_ptr() as *mut T, vec.len() );
+        }
+
+        let map = map.make_read_only()?;
+
+        // The pages we've just written to are still resident, so they'd be counted as our memory
+        // until the kernel decides to reclaim them.
+        release_resident_pages( &map );
+
+        *self = MappedVec::Mapped {
+            length: vec.len(),
+            map: Arc::new( map ),
+            marker: PhantomData
+        };
+
+        Ok(())
+    }
+}
+
+/// A growable array which is either kept in memory, or in a memory mapped file right from the start,
+/// so that it never has to be fully resident in memory while it's being built.
+///
+/// Once it's built it can be turned into a `MappedVec` without copying it.
+pub enum MappedVecMut< T > {
+    InMemory( Vec< T > ),
+    Mapped {
+        fp: File,
+        map: memmap::MmapMut,
+        length: usize,
+        marker: PhantomData< T >
+    }
+}
+
+impl< T > Default for MappedVecMut< T > {
+    fn default() -> Self {
+        MappedVecMut::InMemory( Vec::new() )
+    }
+}
+
+impl< T > From< Vec< T > > for MappedVecMut< T > {
+    fn from( vec: Vec< T > ) -> Self {
+        MappedVecMut::InMemory( vec )
+    }
+}
+
+impl< T: Copy > Clone for MappedVecMut< T > {
+    /// The clone is always kept in memory.
+    fn clone( &self ) -> Self {
+        MappedVecMut::InMemory( self.to_vec() )
+    }
+}
+
+impl< T > Deref for MappedVecMut< T > {
+    type Target = [T];
+
+    #[inline]
+    fn deref( &self ) -> &[T] {
+        match *self {
+            MappedVecMut::InMemory( ref vec ) => vec,
+            MappedVecMut::Mapped { ref map, length, .. } => unsafe {
+                slice::from_raw_parts( map.as_ptr() as *const T, length )
+            }
+        }
+    }
+}
+
+impl< T > DerefMut for MappedVecMut< T > {
+    #[inline]
+    fn deref_mut( &mut self ) -> &mut [T] {
+        match *self {
+            MappedVecMut::InMemory( ref mut vec ) => vec,
+            MappedVecMut::Mapped { ref mut map, length, .. } => unsafe {
+                slice::from_raw_parts_mut( map.as_mut_ptr() as *mut T, length )
+            }
+        }
+    }
+}
+
+impl< T: Copy > MappedVecMut< T > {
+    /// Creates an empty array which is kept in a file created in the given directory.
+    ///
+    /// The file is deleted right away, so it's only kept around for as long as it's mapped.
+    pub fn new_on_disk( directory: &Path ) -> io::Result< Self > {
+        let fp = create_anonymous_file( directory )?;
+        let map = Self::map( &fp, 0 )?;
+        Ok( MappedVecMut::Mapped { fp, map, length: 0, marker: PhantomData } )
+    }
+
+    fn map( fp: &File, capacity: usize ) -> io::Result< memmap::MmapMut > {
+        // The map can't be empty, and a zero sized type doesn't need any space.
+        let size = cmp::max( capacity * mem::size_of::< T >(), 1 );
+        fp.set_len( size as u64 )?;
+        unsafe { memmap::MmapMut::map_mut( fp ) }
+    }
+
+    /// Appends an element at the end.
+    ///
+    /// Panics if the file can't be grown, just as a `Vec` would abort if it ran out of memory.
+    pub fn push( &mut self, value: T ) {
+        match *self {
+            MappedVecMut::InMemory( ref mut vec ) => vec.push( value ),
+            MappedVecMut::Mapped { ref fp, ref mut map, ref mut length, .. } => {
+                let capacity = if mem::size_of::< T >() == 0 { usize::MAX } else { map.len() / mem::size_of::< T >() };
+                if *length == capacity {
+                    let minimum_growth = cmp::max( MINIMUM_GROWTH / cmp::max( mem::size_of::< T >(), 1 ), 1 );
+                    let capacity = capacity + cmp::max( capacity, minimum_growth );
+
+                    // The pages of the old map which are still resident stop being counted as ours once it's unmapped.
+                    *map = Self::map( fp, capacity ).expect( "failed to grow a memory mapped array" );
+                }
+
+                unsafe {
+                    ptr::write( (map.as_mut_ptr() as *mut T).add( *length ), value );
+                }
+
+                *length += 1;
+            }
+        }
+    }
+
+    pub fn shrink_to_fit( &mut self ) {
+        if let MappedVecMut::InMemory( ref mut vec ) = *self {
+            vec.shrink_to_fit();
+        }
+    }
+}
+
+impl< T: Copy > From< MappedVecMut< T > > for MappedVec< T > {
+    fn from( vec: MappedVecMut< T > ) -> Self {
+        let (fp, map, length) = match vec {
+            MappedVecMut::InMemory( vec ) => return MappedVec::InMemory( vec ),
+            MappedVecMut::Mapped { fp, map, length, .. } => (fp, map, length)
+        };
+
+        if length == 0 {
+            return MappedVec::InMemory( Vec::new() );
+        }
+
+        // Nothing past the end is ever accessed, so there's no need to map the file again after it's shrunk.
+        let size = length * mem::size_of::< T >();
+        let map = map.make_read_only().expect( "failed to make a memory mapped array read-only" );
+        if let Err( error ) = fp.set_len( size as u64 ) {
+            warn!( "Failed to truncate a memory mapped array: {}", error );
+        }
+
+        release_resident_pages( &map );
+        MappedVec::Mapped {
+            map: Arc::new( map ),
+            length,
+            marker: PhantomData
+        }
+    }
+}
+
+impl< T > MappedVec< T > {
+    /// How much memory this takes, not counting the parts which are mapped from the disk.
+    pub fn memory_usage( &self ) -> usize {
+        match *self {
+            MappedVec::InMemory( ref vec ) => vec.capacity() * mem::size_of::< T >(),
+            MappedVec::Mapped { .. } => 0
+        }
+    }
+}
+
+#[test]
+fn test_move_to_disk() {
+    let values: Vec< (u64, u8) > = (0..100000).map( |index| (index, index as u8) ).collect();
+    let mut vec = MappedVec::from( values.clone() );
+    assert_ne!( vec.memory_usage(), 0 );
+
+    vec.move_to_disk( &std::env::temp_dir() ).unwrap();
+    assert_eq!( vec.memory_usage(), 0 );
+    assert_eq!( &vec[..], &values[..] );
+
+    let mut empty: MappedVec< u64 > = MappedVec::default();
+    empty.move_to_disk( &std::env::temp_dir() ).unwrap();
+    assert!( empty.is_empty() );
+}
+
+#[test]
+fn test_mapped_vec_mut() {
+    // Big enough to have to grow the file a few times.
+    let values: Vec< [u64; 512] > = (0..10000).map( |index| [index; 512] ).collect();
+    let mut vec = MappedVecMut::new_on_disk( &std::env::temp_dir() ).unwrap();
+    for &value in &values {
+        vec.push( value );
+    }
+
+    vec[ 1 ][ 0 ] = 100;
+    assert_eq!( vec.len(), values.len() );
+    assert_eq!( vec.clone()[ 1 ][ 0 ], 100 );
+
+    let vec: MappedVec< _ > = vec.into();
+    assert_eq!( vec.memory_usage(), 0 );
+    assert_eq!( vec[ 1 ][ 0 ], 100 );
+    assert!( vec[ 1 ][ 1.. ].iter().all( |&value| value == 1 ) );
+    assert_eq!( &vec[ 2.. ], &values[ 2.. ] );
+
+    let empty: MappedVec< u64 > = MappedVecMut::new_on_disk( &std::env::temp_dir() ).unwrap().into();
+    assert!( empty.is_empty() );
+}
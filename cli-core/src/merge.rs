@@ -0,0 +1,392 @@
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use ahash::AHashMap as HashMap;
+use rayon::prelude::*;
+
+use crate::data::{
+    Allocation,
+    AllocationChain,
+    AllocationId,
+    BacktraceId,
+    Data,
+    DataId,
+    Deallocation,
+    FrameId,
+    GroupStatistics,
+    Mallopt,
+    Marker,
+    MemoryMap,
+    MemoryProtect,
+    MemoryRemap,
+    MemoryUnmap,
+    MmapOperation,
+    OperationId,
+    StringId,
+    StringInterner,
+    Timestamp
+};
+use crate::frame::Frame;
+use crate::loader::SymbolicationContext;
+use crate::vecvec::DenseVecVec;
+
+#[derive(Debug)]
+pub enum MergeError {
+    NotEnoughData,
+    DuplicateData( DataId ),
+    ExecutableMismatch( String, String ),
+    ArchitectureMismatch( String, String )
+}
+
+impl fmt::Display for MergeError {
+    fn fmt( &self, formatter: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            MergeError::NotEnoughData => write!( formatter, "at least two data files are needed to merge them" ),
+            MergeError::DuplicateData( id ) => write!( formatter, "data {} was given more than once", id ),
+            MergeError::ExecutableMismatch( ref a, ref b ) => write!( formatter, "cannot merge the data of different executables: '{}' and '{}'", a, b ),
+            MergeError::ArchitectureMismatch( ref a, ref b ) => write!( formatter, "cannot merge the data of different architectures: '{}' and '{}'", a, b )
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+/// Derives the ID of the merged data from the IDs of its sources, so merging the same data twice gives the same ID.
+fn merged_id( sources: &[&Data] ) -> DataId {
+    let mut hasher = DefaultHasher::new();
+    "merged".hash( &mut hasher );
+    for data in sources {
+        data.id().hash( &mut hasher );
+    }
+
+    let a = hasher.finish();
+    a.hash( &mut hasher );
+    let b = hasher.finish();
+    DataId::new( a, b )
+}
+
+/// Returns the value which every one of the sources has, or `None` if they differ.
+fn common_value< T: PartialEq >( sources: &[&Data], callback: impl Fn( &Data ) -> T ) -> Option< T > {
+    let value = callback( sources[ 0 ] );
+    if sources[ 1.. ].iter().all( |data| callback( data ) == value ) {
+        Some( value )
+    } else {
+        None
+    }
+}
+
+fn remap_string( interner: &mut StringInterner, source: &Data, cache: &mut HashMap< StringId, StringId >, id: StringId ) -> StringId {
+    *cache.entry( id ).or_insert_with( || interner.get_or_intern( source.interner().resolve( id ).unwrap() ) )
+}
+
+fn operation_timestamp( allocations: &[Allocation], op: OperationId ) -> Timestamp {
+    let allocation = &allocations[ op.id().raw() as usize ];
+    if op.is_allocation() || op.is_reallocation() {
+        allocation.timestamp
+    } else {
+        allocation.deallocation.as_ref().unwrap().timestamp
+    }
+}
+
+fn cmp_by_time( allocations: &[Allocation], a_id: AllocationId, b_id: AllocationId ) -> std::cmp::Ordering {
+    let a_alloc = &allocations[ a_id.raw() as usize ];
+    let b_alloc = &allocations[ b_id.raw() as usize ];
+    a_alloc.timestamp.cmp( &b_alloc.timestamp ).then_with( ||
+        a_id.raw().cmp( &b_id.raw() )
+    )
+}
+
+impl Data {
+    /// Merges the data of multiple processes of the same executable into a single data set,
+    /// e.g. of the workers forked by a single parent process.
+    ///
+    /// The identical frames and backtraces are deduplicated so that the allocations from every
+    /// process which were made from the same place end up in the same group. The timestamps
+    /// are already on the same clock, since they're all relative to the wall clock.
+    pub fn merge( sources: &[&Data] ) -> Result< Data, MergeError > {
+        if sources.len() < 2 {
+            return Err( MergeError::NotEnoughData );
+        }
+
+        for (index, data) in sources.iter().enumerate() {
+            if sources[ ..index ].iter().any( |other| other.id() == data.id() ) {
+                return Err( MergeError::DuplicateData( data.id() ) );
+            }
+
+            let first = sources[ 0 ];
+            if data.executable() != first.executable() {
+                return Err( MergeError::ExecutableMismatch( first.executable().to_owned(), data.executable().to_owned() ) );
+            }
+
+            if data.architecture() != first.architecture() || data.pointer_size() != first.pointer_size() {
+                return Err( MergeError::ArchitectureMismatch( first.architecture().to_owned(), data.architecture().to_owned() ) );
+            }
+        }
+
+        let mut interner = StringInterner::new();
+        let mut frames: Vec< Frame > = Vec::new();
+        let mut frame_to_id: HashMap< Frame, FrameId > = HashMap::new();
+        let mut backtraces = Vec::new();
+        let mut backtraces_storage = Vec::new();
+        let mut backtrace_to_id: HashMap< Vec< FrameId >, Vec< BacktraceId > > = HashMap::new();
+        let mut backtrace_maps = Vec::with_capacity( sources.len() );
+
+        for data in sources {
+            let mut strings = HashMap::new();
+            let mut frame_map = Vec::with_capacity( data.frames.len() );
+            for frame in &data.frames {
+                let mut remap = |id| remap_string( &mut interner, data, &mut strings, id );
+                let mut key = Frame::new_unknown( frame.address() );
+                key.set_is_inline( frame.is_inline() );
+                if let Some( id ) = frame.library() { key.set_library( remap( id ) ); }
+                if let Some( id ) = frame.function() { key.set_function( remap( id ) ); }
+                if let Some( id ) = frame.raw_function() { key.set_raw_function( remap( id ) ); }
+                if let Some( id ) = frame.source() { key.set_source( remap( id ) ); }
+                if let Some( line ) = frame.line() { key.set_line( line ); }
+                if let Some( column ) = frame.column() { key.set_column( column ); }
+
+                let frame_id = *frame_to_id.entry( key ).or_insert_with_key( |key| {
+                    frames.push( key.clone() );
+                    frames.len() - 1
+                });
+
+                frames[ frame_id ].increment_count( frame.count() );
+                frame_map.push( frame_id );
+            }
+
+            // The same frames can appear in more than one backtrace of a single data file, e.g. when
+            // the frames were skipped or couldn't be resolved, and those have to be kept separate.
+            let mut occurrences: HashMap< Vec< FrameId >, usize > = HashMap::new();
+            let mut backtrace_map = Vec::with_capacity( data.backtraces.len() );
+            for &(offset, length) in &data.backtraces {
+                let key: Vec< FrameId > = data.backtraces_storage[ offset as usize..(offset + length) as usize ].iter()
+                    .map( |&frame_id| frame_map[ frame_id ] )
+                    .collect();
+
+                let occurrence = occurrences.entry( key.clone() ).or_insert( 0 );
+                let candidates = backtrace_to_id.entry( key.clone() ).or_insert_with( Vec::new );
+                if *occurrence == candidates.len() {
+                    backtraces.push( (backtraces_storage.len() as u32, key.len() as u32) );
+                    backtraces_storage.extend_from_slice( &key );
+                    candidates.push( BacktraceId::new( backtraces.len() as u32 - 1 ) );
+                }
+
+                backtrace_map.push( candidates[ *occurrence ] );
+                *occurrence += 1;
+            }
+
+            backtrace_maps.push( backtrace_map );
+        }
+
+        let mut allocations = Vec::with_capacity( sources.iter().map( |data| data.allocations.len() ).sum() );
+        let mut operations = Vec::with_capacity( sources.iter().map( |data| data.operations.len() ).sum() );
+        let mut chains = HashMap::new();
+        let mut mallopts = Vec::new();
+        let mut markers = Vec::new();
+        let mut mmap_operations = Vec::new();
+        let mut group_stats: Vec< GroupStatistics > = (0..backtraces.len()).map( |_| GroupStatistics::default() ).collect();
+
+        for (data, backtrace_map) in sources.iter().zip( backtrace_maps ) {
+            let offset = allocations.len() as u64;
+            let remap_allocation = |id: AllocationId| AllocationId::new( id.raw() + offset );
+            let remap_backtrace = |id: BacktraceId| backtrace_map[ id.raw() as usize ];
+            let mut strings = HashMap::new();
+
+            for allocation in data.allocations.iter() {
+                allocations.push( Allocation {
+                    pointer: allocation.pointer,
+                    timestamp: allocation.timestamp,
+                    thread: allocation.thread,
+                    size: allocation.size,
+                    backtrace: remap_backtrace( allocation.backtrace ),
+                    deallocation: allocation.deallocation.as_ref().map( |deallocation| Deallocation {
+                        timestamp: deallocation.timestamp,
+                        thread: deallocation.thread,
+                        backtrace: deallocation.backtrace.map( remap_backtrace )
+                    }),
+                    reallocation: allocation.reallocation.map( remap_allocation ),
+                    reallocated_from: allocation.reallocated_from.map( remap_allocation ),
+                    first_allocation_in_chain: allocation.first_allocation_in_chain.map( remap_allocation ),
+                    position_in_chain: allocation.position_in_chain,
+                    flags: allocation.flags,
+                    extra_usable_space: allocation.extra_usable_space,
+                    marker: allocation.marker,
+                    preceding_free_space: allocation.preceding_free_space
+                });
+            }
+
+            operations.extend( data.operations.iter().map( |&op| {
+                let id = remap_allocation( op.id() );
+                if op.is_allocation() {
+                    OperationId::new_allocation( id )
+                } else if op.is_reallocation() {
+                    OperationId::new_reallocation( id )
+                } else {
+                    OperationId::new_deallocation( id )
+                }
+            }));
+
+            for (&first, chain) in &data.chains {
+                chains.insert( remap_allocation( first ), AllocationChain {
+                    first: remap_allocation( chain.first ),
+                    last: remap_allocation( chain.last ),
+                    length: chain.length
+                });
+            }
+
+            for (backtrace_id, stats) in data.group_stats.iter().enumerate() {
+                let merged = &mut group_stats[ backtrace_map[ backtrace_id ].raw() as usize ];
+                merged.first_allocation = std::cmp::min( merged.first_allocation, stats.first_allocation );
+                merged.last_allocation = std::cmp::max( merged.last_allocation, stats.last_allocation );
+                merged.alloc_count += stats.alloc_count;
+                merged.alloc_size += stats.alloc_size;
+                merged.free_count += stats.free_count;
+                merged.free_size += stats.free_size;
+                merged.min_size = std::cmp::min( merged.min_size, stats.min_size );
+                merged.max_size = std::cmp::max( merged.max_size, stats.max_size );
+            }
+
+            mallopts.extend( data.mallopts.iter().map( |mallopt| Mallopt {
+                timestamp: mallopt.timestamp,
+                backtrace: remap_backtrace( mallopt.backtrace ),
+                thread: mallopt.thread,
+                kind: mallopt.kind,
+                value: mallopt.value,
+                result: mallopt.result
+            }));
+
+            markers.extend( data.markers.iter().map( |marker| Marker {
+                timestamp: marker.timestamp,
+                backtrace: marker.backtrace.map( remap_backtrace ),
+                thread: marker.thread,
+                value: marker.value
+            }));
+
+            mmap_operations.extend( data.mmap_operations.iter().map( |operation| {
+                match *operation {
+                    MmapOperation::Mmap( ref map ) => MmapOperation::Mmap( MemoryMap {
+                        timestamp: map.timestamp,
+                        pointer: map.pointer,
+                        length: map.length,
+                        backtrace: remap_backtrace( map.backtrace ),
+                        requested_address: map.requested_address,
+                        mmap_protection: map.mmap_protection,
+                        mmap_flags: map.mmap_flags,
+                        file_descriptor: map.file_descriptor,
+                        thread: map.thread,
+                        offset: map.offset,
+                        file_path: map.file_path.map( |id| remap_string( &mut interner, data, &mut strings, id ) )
+                    }),
+                    MmapOperation::Munmap( ref unmap ) => MmapOperation::Munmap( MemoryUnmap {
+                        timestamp: unmap.timestamp,
+                        pointer: unmap.pointer,
+                        length: unmap.length,
+                        backtrace: remap_backtrace( unmap.backtrace ),
+                        thread: unmap.thread
+                    }),
+                    MmapOperation::Mremap( ref remap ) => MmapOperation::Mremap( MemoryRemap {
+                        backtrace: remap_backtrace( remap.backtrace ),
+                        ..remap.clone()
+                    }),
+                    MmapOperation::Mprotect( ref protect ) => MmapOperation::Mprotect( MemoryProtect {
+                        backtrace: remap_backtrace( protect.backtrace ),
+                        ..protect.clone()
+                    })
+                }
+            }));
+        }
+
+        // Everything is sorted with a stable sort, so the ties are still broken by the order of the sources.
+        operations.par_sort_by_key( |&op| operation_timestamp( &allocations, op ) );
+        mallopts.sort_by_key( |mallopt| mallopt.timestamp );
+        markers.sort_by_key( |marker| marker.timestamp );
+        mmap_operations.sort_by_key( |operation| operation.timestamp() );
+
+        let indices: Vec< AllocationId > = (0..allocations.len()).map( |id| AllocationId::new( id as _ ) ).collect();
+        let mut sorted_by_timestamp = indices.clone();
+        let mut sorted_by_address = indices.clone();
+        let mut sorted_by_size = indices;
+        sorted_by_timestamp.par_sort_by( |&a_id, &b_id| cmp_by_time( &allocations, a_id, b_id ) );
+        sorted_by_address.par_sort_by_key( |index| allocations[ index.raw() as usize ].pointer );
+        sorted_by_size.par_sort_by_key( |index| allocations[ index.raw() as usize ].size );
+
+        // The peak usage of the groups has to be recalculated since their allocations are now interleaved.
+        let initial_timestamp = sources.iter().map( |data| data.initial_timestamp() ).min().unwrap();
+        let mut current_usage = vec![ 0_isize; backtraces.len() ];
+        let mut max_usage = vec![ (0_isize, initial_timestamp); backtraces.len() ];
+        for &op in &operations {
+            let allocation = &allocations[ op.id().raw() as usize ];
+            let backtrace = allocation.backtrace.raw() as usize;
+            let mut current = current_usage[ backtrace ];
+            if op.is_deallocation() {
+                current -= allocation.usable_size() as isize;
+            } else if op.is_allocation() {
+                current += allocation.usable_size() as isize;
+            } else if op.is_reallocation() {
+                let old_allocation = &allocations[ allocation.reallocated_from.unwrap().raw() as usize ];
+                current += allocation.usable_size() as isize;
+                current -= old_allocation.usable_size() as isize;
+            }
+
+            if current > max_usage[ backtrace ].0 {
+                max_usage[ backtrace ] = (current, allocation.timestamp);
+            }
+
+            current_usage[ backtrace ] = current;
+        }
+
+        for (stats, (_, timestamp)) in group_stats.iter_mut().zip( max_usage ) {
+            stats.max_total_usage_first_seen_at = timestamp;
+        }
+
+        let mut allocation_ids_by_backtrace = vec![ Vec::new(); backtraces.len() ];
+        for &id in &sorted_by_timestamp {
+            allocation_ids_by_backtrace[ allocations[ id.raw() as usize ].backtrace.raw() as usize ].push( id );
+        }
+
+        let mut allocations_by_backtrace = DenseVecVec::new();
+        for allocation_ids in allocation_ids_by_backtrace {
+            allocations_by_backtrace.push( allocation_ids );
+        }
+
+        let symbolication = SymbolicationContext::merge( sources.iter().map( |data| &data.symbolication ) );
+        let modules = symbolication.modules();
+
+        Ok( Data {
+            id: merged_id( sources ),
+            initial_timestamp,
+            last_timestamp: sources.iter().map( |data| data.last_timestamp() ).max().unwrap(),
+            executable: sources[ 0 ].executable.clone(),
+            architecture: sources[ 0 ].architecture.clone(),
+            pid: common_value( sources, |data| data.pid ).flatten(),
+            cmdline: common_value( sources, |data| data.cmdline.clone() ).unwrap_or_default(),
+            has_wall_clock: sources.iter().all( |data| data.has_wall_clock ),
+            pointer_size: sources[ 0 ].pointer_size,
+            interner,
+            operations,
+            allocations: allocations.into(),
+            sorted_by_timestamp,
+            sorted_by_address,
+            sorted_by_size,
+            frames,
+            backtraces,
+            backtraces_storage,
+            allocations_by_backtrace,
+            total_allocated: sources.iter().map( |data| data.total_allocated ).sum(),
+            total_allocated_count: sources.iter().map( |data| data.total_allocated_count ).sum(),
+            total_freed: sources.iter().map( |data| data.total_freed ).sum(),
+            total_freed_count: sources.iter().map( |data| data.total_freed_count ).sum(),
+            mallopts,
+            markers,
+            mmap_operations,
+            maximum_backtrace_depth: sources.iter().map( |data| data.maximum_backtrace_depth ).max().unwrap(),
+            group_stats,
+            chains,
+            symbolication,
+            modules,
+            sources: sources.iter().map( |data| data.id() ).collect()
+        })
+    }
+}
@@ -152,7 +152,10 @@ pub fn postprocess< F, G, D, I  >( ifp: F, ofp: G, debug_symbols: I, anonymize:
             Event::FreeEx { ref mut backtrace, .. } |
             Event::MemoryMap { ref mut backtrace, .. } |
             Event::MemoryUnmap { ref mut backtrace, .. } |
+            Event::MemoryRemap { ref mut backtrace, .. } |
+            Event::MemoryProtect { ref mut backtrace, .. } |
             Event::Mallopt { ref mut backtrace, .. } |
+            Event::MarkerEx { ref mut backtrace, .. } |
             Event::GroupStatistics { ref mut backtrace, .. } => {
                 if let Some( target_backtrace ) = loader.lookup_backtrace( *backtrace ) {
                     *backtrace = target_backtrace.raw() as _;
@@ -1,5 +1,7 @@
 use std::io::{self, Read};
 
+use flate2::read::MultiGzDecoder;
+
 use common::event::{
     Event,
     HeaderBody
@@ -37,8 +39,55 @@ impl< T > Iterator for Iter< T > where T: Read + Send {
     }
 }
 
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Wraps a decoder of a compressed data file so that its errors are reported as such.
+///
+/// This is necessary since the readers further down the line treat an unexpected EOF
+/// as the end of a data file which was cut short, which is fine for a data file which
+/// is still being written to, but not for a broken archive.
+struct Decompressed< T > {
+    inner: T,
+    kind: &'static str
+}
+
+impl< T: Read > Read for Decompressed< T > {
+    fn read( &mut self, buffer: &mut [u8] ) -> io::Result< usize > {
+        self.inner.read( buffer ).map_err( |error| {
+            io::Error::new( io::ErrorKind::InvalidData, format!( "the {}-compressed data file is truncated or corrupt: {}", self.kind, error ) )
+        })
+    }
+}
+
+/// Detects whether the data file was compressed with gzip or zstd, and if so
+/// returns a reader which transparently decompresses it.
+pub fn decompressed< T >( mut fp: T ) -> io::Result< Box< dyn Read + Send > > where T: Read + Send + 'static {
+    let mut magic = [0; 4];
+    let mut length = 0;
+    while length < magic.len() {
+        match fp.read( &mut magic[ length.. ] ) {
+            Ok( 0 ) => break,
+            Ok( count ) => length += count,
+            Err( ref error ) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err( error ) => return Err( error )
+        }
+    }
+
+    let magic = &magic[ ..length ];
+    let fp = io::Cursor::new( magic.to_vec() ).chain( fp );
+    if magic.starts_with( &GZIP_MAGIC ) {
+        Ok( Box::new( Decompressed { inner: MultiGzDecoder::new( fp ), kind: "gzip" } ) )
+    } else if magic == ZSTD_MAGIC {
+        let decoder = zstd::stream::read::Decoder::new( fp )?;
+        Ok( Box::new( Decompressed { inner: decoder, kind: "zstd" } ) )
+    } else {
+        Ok( Box::new( fp ) )
+    }
+}
+
 pub fn parse_events< T >( fp: T ) -> io::Result< (HeaderBody, impl Iterator< Item = io::Result< Event< 'static > > >) > where T: Read + Send + 'static {
-    let mut fp = Lz4Reader::new( fp );
+    let mut fp = Lz4Reader::new( decompressed( fp )? );
 
     let event = Event::read_from_stream_unbuffered( &mut fp )?;
     let header = match event {
@@ -53,3 +102,43 @@ pub fn parse_events< T >( fp: T ) -> io::Result< (HeaderBody, impl Iterator< Ite
     let iter = Iter { fp, done: false };
     Ok( (header, iter) )
 }
+
+#[test]
+fn test_decompressed() {
+    use std::io::Write;
+
+    let payload: Vec< u8 > = (0..10000_u32).map( |value| (value % 251) as u8 ).collect();
+    let read_all = |data: Vec< u8 >| -> io::Result< Vec< u8 > > {
+        let mut output = Vec::new();
+        decompressed( io::Cursor::new( data ) )?.read_to_end( &mut output )?;
+        Ok( output )
+    };
+
+    let mut gzip = flate2::write::GzEncoder::new( Vec::new(), flate2::Compression::default() );
+    gzip.write_all( &payload ).unwrap();
+    let gzip = gzip.finish().unwrap();
+
+    let zstd = zstd::stream::encode_all( &payload[..], 0 ).unwrap();
+
+    assert_eq!( read_all( payload.clone() ).unwrap(), payload );
+    assert_eq!( read_all( gzip.clone() ).unwrap(), payload );
+    assert_eq!( read_all( zstd.clone() ).unwrap(), payload );
+    assert_eq!( read_all( vec![ 0x1F ] ).unwrap(), vec![ 0x1F ] );
+    assert_eq!( read_all( Vec::new() ).unwrap(), Vec::< u8 >::new() );
+
+    let error = read_all( gzip[ ..gzip.len() / 2 ].to_vec() ).unwrap_err();
+    assert_eq!( error.kind(), io::ErrorKind::InvalidData );
+    assert!( error.to_string().starts_with( "the gzip-compressed data file is truncated or corrupt" ), "{}", error );
+
+    let error = read_all( zstd[ ..zstd.len() / 2 ].to_vec() ).unwrap_err();
+    assert_eq!( error.kind(), io::ErrorKind::InvalidData );
+    assert!( error.to_string().starts_with( "the zstd-compressed data file is truncated or corrupt" ), "{}", error );
+
+    let mut corrupt = gzip.clone();
+    let middle = corrupt.len() / 2;
+    for byte in &mut corrupt[ 10..middle ] {
+        *byte = 0xFF;
+    }
+    let error = read_all( corrupt ).unwrap_err();
+    assert!( error.to_string().starts_with( "the gzip-compressed data file is truncated or corrupt" ), "{}", error );
+}
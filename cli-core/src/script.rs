@@ -9,8 +9,8 @@ use ahash::AHashSet as HashSet;
 use rayon::prelude::*;
 use parking_lot::Mutex;
 use regex::Regex;
-use crate::{AllocationId, BacktraceId, Data, Loader};
-use crate::data::OperationId;
+use crate::{AllocationId, BacktraceId, Data, FrameId, Loader, StringId};
+use crate::data::{MmapOperation, OperationId};
 use crate::exporter_flamegraph_pl::dump_collation_from_iter;
 use crate::filter::{BasicFilter, Duration, Filter, NumberOrFractionOfTotal};
 use crate::timeline::build_timeline;
@@ -140,6 +140,37 @@ impl DataRef {
             filter: None
         }
     }
+
+    fn maps( &mut self ) -> MapList {
+        let operations = self.mmap_operations();
+        let mut indices: Vec< _ > = (0..operations.len()).collect();
+        indices.par_sort_by_key( |&index| (map_operation_timestamp( &operations[ index ] ), index) );
+
+        MapList {
+            data: self.clone(),
+            indices: Arc::new( indices )
+        }
+    }
+
+    fn mallopts( &mut self ) -> rhai::Array {
+        (0..self.0.mallopts().len()).map( |index| rhai::Dynamic::from( Mallopt { data: self.clone(), index } ) ).collect()
+    }
+
+    fn markers( &mut self ) -> rhai::Array {
+        (0..self.0.markers().len()).map( |index| rhai::Dynamic::from( Marker { data: self.clone(), index } ) ).collect()
+    }
+
+    fn backtrace( &mut self, id: i64 ) -> Result< Backtrace, Box< rhai::EvalAltResult > > {
+        if id < 0 || id as usize >= self.0.all_backtraces().len() {
+            return Err( error( format!( "no backtrace with ID {}", id ) ) );
+        }
+
+        Ok( Backtrace {
+            data: self.clone(),
+            id: BacktraceId::new( id as u32 ),
+            strip: false
+        })
+    }
 }
 
 lazy_static::lazy_static! {
@@ -182,10 +213,11 @@ pub struct Backtrace {
 }
 
 impl Backtrace {
-    fn write_to( &self, mut fmt: impl std::fmt::Write ) -> std::fmt::Result {
-        let mut is_first = true;
+    /// Returns the backtrace's frames along with their original indexes, skipping the noisy ones if the backtrace is stripped.
+    fn frames_with_indexes( &self ) -> Vec< (usize, FrameId) > {
         let interner = self.data.interner();
-        for (index, (_, frame)) in self.data.get_backtrace( self.id ).enumerate() {
+        let mut output = Vec::new();
+        for (index, (frame_id, frame)) in self.data.get_backtrace( self.id ).enumerate() {
             let function = frame.any_function().map( |function| interner.resolve( function ).unwrap() );
             if self.strip {
                 if let Some( function ) = function {
@@ -194,32 +226,8 @@ impl Backtrace {
                     }
                 }
             }
-            if !is_first {
-                write!( fmt, "\n" )?;
-            }
 
-            is_first = false;
-
-            write!( fmt, "#{:02}", index )?;
-            if let Some( library ) = frame.library() {
-                write!( fmt, " [{}]", interner.resolve( library ).unwrap() )?;
-            }
-            if let Some( function ) = function {
-                write!( fmt, " {}", function )?;
-            } else {
-                write!( fmt, " {:0x}", frame.address().raw() )?;
-            }
-            if let Some( source ) = frame.source() {
-                let mut source = interner.resolve( source ).unwrap();
-                if let Some( index ) = source.rfind( "/" ) {
-                    source = &source[ index + 1.. ];
-                }
-                write!( fmt, " [{}", source )?;
-                if let Some( line ) = frame.line() {
-                    write!( fmt, ":{}", line )?;
-                }
-                write!( fmt, "]" )?;
-            }
+            output.push( (index, frame_id) );
 
             if self.strip {
                 if let Some( function ) = function {
@@ -230,8 +238,54 @@ impl Backtrace {
             }
         }
 
+        output
+    }
+
+    fn write_to( &self, mut fmt: impl std::fmt::Write ) -> std::fmt::Result {
+        for (nth, (index, frame_id)) in self.frames_with_indexes().into_iter().enumerate() {
+            if nth != 0 {
+                write!( fmt, "\n" )?;
+            }
+
+            write!( fmt, "#{:02} ", index )?;
+            write_frame( &self.data, self.data.get_frame( frame_id ), &mut fmt )?;
+        }
+
         Ok(())
     }
+
+    fn id( &mut self ) -> i64 {
+        self.id.raw() as i64
+    }
+
+    fn frames( &mut self ) -> rhai::Array {
+        self.frames_with_indexes().into_iter().map( |(_, id)| rhai::Dynamic::from( Frame { data: self.data.clone(), id } ) ).collect()
+    }
+}
+
+fn write_frame( data: &Data, frame: &crate::frame::Frame, mut fmt: impl std::fmt::Write ) -> std::fmt::Result {
+    let interner = data.interner();
+    if let Some( library ) = frame.library() {
+        write!( fmt, "[{}] ", interner.resolve( library ).unwrap() )?;
+    }
+    if let Some( function ) = frame.any_function() {
+        write!( fmt, "{}", interner.resolve( function ).unwrap() )?;
+    } else {
+        write!( fmt, "{:0x}", frame.address().raw() )?;
+    }
+    if let Some( source ) = frame.source() {
+        let mut source = interner.resolve( source ).unwrap();
+        if let Some( index ) = source.rfind( "/" ) {
+            source = &source[ index + 1.. ];
+        }
+        write!( fmt, " [{}", source )?;
+        if let Some( line ) = frame.line() {
+            write!( fmt, ":{}", line )?;
+        }
+        write!( fmt, "]" )?;
+    }
+
+    Ok(())
 }
 
 impl std::fmt::Debug for Backtrace {
@@ -246,6 +300,70 @@ impl std::fmt::Display for Backtrace {
     }
 }
 
+/// A single frame of a backtrace.
+#[derive(Clone)]
+pub struct Frame {
+    data: DataRef,
+    id: FrameId
+}
+
+impl std::fmt::Debug for Frame {
+    fn fmt( &self, fmt: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        write!( fmt, "Frame" )
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt( &self, fmt: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        write_frame( &self.data, self.frame(), fmt )
+    }
+}
+
+impl Frame {
+    fn frame( &self ) -> &crate::frame::Frame {
+        self.data.get_frame( self.id )
+    }
+
+    fn resolve( &self, id: Option< StringId > ) -> rhai::Dynamic {
+        match id {
+            Some( id ) => self.data.interner().resolve( id ).unwrap().to_owned().into(),
+            None => rhai::Dynamic::UNIT
+        }
+    }
+
+    fn address( &mut self ) -> i64 {
+        self.frame().address().raw() as i64
+    }
+
+    fn function( &mut self ) -> rhai::Dynamic {
+        self.resolve( self.frame().any_function() )
+    }
+
+    fn raw_function( &mut self ) -> rhai::Dynamic {
+        self.resolve( self.frame().raw_function() )
+    }
+
+    fn library( &mut self ) -> rhai::Dynamic {
+        self.resolve( self.frame().library() )
+    }
+
+    fn source( &mut self ) -> rhai::Dynamic {
+        self.resolve( self.frame().source() )
+    }
+
+    fn line( &mut self ) -> rhai::Dynamic {
+        self.frame().line().map( |line| (line as i64).into() ).unwrap_or( rhai::Dynamic::UNIT )
+    }
+
+    fn column( &mut self ) -> rhai::Dynamic {
+        self.frame().column().map( |column| (column as i64).into() ).unwrap_or( rhai::Dynamic::UNIT )
+    }
+
+    fn is_inline( &mut self ) -> bool {
+        self.frame().is_inline()
+    }
+}
+
 #[derive(Clone)]
 pub struct Allocation {
     data: DataRef,
@@ -302,6 +420,121 @@ fn gen_keypoints( range: (u64, u64), max_points: usize ) -> Vec< u64 > {
     return ret;
 }
 
+fn gen_log_keypoints( range: (u64, u64), max_points: usize ) -> Vec< u64 > {
+    let range = (range.0.min(range.1), range.0.max(range.1));
+    let mut ret = vec![];
+    if range.0 == 0 {
+        ret.push( 0 );
+    }
+
+    for &multipliers in &[&[1, 2, 5][..], &[1][..]] {
+        ret.truncate( if range.0 == 0 { 1 } else { 0 } );
+        let mut power: u64 = 1;
+        'outer: loop {
+            for &multiplier in multipliers {
+                let value = match power.checked_mul( multiplier ) {
+                    Some( value ) if value <= range.1 => value,
+                    _ => break 'outer
+                };
+
+                if value >= range.0 {
+                    ret.push( value );
+                }
+            }
+
+            power = match power.checked_mul( 10 ) {
+                Some( power ) => power,
+                None => break
+            };
+        }
+
+        if ret.len() <= max_points {
+            break;
+        }
+    }
+
+    ret
+}
+
+/// Formats a byte count using binary (KiB, MiB, ...) units.
+fn format_bytes( value: u64 ) -> String {
+    const UNITS: &[&str] = &[ "B", "KiB", "MiB", "GiB", "TiB" ];
+
+    let mut unit = 0;
+    let mut scaled = value as f64;
+    while scaled >= 1024.0 && unit + 1 < UNITS.len() {
+        scaled /= 1024.0;
+        unit += 1;
+    }
+
+    if scaled.fract() == 0.0 {
+        format!( "{} {}", scaled as u64, UNITS[ unit ] )
+    } else if scaled < 10.0 {
+        format!( "{:.02} {}", scaled, UNITS[ unit ] )
+    } else if scaled < 100.0 {
+        format!( "{:.01} {}", scaled, UNITS[ unit ] )
+    } else {
+        format!( "{:.0} {}", scaled, UNITS[ unit ] )
+    }
+}
+
+/// Restricts the datapoints to the `x_min..=x_max` window, carrying over
+/// the last value from before the window so that the graph starts at the right height.
+fn clip_datapoints( datapoints: &[(u64, u64)], x_min: u64, x_max: u64 ) -> Vec< (u64, u64) > {
+    let mut output = Vec::new();
+    let mut last_before = None;
+    for &(x, y) in datapoints {
+        if x < x_min {
+            last_before = Some( y );
+            continue;
+        }
+
+        if x > x_max {
+            break;
+        }
+
+        if let Some( y ) = last_before.take() {
+            if x > x_min {
+                output.push( (x_min, y) );
+            }
+        }
+
+        output.push( (x, y) );
+    }
+
+    if let Some( y ) = last_before {
+        output.push( (x_min, y) );
+    }
+
+    output
+}
+
+#[test]
+fn test_gen_log_keypoints() {
+    assert_eq!( gen_log_keypoints( (0, 1000), 100 ), &[0, 1, 2, 5, 10, 20, 50, 100, 200, 500, 1000] );
+    assert_eq!( gen_log_keypoints( (0, 1000), 5 ), &[0, 1, 10, 100, 1000] );
+    assert_eq!( gen_log_keypoints( (15, 600), 100 ), &[20, 50, 100, 200, 500] );
+}
+
+#[test]
+fn test_format_bytes() {
+    assert_eq!( format_bytes( 0 ), "0 B" );
+    assert_eq!( format_bytes( 1000 ), "1000 B" );
+    assert_eq!( format_bytes( 1024 ), "1 KiB" );
+    assert_eq!( format_bytes( 1536 ), "1.50 KiB" );
+    assert_eq!( format_bytes( 3 * 1024 * 1024 ), "3 MiB" );
+}
+
+#[test]
+fn test_clip_datapoints() {
+    let datapoints = &[(10, 1), (20, 2), (30, 3), (40, 4)];
+    assert_eq!( clip_datapoints( datapoints, 0, 100 ), datapoints );
+    assert_eq!( clip_datapoints( datapoints, 25, 35 ), &[(25, 2), (30, 3)] );
+    assert_eq!( clip_datapoints( datapoints, 20, 30 ), &[(20, 2), (30, 3)] );
+    assert_eq!( clip_datapoints( datapoints, 45, 50 ), &[(45, 4)] );
+    assert_eq!( clip_datapoints( datapoints, 0, 5 ), &[] );
+}
+
 fn to_chrono( timestamp: u64 ) -> chrono::DateTime< chrono::Utc > {
     use chrono::prelude::*;
 
@@ -467,7 +700,7 @@ impl AllocationList {
             (allocation_id, self.data.get_allocation( allocation_id ) )
         });
 
-        dump_collation_from_iter( &self.data, iter, |line| {
+        dump_collation_from_iter( &self.data, iter, &Default::default(), |line| {
             lines.push( line.to_owned() );
             let result: Result< (), () > = Ok(());
             result
@@ -696,7 +929,344 @@ impl AllocationGroupList {
     }
 }
 
-#[derive(Copy, Clone)]
+fn map_operation_timestamp( operation: &MmapOperation ) -> common::Timestamp {
+    operation.timestamp()
+}
+
+/// A single `mmap`, `munmap`, `mremap` or `mprotect` call.
+#[derive(Clone)]
+pub struct Map {
+    data: DataRef,
+    index: usize
+}
+
+impl std::fmt::Debug for Map {
+    fn fmt( &self, fmt: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        write!( fmt, "Map" )
+    }
+}
+
+impl Map {
+    fn operation( &self ) -> &MmapOperation {
+        &self.data.mmap_operations()[ self.index ]
+    }
+
+    fn timestamp( &mut self ) -> Duration {
+        Duration( map_operation_timestamp( self.operation() ) - self.data.initial_timestamp )
+    }
+
+    fn address( &mut self ) -> i64 {
+        match self.operation() {
+            MmapOperation::Mmap( map ) => map.pointer as i64,
+            MmapOperation::Munmap( unmap ) => unmap.pointer as i64,
+            MmapOperation::Mremap( remap ) => remap.new_pointer as i64,
+            MmapOperation::Mprotect( protect ) => protect.pointer as i64
+        }
+    }
+
+    fn length( &mut self ) -> i64 {
+        match self.operation() {
+            MmapOperation::Mmap( map ) => map.length as i64,
+            MmapOperation::Munmap( unmap ) => unmap.length as i64,
+            MmapOperation::Mremap( remap ) => remap.new_length as i64,
+            MmapOperation::Mprotect( protect ) => protect.length as i64
+        }
+    }
+
+    fn is_unmap( &mut self ) -> bool {
+        matches!( self.operation(), MmapOperation::Munmap( _ ) )
+    }
+
+    fn kind( &mut self ) -> String {
+        match self.operation() {
+            MmapOperation::Mmap( _ ) => "mmap",
+            MmapOperation::Munmap( _ ) => "munmap",
+            MmapOperation::Mremap( _ ) => "mremap",
+            MmapOperation::Mprotect( _ ) => "mprotect"
+        }.to_owned()
+    }
+
+    fn protection( &mut self ) -> String {
+        let protection = match self.operation() {
+            MmapOperation::Mmap( map ) => map.mmap_protection,
+            MmapOperation::Mprotect( protect ) => protect.protection,
+            MmapOperation::Munmap( _ ) | MmapOperation::Mremap( _ ) => return String::new()
+        };
+
+        let mut output = String::with_capacity( 3 );
+        output.push( if protection.is_readable() { 'r' } else { '-' } );
+        output.push( if protection.is_writable() { 'w' } else { '-' } );
+        output.push( if protection.is_executable() { 'x' } else { '-' } );
+        output
+    }
+
+    fn flags( &mut self ) -> rhai::Array {
+        let mut output = rhai::Array::new();
+        if let MmapOperation::Mmap( map ) = self.operation() {
+            let flags = map.mmap_flags;
+            let known = [
+                (flags.is_shared(), "shared"),
+                (flags.is_private(), "private"),
+                (flags.is_fixed(), "fixed"),
+                (flags.is_anonymous(), "anonymous"),
+                (flags.is_uninitialized(), "uninitialized")
+            ];
+
+            for &(is_set, name) in &known {
+                if is_set {
+                    output.push( rhai::Dynamic::from( name.to_owned() ) );
+                }
+            }
+        }
+
+        output
+    }
+
+    fn fd( &mut self ) -> i64 {
+        match self.operation() {
+            MmapOperation::Mmap( map ) => map.file_descriptor as i32 as i64,
+            _ => -1
+        }
+    }
+
+    fn backtrace( &mut self ) -> Backtrace {
+        Backtrace {
+            data: self.data.clone(),
+            id: self.operation().backtrace(),
+            strip: false
+        }
+    }
+}
+
+/// A list of `mmap` and `munmap` calls, sorted by time.
+#[derive(Clone)]
+pub struct MapList {
+    data: DataRef,
+    indices: Arc< Vec< usize > >
+}
+
+impl std::fmt::Debug for MapList {
+    fn fmt( &self, fmt: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        write!( fmt, "MapList" )
+    }
+}
+
+pub struct MapListIter {
+    list: MapList,
+    index: usize
+}
+
+impl Iterator for MapListIter {
+    type Item = Map;
+    fn next( &mut self ) -> Option< Self::Item > {
+        let index = *self.list.indices.get( self.index )?;
+        self.index += 1;
+
+        Some( Map {
+            data: self.list.data.clone(),
+            index
+        })
+    }
+}
+
+impl IntoIterator for MapList {
+    type Item = Map;
+    type IntoIter = MapListIter;
+
+    fn into_iter( self ) -> Self::IntoIter {
+        MapListIter {
+            list: self,
+            index: 0
+        }
+    }
+}
+
+impl MapList {
+    fn filter( &self, callback: impl Fn( &MmapOperation ) -> bool + Send + Sync ) -> Self {
+        let operations = self.data.mmap_operations();
+        let indices: Vec< _ > = self.indices.par_iter().copied().filter( |&index| callback( &operations[ index ] ) ).collect();
+        MapList {
+            data: self.data.clone(),
+            indices: Arc::new( indices )
+        }
+    }
+
+    fn filter_by_length( &self, callback: impl Fn( u64 ) -> bool + Send + Sync ) -> Self {
+        self.filter( |operation| {
+            match operation {
+                MmapOperation::Mmap( map ) => callback( map.length ),
+                MmapOperation::Munmap( unmap ) => callback( unmap.length ),
+                MmapOperation::Mremap( remap ) => callback( remap.new_length ),
+                MmapOperation::Mprotect( protect ) => callback( protect.length )
+            }
+        })
+    }
+
+    fn len( &mut self ) -> i64 {
+        self.indices.len() as i64
+    }
+
+    fn get( &mut self, index: i64 ) -> Result< Map, Box< rhai::EvalAltResult > > {
+        let index = *self.indices.get( index as usize ).ok_or_else( || error( "index out of range" ) )?;
+        Ok( Map {
+            data: self.data.clone(),
+            index
+        })
+    }
+
+    fn only_larger( &mut self, value: i64 ) -> Self {
+        self.filter_by_length( |length| length > value as u64 )
+    }
+
+    fn only_larger_or_equal( &mut self, value: i64 ) -> Self {
+        self.filter_by_length( |length| length >= value as u64 )
+    }
+
+    fn only_smaller( &mut self, value: i64 ) -> Self {
+        self.filter_by_length( |length| length < value as u64 )
+    }
+
+    fn only_smaller_or_equal( &mut self, value: i64 ) -> Self {
+        self.filter_by_length( |length| length <= value as u64 )
+    }
+
+    fn only_mmaps( &mut self ) -> Self {
+        self.filter( |operation| matches!( operation, MmapOperation::Mmap( _ ) ) )
+    }
+
+    fn only_munmaps( &mut self ) -> Self {
+        self.filter( |operation| matches!( operation, MmapOperation::Munmap( _ ) ) )
+    }
+
+    fn only_anonymous( &mut self ) -> Self {
+        self.filter( |operation| matches!( operation, MmapOperation::Mmap( map ) if map.mmap_flags.is_anonymous() ) )
+    }
+
+    fn only_not_anonymous( &mut self ) -> Self {
+        self.filter( |operation| matches!( operation, MmapOperation::Mmap( map ) if !map.mmap_flags.is_anonymous() ) )
+    }
+
+    fn only_after_at_least( &mut self, offset: Duration ) -> Self {
+        let threshold = self.data.initial_timestamp + offset.0;
+        self.filter( |operation| map_operation_timestamp( operation ) >= threshold )
+    }
+
+    fn only_until_at_most( &mut self, offset: Duration ) -> Self {
+        let threshold = self.data.initial_timestamp + offset.0;
+        self.filter( |operation| map_operation_timestamp( operation ) <= threshold )
+    }
+}
+
+/// A single `mallopt` call.
+#[derive(Clone)]
+pub struct Mallopt {
+    data: DataRef,
+    index: usize
+}
+
+impl std::fmt::Debug for Mallopt {
+    fn fmt( &self, fmt: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        write!( fmt, "Mallopt" )
+    }
+}
+
+impl Mallopt {
+    fn mallopt( &self ) -> &crate::data::Mallopt {
+        &self.data.0.mallopts()[ self.index ]
+    }
+
+    fn timestamp( &mut self ) -> Duration {
+        Duration( self.mallopt().timestamp - self.data.initial_timestamp )
+    }
+
+    fn thread( &mut self ) -> i64 {
+        self.mallopt().thread as i64
+    }
+
+    fn kind( &mut self ) -> String {
+        let kind = &self.mallopt().kind;
+        kind.name().map( |name| name.to_owned() ).unwrap_or_else( || kind.raw().to_string() )
+    }
+
+    fn raw_kind( &mut self ) -> i64 {
+        self.mallopt().kind.raw() as i64
+    }
+
+    fn value( &mut self ) -> i64 {
+        self.mallopt().value as i64
+    }
+
+    fn result( &mut self ) -> i64 {
+        self.mallopt().result as i64
+    }
+
+    fn backtrace_id( &mut self ) -> i64 {
+        self.mallopt().backtrace.raw() as i64
+    }
+
+    fn backtrace( &mut self ) -> Backtrace {
+        Backtrace {
+            data: self.data.clone(),
+            id: self.mallopt().backtrace,
+            strip: false
+        }
+    }
+}
+
+/// A single marker emitted by the profiled application.
+#[derive(Clone)]
+pub struct Marker {
+    data: DataRef,
+    index: usize
+}
+
+impl std::fmt::Debug for Marker {
+    fn fmt( &self, fmt: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        write!( fmt, "Marker" )
+    }
+}
+
+impl Marker {
+    fn marker( &self ) -> &crate::data::Marker {
+        &self.data.0.markers()[ self.index ]
+    }
+
+    fn timestamp( &mut self ) -> Duration {
+        Duration( self.marker().timestamp - self.data.initial_timestamp )
+    }
+
+    fn thread( &mut self ) -> i64 {
+        self.marker().thread as i64
+    }
+
+    fn value( &mut self ) -> i64 {
+        self.marker().value as i64
+    }
+
+    fn backtrace_id( &mut self ) -> i64 {
+        self.marker().backtrace.map( |backtrace| backtrace.raw() as i64 ).unwrap_or( -1 )
+    }
+
+    fn backtrace( &mut self ) -> rhai::Dynamic {
+        match self.marker().backtrace {
+            Some( backtrace ) => rhai::Dynamic::from( Backtrace {
+                data: self.data.clone(),
+                id: backtrace,
+                strip: false
+            }),
+            None => rhai::Dynamic::UNIT
+        }
+    }
+}
+
+/// The value a script evaluated to.
+pub enum ScriptValue {
+    AllocationList( AllocationList ),
+    MapList( MapList ),
+    Other
+}
+
+#[derive(Copy, Clone, PartialEq)]
 enum GraphKind {
     MemoryUsage,
     LiveAllocations,
@@ -714,19 +1284,56 @@ struct Graph {
     trim_right: bool,
     extend_until: Option< Duration >,
     truncate_until: Option< Duration >,
+    between: Option< (Duration, Duration) >,
+    y_range: Option< (u64, u64) >,
+    log_scale: bool,
+    bytes_formatting: bool,
+    title: Option< String >,
     lists: Vec< AllocationList >,
+    map_lists: Vec< MapList >,
     labels: Vec< Option< String > >,
+    markers: Vec< (Duration, String) >,
     gradient: Option< Arc< colorgrad::Gradient > >,
     kind: GraphKind,
 
-    cached_datapoints: Option< Arc< (Vec< u64 >, Vec< Vec< (u64, u64) > >) > >
+    cached_datapoints: Option< Arc< (Vec< u64 >, Vec< Vec< (u64, u64) > >) > >
+}
+
+const DEFAULT_GRAPH_SIZE: (u32, u32) = (1024, 768);
+
+/// Rasterizing is done fully in memory, so the image's size has to be capped;
+/// this is a little over 8 million pixels, which takes 32MB.
+const MAXIMUM_PNG_PIXEL_COUNT: i64 = 8192 * 1024;
+
+lazy_static::lazy_static! {
+    static ref SVG_OPTIONS: usvg::Options = {
+        let mut options = usvg::Options::default();
+        options.fontdb.load_system_fonts();
+        options
+    };
+}
+
+fn png_size( width: i64, height: i64 ) -> Result< (u32, u32), Box< rhai::EvalAltResult > > {
+    let pixel_count = width.checked_mul( height ).unwrap_or( i64::MAX );
+    if width <= 0 || height <= 0 || pixel_count > MAXIMUM_PNG_PIXEL_COUNT {
+        return Err( error( format!( "invalid image size: {}x{}; the image must have at least one and at most {} pixels", width, height, MAXIMUM_PNG_PIXEL_COUNT ) ) );
+    }
+
+    Ok( (width as u32, height as u32) )
+}
+
+fn svg_to_png( svg: &str, (width, height): (u32, u32) ) -> Result< Vec< u8 >, String > {
+    let tree = usvg::Tree::from_str( svg, &SVG_OPTIONS.to_ref() ).map_err( |error| format!( "failed to parse the SVG: {}", error ) )?;
+    let mut pixmap = tiny_skia::Pixmap::new( width, height ).ok_or( "failed to allocate the image" )?;
+    pixmap.fill( tiny_skia::Color::WHITE );
+    resvg::render( &tree, usvg::FitTo::Size( width, height ), tiny_skia::Transform::default(), pixmap.as_mut() ).ok_or( "failed to render the SVG" )?;
+    pixmap.encode_png().map_err( |error| format!( "failed to encode the PNG: {}", error ) )
 }
 
 fn prepare_graph_datapoints( data: &Data, ops_for_list: &[Vec< OperationId >], kind: GraphKind ) -> (Vec< u64 >, Vec< Vec< (u64, u64) > >) {
     let timestamp_min = ops_for_list.iter().flat_map( |ops| ops.first() ).map( |op| get_timestamp( &data, *op ) ).min().unwrap_or( common::Timestamp::min() );
     let timestamp_max = ops_for_list.iter().flat_map( |ops| ops.last() ).map( |op| get_timestamp( &data, *op ) ).max().unwrap_or( common::Timestamp::min() );
 
-    let mut xs = HashSet::new();
     let mut datapoints_for_ops = Vec::new();
     for ops in ops_for_list {
         if ops.is_empty() {
@@ -735,7 +1342,6 @@ fn prepare_graph_datapoints( data: &Data, ops_for_list: &[Vec< OperationId >], k
         }
 
         let datapoints: Vec< _ > = build_timeline( &data, timestamp_min, timestamp_max, ops ).into_iter().map( |point| {
-            xs.insert( point.timestamp );
             let x = point.timestamp;
             let y = match kind {
                 GraphKind::MemoryUsage => point.memory_usage,
@@ -749,6 +1355,128 @@ fn prepare_graph_datapoints( data: &Data, ops_for_list: &[Vec< OperationId >], k
         datapoints_for_ops.push( datapoints );
     }
 
+    stack_datapoints( datapoints_for_ops )
+}
+
+/// Builds a timeline out of `(timestamp, length, is_mmap)` tuples sorted by time.
+///
+/// The memory usage is the total length of every `mmap` minus the total length of every `munmap`.
+fn build_map_timeline(
+    timestamp_min: common::Timestamp,
+    timestamp_max: common::Timestamp,
+    operations: impl Iterator< Item = (common::Timestamp, u64, bool) >,
+    kind: GraphKind
+) -> Vec< (u64, u64) > {
+    let granularity = std::cmp::max( (timestamp_max - timestamp_min).as_usecs() / 1000, 1 );
+    let mut output = Vec::new();
+
+    let mut current_time = None;
+    let mut current_usage: i64 = 0;
+    let mut current_max_usage: i64 = 0;
+    let mut current_maps_per_time: u64 = 0;
+    let mut current_unmaps_per_time: u64 = 0;
+
+    let mut flush = |time: u64, max_usage: i64, maps_per_time: u64, unmaps_per_time: u64| {
+        let y = match kind {
+            GraphKind::MemoryUsage => std::cmp::max( 0, max_usage ) as u64,
+            GraphKind::NewAllocations => maps_per_time,
+            GraphKind::Deallocations => unmaps_per_time,
+            GraphKind::LiveAllocations => unreachable!()
+        };
+        output.push( (time * granularity, y) );
+    };
+
+    for (timestamp, length, is_mmap) in operations {
+        let next_time = timestamp.as_usecs() / granularity;
+        if let Some( mut time ) = current_time {
+            if time != next_time {
+                while time < next_time {
+                    flush( time, current_max_usage, current_maps_per_time, current_unmaps_per_time );
+                    current_maps_per_time = 0;
+                    current_unmaps_per_time = 0;
+                    current_max_usage = current_usage;
+                    time += 1;
+                }
+            }
+        }
+        current_time = Some( next_time );
+
+        if is_mmap {
+            current_usage += length as i64;
+            current_maps_per_time += 1;
+        } else {
+            current_usage -= length as i64;
+            current_unmaps_per_time += 1;
+        }
+
+        current_max_usage = std::cmp::max( current_max_usage, current_usage );
+    }
+
+    if let Some( time ) = current_time {
+        flush( time, current_max_usage, current_maps_per_time, current_unmaps_per_time );
+    }
+
+    output
+}
+
+#[test]
+fn test_build_map_timeline() {
+    let ts = common::Timestamp::from_usecs;
+    let operations = vec![
+        (ts( 1000 ), 100, true),
+        (ts( 1000 ), 50, true),
+        (ts( 3000 ), 100, false),
+        (ts( 4000 ), 10, true)
+    ];
+
+    let timeline = build_map_timeline( ts( 0 ), ts( 1000 ), operations.iter().copied(), GraphKind::MemoryUsage );
+    assert_eq!( timeline.len(), 3001 );
+    assert_eq!( timeline[ 0 ], (1000, 150) );
+    assert_eq!( timeline[ 1999 ], (2999, 150) );
+    // The usage within a single time slice is its maximum.
+    assert_eq!( timeline[ 2000 ], (3000, 150) );
+    assert_eq!( timeline[ 2001 ], (3001, 50) );
+    assert_eq!( timeline[ 3000 ], (4000, 60) );
+
+    let timeline = build_map_timeline( ts( 0 ), ts( 1000_000 ), operations.iter().copied(), GraphKind::NewAllocations );
+    assert_eq!( timeline, vec![ (1000, 2), (2000, 0), (3000, 0), (4000, 1) ] );
+
+    let timeline = build_map_timeline( ts( 0 ), ts( 1000_000 ), operations.iter().copied(), GraphKind::Deallocations );
+    assert_eq!( timeline, vec![ (1000, 0), (2000, 0), (3000, 1), (4000, 0) ] );
+
+    assert!( build_map_timeline( ts( 0 ), ts( 1000 ), std::iter::empty(), GraphKind::MemoryUsage ).is_empty() );
+}
+
+fn prepare_map_graph_datapoints( data: &Data, series: &[Vec< usize >], kind: GraphKind ) -> Result< (Vec< u64 >, Vec< Vec< (u64, u64) > >), String > {
+    if matches!( kind, GraphKind::LiveAllocations ) {
+        return Err( "graphs of live allocations are not supported for maps".into() );
+    }
+
+    let operations = data.mmap_operations();
+    let timestamp = |index: &usize| map_operation_timestamp( &operations[ *index ] );
+    let timestamp_min = series.iter().flat_map( |indices| indices.first() ).map( timestamp ).min().unwrap_or( common::Timestamp::min() );
+    let timestamp_max = series.iter().flat_map( |indices| indices.last() ).map( timestamp ).max().unwrap_or( common::Timestamp::min() );
+
+    let datapoints_for_ops = series.iter().map( |indices| {
+        let iter = indices.iter().flat_map( |&index| {
+            match operations[ index ] {
+                MmapOperation::Mmap( ref map ) => vec![ (map.timestamp, map.length, true) ],
+                MmapOperation::Munmap( ref unmap ) => vec![ (unmap.timestamp, unmap.length, false) ],
+                MmapOperation::Mremap( ref remap ) if remap.remap_flags.is_dont_unmap() => vec![ (remap.timestamp, remap.new_length, true) ],
+                MmapOperation::Mremap( ref remap ) => vec![ (remap.timestamp, remap.old_length, false), (remap.timestamp, remap.new_length, true) ],
+                MmapOperation::Mprotect( _ ) => Vec::new()
+            }
+        });
+
+        build_map_timeline( timestamp_min, timestamp_max, iter, kind )
+    }).collect();
+
+    Ok( stack_datapoints( datapoints_for_ops ) )
+}
+
+/// Expands every series to a common set of timestamps and stacks them on top of each other.
+fn stack_datapoints( mut datapoints_for_ops: Vec< Vec< (u64, u64) > > ) -> (Vec< u64 >, Vec< Vec< (u64, u64) > >) {
+    let xs: HashSet< _ > = datapoints_for_ops.iter().flat_map( |datapoints| datapoints.iter().map( |&(x, _)| x ) ).collect();
     let mut xs: Vec< _ > = xs.into_iter().collect();
     xs.sort_unstable();
 
@@ -785,8 +1513,15 @@ impl Graph {
             trim_right: false,
             extend_until: None,
             truncate_until: None,
+            between: None,
+            y_range: None,
+            log_scale: false,
+            bytes_formatting: false,
+            title: None,
             lists: Vec::new(),
+            map_lists: Vec::new(),
             labels: Vec::new(),
+            markers: Vec::new(),
             gradient: None,
             kind: GraphKind::MemoryUsage,
 
@@ -820,6 +1555,55 @@ impl Graph {
         cloned
     }
 
+    fn add_maps_with_label( &mut self, label: String, list: MapList ) -> Self {
+        let mut cloned = self.clone();
+        cloned.map_lists.push( list );
+        cloned.labels.push( Some( label ) );
+        cloned.cached_datapoints = None;
+        cloned
+    }
+
+    fn add_maps( &mut self, list: MapList ) -> Self {
+        let mut cloned = self.clone();
+        cloned.map_lists.push( list );
+        cloned.labels.push( None );
+        cloned.cached_datapoints = None;
+        cloned
+    }
+
+    fn add_marker( &mut self, offset: Duration, label: String ) -> Self {
+        let mut cloned = self.clone();
+        cloned.markers.push( (offset, label) );
+        cloned
+    }
+
+    fn data( &self ) -> &DataRef {
+        self.lists.first().map( |list| &list.data ).unwrap_or_else( || &self.map_lists[ 0 ].data )
+    }
+
+    fn generate_map_series( &self ) -> Result< Vec< Vec< usize > >, String > {
+        if !self.lists.is_empty() {
+            return Err( format!( "allocation lists and map lists can't be shown on the same graph" ) );
+        }
+
+        let data = self.map_lists[ 0 ].data.clone();
+        if !self.map_lists.iter().all( |list| list.data.id() == data.id() ) {
+            return Err( format!( "not every map list given is from the same data file" ) );
+        }
+
+        let threshold = self.truncate_until.map( |offset| data.initial_timestamp + offset.0 ).unwrap_or( data.last_timestamp );
+        let operations = data.mmap_operations();
+
+        let mut seen = HashSet::new();
+        let series = self.map_lists.iter().map( |list| {
+            list.indices.iter().copied().filter( |&index| {
+                seen.insert( index ) && map_operation_timestamp( &operations[ index ] ) <= threshold
+            }).collect()
+        }).collect();
+
+        Ok( series )
+    }
+
     fn only_non_empty_series( &mut self ) -> Self {
         let mut cloned = self.clone();
         cloned.hide_empty = true;
@@ -857,6 +1641,44 @@ impl Graph {
         cloned
     }
 
+    fn between( &mut self, start: Duration, end: Duration ) -> Result< Self, Box< rhai::EvalAltResult > > {
+        if start.0 >= end.0 {
+            return Err( error( "the start of the time range must be before its end" ) );
+        }
+
+        let mut cloned = self.clone();
+        cloned.between = Some( (start, end) );
+        Ok( cloned )
+    }
+
+    fn with_range( &mut self, min: i64, max: i64 ) -> Result< Self, Box< rhai::EvalAltResult > > {
+        if min < 0 || min >= max {
+            return Err( error( format!( "invalid range: {}..{}", min, max ) ) );
+        }
+
+        let mut cloned = self.clone();
+        cloned.y_range = Some( (min as u64, max as u64) );
+        Ok( cloned )
+    }
+
+    fn with_log_scale( &mut self ) -> Self {
+        let mut cloned = self.clone();
+        cloned.log_scale = true;
+        cloned
+    }
+
+    fn bytes_formatting( &mut self ) -> Self {
+        let mut cloned = self.clone();
+        cloned.bytes_formatting = true;
+        cloned
+    }
+
+    fn with_title( &mut self, title: String ) -> Self {
+        let mut cloned = self.clone();
+        cloned.title = Some( title );
+        cloned
+    }
+
     fn without_legend( &mut self ) -> Self {
         let mut cloned = self.clone();
         cloned.without_legend = true;
@@ -954,15 +1776,8 @@ impl Graph {
         return Ok( cloned );
     }
 
-    fn save_to_string_impl( &self, xs: &[u64], datapoints_for_ops: &[Vec< (u64, u64) >], labels: &[Option< String >] ) -> Result< String, String > {
-        let data = self.lists[ 0 ].data.clone();
-
-        let mut max_usage = 0;
-        for datapoints in datapoints_for_ops {
-            for (_, value) in datapoints {
-                max_usage = std::cmp::max( max_usage, *value );
-            }
-        }
+    fn save_to_string_impl( &self, xs: &[u64], datapoints_for_ops: &[Vec< (u64, u64) >], labels: &[Option< String >], size: (u32, u32) ) -> Result< String, String > {
+        let data = self.data().clone();
 
         let mut x_min = xs.first().copied().unwrap_or( 0 );
         let mut x_max = xs.last().copied().unwrap_or( 0 );
@@ -979,12 +1794,29 @@ impl Graph {
         if !self.trim_right {
             x_max = std::cmp::max( x_max, data.last_timestamp.as_usecs() );
         }
+        if let Some( (start, end) ) = self.between {
+            x_min = (data.initial_timestamp + start.0).as_usecs();
+            x_max = (data.initial_timestamp + end.0).as_usecs();
+        }
+
+        let datapoints_for_ops: Vec< _ > = datapoints_for_ops.iter().map( |datapoints| clip_datapoints( datapoints, x_min, x_max ) ).collect();
+
+        let mut max_usage = 0;
+        for datapoints in &datapoints_for_ops {
+            for (_, value) in datapoints {
+                max_usage = std::cmp::max( max_usage, *value );
+            }
+        }
+
+        let (y_min, y_max) = self.y_range.unwrap_or( (0, max_usage + 1) );
 
         // This is a dirty hack, but it works.
         thread_local! {
             static SCALE_X: Cell< (u64, u64) > = Cell::new( (0, 0) );
             static SCALE_Y: Cell< (u64, u64) > = Cell::new( (0, 0) );
             static KIND: Cell< GraphKind > = Cell::new( GraphKind::MemoryUsage );
+            static LOG_SCALE: Cell< bool > = Cell::new( false );
+            static BYTES_FORMATTING: Cell< bool > = Cell::new( false );
         }
 
         macro_rules! impl_ranged {
@@ -1024,8 +1856,18 @@ impl Graph {
 
         impl plotters::coord::ranged1d::ValueFormatter< u64 > for SizeRange {
             fn format( value: &u64 ) -> String {
+                if BYTES_FORMATTING.with( |cell| cell.get() ) && KIND.with( |cell| cell.get() ) == GraphKind::MemoryUsage {
+                    return format_bytes( *value );
+                }
+
                 SCALE_Y.with( |cell| {
-                    let (min, max) = cell.get();
+                    // On a logarithmic scale the labels span several orders of magnitude,
+                    // so each one has to pick its own unit.
+                    let (min, max) = if LOG_SCALE.with( |cell| cell.get() ) {
+                        (0, *value)
+                    } else {
+                        cell.get()
+                    };
 
                     if max < 1024 {
                         format!( "{}", value )
@@ -1071,7 +1913,39 @@ impl Graph {
             }
         }
 
-        impl_ranged!( SizeRange );
+        impl Ranged for SizeRange {
+            type FormatOption = plotters::coord::ranged1d::NoDefaultFormatting;
+            type ValueType = u64;
+            fn map( &self, value: &Self::ValueType, limit: (i32, i32) ) -> i32 {
+                let screen_range = limit.1 - limit.0;
+                if screen_range == 0 {
+                    return limit.1;
+                }
+
+                let value = std::cmp::min( std::cmp::max( *value, self.0 ), self.1 );
+                let data_relative_position = if LOG_SCALE.with( |cell| cell.get() ) {
+                    let min = (self.0 as f64).ln_1p();
+                    let max = (self.1 as f64).ln_1p();
+                    ((value as f64).ln_1p() - min) / (max - min)
+                } else {
+                    (value - self.0) as f64 / (self.1 - self.0) as f64
+                };
+
+                limit.0 + (screen_range as f64 * data_relative_position + 1e-3).floor() as i32
+            }
+
+            fn key_points< Hint: plotters::coord::ranged1d::KeyPointHint >( &self, hint: Hint ) -> Vec< Self::ValueType > {
+                if LOG_SCALE.with( |cell| cell.get() ) {
+                    gen_log_keypoints( (self.0, self.1), hint.max_num_points() )
+                } else {
+                    gen_keypoints( (self.0, self.1), hint.max_num_points() )
+                }
+            }
+
+            fn range( &self ) -> std::ops::Range< Self::ValueType > {
+                self.0..self.1
+            }
+        }
 
         struct TimeRange( u64, u64 );
 
@@ -1132,16 +2006,21 @@ impl Graph {
         impl_ranged!( TimeRangeOffset );
 
         SCALE_X.with( |cell| cell.set( (x_min, x_max + 1) ) );
-        SCALE_Y.with( |cell| cell.set( (0, (max_usage + 1) as u64) ) );
+        SCALE_Y.with( |cell| cell.set( (y_min, y_max) ) );
         KIND.with( |cell| cell.set( self.kind ) );
+        LOG_SCALE.with( |cell| cell.set( self.log_scale ) );
+        BYTES_FORMATTING.with( |cell| cell.set( self.bytes_formatting ) );
 
         let mut output = String::new();
         use plotters::prelude::*;
-        let root = SVGBackend::with_string( &mut output, (1024, 768) ).into_drawing_area();
+        let root = SVGBackend::with_string( &mut output, size ).into_drawing_area();
         root.fill( &WHITE ).map_err( |error| format!( "failed to fill the graph with white: {}", error ) )?;
 
         let mut chart = ChartBuilder::on( &root );
         let mut chart = &mut chart;
+        if let Some( ref title ) = self.title {
+            chart = chart.caption( title, ("sans-serif", 20) );
+        }
         if !self.without_axes {
             chart = chart
                 .margin( (1).percent() )
@@ -1153,12 +2032,12 @@ impl Graph {
 
         let mut chart = chart.build_cartesian_2d(
                 TimeRange( x_min, x_max + 1 ),
-                SizeRange( 0, (max_usage + 1) as u64 )
+                SizeRange( y_min, y_max )
             )
             .map_err( |error| format!( "failed to construct the chart builder: {}", error ) )?
             .set_secondary_coord(
                 TimeRangeOffset( x_min, x_max + 1 ),
-                SizeRange( 0, (max_usage + 1) as u64 )
+                SizeRange( y_min, y_max )
             );
 
         let mut colors = Vec::new();
@@ -1184,8 +2063,8 @@ impl Graph {
                     }).chain( std::iter::once((
                         x_max,
                         datapoints.last().copied().map( |(_, y)| y ).unwrap_or( 0 )
-                    ))),
-                    0_u64,
+                    ))).map( |(x, y)| (x, std::cmp::min( std::cmp::max( y, y_min ), y_max )) ),
+                    y_min,
                     color,
                 ).border_style( color.stroke_width( 1 ) ),
             ).map_err( |error| format!( "failed to draw a series: {}", error ) )?;
@@ -1201,6 +2080,18 @@ impl Graph {
             }
         }
 
+        for (offset, label) in &self.markers {
+            let x = (data.initial_timestamp + offset.0).as_usecs();
+            if x < x_min || x > x_max {
+                continue;
+            }
+
+            chart.draw_series( std::iter::once( PathElement::new( vec![ (x, y_min), (x, y_max) ], BLACK.stroke_width( 1 ) ) ) )
+                .map_err( |error| format!( "failed to draw a marker: {}", error ) )?;
+            chart.draw_series( std::iter::once( Text::new( label.clone(), (x, y_max), ("sans-serif", 14).into_font() ) ) )
+                .map_err( |error| format!( "failed to draw a marker's label: {}", error ) )?;
+        }
+
         let mut mesh = chart.configure_mesh();
         let mut mesh = &mut mesh;
         if !self.without_axes {
@@ -1243,8 +2134,14 @@ impl Graph {
         Ok( output )
     }
 
-    fn save_to_string( &mut self ) -> Result< String, Box< rhai::EvalAltResult > > {
+    fn save_to_string( &mut self, size: (u32, u32) ) -> Result< String, Box< rhai::EvalAltResult > > {
         (|| {
+            if self.cached_datapoints.is_none() && !self.map_lists.is_empty() {
+                let series = self.generate_map_series()?;
+                let (xs, datapoints_for_ops) = prepare_map_graph_datapoints( self.data(), &series, self.kind )?;
+                self.cached_datapoints = Some( Arc::new( (xs, datapoints_for_ops) ) );
+            }
+
             if self.cached_datapoints.is_none() {
                 let ops_for_list = self.generate_ops()?;
                 let (xs, datapoints_for_ops) = prepare_graph_datapoints( &self.lists[ 0 ].data, &ops_for_list, self.kind );
@@ -1252,19 +2149,37 @@ impl Graph {
             }
 
             let cached = self.cached_datapoints.as_ref().unwrap();
-            self.save_to_string_impl( &cached.0, &cached.1, &self.labels )
+            self.save_to_string_impl( &cached.0, &cached.1, &self.labels, size )
         }.map_err( |error| {
             Box::new( rhai::EvalAltResult::from( format!( "failed to generate a graph: {}", error ) ) )
         }))()
     }
 
     fn save( &mut self, env: &mut dyn Environment, path: String ) -> Result< Self, Box< rhai::EvalAltResult > > {
-        let data = self.save_to_string()?;
+        let data = self.save_to_string( DEFAULT_GRAPH_SIZE )?;
         env.file_write( &path, FileKind::Svg, data.as_bytes() )?;
         Ok( self.clone() )
     }
 
-    fn save_each_series_as_graph( &mut self, env: &mut dyn Environment, mut path: String ) -> Result< Self, Box< rhai::EvalAltResult > > {
+    fn save_as_png( &mut self, env: &mut dyn Environment, path: String, width: i64, height: i64 ) -> Result< Self, Box< rhai::EvalAltResult > > {
+        let size = png_size( width, height )?;
+        let data = self.save_to_string( size )?;
+        let data = svg_to_png( &data, size ).map_err( |error| self::error( format!( "failed to generate a graph: {}", error ) ) )?;
+        env.file_write( &path, FileKind::Png, &data )?;
+        Ok( self.clone() )
+    }
+
+    fn save_each_series_as_graph( &mut self, env: &mut dyn Environment, path: String ) -> Result< Self, Box< rhai::EvalAltResult > > {
+        self.save_each_series_impl( env, path, None )
+    }
+
+    fn save_each_series_as_png( &mut self, env: &mut dyn Environment, path: String, width: i64, height: i64 ) -> Result< Self, Box< rhai::EvalAltResult > > {
+        let size = png_size( width, height )?;
+        self.save_each_series_impl( env, path, Some( size ) )
+    }
+
+    /// Saves every series as a separate SVG, or as a separate PNG of the given size.
+    fn save_each_series_impl( &mut self, env: &mut dyn Environment, mut path: String, png: Option< (u32, u32) > ) -> Result< Self, Box< rhai::EvalAltResult > > {
         env.mkdir_p( &path )?;
         if path == "." {
             path = "".into();
@@ -1272,19 +2187,31 @@ impl Graph {
             path.push( '/' );
         }
 
-        let ops_for_list = self.generate_ops()?;
-        for (index, (ops, label)) in ops_for_list.into_iter().zip( self.labels.iter() ).enumerate() {
-            let (xs, datapoints_for_ops) = prepare_graph_datapoints( &self.lists[ 0 ].data, &[ops], self.kind );
-            let data = self.save_to_string_impl( &xs, &datapoints_for_ops, &[label.clone()] )?;
+        let datapoints_for_series: Vec< _ > =
+            if !self.map_lists.is_empty() {
+                let series = self.generate_map_series()?;
+                series.into_iter().map( |indices| prepare_map_graph_datapoints( self.data(), &[indices], self.kind ) ).collect::< Result< _, _ > >()?
+            } else {
+                let ops_for_list = self.generate_ops()?;
+                ops_for_list.into_iter().map( |ops| prepare_graph_datapoints( self.data(), &[ops], self.kind ) ).collect()
+            };
 
+        for (index, ((xs, datapoints_for_ops), label)) in datapoints_for_series.into_iter().zip( self.labels.iter() ).enumerate() {
+            let data = self.save_to_string_impl( &xs, &datapoints_for_ops, std::slice::from_ref( label ), png.unwrap_or( DEFAULT_GRAPH_SIZE ) )?;
+            let extension = if png.is_some() { "png" } else { "svg" };
             let file_path =
                 if let Some( label ) = label {
-                    format!( "{}{}.svg", path, label )
+                    format!( "{}{}.{}", path, label, extension )
                 } else {
-                    format!( "{}Series #{}.svg", path, index )
+                    format!( "{}Series #{}.{}", path, index, extension )
                 };
 
-            env.file_write( &file_path, FileKind::Svg, data.as_bytes() )?;
+            if let Some( size ) = png {
+                let data = svg_to_png( &data, size ).map_err( |error| self::error( format!( "failed to generate a graph: {}", error ) ) )?;
+                env.file_write( &file_path, FileKind::Png, &data )?;
+            } else {
+                env.file_write( &file_path, FileKind::Svg, data.as_bytes() )?;
+            }
         }
 
         Ok( self.clone() )
@@ -1431,56 +2358,415 @@ fn intersect_allocations( lhs: AllocationList, mut rhs: AllocationList ) -> Resu
         return Err( Box::new( rhai::EvalAltResult::from( "allocation list don't come from the same data file" ) ) );
     }
 
-    if uses_same_list( &lhs, &rhs ) {
-        let filter = match (lhs.filter.as_ref(), rhs.filter.as_ref()) {
-            (None, None) => None,
-            (Some( lhs ), None) => Some( lhs.clone() ),
-            (None, Some( rhs )) => Some( rhs.clone() ),
-            (Some( lhs ), Some( rhs )) => Some( Filter::And( Box::new( lhs.clone() ), Box::new( rhs.clone() ) ) )
-        };
+    if uses_same_list( &lhs, &rhs ) {
+        let filter = match (lhs.filter.as_ref(), rhs.filter.as_ref()) {
+            (None, None) => None,
+            (Some( lhs ), None) => Some( lhs.clone() ),
+            (None, Some( rhs )) => Some( rhs.clone() ),
+            (Some( lhs ), Some( rhs )) => Some( Filter::And( Box::new( lhs.clone() ), Box::new( rhs.clone() ) ) )
+        };
+
+        Ok( AllocationList {
+            data: lhs.data.clone(),
+            allocation_ids: lhs.allocation_ids.clone(),
+            filter
+        })
+    } else {
+        rhs.apply_filter();
+
+        let mut set: HashSet< AllocationId > = HashSet::new();
+        set.extend( rhs.unfiltered_allocation_ids() );
+
+        let ids: Vec< _ > = lhs.filtered_allocation_ids().filter( |id| set.contains( id ) ).collect();
+        Ok( AllocationList {
+            data: lhs.data.clone(),
+            allocation_ids: Some( Arc::new( ids ) ),
+            filter: None
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TableColumnKind {
+    String,
+    Integer,
+    Float
+}
+
+impl TableColumnKind {
+    pub fn as_str( self ) -> &'static str {
+        match self {
+            TableColumnKind::String => "string",
+            TableColumnKind::Integer => "integer",
+            TableColumnKind::Float => "float"
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TableCell {
+    String( String ),
+    Integer( i64 ),
+    Float( f64 )
+}
+
+impl TableCell {
+    fn from_dynamic( value: rhai::plugin::Dynamic ) -> Result< Self, Box< rhai::EvalAltResult > > {
+        if value.is::< String >() {
+            Ok( TableCell::String( value.cast::< String >() ) )
+        } else if value.is::< i64 >() {
+            Ok( TableCell::Integer( value.cast::< i64 >() ) )
+        } else if value.is::< u64 >() {
+            let value = value.cast::< u64 >();
+            std::convert::TryFrom::try_from( value ).map( TableCell::Integer ).map_err( |_| error( format!( "integer too big for a table cell: {}", value ) ) )
+        } else if value.is::< f64 >() {
+            Ok( TableCell::Float( value.cast::< f64 >() ) )
+        } else {
+            Err( error( format!( "unsupported type of a table cell: {}", value.type_name() ) ) )
+        }
+    }
+
+    fn kind( &self ) -> TableColumnKind {
+        match self {
+            TableCell::String( _ ) => TableColumnKind::String,
+            TableCell::Integer( _ ) => TableColumnKind::Integer,
+            TableCell::Float( _ ) => TableColumnKind::Float
+        }
+    }
+
+    /// Returns an approximate size of the cell, for the purpose of limiting the script's output.
+    pub fn size( &self ) -> usize {
+        match self {
+            TableCell::String( value ) => value.len(),
+            TableCell::Integer( _ ) | TableCell::Float( _ ) => 8
+        }
+    }
+
+    pub fn to_json( &self ) -> serde_json::Value {
+        match self {
+            TableCell::String( value ) => value.as_str().into(),
+            TableCell::Integer( value ) => (*value).into(),
+            TableCell::Float( value ) => (*value).into()
+        }
+    }
+}
+
+impl std::fmt::Display for TableCell {
+    fn fmt( &self, formatter: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        match self {
+            TableCell::String( value ) => value.fmt( formatter ),
+            TableCell::Integer( value ) => value.fmt( formatter ),
+            TableCell::Float( value ) => value.fmt( formatter )
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TableColumn {
+    pub name: String,
+    /// The type of the column; this is determined by the first row.
+    pub kind: Option< TableColumnKind >
+}
+
+#[derive(Clone, Debug)]
+pub struct Table {
+    pub columns: Vec< TableColumn >,
+    pub rows: Vec< Vec< TableCell > >
+}
+
+impl Table {
+    fn new( columns: rhai::Array ) -> Result< Self, Box< rhai::EvalAltResult > > {
+        let columns = columns.into_iter().map( |name| {
+            if name.is::< String >() {
+                Ok( TableColumn {
+                    name: name.cast::< String >(),
+                    kind: None
+                })
+            } else {
+                Err( error( format!( "table column names must be strings; got: {}", name.type_name() ) ) )
+            }
+        }).collect::< Result< Vec< _ >, _ > >()?;
+
+        if columns.is_empty() {
+            return Err( error( "a table must have at least one column" ) );
+        }
+
+        Ok( Table {
+            columns,
+            rows: Vec::new()
+        })
+    }
+
+    fn add_row( &mut self, row: rhai::Array ) -> Result< (), Box< rhai::EvalAltResult > > {
+        if row.len() != self.columns.len() {
+            return Err( error( format!( "expected a row with {} cells; got {}", self.columns.len(), row.len() ) ) );
+        }
+
+        let mut cells = Vec::with_capacity( row.len() );
+        for (column, value) in self.columns.iter().zip( row ) {
+            let mut cell = TableCell::from_dynamic( value )?;
+            match (column.kind, &cell) {
+                (None, _) => {},
+                (Some( TableColumnKind::Float ), &TableCell::Integer( value )) => {
+                    cell = TableCell::Float( value as f64 );
+                },
+                (Some( kind ), _) if kind == cell.kind() => {},
+                (Some( kind ), _) => {
+                    return Err( error( format!( "expected a value of type '{}' in column '{}'; got '{}'", kind.as_str(), column.name, cell.kind().as_str() ) ) );
+                }
+            }
+
+            cells.push( cell );
+        }
+
+        for (column, cell) in self.columns.iter_mut().zip( cells.iter() ) {
+            if column.kind.is_none() {
+                column.kind = Some( cell.kind() );
+            }
+        }
+
+        self.rows.push( cells );
+        Ok(())
+    }
+
+    pub fn to_json( &self ) -> serde_json::Value {
+        let columns: Vec< _ > = self.columns.iter().map( |column| {
+            serde_json::json! {{
+                "name": column.name,
+                "type": column.kind.map( |kind| kind.as_str() )
+            }}
+        }).collect();
+
+        let rows: Vec< Vec< _ > > = self.rows.iter().map( |row| row.iter().map( |cell| cell.to_json() ).collect() ).collect();
+        serde_json::json! {{
+            "columns": columns,
+            "rows": rows
+        }}
+    }
+}
+
+#[test]
+fn test_table() {
+    let mut table = Table::new( vec![ "name".into(), "count".into(), "ratio".into() ] ).unwrap();
+    table.add_row( vec![ "foo".into(), rhai::Dynamic::from( 1_i64 ), rhai::Dynamic::from( 0.5_f64 ) ] ).unwrap();
+    table.add_row( vec![ "bar".into(), rhai::Dynamic::from( 2_u64 ), rhai::Dynamic::from( 1_i64 ) ] ).unwrap();
+    assert!( table.add_row( vec![ "baz".into(), rhai::Dynamic::from( 0.5_f64 ), rhai::Dynamic::from( 1_i64 ) ] ).is_err() );
+    assert!( table.add_row( vec![ "baz".into() ] ).is_err() );
+    assert!( table.add_row( vec![ "baz".into(), rhai::Dynamic::from( 3_i64 ), rhai::Dynamic::from( true ) ] ).is_err() );
+    assert!( Table::new( vec![] ).is_err() );
+    assert!( Table::new( vec![ rhai::Dynamic::from( 1_i64 ) ] ).is_err() );
+
+    assert_eq!( table.to_json(), serde_json::json! {{
+        "columns": [
+            { "name": "name", "type": "string" },
+            { "name": "count", "type": "integer" },
+            { "name": "ratio", "type": "float" }
+        ],
+        "rows": [
+            [ "foo", 1, 0.5 ],
+            [ "bar", 2, 1.0 ]
+        ]
+    }});
+}
+
+#[test]
+fn test_script_files_limit() {
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new().with_files_limit( Some( 10 ) ) ) );
+    let engine = Engine::new( env.clone(), EngineArgs::default() );
+    engine.run( "save_csv(\"a.csv\", [[1, 2]]); save_file(\"b.bin\", [0, 1]);" ).unwrap();
+
+    let output = std::mem::take( &mut env.lock().output );
+    let files: Vec< _ > = output.iter().map( |entry| {
+        match entry {
+            ScriptOutputKind::File { path, mime, data } => (path.as_str(), *mime, data.len()),
+            _ => panic!()
+        }
+    }).collect();
+    assert_eq!( files, vec![ ("/a.csv", "text/csv; charset=utf-8", 5), ("/b.bin", "application/octet-stream", 2) ] );
+
+    let error = engine.run( "save_file(\"c.txt\", \"0123\");" ).err().unwrap();
+    assert_eq!( error.limit, Some( ScriptLimit::Files ) );
+}
+
+#[test]
+fn test_table_row_limit() {
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new().with_table_row_limit( Some( 3 ) ) ) );
+    let engine = Engine::new( env.clone(), EngineArgs::default() );
+    engine.run( "let t = table([\"index\"]); for i in 0..5 { t.add_row([i]); } t.save();" ).unwrap();
+
+    let output = std::mem::take( &mut env.lock().output );
+    assert_eq!( output.len(), 1 );
+    match output[ 0 ] {
+        ScriptOutputKind::Table { ref table, truncated } => {
+            assert!( truncated );
+            assert_eq!( table.rows, vec![ vec![ TableCell::Integer( 0 ) ], vec![ TableCell::Integer( 1 ) ], vec![ TableCell::Integer( 2 ) ] ] );
+        },
+        _ => panic!()
+    }
+}
+
+pub fn error( message: impl Into< String > ) -> Box< rhai::EvalAltResult > {
+    Box::new( rhai::EvalAltResult::from( message.into() ) )
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FileKind {
+    Svg,
+    Png,
+    Csv,
+    Text,
+    Binary
+}
+
+impl FileKind {
+    pub fn mime( self ) -> &'static str {
+        match self {
+            FileKind::Svg => "image/svg+xml",
+            FileKind::Png => "image/png",
+            FileKind::Csv => "text/csv; charset=utf-8",
+            FileKind::Text => "text/plain; charset=utf-8",
+            FileKind::Binary => "application/octet-stream"
+        }
+    }
+}
+
+fn csv_escape( value: &str ) -> std::borrow::Cow< str > {
+    if value.contains( |ch| ch == ',' || ch == '"' || ch == '\n' || ch == '\r' ) {
+        format!( "\"{}\"", value.replace( '"', "\"\"" ) ).into()
+    } else {
+        value.into()
+    }
+}
+
+fn csv_append_row< I, T >( output: &mut String, row: I ) where I: IntoIterator< Item = T >, T: AsRef< str > {
+    for (index, value) in row.into_iter().enumerate() {
+        if index != 0 {
+            output.push( ',' );
+        }
+        output.push_str( &csv_escape( value.as_ref() ) );
+    }
+    output.push_str( "\r\n" );
+}
+
+fn rows_to_csv( rows: rhai::Array ) -> Result< String, Box< rhai::EvalAltResult > > {
+    let mut output = String::new();
+    for row in rows {
+        if !row.is::< rhai::Array >() {
+            return Err( error( format!( "expected every row to be an array; got: {}", row.type_name() ) ) );
+        }
 
-        Ok( AllocationList {
-            data: lhs.data.clone(),
-            allocation_ids: lhs.allocation_ids.clone(),
-            filter
-        })
-    } else {
-        rhs.apply_filter();
+        csv_append_row( &mut output, row.cast::< rhai::Array >().into_iter().map( to_string ) );
+    }
 
-        let mut set: HashSet< AllocationId > = HashSet::new();
-        set.extend( rhs.unfiltered_allocation_ids() );
+    Ok( output )
+}
 
-        let ids: Vec< _ > = lhs.filtered_allocation_ids().filter( |id| set.contains( id ) ).collect();
-        Ok( AllocationList {
-            data: lhs.data.clone(),
-            allocation_ids: Some( Arc::new( ids ) ),
-            filter: None
-        })
+fn table_to_csv( table: &Table ) -> String {
+    let mut output = String::new();
+    csv_append_row( &mut output, table.columns.iter().map( |column| column.name.as_str() ) );
+    for row in &table.rows {
+        csv_append_row( &mut output, row.iter().map( |cell| cell.to_string() ) );
     }
+
+    output
 }
 
-pub fn error( message: impl Into< String > ) -> Box< rhai::EvalAltResult > {
-    Box::new( rhai::EvalAltResult::from( message.into() ) )
+fn bytes_from_array( array: rhai::Array ) -> Result< Vec< u8 >, Box< rhai::EvalAltResult > > {
+    array.into_iter().map( |value| {
+        match value.clone().try_cast::< i64 >() {
+            Some( byte ) if byte >= 0 && byte <= 255 => Ok( byte as u8 ),
+            _ => Err( error( format!( "expected a byte; got: {}", to_string( value ) ) ) )
+        }
+    }).collect()
 }
 
-#[derive(Copy, Clone)]
-pub enum FileKind {
-    Svg
+#[test]
+fn test_csv() {
+    let rows: rhai::Array = vec![
+        rhai::Dynamic::from( vec![ rhai::Dynamic::from( "name" ), rhai::Dynamic::from( "count" ) ] ),
+        rhai::Dynamic::from( vec![ rhai::Dynamic::from( "a,\"b\"" ), rhai::Dynamic::from( 10_i64 ) ] )
+    ];
+    assert_eq!( rows_to_csv( rows ).unwrap(), "name,count\r\n\"a,\"\"b\"\"\",10\r\n" );
+    assert!( rows_to_csv( vec![ rhai::Dynamic::from( 1_i64 ) ] ).is_err() );
+
+    assert_eq!( bytes_from_array( vec![ rhai::Dynamic::from( 0_i64 ), rhai::Dynamic::from( 255_i64 ) ] ).unwrap(), vec![ 0, 255 ] );
+    assert!( bytes_from_array( vec![ rhai::Dynamic::from( 256_i64 ) ] ).is_err() );
 }
 
 pub struct Engine {
-    inner: rhai::Engine
+    inner: rhai::Engine,
+    timeout: Option< std::time::Duration >,
+    deadline: Arc< Mutex< Option< std::time::Instant > > >
 }
 
 #[derive(Default)]
 pub struct EngineArgs {
     pub argv: Vec< String >,
     pub data: Option< Arc< Data > >,
-    pub allocation_ids: Option< Arc< Vec< AllocationId > > >
+    /// Every data file accessible through `datasets()` and `dataset()`;
+    /// if empty then only `data` (if any) is accessible.
+    pub datasets: Vec< Arc< Data > >,
+    pub allocation_ids: Option< Arc< Vec< AllocationId > > >,
+    /// The maximum wall clock time a single `Engine::run` can take.
+    pub timeout: Option< std::time::Duration >,
+    /// The maximum length of strings, arrays and maps created by the script.
+    pub max_collection_size: Option< usize >
+}
+
+/// A resource limit which can terminate a script.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ScriptLimit {
+    Timeout,
+    Output,
+    Memory,
+    Files
+}
+
+impl ScriptLimit {
+    fn from_token( token: &str ) -> Option< Self > {
+        match token {
+            "timeout" => Some( ScriptLimit::Timeout ),
+            "output_limit" => Some( ScriptLimit::Output ),
+            "memory_limit" => Some( ScriptLimit::Memory ),
+            "file_limit" => Some( ScriptLimit::Files ),
+            _ => None
+        }
+    }
+
+    pub fn as_str( self ) -> &'static str {
+        match self {
+            ScriptLimit::Timeout => "timeout",
+            ScriptLimit::Output => "output_limit",
+            ScriptLimit::Memory => "memory_limit",
+            ScriptLimit::Files => "file_limit"
+        }
+    }
+
+    /// Returns an error which terminates the script.
+    pub fn exceeded( self ) -> Box< rhai::EvalAltResult > {
+        Box::new( rhai::EvalAltResult::ErrorTerminated( self.as_str().into(), rhai::Position::NONE ) )
+    }
+}
+
+/// Checks whether the script was terminated because it exceeded one of its limits.
+fn exceeded_limit( mut error: &rhai::EvalAltResult ) -> Option< ScriptLimit > {
+    loop {
+        match error {
+            rhai::EvalAltResult::ErrorInFunctionCall( _, _, inner, _ ) |
+            rhai::EvalAltResult::ErrorInModule( _, inner, _ ) => {
+                error = inner;
+            },
+            rhai::EvalAltResult::ErrorTerminated( token, _ ) => {
+                return token.clone().try_cast::< rhai::ImmutableString >().and_then( |token| ScriptLimit::from_token( &token ) );
+            },
+            rhai::EvalAltResult::ErrorDataTooLarge( .. ) => return Some( ScriptLimit::Memory ),
+            _ => return None
+        }
+    }
 }
 
 pub trait Environment {
-    fn println( &mut self, message: &str );
+    fn println( &mut self, message: &str ) -> Result< (), Box< rhai::EvalAltResult > >;
     fn mkdir_p( &mut self, path: &str ) -> Result< (), Box< rhai::EvalAltResult > >;
     fn chdir( &mut self, path: &str ) -> Result< (), Box< rhai::EvalAltResult > >;
     fn file_write( &mut self, path: &str, kind: FileKind, contents: &[u8] ) -> Result< (), Box< rhai::EvalAltResult > >;
@@ -1490,14 +2776,25 @@ pub trait Environment {
     fn load( &mut self, _path: String ) -> Result< Arc< Data >, Box< rhai::EvalAltResult > > {
         Err( error( "unsupported in this environment" ) )
     }
+    fn table( &mut self, table: &Table ) -> Result< (), Box< rhai::EvalAltResult > > {
+        let header: Vec< _ > = table.columns.iter().map( |column| column.name.as_str() ).collect();
+        self.println( &header.join( "\t" ) )?;
+        for row in &table.rows {
+            let row: Vec< _ > = row.iter().map( |cell| cell.to_string() ).collect();
+            self.println( &row.join( "\t" ) )?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Default)]
 pub struct NativeEnvironment {}
 
 impl Environment for NativeEnvironment {
-    fn println( &mut self, message: &str ) {
+    fn println( &mut self, message: &str ) -> Result< (), Box< rhai::EvalAltResult > > {
         println!( "{}", message );
+        Ok(())
     }
 
     fn mkdir_p( &mut self, path: &str ) -> Result< (), Box< rhai::EvalAltResult > > {
@@ -1553,6 +2850,8 @@ fn to_string( value: rhai::plugin::Dynamic ) -> String {
         format!( "{} allocation(s)", value.len() )
     } else if value.is::< Backtrace >() {
         value.cast::< Backtrace >().to_string()
+    } else if value.is::< Frame >() {
+        value.cast::< Frame >().to_string()
     } else {
         value.type_name().into()
     }
@@ -1626,6 +2925,28 @@ impl Engine {
 
         let argv = args.argv;
 
+        if let Some( max_size ) = args.max_collection_size {
+            engine.set_max_string_size( max_size );
+            engine.set_max_array_size( max_size );
+            engine.set_max_map_size( max_size );
+        }
+
+        let deadline: Arc< Mutex< Option< std::time::Instant > > > = Default::default();
+        if args.timeout.is_some() {
+            let deadline = deadline.clone();
+            engine.on_progress( move |operations| {
+                // Checking the time is relatively expensive, so don't do it on every operation.
+                if operations % 1024 != 0 {
+                    return None;
+                }
+
+                match *deadline.lock() {
+                    Some( deadline ) if std::time::Instant::now() >= deadline => Some( ScriptLimit::Timeout.as_str().into() ),
+                    _ => None
+                }
+            });
+        }
+
         // Utility functions.
         engine.register_fn( "dirname", dirname );
         engine.register_fn( "h", |value: i64| Duration::from_secs( value as u64 * 3600 ) );
@@ -1676,7 +2997,13 @@ impl Engine {
         engine.register_type::< AllocationList >();
         engine.register_type::< AllocationGroupList >();
         engine.register_type::< Backtrace >();
+        engine.register_type::< Frame >();
         engine.register_type::< Graph >();
+        engine.register_type::< Table >();
+        engine.register_type::< Map >();
+        engine.register_type::< MapList >();
+        engine.register_type::< Mallopt >();
+        engine.register_type::< Marker >();
         engine.register_result_fn( "+", merge_allocations );
         engine.register_result_fn( "-", substract_allocations );
         engine.register_result_fn( "&", intersect_allocations );
@@ -1684,6 +3011,9 @@ impl Engine {
         engine.register_fn( "add", Graph::add );
         engine.register_fn( "add", Graph::add_with_label );
         engine.register_fn( "add", Graph::add_group );
+        engine.register_fn( "add", Graph::add_maps );
+        engine.register_fn( "add", Graph::add_maps_with_label );
+        engine.register_fn( "add_marker", Graph::add_marker );
         engine.register_fn( "trim_left", Graph::trim_left );
         engine.register_fn( "trim_right", Graph::trim_right );
         engine.register_fn( "trim", Graph::trim );
@@ -1693,20 +3023,55 @@ impl Engine {
         engine.register_fn( "without_legend", Graph::without_legend );
         engine.register_fn( "without_axes", Graph::without_axes );
         engine.register_fn( "without_grid", Graph::without_grid );
+        engine.register_fn( "with_log_scale", Graph::with_log_scale );
+        engine.register_fn( "bytes_formatting", Graph::bytes_formatting );
+        engine.register_fn( "with_title", Graph::with_title );
+        engine.register_result_fn( "with_range", Graph::with_range );
+        engine.register_result_fn( "between", Graph::between );
         engine.register_fn( "show_memory_usage", Graph::show_memory_usage );
         engine.register_fn( "show_live_allocations", Graph::show_live_allocations );
         engine.register_fn( "show_new_allocations", Graph::show_new_allocations );
         engine.register_fn( "show_deallocations", Graph::show_deallocations );
 
         engine.register_result_fn( "with_gradient_color_scheme", Graph::with_gradient_color_scheme );
+        engine.register_result_fn( "table", Table::new );
+        engine.register_result_fn( "add_row", Table::add_row );
+        engine.register_fn( "len", |table: &mut Table| table.rows.len() as i64 );
         engine.register_fn( "allocations", DataRef::allocations );
+        engine.register_fn( "maps", DataRef::maps );
+        engine.register_fn( "mallopts", DataRef::mallopts );
+        engine.register_fn( "markers", DataRef::markers );
         engine.register_fn( "runtime", |data: &mut DataRef| Duration( data.0.last_timestamp - data.0.initial_timestamp ) );
+        engine.register_fn( "id", |data: &mut DataRef| data.0.id().to_string() );
+        engine.register_fn( "executable", |data: &mut DataRef| data.0.executable().to_owned() );
+        engine.register_fn( "architecture", |data: &mut DataRef| data.0.architecture().to_owned() );
 
         engine.register_fn( "strip", |backtrace: &mut Backtrace| {
             let mut cloned = backtrace.clone();
             cloned.strip = true;
             cloned
         });
+        engine.register_fn( "id", Backtrace::id );
+        engine.register_fn( "frames", Backtrace::frames );
+        engine.register_result_fn( "backtrace", DataRef::backtrace );
+
+        engine.register_fn( "address", Frame::address );
+        engine.register_fn( "function", Frame::function );
+        engine.register_fn( "raw_function", Frame::raw_function );
+        engine.register_fn( "library", Frame::library );
+        engine.register_fn( "source", Frame::source );
+        engine.register_fn( "line", Frame::line );
+        engine.register_fn( "column", Frame::column );
+        engine.register_fn( "is_inline", Frame::is_inline );
+
+        engine.register_fn( "strip_template", |name: &str| crate::strip_template( name, 0 ) );
+        engine.register_result_fn( "strip_template", |name: &str, keep_depth: i64| {
+            if keep_depth < 0 {
+                return Err( error( "the depth can't be negative" ) );
+            }
+
+            Ok( crate::strip_template( name, keep_depth as usize ) )
+        });
 
         fn set_max< T >( target: &mut Option< T >, value: T ) where T: PartialOrd {
             if let Some( target ) = target.as_mut() {
@@ -1919,6 +3284,45 @@ impl Engine {
 
         engine.register_fn( "group_by_backtrace", AllocationList::group_by_backtrace );
 
+        engine.register_fn( "len", MapList::len );
+        engine.register_indexer_get_result( MapList::get );
+        engine.register_iterator::< MapList >();
+        engine.register_fn( "only_larger", MapList::only_larger );
+        engine.register_fn( "only_larger_or_equal", MapList::only_larger_or_equal );
+        engine.register_fn( "only_smaller", MapList::only_smaller );
+        engine.register_fn( "only_smaller_or_equal", MapList::only_smaller_or_equal );
+        engine.register_fn( "only_mmaps", MapList::only_mmaps );
+        engine.register_fn( "only_munmaps", MapList::only_munmaps );
+        engine.register_fn( "only_anonymous", MapList::only_anonymous );
+        engine.register_fn( "only_not_anonymous", MapList::only_not_anonymous );
+        engine.register_fn( "only_after_at_least", MapList::only_after_at_least );
+        engine.register_fn( "only_until_at_most", MapList::only_until_at_most );
+
+        engine.register_fn( "timestamp", Map::timestamp );
+        engine.register_fn( "address", Map::address );
+        engine.register_fn( "length", Map::length );
+        engine.register_fn( "is_unmap", Map::is_unmap );
+        engine.register_fn( "kind", Map::kind );
+        engine.register_fn( "protection", Map::protection );
+        engine.register_fn( "flags", Map::flags );
+        engine.register_fn( "fd", Map::fd );
+        engine.register_fn( "backtrace", Map::backtrace );
+
+        engine.register_fn( "timestamp", Mallopt::timestamp );
+        engine.register_fn( "thread_id", Mallopt::thread );
+        engine.register_fn( "kind", Mallopt::kind );
+        engine.register_fn( "raw_kind", Mallopt::raw_kind );
+        engine.register_fn( "value", Mallopt::value );
+        engine.register_fn( "result", Mallopt::result );
+        engine.register_fn( "backtrace_id", Mallopt::backtrace_id );
+        engine.register_fn( "backtrace", Mallopt::backtrace );
+
+        engine.register_fn( "timestamp", Marker::timestamp );
+        engine.register_fn( "thread_id", Marker::thread );
+        engine.register_fn( "value", Marker::value );
+        engine.register_fn( "backtrace_id", Marker::backtrace_id );
+        engine.register_fn( "backtrace", Marker::backtrace );
+
         engine.register_fn( "only_all_leaked", AllocationGroupList::only_all_leaked );
         engine.register_fn( "only_count_at_least", AllocationGroupList::only_count_at_least );
         engine.register_fn( "len", AllocationGroupList::len );
@@ -1967,6 +3371,29 @@ impl Engine {
             });
         }
 
+        {
+            let datasets: Arc< Vec< Arc< Data > > > =
+                if args.datasets.is_empty() {
+                    Arc::new( args.data.iter().cloned().collect() )
+                } else {
+                    Arc::new( args.datasets.clone() )
+                };
+
+            {
+                let datasets = datasets.clone();
+                engine.register_fn( "datasets", move || -> rhai::Array {
+                    datasets.iter().map( |data| rhai::Dynamic::from( DataRef( data.clone() ) ) ).collect()
+                });
+            }
+
+            engine.register_result_fn( "dataset", move |id: String| {
+                datasets.iter()
+                    .find( |data| data.id().to_string() == id )
+                    .map( |data| DataRef( data.clone() ) )
+                    .ok_or_else( || error( format!( "no data file with ID '{}'", id ) ) )
+            });
+        }
+
         {
             let data = args.data.clone();
             let allocation_ids = args.allocation_ids.clone();
@@ -1983,6 +3410,50 @@ impl Engine {
             });
         }
 
+        {
+            let data = args.data.clone();
+            engine.register_result_fn( "maps", move || {
+                if let Some( ref data ) = data {
+                    Ok( DataRef( data.clone() ).maps() )
+                } else {
+                    Err( error( "no globally loaded maps" ) )
+                }
+            });
+        }
+
+        {
+            let data = args.data.clone();
+            engine.register_result_fn( "mallopts", move || {
+                if let Some( ref data ) = data {
+                    Ok( DataRef( data.clone() ).mallopts() )
+                } else {
+                    Err( error( "no globally loaded mallopts" ) )
+                }
+            });
+        }
+
+        {
+            let data = args.data.clone();
+            engine.register_result_fn( "backtrace", move |id: i64| {
+                if let Some( ref data ) = data {
+                    DataRef( data.clone() ).backtrace( id )
+                } else {
+                    Err( error( "no globally loaded backtraces" ) )
+                }
+            });
+        }
+
+        {
+            let data = args.data.clone();
+            engine.register_result_fn( "markers", move || {
+                if let Some( ref data ) = data {
+                    Ok( DataRef( data.clone() ).markers() )
+                } else {
+                    Err( error( "no globally loaded markers" ) )
+                }
+            });
+        }
+
         {
             let env = env.clone();
             engine.register_result_fn( "load", move |path: String| Ok( DataRef( env.lock().load( path )? ) ) );
@@ -1995,6 +3466,41 @@ impl Engine {
                 move |graph: &mut Graph, path: String| Graph::save( graph, &mut *env.lock(), path )
             );
         }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save",
+                move |table: &mut Table| env.lock().table( table )
+            );
+        }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save_csv",
+                move |path: String, rows: rhai::Array| env.lock().file_write( &path, FileKind::Csv, rows_to_csv( rows )?.as_bytes() )
+            );
+        }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save_csv",
+                move |path: String, table: Table| env.lock().file_write( &path, FileKind::Csv, table_to_csv( &table ).as_bytes() )
+            );
+        }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save_file",
+                move |path: String, contents: String| env.lock().file_write( &path, FileKind::Text, contents.as_bytes() )
+            );
+        }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save_file",
+                move |path: String, contents: rhai::Array| env.lock().file_write( &path, FileKind::Binary, &bytes_from_array( contents )? )
+            );
+        }
         {
             let env = env.clone();
             let graph_counter = graph_counter.clone();
@@ -2003,6 +3509,21 @@ impl Engine {
                 move |graph: &mut Graph| Graph::save( graph, &mut *env.lock(), format!( "Graph #{}.svg", get_counter( &graph_counter ) ) )
             );
         }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save_as_png",
+                move |graph: &mut Graph, path: String, width: i64, height: i64| Graph::save_as_png( graph, &mut *env.lock(), path, width, height )
+            );
+        }
+        {
+            let env = env.clone();
+            let graph_counter = graph_counter.clone();
+            engine.register_result_fn(
+                "save_as_png",
+                move |graph: &mut Graph, width: i64, height: i64| Graph::save_as_png( graph, &mut *env.lock(), format!( "Graph #{}.png", get_counter( &graph_counter ) ), width, height )
+            );
+        }
         {
             let env = env.clone();
             engine.register_result_fn(
@@ -2017,6 +3538,20 @@ impl Engine {
                 move |graph: &mut Graph| Graph::save_each_series_as_graph( graph, &mut *env.lock(), ".".into() )
             );
         }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save_each_series_as_png",
+                move |graph: &mut Graph, path: String, width: i64, height: i64| Graph::save_each_series_as_png( graph, &mut *env.lock(), path, width, height )
+            );
+        }
+        {
+            let env = env.clone();
+            engine.register_result_fn(
+                "save_each_series_as_png",
+                move |graph: &mut Graph, width: i64, height: i64| Graph::save_each_series_as_png( graph, &mut *env.lock(), ".".into(), width, height )
+            );
+        }
         {
             let env = env.clone();
             engine.register_result_fn(
@@ -2064,20 +3599,20 @@ impl Engine {
 
         {
             let env = env.clone();
-            engine.register_fn(
+            engine.register_result_fn(
                 "println",
                 move || {
-                    env.lock().println( "" );
+                    env.lock().println( "" )
                 }
             );
         }
 
         {
             let env = env.clone();
-            engine.register_fn(
+            engine.register_result_fn(
                 "println",
                 move |a0: rhai::plugin::Dynamic| {
-                    env.lock().println( &to_string( a0 ) );
+                    env.lock().println( &to_string( a0 ) )
                 }
             );
         }
@@ -2090,8 +3625,7 @@ impl Engine {
                     let a0 = to_string( a0 );
                     let a1 = to_string( a1 );
                     let message = format( &a0, &[&a1] )?;
-                    env.lock().println( &message );
-                    Ok(())
+                    env.lock().println( &message )
                 }
             );
         }
@@ -2105,8 +3639,7 @@ impl Engine {
                     let a1 = to_string( a1 );
                     let a2 = to_string( a2 );
                     let message = format( &a0, &[&a1, &a2] )?;
-                    env.lock().println( &message );
-                    Ok(())
+                    env.lock().println( &message )
                 }
             );
         }
@@ -2121,43 +3654,253 @@ impl Engine {
                     let a2 = to_string( a2 );
                     let a3 = to_string( a3 );
                     let message = format( &a0, &[&a1, &a2, &a3] )?;
-                    env.lock().println( &message );
-                    Ok(())
+                    env.lock().println( &message )
                 }
             );
         }
 
         Engine {
-            inner: engine
+            inner: engine,
+            timeout: args.timeout,
+            deadline
         }
     }
 
-    pub fn run( &self, code: &str ) -> Result< Option< AllocationList >, EvalError > {
-        match self.inner.eval::< rhai::plugin::Dynamic >( code ) {
-            Ok( value ) => {
-                if value.is::< AllocationList >() {
-                    Ok( Some( value.cast::< AllocationList >() ) )
-                } else {
-                    Ok( None )
-                }
-            },
-            Err( error ) => {
-                let p = error.position();
-                Err( EvalError {
-                    message: error.to_string(),
-                    line: p.line(),
-                    column: p.position()
-                })
+    pub fn run( &self, code: &str ) -> Result< ScriptValue, EvalError > {
+        *self.deadline.lock() = self.timeout.map( |timeout| std::time::Instant::now() + timeout );
+        convert_result( self.inner.eval::< rhai::plugin::Dynamic >( code ) )
+    }
+
+    /// Compiles the script without running it, returning the first syntax error, if any.
+    pub fn check( &self, code: &str ) -> Result< (), EvalError > {
+        self.inner.compile( code ).map( |_| () ).map_err( convert_parse_error )
+    }
+
+    /// Runs a snippet of code in the given session, keeping any variables and functions
+    /// it defines around for the snippets which will be run later.
+    pub fn run_in_session( &self, session: &mut Session, code: &str ) -> Result< ScriptValue, EvalError > {
+        *self.deadline.lock() = self.timeout.map( |timeout| std::time::Instant::now() + timeout );
+        let ast = self.inner.compile_with_scope( &session.scope, code ).map_err( convert_parse_error )?;
+
+        let variable_count = session.scope.len();
+        session.ast += ast;
+        let result = self.inner.eval_ast_with_scope::< rhai::plugin::Dynamic >( &mut session.scope, &session.ast );
+        session.ast.clear_statements();
+
+        if session.is_over_the_limits() {
+            session.scope.rewind( variable_count );
+
+            // The variables which already existed could have grown too.
+            if session.is_over_the_limits() {
+                session.scope.clear();
             }
+
+            return convert_result( Err( ScriptLimit::Memory.exceeded() ) );
+        }
+
+        convert_result( result )
+    }
+}
+
+fn convert_parse_error( error: rhai::ParseError ) -> EvalError {
+    EvalError {
+        message: error.to_string(),
+        line: error.1.line(),
+        column: error.1.position(),
+        limit: None
+    }
+}
+
+fn convert_result( result: Result< rhai::plugin::Dynamic, Box< rhai::EvalAltResult > > ) -> Result< ScriptValue, EvalError > {
+    match result {
+        Ok( value ) => {
+            if value.is::< AllocationList >() {
+                Ok( ScriptValue::AllocationList( value.cast::< AllocationList >() ) )
+            } else if value.is::< MapList >() {
+                Ok( ScriptValue::MapList( value.cast::< MapList >() ) )
+            } else {
+                Ok( ScriptValue::Other )
+            }
+        },
+        Err( error ) => {
+            let p = error.position();
+            let limit = exceeded_limit( &error );
+            let message = match limit {
+                Some( ScriptLimit::Timeout ) => "the script took too long to run".to_owned(),
+                Some( ScriptLimit::Output ) => "the script generated too much output".to_owned(),
+                Some( ScriptLimit::Memory ) => "the script used too much memory".to_owned(),
+                Some( ScriptLimit::Files ) => "the script saved too much data into files".to_owned(),
+                None => error.to_string()
+            };
+
+            Err( EvalError {
+                message,
+                line: p.line(),
+                column: p.position(),
+                limit
+            })
         }
     }
 }
 
+/// The maximum number of variables which can be defined in a single session.
+const MAXIMUM_SESSION_VARIABLE_COUNT: usize = 1024;
+
+/// State which persists between the snippets run through `Engine::run_in_session`.
+#[derive(Default)]
+pub struct Session {
+    scope: rhai::Scope< 'static >,
+    ast: rhai::AST,
+    max_memory_usage: Option< usize >
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits how much memory, roughly, the variables defined in the session can take.
+    pub fn with_max_memory_usage( mut self, max_memory_usage: usize ) -> Self {
+        self.max_memory_usage = Some( max_memory_usage );
+        self
+    }
+
+    fn memory_usage_estimate( &self ) -> usize {
+        self.scope.iter_raw().map( |(name, _, value)| name.len() + value_memory_usage_estimate( value ) ).sum()
+    }
+
+    fn is_over_the_limits( &self ) -> bool {
+        self.scope.len() > MAXIMUM_SESSION_VARIABLE_COUNT ||
+            self.max_memory_usage.map( |limit| self.memory_usage_estimate() > limit ).unwrap_or( false )
+    }
+}
+
+/// Shared strings are counted every time they're referenced, so this can only overestimate.
+fn value_memory_usage_estimate( value: &rhai::Dynamic ) -> usize {
+    let mut size = std::mem::size_of::< rhai::Dynamic >();
+    if let Some( string ) = value.read_lock::< rhai::ImmutableString >() {
+        size += string.len();
+    } else if let Some( array ) = value.read_lock::< rhai::Array >() {
+        size += array.iter().map( value_memory_usage_estimate ).sum::< usize >();
+    } else if let Some( map ) = value.read_lock::< rhai::Map >() {
+        size += map.iter().map( |(key, value)| key.len() + value_memory_usage_estimate( value ) ).sum::< usize >();
+    }
+
+    size
+}
+
 #[derive(Debug)]
 pub struct EvalError {
     pub message: String,
     pub line: Option< usize >,
-    pub column: Option< usize >
+    pub column: Option< usize >,
+    /// Set if the script was terminated for exceeding one of its limits.
+    pub limit: Option< ScriptLimit >
+}
+
+#[test]
+fn test_script_timeout() {
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new() ) );
+    let args = EngineArgs {
+        timeout: Some( std::time::Duration::from_millis( 50 ) ),
+        .. EngineArgs::default()
+    };
+
+    let engine = Engine::new( env, args );
+    let error = engine.run( "loop {}" ).err().unwrap();
+    assert_eq!( error.limit, Some( ScriptLimit::Timeout ) );
+
+    assert!( engine.run( "let x = 1 + 2;" ).is_ok() );
+}
+
+#[test]
+fn test_script_session() {
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new() ) );
+    let engine = Engine::new( env.clone(), EngineArgs::default() );
+    let mut session = Session::new();
+    assert!( engine.run_in_session( &mut session, "let x = 40; fn add( a, b ) { a + b }" ).is_ok() );
+    assert!( engine.run_in_session( &mut session, "println( add( x, 2 ).to_string() );" ).is_ok() );
+    assert!( engine.run_in_session( &mut session, "let y = " ).is_err() );
+    assert!( engine.run_in_session( &mut session, "nonexistent();" ).is_err() );
+    assert!( engine.run_in_session( &mut session, "println( x.to_string() );" ).is_ok() );
+
+    let output: Vec< _ > = env.lock().output.iter().map( |entry| {
+        match entry {
+            ScriptOutputKind::PrintLine( line ) => line.clone(),
+            _ => unreachable!()
+        }
+    }).collect();
+    assert_eq!( output, vec![ "42".to_owned(), "40".to_owned() ] );
+
+    let args = EngineArgs {
+        max_collection_size: Some( 16 ),
+        .. EngineArgs::default()
+    };
+    let engine = Engine::new( env.clone(), args );
+    let error = engine.run_in_session( &mut session, "let a = []; loop { a.push( 1 ); }" ).err().unwrap();
+    assert_eq!( error.limit, Some( ScriptLimit::Memory ) );
+
+    let engine = Engine::new( env, EngineArgs::default() );
+    let mut session = Session::new().with_max_memory_usage( 64 * 1024 );
+    assert!( engine.run_in_session( &mut session, "let a = []; for i in 0..100 { a.push( i ); }" ).is_ok() );
+    let error = engine.run_in_session( &mut session, "let b = []; for i in 0..10000 { b.push( i ); }" ).err().unwrap();
+    assert_eq!( error.limit, Some( ScriptLimit::Memory ) );
+    assert!( engine.run_in_session( &mut session, "a.len();" ).is_ok() );
+    assert!( engine.run_in_session( &mut session, "b.len();" ).is_err() );
+
+    let error = engine.run_in_session( &mut session, "for i in 0..10000 { a.push( i ); }" ).err().unwrap();
+    assert_eq!( error.limit, Some( ScriptLimit::Memory ) );
+    assert!( engine.run_in_session( &mut session, "a.len();" ).is_err() );
+}
+
+#[test]
+fn test_script_sandbox() {
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new().with_sandbox( true ) ) );
+    let engine = Engine::new( env.clone(), EngineArgs::default() );
+    assert!( engine.run( "mkdir_p(\"foo/bar\"); chdir(\"foo\"); save_file(\"bar/a.txt\", \"a\"); save_file(\"b.txt\", \"b\");" ).is_ok() );
+
+    for path in &["/etc/passwd", "../a.txt", "bar/../../a.txt", "..", "/"] {
+        let error = engine.run( &format!( "save_file({:?}, \"\");", path ) ).err().unwrap();
+        assert!( error.message.contains( "access denied" ), "{}: {}", path, error.message );
+        assert!( engine.run( &format!( "mkdir_p({:?});", path ) ).is_err(), "{}", path );
+        assert!( engine.run( &format!( "chdir({:?});", path ) ).is_err(), "{}", path );
+    }
+
+    // Names which merely start with dots are fine.
+    assert!( engine.run( "save_file(\"..a.txt\", \"\"); save_file(\"bar/.a..txt\", \"\");" ).is_ok() );
+    assert_eq!( env.lock().output.len(), 4 );
+
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new() ) );
+    let engine = Engine::new( env, EngineArgs::default() );
+    assert!( engine.run( "save_file(\"/a.txt\", \"\");" ).is_ok() );
+}
+
+#[test]
+fn test_script_check() {
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new() ) );
+    let engine = Engine::new( env.clone(), EngineArgs::default() );
+    assert!( engine.check( "let x = 1;\nprintln( x.to_string() );" ).is_ok() );
+    assert!( engine.check( "nonexistent();" ).is_ok() );
+
+    let error = engine.check( "let x = 1;\nlet y = (x + 2;" ).err().unwrap();
+    assert_eq!( error.line, Some( 2 ) );
+    assert_eq!( error.limit, None );
+
+    assert!( env.lock().output.is_empty() );
+}
+
+#[test]
+fn test_script_output_limit() {
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new().with_output_limit( Some( 10 ) ) ) );
+    let engine = Engine::new( env.clone(), EngineArgs::default() );
+    let error = engine.run( "fn foo() { loop { println(\"0123\"); } } foo();" ).err().unwrap();
+    assert_eq!( error.limit, Some( ScriptLimit::Output ) );
+    assert_eq!( env.lock().output.len(), 2 );
+
+    let env = Arc::new( Mutex::new( VirtualEnvironment::new() ) );
+    let engine = Engine::new( env, EngineArgs::default() );
+    let error = engine.run( "nonexistent();" ).err().unwrap();
+    assert_eq!( error.limit, None );
 }
 
 pub fn run_script( path: &Path, data_path: Option< &Path >, argv: Vec< String > ) -> Result< (), std::io::Error > {
@@ -2265,13 +4008,31 @@ pub fn run_script_slave( data_path: Option< &Path > ) -> Result< (), std::io::Er
 
                             println!( "{}", serde_json::to_string( &payload ).unwrap() );
                         },
-                        ScriptOutputKind::Image { path, data } => {
+                        ScriptOutputKind::Image { path, mime, data } => {
                             let payload = serde_json::json! {{
                                 "kind": "image",
                                 "path": path,
+                                "mime": mime,
+                                "data": &data[..]
+                            }};
+
+                            println!( "{}", serde_json::to_string( &payload ).unwrap() );
+                        },
+                        ScriptOutputKind::File { path, mime, data } => {
+                            let payload = serde_json::json! {{
+                                "kind": "file",
+                                "path": path,
+                                "mime": mime,
                                 "data": &data[..]
                             }};
 
+                            println!( "{}", serde_json::to_string( &payload ).unwrap() );
+                        },
+                        ScriptOutputKind::Table { table, truncated } => {
+                            let mut payload = table.to_json();
+                            payload[ "kind" ] = "table".into();
+                            payload[ "truncated" ] = truncated.into();
+
                             println!( "{}", serde_json::to_string( &payload ).unwrap() );
                         }
                     }
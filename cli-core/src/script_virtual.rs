@@ -161,14 +161,32 @@ pub enum ScriptOutputKind {
     PrintLine( String ),
     Image {
         path: String,
+        mime: &'static str,
         data: Arc< Vec< u8 > >
+    },
+    File {
+        path: String,
+        mime: &'static str,
+        data: Arc< Vec< u8 > >
+    },
+    Table {
+        table: crate::script::Table,
+        /// Whether some of the rows were dropped due to the row limit.
+        truncated: bool
     }
 }
 
 pub struct VirtualEnvironment {
     cwd: String,
     root: Arc< Node >,
-    pub output: Vec< ScriptOutputKind >
+    pub output: Vec< ScriptOutputKind >,
+    output_sender: Option< crossbeam_channel::Sender< ScriptOutputKind > >,
+    output_size: usize,
+    max_output_size: Option< usize >,
+    max_table_rows: Option< usize >,
+    files_size: usize,
+    max_files_size: Option< usize >,
+    sandboxed: bool
 }
 
 impl VirtualEnvironment {
@@ -180,7 +198,72 @@ impl VirtualEnvironment {
                 name: "".into(),
                 kind: Mutex::new( NodeKind::Directory( Default::default() ) )
             }),
-            output: Default::default()
+            output: Default::default(),
+            output_sender: None,
+            output_size: 0,
+            max_output_size: None,
+            max_table_rows: None,
+            files_size: 0,
+            max_files_size: None,
+            sandboxed: false
+        }
+    }
+
+    /// Limits the total size of everything the script prints and writes.
+    pub fn with_output_limit( mut self, max_output_size: Option< usize > ) -> Self {
+        self.max_output_size = max_output_size;
+        self
+    }
+
+    /// Limits the number of rows of every table the script outputs; any extra rows are dropped.
+    pub fn with_table_row_limit( mut self, max_table_rows: Option< usize > ) -> Self {
+        self.max_table_rows = max_table_rows;
+        self
+    }
+
+    /// Limits the total size of all of the files the script writes.
+    pub fn with_files_limit( mut self, max_files_size: Option< usize > ) -> Self {
+        self.max_files_size = max_files_size;
+        self
+    }
+
+    /// Rejects every path which is absolute or which contains a `..` component.
+    pub fn with_sandbox( mut self, sandboxed: bool ) -> Self {
+        self.sandboxed = sandboxed;
+        self
+    }
+
+    fn check_path( &self, path: &str ) -> Result< (), Box< rhai::EvalAltResult > > {
+        if self.sandboxed && (path.starts_with( '/' ) || path.split( '/' ).any( |component| component == ".." )) {
+            return Err( crate::script::error( format!( "access denied to \"{}\": only relative paths without '..' are allowed", path ) ) );
+        }
+
+        Ok(())
+    }
+
+    /// Sends the output through the given channel as soon as it's produced instead of accumulating it in `output`.
+    pub fn with_output_channel( mut self, sender: crossbeam_channel::Sender< ScriptOutputKind > ) -> Self {
+        self.output_sender = Some( sender );
+        self
+    }
+
+    fn push_output( &mut self, item: ScriptOutputKind ) {
+        let item = match self.output_sender {
+            Some( ref sender ) => match sender.send( item ) {
+                Ok( () ) => return,
+                Err( crossbeam_channel::SendError( item ) ) => item
+            },
+            None => item
+        };
+
+        self.output.push( item );
+    }
+
+    fn account_output( &mut self, size: usize ) -> Result< (), Box< rhai::EvalAltResult > > {
+        self.output_size += size;
+        match self.max_output_size {
+            Some( max_output_size ) if self.output_size > max_output_size => Err( crate::script::ScriptLimit::Output.exceeded() ),
+            _ => Ok(())
         }
     }
 
@@ -208,11 +291,14 @@ impl VirtualEnvironment {
 }
 
 impl crate::script::Environment for VirtualEnvironment {
-    fn println( &mut self, message: &str ) {
-        self.output.push( ScriptOutputKind::PrintLine( message.into() ) );
+    fn println( &mut self, message: &str ) -> Result< (), Box< rhai::EvalAltResult > > {
+        self.account_output( message.len() )?;
+        self.push_output( ScriptOutputKind::PrintLine( message.into() ) );
+        Ok(())
     }
 
     fn mkdir_p( &mut self, path: &str ) -> Result< (), Box< rhai::EvalAltResult > > {
+        self.check_path( path )?;
         let path = self.normalize_path( path );
         let mut node = self.root.clone();
         for chunk in path[ 1.. ].split( "/" ) {
@@ -222,6 +308,7 @@ impl crate::script::Environment for VirtualEnvironment {
     }
 
     fn chdir( &mut self, path: &str ) -> Result< (), Box< rhai::EvalAltResult > > {
+        self.check_path( path )?;
         let path = self.normalize_path( path );
         self.root.get_directory_by_relative_path( &path[ 1.. ] )?;
         self.cwd = path.into();
@@ -234,6 +321,14 @@ impl crate::script::Environment for VirtualEnvironment {
         kind: crate::script::FileKind,
         contents: &[u8]
     ) -> Result< (), Box< rhai::EvalAltResult > > {
+        self.check_path( path )?;
+        self.account_output( contents.len() )?;
+        self.files_size += contents.len();
+        match self.max_files_size {
+            Some( max_files_size ) if self.files_size > max_files_size => return Err( crate::script::ScriptLimit::Files.exceeded() ),
+            _ => {}
+        }
+
         let path = self.normalize_path( path );
         let index = path.rfind( "/" ).unwrap();
         let dirname = &path[ ..std::cmp::max( 1, index ) ];
@@ -260,9 +355,17 @@ impl crate::script::Environment for VirtualEnvironment {
 
         use crate::script::FileKind;
         match kind {
-            FileKind::Svg => {
-                self.output.push( ScriptOutputKind::Image {
+            FileKind::Svg | FileKind::Png => {
+                self.push_output( ScriptOutputKind::Image {
+                    path,
+                    mime: kind.mime(),
+                    data: contents
+                });
+            },
+            FileKind::Csv | FileKind::Text | FileKind::Binary => {
+                self.push_output( ScriptOutputKind::File {
                     path,
+                    mime: kind.mime(),
                     data: contents
                 });
             }
@@ -270,4 +373,22 @@ impl crate::script::Environment for VirtualEnvironment {
 
         Ok(())
     }
+
+    fn table( &mut self, table: &crate::script::Table ) -> Result< (), Box< rhai::EvalAltResult > > {
+        let mut table = table.clone();
+        let truncated = match self.max_table_rows {
+            Some( max_table_rows ) if table.rows.len() > max_table_rows => {
+                table.rows.truncate( max_table_rows );
+                true
+            },
+            _ => false
+        };
+
+        let size = table.columns.iter().map( |column| column.name.len() ).sum::< usize >()
+            + table.rows.iter().flat_map( |row| row.iter() ).map( |cell| cell.size() ).sum::< usize >();
+        self.account_output( size )?;
+
+        self.push_output( ScriptOutputKind::Table { table, truncated } );
+        Ok(())
+    }
 }
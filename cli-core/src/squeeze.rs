@@ -365,7 +365,10 @@ pub fn squeeze_data< F, G >( input_fp: F, output_fp: G, threshold: Option< u64 >
                 },
                 Event::MemoryMap { ref mut backtrace, .. } |
                 Event::MemoryUnmap { ref mut backtrace, .. } |
-                Event::Mallopt { ref mut backtrace, .. } => {
+                Event::MemoryRemap { ref mut backtrace, .. } |
+                Event::MemoryProtect { ref mut backtrace, .. } |
+                Event::Mallopt { ref mut backtrace, .. } |
+                Event::MarkerEx { ref mut backtrace, .. } => {
                     *backtrace = backtrace_map.get( backtrace ).copied().unwrap();
                 },
 
@@ -0,0 +1,168 @@
+/// Replaces template arguments nested deeper than `keep_depth` levels with `...`.
+pub fn strip_template( input: &str, keep_depth: usize ) -> String {
+    const OPERATORS: &[&str] = &[ "<=>", "<<=", ">>=", "->*", "<<", ">>", "<=", ">=", "->", "<", ">" ];
+
+    fn is_identifier( ch: char ) -> bool {
+        ch.is_alphanumeric() || ch == '_'
+    }
+
+    let mut out = String::with_capacity( input.len() );
+    let mut depth = 0;
+    let mut position = 0;
+    while position < input.len() {
+        let rest = &input[ position.. ];
+        let is_visible = depth <= keep_depth;
+
+        let is_operator_keyword =
+            rest.starts_with( "operator" ) &&
+            !input[ ..position ].chars().next_back().map( is_identifier ).unwrap_or( false );
+
+        if is_operator_keyword {
+            let after_keyword = &rest[ "operator".len().. ];
+            let symbol_offset = after_keyword.len() - after_keyword.trim_start().len();
+            let symbol = OPERATORS.iter().find( |symbol| after_keyword[ symbol_offset.. ].starts_with( **symbol ) );
+            let length = "operator".len() + symbol.map( |symbol| symbol_offset + symbol.len() ).unwrap_or( 0 );
+            if is_visible {
+                out.push_str( &rest[ ..length ] );
+            }
+
+            position += length;
+            continue;
+        }
+
+        if rest.starts_with( "->" ) {
+            if is_visible {
+                out.push_str( "->" );
+            }
+
+            position += 2;
+            continue;
+        }
+
+        let ch = rest.chars().next().unwrap();
+        position += ch.len_utf8();
+
+        match ch {
+            '<' => {
+                if depth < keep_depth {
+                    out.push( '<' );
+                } else if depth == keep_depth {
+                    out.push_str( "<..." );
+                }
+
+                depth += 1;
+            },
+            '>' if depth > 0 => {
+                depth -= 1;
+                if depth <= keep_depth {
+                    out.push( '>' );
+                }
+            },
+            _ => {
+                if is_visible {
+                    out.push( ch );
+                }
+            }
+        }
+    }
+
+    out
+}
+
+#[test]
+fn test_strip_template() {
+    let strip = |input: &str| strip_template( input, 0 );
+
+    assert_eq!( strip( "foo" ), "foo" );
+    assert_eq!( strip( "foo<int>" ), "foo<...>" );
+    assert_eq!(
+        strip( "std::vector<int, std::allocator<int> >::push_back(int const&)" ),
+        "std::vector<...>::push_back(int const&)"
+    );
+    assert_eq!(
+        strip( "std::vector<std::vector<int>>::size() const" ),
+        "std::vector<...>::size() const"
+    );
+    assert_eq!(
+        strip( "std::_Rb_tree<int, std::pair<int const, int>, std::_Select1st<std::pair<int const, int> >, std::less<int>, std::allocator<std::pair<int const, int> > >::_M_erase(std::_Rb_tree_node<std::pair<int const, int> >*)" ),
+        "std::_Rb_tree<...>::_M_erase(std::_Rb_tree_node<...>*)"
+    );
+}
+
+#[test]
+fn test_strip_template_operators() {
+    let strip = |input: &str| strip_template( input, 0 );
+
+    assert_eq!( strip( "std::ostream::operator<<(int)" ), "std::ostream::operator<<(int)" );
+    assert_eq!( strip( "std::istream::operator>>(int&)" ), "std::istream::operator>>(int&)" );
+    assert_eq!( strip( "foo::operator<(foo const&) const" ), "foo::operator<(foo const&) const" );
+    assert_eq!( strip( "foo::operator>(foo const&) const" ), "foo::operator>(foo const&) const" );
+    assert_eq!( strip( "foo::operator<=(foo const&) const" ), "foo::operator<=(foo const&) const" );
+    assert_eq!( strip( "foo::operator>=(foo const&) const" ), "foo::operator>=(foo const&) const" );
+    assert_eq!( strip( "foo::operator<=>(foo const&) const" ), "foo::operator<=>(foo const&) const" );
+    assert_eq!( strip( "foo::operator<<=(int)" ), "foo::operator<<=(int)" );
+    assert_eq!( strip( "foo::operator>>=(int)" ), "foo::operator>>=(int)" );
+    assert_eq!( strip( "std::unique_ptr<foo>::operator->() const" ), "std::unique_ptr<...>::operator->() const" );
+    assert_eq!( strip( "foo<int>::operator->*(int)" ), "foo<...>::operator->*(int)" );
+    assert_eq!( strip( "bool std::operator< <char>(std::string const&, std::string const&)" ), "bool std::operator< <...>(std::string const&, std::string const&)" );
+    assert_eq!(
+        strip( "std::basic_ostream<char, std::char_traits<char> >& std::operator<< <std::char_traits<char> >(std::basic_ostream<char, std::char_traits<char> >&, char const*)" ),
+        "std::basic_ostream<...>& std::operator<< <...>(std::basic_ostream<...>&, char const*)"
+    );
+    assert_eq!( strip( "std::map<int, int>::operator[](int const&)" ), "std::map<...>::operator[](int const&)" );
+    assert_eq!( strip( "foo::operator bool() const" ), "foo::operator bool() const" );
+    assert_eq!( strip( "my_operator<int>()" ), "my_operator<...>()" );
+    assert_eq!( strip( "decltype (a->b) foo<int>()" ), "decltype (a->b) foo<...>()" );
+}
+
+#[test]
+fn test_strip_template_rust() {
+    let strip = |input: &str| strip_template( input, 0 );
+
+    assert_eq!( strip( "core::ptr::drop_in_place<alloc::vec::Vec<u8>>" ), "core::ptr::drop_in_place<...>" );
+    assert_eq!( strip( "<alloc::boxed::Box<F> as core::ops::function::FnOnce<A>>::call_once" ), "<...>::call_once" );
+    assert_eq!( strip( "std::thread::Builder::spawn_unchecked::{{closure}}" ), "std::thread::Builder::spawn_unchecked::{{closure}}" );
+    assert_eq!(
+        strip( "<core::iter::adapters::Map<I, F> as core::iter::traits::iterator::Iterator>::fold::{{closure}}" ),
+        "<...>::fold::{{closure}}"
+    );
+    assert_eq!( strip( "app::run::<u32>::{{closure}}" ), "app::run::<...>::{{closure}}" );
+}
+
+#[test]
+fn test_strip_template_keep_depth() {
+    assert_eq!( strip_template( "foo<int>", 1 ), "foo<int>" );
+    assert_eq!(
+        strip_template( "std::vector<int, std::allocator<int> >::push_back(int const&)", 1 ),
+        "std::vector<int, std::allocator<...> >::push_back(int const&)"
+    );
+    assert_eq!(
+        strip_template( "std::vector<std::vector<int>>::size() const", 1 ),
+        "std::vector<std::vector<...>>::size() const"
+    );
+    assert_eq!(
+        strip_template( "std::vector<std::vector<int>>::size() const", 2 ),
+        "std::vector<std::vector<int>>::size() const"
+    );
+    assert_eq!(
+        strip_template( "core::ptr::drop_in_place<alloc::vec::Vec<u8>>", 1 ),
+        "core::ptr::drop_in_place<alloc::vec::Vec<...>>"
+    );
+    assert_eq!(
+        strip_template( "std::operator<< <std::char_traits<char> >(std::ostream&, char const*)", 1 ),
+        "std::operator<< <std::char_traits<...> >(std::ostream&, char const*)"
+    );
+}
+
+#[test]
+fn test_strip_template_unbalanced() {
+    assert_eq!( strip_template( "foo<int", 0 ), "foo<..." );
+    assert_eq!( strip_template( "foo<bar<int>", 1 ), "foo<bar<...>" );
+    assert_eq!( strip_template( "foo>bar", 0 ), "foo>bar" );
+    assert_eq!( strip_template( "foo<int>>bar", 0 ), "foo<...>>bar" );
+    assert_eq!( strip_template( "operator", 0 ), "operator" );
+    assert_eq!( strip_template( "operator ", 0 ), "operator " );
+    assert_eq!( strip_template( "<<<", 0 ), "<..." );
+    assert_eq!( strip_template( "", 0 ), "" );
+    assert_eq!( strip_template( "zażółć<gęślą>jaźń", 0 ), "zażółć<...>jaźń" );
+}
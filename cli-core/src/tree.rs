@@ -83,9 +83,17 @@ impl< K, V > Tree< K, V > where K: PartialEq + Clone {
     }
 
     pub fn add_allocation< T >( &mut self, allocation: &Allocation, allocation_id: AllocationId, backtrace: T ) where T: Iterator< Item = (K, V) > {
-        let timestamp = allocation.timestamp;
-        let size = allocation.size;
+        let node_id = self.add( allocation.timestamp, allocation.size, backtrace );
+        let node = &mut self.nodes[ node_id as usize ];
+        let index = node.self_allocations.len();
+        self.allocations.insert( allocation.pointer, (node_id, index) );
+        node.self_allocations.push( allocation_id );
+    }
 
+    /// Adds something of a given size which isn't an allocation, e.g. a memory map.
+    ///
+    /// Returns the node to which it was added.
+    pub fn add< T >( &mut self, timestamp: Timestamp, size: u64, backtrace: T ) -> NodeId where T: Iterator< Item = (K, V) > {
         let mut node_id: NodeId = 0;
         for (key, value) in backtrace {
             {
@@ -130,9 +138,7 @@ impl< K, V > Tree< K, V > where K: PartialEq + Clone {
         node.total_first_timestamp = min( node.total_first_timestamp, timestamp );
         node.total_last_timestamp = max( node.total_last_timestamp, timestamp );
 
-        let index = node.self_allocations.len();
-        self.allocations.insert( allocation.pointer, (node_id, index) );
-        node.self_allocations.push( allocation_id );
+        node_id
     }
 
     pub fn currently_allocated( &self ) -> u64 {
@@ -0,0 +1,130 @@
+use regex::Regex;
+
+use crate::data::Data;
+use crate::frame::Frame;
+
+lazy_static::lazy_static! {
+    /// Functions which mark the point where the runtime hands over control to the user's code,
+    /// in the order of preference.
+    static ref RUNTIME_ENTRY_POINTS: Vec< Regex > = vec![
+        Regex::new( r"(^|::)__rust_begin_short_backtrace(<.*>)?$" ).unwrap(),
+        Regex::new( r"^main$" ).unwrap(),
+        Regex::new( r"^start_thread$" ).unwrap()
+    ];
+
+    /// Functions which are a part of the allocators, or which merely forward the allocations to them.
+    static ref ALLOCATOR_FUNCTIONS: Vec< Regex > = vec![
+        Regex::new( r"^(__libc_)?(malloc|calloc|realloc|reallocarray|free|cfree|posix_memalign|aligned_alloc|memalign|valloc|pvalloc|mmap|mmap64|munmap|mremap)$" ).unwrap(),
+        Regex::new( r"^(je|_rjem|tc|mi)_" ).unwrap(),
+        Regex::new( r"^operator (new|delete)" ).unwrap(),
+        Regex::new( r"^_Z(nw|na|dl|da)" ).unwrap(),
+        Regex::new( r"^__(rust|rdl|rg)_(alloc|dealloc|realloc|alloc_zeroed)$" ).unwrap(),
+        Regex::new( r"(^|::|<)alloc::(alloc|raw_vec)::" ).unwrap(),
+        Regex::new( r"^(std|__gnu_cxx)::(__)?(new_allocator|allocator|allocator_traits)<" ).unwrap(),
+        Regex::new( r"^bytehound::" ).unwrap()
+    ];
+}
+
+fn is_allocator_function( name: &str ) -> bool {
+    ALLOCATOR_FUNCTIONS.iter().any( |regex| regex.is_match( name ) )
+}
+
+/// Returns the innermost frame of a backtrace which isn't a part of the allocator.
+///
+/// The frames whose function is unknown are skipped since there's no telling where they belong.
+pub fn innermost_non_allocator_frame< 'a, I >( data: &Data, backtrace: I ) -> Option< &'a Frame >
+    where I: DoubleEndedIterator< Item = &'a Frame >
+{
+    backtrace.rev().find( |frame| {
+        match frame.function().or( frame.raw_function() ) {
+            Some( id ) => !is_allocator_function( data.interner().resolve( id ).unwrap() ),
+            None => false
+        }
+    })
+}
+
+fn trimmed_count_from_names< 'a, I >( names: I, below: Option< &Regex >, runtime_prefix: bool ) -> usize
+    where I: Iterator< Item = Option< &'a str > > + Clone
+{
+    let runtime_patterns = if runtime_prefix { &RUNTIME_ENTRY_POINTS[..] } else { &[] };
+    for regex in below.into_iter().chain( runtime_patterns.iter() ) {
+        let position = names.clone().position( |name| name.map( |name| regex.is_match( name ) ).unwrap_or( false ) );
+        if let Some( position ) = position {
+            return position;
+        }
+    }
+
+    0
+}
+
+/// Returns how many of the outermost frames of a backtrace should be trimmed.
+///
+/// Everything outward of the first frame (starting from the outermost one) whose function
+/// matches `below` is trimmed; if `runtime_prefix` is set the same is done for a built-in
+/// list of well known entry points. If nothing matches then nothing is trimmed.
+pub fn trimmed_frame_count< 'a, I >( data: &Data, backtrace: I, below: Option< &Regex >, runtime_prefix: bool ) -> usize
+    where I: Iterator< Item = &'a Frame > + Clone
+{
+    if below.is_none() && !runtime_prefix {
+        return 0;
+    }
+
+    let names = backtrace.map( |frame| {
+        frame.function().or( frame.raw_function() ).map( |id| data.interner().resolve( id ).unwrap() )
+    });
+
+    trimmed_count_from_names( names, below, runtime_prefix )
+}
+
+#[test]
+fn test_trimmed_count() {
+    let c_backtrace = [Some( "_start" ), Some( "__libc_start_main" ), Some( "main" ), Some( "foo" ), None, Some( "malloc" )];
+    let rust_backtrace = [
+        Some( "_start" ),
+        Some( "__libc_start_main" ),
+        Some( "main" ),
+        Some( "std::rt::lang_start_internal" ),
+        Some( "std::sys_common::backtrace::__rust_begin_short_backtrace" ),
+        Some( "app::main" ),
+        Some( "alloc::alloc::alloc" )
+    ];
+
+    let count = |names: &[Option< &'static str >], below: Option< &str >, runtime_prefix: bool| {
+        let below = below.map( |below| Regex::new( below ).unwrap() );
+        trimmed_count_from_names( names.iter().cloned(), below.as_ref(), runtime_prefix )
+    };
+
+    assert_eq!( count( &c_backtrace, None, false ), 0 );
+    assert_eq!( count( &c_backtrace, Some( "^main$" ), false ), 2 );
+    assert_eq!( count( &c_backtrace, Some( "^fo" ), false ), 3 );
+    assert_eq!( count( &c_backtrace, Some( "^nonexistent$" ), false ), 0 );
+    assert_eq!( count( &c_backtrace, None, true ), 2 );
+    assert_eq!( count( &c_backtrace, Some( "^nonexistent$" ), true ), 2 );
+
+    assert_eq!( count( &rust_backtrace, None, true ), 4 );
+    assert_eq!( count( &rust_backtrace, Some( "^main$" ), true ), 2 );
+    assert_eq!( count( &rust_backtrace, Some( "^app::" ), false ), 5 );
+
+    assert_eq!( count( &[], Some( "^main$" ), true ), 0 );
+}
+
+#[test]
+fn test_is_allocator_function() {
+    assert!( is_allocator_function( "malloc" ) );
+    assert!( is_allocator_function( "__libc_calloc" ) );
+    assert!( is_allocator_function( "operator new(unsigned long)" ) );
+    assert!( is_allocator_function( "_Znwm" ) );
+    assert!( is_allocator_function( "__rust_alloc" ) );
+    assert!( is_allocator_function( "alloc::alloc::alloc" ) );
+    assert!( is_allocator_function( "alloc::raw_vec::RawVec<T,A>::grow_one" ) );
+    assert!( is_allocator_function( "<alloc::alloc::Global as core::alloc::Allocator>::allocate" ) );
+    assert!( is_allocator_function( "std::allocator<int>::allocate(unsigned long)" ) );
+    assert!( is_allocator_function( "__gnu_cxx::new_allocator<int>::allocate(unsigned long, void const*)" ) );
+    assert!( is_allocator_function( "je_malloc" ) );
+    assert!( is_allocator_function( "bytehound::api::malloc" ) );
+
+    assert!( !is_allocator_function( "main" ) );
+    assert!( !is_allocator_function( "mallocator_init" ) );
+    assert!( !is_allocator_function( "std::vector<int, std::allocator<int> >::push_back(int const&)" ) );
+    assert!( !is_allocator_function( "app::freelist::push" ) );
+}
@@ -18,6 +18,12 @@ impl< K, T > VecVec< K, T > {
         }
     }
 
+    /// Returns how many bytes are allocated for the elements and the index.
+    pub fn memory_usage( &self ) -> usize {
+        self.index.capacity() * std::mem::size_of::< (K, u32, u32) >() +
+        self.storage.capacity() * std::mem::size_of::< T >()
+    }
+
     pub fn shrink_to_fit( &mut self ) {
         self.index.shrink_to_fit();
         self.storage.shrink_to_fit();
@@ -85,7 +91,7 @@ impl< K, T > VecVec< K, T > {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct DenseVecVec< T > {
     index: Vec< (u32, u32) >,
     storage: Vec< T >
@@ -110,6 +116,11 @@ impl< T > DenseVecVec< T > {
         index
     }
 
+    #[inline]
+    pub fn len( &self ) -> usize {
+        self.index.len()
+    }
+
     #[inline]
     pub fn get( &self, index: usize ) -> &[T] {
         let (offset, length) = self.index[ index ];
@@ -120,6 +131,12 @@ impl< T > DenseVecVec< T > {
         self.index.shrink_to_fit();
         self.storage.shrink_to_fit();
     }
+
+    /// Returns how many bytes are allocated for the elements and the index.
+    pub fn memory_usage( &self ) -> usize {
+        self.index.capacity() * std::mem::size_of::< (u32, u32) >() +
+        self.storage.capacity() * std::mem::size_of::< T >()
+    }
 }
 
 #[test]
@@ -69,6 +69,101 @@ enum Opt {
         /// The port on which to start the HTTP server
         #[structopt(short = "p", long = "port", default_value = "8080")]
         port: u16,
+        /// Compute the most commonly requested analyses in the background right after loading
+        #[structopt(long = "precompute")]
+        precompute: bool,
+        /// Rewrites the given prefix of source file paths, e.g. `/builds/src=>/home/user/src`; can be specified multiple times
+        #[structopt(long = "remap-source", parse(try_from_str = "parse_remap_source"))]
+        remap_source: Vec< (String, String) >,
+        /// A directory from which source code snippets can be served; can be specified multiple times
+        #[structopt(long = "source-root", parse(from_os_str))]
+        source_root: Vec< PathBuf >,
+        /// The maximum number of seconds a single script can run
+        #[structopt(long = "script-timeout-secs")]
+        script_timeout_secs: Option< u64 >,
+        /// The maximum number of bytes a single script can output
+        #[structopt(long = "script-max-output-bytes")]
+        script_max_output_bytes: Option< usize >,
+        /// The number of seconds after which an unused script session expires
+        #[structopt(long = "script-session-timeout-secs", default_value = "1800")]
+        script_session_timeout_secs: u64,
+        /// The maximum number of rows of a single table output by a script; any extra rows are dropped
+        #[structopt(long = "script-max-table-rows", default_value = "10000")]
+        script_max_table_rows: usize,
+        /// The maximum total number of bytes of files a single script can save
+        #[structopt(long = "script-max-file-bytes", default_value = "16777216")]
+        script_max_file_bytes: usize,
+        /// A directory where named scripts are stored; if not specified the script library is disabled
+        #[structopt(long = "scripts-dir", parse(from_os_str))]
+        scripts_dir: Option< PathBuf >,
+        /// Allows scripts to use absolute paths and '..' when saving files
+        #[structopt(long = "allow-script-fs")]
+        allow_script_fs: bool,
+        /// Forbids running scripts, including custom filters and per-group graphs
+        #[structopt(long = "disable-scripts")]
+        disable_scripts: bool,
+        /// Requires every request to contain the given token, either through an 'Authorization: Bearer' header or a 'token' query parameter
+        #[structopt(long = "auth-token")]
+        auth_token: Option< String >,
+        /// Same as '--auth-token', except the token is randomly generated and printed out at startup
+        #[structopt(long = "generate-auth-token", conflicts_with = "auth_token")]
+        generate_auth_token: bool,
+        /// An origin from which the server can be accessed by a browser, e.g. `http://localhost:1234`; can be specified multiple times.
+        /// Use `*` to allow every origin (the default) or `none` to forbid cross-origin requests
+        #[structopt(long = "cors-origin")]
+        cors_origin: Vec< String >,
+        /// Collects metrics about the server and exports them in the Prometheus format through `/metrics`
+        #[structopt(long = "metrics")]
+        metrics: bool,
+        /// The number of threads which process the heavy requests; by default half of the physical cores are used
+        #[structopt(long = "heavy-workers")]
+        heavy_workers: Option< usize >,
+        /// How many megabytes of files generated by scripts (e.g. graphs) are kept in memory
+        #[structopt(long = "generated-files-cache-mb", default_value = "32")]
+        generated_files_cache_mb: usize,
+        /// A directory to which the files generated by scripts are written once they don't fit in memory anymore
+        #[structopt(long = "generated-files-dir", parse(from_os_str))]
+        generated_files_dir: Option< PathBuf >,
+        /// Logs every request along with its status, response size and how long it took
+        #[structopt(long = "log-requests")]
+        log_requests: bool,
+        /// Requests which take longer than this many milliseconds are logged as warnings; 0 disables the warnings
+        #[structopt(long = "slow-request-threshold-ms", default_value = "10000")]
+        slow_request_threshold_ms: u64,
+        /// The default number of milliseconds after which a request is aborted, unless it specifies its own 'deadline_ms'
+        #[structopt(long = "default-deadline-ms")]
+        default_deadline_ms: Option< u64 >,
+        /// A directory from which data files can be loaded at runtime through '/data/load'; can be specified multiple times
+        #[structopt(long = "data-dir", parse(from_os_str))]
+        data_dir: Vec< PathBuf >,
+        /// Only loads the allocations which were alive during the given time range, e.g. `10m..20m`;
+        /// the times are relative to the start of the profiling and are in seconds unless they have a `ms`, `s`, `m` or `h` suffix
+        #[structopt(long = "load-range")]
+        load_range: Option< cli_core::LoadRange >,
+        /// An address, e.g. `0.0.0.0:8100`, on which to accept data streamed from running processes;
+        /// what was received so far is available while the data is still being streamed. If an authentication token
+        /// is required then it has to be sent first, on a line of its own
+        #[structopt(long = "listen-for-profiles")]
+        listen_for_profiles: Option< String >,
+        /// A file in which the names given to the data through '/data/<id>/name' are stored, so that they're kept across restarts
+        #[structopt(long = "aliases-file", parse(from_os_str))]
+        aliases_file: Option< PathBuf >,
+        /// A directory in which the loaded data is cached, which makes loading the same data files again much faster
+        #[structopt(long = "cache-dir", parse(from_os_str))]
+        cache_dir: Option< PathBuf >,
+        /// How many megabytes the caches in the '--cache-dir' can take; the least recently used ones are removed once that's exceeded
+        #[structopt(long = "cache-size-mb", default_value = "16384")]
+        cache_size_mb: u64,
+        /// Exit with an error if any of the data files fails to load instead of serving the ones which were loaded
+        #[structopt(long = "strict")]
+        strict: bool,
+        /// Keep the allocations in memory mapped files (in the `--cache-dir`, if given) so that they can be paged out,
+        /// which makes it possible to load data bigger than the available memory at the cost of slower queries
+        #[structopt(long = "low-memory")]
+        low_memory: bool,
+        /// The path under which the server is available, e.g. `/bytehound` when it's behind a reverse proxy
+        #[structopt(long = "base-path", default_value = "/")]
+        base_path: String,
         #[structopt(parse(from_os_str), required = false)]
         input: Vec< PathBuf >
     },
@@ -150,6 +245,11 @@ enum Opt {
     }
 }
 
+#[cfg(feature = "subcommand-server")]
+fn parse_remap_source( value: &str ) -> Result< (String, String), String > {
+    server_core::parse_source_prefix_rule( value ).ok_or_else( || format!( "invalid source remapping rule '{}'; expected FROM=>TO", value ) )
+}
+
 fn run( opt: Opt ) -> Result< (), Box< dyn Error > > {
     match opt {
         Opt::ExportReplay { output, input } => {
@@ -172,8 +272,41 @@ fn run( opt: Opt ) -> Result< (), Box< dyn Error > > {
             cli_core::cmd_gather::main( target.as_ref().map( |target| target.as_str() ) )?;
         },
         #[cfg(feature = "subcommand-server")]
-        Opt::Server { debug_symbols, input, interface, port } => {
-            server_core::main( input, debug_symbols, false, &interface, port )?;
+        Opt::Server { debug_symbols, input, interface, port, precompute, remap_source, source_root, script_timeout_secs, script_max_output_bytes, script_session_timeout_secs, script_max_table_rows, script_max_file_bytes, scripts_dir, allow_script_fs, disable_scripts, auth_token, generate_auth_token, cors_origin, metrics, heavy_workers, generated_files_cache_mb, generated_files_dir, log_requests, slow_request_threshold_ms, default_deadline_ms, data_dir, load_range, listen_for_profiles, aliases_file, cache_dir, cache_size_mb, strict, low_memory, base_path } => {
+            let options = server_core::ServerOptions {
+                precompute,
+                source_prefix_map: remap_source,
+                source_roots: source_root,
+                script_timeout: script_timeout_secs.map( std::time::Duration::from_secs ),
+                script_max_output_size: script_max_output_bytes,
+                script_session_idle_timeout: std::time::Duration::from_secs( script_session_timeout_secs ),
+                script_max_table_rows,
+                script_max_files_size: script_max_file_bytes,
+                scripts_dir,
+                allow_script_fs,
+                disable_scripts,
+                auth_token,
+                generate_auth_token,
+                cors_origins: server_core::parse_cors_origins( &cors_origin )?,
+                metrics,
+                heavy_workers,
+                generated_files_cache_size: generated_files_cache_mb * 1024 * 1024,
+                generated_files_dir,
+                log_requests,
+                slow_request_threshold: if slow_request_threshold_ms == 0 { None } else { Some( std::time::Duration::from_millis( slow_request_threshold_ms ) ) },
+                default_deadline: default_deadline_ms.map( std::time::Duration::from_millis ),
+                data_dirs: data_dir,
+                load_range,
+                listen_for_profiles,
+                aliases_file,
+                cache_dir,
+                cache_size_limit: cache_size_mb * 1024 * 1024,
+                strict,
+                low_memory,
+                base_path: server_core::normalize_base_path( &base_path )?
+            };
+
+            server_core::main( input, debug_symbols, false, &interface, port, options )?;
         },
         Opt::Postprocess { debug_symbols, output, input, anonymize } => {
             let ifp = File::open( input )?;
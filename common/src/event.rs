@@ -242,6 +242,32 @@ pub enum Event< 'a > {
         backtrace: u64,
         thread: u32
     },
+    /// A marker along with where and when it was set; since it's a new event
+    /// the captures which contain it can't be loaded by older versions.
+    MarkerEx {
+        timestamp: Timestamp,
+        backtrace: u64,
+        thread: u32,
+        value: u32
+    },
+    MemoryRemap {
+        timestamp: Timestamp,
+        old_pointer: u64,
+        old_length: u64,
+        new_pointer: u64,
+        new_length: u64,
+        backtrace: u64,
+        remap_flags: u32,
+        thread: u32
+    },
+    MemoryProtect {
+        timestamp: Timestamp,
+        pointer: u64,
+        length: u64,
+        backtrace: u64,
+        protection: u32,
+        thread: u32
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
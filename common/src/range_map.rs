@@ -20,6 +20,7 @@ impl< T: PartialOrd > RangeExt< T > for Range< T > {
     }
 }
 
+#[derive(Clone)]
 pub struct RangeMap< T > {
     values: Vec< (Range< u64 >, T) >
 }
@@ -135,7 +135,7 @@ def process root, section
                                 else
                                     output_preprocessed << obj
                                 end
-                            when "image"
+                            when "image", "file", "table"
                                 output_preprocessed << obj
                             when "runtime_error", "syntax_error"
                                 STDERR.puts "Error while running '#{chapter["path"]}': #{obj["message"]}"
@@ -163,8 +163,9 @@ def process root, section
                     when "image"
                         data = obj["data"].pack( "C*" )
                         file_checksum = Digest::MD5.hexdigest( data )
-                        target_path = File.join generated_root, "#{file_checksum}.svg"
-                        target_filename = "#{file_checksum}.svg"
+                        extension = obj["mime"] == "image/png" ? "png" : "svg"
+                        target_path = File.join generated_root, "#{file_checksum}.#{extension}"
+                        target_filename = "#{file_checksum}.#{extension}"
                         unless File.exist? target_path
                             File.write target_path, data
                         end
@@ -174,6 +175,20 @@ def process root, section
 
                         content += "\n" unless content.empty?
                         content += "[![](#{prefix}generated/#{target_filename})](#{prefix}generated/#{target_filename})"
+                    when "file"
+                        content += "\n" if !content.empty?
+                        content += "```\n"
+                        content += obj["data"].pack( "C*" ).force_encoding( "UTF-8" )
+                        content += "\n" unless content.end_with? "\n"
+                        content += "```\n\n"
+                    when "table"
+                        content += "\n" unless content.empty?
+                        content += "| " + obj["columns"].map { |column| column["name"] }.join( " | " ) + " |\n"
+                        content += "|" + obj["columns"].map { |_| " --- |" }.join( "" ) + "\n"
+                        obj["rows"].each do |row|
+                            content += "| " + row.map { |cell| cell.to_s }.join( " | " ) + " |\n"
+                        end
+                        content += "\n"
                     else
                         raise
                 end
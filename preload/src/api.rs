@@ -849,11 +849,22 @@ pub unsafe extern "C" fn pvalloc( _size: size_t ) -> *mut c_void {
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn memory_profiler_set_marker( value: u32 ) {
     let thread = StrongThreadHandle::acquire();
+    let mut thread = if let Some( thread ) = thread {
+        thread
+    } else {
+        send_event( InternalEvent::SetMarkerWithoutBacktrace { value } );
+        return;
+    };
+
+    let backtrace = unwind::grab( &mut thread );
+
+    let timestamp = get_timestamp();
     send_event( InternalEvent::SetMarker {
-        value
+        value,
+        backtrace,
+        timestamp,
+        thread: thread.decay()
     });
-
-    mem::drop( thread );
 }
 
 #[cfg_attr(not(test), no_mangle)]
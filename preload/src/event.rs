@@ -101,6 +101,13 @@ pub(crate) enum InternalEvent {
     Exit,
     GrabMemoryDump,
     SetMarker {
+        value: u32,
+        backtrace: Backtrace,
+        timestamp: Timestamp,
+        thread: WeakThreadHandle
+    },
+    /// A marker set when the thread couldn't be acquired, e.g. while it was being torn down.
+    SetMarkerWithoutBacktrace {
         value: u32
     },
     Mmap {
@@ -799,7 +799,28 @@ pub(crate) fn thread_main() {
                         allocation_lock_for_memory_dump = Some( AllocationLock::new() );
                     }
                 },
-                InternalEvent::SetMarker { value } => {
+                InternalEvent::SetMarker { value, mut timestamp, backtrace, thread } => {
+                    let system_tid = thread.system_tid();
+                    mem::drop( thread );
+
+                    if skip {
+                        continue;
+                    }
+
+                    if timestamp == Timestamp::min() {
+                        timestamp = coarse_timestamp;
+                    }
+
+                    let timestamp = timestamp_override.take().unwrap_or( timestamp );
+
+                    let event = match writers::write_backtrace( &mut *serializer, backtrace, &mut backtrace_cache ) {
+                        Ok( backtrace ) => Event::MarkerEx { timestamp, backtrace, thread: system_tid, value },
+                        Err( _ ) => Event::Marker { value }
+                    };
+
+                    let _ = event.write_to_stream( &mut *serializer );
+                },
+                InternalEvent::SetMarkerWithoutBacktrace { value } => {
                     if skip {
                         continue;
                     }
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use common::event::DataId;
+
+const MAXIMUM_ALIAS_LENGTH: usize = 64;
+
+#[derive(Debug)]
+pub enum AliasError {
+    InvalidName,
+    /// The alias is already used by other data.
+    Taken( DataId ),
+    Io( io::Error )
+}
+
+impl From< io::Error > for AliasError {
+    fn from( error: io::Error ) -> Self {
+        AliasError::Io( error )
+    }
+}
+
+/// Human friendly names given to the data, which can be used instead of their IDs.
+///
+/// If there's a file then the aliases are also stored there, so they're kept
+/// across restarts as long as the same data files are loaded again.
+#[derive(Debug, Default)]
+pub struct Aliases {
+    by_name: BTreeMap< String, DataId >,
+    path: Option< PathBuf >
+}
+
+/// Only allows names which can be put in a URL as-is, and which can't be mistaken for anything else.
+fn is_valid_name( name: &str ) -> bool {
+    !name.is_empty() &&
+    name.len() <= MAXIMUM_ALIAS_LENGTH &&
+    name != "last" &&
+    !name.starts_with( '.' ) &&
+    name.parse::< DataId >().is_err() &&
+    name.chars().all( |ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-' || ch == '.' )
+}
+
+impl Aliases {
+    /// Loads the aliases which were previously saved to the given file, if any.
+    pub fn new( path: Option< PathBuf > ) -> Result< Self, io::Error > {
+        let mut by_name = BTreeMap::new();
+        if let Some( ref path ) = path {
+            match fs::read( path ) {
+                Ok( contents ) => {
+                    let raw: BTreeMap< String, String > = serde_json::from_slice( &contents ).map_err( |error| {
+                        io::Error::new( io::ErrorKind::InvalidData, format!( "failed to parse {:?}: {}", path, error ) )
+                    })?;
+
+                    for (name, id) in raw {
+                        match id.parse() {
+                            Ok( id ) if is_valid_name( &name ) => {
+                                by_name.insert( name, id );
+                            },
+                            _ => warn!( "Ignoring an invalid alias in {:?}: '{}'", path, name )
+                        }
+                    }
+                },
+                Err( ref error ) if error.kind() == io::ErrorKind::NotFound => {},
+                Err( error ) => return Err( error )
+            }
+        }
+
+        Ok( Aliases { by_name, path } )
+    }
+
+    pub fn get( &self, name: &str ) -> Option< DataId > {
+        self.by_name.get( name ).cloned()
+    }
+
+    pub fn name_of( &self, id: DataId ) -> Option< &str > {
+        self.by_name.iter().find( |&(_, &existing_id)| existing_id == id ).map( |(name, _)| name.as_str() )
+    }
+
+    /// Gives the data a new alias, replacing the old one if it had any.
+    ///
+    /// An alias used by data which isn't loaded, according to `is_loaded`, can be taken over.
+    pub fn set( &mut self, id: DataId, name: &str, is_loaded: impl Fn( DataId ) -> bool ) -> Result< (), AliasError > {
+        if !is_valid_name( name ) {
+            return Err( AliasError::InvalidName );
+        }
+
+        if let Some( existing_id ) = self.get( name ) {
+            if existing_id == id {
+                return Ok(());
+            }
+
+            if is_loaded( existing_id ) {
+                return Err( AliasError::Taken( existing_id ) );
+            }
+        }
+
+        self.by_name.retain( |_, &mut existing_id| existing_id != id );
+        self.by_name.insert( name.to_owned(), id );
+        self.save()
+    }
+
+    /// Removes the alias of the given data; returns `false` if it didn't have one.
+    pub fn remove( &mut self, id: DataId ) -> Result< bool, AliasError > {
+        let count = self.by_name.len();
+        self.by_name.retain( |_, &mut existing_id| existing_id != id );
+        if self.by_name.len() == count {
+            return Ok( false );
+        }
+
+        self.save()?;
+        Ok( true )
+    }
+
+    fn save( &self ) -> Result< (), AliasError > {
+        let path = match self.path {
+            Some( ref path ) => path,
+            None => return Ok(())
+        };
+
+        let raw: BTreeMap< &str, String > = self.by_name.iter().map( |(name, id)| (name.as_str(), format!( "{}", id )) ).collect();
+        let contents = serde_json::to_vec_pretty( &raw ).unwrap();
+
+        // Written to a temporary file first so that the aliases aren't lost if we crash midway.
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push( ".tmp" );
+        fs::write( &tmp_path, contents )?;
+        fs::rename( &tmp_path, path )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_aliases() {
+    let path = std::env::temp_dir().join( format!( "bytehound-aliases-test-{}.json", std::process::id() ) );
+    let _ = fs::remove_file( &path );
+
+    let first = DataId::new( 1, 2 );
+    let second = DataId::new( 3, 4 );
+    let mut aliases = Aliases::new( Some( path.clone() ) ).unwrap();
+    aliases.set( first, "nightly-run", |_| true ).unwrap();
+    assert_eq!( aliases.get( "nightly-run" ), Some( first ) );
+    assert_eq!( aliases.name_of( first ), Some( "nightly-run" ) );
+
+    assert!( matches!( aliases.set( second, "nightly-run", |_| true ), Err( AliasError::Taken( id ) ) if id == first ) );
+    for name in &[ "", "last", "with space", "a/b", ".hidden", &format!( "{}", second ), &"a".repeat( MAXIMUM_ALIAS_LENGTH + 1 ) ] {
+        assert!( matches!( aliases.set( second, name, |_| true ), Err( AliasError::InvalidName ) ), "{}", name );
+    }
+
+    aliases.set( second, "baseline", |_| true ).unwrap();
+    aliases.set( second, "baseline_2", |_| true ).unwrap();
+    assert_eq!( aliases.get( "baseline" ), None );
+
+    let aliases = Aliases::new( Some( path.clone() ) ).unwrap();
+    assert_eq!( aliases.get( "nightly-run" ), Some( first ) );
+    assert_eq!( aliases.get( "baseline_2" ), Some( second ) );
+
+    // Data which isn't loaded anymore can lose its alias.
+    let mut aliases = aliases;
+    aliases.set( second, "nightly-run", |id| id != first ).unwrap();
+    assert_eq!( aliases.name_of( first ), None );
+    assert!( aliases.remove( second ).unwrap() );
+    assert!( !aliases.remove( second ).unwrap() );
+
+    let _ = fs::remove_file( &path );
+}
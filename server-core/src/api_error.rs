@@ -0,0 +1,169 @@
+use std::fmt;
+
+use actix_web::{Error, HttpResponse, ResponseError};
+use actix_web::body::Body;
+use actix_web::dev::{ResponseBody, Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderValue, StatusCode, header};
+use futures::future::Future;
+
+/// An error returned by the server's API.
+///
+/// Every error is sent as `{"error": {"code": ..., "message": ..., "field": ..., "details": ...}}`, where `code`
+/// is a stable, machine readable identifier, `field` is the name of the offending parameter, if any,
+/// and `details` contains any extra information specific to the given error code.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    field: Option< String >,
+    details: Option< serde_json::Value >
+}
+
+impl ApiError {
+    pub fn new( status: StatusCode, code: &'static str, message: impl Into< String > ) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            field: None,
+            details: None
+        }
+    }
+
+    pub fn with_field( mut self, field: impl Into< String > ) -> Self {
+        self.field = Some( field.into() );
+        self
+    }
+
+    pub fn with_details( mut self, details: serde_json::Value ) -> Self {
+        self.details = Some( details );
+        self
+    }
+
+    pub fn not_found( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::NOT_FOUND, "not_found", message )
+    }
+
+    pub fn bad_request( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::BAD_REQUEST, "bad_request", message )
+    }
+
+    pub fn invalid_parameter( field: impl Into< String >, message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::BAD_REQUEST, "invalid_parameter", message ).with_field( field )
+    }
+
+    pub fn unauthorized( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::UNAUTHORIZED, "unauthorized", message )
+    }
+
+    pub fn forbidden( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::FORBIDDEN, "forbidden", message )
+    }
+
+    pub fn conflict( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::CONFLICT, "conflict", message )
+    }
+
+    pub fn payload_too_large( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", message )
+    }
+
+    pub fn service_unavailable( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::SERVICE_UNAVAILABLE, "service_unavailable", message )
+    }
+
+    pub fn internal( message: impl Into< String > ) -> Self {
+        ApiError::new( StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message )
+    }
+
+    pub fn to_json( &self ) -> serde_json::Value {
+        let mut error = serde_json::json! {{
+            "code": self.code,
+            "message": self.message
+        }};
+
+        if let Some( ref field ) = self.field {
+            error[ "field" ] = field.clone().into();
+        }
+
+        if let Some( ref details ) = self.details {
+            error[ "details" ] = details.clone();
+        }
+
+        serde_json::json! {{
+            "error": error
+        }}
+    }
+
+    /// Builds a response for this error, which can then be further customized.
+    pub fn response( &self ) -> HttpResponse {
+        HttpResponse::build( self.status ).json( self.to_json() )
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        write!( fmt, "{}", self.message )
+    }
+}
+
+impl ResponseError for ApiError {
+    fn error_response( &self ) -> HttpResponse {
+        self.response()
+    }
+
+    fn render_response( &self ) -> HttpResponse {
+        self.response()
+    }
+}
+
+/// Returns a generic error code for errors which weren't created through `ApiError`.
+fn code_for_status( status: StatusCode ) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::RANGE_NOT_SATISFIABLE => "range_not_satisfiable",
+        StatusCode::SERVICE_UNAVAILABLE => "service_unavailable",
+        status if status.is_server_error() => "internal_error",
+        _ => "error"
+    }
+}
+
+/// A middleware which converts every other error response, e.g. the ones generated by actix itself
+/// for unknown routes or malformed payloads, into the same JSON format as the `ApiError`s.
+pub fn middleware< S >( req: ServiceRequest, service: &mut S ) -> impl Future< Item = ServiceResponse< Body >, Error = Error >
+    where S: Service< Request = ServiceRequest, Response = ServiceResponse< Body >, Error = Error >
+{
+    service.call( req ).map( |response| {
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return response;
+        }
+
+        let is_json = response.headers().get( header::CONTENT_TYPE )
+            .and_then( |value| value.to_str().ok() )
+            .map( |value| value.starts_with( "application/json" ) )
+            .unwrap_or( false );
+
+        if is_json {
+            return response;
+        }
+
+        let message = match response.response().error() {
+            Some( error ) => error.to_string(),
+            None => status.canonical_reason().unwrap_or( "error" ).to_owned()
+        };
+
+        let body = ApiError::new( status, code_for_status( status ), message ).to_json().to_string();
+        response.map_body( move |head, _| {
+            head.headers.insert( header::CONTENT_TYPE, HeaderValue::from_static( "application/json" ) );
+            ResponseBody::Body( Body::from( body ) )
+        })
+    })
+}
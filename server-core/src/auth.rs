@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::{self, Read};
+
+use actix_web::{Error, HttpMessage, HttpResponse};
+use actix_web::body::Body;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderValue, header};
+use futures::future::{self, Either, Future};
+
+use crate::StateRef;
+use crate::api_error::ApiError;
+
+/// The cookie in which the token is remembered once it was given in the query string,
+/// so that the web UI can be opened through a link containing the token.
+const TOKEN_COOKIE: &str = "bytehound_token";
+
+/// Generates a new random token.
+pub fn generate_token() -> io::Result< String > {
+    let mut bytes = [0; 16];
+    File::open( "/dev/urandom" )?.read_exact( &mut bytes )?;
+    Ok( bytes.iter().map( |byte| format!( "{:02x}", byte ) ).collect() )
+}
+
+/// Compares the tokens in constant time so that they can't be guessed through timing.
+pub fn tokens_equal( lhs: &str, rhs: &str ) -> bool {
+    if lhs.len() != rhs.len() {
+        return false;
+    }
+
+    lhs.bytes().zip( rhs.bytes() ).fold( 0, |acc, (a, b)| acc | (a ^ b) ) == 0
+}
+
+fn token_from_query( req: &ServiceRequest ) -> Option< String > {
+    let params: Vec< (String, String) > = serde_urlencoded::from_str( req.query_string() ).ok()?;
+    params.into_iter().find( |(key, _)| key == "token" ).map( |(_, value)| value )
+}
+
+fn token_from_header( req: &ServiceRequest ) -> Option< String > {
+    let value = req.headers().get( header::AUTHORIZATION )?.to_str().ok()?;
+    if !value.starts_with( "Bearer " ) {
+        return None;
+    }
+
+    Some( value[ "Bearer ".len().. ].trim().to_owned() )
+}
+
+/// Returns whether the path can be accessed without a token.
+fn is_public( path: &str ) -> bool {
+    path.is_empty() || path == "/" || path == "/healthz" || crate::WEBUI_ASSETS.iter().any( |(key, _)| path[ 1.. ] == **key )
+}
+
+/// A middleware which rejects every request without a valid token, except for the static assets and `/healthz`.
+///
+/// The token can be given either in an `Authorization: Bearer` header, in a `token` query parameter
+/// or in a cookie, which is set whenever a valid token is given in the query string.
+pub fn middleware< S >( req: ServiceRequest, service: &mut S ) -> impl Future< Item = ServiceResponse< Body >, Error = Error >
+    where S: Service< Request = ServiceRequest, Response = ServiceResponse< Body >, Error = Error >
+{
+    let (expected, is_public) = match req.app_data::< StateRef >() {
+        Some( state ) => match state.auth_token.clone() {
+            Some( token ) => (token, state.strip_base_path( req.path() ).map( is_public ).unwrap_or( false )),
+            None => return Either::A( service.call( req ) )
+        },
+        None => return Either::A( service.call( req ) )
+    };
+
+    let token_in_query = token_from_query( &req );
+    let is_valid = |token: &Option< String >| token.as_ref().map( |token| tokens_equal( token, &expected ) ).unwrap_or( false );
+    let remember = is_valid( &token_in_query );
+    let is_authorized =
+        remember ||
+        is_valid( &token_from_header( &req ) ) ||
+        is_valid( &req.cookie( TOKEN_COOKIE ).map( |cookie| cookie.value().to_owned() ) );
+
+    if !is_authorized && !is_public {
+        let response = HttpResponse::Unauthorized()
+            .header( header::WWW_AUTHENTICATE, "Bearer" )
+            .json( ApiError::unauthorized( "a valid authentication token is required" ).to_json() );
+
+        return Either::B( Either::A( future::ok( req.into_response( response ) ) ) );
+    }
+
+    let remember = remember && expected.bytes().all( |byte| byte.is_ascii_alphanumeric() || b"-._~".contains( &byte ) );
+    Either::B( Either::B( service.call( req ).map( move |mut response| {
+        if remember {
+            let cookie = format!( "{}={}; Path=/; HttpOnly; SameSite=Strict", TOKEN_COOKIE, expected );
+            if let Ok( cookie ) = HeaderValue::from_str( &cookie ) {
+                response.headers_mut().append( header::SET_COOKIE, cookie );
+            }
+        }
+
+        response
+    })))
+}
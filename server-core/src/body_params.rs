@@ -0,0 +1,121 @@
+use serde_json::{Map, Value};
+
+/// The body of a POST request, for when the parameters are too long to be put in the query string.
+///
+/// Each of these is the JSON equivalent of the query parameters accepted by the corresponding GET request.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RequestBody {
+    #[serde(default)]
+    pub filter: Map< String, Value >,
+    #[serde(default)]
+    pub custom_filter: Option< String >,
+    #[serde(default)]
+    pub custom_filter_name: Option< String >,
+    #[serde(default)]
+    pub backtrace_format: Map< String, Value >,
+    #[serde(default)]
+    pub params: Map< String, Value >
+}
+
+/// The parameters taken from the body of a POST request, in the same format as a query string.
+pub struct BodyParams( pub String );
+
+fn push_pair( output: &mut Vec< (String, String) >, key: String, value: String ) -> Result< (), String > {
+    if output.iter().any( |(existing, _)| *existing == key ) {
+        return Err( format!( "duplicate parameter: '{}'", key ) );
+    }
+
+    output.push( (key, value) );
+    Ok(())
+}
+
+fn push_pairs( output: &mut Vec< (String, String) >, section: &str, map: Map< String, Value > ) -> Result< (), String > {
+    for (key, value) in map {
+        let value = match value {
+            Value::Null => continue,
+            Value::String( value ) => value,
+            Value::Bool( value ) => value.to_string(),
+            Value::Number( value ) => value.to_string(),
+            Value::Array( _ ) | Value::Object( _ ) => {
+                return Err( format!( "invalid '{}.{}': expected a string, a number or a boolean", section, key ) );
+            }
+        };
+
+        push_pair( output, key, value )?;
+    }
+
+    Ok(())
+}
+
+/// Converts the body of a POST request into a query string, so that it's deserialized in
+/// exactly the same way as the query string of an equivalent GET request would be.
+///
+/// The parameters from the `query_string` which aren't present in the body are also kept,
+/// so that e.g. the authentication token can still be passed there.
+pub fn body_to_query( body: &[u8], query_string: &str ) -> Result< String, String > {
+    let body: RequestBody = if body.is_empty() {
+        RequestBody::default()
+    } else {
+        serde_json::from_slice( body ).map_err( |error| format!( "invalid request body: {}", error ) )?
+    };
+
+    let mut pairs = Vec::new();
+    push_pairs( &mut pairs, "filter", body.filter )?;
+    push_pairs( &mut pairs, "backtrace_format", body.backtrace_format )?;
+    push_pairs( &mut pairs, "params", body.params )?;
+
+    if let Some( custom_filter ) = body.custom_filter {
+        push_pair( &mut pairs, "custom_filter".into(), custom_filter )?;
+    }
+
+    if let Some( custom_filter_name ) = body.custom_filter_name {
+        push_pair( &mut pairs, "custom_filter_name".into(), custom_filter_name )?;
+    }
+
+    let from_query: Vec< (String, String) > = serde_urlencoded::from_str( query_string )
+        .map_err( |error| format!( "invalid query: {}", error ) )?;
+
+    for (key, value) in from_query {
+        if !pairs.iter().any( |(existing, _)| *existing == key ) {
+            pairs.push( (key, value) );
+        }
+    }
+
+    serde_urlencoded::to_string( &pairs ).map_err( |error| error.to_string() )
+}
+
+/// Converts just the `filter` part of a request body into a query string.
+pub fn filter_to_query( filter: Map< String, Value > ) -> Result< String, String > {
+    let mut pairs = Vec::new();
+    push_pairs( &mut pairs, "filter", filter )?;
+    serde_urlencoded::to_string( &pairs ).map_err( |error| error.to_string() )
+}
+
+#[test]
+fn test_body_to_query() {
+    let body = br#"{
+        "filter": { "size_min": 100, "function_regex": "foo&bar", "mmaped": null },
+        "custom_filter": "allocations.only_leaked()",
+        "backtrace_format": { "collapse_inlined": true },
+        "params": { "page": 2 }
+    }"#;
+
+    let query = body_to_query( body, "token=1234&page=1" ).unwrap();
+    let pairs: Vec< (String, String) > = serde_urlencoded::from_str( &query ).unwrap();
+    let mut pairs: Vec< (&str, &str) > = pairs.iter().map( |(key, value)| (key.as_str(), value.as_str()) ).collect();
+    pairs.sort();
+    assert_eq!( pairs, vec![
+        ("collapse_inlined", "true"),
+        ("custom_filter", "allocations.only_leaked()"),
+        ("function_regex", "foo&bar"),
+        ("page", "2"),
+        ("size_min", "100"),
+        ("token", "1234")
+    ]);
+
+    assert_eq!( body_to_query( b"", "page=1" ).unwrap(), "page=1" );
+    assert!( body_to_query( br#"{ "filter": { "size_min": [1] } }"#, "" ).is_err() );
+    assert!( body_to_query( br#"{ "filter": { "size_min": 1 }, "params": { "size_min": 2 } }"#, "" ).is_err() );
+    assert!( body_to_query( br#"{ "unknown": {} }"#, "" ).is_err() );
+}
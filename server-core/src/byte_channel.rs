@@ -3,25 +3,63 @@ use std::mem;
 use std::io;
 
 use bytes::Bytes;
-use crate::streaming_channel::{self, streaming_channel};
+use crate::deadline::Deadline;
+use crate::streaming_channel::{self, streaming_channel, DisconnectedFlag};
 
 pub struct ByteSender {
     buffer: Vec< u8 >,
-    tx: streaming_channel::Sender< Bytes >
+    tx: streaming_channel::Sender< Bytes >,
+    disconnected: DisconnectedFlag,
+    deadline: Deadline
 }
 
 pub fn byte_channel() -> (ByteSender, streaming_channel::Receiver< Bytes >) {
     let (tx, rx) = streaming_channel();
+    let disconnected = tx.disconnected_flag();
     let tx = ByteSender {
         buffer: Vec::new(),
-        tx
+        tx,
+        disconnected,
+        deadline: Deadline::none()
     };
 
     (tx, rx)
 }
 
 impl ByteSender {
+    /// Returns a flag which can be checked to stop generating the response once the client is gone.
+    pub fn disconnected_flag( &self ) -> DisconnectedFlag {
+        self.disconnected.clone()
+    }
+
+    pub fn is_disconnected( &self ) -> bool {
+        self.disconnected.is_set()
+    }
+
+    /// Makes the writes fail once the deadline passes, in which case the response is aborted.
+    pub fn set_deadline( &mut self, deadline: Deadline ) {
+        self.deadline = deadline;
+    }
+
+    /// Sends whatever was already written and then makes the receiver end with an error,
+    /// so that the client can tell that the response is incomplete.
+    pub fn abort( mut self ) {
+        let _ = self.flush_buffer();
+        self.tx.abort();
+    }
+
     fn write_buffer( &mut self, buffer: &[u8] ) -> Result< (), () > {
+        // Fail right away instead of waiting until the buffer is flushed
+        // so that whoever's writing can stop as soon as possible.
+        if self.is_disconnected() {
+            self.buffer.clear();
+            return Err(());
+        }
+
+        if self.deadline.check_periodically() {
+            return Err(());
+        }
+
         self.buffer.extend_from_slice( buffer );
         if self.buffer.len() >= 128 * 1024 {
             self.flush_buffer()?;
@@ -35,6 +73,12 @@ impl ByteSender {
             return Ok(());
         }
 
+        // The client is reading the response slower than we're generating it, so there's
+        // no point in keeping one of the workers which could be processing other requests.
+        if self.tx.is_full() {
+            crate::worker_pool::release_current_worker();
+        }
+
         let mut vec = Vec::with_capacity( self.buffer.capacity() );
         mem::swap( &mut vec, &mut self.buffer );
         return self.tx.send( vec.into() );
@@ -44,6 +88,11 @@ impl ByteSender {
 impl Drop for ByteSender {
     fn drop( &mut self ) {
         let _ = self.flush_buffer();
+
+        // Whatever was generated after the deadline was hit is most likely incomplete.
+        if self.deadline.was_exceeded() {
+            self.tx.abort();
+        }
     }
 }
 
@@ -65,3 +114,86 @@ impl io::Write for ByteSender {
         self.flush_buffer().map_err( |_| io::Error::new( io::ErrorKind::Other, "write failed" ) )
     }
 }
+
+#[test]
+fn test_byte_channel_stops_writing_when_the_receiver_is_dropped() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use crate::streaming_serializer::StreamingSerializer;
+
+    let (tx, rx) = byte_channel();
+    let disconnected = tx.disconnected_flag();
+    let (done_tx, done_rx) = mpsc::channel();
+    std::thread::spawn( move || {
+        let _ = serde_json::to_writer( tx, &StreamingSerializer::new( || 0_u64.. ) );
+        let _ = done_tx.send( () );
+    });
+
+    std::thread::sleep( Duration::from_millis( 50 ) );
+    drop( rx );
+
+    done_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap();
+    assert!( disconnected.is_set() );
+}
+
+#[test]
+fn test_byte_channel_is_not_disconnected_when_everything_was_received() {
+    use futures::{Async, Stream};
+    use std::io::Write;
+
+    let (mut tx, mut rx) = byte_channel();
+    let disconnected = tx.disconnected_flag();
+    tx.write_all( b"1234" ).unwrap();
+    drop( tx );
+
+    assert_eq!( rx.poll(), Ok( Async::Ready( Some( Bytes::from_static( b"1234" ) ) ) ) );
+    assert_eq!( rx.poll(), Ok( Async::Ready( None ) ) );
+    drop( rx );
+    assert!( !disconnected.is_set() );
+}
+
+#[test]
+fn test_byte_channel_abort_is_reported_to_the_receiver() {
+    use futures::{Async, Stream};
+    use std::io::Write;
+
+    let (mut tx, mut rx) = byte_channel();
+    tx.write_all( b"1234" ).unwrap();
+    tx.abort();
+
+    assert_eq!( rx.poll(), Ok( Async::Ready( Some( Bytes::from_static( b"1234" ) ) ) ) );
+    assert_eq!( rx.poll(), Err(()) );
+
+    let (tx, mut rx) = byte_channel();
+    let _ = std::thread::spawn( move || {
+        let _tx = tx;
+        panic!( "generating the response has failed" );
+    }).join();
+
+    assert_eq!( rx.poll(), Err(()) );
+}
+
+#[test]
+fn test_byte_channel_releases_the_worker_when_the_receiver_is_slow() {
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use crate::worker_pool::WorkerPool;
+
+    let pool = WorkerPool::new( 1, 4 );
+    let (mut tx, rx) = byte_channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    assert!( pool.spawn( move || {
+        // Nobody's receiving this, so it'll block once the channel is full.
+        for _ in 0..64 {
+            if tx.write_all( &vec![ 0; 1024 * 1024 ] ).is_err() {
+                break;
+            }
+        }
+    }).is_ok() );
+
+    assert!( pool.spawn( move || done_tx.send( () ).unwrap() ).is_ok() );
+    done_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap();
+    assert_eq!( pool.released_count(), 1 );
+    drop( rx );
+}
@@ -0,0 +1,104 @@
+use actix_cors::Cors;
+use actix_web::Error;
+use actix_web::body::Body;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{Uri, header};
+use futures::future::{self, Either, Future};
+
+use crate::StateRef;
+use crate::api_error::ApiError;
+
+/// Which origins can access the server from within a browser.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CorsOrigins {
+    Any,
+    Only( Vec< String > ),
+    None
+}
+
+impl Default for CorsOrigins {
+    fn default() -> Self {
+        CorsOrigins::Any
+    }
+}
+
+/// Parses the values of `--cors-origin`, where `*` allows every origin and `none` disables cross-origin requests.
+///
+/// No values at all means that every origin is allowed.
+pub fn parse_cors_origins( values: &[String] ) -> Result< CorsOrigins, String > {
+    if values.is_empty() {
+        return Ok( CorsOrigins::Any );
+    }
+
+    if values.iter().any( |value| value == "*" || value == "none" ) {
+        if values.len() != 1 {
+            return Err( format!( "'*' and 'none' can't be combined with any other CORS origin" ) );
+        }
+
+        if values[ 0 ] == "*" {
+            return Ok( CorsOrigins::Any );
+        } else {
+            return Ok( CorsOrigins::None );
+        }
+    }
+
+    for value in values {
+        let is_valid = value.parse::< Uri >().ok().map( |uri| {
+            uri.scheme_str().is_some() && uri.host().is_some() && !value.ends_with( '/' ) && uri.path_and_query().map( |path| path.as_str() == "/" ).unwrap_or( true )
+        }).unwrap_or( false );
+
+        if !is_valid {
+            return Err( format!( "invalid CORS origin '{}'; expected something like 'http://localhost:1234'", value ) );
+        }
+    }
+
+    Ok( CorsOrigins::Only( values.to_owned() ) )
+}
+
+pub fn new_cors( origins: &CorsOrigins ) -> Cors {
+    let mut cors = Cors::new();
+    if let CorsOrigins::Only( ref origins ) = *origins {
+        for origin in origins {
+            cors = cors.allowed_origin( origin );
+        }
+    }
+
+    cors
+}
+
+/// Returns whether the request's `Origin` is the same as the origin of the server itself.
+fn is_same_origin( req: &ServiceRequest ) -> bool {
+    let origin = req.headers().get( header::ORIGIN ).and_then( |value| value.to_str().ok() );
+    let host = req.headers().get( header::HOST ).and_then( |value| value.to_str().ok() );
+    match (origin, host) {
+        (Some( origin ), Some( host )) => origin.splitn( 2, "://" ).nth( 1 ) == Some( host ),
+        _ => false
+    }
+}
+
+/// A middleware which has to wrap the `Cors` middleware.
+///
+/// Browsers also send the `Origin` header with same-origin requests, so it's removed from them here
+/// to make sure the web UI served by the server itself works regardless of which origins are allowed.
+/// If cross-origin requests are disabled then they're rejected outright.
+pub fn middleware< S >( mut req: ServiceRequest, service: &mut S ) -> impl Future< Item = ServiceResponse< Body >, Error = Error >
+    where S: Service< Request = ServiceRequest, Response = ServiceResponse< Body >, Error = Error >
+{
+    if !req.headers().contains_key( header::ORIGIN ) {
+        return Either::A( service.call( req ) );
+    }
+
+    if is_same_origin( &req ) {
+        req.headers_mut().remove( header::ORIGIN );
+        return Either::A( service.call( req ) );
+    }
+
+    let is_disabled = req.app_data::< StateRef >().map( |state| state.cors_origins == CorsOrigins::None ).unwrap_or( false );
+    if is_disabled {
+        let response = ApiError::forbidden( "cross-origin requests are not allowed" ).response();
+
+        return Either::B( future::ok( req.into_response( response ) ) );
+    }
+
+    Either::A( service.call( req ) )
+}
@@ -0,0 +1,139 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::Error;
+use actix_web::body::Body;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use futures::future::Future;
+
+use crate::api_error::ApiError;
+
+/// How many times `Deadline::check_periodically` can be called before the time is actually checked.
+const CHECK_INTERVAL: u32 = 1024;
+
+struct Inner {
+    instant: Instant,
+    exceeded: AtomicBool
+}
+
+/// The point in time after which a request should be aborted, if any.
+#[derive(Clone, Default)]
+pub struct Deadline( Option< Arc< Inner > > );
+
+thread_local! {
+    static CHECK_COUNTER: Cell< u32 > = Cell::new( 0 );
+}
+
+impl Deadline {
+    pub fn none() -> Self {
+        Deadline( None )
+    }
+
+    pub fn after( duration: Duration ) -> Self {
+        Deadline( Some( Arc::new( Inner {
+            instant: Instant::now() + duration,
+            exceeded: AtomicBool::new( false )
+        })))
+    }
+
+    /// Checks whether the deadline has passed.
+    pub fn is_exceeded( &self ) -> bool {
+        let inner = match self.0 {
+            Some( ref inner ) => inner,
+            None => return false
+        };
+
+        if inner.exceeded.load( Ordering::Relaxed ) {
+            return true;
+        }
+
+        if Instant::now() >= inner.instant {
+            inner.exceeded.store( true, Ordering::Relaxed );
+            return true;
+        }
+
+        false
+    }
+
+    /// Checks whether the deadline has passed, but only looks at the clock every few calls,
+    /// so this can be called for every element processed in a tight loop.
+    pub fn check_periodically( &self ) -> bool {
+        let inner = match self.0 {
+            Some( ref inner ) => inner,
+            None => return false
+        };
+
+        let should_check = CHECK_COUNTER.with( |counter| {
+            let value = counter.get().wrapping_add( 1 );
+            counter.set( value );
+            value % CHECK_INTERVAL == 0
+        });
+
+        if should_check {
+            self.is_exceeded()
+        } else {
+            inner.exceeded.load( Ordering::Relaxed )
+        }
+    }
+
+    /// Returns whether the deadline was found to be exceeded by any of the previous checks.
+    pub fn was_exceeded( &self ) -> bool {
+        self.0.as_ref().map( |inner| inner.exceeded.load( Ordering::Relaxed ) ).unwrap_or( false )
+    }
+
+    /// Returns how much time is left, or `None` if there's no deadline.
+    pub fn remaining( &self ) -> Option< Duration > {
+        self.0.as_ref().map( |inner| inner.instant.saturating_duration_since( Instant::now() ) )
+    }
+
+    pub fn check( &self ) -> Result< (), ApiError > {
+        if self.is_exceeded() {
+            return Err( deadline_exceeded() );
+        }
+
+        Ok(())
+    }
+}
+
+pub fn deadline_exceeded() -> ApiError {
+    ApiError::new( StatusCode::SERVICE_UNAVAILABLE, "deadline_exceeded", "the request has exceeded its deadline" )
+}
+
+/// A middleware which replaces the response with an error if the handler has hit the request's deadline
+/// at any point, since in that case whatever it has computed is most likely incomplete.
+///
+/// The responses which are streamed are instead aborted midway when that happens.
+pub fn middleware< S >( req: ServiceRequest, service: &mut S ) -> impl Future< Item = ServiceResponse< Body >, Error = Error >
+    where S: Service< Request = ServiceRequest, Response = ServiceResponse< Body >, Error = Error >
+{
+    service.call( req ).map( |response| {
+        let was_exceeded = response.request().extensions().get::< Deadline >().map( |deadline| deadline.was_exceeded() ).unwrap_or( false );
+        if !was_exceeded || !response.status().is_success() {
+            return response;
+        }
+
+        let error_response = deadline_exceeded().response();
+        response.into_response( error_response )
+    })
+}
+
+#[test]
+fn test_deadline() {
+    let deadline = Deadline::none();
+    assert!( !deadline.is_exceeded() );
+    assert!( !deadline.check_periodically() );
+    assert!( deadline.remaining().is_none() );
+
+    let deadline = Deadline::after( Duration::from_secs( 3600 ) );
+    assert!( !deadline.is_exceeded() );
+    assert!( deadline.remaining().unwrap() > Duration::from_secs( 3500 ) );
+
+    let deadline = Deadline::after( Duration::from_millis( 0 ) );
+    assert!( !deadline.was_exceeded() );
+    assert!( (0..CHECK_INTERVAL).any( |_| deadline.check_periodically() ) );
+    assert!( deadline.was_exceeded() );
+    assert!( deadline.clone().check().is_err() );
+}
@@ -0,0 +1,111 @@
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{Error, HttpResponse};
+use actix_web::body::Body;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use futures::future::{self, Either, Future};
+
+use common::event::DataId;
+
+use crate::{State, StateRef};
+
+/// Responses can change between versions of the server even if the data stays the same.
+const BUILD_ID: &str = concat!( env!( "CARGO_PKG_NAME" ), "/", env!( "CARGO_PKG_VERSION" ) );
+
+/// Query parameters which make the response depend on something else than the data itself.
+const UNCACHEABLE_PARAMETERS: &[&str] = &[ "custom_filter_name", "generate_graphs" ];
+
+/// Returns a value which is mixed into every `ETag` generated by this instance of the server.
+///
+/// The server's options also affect the responses, so the `ETag`s are not reused across restarts.
+pub fn new_salt() -> String {
+    let timestamp = SystemTime::now().duration_since( UNIX_EPOCH ).map( |duration| duration.as_nanos() ).unwrap_or( 0 );
+    format!( "{}/{}", BUILD_ID, timestamp )
+}
+
+/// Returns whether the request's `If-None-Match` header matches the given `etag`.
+pub fn if_none_match( headers: &HeaderMap, etag: &str ) -> bool {
+    headers.get_all( header::IF_NONE_MATCH )
+        .filter_map( |value| value.to_str().ok() )
+        .flat_map( |value| value.split( ',' ) )
+        .map( |tag| tag.trim() )
+        .any( |tag| tag == "*" || tag.trim_start_matches( "W/" ) == etag )
+}
+
+/// Generates an `ETag` for a request to one of the `/data/{id}/...` endpoints.
+///
+/// The data files are immutable, so the response only depends on the data file,
+/// the endpoint, the query string, whose parameters are sorted beforehand,
+/// and the `Accept` header, which picks the format of the response.
+fn data_etag( state: &State, path: &str, query: &str, accept: &str ) -> Option< String > {
+    let path = state.strip_base_path( path )?;
+    if !path.starts_with( "/data/" ) {
+        return None;
+    }
+
+    let path = &path[ "/data/".len().. ];
+    let index = path.find( '/' )?;
+    let (id, endpoint) = path.split_at( index );
+    if endpoint.starts_with( "/script_files/" ) {
+        // These already have their own `ETag`s based on their contents.
+        return None;
+    }
+
+    let id: DataId = if id == "last" {
+        state.last_id()?
+    } else {
+        id.parse().ok()?
+    };
+
+    if !state.data.read().contains_key( &id ) {
+        return None;
+    }
+
+    let mut params: Vec< (String, String) > = serde_urlencoded::from_str( query ).ok()?;
+    if params.iter().any( |(key, _)| UNCACHEABLE_PARAMETERS.contains( &key.as_str() ) ) {
+        return None;
+    }
+
+    params.sort();
+    let query = serde_urlencoded::to_string( &params ).ok()?;
+    let generation = state.data_generation.load( Ordering::SeqCst );
+    let key = format!( "{}\n{}\n{}\n{}\n{}\n{}", state.etag_salt, generation, id, endpoint, query, accept );
+    Some( format!( "\"{:x}\"", md5::compute( key ) ) )
+}
+
+/// A middleware which adds `ETag`s to the responses of the data endpoints.
+///
+/// Requests with a matching `If-None-Match` header are answered with a `304 Not Modified`
+/// without calling the handler, so nothing gets recomputed.
+pub fn middleware< S >( req: ServiceRequest, service: &mut S ) -> impl Future< Item = ServiceResponse< Body >, Error = Error >
+    where S: Service< Request = ServiceRequest, Response = ServiceResponse< Body >, Error = Error >
+{
+    let etag = if req.method() == Method::GET || req.method() == Method::HEAD {
+        let accept = req.headers().get( header::ACCEPT ).and_then( |value| value.to_str().ok() ).unwrap_or( "" );
+        req.app_data::< StateRef >().and_then( |state| data_etag( &state, req.path(), req.query_string(), accept ) )
+    } else {
+        None
+    };
+
+    let etag = match etag {
+        Some( etag ) => etag,
+        None => return Either::A( service.call( req ) )
+    };
+
+    if if_none_match( req.headers(), &etag ) {
+        let response = HttpResponse::NotModified().header( header::ETAG, etag ).finish();
+        return Either::B( Either::A( future::ok( req.into_response( response ) ) ) );
+    }
+
+    Either::B( Either::B( service.call( req ).map( move |mut response| {
+        if response.status() == StatusCode::OK {
+            if let Ok( etag ) = HeaderValue::from_str( &etag ) {
+                response.headers_mut().insert( header::ETAG, etag );
+            }
+        }
+
+        response
+    })))
+}
@@ -0,0 +1,159 @@
+use std::fmt::Write;
+
+use crate::protocol::{self, LifetimeFilter};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    Flamegraph,
+    FlamegraphPl,
+    Heaptrack,
+    Replay
+}
+
+impl ExportFormat {
+    pub fn content_type( self ) -> &'static str {
+        match self {
+            ExportFormat::Flamegraph => "image/svg+xml",
+            ExportFormat::FlamegraphPl => "text/plain; charset=utf-8",
+            ExportFormat::Heaptrack | ExportFormat::Replay => "application/octet-stream"
+        }
+    }
+
+    fn extension( self ) -> &'static str {
+        match self {
+            ExportFormat::Flamegraph => "svg",
+            ExportFormat::FlamegraphPl => "folded",
+            ExportFormat::Heaptrack => "heaptrack.dat",
+            ExportFormat::Replay => "replay.dat"
+        }
+    }
+}
+
+/// Returns a short description of the filter to be put in the filename.
+fn filter_label( filter: &protocol::AllocFilter, custom_filter: &protocol::CustomFilter ) -> Option< String > {
+    let is_filtered =
+        *filter != protocol::AllocFilter { lifetime: filter.lifetime, ..Default::default() } ||
+        custom_filter.custom_filter.is_some() ||
+        custom_filter.custom_filter_name.is_some();
+
+    let label = match filter.lifetime {
+        None | Some( LifetimeFilter::All ) => None,
+        Some( LifetimeFilter::OnlyLeaked ) => Some( "leaked" ),
+        Some( LifetimeFilter::OnlyTemporary ) => Some( "temporary" ),
+        Some( LifetimeFilter::OnlyWholeGroupLeaked ) => Some( "group-leaked" ),
+        Some( LifetimeFilter::OnlyNotDeallocatedInCurrentRange ) => Some( "not-deallocated" ),
+        Some( LifetimeFilter::OnlyDeallocatedInCurrentRange ) => Some( "deallocated" )
+    };
+
+    match (label, is_filtered) {
+        (Some( label ), true) => Some( format!( "{}-filtered", label ) ),
+        (Some( label ), false) => Some( label.to_owned() ),
+        (None, true) => Some( "filtered".to_owned() ),
+        (None, false) => None
+    }
+}
+
+/// Builds a default filename for an export, e.g. `myapp-<data id>-leaked.svg`.
+pub fn default_filename(
+    executable: &str,
+    data_id: &str,
+    format: ExportFormat,
+    filter: &protocol::AllocFilter,
+    custom_filter: &protocol::CustomFilter
+) -> String {
+    let executable = executable.rsplit( '/' ).next().unwrap_or( "" );
+    let executable = if executable.is_empty() { "data" } else { executable };
+
+    let mut filename = format!( "{}-{}", executable, data_id );
+    if let Some( label ) = filter_label( filter, custom_filter ) {
+        filename.push( '-' );
+        filename.push_str( &label );
+    }
+
+    filename.push( '.' );
+    filename.push_str( format.extension() );
+    filename
+}
+
+/// Builds a default filename for an export of the memory maps, e.g. `myapp-<data id>-live-mmaps.svg`.
+pub fn default_mmap_filename(
+    executable: &str,
+    data_id: &str,
+    format: ExportFormat,
+    weight: protocol::MmapWeight
+) -> String {
+    let executable = executable.rsplit( '/' ).next().unwrap_or( "" );
+    let executable = if executable.is_empty() { "data" } else { executable };
+    let label = match weight {
+        protocol::MmapWeight::Mapped => "mmaps",
+        protocol::MmapWeight::Live => "live-mmaps"
+    };
+
+    format!( "{}-{}-{}.{}", executable, data_id, label, format.extension() )
+}
+
+/// Characters which can appear in a `filename*` parameter without being percent-encoded, as per RFC 5987.
+fn is_attr_char( byte: u8 ) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains( &byte )
+}
+
+/// Builds the value of a `Content-Disposition` header as per RFC 6266.
+///
+/// Non-ASCII filenames are sent both through `filename*`, and as an ASCII-only fallback
+/// through `filename` for the clients which don't understand the former.
+pub fn content_disposition( filename: &str ) -> String {
+    // Never let anything which looks like a path through.
+    let filename = filename.rsplit( |ch| ch == '/' || ch == '\\' ).next().unwrap_or( "" );
+    let filename = filename.trim_start_matches( '.' );
+    let filename = if filename.is_empty() { "download" } else { filename };
+
+    let fallback: String = filename.chars().map( |ch| {
+        if ch.is_ascii() && !ch.is_ascii_control() && ch != '"' && ch != '\\' && ch != '%' { ch } else { '_' }
+    }).collect();
+
+    let mut value = format!( "attachment; filename=\"{}\"", fallback );
+    if fallback != filename {
+        value.push_str( "; filename*=UTF-8''" );
+        for &byte in filename.as_bytes() {
+            if is_attr_char( byte ) {
+                value.push( byte as char );
+            } else {
+                write!( value, "%{:02X}", byte ).unwrap();
+            }
+        }
+    }
+
+    value
+}
+
+#[test]
+fn test_default_filename() {
+    let custom_filter = protocol::CustomFilter { custom_filter: None, custom_filter_name: None };
+    let mut filter = protocol::AllocFilter::default();
+    assert_eq!( default_filename( "/usr/bin/myapp", "1234", ExportFormat::Flamegraph, &filter, &custom_filter ), "myapp-1234.svg" );
+    assert_eq!( default_filename( "", "1234", ExportFormat::Heaptrack, &filter, &custom_filter ), "data-1234.heaptrack.dat" );
+
+    filter.lifetime = Some( LifetimeFilter::OnlyLeaked );
+    assert_eq!( default_filename( "myapp", "1234", ExportFormat::Flamegraph, &filter, &custom_filter ), "myapp-1234-leaked.svg" );
+
+    filter.size_min = Some( 100 );
+    assert_eq!( default_filename( "myapp", "1234", ExportFormat::FlamegraphPl, &filter, &custom_filter ), "myapp-1234-leaked-filtered.folded" );
+
+    filter.lifetime = None;
+    assert_eq!( default_filename( "myapp", "1234", ExportFormat::Replay, &filter, &custom_filter ), "myapp-1234-filtered.replay.dat" );
+}
+
+#[test]
+fn test_content_disposition() {
+    assert_eq!( content_disposition( "flame.svg" ), "attachment; filename=\"flame.svg\"" );
+    assert_eq!( content_disposition( "../../etc/passwd" ), "attachment; filename=\"passwd\"" );
+    assert_eq!( content_disposition( "" ), "attachment; filename=\"download\"" );
+    assert_eq!(
+        content_disposition( "a \"b\".svg" ),
+        "attachment; filename=\"a _b_.svg\"; filename*=UTF-8''a%20%22b%22.svg"
+    );
+    assert_eq!(
+        content_disposition( "zażółć-1234.svg" ),
+        "attachment; filename=\"za____-1234.svg\"; filename*=UTF-8''za%C5%BC%C3%B3%C5%82%C4%87-1234.svg"
+    );
+}
@@ -1,8 +1,8 @@
 use std::sync::Arc;
 use ahash::AHashMap as HashMap;
 use ahash::AHashSet as HashSet;
-use parking_lot::Mutex;
 
+use rayon::prelude::*;
 use regex::{self, Regex};
 
 use cli_core::{
@@ -13,7 +13,9 @@ use cli_core::{
     Timestamp
 };
 
+use crate::deadline::Deadline;
 use crate::protocol;
+use crate::ScriptLimits;
 
 #[derive(Clone, Debug)]
 pub struct GroupFilter {
@@ -44,63 +46,132 @@ impl From< crate::protocol::NumberOrPercentage > for cli_core::NumberOrFractionO
     }
 }
 
-fn run_custom_filter( data: &Arc< Data >, custom_filter: &protocol::CustomFilter ) -> Result< Option< Arc< HashSet< AllocationId > > >, cli_core::script::EvalError > {
+fn run_custom_filter( data: &Arc< Data >, custom_filter: &protocol::CustomFilter, limits: &ScriptLimits ) -> Result< Option< Arc< HashSet< AllocationId > > >, cli_core::script::EvalError > {
     let mut custom_set = None;
     if let Some( ref custom_filter ) = custom_filter.custom_filter {
         if custom_filter.is_empty() {
             return Ok( None );
         }
 
-        let args = cli_core::script::EngineArgs {
-            data: Some( data.clone() ),
-            .. cli_core::script::EngineArgs::default()
-        };
-
-        let env = Arc::new( Mutex::new( cli_core::script::VirtualEnvironment::new() ) );
-        let engine = cli_core::script::Engine::new( env.clone(), args );
+        // Custom filters can only ever see the data file they're filtering.
+        let engine = limits.new_engine( data.clone(), Vec::new(), limits.new_environment() );
         let custom_set = custom_set.get_or_insert( HashSet::new() );
         match engine.run( &custom_filter )? {
-            Some( mut list ) => {
+            cli_core::script::ScriptValue::AllocationList( mut list ) => {
                 custom_set.extend( list.allocation_ids().iter().copied() );
             },
-            None => {}
+            cli_core::script::ScriptValue::MapList( _ ) => {
+                return Err( cli_core::script::EvalError {
+                    message: "the custom filter must evaluate to a list of allocations, not a list of maps".into(),
+                    line: None,
+                    column: None,
+                    limit: None
+                });
+            },
+            cli_core::script::ScriptValue::Other => {}
         }
     }
 
     Ok( custom_set.map( |set| Arc::new( set ) ) )
 }
 
+#[derive(Clone)]
+enum Matcher {
+    Compiled {
+        filter: Box< cli_core::CompiledFilter >,
+        custom_filter: Option< Arc< HashSet< AllocationId > > >
+    },
+    Precomputed( Arc< MatchBitmap > )
+}
+
 #[derive(Clone)]
 pub struct AllocationFilter {
-    filter: cli_core::CompiledFilter,
-    custom_filter: Option< Arc< HashSet< AllocationId > > >
+    matcher: Matcher,
+    deadline: Deadline
 }
 
 impl AllocationFilter {
+    /// Creates a filter which matches exactly the allocations which were already matched before.
+    pub fn precomputed( matches: Arc< MatchBitmap >, deadline: Deadline ) -> Self {
+        AllocationFilter { matcher: Matcher::Precomputed( matches ), deadline }
+    }
+
     pub fn try_match( &self, data: &Data, id: AllocationId, allocation: &Allocation ) -> bool {
-        if let Some( ref custom_filter ) = self.custom_filter {
-            if !custom_filter.contains( &id ) {
-                return false;
-            }
+        // Once the deadline's hit nothing matches anymore, so that whatever is iterating over
+        // the allocations finishes quickly; the response is then replaced with an error.
+        if self.deadline.check_periodically() {
+            return false;
         }
 
-        if !self.filter.try_match( data, allocation ) {
-            return false;
+        match self.matcher {
+            Matcher::Compiled { ref filter, ref custom_filter } => {
+                if let Some( ref custom_filter ) = custom_filter {
+                    if !custom_filter.contains( &id ) {
+                        return false;
+                    }
+                }
+
+                filter.try_match( data, allocation )
+            },
+            Matcher::Precomputed( ref matches ) => matches.contains( id )
         }
+    }
+}
+
+/// The set of allocations matched by a filter, one bit per allocation.
+pub struct MatchBitmap {
+    bits: Vec< u64 >
+}
 
-        true
+impl MatchBitmap {
+    pub fn new( data: &Data, filter: &AllocationFilter ) -> Self {
+        let count = data.alloc_sorted_by_timestamp( None, None ).len() as u64;
+        let bits = (0..(count + 63) / 64).into_par_iter().map( |index| {
+            let mut word = 0;
+            for raw_id in index * 64..std::cmp::min( (index + 1) * 64, count ) {
+                let id = data.get_allocation_id( raw_id ).unwrap();
+                if filter.try_match( data, id, data.get_allocation( id ) ) {
+                    word |= 1 << (raw_id % 64);
+                }
+            }
+            word
+        }).collect();
+
+        MatchBitmap { bits }
+    }
+
+    pub fn contains( &self, id: AllocationId ) -> bool {
+        let raw_id = id.raw();
+        self.bits.get( (raw_id / 64) as usize ).map( |word| word & (1 << (raw_id % 64)) != 0 ).unwrap_or( false )
+    }
+
+    pub fn size_in_bytes( &self ) -> usize {
+        self.bits.len() * std::mem::size_of::< u64 >()
     }
 }
 
 pub fn prepare_filter(
     data: &Arc< Data >,
     filter: &protocol::AllocFilter,
-    custom_filter: &protocol::CustomFilter
+    custom_filter: &protocol::CustomFilter,
+    limits: &ScriptLimits
 ) -> Result< AllocationFilter, PrepareFilterError > {
     let filter = prepare_raw_filter( data, filter )?.compile( data );
-    let custom_filter = run_custom_filter( data, custom_filter ).map_err( |error| PrepareFilterError::InvalidCustomFilter( error.message ) )?;
+    limits.deadline.check().map_err( |_| PrepareFilterError::DeadlineExceeded )?;
+
+    let custom_filter = run_custom_filter( data, custom_filter, limits ).map_err( |error| {
+        // The script's timeout is bounded by the deadline, so that's most likely why it has failed.
+        if limits.deadline.is_exceeded() {
+            PrepareFilterError::DeadlineExceeded
+        } else {
+            PrepareFilterError::InvalidCustomFilter( error.message )
+        }
+    })?;
 
-    Ok( AllocationFilter { filter, custom_filter } )
+    Ok( AllocationFilter {
+        matcher: Matcher::Compiled { filter: Box::new( filter ), custom_filter },
+        deadline: limits.deadline.clone()
+    })
 }
 
 pub fn prepare_raw_filter( data: &Data, filter: &protocol::AllocFilter ) -> Result< cli_core::Filter, PrepareFilterError > {
@@ -220,7 +291,8 @@ pub fn prepare_raw_filter( data: &Data, filter: &protocol::AllocFilter ) -> Resu
 
 pub enum PrepareFilterError {
     InvalidRegex( &'static str, regex::Error ),
-    InvalidCustomFilter( String )
+    InvalidCustomFilter( String ),
+    DeadlineExceeded
 }
 
 pub fn prepare_backtrace_filter( filter: &protocol::BacktraceFilter ) -> Result< BacktraceFilter, PrepareFilterError > {
@@ -365,3 +437,38 @@ pub fn match_backtrace< 'a >(
 
     positive_matched && !negative_matched
 }
+
+#[test]
+fn test_custom_filter_sees_only_its_data_file() {
+    let data = Arc::new( crate::load_test_data( cli_core::DataId::new( 0, 1 ), Vec::new() ) );
+    let limits = ScriptLimits::default();
+    let run = |code: &str| {
+        let custom_filter = protocol::CustomFilter { custom_filter: Some( code.into() ), custom_filter_name: None };
+        run_custom_filter( &data, &custom_filter, &limits )
+    };
+
+    assert!( run( "dataset(\"00000000000000000000000000000001\").allocations()" ).is_ok() );
+    assert!( run( "datasets()[0].allocations()" ).is_ok() );
+    assert!( run( "dataset(\"00000000000000000000000000000002\").allocations()" ).is_err() );
+}
+
+#[test]
+fn test_match_bitmap() {
+    use common::event::Event;
+
+    let mut events = vec![ Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() } ];
+    events.extend( (0..100).map( |index| crate::test_alloc( 1, 0x1000 + index * 0x100, index + 1, 1 ) ) );
+
+    let data = Arc::new( crate::load_test_data( cli_core::DataId::new( 0, 1 ), events ) );
+    let limits = ScriptLimits::default();
+    let alloc_filter = protocol::AllocFilter { size_min: Some( 71 ), .. Default::default() };
+    let custom_filter = protocol::CustomFilter { custom_filter: None, custom_filter_name: None };
+    let filter = prepare_filter( &data, &alloc_filter, &custom_filter, &limits ).ok().unwrap();
+    let matches = MatchBitmap::new( &data, &filter );
+    assert_eq!( matches.size_in_bytes(), 16 );
+
+    let precomputed = AllocationFilter::precomputed( Arc::new( matches ), limits.deadline.clone() );
+    for (id, allocation) in data.allocations_with_id() {
+        assert_eq!( precomputed.try_match( &data, id, allocation ), allocation.size >= 71 );
+    }
+}
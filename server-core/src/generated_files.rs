@@ -0,0 +1,320 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use ahash::AHashMap as HashMap;
+use common::event::DataId;
+
+/// The default size of the in-memory cache of files generated by scripts.
+pub const DEFAULT_GENERATED_FILES_CACHE_SIZE: usize = 32 * 1024 * 1024;
+
+/// How long the files spilled to disk are kept around.
+const GENERATED_FILE_DISK_TTL: Duration = Duration::from_secs( 24 * 60 * 60 );
+
+#[derive(Clone)]
+pub struct GeneratedFile {
+    pub data_id: DataId,
+    timestamp: Instant,
+    pub hash: String,
+    pub mime: &'static str,
+    pub data: Arc< Vec< u8 > >
+}
+
+impl GeneratedFile {
+    pub fn new( data_id: DataId, mime: &'static str, data: Arc< Vec< u8 > > ) -> Self {
+        GeneratedFile {
+            data_id,
+            timestamp: Instant::now(),
+            hash: format!( "{:x}", md5::compute( &*data ) ),
+            mime,
+            data
+        }
+    }
+
+    pub fn url( &self, base_path: &str, basename: &str ) -> String {
+        format!( "{}/data/{}/script_files/{}/{}", base_path, self.data_id, self.hash, basename )
+    }
+}
+
+/// A file which was evicted from memory and written to disk.
+struct SpilledFile {
+    data_id: DataId,
+    mime: &'static str,
+    size: usize,
+    checksum: String,
+    created: SystemTime
+}
+
+pub struct GeneratedFilesCollection {
+    pub by_hash: HashMap< String, GeneratedFile >,
+    pub total_size: usize,
+    maximum_size: usize,
+    directory: Option< PathBuf >,
+    on_disk: HashMap< String, SpilledFile >
+}
+
+impl Default for GeneratedFilesCollection {
+    fn default() -> Self {
+        GeneratedFilesCollection::new( DEFAULT_GENERATED_FILES_CACHE_SIZE, None )
+    }
+}
+
+/// The names of the files we create in the spill directory; anything else is left alone.
+fn is_spilled_file_name( name: &str ) -> bool {
+    let name = name.trim_end_matches( ".tmp" );
+    let hash = name.split( '-' ).next().unwrap();
+    hash.len() == 32 && hash.bytes().all( |byte| byte.is_ascii_hexdigit() ) &&
+        name[ hash.len().. ].chars().all( |ch| ch == '-' || ch.is_ascii_digit() )
+}
+
+fn write_atomically( path: &Path, data: &[u8] ) -> io::Result< () > {
+    // Write into a temporary file first so that a partially written file never ends up being served.
+    let tmp_path = path.with_extension( "tmp" );
+    if let Err( error ) = fs::write( &tmp_path, data ) {
+        let _ = fs::remove_file( &tmp_path );
+        return Err( error );
+    }
+
+    fs::rename( &tmp_path, path )
+}
+
+impl GeneratedFilesCollection {
+    /// Creates a new collection which keeps at most `maximum_size` bytes in memory,
+    /// and, if a `directory` is given, writes the files evicted from memory there.
+    pub fn new( maximum_size: usize, directory: Option< PathBuf > ) -> Self {
+        if let Some( ref directory ) = directory {
+            if let Err( error ) = fs::create_dir_all( directory ) {
+                warn!( "Failed to create {:?}: {}", directory, error );
+            }
+
+            // The files left over from a previous run can't be accessed anymore.
+            if let Ok( entries ) = fs::read_dir( directory ) {
+                for entry in entries.flatten() {
+                    if entry.file_name().to_str().map( is_spilled_file_name ).unwrap_or( false ) {
+                        let _ = fs::remove_file( entry.path() );
+                    }
+                }
+            }
+        }
+
+        GeneratedFilesCollection {
+            by_hash: HashMap::new(),
+            total_size: 0,
+            maximum_size,
+            directory,
+            on_disk: HashMap::new()
+        }
+    }
+
+    fn spill_path( &self, key: &str ) -> Option< PathBuf > {
+        self.directory.as_ref().map( |directory| directory.join( key ) )
+    }
+
+    fn spill( &mut self, entry: &GeneratedFile ) {
+        let path = match self.spill_path( &entry.hash ) {
+            Some( path ) => path,
+            None => return
+        };
+
+        if let Err( error ) = write_atomically( &path, &entry.data ) {
+            warn!( "Failed to write a generated file to {:?}: {}", path, error );
+            return;
+        }
+
+        self.on_disk.insert( entry.hash.clone(), SpilledFile {
+            data_id: entry.data_id,
+            mime: entry.mime,
+            size: entry.data.len(),
+            checksum: format!( "{:x}", md5::compute( &*entry.data ) ),
+            created: SystemTime::now()
+        });
+    }
+
+    fn remove_spilled( &mut self, key: &str ) {
+        if self.on_disk.remove( key ).is_some() {
+            if let Some( path ) = self.spill_path( key ) {
+                let _ = fs::remove_file( path );
+            }
+        }
+    }
+
+    fn remove_expired( &mut self ) {
+        let now = SystemTime::now();
+        let expired: Vec< _ > = self.on_disk.iter()
+            .filter( |(_, file)| now.duration_since( file.created ).map( |age| age > GENERATED_FILE_DISK_TTL ).unwrap_or( false ) )
+            .map( |(key, _)| key.clone() )
+            .collect();
+
+        for key in expired {
+            self.remove_spilled( &key );
+        }
+    }
+
+    fn purge_old_if_too_big( &mut self ) {
+        if self.total_size < self.maximum_size {
+            return;
+        }
+
+        let mut list: Vec< _ > = self.by_hash.values().cloned().collect();
+        list.sort_by_key( |entry| entry.timestamp );
+        list.reverse();
+
+        while let Some( entry ) = list.pop() {
+            if self.total_size <= self.maximum_size / 2 {
+                break;
+            }
+
+            self.total_size -= entry.data.len();
+            self.by_hash.remove( &entry.hash );
+            self.spill( &entry );
+        }
+    }
+
+    pub fn remove_files_for( &mut self, data_id: DataId ) {
+        let total_size = &mut self.total_size;
+        self.by_hash.retain( |_, entry| {
+            if entry.data_id != data_id {
+                return true;
+            }
+
+            *total_size -= entry.data.len();
+            false
+        });
+
+        let spilled: Vec< _ > = self.on_disk.iter().filter( |(_, file)| file.data_id == data_id ).map( |(key, _)| key.clone() ).collect();
+        for key in spilled {
+            self.remove_spilled( &key );
+        }
+    }
+
+    /// Checks whether the file stored under the given key has exactly the given contents.
+    fn is_same_file( &self, key: &str, data: &[u8] ) -> Option< bool > {
+        if let Some( existing ) = self.by_hash.get( key ) {
+            return Some( *existing.data == *data );
+        }
+
+        let spilled = self.on_disk.get( key )?;
+        if spilled.size != data.len() {
+            return Some( false );
+        }
+
+        let path = self.spill_path( key )?;
+        Some( fs::read( path ).map( |existing| existing == data ).unwrap_or( false ) )
+    }
+
+    /// Adds a new file and returns it; the returned entry's hash might be different than
+    /// the original one if a different file with the same hash was already added.
+    pub fn add_file( &mut self, mut entry: GeneratedFile ) -> GeneratedFile {
+        self.remove_expired();
+        self.purge_old_if_too_big();
+
+        let hash = entry.hash.clone();
+        for index in 1.. {
+            match self.is_same_file( &entry.hash, &entry.data ) {
+                Some( true ) => {
+                    if let Some( existing ) = self.by_hash.get( &entry.hash ) {
+                        return existing.clone();
+                    }
+
+                    // Bring it back into memory since it's obviously being used.
+                    self.remove_spilled( &entry.hash );
+                    break;
+                },
+                Some( false ) => {
+                    entry.hash = format!( "{}-{}", hash, index );
+                },
+                None => break
+            }
+        }
+
+        self.total_size += entry.data.len();
+        self.by_hash.insert( entry.hash.clone(), entry.clone() );
+        entry
+    }
+
+    /// Returns the file either from memory or from disk.
+    pub fn get( &mut self, key: &str ) -> Option< GeneratedFile > {
+        if let Some( entry ) = self.by_hash.get( key ) {
+            return Some( entry.clone() );
+        }
+
+        self.remove_expired();
+        let spilled = self.on_disk.get( key )?;
+        let path = self.spill_path( key )?;
+        let data = match fs::read( &path ) {
+            Ok( data ) => data,
+            Err( error ) => {
+                warn!( "Failed to read a generated file from {:?}: {}", path, error );
+                self.remove_spilled( key );
+                return None;
+            }
+        };
+
+        if data.len() != spilled.size || format!( "{:x}", md5::compute( &data ) ) != spilled.checksum {
+            warn!( "The generated file at {:?} is corrupted", path );
+            self.remove_spilled( key );
+            return None;
+        }
+
+        Some( GeneratedFile {
+            data_id: spilled.data_id,
+            timestamp: Instant::now(),
+            hash: key.to_owned(),
+            mime: spilled.mime,
+            data: Arc::new( data )
+        })
+    }
+}
+
+#[test]
+fn test_generated_files_spill_to_disk() {
+    let directory = std::env::temp_dir().join( format!( "bytehound-generated-files-test-{}", std::process::id() ) );
+    let _ = fs::remove_dir_all( &directory );
+    fs::create_dir_all( &directory ).unwrap();
+
+    let data_id = DataId::new( 1, 2 );
+    let mut collection = GeneratedFilesCollection::new( 16, Some( directory.clone() ) );
+    let first = collection.add_file( GeneratedFile::new( data_id, "text/plain", Arc::new( vec![ 1; 10 ] ) ) );
+    let second = collection.add_file( GeneratedFile::new( data_id, "text/plain", Arc::new( vec![ 2; 10 ] ) ) );
+    let third = collection.add_file( GeneratedFile::new( data_id, "text/plain", Arc::new( vec![ 3; 10 ] ) ) );
+
+    // The first one was evicted from memory, but can still be read back.
+    assert!( !collection.by_hash.contains_key( &first.hash ) );
+    assert!( directory.join( &first.hash ).exists() );
+    assert_eq!( *collection.get( &first.hash ).unwrap().data, vec![ 1; 10 ] );
+    assert_eq!( *collection.get( &second.hash ).unwrap().data, vec![ 2; 10 ] );
+    assert_eq!( *collection.get( &third.hash ).unwrap().data, vec![ 3; 10 ] );
+
+    // Adding the same contents again gives back the same entry.
+    assert_eq!( collection.add_file( GeneratedFile::new( data_id, "text/plain", Arc::new( vec![ 3; 10 ] ) ) ).hash, third.hash );
+
+    // A different file with the same hash gets a different key.
+    let mut colliding = GeneratedFile::new( data_id, "text/plain", Arc::new( vec![ 4; 10 ] ) );
+    colliding.hash = third.hash.clone();
+    let colliding = collection.add_file( colliding );
+    assert_eq!( colliding.hash, format!( "{}-1", third.hash ) );
+    assert_eq!( *collection.get( &colliding.hash ).unwrap().data, vec![ 4; 10 ] );
+    assert_eq!( *collection.get( &third.hash ).unwrap().data, vec![ 3; 10 ] );
+
+    // Corrupted files are not served.
+    fs::write( directory.join( &first.hash ), &[ 1; 5 ] ).unwrap();
+    assert!( collection.get( &first.hash ).is_none() );
+    assert!( !directory.join( &first.hash ).exists() );
+
+    collection.remove_files_for( data_id );
+    assert!( collection.by_hash.is_empty() );
+    assert!( fs::read_dir( &directory ).unwrap().next().is_none() );
+
+    let _ = fs::remove_dir_all( &directory );
+}
+
+#[test]
+fn test_is_spilled_file_name() {
+    assert!( is_spilled_file_name( "0123456789abcdef0123456789abcdef" ) );
+    assert!( is_spilled_file_name( "0123456789abcdef0123456789abcdef-12" ) );
+    assert!( is_spilled_file_name( "0123456789abcdef0123456789abcdef.tmp" ) );
+    assert!( !is_spilled_file_name( "0123456789abcdef0123456789abcdef.svg" ) );
+    assert!( !is_spilled_file_name( "foo" ) );
+}
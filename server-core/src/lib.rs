@@ -4,9 +4,9 @@ extern crate log;
 extern crate serde_derive;
 
 use std::collections::BTreeMap;
-use std::fs::File;
 use std::error::Error;
-use std::sync::Arc;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::ops::Bound::{self, Unbounded};
 use std::fmt::{self, Write};
 use std::thread;
@@ -14,7 +14,7 @@ use std::io;
 use std::borrow::Cow;
 use std::cmp::{min, max};
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use actix_web::{
     body::{
@@ -31,21 +31,26 @@ use actix_web::{
 
 use ahash::AHashMap as HashMap;
 
-use actix_web::error::{ErrorNotFound, ErrorBadRequest, ErrorInternalServerError};
 use actix_web::error::Error as ActixWebError;
-use actix_cors::Cors;
-use futures::Stream;
+use actix_web::http::StatusCode;
+use crate::api_error::ApiError;
+use crate::exports::ExportFormat;
+use crate::body_params::BodyParams;
+use crate::deadline::Deadline;
+use crate::response_format::ResponseFormat;
+use futures::{Future, Stream};
+use futures::future;
 use serde::Serialize;
 use itertools::Itertools;
 use lru::LruCache;
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex, RwLock};
 use rayon::prelude::*;
 
 use cli_core::{
-    Loader,
     Data,
     DataId,
     BacktraceId,
+    StringId,
     Operation,
     OperationId,
     Frame,
@@ -57,13 +62,21 @@ use cli_core::{
     MalloptKind,
     VecVec,
     MmapOperation,
+    DemangleStyle,
+    FlamegraphOptions,
     MemoryMap,
+    MemoryProtect,
+    MemoryRemap,
     MemoryUnmap,
     CountAndSize,
+    LoadRange,
+    MergeError,
     export_as_replay,
     export_as_heaptrack,
     export_as_flamegraph,
     export_as_flamegraph_pl,
+    export_sizes_as_flamegraph,
+    export_sizes_as_flamegraph_pl,
     table_to_string
 };
 
@@ -75,10 +88,34 @@ mod streaming_channel;
 mod byte_channel;
 mod streaming_serializer;
 mod filter;
+mod source_files;
+mod script_library;
+mod etag;
+mod auth;
+mod cors;
+mod metrics;
+mod loading;
+mod live;
+mod mmap_state;
+mod aliases;
+mod worker_pool;
+mod generated_files;
+mod request_log;
+mod range;
+mod api_error;
+mod exports;
+mod body_params;
+mod deadline;
+mod response_format;
 
 use crate::byte_channel::byte_channel;
 use crate::streaming_serializer::StreamingSerializer;
+use crate::source_files::SourceFiles;
+use crate::script_library::{ScriptLibrary, ScriptLibraryError, MAXIMUM_SCRIPT_SIZE};
 use crate::filter::{AllocationFilter, PrepareFilterError, prepare_filter, prepare_raw_filter};
+use crate::generated_files::{GeneratedFile, GeneratedFilesCollection};
+
+pub use crate::cors::{CorsOrigins, parse_cors_origins};
 
 struct AllocationGroups {
     allocations_by_backtrace: VecVec< BacktraceId, AllocationId >
@@ -138,1533 +175,9818 @@ struct AllocationGroupsKey {
     order: protocol::Order
 }
 
-#[derive(Clone)]
-struct GeneratedFile {
-    timestamp: Instant,
-    hash: String,
-    mime: &'static str,
-    data: Arc< Vec< u8 > >
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct MatchCacheKey {
+    data_id: DataId,
+    filter: protocol::AllocFilter,
+    /// The source of the custom filter, after its name was resolved.
+    custom_filter: Option< String >
 }
 
-#[derive(Default)]
-struct GeneratedFilesCollection {
-    by_hash: HashMap< String, GeneratedFile >,
-    total_size: usize
+/// How long finished script jobs are kept around.
+const SCRIPT_JOB_RETENTION: Duration = Duration::from_secs( 60 * 60 );
+
+/// How many finished script jobs are kept around at most.
+const MAXIMUM_FINISHED_SCRIPT_JOB_COUNT: usize = 64;
+
+/// How often a script job's output stream checks whether the job has printed something or has finished.
+const SCRIPT_JOB_STREAM_POLL_INTERVAL: Duration = Duration::from_millis( 100 );
+
+/// How long a script job's output stream can stay silent before a keep-alive is sent.
+const SCRIPT_JOB_STREAM_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs( 15 );
+
+/// How many data files `/execute_script_all` can run a script on at the same time.
+const MAXIMUM_EXECUTE_SCRIPT_ALL_PARALLELISM: usize = 16;
+
+/// How many heavy requests can wait for a free worker before new ones are rejected.
+const MAXIMUM_QUEUED_HEAVY_REQUESTS: usize = 64;
+
+/// The value of the `Retry-After` header sent when the heavy request queue is full, in seconds.
+const HEAVY_REQUEST_RETRY_AFTER: u64 = 5;
+
+struct ScriptJobResult {
+    elapsed: Duration,
+    finished_at: Instant,
+    error: Option< cli_core::script::EvalError >
+}
+
+struct ScriptJobProgress {
+    output: Vec< serde_json::Value >,
+    result: Option< ScriptJobResult >
+}
+
+struct ScriptJob {
+    id: u64,
+    data_id: DataId,
+    started_at: Instant,
+    output: crossbeam_channel::Receiver< cli_core::script::ScriptOutputKind >,
+    /// Held while receiving the output so that it's always appended in the order it was produced.
+    output_lock: Mutex< () >,
+    progress: Mutex< ScriptJobProgress >
 }
 
-impl GeneratedFilesCollection {
-    fn purge_old_if_too_big( &mut self ) {
-        if self.total_size < 32 * 1024 * 1024 {
+impl ScriptJob {
+    /// Moves everything the script has printed so far into the job's output.
+    fn collect_output( &self, state: &State ) {
+        let _lock = self.output_lock.lock();
+        let items: Vec< _ > = self.output.try_iter().collect();
+        if items.is_empty() {
             return;
         }
 
-        let mut list: Vec< _ > = self.by_hash.values().cloned().collect();
-        list.sort_by_key( |entry| entry.timestamp );
-        list.reverse();
+        let output = script_output_to_json( state, self.data_id, items );
+        self.progress.lock().output.extend( output );
+    }
 
-        while let Some( entry ) = list.pop() {
-            if self.total_size <= 16 * 1024 * 1024 {
-                break;
-            }
+    fn to_json( &self, include_id: bool ) -> serde_json::Value {
+        let progress = self.progress.lock();
+        let mut value = match progress.result {
+            None => {
+                serde_json::json! {{
+                    "status": "running",
+                    "elapsed": self.started_at.elapsed().as_secs_f64(),
+                    "output": progress.output
+                }}
+            },
+            Some( ref result ) => script_result_to_json( result.error.as_ref(), result.elapsed, &progress.output )
+        };
 
-            self.total_size -= entry.data.len();
-            self.by_hash.remove( &entry.hash );
+        if include_id {
+            value[ "id" ] = self.id.into();
         }
-    }
 
-    fn add_file( &mut self, entry: GeneratedFile ) {
-        if !self.by_hash.contains_key( &entry.hash ) {
-            self.total_size += entry.data.len();
-            self.by_hash.insert( entry.hash.clone(), entry );
-        }
+        value
     }
 }
 
-struct State {
-    data: HashMap< DataId, Arc< Data > >,
-    data_ids: Vec< DataId >,
-    allocation_group_cache: Mutex< LruCache< AllocationGroupsKey, Arc< AllocationGroups > > >,
-    generated_files: Mutex< GeneratedFilesCollection >
+/// Resource limits applied to every script run by the server, including custom filters.
+#[derive(Clone, Default)]
+struct ScriptLimits {
+    timeout: Option< Duration >,
+    max_output_size: Option< usize >,
+    max_collection_size: Option< usize >,
+    max_table_rows: Option< usize >,
+    max_files_size: Option< usize >,
+    /// Whether scripts can use absolute paths and `..` within their virtual filesystem.
+    allow_fs: bool,
+    /// The deadline of the request for which the script is run.
+    deadline: Deadline
 }
 
-impl State {
-    fn new() -> Self {
-        State {
-            data: HashMap::new(),
-            data_ids: Vec::new(),
-            allocation_group_cache: Mutex::new( LruCache::new( 4 ) ),
-            generated_files: Default::default(),
+impl ScriptLimits {
+    /// Returns the limits for scripts run in a session, which are additionally
+    /// bounded since everything they define is kept around until the session expires.
+    fn for_session( &self ) -> Self {
+        let max_output_size = self.max_output_size.map( |size| min( size, MAXIMUM_SCRIPT_SESSION_OUTPUT_SIZE ) ).unwrap_or( MAXIMUM_SCRIPT_SESSION_OUTPUT_SIZE );
+        ScriptLimits {
+            timeout: self.timeout,
+            max_output_size: Some( max_output_size ),
+            max_collection_size: Some( MAXIMUM_SCRIPT_SESSION_COLLECTION_SIZE ),
+            max_table_rows: self.max_table_rows,
+            max_files_size: self.max_files_size,
+            allow_fs: self.allow_fs,
+            deadline: self.deadline.clone()
         }
     }
 
-    fn add_data( &mut self, data: Data ) {
-        if self.data.contains_key( &data.id() ) {
-            return;
+    /// Returns the limits for scripts run as part of a request with the given deadline.
+    fn with_deadline( &self, deadline: Deadline ) -> Self {
+        let timeout = match (self.timeout, deadline.remaining()) {
+            (Some( timeout ), Some( remaining )) => Some( min( timeout, remaining ) ),
+            (timeout, remaining) => timeout.or( remaining )
+        };
+
+        ScriptLimits {
+            timeout,
+            deadline,
+            .. self.clone()
         }
+    }
 
-        self.data_ids.push( data.id() );
-        self.data.insert( data.id(), Arc::new( data ) );
+    fn new_environment( &self ) -> Arc< Mutex< cli_core::script::VirtualEnvironment > > {
+        Arc::new( Mutex::new( self.environment() ) )
     }
 
-    fn last_id( &self ) -> Option< DataId > {
-        self.data_ids.last().cloned()
+    /// Returns a new environment which sends the script's output through a channel as soon as it's produced.
+    fn new_streaming_environment( &self ) -> (Arc< Mutex< cli_core::script::VirtualEnvironment > >, crossbeam_channel::Receiver< cli_core::script::ScriptOutputKind >) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let env = self.environment().with_output_channel( sender );
+        (Arc::new( Mutex::new( env ) ), receiver)
     }
-}
 
-type StateRef = Arc< State >;
+    fn environment( &self ) -> cli_core::script::VirtualEnvironment {
+        cli_core::script::VirtualEnvironment::new()
+            .with_output_limit( self.max_output_size )
+            .with_table_row_limit( self.max_table_rows )
+            .with_files_limit( self.max_files_size )
+            .with_sandbox( !self.allow_fs )
+    }
 
-trait StateGetter {
-    fn state( &self ) -> &StateRef;
-}
+    /// Creates a new engine for a script running on `data`, which can additionally
+    /// access every data file in `datasets`.
+    fn new_engine( &self, data: Arc< Data >, datasets: Vec< Arc< Data > >, env: Arc< Mutex< cli_core::script::VirtualEnvironment > > ) -> cli_core::script::Engine {
+        self.new_filtered_engine( data, None, datasets, env )
+    }
 
-impl StateGetter for HttpRequest {
-    fn state( &self ) -> &StateRef {
-        self.app_data::< StateRef >().unwrap()
+    /// Same as `new_engine`, except if `allocation_ids` is set then `allocations()` only returns those allocations.
+    fn new_filtered_engine(
+        &self,
+        data: Arc< Data >,
+        allocation_ids: Option< Arc< Vec< AllocationId > > >,
+        datasets: Vec< Arc< Data > >,
+        env: Arc< Mutex< cli_core::script::VirtualEnvironment > >
+    ) -> cli_core::script::Engine {
+        let args = cli_core::script::EngineArgs {
+            data: Some( data ),
+            allocation_ids,
+            datasets,
+            timeout: self.timeout,
+            max_collection_size: self.max_collection_size,
+            .. cli_core::script::EngineArgs::default()
+        };
+
+        cli_core::script::Engine::new( env, args )
     }
 }
 
-fn query< 'a, T: serde::Deserialize< 'a > >( req: &'a HttpRequest ) -> Result< T > {
-    serde_urlencoded::from_str::<T>( req.query_string() )
-        .map_err( |e| e.into() )
+#[derive(Default)]
+struct ScriptJobCollection {
+    by_id: HashMap< u64, Arc< ScriptJob > >,
+    next_id: u64
 }
 
-fn get_data_id( req: &HttpRequest ) -> Result< DataId > {
-    let id = req.match_info().get( "id" ).unwrap();
-    if id == "last" {
-        return req.state().last_id().ok_or( ErrorNotFound( "data not found" ) );
-    }
+impl ScriptJobCollection {
+    fn purge_old( &mut self ) {
+        let mut list: Vec< _ > = self.by_id.values().filter_map( |job| {
+            job.progress.lock().result.as_ref().map( |result| (result.finished_at, job.id) )
+        }).collect();
+
+        list.sort();
+        list.reverse();
 
-    let id: DataId = id.parse().map_err( |_| ErrorNotFound( "data not found" ) )?;
-    if !req.state().data.contains_key( &id ) {
-        return Err( ErrorNotFound( "data not found" ) );
+        while let Some( (finished_at, id) ) = list.pop() {
+            if list.len() < MAXIMUM_FINISHED_SCRIPT_JOB_COUNT && finished_at.elapsed() <= SCRIPT_JOB_RETENTION {
+                break;
+            }
+
+            self.by_id.remove( &id );
+        }
     }
-    Ok( id )
-}
 
-fn get_data( req: &HttpRequest ) -> Result< &Arc< Data > > {
-    let id = get_data_id( req )?;
-    req.state().data.get( &id ).ok_or_else( || ErrorNotFound( "data not found" ) )
+    fn add_job( &mut self, data_id: DataId, output: crossbeam_channel::Receiver< cli_core::script::ScriptOutputKind > ) -> Arc< ScriptJob > {
+        self.purge_old();
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let job = Arc::new( ScriptJob {
+            id,
+            data_id,
+            started_at: Instant::now(),
+            output,
+            output_lock: Mutex::new( () ),
+            progress: Mutex::new( ScriptJobProgress {
+                output: Vec::new(),
+                result: None
+            })
+        });
+
+        self.by_id.insert( id, job.clone() );
+        job
+    }
 }
 
-impl From< PrepareFilterError > for ActixWebError {
-    fn from( error: PrepareFilterError ) -> Self {
-        match error {
-            PrepareFilterError::InvalidRegex( field, inner_err ) => {
-                ErrorBadRequest( format!( "invalid '{}': {}", field, inner_err ) )
-            },
-            PrepareFilterError::InvalidCustomFilter( message ) => {
-                ErrorBadRequest( format!( "failed to evaluate custom filter: {}", message ) )
-            }
-        }
+#[test]
+fn test_script_job_collection_purges_old_jobs() {
+    let mut jobs = ScriptJobCollection::default();
+    let data_id = DataId::new( 0, 0 );
+    let env = || crossbeam_channel::unbounded().1;
+    let running = jobs.add_job( data_id, env() );
+    for _ in 0..MAXIMUM_FINISHED_SCRIPT_JOB_COUNT + 8 {
+        let job = jobs.add_job( data_id, env() );
+        job.progress.lock().result = Some( ScriptJobResult {
+            elapsed: Duration::from_secs( 0 ),
+            finished_at: Instant::now(),
+            error: None
+        });
     }
+
+    let last = jobs.add_job( data_id, env() );
+    assert_eq!( jobs.by_id.len(), MAXIMUM_FINISHED_SCRIPT_JOB_COUNT + 2 );
+    assert!( jobs.by_id.contains_key( &running.id ) );
+    assert!( jobs.by_id.contains_key( &last.id ) );
+    assert!( !jobs.by_id.contains_key( &(running.id + 1) ) );
+    assert!( jobs.by_id.contains_key( &(last.id - 1) ) );
 }
 
-fn async_data_handler< F: FnOnce( Arc< Data >, byte_channel::ByteSender ) + Send + 'static >( req: &HttpRequest, callback: F ) -> Result< Body > {
-    let (tx, rx) = byte_channel();
-    let rx = rx.map_err( |_| ErrorInternalServerError( "internal error" ) );
-    let rx = BodyStream::new( rx );
-    let body = Body::Message( Box::new( rx ) );
+/// How many script sessions can exist at the same time.
+const MAXIMUM_SCRIPT_SESSION_COUNT: usize = 16;
 
-    let data_id = get_data_id( &req )?;
-    let state = req.state().clone();
-    thread::spawn( move || {
-        let data = match state.data.get( &data_id ) {
-            Some( data ) => data,
-            None => return
-        };
+/// The maximum size of everything a single script session can output over its lifetime.
+const MAXIMUM_SCRIPT_SESSION_OUTPUT_SIZE: usize = 64 * 1024 * 1024;
 
-        callback( data.clone(), tx );
-    });
+/// The maximum length of strings, arrays and maps created in a script session.
+const MAXIMUM_SCRIPT_SESSION_COLLECTION_SIZE: usize = 16 * 1024 * 1024;
 
-    Ok( body )
+/// Roughly how much memory the variables of a single script session can take,
+/// so that every session taken together can't take more than a gigabyte.
+const MAXIMUM_SCRIPT_SESSION_MEMORY_USAGE: usize = 64 * 1024 * 1024;
+
+struct ScriptSessionRequest {
+    code: String,
+    reply: mpsc::Sender< serde_json::Value >
 }
 
-fn strip_template( input: &str ) -> String {
-    let mut out = String::new();
-    let mut buffered = String::new();
-    let mut depth = 0;
-    for ch in input.chars() {
-        if ch == '<' {
-            if out.ends_with( "operator" ) {
-                out.push( ch );
-                continue
-            }
+/// A script engine which keeps its variables between evaluations.
+///
+/// The engine can't be shared between threads, so every session has its own
+/// thread which exits as soon as the session is dropped.
+struct ScriptSession {
+    /// Every data file which the session can access, and which is kept in memory until the session is gone.
+    data_ids: Vec< DataId >,
+    sender: Mutex< mpsc::Sender< ScriptSessionRequest > >,
+    last_used: Mutex< Instant >
+}
 
-            if depth == 0 {
-                buffered.clear();
-                out.push( ch );
-            } else {
-                buffered.push( ch );
-            }
+impl ScriptSession {
+    fn eval( &self, code: String ) -> Option< serde_json::Value > {
+        *self.last_used.lock() = Instant::now();
 
-            depth += 1;
-            continue;
-        }
+        let (reply, receiver) = mpsc::channel();
+        self.sender.lock().send( ScriptSessionRequest { code, reply } ).ok()?;
+        let result = receiver.recv().ok();
 
-        if depth > 0 {
-            if ch == '>' {
-                depth -= 1;
-                if depth == 0 {
-                    out.push_str( "..." );
-                    out.push( ch );
-                    buffered.clear();
-                }
+        *self.last_used.lock() = Instant::now();
+        result
+    }
+}
 
-                continue;
-            }
-            buffered.push( ch );
-            continue;
-        }
+#[derive(Default)]
+struct ScriptSessionCollection {
+    by_id: HashMap< u64, Arc< ScriptSession > >,
+    next_id: u64
+}
 
-        out.push( ch );
+impl ScriptSessionCollection {
+    fn purge_idle( &mut self, idle_timeout: Duration ) {
+        self.by_id.retain( |_, session| session.last_used.lock().elapsed() <= idle_timeout );
     }
 
-    out.push_str( &buffered );
-    out
-}
+    fn get( &mut self, id: u64, idle_timeout: Duration ) -> Option< Arc< ScriptSession > > {
+        self.purge_idle( idle_timeout );
+        self.by_id.get( &id ).cloned()
+    }
 
-fn get_frame< 'a >( data: &'a Data, format: &protocol::BacktraceFormat, frame: &Frame ) -> protocol::Frame< 'a > {
-    let mut function = frame.function().map( |id| Cow::Borrowed( data.interner().resolve( id ).unwrap() ) );
-    if format.strip_template_args.unwrap_or( false ) {
-        function = function.map( |function| strip_template( &function ).into() );
+    fn add_session( &mut self, data_ids: Vec< DataId >, sender: mpsc::Sender< ScriptSessionRequest >, idle_timeout: Duration ) -> Option< u64 > {
+        self.purge_idle( idle_timeout );
+        if self.by_id.len() >= MAXIMUM_SCRIPT_SESSION_COUNT {
+            return None;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.by_id.insert( id, Arc::new( ScriptSession {
+            data_ids,
+            sender: Mutex::new( sender ),
+            last_used: Mutex::new( Instant::now() )
+        }));
+
+        Some( id )
     }
 
-    protocol::Frame {
-        address: frame.address().raw(),
-        address_s: format!( "{:016X}", frame.address().raw() ),
-        count: frame.count(),
-        library: frame.library().map( |id| data.interner().resolve( id ).unwrap() ),
-        function,
-        raw_function: frame.raw_function().map( |id| data.interner().resolve( id ).unwrap() ),
-        source: frame.source().map( |id| data.interner().resolve( id ).unwrap() ),
-        line: frame.line(),
-        column: frame.column(),
-        is_inline: frame.is_inline()
+    /// Removes every session which can access the given data; they exit once they're done with whatever they're running.
+    fn remove_sessions_for( &mut self, data_id: DataId ) {
+        self.by_id.retain( |_, session| !session.data_ids.contains( &data_id ) );
     }
 }
 
-impl protocol::ResponseMetadata {
-    fn new( data: &Data ) -> Self {
-        protocol::ResponseMetadata {
-            id: format!( "{}", data.id() ),
-            executable: data.executable().to_owned(),
-            architecture: data.architecture().to_owned(),
-            final_allocated: data.total_allocated() - data.total_freed(),
-            final_allocated_count: data.total_allocated_count() - data.total_freed_count(),
-            runtime: (data.last_timestamp() - data.initial_timestamp()).into(),
-            unique_backtrace_count: data.unique_backtrace_count() as u64,
-            maximum_backtrace_depth: data.maximum_backtrace_depth(),
-            timestamp: data.initial_timestamp().into()
-        }
+#[test]
+fn test_script_session_collection() {
+    let mut sessions = ScriptSessionCollection::default();
+    let timeout = Duration::from_secs( 60 );
+    let mut receivers = Vec::new();
+    let mut ids = Vec::new();
+    for _ in 0..MAXIMUM_SCRIPT_SESSION_COUNT {
+        let (sender, receiver) = mpsc::channel();
+        receivers.push( receiver );
+        ids.push( sessions.add_session( vec![ DataId::new( 1, 2 ) ], sender, timeout ).unwrap() );
     }
+
+    let (sender, _) = mpsc::channel();
+    assert!( sessions.add_session( vec![ DataId::new( 1, 2 ) ], sender.clone(), timeout ).is_none() );
+    assert!( sessions.get( ids[ 0 ], timeout ).is_some() );
+
+    *sessions.by_id[ &ids[ 1 ] ].last_used.lock() -= Duration::from_secs( 120 );
+    assert!( sessions.get( ids[ 1 ], timeout ).is_none() );
+    let id = sessions.add_session( vec![ DataId::new( 1, 2 ), DataId::new( 3, 4 ) ], sender, timeout ).unwrap();
+
+    // A session also keeps in memory the other data files which it can access.
+    sessions.remove_sessions_for( DataId::new( 3, 4 ) );
+    assert!( sessions.get( id, timeout ).is_none() );
+    assert!( sessions.get( ids[ 0 ], timeout ).is_some() );
 }
 
-fn handler_list( req: HttpRequest ) -> HttpResponse {
-    let list: Vec< _ > = req.state().data.values().map( |data| {
-        protocol::ResponseMetadata::new( data )
-    }).collect();
+#[test]
+fn test_script_session() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    HttpResponse::Ok().json( list )
+    let id = DataId::new( 1, 2 );
+    let (state, mut app) = test_app( vec![ load_test_data( id, Vec::new() ) ] );
+
+    let request = test::TestRequest::post().uri( "/data/last/script_session" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let session_id = response[ "id" ].as_u64().unwrap();
+
+    let request = test::TestRequest::post().uri( &format!( "/script_session/{}/eval", session_id ) ).set_payload( "let x = 40;" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "status" ], "ok", "{}", response );
+
+    let request = test::TestRequest::post().uri( &format!( "/script_session/{}/eval", session_id ) ).set_payload( "println( (x + 2).to_string() );" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "output" ][ 0 ][ "value" ], "42", "{}", response );
+
+    let request = test::TestRequest::post().uri( &format!( "/script_session/{}/eval", session_id + 1 ) ).set_payload( "" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
+
+    // The session is gone along with its data.
+    assert!( state.remove_data( id ) );
+    let request = test::TestRequest::post().uri( &format!( "/script_session/{}/eval", session_id ) ).set_payload( "" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
 }
 
-fn get_fragmentation_timeline( data: &Data ) -> protocol::ResponseFragmentationTimeline {
-    #[inline(always)]
-    fn is_matched( allocation: &Allocation ) -> bool {
-        allocation.in_main_arena() && !allocation.is_mmaped() && !allocation.is_jemalloc()
-    }
+fn vec_size_in_bytes< T >( vec: &Vec< T > ) -> usize {
+    vec.capacity() * std::mem::size_of::< T >()
+}
 
-    let maximum_len = (data.last_timestamp().as_secs() - data.initial_timestamp().as_secs()) as usize;
-    let mut xs = Vec::with_capacity( maximum_len );
-    let mut x = (-1_i32) as u64;
+fn timeline_size_in_bytes( timeline: &protocol::ResponseTimeline ) -> usize {
+    vec_size_in_bytes( &timeline.xs ) +
+    vec_size_in_bytes( &timeline.size_delta ) +
+    vec_size_in_bytes( &timeline.count_delta ) +
+    vec_size_in_bytes( &timeline.allocated_size ) +
+    vec_size_in_bytes( &timeline.allocated_count ) +
+    vec_size_in_bytes( &timeline.allocations ) +
+    vec_size_in_bytes( &timeline.deallocations ) +
+    timeline.markers.as_ref().map( vec_size_in_bytes ).unwrap_or( 0 ) +
+    timeline.xs_wall_clock.as_ref().and_then( |xs| xs.as_ref() ).map( |xs| xs.iter().map( |x| x.len() ).sum::< usize >() + vec_size_in_bytes( xs ) ).unwrap_or( 0 )
+}
 
-    let mut current_used_address_space = 0;
-    let mut fragmentation = Vec::with_capacity( maximum_len );
-    let mut address_map: BTreeMap< u64, i64 > = BTreeMap::new();
-    let mut current_address_min = std::u64::MAX;
-    let mut current_address_max = 0;
+/// An LRU cache which makes sure that a given value is never computed twice concurrently.
+struct ComputeCache< K, V > where K: Clone + Eq + std::hash::Hash {
+    inner: Mutex< ComputeCacheInner< K, V > >,
+    condvar: Condvar,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Roughly how many bytes a value occupies.
+    weight: fn( &V ) -> usize,
+    /// If set the cache is bounded by the total weight of its values instead of by their count.
+    max_weight: Option< usize >
+}
 
-    fn trim_front( address_map: &mut BTreeMap< u64, i64 > ) {
-        while let Some( (&address, &count) ) = address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).next() {
-            if count == 0 {
-                address_map.remove( &address );
-            } else {
-                break;
-            }
+struct ComputeCacheInner< K, V > where K: Clone + Eq + std::hash::Hash {
+    cache: LruCache< K, Arc< V > >,
+    in_flight: std::collections::HashSet< K >,
+    total_weight: usize
+}
+
+impl< K, V > ComputeCache< K, V > where K: Clone + Eq + std::hash::Hash {
+    fn new( capacity: usize ) -> Self {
+        ComputeCache {
+            inner: Mutex::new( ComputeCacheInner {
+                cache: LruCache::new( capacity ),
+                in_flight: Default::default(),
+                total_weight: 0
+            }),
+            condvar: Condvar::new(),
+            hits: AtomicU64::new( 0 ),
+            misses: AtomicU64::new( 0 ),
+            weight: |_| 0,
+            max_weight: None
         }
     }
 
-    fn trim_back( address_map: &mut BTreeMap< u64, i64 > ) {
-        while let Some( (&address, &count) ) = address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).rev().next() {
-            if count == 0 {
-                address_map.remove( &address );
-            } else {
-                break;
-            }
-        }
+    /// Creates a cache which keeps track of the total weight of its values, but is still bounded by their count.
+    fn with_weight( capacity: usize, weight: fn( &V ) -> usize ) -> Self {
+        let mut cache = Self::new( capacity );
+        cache.weight = weight;
+        cache
     }
 
-    fn min( address_map: &BTreeMap< u64, i64 > ) -> u64 {
-        for (&address, &count) in address_map.range( (Unbounded as Bound< u64 >, Unbounded) ) {
-            if count == 0 {
-                continue;
-            }
+    /// Creates a cache which evicts the least recently used values once their total weight exceeds `max_weight`.
+    fn with_max_weight( max_weight: usize, weight: fn( &V ) -> usize ) -> Self {
+        let mut cache = Self::with_weight( 1, weight );
+        cache.inner.get_mut().cache = LruCache::unbounded();
+        cache.max_weight = Some( max_weight );
+        cache
+    }
 
-            return address;
-        }
+    /// Returns how many times a value was found in the cache, and how many times it had to be computed.
+    fn hits_and_misses( &self ) -> (u64, u64) {
+        (self.hits.load( Ordering::Relaxed ), self.misses.load( Ordering::Relaxed ))
+    }
 
-        std::u64::MAX
+    /// Returns the total weight of the cached values.
+    fn total_weight( &self ) -> usize {
+        self.inner.lock().total_weight
     }
 
-    fn max( address_map: &BTreeMap< u64, i64 > ) -> u64 {
-        for (&address, &count) in address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).rev() {
-            if count == 0 {
-                continue;
-            }
+    fn get_or_compute( &self, key: K, callback: impl FnOnce() -> V ) -> Arc< V > {
+        self.try_get_or_compute( key, || Ok( callback() ) ).unwrap_or_else( |never: std::convert::Infallible| match never {} )
+    }
 
-            return address;
+    /// Same as `get_or_compute`, except if the computation fails then nothing is cached.
+    fn try_get_or_compute< E >( &self, key: K, callback: impl FnOnce() -> std::result::Result< V, E > ) -> std::result::Result< Arc< V >, E > {
+        struct InFlightGuard< 'a, K, V > where K: Clone + Eq + std::hash::Hash {
+            parent: &'a ComputeCache< K, V >,
+            key: K
         }
 
-        0
-    }
+        impl< 'a, K, V > Drop for InFlightGuard< 'a, K, V > where K: Clone + Eq + std::hash::Hash {
+            fn drop( &mut self ) {
+                self.parent.inner.lock().in_flight.remove( &self.key );
+                self.parent.condvar.notify_all();
+            }
+        }
 
-    for op in data.operations() {
-        let timestamp = match op {
-            Operation::Allocation { allocation, .. } => {
-                if !is_matched( allocation ) {
-                    continue;
+        {
+            let mut inner = self.inner.lock();
+            loop {
+                if let Some( value ) = inner.cache.get( &key ) {
+                    self.hits.fetch_add( 1, Ordering::Relaxed );
+                    return Ok( value.clone() );
                 }
 
-                let range = allocation.actual_range( &data );
-                *address_map.entry( range.start ).or_insert( 0 ) += 1;
-                *address_map.entry( range.end ).or_insert( 0 ) += 1;
-                current_used_address_space += range.end - range.start;
-
-                if range.start < current_address_min {
-                    current_address_min = range.start;
+                if !inner.in_flight.contains( &key ) {
+                    break;
                 }
 
-                if range.end > current_address_max {
-                    current_address_max = range.end;
-                }
+                self.condvar.wait( &mut inner );
+            }
 
-                allocation.timestamp
-            },
-            Operation::Deallocation { allocation, deallocation, .. } => {
-                if !is_matched( allocation ) {
-                    continue;
-                }
+            inner.in_flight.insert( key.clone() );
+        }
 
-                let range = allocation.actual_range( &data );
-                *address_map.entry( range.start ).or_insert( 0 ) -= 1;
-                *address_map.entry( range.end ).or_insert( 0 ) -= 1;
-                current_used_address_space -= range.end - range.start;
+        self.misses.fetch_add( 1, Ordering::Relaxed );
 
-                if range.start == current_address_min {
-                    trim_front( &mut address_map );
-                    current_address_min = min( &address_map );
-                }
+        let guard = InFlightGuard { parent: self, key };
+        let value = Arc::new( callback()? );
 
-                if range.end == current_address_max {
-                    trim_back( &mut address_map );
-                    current_address_max = max( &address_map );
-                }
+        let mut inner = self.inner.lock();
+        let weight = (self.weight)( &value );
+        if let Some( max_weight ) = self.max_weight {
+            if weight > max_weight {
+                return Ok( value );
+            }
 
-                deallocation.timestamp
-            },
-            Operation::Reallocation { new_allocation, old_allocation, .. } => {
-                if !is_matched( new_allocation ) && !is_matched( old_allocation ) {
-                    continue;
+            while inner.total_weight + weight > max_weight {
+                match inner.cache.pop_lru() {
+                    Some( (_, evicted) ) => inner.total_weight -= (self.weight)( &evicted ),
+                    None => break
                 }
+            }
+        }
 
-                if is_matched( old_allocation ) {
-                    let old_range = old_allocation.actual_range( &data );
-                    *address_map.entry( old_range.start ).or_insert( 0 ) -= 1;
-                    *address_map.entry( old_range.end ).or_insert( 0 ) -= 1;
+        if inner.cache.len() == inner.cache.cap() && !inner.cache.contains( &guard.key ) {
+            if let Some( (_, evicted) ) = inner.cache.pop_lru() {
+                inner.total_weight -= (self.weight)( &evicted );
+            }
+        }
 
-                    current_used_address_space -= old_range.end - old_range.start;
+        inner.total_weight += weight;
+        if let Some( old_value ) = inner.cache.put( guard.key.clone(), value.clone() ) {
+            inner.total_weight -= (self.weight)( &old_value );
+        }
 
-                    if old_range.start == current_address_min {
-                        trim_front( &mut address_map );
-                        current_address_min = min( &address_map );
-                    }
+        Ok( value )
+    }
 
-                    if old_range.end == current_address_max {
-                        trim_back( &mut address_map );
-                        current_address_max = max( &address_map );
-                    }
-                }
+    fn remove_where( &self, predicate: impl Fn( &K ) -> bool ) {
+        let mut inner = self.inner.lock();
+        let keys: Vec< K > = inner.cache.iter().map( |(key, _)| key ).filter( |key| predicate( key ) ).cloned().collect();
+        for key in keys {
+            if let Some( value ) = inner.cache.pop( &key ) {
+                inner.total_weight -= (self.weight)( &value );
+            }
+        }
+    }
+}
 
-                if is_matched( new_allocation ) {
-                    let new_range = new_allocation.actual_range( &data );
-                    *address_map.entry( new_range.start ).or_insert( 0 ) += 1;
-                    *address_map.entry( new_range.end ).or_insert( 0 ) += 1;
-                    current_used_address_space += new_range.end - new_range.start;
+#[test]
+fn test_compute_cache_computes_only_once() {
+    let cache: Arc< ComputeCache< u32, u32 > > = Arc::new( ComputeCache::new( 4 ) );
+    let counter = Arc::new( AtomicUsize::new( 0 ) );
+    let handles: Vec< _ > = (0..4).map( |_| {
+        let cache = cache.clone();
+        let counter = counter.clone();
+        thread::spawn( move || {
+            *cache.get_or_compute( 1, || {
+                counter.fetch_add( 1, Ordering::SeqCst );
+                thread::sleep( std::time::Duration::from_millis( 50 ) );
+                10
+            })
+        })
+    }).collect();
 
-                    if new_range.start < current_address_min {
-                        current_address_min = new_range.start;
-                    }
+    for handle in handles {
+        assert_eq!( handle.join().unwrap(), 10 );
+    }
 
-                    if new_range.end > current_address_max {
-                        current_address_max = new_range.end;
-                    }
-                }
+    assert_eq!( counter.load( Ordering::SeqCst ), 1 );
+    assert_eq!( *cache.get_or_compute( 2, || 20 ), 20 );
 
-                new_allocation.timestamp
-            }
-        };
+    assert_eq!( cache.try_get_or_compute( 3, || Err( () ) ), Err( () ) );
+    assert_eq!( *cache.get_or_compute( 3, || 30 ), 30 );
+}
 
-        let timestamp = timestamp.as_secs();
-        if timestamp != x {
-            if x != (-1_i32 as u64) && x + 1 != timestamp {
-                let last_fragmentation = fragmentation.last().cloned().unwrap();
+#[test]
+fn test_compute_cache_evicts_by_weight() {
+    let cache: ComputeCache< u32, Vec< u8 > > = ComputeCache::with_max_weight( 10, |value| value.len() );
+    cache.get_or_compute( 1, || vec![ 0; 4 ] );
+    cache.get_or_compute( 2, || vec![ 0; 4 ] );
+    cache.get_or_compute( 1, || unreachable!() );
+
+    // The least recently used value is evicted to make room.
+    cache.get_or_compute( 3, || vec![ 0; 4 ] );
+    assert_eq!( cache.hits_and_misses(), (1, 3) );
+    cache.get_or_compute( 1, || unreachable!() );
+    cache.get_or_compute( 3, || unreachable!() );
+    assert_eq!( cache.get_or_compute( 2, || vec![ 1 ] ).len(), 1 );
+
+    // Values which would never fit aren't cached at all.
+    assert_eq!( cache.get_or_compute( 4, || vec![ 0; 11 ] ).len(), 11 );
+    assert_eq!( cache.get_or_compute( 4, Vec::new ).len(), 0 );
+
+    cache.remove_where( |&key| key != 2 );
+    assert_eq!( cache.inner.lock().total_weight, 1 );
+}
 
-                xs.push( (x + 1) * 1000 );
-                fragmentation.push( last_fragmentation );
+#[test]
+fn test_compute_cache_tracks_weight_when_evicting_by_count() {
+    let cache: ComputeCache< u32, Vec< u8 > > = ComputeCache::with_weight( 2, |value| value.len() );
+    cache.get_or_compute( 1, || vec![ 0; 4 ] );
+    cache.get_or_compute( 2, || vec![ 0; 5 ] );
+    assert_eq!( cache.total_weight(), 9 );
 
-                if x + 2 != timestamp {
-                    xs.push( (timestamp - 1) * 1000 );
-                    fragmentation.push( last_fragmentation );
-                }
-            }
+    cache.get_or_compute( 3, || vec![ 0; 6 ] );
+    assert_eq!( cache.total_weight(), 11 );
 
-            x = timestamp;
-            xs.push( x * 1000 );
-            fragmentation.push( 0 );
+    cache.remove_where( |_| true );
+    assert_eq!( cache.total_weight(), 0 );
+}
+
+struct State {
+    data: RwLock< HashMap< DataId, Arc< Data > > >,
+    data_ids: RwLock< Vec< DataId > >,
+    /// Every file given on the command line, including the ones which are still being loaded.
+    inputs: RwLock< Vec< Arc< loading::InputFile > > >,
+    /// The data which is still being received from a running process.
+    live: Mutex< std::collections::HashSet< DataId > >,
+    /// Computed when the data is added so that listing the data never has to compute anything;
+    /// always modified together with `data`.
+    summaries: RwLock< HashMap< DataId, Arc< DataSummary > > >,
+    aliases: Mutex< aliases::Aliases >,
+    /// Extra debug symbols used when loading the data files.
+    debug_symbols: Vec< PathBuf >,
+    /// Held while extra debug symbols are being applied, so that two uploads can't overwrite each other's symbols.
+    symbolication_lock: Mutex< () >,
+    /// Whenever to compute the most commonly requested analyses in the background right after loading.
+    precompute: bool,
+    /// The canonicalized directories from which the data files can be loaded on request.
+    data_dirs: Vec< PathBuf >,
+    /// Where the loaded data is cached so that it can be loaded faster the next time; if `None` it isn't cached.
+    cache_dir: Option< PathBuf >,
+    /// How many bytes the caches in the `cache_dir` can take at most.
+    cache_size_limit: u64,
+    /// Whenever the allocations of the loaded data are kept in memory mapped files instead of in memory.
+    low_memory: bool,
+    allocation_group_cache: ComputeCache< AllocationGroupsKey, AllocationGroups >,
+    timeline_cache: ComputeCache< DataId, protocol::ResponseTimeline >,
+    /// The timelines of the temporary allocations, keyed by their maximum lifetime.
+    temporary_timeline_cache: ComputeCache< (DataId, Option< u64 >), protocol::ResponseTimeline >,
+    fragmentation_timeline_cache: ComputeCache< DataId, protocol::ResponseFragmentationTimeline >,
+    library_by_backtrace_cache: Mutex< LruCache< DataId, Arc< Vec< Option< StringId > > > > >,
+    backtraces_by_address_cache: ComputeCache< DataId, Vec< (u64, BacktraceId) > >,
+    mmap_state_cache: ComputeCache< DataId, mmap_state::MmapState >,
+    allocations_by_address_cache: ComputeCache< DataId, Vec< AllocationRangeEntry > >,
+    backtrace_clusters_cache: ComputeCache< (DataId, u64), Vec< BacktraceCluster > >,
+    /// Which allocations were matched by the recently used filters; shared by every endpoint which takes a filter.
+    match_cache: ComputeCache< MatchCacheKey, filter::MatchBitmap >,
+    precompute_progress: Mutex< HashMap< DataId, Arc< AtomicUsize > > >,
+    /// Where the data came from, and how long it took to load it.
+    provenance: Mutex< HashMap< DataId, loading::Provenance > >,
+    source_prefix_map: Vec< (String, String) >,
+    source_files: Arc< SourceFiles >,
+    generated_files: Mutex< GeneratedFilesCollection >,
+    script_jobs: Mutex< ScriptJobCollection >,
+    script_limits: ScriptLimits,
+    script_sessions: Mutex< ScriptSessionCollection >,
+    script_session_idle_timeout: Duration,
+    script_library: ScriptLibrary,
+    scripts_disabled: bool,
+    /// Bumped every time any of the data files is modified, which invalidates every `ETag`.
+    data_generation: AtomicUsize,
+    etag_salt: String,
+    /// The token which every request must contain; if `None` then no authentication is required.
+    auth_token: Option< String >,
+    cors_origins: CorsOrigins,
+    /// If `None` then the metrics are disabled.
+    metrics: Option< metrics::Metrics >,
+    /// Runs the handlers which stream their responses from a background thread.
+    heavy_workers: worker_pool::WorkerPool,
+    /// Whenever every request should be logged at the `info` level.
+    log_requests: bool,
+    /// The requests which take longer than this are logged as warnings.
+    slow_request_threshold: Option< Duration >,
+    /// The deadline of the requests which don't specify their own.
+    default_deadline: Option< Duration >,
+    /// The path under which every route is available; empty if the server is available at `/`.
+    base_path: String
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            data: RwLock::new( HashMap::new() ),
+            data_ids: RwLock::new( Vec::new() ),
+            inputs: RwLock::new( Vec::new() ),
+            live: Mutex::new( Default::default() ),
+            aliases: Mutex::new( Default::default() ),
+            debug_symbols: Vec::new(),
+            symbolication_lock: Mutex::new( () ),
+            precompute: false,
+            data_dirs: Vec::new(),
+            cache_dir: None,
+            cache_size_limit: u64::MAX,
+            low_memory: false,
+            allocation_group_cache: ComputeCache::with_weight( 4, |groups| groups.allocations_by_backtrace.memory_usage() ),
+            timeline_cache: ComputeCache::with_weight( 16, timeline_size_in_bytes ),
+            temporary_timeline_cache: ComputeCache::with_weight( 16, timeline_size_in_bytes ),
+            fragmentation_timeline_cache: ComputeCache::with_weight( 16, |timeline| (timeline.xs.capacity() + timeline.fragmentation.capacity()) * 8 ),
+            library_by_backtrace_cache: Mutex::new( LruCache::new( 4 ) ),
+            backtraces_by_address_cache: ComputeCache::with_weight( 4, vec_size_in_bytes ),
+            mmap_state_cache: ComputeCache::with_weight( 4, mmap_state::MmapState::size_in_bytes ),
+            allocations_by_address_cache: ComputeCache::with_weight( 4, vec_size_in_bytes ),
+            summaries: RwLock::new( HashMap::new() ),
+            backtrace_clusters_cache: ComputeCache::with_weight( 4, |clusters| {
+                vec_size_in_bytes( clusters ) + clusters.iter().map( |cluster| vec_size_in_bytes( &cluster.members ) ).sum::< usize >()
+            }),
+            match_cache: ComputeCache::with_max_weight( 128 * 1024 * 1024, filter::MatchBitmap::size_in_bytes ),
+            precompute_progress: Mutex::new( HashMap::new() ),
+            provenance: Mutex::new( HashMap::new() ),
+            source_prefix_map: Vec::new(),
+            source_files: Arc::new( SourceFiles::new( &[] ) ),
+            generated_files: Default::default(),
+            script_jobs: Default::default(),
+            script_limits: Default::default(),
+            script_sessions: Default::default(),
+            script_session_idle_timeout: Duration::from_secs( 30 * 60 ),
+            script_library: ScriptLibrary::new( None ),
+            scripts_disabled: false,
+            data_generation: AtomicUsize::new( 0 ),
+            etag_salt: etag::new_salt(),
+            auth_token: None,
+            cors_origins: CorsOrigins::Any,
+            metrics: None,
+            heavy_workers: worker_pool::WorkerPool::new( worker_pool::default_worker_count(), MAXIMUM_QUEUED_HEAVY_REQUESTS ),
+            log_requests: false,
+            slow_request_threshold: None,
+            default_deadline: None,
+            base_path: String::new()
         }
+    }
 
-        let range = if current_address_max == 0 {
-            0
+    fn add_data( &self, data: Data ) {
+        if self.data.read().contains_key( &data.id() ) {
+            return;
+        }
+
+        let summary = Arc::new( get_data_summary( &data ) );
+        let mut map = self.data.write();
+        if map.contains_key( &data.id() ) {
+            return;
+        }
+
+        self.data_ids.write().push( data.id() );
+        self.summaries.write().insert( data.id(), summary );
+        map.insert( data.id(), Arc::new( data ) );
+    }
+
+    /// Strips the base path from the request's path; returns `None` if it's not under the base path.
+    fn strip_base_path< 'a >( &self, path: &'a str ) -> Option< &'a str > {
+        if self.base_path.is_empty() {
+            return Some( path );
+        }
+
+        let path = path.strip_prefix( self.base_path.as_str() )?;
+        if path.is_empty() || path.starts_with( '/' ) {
+            Some( path )
         } else {
-            current_address_max - current_address_min
-        };
+            None
+        }
+    }
 
-        *fragmentation.last_mut().unwrap() = range - current_used_address_space;
+    fn get_data( &self, id: DataId ) -> Option< Arc< Data > > {
+        self.data.read().get( &id ).cloned()
     }
 
-    protocol::ResponseFragmentationTimeline {
-        xs,
-        fragmentation
+    fn get_summary( &self, id: DataId ) -> Option< Arc< DataSummary > > {
+        self.summaries.read().get( &id ).cloned()
     }
-}
 
-fn handler_fragmentation_timeline( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let response = get_fragmentation_timeline( data );
-    Ok( HttpResponse::Ok().json( response ) )
-}
+    fn is_live( &self, id: DataId ) -> bool {
+        self.live.lock().contains( &id )
+    }
 
-fn build_timeline( data: &Data, ops: &[OperationId] ) -> protocol::ResponseTimeline {
-    let timeline = cli_core::build_timeline( data, data.initial_timestamp(), data.last_timestamp(), ops );
+    /// Adds or replaces the data which is still being received; returns `false` if a data
+    /// file with the same ID which isn't live is already loaded.
+    ///
+    /// Once `is_finished` is set the data becomes a normal one and can't be replaced anymore.
+    fn update_live_data( &self, data: Data, is_finished: bool ) -> bool {
+        let id = data.id();
+        let summary = Arc::new( get_data_summary( &data ) );
+        {
+            let mut map = self.data.write();
+            let mut live = self.live.lock();
+            if map.contains_key( &id ) && !live.contains( &id ) {
+                return false;
+            }
 
-    let mut xs = Vec::with_capacity( timeline.len() );
-    let mut size_delta = Vec::with_capacity( timeline.len() );
-    let mut count_delta = Vec::with_capacity( timeline.len() );
-    let mut allocated_size = Vec::with_capacity( timeline.len() );
-    let mut allocated_count = Vec::with_capacity( timeline.len() );
-    let mut allocations = Vec::with_capacity( timeline.len() );
-    let mut deallocations = Vec::with_capacity( timeline.len() );
+            self.summaries.write().insert( id, summary );
+            if map.insert( id, Arc::new( data ) ).is_none() {
+                self.data_ids.write().push( id );
+            }
 
-    let mut last_size = 0;
-    let mut last_count = 0;
-    for point in timeline {
-        xs.push( point.timestamp / 1000 );
-        size_delta.push( point.memory_usage as i64 - last_size );
-        count_delta.push( point.allocations as i64 - last_count );
-        allocated_size.push( point.memory_usage );
-        allocated_count.push( point.allocations );
-        allocations.push( point.allocations_per_time as u32 );
-        deallocations.push( point.deallocations_per_time as u32 );
+            if is_finished {
+                live.remove( &id );
+            } else {
+                live.insert( id );
+            }
+        }
 
-        last_size = point.memory_usage as i64;
-        last_count = point.allocations as i64;
+        self.invalidate_computed( id );
+        true
     }
 
-    protocol::ResponseTimeline {
-        xs,
-        size_delta,
-        count_delta,
-        allocated_size,
-        allocated_count,
-        allocations,
-        deallocations
+    /// Replaces the data with its modified copy, unless it was removed or replaced in the meantime.
+    fn replace_data( &self, old: &Arc< Data >, new: Data ) -> bool {
+        let id = new.id();
+        let summary = Arc::new( get_data_summary( &new ) );
+        {
+            let mut map = self.data.write();
+            match map.get_mut( &id ) {
+                Some( data ) if Arc::ptr_eq( data, old ) => *data = Arc::new( new ),
+                _ => return false
+            }
+
+            self.summaries.write().insert( id, summary );
+        }
+
+        self.invalidate_computed( id );
+        true
     }
-}
 
-fn handler_timeline( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let timeline = build_timeline( &data, data.operation_ids() );
-    Ok( HttpResponse::Ok().json( timeline ) )
-}
+    /// Throws away everything that was computed for the given data file.
+    fn invalidate_computed( &self, id: DataId ) {
+        self.data_generation.fetch_add( 1, Ordering::SeqCst );
+        self.allocation_group_cache.remove_where( |key| key.data_id == id );
+        self.timeline_cache.remove_where( |&key| key == id );
+        self.temporary_timeline_cache.remove_where( |&(key, _)| key == id );
+        self.fragmentation_timeline_cache.remove_where( |&key| key == id );
+        self.backtraces_by_address_cache.remove_where( |&key| key == id );
+        self.mmap_state_cache.remove_where( |&key| key == id );
+        self.allocations_by_address_cache.remove_where( |&key| key == id );
+        self.backtrace_clusters_cache.remove_where( |&(key, _)| key == id );
+        self.match_cache.remove_where( |key| key.data_id == id );
+        self.library_by_backtrace_cache.lock().pop( &id );
+    }
 
-fn handler_timeline_leaked( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let ops: Vec< _ > = data.operation_ids().par_iter().flat_map( |op| {
-        let allocation = data.get_allocation( op.id() );
-        if allocation.deallocation.is_some() {
-            None
-        } else {
-            Some( OperationId::new_allocation( op.id() ) )
+    /// Removes the data file along with everything that was computed for it.
+    ///
+    /// The memory is freed once the requests which are still using it finish.
+    fn remove_data( &self, id: DataId ) -> bool {
+        {
+            let mut map = self.data.write();
+            if map.remove( &id ).is_none() {
+                return false;
+            }
+
+            self.data_ids.write().retain( |&existing_id| existing_id != id );
+            self.summaries.write().remove( &id );
         }
-    }).collect();
 
-    let timeline = build_timeline( &data, &ops );
-    Ok( HttpResponse::Ok().json( timeline ) )
-}
+        self.inputs.write().retain( |input| input.status() != loading::LoadStatus::Loaded( id ) );
+        self.live.lock().remove( &id );
+        self.invalidate_computed( id );
+        self.precompute_progress.lock().remove( &id );
+        self.provenance.lock().remove( &id );
+        self.generated_files.lock().remove_files_for( id );
+        self.script_sessions.lock().remove_sessions_for( id );
 
-fn prefiltered_allocation_ids< 'a >(
-    data: &'a Data,
-    sort_by: protocol::AllocSortBy,
-    _filter: &AllocationFilter
- ) -> &'a [AllocationId] {
-    // TODO: Use the filter to narrow down the range.
-    match sort_by {
-        protocol::AllocSortBy::Timestamp => data.alloc_sorted_by_timestamp( None, None ),
-        protocol::AllocSortBy::Address => data.alloc_sorted_by_address( None, None ),
-        protocol::AllocSortBy::Size => data.alloc_sorted_by_size( None, None )
+        true
+    }
+
+    fn last_id( &self ) -> Option< DataId > {
+        self.data_ids.read().last().cloned()
+    }
+
+    fn record_script_execution< T >( &self, result: &Result< T, cli_core::script::EvalError > ) {
+        if let Some( ref metrics ) = self.metrics {
+            metrics.record_script_execution( result.is_err() );
+        }
+    }
+
+    /// Returns a snapshot of every loaded data file, in the order they were loaded.
+    fn datasets( &self ) -> Vec< Arc< Data > > {
+        let map = self.data.read();
+        self.data_ids.read().iter().filter_map( |id| map.get( id ).cloned() ).collect()
     }
 }
 
-fn allocations_iter< 'a >(
-    data: &'a Data,
-    array: &'a [AllocationId],
-    order: protocol::Order,
-    filter: AllocationFilter
-) -> Box< dyn DoubleEndedIterator< Item = (AllocationId, &'a Allocation) > + 'a > {
-    match order {
-        protocol::Order::Asc => Box::new( array.iter()
-            .map( move |&id| (id, data.get_allocation( id )) )
-            .filter( move |(id, allocation)| filter.try_match( data, *id, allocation ) )
-        ),
-        protocol::Order::Dsc => Box::new( array.iter().rev()
-            .map( move |&id| (id, data.get_allocation( id )) )
-            .filter( move |(id, allocation)| filter.try_match( data, *id, allocation ) )
-        )
+type StateRef = Arc< State >;
+
+trait StateGetter {
+    fn state( &self ) -> &StateRef;
+}
+
+impl StateGetter for HttpRequest {
+    fn state( &self ) -> &StateRef {
+        self.app_data::< StateRef >().unwrap()
     }
 }
 
-fn timestamp_to_fraction( data: &Data, timestamp: Timestamp ) -> f32 {
-    let relative = timestamp - data.initial_timestamp();
-    let range = data.last_timestamp() - data.initial_timestamp();
-    (relative.as_usecs() as f64 / range.as_usecs() as f64) as f32
+/// Deserializes the parameters either from the query string, or from the body if it's a POST request.
+fn query< T: serde::de::DeserializeOwned >( req: &HttpRequest ) -> Result< T > {
+    let result = match req.extensions().get::< BodyParams >() {
+        Some( params ) => serde_urlencoded::from_str::<T>( &params.0 ),
+        None => serde_urlencoded::from_str::<T>( req.query_string() )
+    };
+
+    result.map_err( |error| ApiError::bad_request( format!( "invalid query: {}", error ) ).into() )
 }
 
-fn get_allocations< 'a >(
-    data: &'a Arc< Data >,
-    backtrace_format: protocol::BacktraceFormat,
-    params: protocol::RequestAllocations,
-    filter: crate::filter::AllocationFilter
-) -> protocol::ResponseAllocations< impl Serialize + 'a > {
-    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
-    let skip = params.skip.unwrap_or( 0 ) as usize;
-    let sort_by = params.sort_by.unwrap_or( protocol::AllocSortBy::Timestamp );
-    let order = params.order.unwrap_or( protocol::Order::Asc );
+/// Handles a POST request in exactly the same way as the equivalent GET request,
+/// except the parameters are taken from a JSON body instead of the query string.
+fn post_handler( req: HttpRequest, body: web::Bytes, handler: fn( HttpRequest ) -> Result< HttpResponse > ) -> Result< HttpResponse > {
+    let params = body_params::body_to_query( &body, req.query_string() ).map_err( ApiError::bad_request )?;
+    req.extensions_mut().insert( BodyParams( params ) );
+    handler( req )
+}
 
-    let allocation_ids = prefiltered_allocation_ids( data, sort_by, &filter );
-    let total_count =
-        allocation_ids
-        .par_iter()
-        .filter( |&&id| filter.try_match( data, id, data.get_allocation( id ) ) )
-        .count() as u64;
+/// Parses the backtrace format from the query string and fills it in with the server-wide settings.
+fn get_backtrace_format( req: &HttpRequest ) -> Result< protocol::BacktraceFormat > {
+    let mut format: protocol::BacktraceFormat = query( req )?;
+    let server_rules = &req.state().source_prefix_map;
+    if !server_rules.is_empty() {
+        format.source_prefix_map.get_or_insert_with( Default::default ).0.extend( server_rules.iter().cloned() );
+    }
 
-    let allocations = move || {
-        let backtrace_format = backtrace_format.clone();
-        let filter = filter.clone();
+    if format.include_source_snippet.unwrap_or( false ) && !req.state().source_files.is_empty() {
+        format.source_files = Some( req.state().source_files.clone() );
+    }
 
-        allocations_iter( data, allocation_ids, order, filter )
-            .skip( skip )
-            .take( remaining )
-            .map( move |(allocation_id, allocation)| {
-                let backtrace = data.get_backtrace( allocation.backtrace ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
-                let chain = data.get_chain_by_any_allocation( allocation_id );
-                protocol::Allocation {
-                    id: allocation_id.raw(),
-                    address: allocation.pointer,
-                    address_s: format!( "{:016X}", allocation.pointer ),
-                    timestamp: allocation.timestamp.into(),
-                    timestamp_relative: (allocation.timestamp - data.initial_timestamp()).into(),
-                    timestamp_relative_p: timestamp_to_fraction( data, allocation.timestamp ),
-                    thread: allocation.thread,
-                    size: allocation.size,
-                    backtrace_id: allocation.backtrace.raw(),
-                    deallocation: allocation.deallocation.as_ref().map( |deallocation| {
-                        protocol::Deallocation {
-                            timestamp: deallocation.timestamp.into(),
-                            thread: deallocation.thread
-                        }
-                    }),
-                    backtrace,
-                    in_main_arena: !allocation.in_non_main_arena(),
-                    is_mmaped: allocation.is_mmaped(),
-                    is_jemalloc: allocation.is_jemalloc(),
-                    extra_space: allocation.extra_usable_space,
-                    chain_lifetime: chain.lifetime( data ).map( |lifetime| lifetime.into() ),
-                    position_in_chain: allocation.position_in_chain,
-                    chain_length: chain.length
-                }
-            })
-    };
+    Ok( format )
+}
 
-    protocol::ResponseAllocations {
-        allocations: StreamingSerializer::new( allocations ),
-        total_count
+/// Fails with a 403 if the server was started with scripting disabled.
+fn check_scripts_enabled( req: &HttpRequest ) -> std::result::Result< (), ApiError > {
+    if req.state().scripts_disabled {
+        return Err( ApiError::forbidden( "scripts are disabled on this server" ) );
     }
+
+    Ok(())
 }
 
-fn handler_allocations( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let params: protocol::RequestAllocations = query( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter, &custom_filter )?;
-    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+/// Returns the deadline of the request, which is either given through `deadline_ms` or is the server-wide default.
+fn get_deadline( req: &HttpRequest ) -> Result< Deadline > {
+    if let Some( deadline ) = req.extensions().get::< Deadline >() {
+        return Ok( deadline.clone() );
+    }
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let response = get_allocations( &data, backtrace_format, params, filter );
-        let _ = serde_json::to_writer( tx, &response );
-    })?;
+    let params: protocol::RequestDeadline = query( req )?;
+    let deadline = match params.deadline_ms.map( Duration::from_millis ).or( req.state().default_deadline ) {
+        Some( duration ) => Deadline::after( duration ),
+        None => Deadline::none()
+    };
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+    // Cached so that every part of the request shares the same deadline, and also so that
+    // `deadline::middleware` can tell whether it was exceeded.
+    req.extensions_mut().insert( deadline.clone() );
+    Ok( deadline )
 }
 
-fn get_allocation_group_data< 'a, I >( data: &Data, iter: I ) -> protocol::AllocationGroupData
-    where I: ParallelIterator< Item = &'a Allocation >
-{
-    #[derive(Clone)]
-    struct Group {
-        size_sum: u64,
-        min_size: u64,
-        max_size: u64,
-        min_timestamp: Timestamp,
-        max_timestamp: Timestamp,
-        leaked_count: u64,
-        allocated_count: u64
+/// Returns the limits for the scripts run as part of the request, e.g. for custom filters.
+fn script_limits( req: &HttpRequest ) -> Result< ScriptLimits > {
+    Ok( req.state().script_limits.with_deadline( get_deadline( req )? ) )
+}
+
+/// Parses the custom filter from the query string, loading it from the script library if it was given by name.
+/// Fails with a 403 if the server was started with scripting disabled.
+fn get_custom_filter( req: &HttpRequest ) -> Result< protocol::CustomFilter > {
+    let custom_filter: protocol::CustomFilter = query( req )?;
+    Ok( resolve_custom_filter( req, custom_filter )? )
+}
+
+fn resolve_custom_filter( req: &HttpRequest, mut custom_filter: protocol::CustomFilter ) -> std::result::Result< protocol::CustomFilter, ApiError > {
+    if custom_filter.custom_filter.is_some() || custom_filter.custom_filter_name.is_some() {
+        check_scripts_enabled( req )?;
     }
 
-    impl Default for Group {
-        fn default() -> Self {
-            Group {
-                size_sum: 0,
-                min_size: !0,
-                max_size: 0,
-                min_timestamp: Timestamp::max(),
-                max_timestamp: Timestamp::min(),
-                leaked_count: 0,
-                allocated_count: 0
-            }
+    if let Some( ref name ) = custom_filter.custom_filter_name {
+        if custom_filter.custom_filter.is_some() {
+            return Err( ApiError::bad_request( "'custom_filter' and 'custom_filter_name' can't be used at the same time" ) );
         }
+
+        custom_filter.custom_filter = Some( req.state().script_library.get( name )? );
     }
 
-    let group = iter.fold_with(
-        Group::default(),
-        |mut group, allocation| {
-            let size = allocation.size;
-            let timestamp = allocation.timestamp;
+    Ok( custom_filter )
+}
+
+/// Same as `prepare_filter`, except the matching allocations are cached, so that the
+/// same filter used by another endpoint doesn't have to be evaluated again.
+fn prepare_cached_filter( req: &HttpRequest, data: &Arc< Data >, filter: &protocol::AllocFilter, custom_filter: &protocol::CustomFilter ) -> Result< AllocationFilter > {
+    let limits = script_limits( req )?;
+    let custom_source = custom_filter.custom_filter.clone().filter( |source| !source.is_empty() );
+    if custom_source.is_none() && *filter == protocol::AllocFilter::default() {
+        // This matches everything, so there's nothing worth caching.
+        return Ok( prepare_filter( data, filter, custom_filter, &limits )? );
+    }
+
+    let key = MatchCacheKey {
+        data_id: data.id(),
+        filter: filter.clone(),
+        custom_filter: custom_source
+    };
+
+    let matches = req.state().match_cache.try_get_or_compute( key, || {
+        let matches = filter::MatchBitmap::new( data, &prepare_filter( data, filter, custom_filter, &limits )? );
+        if limits.deadline.was_exceeded() {
+            return Err( deadline::deadline_exceeded() );
+        }
+
+        Ok( matches )
+    })?;
+
+    Ok( AllocationFilter::precomputed( matches, limits.deadline ) )
+}
+
+fn get_data_id( req: &HttpRequest ) -> Result< DataId > {
+    let id = req.match_info().get( "id" ).unwrap();
+    parse_data_id( req, id )
+}
+
+/// Returns a 409 along with the loading progress if the data is still being loaded, or a 404 otherwise.
+fn data_not_found( state: &State, id: Option< DataId > ) -> ApiError {
+    let inputs = state.inputs.read();
+    let input = inputs.iter().find( |input| {
+        match id {
+            Some( id ) => input.is_loading( id ),
+            None => input.status() == loading::LoadStatus::Loading
+        }
+    });
+
+    match input {
+        Some( input ) => {
+            ApiError::new( StatusCode::CONFLICT, "data_loading", "the data is still being loaded; try again later" )
+                .with_details( serde_json::json! {{
+                    "path": input.path.to_string_lossy(),
+                    "progress": input.progress() / 100.0
+                }})
+        },
+        None => ApiError::not_found( "data not found" )
+    }
+}
+
+/// Resolves the ID of the data from a path segment, which can be either `last`, an exact ID or an alias.
+fn parse_data_id( req: &HttpRequest, id: &str ) -> Result< DataId > {
+    if id == "last" {
+        return req.state().last_id().ok_or_else( || data_not_found( req.state(), None ).into() );
+    }
+
+    let id: DataId = match id.parse() {
+        Ok( id ) => id,
+        Err( _ ) => req.state().aliases.lock().get( id ).ok_or_else( || ApiError::not_found( "data not found" ) )?
+    };
+
+    if !req.state().data.read().contains_key( &id ) {
+        return Err( data_not_found( req.state(), Some( id ) ).into() );
+    }
+    Ok( id )
+}
+
+fn get_data( req: &HttpRequest ) -> Result< Arc< Data > > {
+    let id = get_data_id( req )?;
+    req.state().get_data( id ).ok_or_else( || ApiError::not_found( "data not found" ).into() )
+}
+
+impl From< ScriptLibraryError > for ApiError {
+    fn from( error: ScriptLibraryError ) -> Self {
+        match error {
+            ScriptLibraryError::Disabled => ApiError::not_found( "the script library is disabled" ),
+            ScriptLibraryError::InvalidName => ApiError::bad_request( "invalid script name" ),
+            ScriptLibraryError::TooLarge => ApiError::payload_too_large( "the script is too big" ),
+            ScriptLibraryError::NotFound => ApiError::not_found( "script not found" ),
+            ScriptLibraryError::Io( error ) => {
+                error!( "Failed to access the script library: {}", error );
+                ApiError::internal( "failed to access the script library" )
+            }
+        }
+    }
+}
+
+impl From< ScriptLibraryError > for ActixWebError {
+    fn from( error: ScriptLibraryError ) -> Self {
+        ApiError::from( error ).into()
+    }
+}
+
+impl From< PrepareFilterError > for ApiError {
+    fn from( error: PrepareFilterError ) -> Self {
+        match error {
+            PrepareFilterError::InvalidRegex( field, inner_err ) => {
+                ApiError::invalid_parameter( field, format!( "invalid '{}': {}", field, inner_err ) )
+            },
+            PrepareFilterError::InvalidCustomFilter( message ) => {
+                ApiError::new( StatusCode::BAD_REQUEST, "invalid_custom_filter", format!( "failed to evaluate custom filter: {}", message ) )
+                    .with_field( "custom_filter" )
+            },
+            PrepareFilterError::DeadlineExceeded => deadline::deadline_exceeded()
+        }
+    }
+}
+
+impl From< PrepareFilterError > for ActixWebError {
+    fn from( error: PrepareFilterError ) -> Self {
+        ApiError::from( error ).into()
+    }
+}
+
+fn async_data_handler< F: FnOnce( Arc< Data >, byte_channel::ByteSender ) + Send + 'static >( req: &HttpRequest, callback: F ) -> Result< Body > {
+    let data_id = get_data_id( &req )?;
+    let state = req.state().clone();
+    async_handler( req, move |tx| {
+        let data = match state.get_data( data_id ) {
+            Some( data ) => data,
+            None => {
+                // It was unloaded in the meantime.
+                tx.abort();
+                return;
+            }
+        };
+
+        callback( data, tx );
+    })
+}
+
+/// Same as `async_data_handler`, except for requests which aren't about any particular data.
+fn async_handler< F: FnOnce( byte_channel::ByteSender ) + Send + 'static >( req: &HttpRequest, callback: F ) -> Result< Body > {
+    let (mut tx, rx) = byte_channel();
+    tx.set_deadline( get_deadline( req )? );
+    let rx = rx.map_err( |_| ApiError::internal( "internal error" ) );
+    let rx = BodyStream::new( rx );
+    let body = Body::Message( Box::new( rx ) );
+
+    let path = req.path().to_owned();
+    let state = req.state().clone();
+    let disconnected = tx.disconnected_flag();
+    let result = req.state().heavy_workers.spawn( move || {
+        callback( tx );
+        if disconnected.is_set() {
+            debug!( "The client has disconnected before the response for {} was fully sent", path );
+            if let Some( ref metrics ) = state.metrics {
+                metrics.record_cancelled_request();
+            }
+        }
+    });
+
+    if result.is_err() {
+        return Err( too_many_queued_requests() );
+    }
+
+    Ok( body )
+}
+
+/// The error returned when a heavy request couldn't be queued.
+fn too_many_queued_requests() -> ActixWebError {
+    let response = HttpResponse::ServiceUnavailable()
+        .header( "Retry-After", HEAVY_REQUEST_RETRY_AFTER.to_string() )
+        .json( ApiError::service_unavailable( "the server is too busy; try again later" ).to_json() );
+
+    actix_web::error::InternalError::from_response( "too many queued requests", response ).into()
+}
+
+/// Makes sure that the client can tell that the response is incomplete if generating it has failed midway.
+fn finish_response< E: fmt::Display >( tx: byte_channel::ByteSender, result: std::result::Result< (), E > ) {
+    if let Err( error ) = result {
+        if tx.is_disconnected() {
+            return;
+        }
+
+        error!( "Failed to generate a response: {}", error );
+        tx.abort();
+    }
+}
+
+fn write_json< T: Serialize >( mut tx: byte_channel::ByteSender, value: &T ) {
+    let result = serde_json::to_writer( &mut tx, value );
+    finish_response( tx, result );
+}
+
+fn write_response< T: Serialize >( mut tx: byte_channel::ByteSender, format: ResponseFormat, value: &T ) {
+    let result = format.write( &mut tx, value );
+    finish_response( tx, result );
+}
+
+/// Returns the format in which the response should be serialized, as given either
+/// through the `format` parameter or through the `Accept` header.
+fn get_response_format( req: &HttpRequest ) -> Result< ResponseFormat > {
+    let params: protocol::RequestFormat = query( req )?;
+    if let Some( format ) = params.format {
+        return Ok( format );
+    }
+
+    let accept = req.headers().get( "Accept" ).and_then( |value| value.to_str().ok() ).unwrap_or( "" );
+    Ok( ResponseFormat::from_accept( accept ) )
+}
+
+fn response_with_format( format: ResponseFormat ) -> actix_web::dev::HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    response.content_type( format.content_type() ).header( "Vary", "Accept" );
+    response
+}
+
+impl From< protocol::Demangle > for DemangleStyle {
+    fn from( value: protocol::Demangle ) -> Self {
+        match value {
+            protocol::Demangle::Full => DemangleStyle::Full,
+            protocol::Demangle::Short => DemangleStyle::Short,
+            protocol::Demangle::None => DemangleStyle::None
+        }
+    }
+}
+
+/// Normalizes the path under which the server is supposed to be available, e.g. `/bytehound/` becomes `/bytehound`.
+///
+/// The root path is represented by an empty string.
+pub fn normalize_base_path( path: &str ) -> std::result::Result< String, String > {
+    let path = path.trim_end_matches( '/' );
+    if path.is_empty() {
+        return Ok( String::new() );
+    }
+
+    if !path.starts_with( '/' ) {
+        return Err( format!( "invalid base path '{}': it must start with a '/'", path ) );
+    }
+
+    let is_valid = path[ 1.. ].split( '/' ).all( |segment| {
+        !segment.is_empty() && segment != "." && segment != ".." && segment.bytes().all( |byte| byte.is_ascii_alphanumeric() || b"-._~".contains( &byte ) )
+    });
+
+    if !is_valid {
+        return Err( format!( "invalid base path '{}'", path ) );
+    }
+
+    Ok( path.to_owned() )
+}
+
+/// Parses a single `from=>to` source path prefix rewriting rule.
+pub fn parse_source_prefix_rule( rule: &str ) -> Option< (String, String) > {
+    let mut iter = rule.splitn( 2, "=>" );
+    let from = iter.next()?.trim();
+    let to = iter.next()?.trim();
+    if from.is_empty() {
+        return None;
+    }
+
+    Some( (from.to_owned(), to.to_owned()) )
+}
+
+/// Rewrites the source path using the rule with the longest matching prefix.
+///
+/// Prefixes only match on whole path components; returns `None` if no rule matches.
+fn remap_source_path( rules: &[(String, String)], path: &str ) -> Option< String > {
+    let mut best_match: Option< &(String, String) > = None;
+    for rule in rules {
+        let from = rule.0.trim_end_matches( '/' );
+        let matches = path.starts_with( from ) && (
+            path.len() == from.len() ||
+            path[ from.len().. ].starts_with( '/' ) ||
+            from.is_empty()
+        );
+
+        if matches && best_match.map( |best_match| best_match.0.trim_end_matches( '/' ).len() < from.len() ).unwrap_or( true ) {
+            best_match = Some( rule );
+        }
+    }
+
+    let (from, to) = best_match?;
+    let rest = &path[ from.trim_end_matches( '/' ).len().. ];
+    Some( format!( "{}{}", to.trim_end_matches( '/' ), rest ) )
+}
+
+#[test]
+fn test_parse_source_prefix_rule() {
+    assert_eq!( parse_source_prefix_rule( "/a=>/b" ), Some( ("/a".to_owned(), "/b".to_owned()) ) );
+    assert_eq!( parse_source_prefix_rule( " /a => /b " ), Some( ("/a".to_owned(), "/b".to_owned()) ) );
+    assert_eq!( parse_source_prefix_rule( "/a=>" ), Some( ("/a".to_owned(), "".to_owned()) ) );
+    assert_eq!( parse_source_prefix_rule( "=>/b" ), None );
+    assert_eq!( parse_source_prefix_rule( "/a" ), None );
+}
+
+#[test]
+fn test_remap_source_path() {
+    let rules = vec![
+        ("/builds/runner/src".to_owned(), "/home/me/project".to_owned()),
+        ("/builds/runner/src/vendor/".to_owned(), "/opt/vendor".to_owned()),
+        ("/rustc/abcdef".to_owned(), "/home/me/.rustup/src".to_owned())
+    ];
+
+    assert_eq!( remap_source_path( &rules, "/builds/runner/src/main.rs" ).as_deref(), Some( "/home/me/project/main.rs" ) );
+    assert_eq!( remap_source_path( &rules, "/builds/runner/src/vendor/lib.rs" ).as_deref(), Some( "/opt/vendor/lib.rs" ) );
+    assert_eq!( remap_source_path( &rules, "/builds/runner/src" ).as_deref(), Some( "/home/me/project" ) );
+    assert_eq!( remap_source_path( &rules, "/builds/runner/srcfoo/main.rs" ), None );
+    assert_eq!( remap_source_path( &rules, "/usr/include/stdlib.h" ), None );
+    assert_eq!( remap_source_path( &[], "/builds/runner/src/main.rs" ), None );
+}
+
+fn get_source< 'a >( data: &'a Data, format: &protocol::BacktraceFormat, frame: &Frame ) -> (Option< Cow< 'a, str > >, Option< &'a str >) {
+    let source = match frame.source() {
+        Some( id ) => data.interner().resolve( id ).unwrap(),
+        None => return (None, None)
+    };
+
+    let remapped = format.source_prefix_map.as_ref().and_then( |map| remap_source_path( &map.0, source ) );
+    match remapped {
+        Some( remapped ) => (Some( remapped.into() ), Some( source )),
+        None => (Some( source.into() ), None)
+    }
+}
+
+const DEFAULT_SOURCE_SNIPPET_CONTEXT: u32 = 3;
+const MAXIMUM_SOURCE_SNIPPET_CONTEXT: u32 = 50;
+
+fn get_source_snippet( format: &protocol::BacktraceFormat, source: Option< &str >, line: Option< u32 > ) -> Option< protocol::SourceSnippet > {
+    let source_files = format.source_files.as_ref()?;
+    let context = min( format.source_snippet_context.unwrap_or( DEFAULT_SOURCE_SNIPPET_CONTEXT ), MAXIMUM_SOURCE_SNIPPET_CONTEXT );
+    let (first_line, lines) = source_files.get_snippet( source?.as_ref(), line?, context )?;
+    Some( protocol::SourceSnippet {
+        first_line,
+        lines
+    })
+}
+
+fn get_frame< 'a >( data: &'a Data, format: &protocol::BacktraceFormat, frame: &Frame ) -> protocol::Frame< 'a > {
+    let mut function = frame.function().map( |id| Cow::Borrowed( data.interner().resolve( id ).unwrap() ) );
+    if let Some( style ) = format.demangle {
+        if let Some( raw_function ) = frame.raw_function().map( |id| data.interner().resolve( id ).unwrap() ) {
+            function = Some( cli_core::demangle_symbol( raw_function, style.into() ) );
+        }
+    }
+
+    if format.strip_template_args.unwrap_or( false ) || format.template_depth.is_some() {
+        let keep_depth = format.template_depth.unwrap_or( 0 ) as usize;
+        function = function.map( |function| cli_core::strip_template( &function, keep_depth ).into() );
+    }
+
+    let (source, source_original) = get_source( data, format, frame );
+    let source_snippet = get_source_snippet( format, source.as_deref(), frame.line() );
+    let module = data.module_for_address( frame.address().raw() );
+    protocol::Frame {
+        address: frame.address().raw(),
+        address_s: format!( "{:016X}", frame.address().raw() ),
+        count: frame.count(),
+        library: frame.library().map( |id| data.interner().resolve( id ).unwrap() ),
+        library_offset: module.map( |module| frame.address().raw() - module.address_range.start ),
+        build_id: module.and_then( |module| module.build_id.as_deref() ),
+        function,
+        raw_function: frame.raw_function().map( |id| data.interner().resolve( id ).unwrap() ),
+        source,
+        source_original,
+        line: frame.line(),
+        column: frame.column(),
+        source_snippet,
+        is_inline: frame.is_inline(),
+        inlined_count: None
+    }
+}
+
+/// A physical frame with all of its inline frames merged into it.
+#[derive(Copy, Clone)]
+struct CollapsedFrame< 'a > {
+    outermost: &'a Frame,
+    innermost: &'a Frame,
+    inlined_count: u32
+}
+
+/// Merges consecutive inline frames into their parent frame.
+///
+/// The frames are expected to be ordered from the outermost one, as returned by `Data::get_backtrace`.
+/// The key of every merged frame is the pair of the keys of its outermost and innermost frames.
+fn collapse_inlined_frames< 'a, K: Copy >( backtrace: impl Iterator< Item = (K, &'a Frame) > ) -> Vec< ((K, K), CollapsedFrame< 'a >) > {
+    let mut output: Vec< ((K, K), CollapsedFrame) > = Vec::new();
+    for (key, frame) in backtrace {
+        if let Some( ((_, last_key), last) ) = output.last_mut() {
+            if frame.is_inline() && frame.address() == last.outermost.address() {
+                *last_key = key;
+                last.innermost = frame;
+                last.inlined_count += 1;
+                continue;
+            }
+        }
+
+        output.push( ((key, key), CollapsedFrame {
+            outermost: frame,
+            innermost: frame,
+            inlined_count: 0
+        }));
+    }
+
+    output
+}
+
+fn get_collapsed_frame< 'a >( data: &'a Data, format: &protocol::BacktraceFormat, frame: &CollapsedFrame ) -> protocol::Frame< 'a > {
+    let mut output = get_frame( data, format, frame.outermost );
+    if frame.inlined_count > 0 {
+        let (source, source_original) = get_source( data, format, frame.innermost );
+        output.source_snippet = get_source_snippet( format, source.as_deref(), frame.innermost.line() );
+        output.source = source;
+        output.source_original = source_original;
+        output.line = frame.innermost.line();
+        output.column = frame.innermost.column();
+        output.inlined_count = Some( frame.inlined_count );
+    }
+
+    output
+}
+
+fn trimmed_frame_count< 'a >( data: &Data, format: &protocol::BacktraceFormat, backtrace: impl Iterator< Item = (FrameId, &'a Frame) > + Clone ) -> usize {
+    cli_core::trimmed_frame_count(
+        data,
+        backtrace.map( |(_, frame)| frame ),
+        format.trim_below.as_ref().map( |pattern| &pattern.0 ),
+        format.trim_runtime_prefix.unwrap_or( false )
+    )
+}
+
+/// Returns the range of frames which should be kept out of a backtrace of the given length.
+fn truncated_frame_range( max_frames: Option< u32 >, from: protocol::FramesFrom, length: usize ) -> std::ops::Range< usize > {
+    let max_frames = match max_frames {
+        Some( max_frames ) => max_frames as usize,
+        None => return 0..length
+    };
+
+    if length <= max_frames {
+        return 0..length;
+    }
+
+    match from {
+        protocol::FramesFrom::Leaf => length - max_frames..length,
+        protocol::FramesFrom::Root => 0..max_frames
+    }
+}
+
+fn truncate_frames< 'a, T >( format: &protocol::BacktraceFormat, frames: &'a [T] ) -> (&'a [T], Option< u32 >) {
+    let range = truncated_frame_range( format.max_frames, format.frames_from.unwrap_or( protocol::FramesFrom::Leaf ), frames.len() );
+    let truncated_count = format.max_frames.map( |_| (frames.len() - range.len()) as u32 );
+    (&frames[ range ], truncated_count)
+}
+
+#[test]
+fn test_truncated_frame_range() {
+    use protocol::FramesFrom::{Leaf, Root};
+
+    assert_eq!( truncated_frame_range( None, Leaf, 10 ), 0..10 );
+    assert_eq!( truncated_frame_range( Some( 3 ), Leaf, 10 ), 7..10 );
+    assert_eq!( truncated_frame_range( Some( 3 ), Root, 10 ), 0..3 );
+    assert_eq!( truncated_frame_range( Some( 10 ), Leaf, 10 ), 0..10 );
+    assert_eq!( truncated_frame_range( Some( 20 ), Root, 10 ), 0..10 );
+    assert_eq!( truncated_frame_range( Some( 0 ), Leaf, 10 ), 10..10 );
+    assert_eq!( truncated_frame_range( Some( 0 ), Root, 10 ), 0..0 );
+    assert_eq!( truncated_frame_range( Some( 0 ), Leaf, 0 ), 0..0 );
+}
+
+/// The frames of a backtrace which should be shown, keyed by the ids of their outermost and
+/// innermost frames, along with the number of frames omitted due to `max_frames`, if set.
+type SelectedFrames< 'a > = (Vec< ((FrameId, FrameId), CollapsedFrame< 'a >) >, Option< u32 >);
+
+/// Returns which frames of a backtrace should be shown, ordered from the outermost one, along
+/// with the number of frames omitted due to `max_frames`, if set.
+///
+/// The frames are first trimmed, then collapsed and only then truncated. When the inline frames
+/// aren't collapsed every frame is returned as its own collapsed frame.
+fn select_frames< 'a >( data: &Data, format: &protocol::BacktraceFormat, backtrace: impl Iterator< Item = (FrameId, &'a Frame) > + Clone ) -> SelectedFrames< 'a > {
+    let backtrace = backtrace.clone().skip( trimmed_frame_count( data, format, backtrace ) );
+    if format.collapse_inlined.unwrap_or( false ) {
+        let frames = collapse_inlined_frames( backtrace );
+        let (frames, truncated_count) = truncate_frames( format, &frames );
+        (frames.to_vec(), truncated_count)
+    } else if format.max_frames == Some( 0 ) {
+        (Vec::new(), Some( backtrace.count() as u32 ))
+    } else {
+        let frames: Vec< _ > = backtrace.map( |(id, frame)| ((id, id), CollapsedFrame { outermost: frame, innermost: frame, inlined_count: 0 }) ).collect();
+        let (frames, truncated_count) = truncate_frames( format, &frames );
+        (frames.to_vec(), truncated_count)
+    }
+}
+
+/// Returns the frames of a backtrace, ordered from the outermost one, along with the number
+/// of frames omitted due to `max_frames`, if set.
+fn get_frames< 'a >( data: &'a Data, format: &protocol::BacktraceFormat, backtrace: impl Iterator< Item = (FrameId, &'a Frame) > + Clone ) -> (Vec< protocol::Frame< 'a > >, Option< u32 >) {
+    let (frames, truncated_count) = select_frames( data, format, backtrace );
+    (frames.iter().map( |(_, frame)| get_collapsed_frame( data, format, frame ) ).collect(), truncated_count)
+}
+
+/// Formats the backtraces of a single response.
+///
+/// A lot of what's returned tends to share the same backtraces, and even different backtraces
+/// share most of their frames, so every backtrace is only trimmed and truncated once, and every
+/// frame is only formatted once and then copied.
+///
+/// What's remembered depends on every one of the `BacktraceFormat`'s options, which is why
+/// the formatter owns its format and can't be used with any other one.
+struct BacktraceFormatter< 'a > {
+    data: &'a Data,
+    format: protocol::BacktraceFormat,
+    frames: HashMap< (FrameId, FrameId), protocol::Frame< 'a > >,
+    backtraces: HashMap< BacktraceId, SelectedFrames< 'a > >
+}
+
+impl< 'a > BacktraceFormatter< 'a > {
+    fn new( data: &'a Data, format: protocol::BacktraceFormat ) -> Self {
+        BacktraceFormatter {
+            data,
+            format,
+            frames: HashMap::new(),
+            backtraces: HashMap::new()
+        }
+    }
+
+    /// Same as `get_frames`.
+    fn get_frames( &mut self, backtrace_id: BacktraceId ) -> (Vec< protocol::Frame< 'a > >, Option< u32 >) {
+        let data = self.data;
+        let format = &self.format;
+        let cached_frames = &mut self.frames;
+        let (frames, truncated_count) = self.backtraces.entry( backtrace_id ).or_insert_with( || select_frames( data, format, data.get_backtrace( backtrace_id ) ) );
+        let frames = frames.iter().map( |(key, frame)| {
+            cached_frames.entry( *key ).or_insert_with( || get_collapsed_frame( data, format, frame ) ).clone()
+        }).collect();
+
+        (frames, *truncated_count)
+    }
+}
+
+#[test]
+fn test_collapse_inlined_frames() {
+    fn frame( address: u64, is_inline: bool, line: u32 ) -> Frame {
+        let mut frame = Frame::new_unknown( cli_core::CodePointer::new( address ) );
+        frame.set_is_inline( is_inline );
+        frame.set_line( line );
+        frame
+    }
+
+    let frames = vec![
+        frame( 0x1000, false, 1 ),
+        frame( 0x2000, false, 2 ),
+        frame( 0x2000, true, 3 ),
+        frame( 0x2000, true, 4 ),
+        frame( 0x3000, false, 5 ),
+        frame( 0x4000, true, 6 ),
+        frame( 0x5000, false, 7 ),
+        frame( 0x5000, true, 8 )
+    ];
+
+    let collapsed = collapse_inlined_frames( frames.iter().enumerate() );
+    let collapsed: Vec< _ > = collapsed.into_iter().map( |(key, frame)| {
+        (key, frame.outermost.line().unwrap(), frame.innermost.line().unwrap(), frame.inlined_count)
+    }).collect();
+
+    assert_eq!( collapsed, vec![
+        ((0, 0), 1, 1, 0),
+        ((1, 3), 2, 4, 2),
+        ((4, 4), 5, 5, 0),
+        ((5, 5), 6, 6, 0),
+        ((6, 7), 7, 8, 1)
+    ]);
+
+    assert!( collapse_inlined_frames( std::iter::empty::< (usize, &Frame) >() ).is_empty() );
+}
+
+#[test]
+fn test_backtrace_formatter() {
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        test_string( 0, "libfoo.so" ),
+        test_string( 1, "main" ),
+        test_string( 2, "std::vector<int, std::allocator<int> >::push_back(int const&)" ),
+        test_string( 3, "/src/main.cpp" ),
+        test_frame( 0x1000, 2, 3, 10, true ),
+        test_frame( 0x1000, 2, 3, 11, false ),
+        test_frame( 0x2000, 1, 3, 20, false ),
+        test_frame( 0x3000, 1, 3, 30, false ),
+        Event::DecodedBacktrace { frames: vec![ 0, 1, 2 ].into() },
+        Event::DecodedBacktrace { frames: vec![ 0, 1, 3 ].into() },
+        test_alloc( 1, 0x1000, 1, 0 ),
+        test_alloc( 1, 0x2000, 1, 1 ),
+        test_alloc( 1, 0x3000, 1, 0 ),
+        test_alloc( 1, 0x4000, 1, 1 )
+    ]);
+
+    let queries = [
+        "",
+        "strip_template_args=true",
+        "collapse_inlined=true",
+        "max_frames=2",
+        "max_frames=2&frames_from=root",
+        "max_frames=0"
+    ];
+
+    for query in &queries {
+        let format: protocol::BacktraceFormat = serde_urlencoded::from_str( query ).unwrap();
+        let mut backtraces = BacktraceFormatter::new( &data, format.clone() );
+        for (_, allocation) in data.allocations_with_id() {
+            let expected = get_frames( &data, &format, data.get_backtrace( allocation.backtrace ) );
+            let actual = backtraces.get_frames( allocation.backtrace );
+            assert_eq!( serde_json::to_value( &actual ).unwrap(), serde_json::to_value( &expected ).unwrap(), "{}", query );
+        }
+
+        if query.is_empty() {
+            // The frames shared by both of the backtraces are only formatted once.
+            assert_eq!( backtraces.backtraces.len(), 2 );
+            assert_eq!( backtraces.frames.len(), 4 );
+        }
+    }
+}
+
+#[test]
+fn test_modules_and_library_offsets() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let maps = "\
+        00010000-00012000 r--p 00000000 08:01 1234 /usr/bin/app\n\
+        00012000-00020000 r-xp 00002000 08:01 1234 /usr/bin/app\n\
+        00030000-00031000 rw-p 00000000 00:00 0 [heap]\n";
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::File { timestamp: Timestamp::from_secs( 1 ), path: "/proc/self/maps".into(), contents: maps.as_bytes().to_vec().into() },
+        test_string( 0, "/usr/bin/app" ),
+        test_frame( 0x12345, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, false ),
+        test_frame( 0x90000, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, false ),
+        Event::DecodedBacktrace { frames: vec![ 0 ].into() },
+        Event::DecodedBacktrace { frames: vec![ 1 ].into() },
+        test_alloc( 2, 0x1000, 16, 0 ),
+        test_alloc( 2, 0x2000, 16, 1 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/modules" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "modules" ], serde_json::json!([
+        { "path": "/usr/bin/app", "start": 0x10000, "end": 0x20000, "start_s": "0000000000010000", "end_s": "0000000000020000" }
+    ]));
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?sort_by=address" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let allocations = response[ "allocations" ].as_array().unwrap();
+    assert_eq!( allocations[ 0 ][ "backtrace" ][ 0 ][ "library_offset" ], 0x2345 );
+    assert!( allocations[ 1 ][ "backtrace" ][ 0 ].get( "library_offset" ).is_none() );
+    assert!( allocations[ 1 ][ "backtrace" ][ 0 ].get( "build_id" ).is_none() );
+}
+
+/// The parts of the metadata which are too expensive to compute every time the data is listed.
+struct DataSummary {
+    peak: Option< (Timestamp, u64) >,
+    thread_count: u64,
+    mmap_count: u64,
+    mmap_bytes: u64,
+    munmap_count: u64,
+    munmap_bytes: u64
+}
+
+fn get_data_summary( data: &Data ) -> DataSummary {
+    let ops = operations_for_allocations( data, data.alloc_sorted_by_timestamp( None, None ) );
+    let peak = find_peak( data, &ops ).map( |(index, usage)| (get_operation_timestamp( data, ops[ index ] ), usage as u64) );
+
+    let mut threads = std::collections::HashSet::new();
+    for allocation in data.unsorted_allocations() {
+        threads.insert( allocation.thread );
+        if let Some( ref deallocation ) = allocation.deallocation {
+            threads.insert( deallocation.thread );
+        }
+    }
+
+    let mut summary = DataSummary {
+        peak,
+        thread_count: 0,
+        mmap_count: 0,
+        mmap_bytes: 0,
+        munmap_count: 0,
+        munmap_bytes: 0
+    };
+
+    for op in data.mmap_operations() {
+        threads.insert( op.thread() );
+        match *op {
+            MmapOperation::Mmap( ref mmap ) => {
+                summary.mmap_count += 1;
+                summary.mmap_bytes += mmap.length;
+            },
+            MmapOperation::Munmap( ref munmap ) => {
+                summary.munmap_count += 1;
+                summary.munmap_bytes += munmap.length;
+            },
+            _ => {}
+        }
+    }
+
+    summary.thread_count = threads.len() as u64;
+    summary
+}
+
+impl protocol::ResponseMetadata {
+    fn new( data: &Data, summary: &DataSummary ) -> Self {
+        protocol::ResponseMetadata {
+            id: format!( "{}", data.id() ),
+            executable: data.executable().to_owned(),
+            architecture: data.architecture().to_owned(),
+            final_allocated: data.total_allocated() - data.total_freed(),
+            final_allocated_count: data.total_allocated_count() - data.total_freed_count(),
+            total_allocated: data.total_allocated(),
+            total_allocated_count: data.total_allocated_count(),
+            total_freed: data.total_freed(),
+            total_freed_count: data.total_freed_count(),
+            runtime: (data.last_timestamp() - data.initial_timestamp()).into(),
+            unique_backtrace_count: data.unique_backtrace_count() as u64,
+            maximum_backtrace_depth: data.maximum_backtrace_depth(),
+            timestamp: data.initial_timestamp().into(),
+            precompute: None,
+            sources: data.sources().iter().map( |id| format!( "{}", id ) ).collect(),
+            source_kind: None,
+            source_path: None,
+            source_size: None,
+            loaded_at: None,
+            load_duration_secs: None,
+            approx_resident_bytes: data.memory_usage_estimate(),
+            peak_allocated: summary.peak.map( |(_, usage)| usage ).unwrap_or( 0 ),
+            peak_timestamp: summary.peak.map( |(timestamp, _)| timestamp.into() ),
+            operation_count: data.operation_ids().len() as u64,
+            thread_count: summary.thread_count,
+            mmap_count: summary.mmap_count,
+            mmap_bytes: summary.mmap_bytes,
+            munmap_count: summary.munmap_count,
+            munmap_bytes: summary.munmap_bytes,
+            pid: data.pid(),
+            cmdline: data.cmdline().to_vec(),
+            has_wall_clock: data.wall_clock_start().is_some(),
+            wall_clock_start: data.wall_clock_start().map( to_iso8601 )
+        }
+    }
+
+    fn set_provenance( &mut self, provenance: &loading::Provenance ) {
+        self.source_kind = Some( provenance.source.kind() );
+        self.source_path = match provenance.source {
+            loading::Source::File( ref path ) => Some( path.to_string_lossy().into_owned() ),
+            loading::Source::Live( ref address ) => Some( address.clone() ),
+            loading::Source::Upload | loading::Source::Merge => None
+        };
+        self.source_size = provenance.source_size;
+        self.loaded_at = provenance.loaded_at.duration_since( std::time::UNIX_EPOCH ).map( |duration| duration.as_secs() ).ok();
+        self.load_duration_secs = Some( provenance.load_duration.as_secs_f64() );
+    }
+}
+
+fn handler_list( req: HttpRequest ) -> HttpResponse {
+    let state = req.state();
+    let loaded: Vec< _ > = {
+        let data = state.data.read();
+        let summaries = state.summaries.read();
+        data.values().map( |data| (data.clone(), summaries[ &data.id() ].clone()) ).collect()
+    };
+
+    let precompute_progress = state.precompute_progress.lock();
+    let aliases = state.aliases.lock();
+    let provenance = state.provenance.lock();
+    let mut list: Vec< _ > = loaded.iter().map( |(data, summary)| {
+        let mut metadata = protocol::ResponseMetadata::new( data, &summary );
+        if let Some( provenance ) = provenance.get( &data.id() ) {
+            metadata.set_provenance( provenance );
+        }
+
+        metadata.precompute = precompute_progress.get( &data.id() ).map( |completed| {
+            protocol::PrecomputeProgress {
+                completed: completed.load( Ordering::Relaxed ) as u32,
+                total: PRECOMPUTE_TASK_COUNT as u32
+            }
+        });
+
+        let mut entry = serde_json::to_value( metadata ).unwrap();
+        if state.is_live( data.id() ) {
+            entry[ "live" ] = true.into();
+        }
+
+        if let Some( name ) = aliases.name_of( data.id() ) {
+            entry[ "name" ] = name.into();
+        }
+
+        entry
+    }).collect();
+
+    for entry in &mut list {
+        entry[ "state" ] = "ready".into();
+        entry[ "progress" ] = 1.0.into();
+    }
+
+    // The files which are still being loaded, or which have failed to load.
+    for input in state.inputs.read().iter() {
+        let mut entry = serde_json::json! {{
+            "path": input.path.to_string_lossy(),
+            "progress": input.progress() / 100.0
+        }};
+
+        match input.status() {
+            loading::LoadStatus::Loaded( _ ) => continue,
+            loading::LoadStatus::Loading => {
+                entry[ "state" ] = "loading".into();
+                if let Some( phase ) = input.phase() {
+                    entry[ "phase" ] = phase.name().into();
+                }
+            },
+            loading::LoadStatus::Failed( error ) => {
+                entry[ "state" ] = "failed".into();
+                entry[ "error" ] = error.into();
+            }
+        }
+
+        if let Some( header ) = input.header() {
+            if state.data.read().contains_key( &header.id ) {
+                // It was just loaded.
+                continue;
+            }
+
+            entry[ "id" ] = format!( "{}", header.id ).into();
+            entry[ "executable" ] = header.executable.into();
+        }
+
+        list.push( entry );
+    }
+
+    HttpResponse::Ok().json( list )
+}
+
+/// Exposes the server's configuration which is relevant to its clients.
+fn handler_config( req: HttpRequest ) -> HttpResponse {
+    HttpResponse::Ok().json( serde_json::json! {{
+        "base_path": req.state().base_path
+    }})
+}
+
+fn redirect_to_base_path( base_path: &str, req: HttpRequest ) -> HttpResponse {
+    let mut location = format!( "{}/", base_path );
+    if !req.query_string().is_empty() {
+        location.push( '?' );
+        location.push_str( req.query_string() );
+    }
+
+    HttpResponse::MovedPermanently().header( "Location", location ).finish()
+}
+
+fn handler_healthz( _: HttpRequest ) -> HttpResponse {
+    HttpResponse::Ok().json( serde_json::json! {{
+        "status": "ok"
+    }})
+}
+
+impl From< loading::LoadError > for ApiError {
+    fn from( error: loading::LoadError ) -> Self {
+        match error {
+            loading::LoadError::InvalidFile( error ) => ApiError::bad_request( format!( "not a valid data file: {}", error ) ),
+            loading::LoadError::AlreadyLoaded( id ) => {
+                ApiError::conflict( format!( "data with the same ID ({}) is already loaded", id ) )
+                    .with_details( serde_json::json! {{ "id": format!( "{}", id ) }} )
+            },
+            loading::LoadError::Disabled => ApiError::forbidden( "loading data files by path is disabled on this server" ),
+            loading::LoadError::NotFound => ApiError::not_found( "file not found" ),
+            loading::LoadError::Forbidden => ApiError::forbidden( "the file is outside of the data directories" )
+        }
+    }
+}
+
+impl From< loading::LoadError > for ActixWebError {
+    fn from( error: loading::LoadError ) -> Self {
+        ApiError::from( error ).into()
+    }
+}
+
+impl From< aliases::AliasError > for ActixWebError {
+    fn from( error: aliases::AliasError ) -> Self {
+        match error {
+            aliases::AliasError::InvalidName => {
+                ApiError::bad_request( "invalid name; it can only contain letters, digits, '_', '-' and '.', and can't look like an ID" ).into()
+            },
+            aliases::AliasError::Taken( id ) => {
+                ApiError::conflict( "the name is already used by other data" )
+                    .with_details( serde_json::json! {{ "id": format!( "{}", id ) }} )
+                    .into()
+            },
+            aliases::AliasError::Io( error ) => {
+                error!( "Failed to save the aliases: {}", error );
+                ApiError::internal( "failed to save the aliases" ).into()
+            }
+        }
+    }
+}
+
+impl From< MergeError > for ApiError {
+    fn from( error: MergeError ) -> Self {
+        match error {
+            MergeError::NotEnoughData | MergeError::DuplicateData( _ ) => ApiError::bad_request( error.to_string() ),
+            MergeError::ExecutableMismatch( .. ) | MergeError::ArchitectureMismatch( .. ) => {
+                ApiError::new( StatusCode::BAD_REQUEST, "incompatible_data", error.to_string() )
+            }
+        }
+    }
+}
+
+fn loading_response( id: DataId ) -> HttpResponse {
+    HttpResponse::Accepted().json( serde_json::json! {{
+        "id": format!( "{}", id ),
+        "state": "loading"
+    }})
+}
+
+/// Loads a data file from one of the directories given through `--data-dir`.
+fn handler_load( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    let request: protocol::RequestLoad = serde_json::from_slice( &body ).map_err( |error| ApiError::bad_request( format!( "invalid request body: {}", error ) ) )?;
+    let state = req.state();
+    let path = loading::resolve_data_path( &state.data_dirs, &request.path )?;
+    let id = loading::load_file( state.clone(), path, request.range )?;
+    Ok( loading_response( id ) )
+}
+
+/// Merges already loaded data into a new data set, e.g. the data of every worker process of an application.
+fn handler_merge( req: HttpRequest, body: web::Bytes ) -> Box< dyn Future< Item = HttpResponse, Error = ActixWebError > > {
+    let sources = serde_json::from_slice( &body )
+        .map_err( |error| ApiError::bad_request( format!( "invalid request body: {}", error ) ).into() )
+        .and_then( |request: protocol::RequestMerge| {
+            request.ids.iter().map( |id| {
+                let id = parse_data_id( &req, id )?;
+                req.state().get_data( id ).ok_or_else( || ApiError::not_found( "data not found" ).into() )
+            }).collect::< Result< Vec< _ > > >()
+        });
+
+    let sources = match sources {
+        Ok( sources ) => sources,
+        Err( error ) => return Box::new( future::err( error ) )
+    };
+
+    let (tx, rx) = futures::sync::oneshot::channel();
+    let state = req.state().clone();
+    let result = req.state().heavy_workers.spawn( move || {
+        let _ = tx.send( merge_data( &state, &sources ) );
+    });
+
+    if result.is_err() {
+        return Box::new( future::err( too_many_queued_requests() ) );
+    }
+
+    let response = rx
+        .map_err( |_| ApiError::internal( "internal error" ).into() )
+        .and_then( |result| {
+            let response = result?;
+            Ok( HttpResponse::Created().json( response ) )
+        });
+
+    Box::new( response )
+}
+
+fn merge_data( state: &Arc< State >, sources: &[Arc< Data >] ) -> std::result::Result< protocol::ResponseMetadata, ApiError > {
+    let sources: Vec< &Data > = sources.iter().map( |data| &**data ).collect();
+    let started = Instant::now();
+    let data = Data::merge( &sources )?;
+    let id = data.id();
+    if state.get_data( id ).is_some() {
+        return Err( loading::LoadError::AlreadyLoaded( id ).into() );
+    }
+
+    info!( "Merged {} data sets into {}", sources.len(), id );
+    let provenance = loading::Provenance::new( loading::Source::Merge, None, started );
+    if state.precompute {
+        state.precompute_progress.lock().insert( id, Default::default() );
+    }
+
+    state.provenance.lock().insert( id, provenance.clone() );
+    state.add_data( data );
+
+    let (data, summary) = state.get_data( id ).zip( state.get_summary( id ) ).ok_or_else( || ApiError::not_found( "the merged data was removed" ) )?;
+    let mut response = protocol::ResponseMetadata::new( &data, &summary );
+    response.set_provenance( &provenance );
+    if state.precompute {
+        schedule_precompute( state, id );
+    }
+
+    Ok( response )
+}
+
+/// Receives a data file and starts loading it in the background; responds with the ID
+/// of the data, which can be used right away to track its loading progress.
+///
+/// The body is written to a temporary file as it's received so that even huge files don't have to fit in memory.
+fn handler_upload( req: HttpRequest, payload: web::Payload ) -> Box< dyn Future< Item = HttpResponse, Error = ActixWebError > > {
+    let upload = match loading::Upload::create() {
+        Ok( upload ) => upload,
+        Err( error ) => {
+            error!( "Failed to create a temporary file for an upload: {}", error );
+            return Box::new( future::err( ApiError::internal( "failed to create a temporary file" ).into() ) );
+        }
+    };
+
+    let state = req.state().clone();
+    let response = payload
+        .map_err( ActixWebError::from )
+        .fold( upload, |mut upload, chunk| {
+            upload.write( &chunk ).map( |_| upload ).map_err( |error| {
+                error!( "Failed to write an upload to a temporary file: {}", error );
+                ActixWebError::from( ApiError::internal( "failed to write the data to a temporary file" ) )
+            })
+        })
+        .and_then( move |upload| {
+            let id = loading::load_upload( state, upload )?;
+            Ok( loading_response( id ) )
+        });
+
+    Box::new( response )
+}
+
+/// Responds with `200 OK` once every input file has finished loading, successfully or not,
+/// and with `503 Service Unavailable` otherwise.
+fn handler_readyz( req: HttpRequest ) -> HttpResponse {
+    let state = req.state();
+    let inputs = state.inputs.read();
+    let files: Vec< _ > = inputs.iter().map( |input| input.to_json() ).collect();
+    let is_ready = inputs.iter().all( |input| input.status() != loading::LoadStatus::Loading );
+    let body = serde_json::json! {{
+        "status": if is_ready { "ready" } else { "loading" },
+        "files": files
+    }};
+
+    if is_ready {
+        HttpResponse::Ok().json( body )
+    } else {
+        HttpResponse::ServiceUnavailable().json( body )
+    }
+}
+
+/// Returns the progress of every data file which was given to the server, whether it's still being loaded or not.
+fn handler_load_progress( req: HttpRequest ) -> HttpResponse {
+    let files: Vec< _ > = req.state().inputs.read().iter().map( |input| input.to_json() ).collect();
+    HttpResponse::Ok().json( files )
+}
+
+fn handler_metrics( req: HttpRequest ) -> Result< HttpResponse > {
+    let state = req.state();
+    let metrics = state.metrics.as_ref().ok_or_else( || ApiError::not_found( "metrics are disabled" ) )?;
+
+    let mut output = String::new();
+    metrics.write_to( &mut output );
+
+    let caches = [
+        ("allocation_groups", state.allocation_group_cache.hits_and_misses()),
+        ("timeline", state.timeline_cache.hits_and_misses()),
+        ("temporary_timeline", state.temporary_timeline_cache.hits_and_misses()),
+        ("fragmentation_timeline", state.fragmentation_timeline_cache.hits_and_misses()),
+        ("backtraces_by_address", state.backtraces_by_address_cache.hits_and_misses()),
+        ("mmap_state", state.mmap_state_cache.hits_and_misses()),
+        ("allocations_by_address", state.allocations_by_address_cache.hits_and_misses()),
+        ("backtrace_clusters", state.backtrace_clusters_cache.hits_and_misses()),
+        ("matches", state.match_cache.hits_and_misses())
+    ];
+
+    metrics::write_header( &mut output, "bytehound_cache_hits_total", "counter", "The number of times a value was found in a cache." );
+    for (name, (hits, _)) in &caches {
+        writeln!( output, "bytehound_cache_hits_total{{cache=\"{}\"}} {}", name, hits ).unwrap();
+    }
+
+    metrics::write_header( &mut output, "bytehound_cache_misses_total", "counter", "The number of times a value had to be computed because it wasn't in a cache." );
+    for (name, (_, misses)) in &caches {
+        writeln!( output, "bytehound_cache_misses_total{{cache=\"{}\"}} {}", name, misses ).unwrap();
+    }
+
+    let library_by_backtrace_size: usize = state.library_by_backtrace_cache.lock().iter().map( |(_, libraries)| vec_size_in_bytes( libraries ) ).sum();
+    let cache_sizes = [
+        ("allocation_groups", state.allocation_group_cache.total_weight()),
+        ("timeline", state.timeline_cache.total_weight()),
+        ("temporary_timeline", state.temporary_timeline_cache.total_weight()),
+        ("fragmentation_timeline", state.fragmentation_timeline_cache.total_weight()),
+        ("library_by_backtrace", library_by_backtrace_size),
+        ("backtraces_by_address", state.backtraces_by_address_cache.total_weight()),
+        ("mmap_state", state.mmap_state_cache.total_weight()),
+        ("allocations_by_address", state.allocations_by_address_cache.total_weight()),
+        ("backtrace_clusters", state.backtrace_clusters_cache.total_weight()),
+        ("matches", state.match_cache.total_weight())
+    ];
+
+    metrics::write_header( &mut output, "bytehound_cache_bytes", "gauge", "A rough estimate of how much memory the values in a cache occupy." );
+    for (name, size) in &cache_sizes {
+        writeln!( output, "bytehound_cache_bytes{{cache=\"{}\"}} {}", name, size ).unwrap();
+    }
+
+    let (generated_files_count, generated_files_size) = {
+        let generated = state.generated_files.lock();
+        (generated.by_hash.len(), generated.total_size)
+    };
+
+    metrics::write_metric( &mut output, "bytehound_generated_files", "gauge", "The number of files generated by scripts which are kept in memory.", generated_files_count as u64 );
+    metrics::write_metric( &mut output, "bytehound_generated_files_bytes", "gauge", "The total size of the files generated by scripts which are kept in memory.", generated_files_size as u64 );
+
+    let datasets = state.datasets();
+    metrics::write_header( &mut output, "bytehound_dataset_allocations", "gauge", "The number of allocations in a loaded data file." );
+    for data in &datasets {
+        writeln!( output, "bytehound_dataset_allocations{{data_id=\"{}\"}} {}", data.id(), data.total_allocated_count() ).unwrap();
+    }
+
+    metrics::write_header( &mut output, "bytehound_dataset_resident_bytes_estimate", "gauge", "A rough estimate of how much memory a loaded data file occupies." );
+    for data in &datasets {
+        writeln!( output, "bytehound_dataset_resident_bytes_estimate{{data_id=\"{}\"}} {}", data.id(), data.memory_usage_estimate() ).unwrap();
+    }
+
+    let workers = &state.heavy_workers;
+    let (processed, queue_wait) = workers.queue_wait();
+    metrics::write_metric( &mut output, "bytehound_heavy_workers", "gauge", "The number of threads which process the heavy requests.", workers.worker_count() as u64 );
+    metrics::write_metric( &mut output, "bytehound_heavy_requests_queued", "gauge", "The number of heavy requests waiting for a free worker.", workers.queued_count() as u64 );
+    metrics::write_metric( &mut output, "bytehound_heavy_workers_released", "gauge", "The number of workers which were replaced while waiting for slow clients to receive their responses.", workers.released_count() as u64 );
+    metrics::write_metric( &mut output, "bytehound_heavy_requests_rejected_total", "counter", "The number of heavy requests rejected because too many were already queued.", workers.rejected_count() );
+    metrics::write_header( &mut output, "bytehound_heavy_request_queue_wait_seconds", "summary", "How long the heavy requests had to wait for a free worker." );
+    writeln!( output, "bytehound_heavy_request_queue_wait_seconds_sum {}", queue_wait ).unwrap();
+    writeln!( output, "bytehound_heavy_request_queue_wait_seconds_count {}", processed ).unwrap();
+
+    Ok( HttpResponse::Ok().content_type( "text/plain; version=0.0.4" ).body( output ) )
+}
+
+fn get_fragmentation_timeline( data: &Data ) -> protocol::ResponseFragmentationTimeline {
+    #[inline(always)]
+    fn is_matched( allocation: &Allocation ) -> bool {
+        allocation.in_main_arena() && !allocation.is_mmaped() && !allocation.is_jemalloc()
+    }
+
+    let maximum_len = (data.last_timestamp().as_secs() - data.initial_timestamp().as_secs()) as usize;
+    let mut xs = Vec::with_capacity( maximum_len );
+    let mut x = (-1_i32) as u64;
+
+    let mut current_used_address_space = 0;
+    let mut fragmentation = Vec::with_capacity( maximum_len );
+    let mut address_map: BTreeMap< u64, i64 > = BTreeMap::new();
+    let mut current_address_min = std::u64::MAX;
+    let mut current_address_max = 0;
+
+    fn trim_front( address_map: &mut BTreeMap< u64, i64 > ) {
+        while let Some( (&address, &count) ) = address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).next() {
+            if count == 0 {
+                address_map.remove( &address );
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn trim_back( address_map: &mut BTreeMap< u64, i64 > ) {
+        while let Some( (&address, &count) ) = address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).rev().next() {
+            if count == 0 {
+                address_map.remove( &address );
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn min( address_map: &BTreeMap< u64, i64 > ) -> u64 {
+        for (&address, &count) in address_map.range( (Unbounded as Bound< u64 >, Unbounded) ) {
+            if count == 0 {
+                continue;
+            }
+
+            return address;
+        }
+
+        std::u64::MAX
+    }
+
+    fn max( address_map: &BTreeMap< u64, i64 > ) -> u64 {
+        for (&address, &count) in address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).rev() {
+            if count == 0 {
+                continue;
+            }
+
+            return address;
+        }
+
+        0
+    }
+
+    for op in data.operations() {
+        let timestamp = match op {
+            Operation::Allocation { allocation, .. } => {
+                if !is_matched( allocation ) {
+                    continue;
+                }
+
+                let range = allocation.actual_range( &data );
+                *address_map.entry( range.start ).or_insert( 0 ) += 1;
+                *address_map.entry( range.end ).or_insert( 0 ) += 1;
+                current_used_address_space += range.end - range.start;
+
+                if range.start < current_address_min {
+                    current_address_min = range.start;
+                }
+
+                if range.end > current_address_max {
+                    current_address_max = range.end;
+                }
+
+                allocation.timestamp
+            },
+            Operation::Deallocation { allocation, deallocation, .. } => {
+                if !is_matched( allocation ) {
+                    continue;
+                }
+
+                let range = allocation.actual_range( &data );
+                *address_map.entry( range.start ).or_insert( 0 ) -= 1;
+                *address_map.entry( range.end ).or_insert( 0 ) -= 1;
+                current_used_address_space -= range.end - range.start;
+
+                if range.start == current_address_min {
+                    trim_front( &mut address_map );
+                    current_address_min = min( &address_map );
+                }
+
+                if range.end == current_address_max {
+                    trim_back( &mut address_map );
+                    current_address_max = max( &address_map );
+                }
+
+                deallocation.timestamp
+            },
+            Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                if !is_matched( new_allocation ) && !is_matched( old_allocation ) {
+                    continue;
+                }
+
+                if is_matched( old_allocation ) {
+                    let old_range = old_allocation.actual_range( &data );
+                    *address_map.entry( old_range.start ).or_insert( 0 ) -= 1;
+                    *address_map.entry( old_range.end ).or_insert( 0 ) -= 1;
+
+                    current_used_address_space -= old_range.end - old_range.start;
+
+                    if old_range.start == current_address_min {
+                        trim_front( &mut address_map );
+                        current_address_min = min( &address_map );
+                    }
+
+                    if old_range.end == current_address_max {
+                        trim_back( &mut address_map );
+                        current_address_max = max( &address_map );
+                    }
+                }
+
+                if is_matched( new_allocation ) {
+                    let new_range = new_allocation.actual_range( &data );
+                    *address_map.entry( new_range.start ).or_insert( 0 ) += 1;
+                    *address_map.entry( new_range.end ).or_insert( 0 ) += 1;
+                    current_used_address_space += new_range.end - new_range.start;
+
+                    if new_range.start < current_address_min {
+                        current_address_min = new_range.start;
+                    }
+
+                    if new_range.end > current_address_max {
+                        current_address_max = new_range.end;
+                    }
+                }
+
+                new_allocation.timestamp
+            }
+        };
+
+        let timestamp = timestamp.as_secs();
+        if timestamp != x {
+            if x != (-1_i32 as u64) && x + 1 != timestamp {
+                let last_fragmentation = fragmentation.last().cloned().unwrap();
+
+                xs.push( (x + 1) * 1000 );
+                fragmentation.push( last_fragmentation );
+
+                if x + 2 != timestamp {
+                    xs.push( (timestamp - 1) * 1000 );
+                    fragmentation.push( last_fragmentation );
+                }
+            }
+
+            x = timestamp;
+            xs.push( x * 1000 );
+            fragmentation.push( 0 );
+        }
+
+        let range = if current_address_max == 0 {
+            0
+        } else {
+            current_address_max - current_address_min
+        };
+
+        *fragmentation.last_mut().unwrap() = range - current_used_address_space;
+    }
+
+    protocol::ResponseFragmentationTimeline {
+        xs,
+        fragmentation
+    }
+}
+
+fn handler_fragmentation_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let response = req.state().fragmentation_timeline_cache.get_or_compute( data.id(), || get_fragmentation_timeline( data ) );
+    Ok( HttpResponse::Ok().json( &*response ) )
+}
+
+fn build_timeline( data: &Data, ops: &[OperationId] ) -> protocol::ResponseTimeline {
+    let timeline = cli_core::build_timeline( data, data.initial_timestamp(), data.last_timestamp(), ops );
+
+    let mut xs = Vec::with_capacity( timeline.len() );
+    let mut size_delta = Vec::with_capacity( timeline.len() );
+    let mut count_delta = Vec::with_capacity( timeline.len() );
+    let mut allocated_size = Vec::with_capacity( timeline.len() );
+    let mut allocated_count = Vec::with_capacity( timeline.len() );
+    let mut allocations = Vec::with_capacity( timeline.len() );
+    let mut deallocations = Vec::with_capacity( timeline.len() );
+
+    let mut last_size = 0;
+    let mut last_count = 0;
+    for point in timeline {
+        xs.push( point.timestamp / 1000 );
+        size_delta.push( point.memory_usage as i64 - last_size );
+        count_delta.push( point.allocations as i64 - last_count );
+        allocated_size.push( point.memory_usage );
+        allocated_count.push( point.allocations );
+        allocations.push( point.allocations_per_time as u32 );
+        deallocations.push( point.deallocations_per_time as u32 );
+
+        last_size = point.memory_usage as i64;
+        last_count = point.allocations as i64;
+    }
+
+    protocol::ResponseTimeline {
+        xs,
+        size_delta,
+        count_delta,
+        allocated_size,
+        allocated_count,
+        allocations,
+        deallocations,
+        markers: None,
+        xs_wall_clock: None
+    }
+}
+
+/// The optional additions to a timeline which were requested; parsed up front so that
+/// they can be added once the timeline itself is computed.
+struct TimelineExtras {
+    include_markers: bool,
+    marked_backtrace: Option< BacktraceId >,
+    wall_clock: bool
+}
+
+impl TimelineExtras {
+    fn new( req: &HttpRequest, data: &Data ) -> Result< Self > {
+        let markers: protocol::RequestTimelineMarkers = query( req )?;
+        let wall_clock: protocol::RequestWallClock = query( req )?;
+        let include_markers = markers.include_markers.unwrap_or( false );
+        let mut marked_backtrace = None;
+        if include_markers {
+            let filter: protocol::AllocFilter = query( req )?;
+            if let Some( backtrace_id ) = filter.backtraces {
+                if backtrace_id as usize >= data.all_backtraces().len() {
+                    return Err( ApiError::invalid_parameter( "backtraces", "invalid 'backtraces'" ).into() );
+                }
+
+                marked_backtrace = Some( BacktraceId::new( backtrace_id ) );
+            }
+        }
+
+        Ok( TimelineExtras {
+            include_markers,
+            marked_backtrace,
+            wall_clock: wall_clock.wall_clock.unwrap_or( false )
+        })
+    }
+
+    fn add_to( &self, data: &Data, timeline: &mut protocol::ResponseTimeline ) {
+        if self.include_markers {
+            add_timeline_markers( data, self.marked_backtrace, timeline );
+        }
+
+        if self.wall_clock {
+            timeline.xs_wall_clock = Some( data.wall_clock_start().map( |_| {
+                timeline.xs.iter().map( |&x| to_iso8601( Timestamp::from_msecs( x ) ) ).collect()
+            }));
+        }
+    }
+}
+
+fn add_timeline_markers( data: &Data, marked_backtrace: Option< BacktraceId >, timeline: &mut protocol::ResponseTimeline ) {
+    let mut markers = Vec::new();
+    if let Some( (index, &value) ) = timeline.allocated_size.iter().enumerate().max_by_key( |&(index, value)| (value, std::cmp::Reverse( index )) ) {
+        markers.push( protocol::TimelineMarker {
+            kind: protocol::TimelineMarkerKind::GlobalPeak,
+            x: timeline.xs[ index ],
+            value,
+            backtrace_id: None
+        });
+    }
+
+    if let Some( backtrace_id ) = marked_backtrace {
+        let ops = operations_for_allocations( data, data.get_allocation_ids_by_backtrace( backtrace_id ) );
+        if let Some( (index, value) ) = find_peak( data, &ops ) {
+            markers.push( protocol::TimelineMarker {
+                kind: protocol::TimelineMarkerKind::GroupPeak,
+                x: get_operation_timestamp( data, ops[ index ] ).as_usecs() / 1000,
+                value: value as u64,
+                backtrace_id: Some( backtrace_id.raw() )
+            });
+        }
+    }
+
+    timeline.markers = Some( markers );
+}
+
+fn get_operation_timestamp( data: &Data, op: OperationId ) -> Timestamp {
+    let allocation = data.get_allocation( op.id() );
+    if op.is_deallocation() {
+        allocation.deallocation.as_ref().unwrap().timestamp
+    } else {
+        allocation.timestamp
+    }
+}
+
+/// Generates a sorted list of operations for the given allocations,
+/// treating each reallocation as a deallocation followed by an allocation.
+fn operations_for_allocations( data: &Data, ids: &[AllocationId] ) -> Vec< OperationId > {
+    let mut ops = Vec::with_capacity( ids.len() * 2 );
+    for &id in ids {
+        ops.push( OperationId::new_allocation( id ) );
+        if data.get_allocation( id ).deallocation.is_some() {
+            ops.push( OperationId::new_deallocation( id ) );
+        }
+    }
+
+    ops.par_sort_by_key( |&op| get_operation_timestamp( data, op ) );
+    ops
+}
+
+fn matching_allocation_ids( data: &Data, filter: &AllocationFilter ) -> Vec< AllocationId > {
+    prefiltered_allocation_ids( data, protocol::AllocSortBy::Timestamp, filter )
+        .par_iter()
+        .copied()
+        .filter( |&id| filter.try_match( data, id, data.get_allocation( id ) ) )
+        .collect()
+}
+
+/// The allocated size and the allocation count of one of the series of a stacked timeline.
+type StackedTimelineSeries = (Vec< u64 >, Vec< u64 >);
+
+/// Builds a timeline for each series separately and then aligns
+/// them so that they all share the same X axis.
+fn build_stacked_timeline( data: &Data, ops_for_series: &[Vec< OperationId >], window: &protocol::TimelineWindow ) -> (Vec< u64 >, Vec< StackedTimelineSeries >) {
+    let timelines: Vec< _ > = ops_for_series.par_iter().map( |ops| {
+        if ops.is_empty() {
+            return (Vec::new(), 0..=0);
+        }
+
+        build_unclipped_timeline_points( data, ops, window )
+    }).collect();
+
+    // The points before the window are still needed to know the usage at the start of the window.
+    let mut xs: Vec< u64 > = timelines.iter().flat_map( |(timeline, range)| {
+        timeline.iter().map( |point| point.timestamp ).filter( move |timestamp| range.contains( timestamp ) )
+    }).collect();
+    xs.par_sort_unstable();
+    xs.dedup();
+
+    let series = timelines.into_par_iter().map( |(timeline, _)| {
+        let mut allocated_size = Vec::with_capacity( xs.len() );
+        let mut allocated_count = Vec::with_capacity( xs.len() );
+        let mut points = timeline.into_iter().peekable();
+        let mut last_size = 0;
+        let mut last_count = 0;
+        for &x in &xs {
+            while let Some( point ) = points.peek() {
+                if point.timestamp > x {
+                    break;
+                }
+
+                last_size = point.memory_usage;
+                last_count = point.allocations;
+                points.next();
+            }
+
+            allocated_size.push( last_size );
+            allocated_count.push( last_count );
+        }
+
+        (allocated_size, allocated_count)
+    }).collect();
+
+    let xs = xs.into_iter().map( |x| x / 1000 ).collect();
+    (xs, series)
+}
+
+fn backtrace_label( data: &Data, backtrace_id: BacktraceId ) -> String {
+    let frame = data.get_backtrace( backtrace_id ).rev().map( |(_, frame)| frame ).find( |frame| frame.any_function().is_some() );
+    if let Some( frame ) = frame {
+        data.interner().resolve( frame.any_function().unwrap() ).unwrap().to_owned()
+    } else if let Some( (_, frame) ) = data.get_backtrace( backtrace_id ).last() {
+        format!( "0x{:016X}", frame.address().raw() )
+    } else {
+        format!( "Backtrace #{}", backtrace_id.raw() )
+    }
+}
+
+/// Partitions the operations into the `top` series which had the highest peak
+/// memory usage, plus an extra series for everything else, and builds a stacked
+/// timeline out of them.
+fn build_top_series_timeline< K, F >( data: &Data, ops: Vec< OperationId >, top: usize, window: &protocol::TimelineWindow, get_key: F ) -> (Vec< u64 >, Vec< (Option< K >, StackedTimelineSeries) >)
+    where K: Copy + Eq + Ord + std::hash::Hash,
+          F: Fn( &Allocation ) -> K
+{
+    let mut usage_per_key: HashMap< K, (i64, i64) > = HashMap::new();
+    for &op in &ops {
+        let allocation = data.get_allocation( op.id() );
+        let entry = usage_per_key.entry( get_key( allocation ) ).or_insert( (0, 0) );
+        if op.is_deallocation() {
+            entry.0 -= allocation.size as i64;
+        } else {
+            entry.0 += allocation.size as i64;
+            entry.1 = max( entry.1, entry.0 );
+        }
+    }
+
+    let mut keys: Vec< _ > = usage_per_key.into_iter().map( |(key, (_, peak))| (key, peak) ).collect();
+    keys.sort_unstable_by_key( |&(key, peak)| (std::cmp::Reverse( peak ), key) );
+    keys.truncate( top );
+
+    let index_for_key: HashMap< K, usize > = keys.iter().enumerate().map( |(index, &(key, _))| (key, index) ).collect();
+    let mut ops_for_series = vec![ Vec::new(); keys.len() + 1 ];
+    for op in ops {
+        let key = get_key( data.get_allocation( op.id() ) );
+        let index = index_for_key.get( &key ).copied().unwrap_or( keys.len() );
+        ops_for_series[ index ].push( op );
+    }
+
+    let (xs, timelines) = build_stacked_timeline( data, &ops_for_series, window );
+    let series = timelines.into_iter().enumerate().map( |(index, series)| {
+        (keys.get( index ).map( |&(key, _)| key ), series)
+    }).collect();
+
+    (xs, series)
+}
+
+fn handler_timeline_by_group( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestStackedTimeline = query( &req )?;
+    let window: protocol::TimelineWindow = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+    let top = params.top.unwrap_or( 10 ) as usize;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let allocation_ids = matching_allocation_ids( &data, &filter );
+        let ops = operations_for_allocations( &data, &allocation_ids );
+        let (xs, series) = build_top_series_timeline( &data, ops, top, &window, |allocation| allocation.backtrace );
+        let series = series.into_iter().map( |(backtrace_id, (allocated_size, allocated_count))| {
+            protocol::TimelineSeries {
+                backtrace_id: backtrace_id.map( |backtrace_id| backtrace_id.raw() ),
+                label: backtrace_id.map( |backtrace_id| backtrace_label( &data, backtrace_id ) ).unwrap_or_else( || "other".into() ),
+                allocated_size,
+                allocated_count
+            }
+        }).collect();
+
+        let response = protocol::ResponseStackedTimeline {
+            xs,
+            series
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_library_by_backtrace( state: &State, data: &Data ) -> Arc< Vec< Option< StringId > > > {
+    if let Some( libraries ) = state.library_by_backtrace_cache.lock().get( &data.id() ) {
+        return libraries.clone();
+    }
+
+    let libraries: Vec< _ > = data.all_backtraces().map( |(_, frames)| {
+        frames.rev().map( |(_, frame)| frame ).find_map( |frame| frame.library() )
+    }).collect();
+
+    let libraries = Arc::new( libraries );
+    state.library_by_backtrace_cache.lock().put( data.id(), libraries.clone() );
+    libraries
+}
+
+fn handler_timeline_by_library( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestStackedTimeline = query( &req )?;
+    let window: protocol::TimelineWindow = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+    let top = params.top.unwrap_or( 10 ) as usize;
+    let state = req.state().clone();
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let libraries = get_library_by_backtrace( &state, &data );
+        let allocation_ids = matching_allocation_ids( &data, &filter );
+        let ops = operations_for_allocations( &data, &allocation_ids );
+        let (xs, series) = build_top_series_timeline( &data, ops, top, &window, |allocation| libraries[ allocation.backtrace.raw() as usize ] );
+        let series = series.into_iter().map( |(library, (allocated_size, allocated_count))| {
+            let label = match library {
+                Some( Some( library ) ) => data.interner().resolve( library ).unwrap().to_owned(),
+                Some( None ) => "???".into(),
+                None => "other".into()
+            };
+
+            protocol::TimelineSeries {
+                backtrace_id: None,
+                label,
+                allocated_size,
+                allocated_count
+            }
+        }).collect();
+
+        let response = protocol::ResponseStackedTimeline {
+            xs,
+            series
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+#[test]
+fn test_stacked_timeline_window() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let mut events = vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000 ].into() }
+    ];
+
+    for index in 0..100 {
+        events.push( test_alloc( 1000 + index, 0x10000 + index * 0x100, 100, 1 + index % 2 ) );
+    }
+
+    let data = load_test_data( DataId::new( 1, 2 ), events );
+    let window_start = data.initial_timestamp() + Timestamp::from_usecs( (data.last_timestamp() - data.initial_timestamp()).as_usecs() * 95 / 100 );
+    let (_, mut app) = test_app( vec![ data ] );
+
+    for endpoint in &[ "timeline_by_group", "timeline_by_library" ] {
+        let request = test::TestRequest::get().uri( &format!( "/data/last/{}", endpoint ) ).to_request();
+        let response: serde_json::Value = test::read_response_json( &mut app, request );
+        let xs = response[ "xs" ].as_array().unwrap();
+        assert!( xs.len() > 20 );
+        assert!( xs[ 0 ].as_u64().unwrap() <= 1_000_000 );
+
+        let request = test::TestRequest::get().uri( &format!( "/data/last/{}?resolution=10&window_start=95%25", endpoint ) ).to_request();
+        let response: serde_json::Value = test::read_response_json( &mut app, request );
+        let xs = response[ "xs" ].as_array().unwrap();
+        assert!( !xs.is_empty() && xs.len() <= 12 );
+        assert!( xs.iter().all( |x| x.as_u64().unwrap() >= window_start.as_msecs() ) );
+
+        // The usage at the start of the window includes what was allocated before it.
+        let total: u64 = response[ "series" ].as_array().unwrap().iter().map( |series| series[ "allocated_size" ][ 0 ].as_u64().unwrap() ).sum();
+        assert!( total >= 4000 );
+    }
+}
+
+/// Builds a timeline with the resolution requested by the client, and returns
+/// it along with the range of timestamps which fall within the requested time window.
+fn build_unclipped_timeline_points( data: &Data, ops: &[OperationId], window: &protocol::TimelineWindow ) -> (Vec< cli_core::TimelinePoint >, std::ops::RangeInclusive< u64 >) {
+    let start = window.window_start.map( |ts| data.initial_timestamp() + ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( data.initial_timestamp() );
+    let end = window.window_end.map( |ts| data.initial_timestamp() + ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( data.last_timestamp() );
+    let end = max( start, min( end, data.last_timestamp() ) );
+    let resolution = min( max( window.resolution.unwrap_or( 1000 ), 1 ), 100_000 );
+
+    let timeline = cli_core::build_timeline_with_resolution( data, start, end, ops, resolution as u64 );
+    let range = if window.window_start.is_some() || window.window_end.is_some() {
+        start.as_usecs()..=end.as_usecs()
+    } else {
+        0..=std::u64::MAX
+    };
+
+    (timeline, range)
+}
+
+/// Builds a timeline with the resolution and the time window requested by the client.
+fn build_timeline_points( data: &Data, ops: &[OperationId], window: &protocol::TimelineWindow ) -> Vec< cli_core::TimelinePoint > {
+    let (mut timeline, range) = build_unclipped_timeline_points( data, ops, window );
+    timeline.retain( |point| range.contains( &point.timestamp ) );
+    timeline
+}
+
+fn build_cumulative_timeline( timeline: &[cli_core::TimelinePoint], range: std::ops::RangeInclusive< u64 > ) -> protocol::ResponseTimelineCumulative {
+    let mut output = protocol::ResponseTimelineCumulative {
+        xs: Vec::new(),
+        allocated_size: Vec::new(),
+        allocated_count: Vec::new(),
+        freed_size: Vec::new(),
+        freed_count: Vec::new()
+    };
+
+    let mut allocated_size = 0;
+    let mut allocated_count = 0;
+    let mut freed_size = 0;
+    let mut freed_count = 0;
+    for point in timeline {
+        allocated_size += point.allocated_size_per_time;
+        allocated_count += point.allocations_per_time;
+        freed_size += point.freed_size_per_time;
+        freed_count += point.deallocations_per_time;
+
+        if !range.contains( &point.timestamp ) {
+            continue;
+        }
+
+        output.xs.push( point.timestamp / 1000 );
+        output.allocated_size.push( allocated_size );
+        output.allocated_count.push( allocated_count );
+        output.freed_size.push( freed_size );
+        output.freed_count.push( freed_count );
+    }
+
+    output
+}
+
+#[test]
+fn test_build_cumulative_timeline() {
+    fn point( timestamp: u64, allocations: u64, allocated_size: u64, deallocations: u64, freed_size: u64 ) -> cli_core::TimelinePoint {
+        cli_core::TimelinePoint {
+            timestamp,
+            memory_usage: 0,
+            extra_usable_space: 0,
+            allocations: 0,
+            allocations_per_time: allocations,
+            deallocations_per_time: deallocations,
+            allocated_size_per_time: allocated_size,
+            freed_size_per_time: freed_size
+        }
+    }
+
+    let timeline = [
+        point( 1000, 2, 30, 0, 0 ),
+        point( 2000, 1, 5, 1, 10 ),
+        point( 3000, 0, 0, 2, 25 ),
+        point( 3001, 0, 0, 0, 0 )
+    ];
+
+    let output = build_cumulative_timeline( &timeline, 0..=std::u64::MAX );
+    assert_eq!( output.xs, [1, 2, 3, 3] );
+    assert_eq!( output.allocated_count, [2, 3, 3, 3] );
+    assert_eq!( output.allocated_size, [30, 35, 35, 35] );
+    assert_eq!( output.freed_count, [0, 1, 3, 3] );
+    assert_eq!( output.freed_size, [0, 10, 35, 35] );
+
+    let output = build_cumulative_timeline( &timeline, 2000..=3000 );
+    assert_eq!( output.xs, [2, 3] );
+    assert_eq!( output.allocated_size, [35, 35] );
+    assert_eq!( output.freed_size, [10, 35] );
+}
+
+fn handler_timeline_cumulative( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let window: protocol::TimelineWindow = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let allocation_ids = matching_allocation_ids( &data, &filter );
+        let ops = operations_for_allocations( &data, &allocation_ids );
+        let (timeline, range) = build_unclipped_timeline_points( &data, &ops, &window );
+        let response = build_cumulative_timeline( &timeline, range );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn handler_timeline_waste( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let window: protocol::TimelineWindow = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let allocation_ids = matching_allocation_ids( &data, &filter );
+        let ops = operations_for_allocations( &data, &allocation_ids );
+        let timeline = build_timeline_points( &data, &ops, &window );
+
+        let response = protocol::ResponseTimelineWaste {
+            xs: timeline.iter().map( |point| point.timestamp / 1000 ).collect(),
+            allocated_size: timeline.iter().map( |point| point.memory_usage ).collect(),
+            extra_usable_space: timeline.iter().map( |point| point.extra_usable_space ).collect()
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn handler_timeline_rates( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let window: protocol::TimelineWindow = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let allocation_ids = matching_allocation_ids( &data, &filter );
+        let ops = operations_for_allocations( &data, &allocation_ids );
+        let timeline = build_timeline_points( &data, &ops, &window );
+
+        let response = protocol::ResponseTimelineRates {
+            xs: timeline.iter().map( |point| point.timestamp / 1000 ).collect(),
+            allocated_size: timeline.iter().map( |point| point.allocated_size_per_time ).collect(),
+            freed_size: timeline.iter().map( |point| point.freed_size_per_time ).collect(),
+            net_size: timeline.iter().map( |point| point.allocated_size_per_time as i64 - point.freed_size_per_time as i64 ).collect(),
+            allocations: timeline.iter().map( |point| point.allocations_per_time ).collect(),
+            deallocations: timeline.iter().map( |point| point.deallocations_per_time ).collect()
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn handler_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let timeline = req.state().timeline_cache.get_or_compute( data.id(), || build_timeline( &data, data.operation_ids() ) );
+    let mut timeline = (*timeline).clone();
+    TimelineExtras::new( &req, data )?.add_to( data, &mut timeline );
+    Ok( HttpResponse::Ok().json( timeline ) )
+}
+
+fn handler_timeline_leaked( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let ops: Vec< _ > = data.operation_ids().par_iter().flat_map( |op| {
+        let allocation = data.get_allocation( op.id() );
+        if allocation.deallocation.is_some() {
+            None
+        } else {
+            Some( OperationId::new_allocation( op.id() ) )
+        }
+    }).collect();
+
+    let mut timeline = build_timeline( &data, &ops );
+    TimelineExtras::new( &req, data )?.add_to( data, &mut timeline );
+    Ok( HttpResponse::Ok().json( timeline ) )
+}
+
+/// Builds the timeline of the allocations which were freed within the same bucket or within `max_lifetime`.
+fn build_temporary_timeline( data: &Data, granularity: u64, max_lifetime: Option< u64 > ) -> protocol::ResponseTimeline {
+    let is_temporary = |id: AllocationId| {
+        let allocation = data.get_allocation( id );
+        let deallocation = match allocation.deallocation {
+            Some( ref deallocation ) => deallocation,
+            None => return false
+        };
+
+        if allocation.timestamp.as_usecs() / granularity == deallocation.timestamp.as_usecs() / granularity {
+            return true;
+        }
+
+        if let Some( max_lifetime ) = max_lifetime {
+            (deallocation.timestamp - allocation.timestamp).as_usecs() < max_lifetime
+        } else {
+            false
+        }
+    };
+
+    // Every allocation is treated separately here, so a reallocation
+    // is split into a deallocation of the old allocation and a fresh allocation.
+    let mut ops = Vec::new();
+    for op in data.operation_ids() {
+        let id = op.id();
+        if op.is_deallocation() {
+            if is_temporary( id ) {
+                ops.push( *op );
+            }
+            continue;
+        }
+
+        if op.is_reallocation() {
+            let old_id = data.get_allocation( id ).reallocated_from.unwrap();
+            if is_temporary( old_id ) {
+                ops.push( OperationId::new_deallocation( old_id ) );
+            }
+        }
+
+        if is_temporary( id ) {
+            ops.push( OperationId::new_allocation( id ) );
+        }
+    }
+
+    build_timeline( data, &ops )
+}
+
+fn handler_timeline_temporary( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestTimelineTemporary = query( &req )?;
+    let extras = TimelineExtras::new( &req, data )?;
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        // This has to match the granularity used by `cli_core::build_timeline`.
+        let granularity = max( (data.last_timestamp() - data.initial_timestamp()).as_usecs() / 1000, 1 );
+        let max_lifetime = params.max_lifetime_ms.map( |value| value * 1000 );
+        let timeline = state.temporary_timeline_cache.get_or_compute( (data.id(), max_lifetime), || build_temporary_timeline( &data, granularity, max_lifetime ) );
+        let mut timeline = (*timeline).clone();
+        extras.add_to( &data, &mut timeline );
+
+        let response = protocol::ResponseTimelineTemporary {
+            max_lifetime_ms: params.max_lifetime_ms,
+            bucket_duration_us: granularity,
+            timeline
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+/// Returns the index of the operation at which the memory usage
+/// was the highest, along with that usage.
+fn find_peak( data: &Data, ops: &[OperationId] ) -> Option< (usize, i64) > {
+    let mut usage: i64 = 0;
+    let mut peak = None;
+    for (index, &op) in ops.iter().enumerate() {
+        let allocation = data.get_allocation( op.id() );
+        if op.is_deallocation() {
+            usage -= allocation.size as i64;
+        } else {
+            usage += allocation.size as i64;
+            if usage > peak.map( |(_, peak_usage)| peak_usage ).unwrap_or( 0 ) {
+                peak = Some( (index, usage) );
+            }
+        }
+    }
+
+    peak
+}
+
+fn get_peak( data: &Data, ops: &[OperationId], top: usize ) -> protocol::ResponsePeak {
+    let (peak_index, peak_usage) = match find_peak( data, ops ) {
+        Some( peak ) => peak,
+        None => {
+            return protocol::ResponsePeak {
+                timestamp: data.initial_timestamp().into(),
+                timestamp_relative: Timestamp::min().into(),
+                allocated_size: 0,
+                allocated_count: 0,
+                groups: Vec::new()
+            };
+        }
+    };
+
+    let mut live_per_backtrace: HashMap< BacktraceId, (i64, i64) > = HashMap::new();
+    for &op in &ops[ ..=peak_index ] {
+        let allocation = data.get_allocation( op.id() );
+        let entry = live_per_backtrace.entry( allocation.backtrace ).or_insert( (0, 0) );
+        if op.is_deallocation() {
+            entry.0 -= allocation.size as i64;
+            entry.1 -= 1;
+        } else {
+            entry.0 += allocation.size as i64;
+            entry.1 += 1;
+        }
+    }
+
+    let allocated_count = live_per_backtrace.values().map( |&(_, count)| count ).sum::< i64 >();
+    let mut groups: Vec< _ > = live_per_backtrace.into_iter().filter( |&(_, (size, _))| size > 0 ).collect();
+    groups.sort_unstable_by_key( |&(backtrace_id, (size, _))| (std::cmp::Reverse( size ), backtrace_id) );
+    groups.truncate( top );
+
+    let timestamp = get_operation_timestamp( data, ops[ peak_index ] );
+    protocol::ResponsePeak {
+        timestamp: timestamp.into(),
+        timestamp_relative: (timestamp - data.initial_timestamp()).into(),
+        allocated_size: peak_usage as u64,
+        allocated_count: max( allocated_count, 0 ) as u64,
+        groups: groups.into_iter().map( |(backtrace_id, (size, count))| {
+            protocol::LiveGroup {
+                backtrace_id: backtrace_id.raw(),
+                label: backtrace_label( data, backtrace_id ),
+                live_size: size as u64,
+                live_count: max( count, 0 ) as u64
+            }
+        }).collect()
+    }
+}
+
+fn handler_peak( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestPeak = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+    let top = params.top.unwrap_or( 10 ) as usize;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let allocation_ids = matching_allocation_ids( &data, &filter );
+        let ops = operations_for_allocations( &data, &allocation_ids );
+        let response = get_peak( &data, &ops, top );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_leak_rates( data: &Data, ops: &[OperationId], params: protocol::RequestLeakRates ) -> protocol::ResponseLeakRates {
+    #[derive(Clone, Default)]
+    struct Regression {
+        live_size: i64,
+        sum_y: f64,
+        sum_yy: f64,
+        sum_xy: f64
+    }
+
+    let mut index_for_backtrace = HashMap::new();
+    let mut groups: Vec< (BacktraceId, Regression) > = Vec::new();
+    for &op in ops {
+        let backtrace_id = data.get_allocation( op.id() ).backtrace;
+        index_for_backtrace.entry( backtrace_id ).or_insert_with( || {
+            groups.push( (backtrace_id, Regression::default()) );
+            groups.len() - 1
+        });
+    }
+
+    let sample_count = min( max( params.samples.unwrap_or( 100 ), 2 ), 10_000 ) as u64;
+    let range = (data.last_timestamp() - data.initial_timestamp()).as_usecs();
+    let sample_at = |index: u64| data.initial_timestamp() + Timestamp::from_usecs( range * (index + 1) / sample_count );
+
+    let mut sum_x = 0.0;
+    let mut sum_xx = 0.0;
+    let mut sample_index = 0;
+    let mut take_sample = |sample_index: u64, groups: &mut Vec< (BacktraceId, Regression) >| {
+        let x = (sample_at( sample_index ) - data.initial_timestamp()).as_usecs() as f64 / 1_000_000.0;
+        sum_x += x;
+        sum_xx += x * x;
+        for (_, group) in groups.iter_mut() {
+            let y = max( group.live_size, 0 ) as f64;
+            group.sum_y += y;
+            group.sum_yy += y * y;
+            group.sum_xy += x * y;
+        }
+    };
+
+    for &op in ops {
+        let timestamp = get_operation_timestamp( data, op );
+        while sample_index < sample_count && sample_at( sample_index ) < timestamp {
+            take_sample( sample_index, &mut groups );
+            sample_index += 1;
+        }
+
+        let allocation = data.get_allocation( op.id() );
+        let group = &mut groups[ index_for_backtrace[ &allocation.backtrace ] ].1;
+        if op.is_deallocation() {
+            group.live_size -= allocation.size as i64;
+        } else {
+            group.live_size += allocation.size as i64;
+        }
+    }
+
+    while sample_index < sample_count {
+        take_sample( sample_index, &mut groups );
+        sample_index += 1;
+    }
+
+    let n = sample_count as f64;
+    let project_at = params.project_at.map( |interval| interval.0.as_usecs() as f64 / 1_000_000.0 );
+    let mut output: Vec< _ > = groups.into_iter().map( |(backtrace_id, group)| {
+        let denominator_x = n * sum_xx - sum_x * sum_x;
+        let denominator_y = n * group.sum_yy - group.sum_y * group.sum_y;
+        let numerator = n * group.sum_xy - sum_x * group.sum_y;
+        let slope = if denominator_x != 0.0 { numerator / denominator_x } else { 0.0 };
+        let intercept = (group.sum_y - slope * sum_x) / n;
+        let r_squared = if denominator_x != 0.0 && denominator_y != 0.0 {
+            (numerator * numerator) / (denominator_x * denominator_y)
+        } else {
+            0.0
+        };
+
+        protocol::LeakRate {
+            backtrace_id: backtrace_id.raw(),
+            label: backtrace_label( data, backtrace_id ),
+            slope,
+            r_squared,
+            final_size: max( group.live_size, 0 ) as u64,
+            projected_size: project_at.map( |x| intercept + slope * x )
+        }
+    }).collect();
+
+    output.sort_by( |lhs, rhs| rhs.slope.partial_cmp( &lhs.slope ).unwrap_or( std::cmp::Ordering::Equal ).then( lhs.backtrace_id.cmp( &rhs.backtrace_id ) ) );
+    if let Some( top ) = params.top {
+        output.truncate( top as usize );
+    }
+
+    protocol::ResponseLeakRates {
+        project_at: params.project_at.map( |interval| interval.0.into() ),
+        groups: output
+    }
+}
+
+fn handler_leak_rates( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestLeakRates = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let allocation_ids = matching_allocation_ids( &data, &filter );
+        let ops = operations_for_allocations( &data, &allocation_ids );
+        let response = get_leak_rates( &data, &ops, params );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+/// Samples the memory usage at the given offsets relative to the start of the profile.
+fn sample_memory_usage( data: &Data, timeline: &protocol::ResponseTimeline, xs: &[u64] ) -> Vec< Option< u64 > > {
+    let range = (data.last_timestamp() - data.initial_timestamp()).as_usecs();
+    let initial = data.initial_timestamp().as_usecs();
+
+    let mut points = timeline.xs.iter().zip( timeline.allocated_size.iter() ).peekable();
+    let mut last_usage = 0;
+    xs.iter().map( |&x| {
+        if x > range {
+            return None;
+        }
+
+        while let Some( &(&timestamp, &memory_usage) ) = points.peek() {
+            if (timestamp * 1000).saturating_sub( initial ) > x {
+                break;
+            }
+
+            last_usage = memory_usage;
+            points.next();
+        }
+
+        Some( last_usage )
+    }).collect()
+}
+
+fn handler_compare_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let params: protocol::RequestCompare = query( &req )?;
+    let id_a = parse_data_id( &req, &params.a )?;
+    let id_b = parse_data_id( &req, &params.b )?;
+
+    let state = req.state().clone();
+    let body = async_handler( &req, move |tx| {
+        let (data_a, data_b) = match (state.get_data( id_a ), state.get_data( id_b )) {
+            (Some( data_a ), Some( data_b )) => (data_a, data_b),
+            _ => {
+                // One of them was unloaded in the meantime.
+                tx.abort();
+                return;
+            }
+        };
+
+        let range_a = (data_a.last_timestamp() - data_a.initial_timestamp()).as_usecs();
+        let range_b = (data_b.last_timestamp() - data_b.initial_timestamp()).as_usecs();
+        let range = max( range_a, range_b );
+        let granularity = max( range / 1000, 1 );
+        let xs: Vec< _ > = (0..=range / granularity).map( |index| index * granularity ).collect();
+
+        let get_timeline = |data: &Data| state.timeline_cache.get_or_compute( data.id(), || build_timeline( data, data.operation_ids() ) );
+        let (timeline_a, timeline_b) = rayon::join( || get_timeline( &data_a ), || get_timeline( &data_b ) );
+        let a = sample_memory_usage( &data_a, &timeline_a, &xs );
+        let b = sample_memory_usage( &data_b, &timeline_b, &xs );
+
+        let delta = a.iter().zip( b.iter() ).map( |(a, b)| {
+            match (a, b) {
+                (Some( a ), Some( b )) => Some( *b as i64 - *a as i64 ),
+                _ => None
+            }
+        }).collect();
+
+        let response = protocol::ResponseCompareTimeline {
+            xs: xs.into_iter().map( |x| x / 1000 ).collect(),
+            a,
+            b,
+            delta
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_churn( data: &Data, params: protocol::RequestChurn, filter: AllocationFilter ) -> protocol::ResponseChurn {
+    #[derive(Copy, Clone, Default)]
+    struct Churn {
+        pairs: u64,
+        bytes: u64,
+        total_lifetime: u64
+    }
+
+    let churn_per_backtrace = prefiltered_allocation_ids( data, protocol::AllocSortBy::Timestamp, &filter )
+        .par_iter()
+        .fold( || HashMap::new(), |mut churn_per_backtrace: HashMap< BacktraceId, Churn >, &id| {
+            let allocation = data.get_allocation( id );
+            let deallocation = match allocation.deallocation {
+                Some( ref deallocation ) => deallocation,
+                None => return churn_per_backtrace
+            };
+
+            if !filter.try_match( data, id, allocation ) {
+                return churn_per_backtrace;
+            }
+
+            let churn = churn_per_backtrace.entry( allocation.backtrace ).or_default();
+            churn.pairs += 1;
+            churn.bytes += allocation.size;
+            churn.total_lifetime += (deallocation.timestamp - allocation.timestamp).as_usecs();
+            churn_per_backtrace
+        })
+        .reduce( || HashMap::new(), |mut a, mut b| {
+            if b.len() > a.len() {
+                std::mem::swap( &mut a, &mut b );
+            }
+
+            for (backtrace_id, churn) in b {
+                let entry = a.entry( backtrace_id ).or_default();
+                entry.pairs += churn.pairs;
+                entry.bytes += churn.bytes;
+                entry.total_lifetime += churn.total_lifetime;
+            }
+
+            a
+        });
+
+    let min_pairs = params.min_pairs.unwrap_or( 1 );
+    let mut groups: Vec< _ > = churn_per_backtrace.into_iter().filter( |(_, churn)| churn.pairs >= min_pairs ).collect();
+    groups.par_sort_unstable_by_key( |&(backtrace_id, churn)| (std::cmp::Reverse( churn.pairs ), backtrace_id) );
+
+    let total_count = groups.len() as u64;
+    let groups = groups.into_iter()
+        .skip( params.skip.unwrap_or( 0 ) as usize )
+        .take( params.count.unwrap_or( -1_i32 as _ ) as usize )
+        .map( |(backtrace_id, churn)| {
+            protocol::ChurnGroup {
+                backtrace_id: backtrace_id.raw(),
+                label: backtrace_label( data, backtrace_id ),
+                pairs: churn.pairs,
+                bytes: churn.bytes,
+                mean_lifetime: Timestamp::from_usecs( churn.total_lifetime / churn.pairs ).into()
+            }
+        })
+        .collect();
+
+    protocol::ResponseChurn {
+        groups,
+        total_count
+    }
+}
+
+fn handler_churn( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestChurn = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = get_churn( &data, params, filter );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_thread_statistics( data: &Data ) -> protocol::ResponseThreads {
+    struct Stats {
+        allocated_count: u64,
+        allocated_size: u64,
+        live_size: i64,
+        peak_size: i64,
+        first_activity: Timestamp,
+        last_activity: Timestamp,
+        cross_thread_frees: u64
+    }
+
+    fn get_stats( stats_per_thread: &mut HashMap< u32, Stats >, thread: u32, timestamp: Timestamp ) -> &mut Stats {
+        let stats = stats_per_thread.entry( thread ).or_insert_with( || Stats {
+            allocated_count: 0,
+            allocated_size: 0,
+            live_size: 0,
+            peak_size: 0,
+            first_activity: timestamp,
+            last_activity: timestamp,
+            cross_thread_frees: 0
+        });
+
+        stats.first_activity = min( stats.first_activity, timestamp );
+        stats.last_activity = max( stats.last_activity, timestamp );
+        stats
+    }
+
+    fn on_deallocation( stats_per_thread: &mut HashMap< u32, Stats >, allocation: &Allocation ) {
+        let deallocation = allocation.deallocation.as_ref().unwrap();
+        let stats = get_stats( stats_per_thread, deallocation.thread, deallocation.timestamp );
+        if deallocation.thread != allocation.thread {
+            stats.cross_thread_frees += 1;
+        }
+
+        let stats = get_stats( stats_per_thread, allocation.thread, allocation.timestamp );
+        stats.live_size -= allocation.size as i64;
+    }
+
+    let mut stats_per_thread: HashMap< u32, Stats > = HashMap::new();
+    for &op in data.operation_ids() {
+        let allocation = data.get_allocation( op.id() );
+        if op.is_deallocation() {
+            on_deallocation( &mut stats_per_thread, allocation );
+            continue;
+        }
+
+        if op.is_reallocation() {
+            let old_allocation = data.get_allocation( allocation.reallocated_from.unwrap() );
+            on_deallocation( &mut stats_per_thread, old_allocation );
+        }
+
+        let stats = get_stats( &mut stats_per_thread, allocation.thread, allocation.timestamp );
+        stats.allocated_count += 1;
+        stats.allocated_size += allocation.size;
+        stats.live_size += allocation.size as i64;
+        stats.peak_size = max( stats.peak_size, stats.live_size );
+    }
+
+    let mut threads: Vec< _ > = stats_per_thread.into_iter().map( |(thread, stats)| {
+        protocol::ThreadStatistics {
+            thread,
+            allocated_count: stats.allocated_count,
+            allocated_size: stats.allocated_size,
+            leaked_size: max( stats.live_size, 0 ) as u64,
+            peak_size: max( stats.peak_size, 0 ) as u64,
+            first_activity: stats.first_activity.into(),
+            last_activity: stats.last_activity.into(),
+            cross_thread_frees: stats.cross_thread_frees
+        }
+    }).collect();
+
+    threads.sort_by_key( |stats| stats.thread );
+    protocol::ResponseThreads {
+        threads
+    }
+}
+
+fn handler_threads( req: HttpRequest ) -> Result< HttpResponse > {
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = get_thread_statistics( &data );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_snapshot( data: &Data, at: Timestamp, top: usize, filter: &AllocationFilter ) -> protocol::ResponseSnapshot {
+    let live_per_backtrace = data.alloc_sorted_by_timestamp( None, Some( at ) )
+        .par_iter()
+        .fold( || HashMap::new(), |mut live_per_backtrace: HashMap< BacktraceId, (u64, u64) >, &id| {
+            let allocation = data.get_allocation( id );
+            if let Some( ref deallocation ) = allocation.deallocation {
+                if deallocation.timestamp <= at {
+                    return live_per_backtrace;
+                }
+            }
+
+            if !filter.try_match( data, id, allocation ) {
+                return live_per_backtrace;
+            }
+
+            let entry = live_per_backtrace.entry( allocation.backtrace ).or_insert( (0, 0) );
+            entry.0 += allocation.size;
+            entry.1 += 1;
+            live_per_backtrace
+        })
+        .reduce( || HashMap::new(), |mut a, mut b| {
+            if b.len() > a.len() {
+                std::mem::swap( &mut a, &mut b );
+            }
+
+            for (backtrace_id, (size, count)) in b {
+                let entry = a.entry( backtrace_id ).or_insert( (0, 0) );
+                entry.0 += size;
+                entry.1 += count;
+            }
+
+            a
+        });
+
+    let allocated_size = live_per_backtrace.values().map( |&(size, _)| size ).sum();
+    let allocated_count = live_per_backtrace.values().map( |&(_, count)| count ).sum();
+    let mut groups: Vec< _ > = live_per_backtrace.into_iter().collect();
+    groups.par_sort_unstable_by_key( |&(backtrace_id, (size, _))| (std::cmp::Reverse( size ), backtrace_id) );
+    groups.truncate( top );
+
+    protocol::ResponseSnapshot {
+        timestamp: at.into(),
+        timestamp_relative: (at - data.initial_timestamp()).into(),
+        allocated_size,
+        allocated_count,
+        groups: groups.into_iter().map( |(backtrace_id, (size, count))| {
+            protocol::LiveGroup {
+                backtrace_id: backtrace_id.raw(),
+                label: backtrace_label( data, backtrace_id ),
+                live_size: size,
+                live_count: count
+            }
+        }).collect()
+    }
+}
+
+fn handler_snapshot( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestSnapshot = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let at = data.initial_timestamp() + params.at.to_timestamp( data.initial_timestamp(), data.last_timestamp() );
+    let top = params.top.unwrap_or( 10 ) as usize;
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = get_snapshot( &data, at, top, &filter );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_survival( data: &Data, filter: &AllocationFilter ) -> protocol::ResponseSurvival {
+    #[derive(Clone, Default)]
+    struct Bucket {
+        freed_count: u64,
+        freed_size: u64,
+        censored_count: u64,
+        censored_size: u64
+    }
+
+    let runtime = max( (data.last_timestamp() - data.initial_timestamp()).as_usecs(), 1 );
+    let mut durations = Vec::new();
+    let mut duration = 1;
+    while duration < runtime {
+        durations.push( duration );
+        duration *= 10;
+    }
+    durations.push( runtime );
+
+    // Bucket #N covers lifetimes within (durations[N - 1], durations[N]];
+    // the extra last bucket is for anything which lived longer than that.
+    let bucket_count = durations.len() + 1;
+    let buckets = prefiltered_allocation_ids( data, protocol::AllocSortBy::Timestamp, filter )
+        .par_iter()
+        .fold( || vec![ Bucket::default(); bucket_count ], |mut buckets, &id| {
+            let allocation = data.get_allocation( id );
+            if !filter.try_match( data, id, allocation ) {
+                return buckets;
+            }
+
+            let (lifetime, is_leaked) = match allocation.deallocation {
+                Some( ref deallocation ) => (deallocation.timestamp - allocation.timestamp, false),
+                None => (data.last_timestamp() - allocation.timestamp, true)
+            };
+
+            let index = match durations.binary_search( &lifetime.as_usecs() ) {
+                Ok( index ) | Err( index ) => index
+            };
+
+            let bucket = &mut buckets[ index ];
+            if is_leaked {
+                bucket.censored_count += 1;
+                bucket.censored_size += allocation.size;
+            } else {
+                bucket.freed_count += 1;
+                bucket.freed_size += allocation.size;
+            }
+
+            buckets
+        })
+        .reduce( || vec![ Bucket::default(); bucket_count ], |mut a, b| {
+            for (a, b) in a.iter_mut().zip( b ) {
+                a.freed_count += b.freed_count;
+                a.freed_size += b.freed_size;
+                a.censored_count += b.censored_count;
+                a.censored_size += b.censored_size;
+            }
+            a
+        });
+
+    let total_count: u64 = buckets.iter().map( |bucket| bucket.freed_count + bucket.censored_count ).sum();
+    let total_size: u64 = buckets.iter().map( |bucket| bucket.freed_size + bucket.censored_size ).sum();
+    let leaked_count = buckets.iter().map( |bucket| bucket.censored_count ).sum();
+    let leaked_size = buckets.iter().map( |bucket| bucket.censored_size ).sum();
+
+    // This is a Kaplan-Meier estimator where the leaked allocations
+    // are treated as right-censored at the end of the profile.
+    let mut survival_by_count = Vec::with_capacity( durations.len() );
+    let mut survival_by_size = Vec::with_capacity( durations.len() );
+    let mut at_risk_count = total_count;
+    let mut at_risk_size = total_size;
+    let mut current_by_count = 1.0;
+    let mut current_by_size = 1.0;
+    for bucket in &buckets[ ..durations.len() ] {
+        if at_risk_count != 0 {
+            current_by_count *= 1.0 - bucket.freed_count as f64 / at_risk_count as f64;
+        }
+        if at_risk_size != 0 {
+            current_by_size *= 1.0 - bucket.freed_size as f64 / at_risk_size as f64;
+        }
+
+        survival_by_count.push( current_by_count );
+        survival_by_size.push( current_by_size );
+
+        at_risk_count -= bucket.freed_count + bucket.censored_count;
+        at_risk_size -= bucket.freed_size + bucket.censored_size;
+    }
+
+    protocol::ResponseSurvival {
+        durations_us: durations,
+        survival_by_count,
+        survival_by_size,
+        total_count,
+        total_size,
+        leaked_count,
+        leaked_size
+    }
+}
+
+fn handler_survival( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = get_survival( &data, &filter );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_age_distribution( data: &Data, at: Timestamp, filter: &AllocationFilter ) -> protocol::ResponseAgeDistribution {
+    // Bucket #0 is for allocations younger than a microsecond, and bucket #N is for [2^(N-1), 2^N) microseconds.
+    let bucket_count = 65;
+    let buckets = data.alloc_sorted_by_timestamp( None, Some( at ) )
+        .par_iter()
+        .fold( || vec![ (0_u64, 0_u64); bucket_count ], |mut buckets, &id| {
+            let allocation = data.get_allocation( id );
+            if let Some( ref deallocation ) = allocation.deallocation {
+                if deallocation.timestamp <= at {
+                    return buckets;
+                }
+            }
+
+            if !filter.try_match( data, id, allocation ) {
+                return buckets;
+            }
+
+            let age = (at - allocation.timestamp).as_usecs();
+            let bucket = &mut buckets[ (64 - age.leading_zeros()) as usize ];
+            bucket.0 += 1;
+            bucket.1 += allocation.size;
+            buckets
+        })
+        .reduce( || vec![ (0, 0); bucket_count ], |mut a, b| {
+            for (a, b) in a.iter_mut().zip( b ) {
+                a.0 += b.0;
+                a.1 += b.1;
+            }
+            a
+        });
+
+    let last_non_empty = buckets.iter().rposition( |&(count, _)| count != 0 ).map( |index| index + 1 ).unwrap_or( 0 );
+    let buckets = buckets.into_iter().take( last_non_empty ).enumerate().map( |(index, (count, size))| {
+        protocol::AgeBucket {
+            age_min_us: if index == 0 { 0 } else { 1 << (index - 1) },
+            age_max_us: if index == 64 { None } else { Some( 1 << index ) },
+            count,
+            size
+        }
+    }).collect();
+
+    protocol::ResponseAgeDistribution {
+        at: at.into(),
+        at_relative: (at - data.initial_timestamp()).into(),
+        buckets
+    }
+}
+
+fn handler_age_distribution( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestAgeDistribution = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let at = match params.at {
+            protocol::TimestampOrPeak::Timestamp( at ) => data.initial_timestamp() + at.to_timestamp( data.initial_timestamp(), data.last_timestamp() ),
+            protocol::TimestampOrPeak::Peak => {
+                let allocation_ids = matching_allocation_ids( &data, &filter );
+                let ops = operations_for_allocations( &data, &allocation_ids );
+                match find_peak( &data, &ops ) {
+                    Some( (index, _) ) => get_operation_timestamp( &data, ops[ index ] ),
+                    None => data.initial_timestamp()
+                }
+            }
+        };
+
+        let response = get_age_distribution( &data, at, &filter );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn prefiltered_allocation_ids< 'a >(
+    data: &'a Data,
+    sort_by: protocol::AllocSortBy,
+    _filter: &AllocationFilter
+ ) -> &'a [AllocationId] {
+    // TODO: Use the filter to narrow down the range.
+    match sort_by {
+        protocol::AllocSortBy::Timestamp => data.alloc_sorted_by_timestamp( None, None ),
+        protocol::AllocSortBy::Address => data.alloc_sorted_by_address( None, None ),
+        protocol::AllocSortBy::Size => data.alloc_sorted_by_size( None, None )
+    }
+}
+
+fn allocations_iter< 'a >(
+    data: &'a Data,
+    array: &'a [AllocationId],
+    order: protocol::Order,
+    filter: AllocationFilter
+) -> Box< dyn DoubleEndedIterator< Item = (AllocationId, &'a Allocation) > + 'a > {
+    match order {
+        protocol::Order::Asc => Box::new( array.iter()
+            .map( move |&id| (id, data.get_allocation( id )) )
+            .filter( move |(id, allocation)| filter.try_match( data, *id, allocation ) )
+        ),
+        protocol::Order::Dsc => Box::new( array.iter().rev()
+            .map( move |&id| (id, data.get_allocation( id )) )
+            .filter( move |(id, allocation)| filter.try_match( data, *id, allocation ) )
+        )
+    }
+}
+
+fn timestamp_to_fraction( data: &Data, timestamp: Timestamp ) -> f32 {
+    let relative = timestamp - data.initial_timestamp();
+    let range = data.last_timestamp() - data.initial_timestamp();
+    (relative.as_usecs() as f64 / range.as_usecs() as f64) as f32
+}
+
+fn to_iso8601( timestamp: Timestamp ) -> String {
+    use chrono::{SecondsFormat, TimeZone, Utc};
+
+    let usecs = timestamp.as_usecs();
+    let secs = usecs / 1_000_000;
+    Utc.timestamp( secs as i64, ((usecs - secs * 1_000_000) * 1000) as u32 ).to_rfc3339_opts( SecondsFormat::Micros, true )
+}
+
+/// Renders a timestamp for one of the `*_wall_clock` fields; it's `None` if those weren't requested,
+/// and `Some( None )` if we don't know when the data was recorded.
+///
+/// If we do know then the timestamps are already on the wall clock, so there's no need to shift them.
+fn wall_clock( data: &Data, is_requested: bool, timestamp: Timestamp ) -> Option< Option< String > > {
+    if !is_requested {
+        return None;
+    }
+
+    Some( data.wall_clock_start().map( |_| to_iso8601( timestamp ) ) )
+}
+
+fn get_backing_map< 'a >( data: &'a Data, mmap_state: &mmap_state::MmapState, allocation: &Allocation ) -> Option< protocol::BackingMap< 'a > > {
+    let region = mmap_state.find_region( allocation.actual_range( data ), allocation.timestamp )?;
+    let mapping = &mmap_state.mappings[ region.mapping ];
+    let mmap = mapping.mmap( data );
+    Some( protocol::BackingMap {
+        id: mapping.index as u64,
+        pointer: mapping.pointer,
+        pointer_s: format!( "{:016}", mapping.pointer ),
+        length: mapping.length,
+        timestamp: mapping.timestamp.into(),
+        is_readable: mmap.mmap_protection.is_readable(),
+        is_writable: mmap.mmap_protection.is_writable(),
+        is_executable: mmap.mmap_protection.is_executable(),
+        is_shared: mmap.mmap_flags.is_shared(),
+        is_private: mmap.mmap_flags.is_private(),
+        is_anonymous: mmap.mmap_flags.is_anonymous(),
+        file_path: mapping.file_path( data )
+    })
+}
+
+fn get_allocation< 'a >(
+    data: &'a Data,
+    backtraces: &mut BacktraceFormatter< 'a >,
+    fields: protocol::AllocationFields,
+    mmap_state: Option< &mmap_state::MmapState >,
+    include_dealloc_backtrace: bool,
+    allocation_id: AllocationId,
+    allocation: &Allocation
+) -> protocol::Allocation< 'a > {
+    use protocol::AllocationField as Field;
+
+    // Resolving the backtraces and looking up the chains is relatively expensive, so skip it if possible.
+    let (backtrace, truncated_count) = if fields.contains( Field::Backtrace ) || fields.contains( Field::TruncatedCount ) {
+        let (backtrace, truncated_count) = backtraces.get_frames( allocation.backtrace );
+        (fields.select( Field::Backtrace, || backtrace ), truncated_count.filter( |_| fields.contains( Field::TruncatedCount ) ))
+    } else {
+        (None, None)
+    };
+
+    let chain = if fields.contains( Field::ChainLifetime ) || fields.contains( Field::ChainLength ) {
+        Some( data.get_chain_by_any_allocation( allocation_id ) )
+    } else {
+        None
+    };
+
+    protocol::Allocation {
+        id: fields.select( Field::Id, || allocation_id.raw() ),
+        address: fields.select( Field::Address, || allocation.pointer ),
+        address_s: fields.select( Field::AddressS, || format!( "{:016X}", allocation.pointer ) ),
+        timestamp: fields.select( Field::Timestamp, || allocation.timestamp.into() ),
+        timestamp_wall_clock: None,
+        timestamp_relative: fields.select( Field::TimestampRelative, || (allocation.timestamp - data.initial_timestamp()).into() ),
+        timestamp_relative_p: fields.select( Field::TimestampRelativeP, || timestamp_to_fraction( data, allocation.timestamp ) ),
+        thread: fields.select( Field::Thread, || allocation.thread ),
+        size: fields.select( Field::Size, || allocation.size ),
+        backtrace_id: fields.select( Field::BacktraceId, || allocation.backtrace.raw() ),
+        deallocation: allocation.deallocation.as_ref().filter( |_| fields.contains( Field::Deallocation ) ).map( |deallocation| {
+            let backtrace_id = deallocation.backtrace.filter( |_| include_dealloc_backtrace );
+            let (backtrace, truncated_count) = match backtrace_id {
+                Some( backtrace_id ) => {
+                    let (backtrace, truncated_count) = backtraces.get_frames( backtrace_id );
+                    (Some( backtrace ), truncated_count)
+                },
+                None => (None, None)
+            };
+
+            protocol::Deallocation {
+                timestamp: deallocation.timestamp.into(),
+                timestamp_wall_clock: None,
+                thread: deallocation.thread,
+                backtrace_id: backtrace_id.map( |backtrace_id| backtrace_id.raw() ),
+                backtrace,
+                truncated_count
+            }
+        }),
+        backtrace,
+        truncated_count,
+        in_main_arena: fields.select( Field::InMainArena, || !allocation.in_non_main_arena() ),
+        is_mmaped: fields.select( Field::IsMmaped, || allocation.is_mmaped() ),
+        is_jemalloc: fields.select( Field::IsJemalloc, || allocation.is_jemalloc() ),
+        is_partially_observed: fields.select( Field::IsPartiallyObserved, || allocation.is_partially_observed() ),
+        extra_space: fields.select( Field::ExtraSpace, || allocation.extra_usable_space ),
+        chain_lifetime: chain.as_ref().filter( |_| fields.contains( Field::ChainLifetime ) ).and_then( |chain| chain.lifetime( data ) ).map( |lifetime| lifetime.into() ),
+        position_in_chain: fields.select( Field::PositionInChain, || allocation.position_in_chain ),
+        chain_length: chain.as_ref().filter( |_| fields.contains( Field::ChainLength ) ).map( |chain| chain.length ),
+        backing_map: mmap_state.filter( |_| allocation.is_mmaped() ).map( |mmap_state| get_backing_map( data, mmap_state, allocation ) ),
+        origin: None,
+        alive_at_peak: None
+    }
+}
+
+/// Renders the innermost frame of a backtrace which isn't a part of the allocator as `function (file:line)`.
+fn get_origin( data: &Data, format: &protocol::BacktraceFormat, backtrace_id: BacktraceId ) -> Option< String > {
+    let frame = cli_core::innermost_non_allocator_frame( data, data.get_backtrace( backtrace_id ).map( |(_, frame)| frame ) )?;
+    let frame = get_frame( data, format, frame );
+    let function = frame.function.or( frame.raw_function.map( Cow::Borrowed ) )?;
+    let origin = match (frame.source, frame.line) {
+        (Some( source ), Some( line )) => format!( "{} ({}:{})", function, source, line ),
+        (Some( source ), None) => format!( "{} ({})", function, source ),
+        (None, _) => function.into_owned()
+    };
+
+    Some( origin )
+}
+
+fn get_allocations< 'a >(
+    data: &'a Arc< Data >,
+    backtrace_format: protocol::BacktraceFormat,
+    params: protocol::RequestAllocations,
+    filter: crate::filter::AllocationFilter,
+    mmap_state: Option< Arc< mmap_state::MmapState > >,
+    include_wall_clock: bool,
+    peak: Option< Option< Timestamp > >
+) -> protocol::ResponseAllocations< impl Serialize + 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::AllocSortBy::Timestamp );
+    let order = params.order.unwrap_or( protocol::Order::Asc );
+    let fields = params.fields.unwrap_or_else( protocol::AllocationFields::all );
+    let include_dealloc_backtrace = params.include_dealloc_backtrace.unwrap_or( false );
+    let include_origin = params.include_origin.unwrap_or( false );
+
+    #[derive(Default)]
+    struct Totals {
+        count: u64,
+        size: u64,
+        leaked_count: u64,
+        leaked_size: u64
+    }
+
+    let allocation_ids = prefiltered_allocation_ids( data, sort_by, &filter );
+    let totals =
+        allocation_ids
+        .par_iter()
+        .fold( Totals::default, |mut totals, &id| {
+            let allocation = data.get_allocation( id );
+            if !filter.try_match( data, id, allocation ) {
+                return totals;
+            }
+
+            totals.count += 1;
+            totals.size += allocation.size;
+            if allocation.deallocation.is_none() {
+                totals.leaked_count += 1;
+                totals.leaked_size += allocation.size;
+            }
+
+            totals
+        })
+        .reduce( Totals::default, |a, b| Totals {
+            count: a.count + b.count,
+            size: a.size + b.size,
+            leaked_count: a.leaked_count + b.leaked_count,
+            leaked_size: a.leaked_size + b.leaked_size
+        });
+
+    let allocations = move || {
+        let backtrace_format = backtrace_format.clone();
+        let filter = filter.clone();
+        let mmap_state = mmap_state.clone();
+        let mut backtraces = BacktraceFormatter::new( data, backtrace_format.clone() );
+        let mut origins: HashMap< BacktraceId, Option< String > > = HashMap::new();
+
+        allocations_iter( data, allocation_ids, order, filter )
+            .skip( skip )
+            .take( remaining )
+            .map( move |(allocation_id, allocation)| {
+                let mut output = get_allocation( data, &mut backtraces, fields, mmap_state.as_deref(), include_dealloc_backtrace, allocation_id, allocation );
+                if include_origin {
+                    // A lot of allocations share the same backtrace, so this is only done once per backtrace.
+                    let origin = origins.entry( allocation.backtrace ).or_insert_with( || get_origin( data, &backtrace_format, allocation.backtrace ) );
+                    output.origin = Some( origin.clone() );
+                }
+                if include_wall_clock {
+                    if fields.contains( protocol::AllocationField::Timestamp ) {
+                        output.timestamp_wall_clock = wall_clock( data, true, allocation.timestamp );
+                    }
+                    if let (Some( output ), Some( deallocation )) = (output.deallocation.as_mut(), allocation.deallocation.as_ref()) {
+                        output.timestamp_wall_clock = wall_clock( data, true, deallocation.timestamp );
+                    }
+                }
+                if let Some( peak ) = peak {
+                    output.alive_at_peak = Some( peak.map( |peak| {
+                        allocation.timestamp <= peak && allocation.deallocation.as_ref().map( |deallocation| peak < deallocation.timestamp ).unwrap_or( true )
+                    }).unwrap_or( false ) );
+                }
+                output
+            })
+    };
+
+    protocol::ResponseAllocations {
+        allocations: StreamingSerializer::new( allocations ),
+        total_count: totals.count,
+        total_leaked_count: totals.leaked_count,
+        total_freed_count: totals.count - totals.leaked_count,
+        total_size: totals.size,
+        total_leaked_size: totals.leaked_size
+    }
+}
+
+fn handler_allocations( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestAllocations = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let format = get_response_format( &req )?;
+    let wall_clock_params: protocol::RequestWallClock = query( &req )?;
+    let include_wall_clock = wall_clock_params.wall_clock.unwrap_or( false );
+    let peak = if params.include_peak_flag.unwrap_or( false ) {
+        let summary = req.state().get_summary( data.id() ).unwrap_or_else( || Arc::new( get_data_summary( data ) ) );
+        Some( summary.peak.map( |(timestamp, _)| timestamp ) )
+    } else {
+        None
+    };
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let mmap_state = if params.include_backing_map.unwrap_or( false ) {
+            Some( state.mmap_state_cache.get_or_compute( data.id(), || mmap_state::MmapState::new( &data ) ) )
+        } else {
+            None
+        };
+
+        let response = get_allocations( &data, backtrace_format, params, filter, mmap_state, include_wall_clock, peak );
+        write_response( tx, format, &response );
+    })?;
+
+    Ok( response_with_format( format ).body( body ) )
+}
+
+fn prepare_batch_filter(
+    req: &HttpRequest,
+    data: &Arc< Data >,
+    entry: protocol::RequestBatchCountsEntry,
+    limits: &ScriptLimits
+) -> std::result::Result< AllocationFilter, ApiError > {
+    let filter = body_params::filter_to_query( entry.filter ).map_err( ApiError::bad_request )?;
+    let filter: protocol::AllocFilter = serde_urlencoded::from_str( &filter ).map_err( |error| ApiError::bad_request( format!( "invalid filter: {}", error ) ) )?;
+    let custom_filter = resolve_custom_filter( req, protocol::CustomFilter {
+        custom_filter: entry.custom_filter,
+        custom_filter_name: entry.custom_filter_name
+    })?;
+
+    Ok( prepare_filter( data, &filter, &custom_filter, limits )? )
+}
+
+/// Counts the allocations matched by each of the given filters, all in a single pass.
+fn handler_batch_counts( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let entries: Vec< protocol::RequestBatchCountsEntry > = serde_json::from_slice( &body ).map_err( |error| ApiError::bad_request( format!( "invalid request body: {}", error ) ) )?;
+    let limits = script_limits( &req )?;
+
+    let mut names = Vec::with_capacity( entries.len() );
+    let mut filters = Vec::with_capacity( entries.len() );
+    for entry in entries {
+        names.push( entry.name.clone() );
+        match prepare_batch_filter( &req, data, entry, &limits ) {
+            Ok( filter ) => filters.push( Ok( filter ) ),
+            // The deadline applies to the whole request, so there's no point in continuing.
+            Err( error ) if limits.deadline.is_exceeded() => return Err( error.into() ),
+            Err( error ) => filters.push( Err( error ) )
+        }
+    }
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let counts = data.alloc_sorted_by_timestamp( None, None )
+            .par_iter()
+            .fold( || vec![ protocol::BatchCounts::default(); filters.len() ], |mut counts, &id| {
+                let allocation = data.get_allocation( id );
+                for (counts, filter) in counts.iter_mut().zip( filters.iter() ) {
+                    let filter = match filter {
+                        Ok( filter ) => filter,
+                        Err( _ ) => continue
+                    };
+
+                    if !filter.try_match( &data, id, allocation ) {
+                        continue;
+                    }
+
+                    counts.matched_count += 1;
+                    counts.matched_size += allocation.size;
+                    if allocation.deallocation.is_none() {
+                        counts.leaked_count += 1;
+                        counts.leaked_size += allocation.size;
+                    }
+                }
+
+                counts
+            })
+            .reduce( || vec![ protocol::BatchCounts::default(); filters.len() ], |mut a, b| {
+                for (a, b) in a.iter_mut().zip( b ) {
+                    a.matched_count += b.matched_count;
+                    a.matched_size += b.matched_size;
+                    a.leaked_count += b.leaked_count;
+                    a.leaked_size += b.leaked_size;
+                }
+                a
+            });
+
+        let response: Vec< _ > = names.into_iter().zip( filters ).zip( counts ).map( |((name, filter), counts)| {
+            match filter {
+                Ok( _ ) => protocol::BatchCountsEntry { name, counts: Some( counts ), error: None },
+                Err( error ) => protocol::BatchCountsEntry { name, counts: None, error: Some( error.to_json()[ "error" ].take() ) }
+            }
+        }).collect();
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn handler_allocation( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let allocation_id = req.match_info().get( "allocation_id" ).unwrap();
+    let allocation_id = allocation_id.parse().ok()
+        .and_then( |raw| data.get_allocation_id( raw ) )
+        .ok_or_else( || ApiError::not_found( "allocation not found" ) )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let data = &data;
+        let allocation = data.get_allocation( allocation_id );
+        let mmap_state = if allocation.is_mmaped() {
+            Some( state.mmap_state_cache.get_or_compute( data.id(), || mmap_state::MmapState::new( data ) ) )
+        } else {
+            None
+        };
+
+        let deallocation_backtrace_id = allocation.deallocation.as_ref().and_then( |deallocation| deallocation.backtrace );
+        let (deallocation_backtrace, deallocation_truncated_count) = match deallocation_backtrace_id {
+            Some( backtrace_id ) => {
+                let (frames, truncated_count) = get_frames( data, &backtrace_format, data.get_backtrace( backtrace_id ) );
+                (Some( frames ), truncated_count)
+            },
+            None => (None, None)
+        };
+
+        let chain = data.get_chain_by_any_allocation( allocation_id );
+        let chain = std::iter::successors( Some( chain.first ), |&id| data.get_allocation( id ).reallocation )
+            .take( chain.length as usize )
+            .map( |id| {
+                let link = data.get_allocation( id );
+                protocol::ChainLink {
+                    id: id.raw(),
+                    address: link.pointer,
+                    address_s: format!( "{:016X}", link.pointer ),
+                    size: link.size,
+                    timestamp: link.timestamp.into()
+                }
+            })
+            .collect();
+
+        let response = protocol::ResponseAllocation {
+            allocation: get_allocation( data, &mut BacktraceFormatter::new( data, backtrace_format.clone() ), protocol::AllocationFields::all(), mmap_state.as_deref(), false, allocation_id, allocation ),
+            deallocation_backtrace_id: deallocation_backtrace_id.map( |backtrace_id| backtrace_id.raw() ),
+            deallocation_backtrace,
+            deallocation_truncated_count,
+            chain
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+fn get_size_histogram(
+    data: &Data,
+    params: protocol::RequestSizeHistogram,
+    filter: AllocationFilter
+) -> protocol::ResponseSizeHistogram {
+    #[derive(Clone, Default)]
+    struct Counts {
+        count: u64,
+        live_count: u64,
+        bytes: u64,
+        live_bytes: u64
+    }
+
+    let mut boundaries = match params.buckets {
+        Some( list ) => list.0,
+        None => Vec::new()
+    };
+
+    let is_logarithmic = boundaries.is_empty();
+    if is_logarithmic {
+        // Bucket #0 is for zero-sized allocations, and bucket #N is for [2^(N-1), 2^N).
+        boundaries.push( 0 );
+        boundaries.extend( (0..64).map( |power| 1 << power ) );
+    } else {
+        boundaries.sort_unstable();
+        boundaries.dedup();
+    }
+
+    let at = params.at.map( |at| data.initial_timestamp() + at.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let bucket_count = boundaries.len();
+    let allocation_ids = prefiltered_allocation_ids( data, protocol::AllocSortBy::Timestamp, &filter );
+    let counts = allocation_ids.par_iter()
+        .fold( || vec![ Counts::default(); bucket_count ], |mut counts, &id| {
+            let allocation = data.get_allocation( id );
+            if !filter.try_match( data, id, allocation ) {
+                return counts;
+            }
+
+            if let Some( at ) = at {
+                if allocation.timestamp > at {
+                    return counts;
+                }
+            }
+
+            let index = if is_logarithmic {
+                (64 - allocation.size.leading_zeros()) as usize
+            } else {
+                match boundaries.binary_search( &allocation.size ) {
+                    Ok( index ) => index,
+                    Err( 0 ) => return counts,
+                    Err( index ) => index - 1
+                }
+            };
+
+            let is_live = match allocation.deallocation {
+                Some( ref deallocation ) => at.map( |at| deallocation.timestamp > at ).unwrap_or( false ),
+                None => true
+            };
+
+            let bucket = &mut counts[ index ];
+            bucket.count += 1;
+            bucket.bytes += allocation.size;
+            if is_live {
+                bucket.live_count += 1;
+                bucket.live_bytes += allocation.size;
+            }
+
+            counts
+        })
+        .reduce( || vec![ Counts::default(); bucket_count ], |mut lhs, rhs| {
+            for (lhs, rhs) in lhs.iter_mut().zip( rhs ) {
+                lhs.count += rhs.count;
+                lhs.live_count += rhs.live_count;
+                lhs.bytes += rhs.bytes;
+                lhs.live_bytes += rhs.live_bytes;
+            }
+            lhs
+        });
+
+    let mut buckets: Vec< _ > = counts.into_iter().enumerate().map( |(index, counts)| {
+        protocol::SizeHistogramBucket {
+            size_min: boundaries[ index ],
+            size_max: boundaries.get( index + 1 ).copied(),
+            count: counts.count,
+            live_count: counts.live_count,
+            bytes: counts.bytes,
+            live_bytes: counts.live_bytes
+        }
+    }).collect();
+
+    if is_logarithmic {
+        let last_non_empty = buckets.iter().rposition( |bucket| bucket.count != 0 ).map( |index| index + 1 ).unwrap_or( 0 );
+        buckets.truncate( last_non_empty );
+    }
+
+    protocol::ResponseSizeHistogram {
+        at: at.map( |at| at.into() ),
+        buckets
+    }
+}
+
+fn handler_size_histogram( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestSizeHistogram = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = get_size_histogram( &data, params, filter );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn get_allocation_group_data< 'a, I >( data: &Data, iter: I ) -> protocol::AllocationGroupData
+    where I: ParallelIterator< Item = &'a Allocation >
+{
+    #[derive(Clone)]
+    struct Group {
+        size_sum: u64,
+        min_size: u64,
+        max_size: u64,
+        min_timestamp: Timestamp,
+        max_timestamp: Timestamp,
+        leaked_count: u64,
+        allocated_count: u64
+    }
+
+    impl Default for Group {
+        fn default() -> Self {
+            Group {
+                size_sum: 0,
+                min_size: !0,
+                max_size: 0,
+                min_timestamp: Timestamp::max(),
+                max_timestamp: Timestamp::min(),
+                leaked_count: 0,
+                allocated_count: 0
+            }
+        }
+    }
+
+    let group = iter.fold_with(
+        Group::default(),
+        |mut group, allocation| {
+            let size = allocation.size;
+            let timestamp = allocation.timestamp;
             group.size_sum += size;
             group.min_size = min( group.min_size, size );
             group.max_size = max( group.max_size, size );
             group.min_timestamp = min( group.min_timestamp, timestamp );
             group.max_timestamp = max( group.max_timestamp, timestamp );
 
-            group.allocated_count += 1;
-            if allocation.deallocation.is_none() {
-                group.leaked_count += 1;
+            group.allocated_count += 1;
+            if allocation.deallocation.is_none() {
+                group.leaked_count += 1;
+            }
+
+            group
+        }
+    ).reduce(
+        || Group::default(),
+        |mut a, b| {
+            a.size_sum += b.size_sum;
+            a.min_size = min( a.min_size, b.min_size );
+            a.max_size = max( a.max_size, b.max_size );
+            a.min_timestamp = min( a.min_timestamp, b.min_timestamp );
+            a.max_timestamp = max( a.max_timestamp, b.max_timestamp );
+            a.allocated_count += b.allocated_count;
+            a.leaked_count += b.leaked_count;
+
+            a
+        }
+    );
+
+    protocol::AllocationGroupData {
+        leaked_count: group.leaked_count,
+        allocated_count: group.allocated_count,
+        size: group.size_sum,
+        min_size: group.min_size,
+        max_size: group.max_size,
+        min_timestamp: group.min_timestamp.into(),
+        min_timestamp_relative: (group.min_timestamp - data.initial_timestamp()).into(),
+        min_timestamp_relative_p: timestamp_to_fraction( data, group.min_timestamp ),
+        max_timestamp: group.max_timestamp.into(),
+        max_timestamp_relative: (group.max_timestamp - data.initial_timestamp()).into(),
+        max_timestamp_relative_p: timestamp_to_fraction( data, group.max_timestamp ),
+        interval: (group.max_timestamp - group.min_timestamp).into(),
+        graph_url: None,
+        graph_preview_url: None,
+        max_total_usage_first_seen_at: None,
+        max_total_usage_first_seen_at_relative: None,
+        max_total_usage_first_seen_at_relative_p: None,
+        min_timestamp_wall_clock: None,
+        max_timestamp_wall_clock: None
+    }
+}
+
+fn get_global_group_data( data: &Data, backtrace_id: BacktraceId ) -> protocol::AllocationGroupData {
+    let stats = data.get_group_statistics( backtrace_id );
+
+    let leaked_count = stats.alloc_count - stats.free_count;
+    let allocated_count = stats.alloc_count;
+    let size_sum = stats.alloc_size;
+    let min_size = stats.min_size;
+    let max_size = stats.max_size;
+    let min_timestamp = stats.first_allocation;
+    let max_timestamp = stats.last_allocation;
+
+    protocol::AllocationGroupData {
+        leaked_count,
+        allocated_count,
+        size: size_sum,
+        min_size,
+        max_size,
+        min_timestamp: min_timestamp.into(),
+        min_timestamp_relative: (min_timestamp - data.initial_timestamp()).into(),
+        min_timestamp_relative_p: timestamp_to_fraction( data, min_timestamp ),
+        max_timestamp: max_timestamp.into(),
+        max_timestamp_relative: (max_timestamp - data.initial_timestamp()).into(),
+        max_timestamp_relative_p: timestamp_to_fraction( data, max_timestamp ),
+        interval: (max_timestamp - min_timestamp).into(),
+        graph_url: None,
+        graph_preview_url: None,
+        max_total_usage_first_seen_at: Some( stats.max_total_usage_first_seen_at.into() ),
+        max_total_usage_first_seen_at_relative: Some( (stats.max_total_usage_first_seen_at - data.initial_timestamp()).into() ),
+        max_total_usage_first_seen_at_relative_p: Some( timestamp_to_fraction( data, stats.max_total_usage_first_seen_at ) ),
+        min_timestamp_wall_clock: None,
+        max_timestamp_wall_clock: None
+    }
+}
+
+fn set_group_wall_clock( data: &Data, group: &mut protocol::AllocationGroupData ) {
+    group.min_timestamp_wall_clock = wall_clock( data, true, (&group.min_timestamp).into() );
+    group.max_timestamp_wall_clock = wall_clock( data, true, (&group.max_timestamp).into() );
+}
+
+fn get_allocation_groups< 'a >(
+    state: &'a State,
+    data: &'a Arc< Data >,
+    backtrace_format: protocol::BacktraceFormat,
+    params: protocol::RequestAllocationGroups,
+    allocation_groups: Arc< AllocationGroups >,
+    include_wall_clock: bool
+) -> protocol::ResponseAllocationGroups< impl Serialize + 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let generate_graphs = params.generate_graphs.unwrap_or( false );
+
+    let total_count = allocation_groups.len();
+    let factory = move || {
+        let mut backtraces = BacktraceFormatter::new( data, backtrace_format.clone() );
+        let allocations = allocation_groups.clone();
+        (0..allocations.allocations_by_backtrace.len())
+            .skip( skip )
+            .take( remaining )
+            .map( move |index| {
+                let (&backtrace_id, matched_allocation_ids) = allocations.allocations_by_backtrace.get( index );
+                let mut all = get_global_group_data( data, backtrace_id );
+                let mut only_matched = get_allocation_group_data( data, matched_allocation_ids.into_par_iter().map( |&allocation_id| data.get_allocation( allocation_id ) ) );
+                let (backtrace, truncated_count) = backtraces.get_frames( backtrace_id );
+
+                if include_wall_clock {
+                    set_group_wall_clock( data, &mut all );
+                    set_group_wall_clock( data, &mut only_matched );
+                }
+
+                if generate_graphs {
+                    let code = format!( r#"
+                        let graph = graph()
+                            .add("Matched", allocations())
+                            .add("Global", data().allocations().only_matching_backtraces([{}]))
+                            .save()
+                            .without_axes()
+                            .without_legend()
+                            .trim()
+                            .save();
+                    "#, backtrace_id.raw() );
+
+                    let args = cli_core::script::EngineArgs {
+                        data: Some( data.clone() ),
+                        allocation_ids: Some( Arc::new( matched_allocation_ids.to_owned() ) ),
+                        .. cli_core::script::EngineArgs::default()
+                    };
+
+                    let env = Arc::new( Mutex::new( cli_core::script::VirtualEnvironment::new() ) );
+                    let engine = cli_core::script::Engine::new( env.clone(), args );
+                    engine.run( &code ).unwrap();
+                    let mut urls = Vec::new();
+                    let files = std::mem::take( &mut env.lock().output );
+                    for file in files {
+                        let (path, mime, bytes) = match file {
+                            cli_core::script::ScriptOutputKind::Image { path, mime, data: bytes } => (path, mime, bytes),
+                            cli_core::script::ScriptOutputKind::File { path, mime, data: bytes } => (path, mime, bytes),
+                            _ => continue
+                        };
+
+                        let basename = &path[ path.rfind( "/" ).unwrap() + 1.. ];
+                        let entry = state.generated_files.lock().add_file( GeneratedFile::new( data.id(), mime, bytes ) );
+                        urls.push( entry.url( &state.base_path, basename ) );
+                    }
+
+                    let mut urls = urls.into_iter();
+                    only_matched.graph_url = urls.next();
+                    only_matched.graph_preview_url = urls.next();
+                }
+
+                protocol::AllocationGroup {
+                    all,
+                    only_matched,
+                    backtrace_id: backtrace_id.raw(),
+                    backtrace,
+                    truncated_count
+                }
+            })
+    };
+
+    let response = protocol::ResponseAllocationGroups {
+        allocations: StreamingSerializer::new( factory ),
+        total_count: total_count as _
+    };
+
+    response
+}
+
+fn compute_allocation_groups(
+    data: &Data,
+    filter: &AllocationFilter,
+    sort_by: protocol::AllocGroupsSortBy,
+    order: protocol::Order
+) -> AllocationGroups {
+    fn sort_groups_by< T, F >( data: &Data, groups: &mut AllocationGroups, order: protocol::Order, is_global: bool, callback: F )
+        where F: Fn( &protocol::AllocationGroupData ) -> T + Send + Sync,
+              T: Ord + Send + Sync
+    {
+        if is_global {
+            groups.allocations_by_backtrace.par_sort_by_key( |(&backtrace_id, _)| {
+                let group_data = get_global_group_data( data, backtrace_id );
+                callback( &group_data )
+            });
+        } else {
+            let key_for_backtrace: Vec< _ > =
+                groups.allocations_by_backtrace.par_iter().map( |(&backtrace_id, ids)| {
+                    let allocations = ids.par_iter().map( |&id| data.get_allocation( id ) );
+                    let group_data = get_allocation_group_data( data, allocations );
+                    (backtrace_id, callback( &group_data ))
+                }).collect();
+
+            let key_for_backtrace: HashMap< _, _ > = key_for_backtrace.into_iter().collect();
+            groups.allocations_by_backtrace.par_sort_by_key( |(&backtrace_id, _)| {
+                key_for_backtrace.get( &backtrace_id ).unwrap().clone()
+            });
+        }
+
+        match order {
+            protocol::Order::Asc => {},
+            protocol::Order::Dsc => {
+                groups.allocations_by_backtrace.reverse();
+            }
+        }
+    }
+
+    let iter = prefiltered_allocation_ids( data, Default::default(), filter )
+        .par_iter()
+        .map( |&allocation_id| (allocation_id, data.get_allocation( allocation_id )) )
+        .filter( move |(id, allocation)| filter.try_match( data, *id, allocation ) );
+
+    let mut groups = AllocationGroups::new( iter );
+    match sort_by {
+        protocol::AllocGroupsSortBy::MinTimestamp => {
+            sort_groups_by( data, &mut groups, order, false, |group_data| group_data.min_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::MaxTimestamp => {
+            sort_groups_by( data, &mut groups, order, false, |group_data| group_data.max_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::Interval => {
+            sort_groups_by( data, &mut groups, order, false, |group_data| group_data.interval.clone() );
+        },
+        protocol::AllocGroupsSortBy::AllocatedCount => {
+            sort_groups_by( data, &mut groups, order, false, |group_data| group_data.allocated_count );
+        },
+        protocol::AllocGroupsSortBy::LeakedCount => {
+            sort_groups_by( data, &mut groups, order, false, |group_data| group_data.leaked_count );
+        },
+        protocol::AllocGroupsSortBy::Size => {
+            sort_groups_by( data, &mut groups, order, false, |group_data| group_data.size );
+        },
+        protocol::AllocGroupsSortBy::GlobalMinTimestamp => {
+            sort_groups_by( data, &mut groups, order, true, |group_data| group_data.min_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::GlobalMaxTimestamp => {
+            sort_groups_by( data, &mut groups, order, true, |group_data| group_data.max_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::GlobalInterval => {
+            sort_groups_by( data, &mut groups, order, true, |group_data| group_data.interval.clone() );
+        },
+        protocol::AllocGroupsSortBy::GlobalAllocatedCount => {
+            sort_groups_by( data, &mut groups, order, true, |group_data| group_data.allocated_count );
+        },
+        protocol::AllocGroupsSortBy::GlobalLeakedCount => {
+            sort_groups_by( data, &mut groups, order, true, |group_data| group_data.leaked_count );
+        },
+        protocol::AllocGroupsSortBy::GlobalSize => {
+            sort_groups_by( data, &mut groups, order, true, |group_data| group_data.size );
+        },
+        protocol::AllocGroupsSortBy::GlobalMaxTotalUsageFirstSeenAt => {
+            sort_groups_by( data, &mut groups, order, true, |group_data| group_data.max_total_usage_first_seen_at.clone() );
+        }
+    }
+
+    groups
+}
+
+fn handler_allocation_groups( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter_params: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter_params, &custom_filter )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let params: protocol::RequestAllocationGroups = query( &req )?;
+    if params.generate_graphs.unwrap_or( false ) {
+        check_scripts_enabled( &req )?;
+    }
+    let wall_clock_params: protocol::RequestWallClock = query( &req )?;
+    let include_wall_clock = wall_clock_params.wall_clock.unwrap_or( false );
+
+    let key = AllocationGroupsKey {
+        data_id: data.id(),
+        filter: filter_params,
+        custom_filter,
+        sort_by: params.sort_by.unwrap_or( protocol::AllocGroupsSortBy::MinTimestamp ),
+        order: params.order.unwrap_or( protocol::Order::Asc )
+    };
+
+    let deadline = get_deadline( &req )?;
+    let allocation_groups = req.state().allocation_group_cache.try_get_or_compute( key.clone(), || {
+        let groups = compute_allocation_groups( data, &filter, key.sort_by, key.order );
+
+        // If the filter has hit the deadline then these are incomplete, so they can't be cached.
+        if deadline.was_exceeded() {
+            return Err( deadline::deadline_exceeded() );
+        }
+
+        Ok( groups )
+    })?;
+
+    let format = get_response_format( &req )?;
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = get_allocation_groups( &state, &data, backtrace_format, params, allocation_groups, include_wall_clock );
+        write_response( tx, format, &response );
+    })?;
+
+    Ok( response_with_format( format ).body( body ) )
+}
+
+fn handler_raw_allocations( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let iter = data.alloc_sorted_by_timestamp( None, None ).iter().map( |&id| data.get_allocation( id ) );
+
+    let mut output = String::new();
+    output.push_str( "[" );
+
+    let mut is_first = true;
+    for allocation in iter {
+        if !is_first {
+            output.push_str( "," );
+        } else {
+            is_first = false;
+        }
+
+        output.push_str( "{\"backtrace\":[" );
+        let mut is_first = true;
+        for (_, frame) in data.get_backtrace( allocation.backtrace ) {
+            if !is_first {
+                output.push_str( "," );
+            } else {
+                is_first = false;
+            }
+
+            let address = frame.address().raw();
+            write!( output, "\"{:016X}\"", address ).unwrap();
+        }
+        output.push_str( "]}" );
+    }
+
+    output.push_str( "]" );
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( output ) )
+}
+
+/// Serializes a node of the tree along with all of its children, with each node's value converted through `value`.
+struct SerializableNode< 'a, K, V, F > {
+    tree: &'a Tree< K, V >,
+    node_id: NodeId,
+    value: &'a F
+}
+
+impl< 'a, K, V, F, R > Serialize for SerializableNode< 'a, K, V, F >
+    where K: PartialEq + Clone,
+          F: Fn( &V ) -> R,
+          R: Serialize
+{
+    fn serialize< S: serde::Serializer >( &self, serializer: S ) -> std::result::Result< S::Ok, S::Error > {
+        use serde::ser::SerializeMap;
+
+        let node = self.tree.get_node( self.node_id );
+        let children: Vec< _ > = node.children.iter().map( |&(_, child_id)| SerializableNode {
+            tree: self.tree,
+            node_id: child_id,
+            value: self.value
+        }).collect();
+
+        let mut map = serializer.serialize_map( Some( if node.value().is_some() { 6 } else { 5 } ) )?;
+        map.serialize_entry( "size", &node.total_size )?;
+        map.serialize_entry( "count", &node.total_count )?;
+        map.serialize_entry( "first", &node.total_first_timestamp.as_secs() )?;
+        map.serialize_entry( "last", &node.total_last_timestamp.as_secs() )?;
+        if let Some( value ) = node.value() {
+            map.serialize_entry( "value", &(self.value)( value ) )?;
+        }
+        map.serialize_entry( "children", &children )?;
+        map.end()
+    }
+}
+
+fn handler_tree( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let format = get_response_format( &req )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let allocations = data.allocations_with_id().filter( |&(allocation_id, allocation)| filter.try_match( &data, allocation_id, allocation ) );
+        let mut trimmed_count_cache = HashMap::new();
+        let mut get_backtrace = |backtrace_id| {
+            let backtrace = data.get_backtrace( backtrace_id );
+            let trimmed_count = *trimmed_count_cache.entry( backtrace_id ).or_insert_with( || trimmed_frame_count( &data, &backtrace_format, backtrace.clone() ) );
+            backtrace.skip( trimmed_count )
+        };
+
+        if backtrace_format.collapse_inlined.unwrap_or( false ) {
+            let mut tree: Tree< (FrameId, FrameId), CollapsedFrame > = Tree::new();
+            for (allocation_id, allocation) in allocations {
+                if tx.is_disconnected() {
+                    return;
+                }
+
+                let frames = collapse_inlined_frames( get_backtrace( allocation.backtrace ) );
+                let (frames, _) = truncate_frames( &backtrace_format, &frames );
+                tree.add_allocation( allocation, allocation_id, frames.iter().cloned() );
+            }
+
+            let root = SerializableNode { tree: &tree, node_id: 0, value: &|frame: &CollapsedFrame| get_collapsed_frame( &data, &backtrace_format, frame ) };
+            write_response( tx, format, &root );
+        } else {
+            let frames_from = backtrace_format.frames_from.unwrap_or( protocol::FramesFrom::Leaf );
+            let mut tree: Tree< FrameId, &Frame > = Tree::new();
+            for (allocation_id, allocation) in allocations {
+                if tx.is_disconnected() {
+                    return;
+                }
+
+                let backtrace = get_backtrace( allocation.backtrace );
+                let range = truncated_frame_range( backtrace_format.max_frames, frames_from, backtrace.clone().count() );
+                tree.add_allocation( allocation, allocation_id, backtrace.skip( range.start ).take( range.len() ) );
+            }
+
+            let root = SerializableNode { tree: &tree, node_id: 0, value: &|frame: &&Frame| get_frame( &data, &backtrace_format, frame ) };
+            write_response( tx, format, &root );
+        }
+    })?;
+
+    Ok( response_with_format( format ).body( body ) )
+}
+
+fn handler_mmaps( req: HttpRequest ) -> Result< HttpResponse > {
+    let backtrace_format = get_backtrace_format( &req )?;
+    let filter: protocol::MmapFilter = query( &req )?;
+    let wall_clock_params: protocol::RequestWallClock = query( &req )?;
+    let include_wall_clock = wall_clock_params.wall_clock.unwrap_or( false );
+    let body = async_data_handler( &req, move |data, tx| {
+        let factory = || {
+            data.mmap_operations().iter().flat_map( |op| {
+                match *op {
+                    MmapOperation::Mmap( MemoryMap {
+                        timestamp,
+                        pointer,
+                        length,
+                        backtrace: backtrace_id,
+                        requested_address,
+                        mmap_protection,
+                        mmap_flags,
+                        file_descriptor,
+                        thread,
+                        offset,
+                        file_path
+                    }) => {
+                        let file_path = file_path.map( |id| data.interner().resolve( id ).unwrap() );
+                        if !filter.matches( length, file_path ) {
+                            return None;
+                        }
+                        let (backtrace, truncated_count) = get_frames( &data, &backtrace_format, data.get_backtrace( backtrace_id ) );
+                        Some( protocol::MmapOperation::Mmap {
+                            timestamp: timestamp.into(),
+                            timestamp_wall_clock: wall_clock( &data, include_wall_clock, timestamp ),
+                            pointer,
+                            pointer_s: format!( "{:016}", pointer ),
+                            length,
+                            backtrace,
+                            truncated_count,
+                            backtrace_id: backtrace_id.raw(),
+                            requested_address,
+                            requested_address_s: format!( "{:016}", requested_address ),
+                            is_readable: mmap_protection.is_readable(),
+                            is_writable: mmap_protection.is_writable(),
+                            is_executable: mmap_protection.is_executable(),
+                            is_semaphore: mmap_protection.is_semaphore(),
+                            grows_down: mmap_protection.grows_down(),
+                            grows_up: mmap_protection.grows_up(),
+                            is_shared: mmap_flags.is_shared(),
+                            is_private: mmap_flags.is_private(),
+                            is_fixed: mmap_flags.is_fixed(),
+                            is_anonymous: mmap_flags.is_anonymous(),
+                            is_uninitialized: mmap_flags.is_uninitialized(),
+                            offset,
+                            file_descriptor: file_descriptor as i32,
+                            file_path,
+                            thread
+                        })
+                    },
+                    MmapOperation::Munmap( MemoryUnmap {
+                        timestamp,
+                        pointer,
+                        length,
+                        backtrace: backtrace_id,
+                        thread
+                    }) => {
+                        if !filter.matches( length, None ) {
+                            return None;
+                        }
+                        let (backtrace, truncated_count) = get_frames( &data, &backtrace_format, data.get_backtrace( backtrace_id ) );
+                        Some(protocol::MmapOperation::Munmap {
+                            timestamp: timestamp.into(),
+                            timestamp_wall_clock: wall_clock( &data, include_wall_clock, timestamp ),
+                            pointer,
+                            pointer_s: format!( "{:016}", pointer ),
+                            length,
+                            backtrace,
+                            truncated_count,
+                            backtrace_id: backtrace_id.raw(),
+                            thread
+                        })
+                    },
+                    MmapOperation::Mremap( MemoryRemap {
+                        timestamp,
+                        old_pointer,
+                        old_length,
+                        new_pointer,
+                        new_length,
+                        backtrace: backtrace_id,
+                        remap_flags,
+                        thread
+                    }) => {
+                        if !filter.matches( new_length, None ) {
+                            return None;
+                        }
+                        let (backtrace, truncated_count) = get_frames( &data, &backtrace_format, data.get_backtrace( backtrace_id ) );
+                        Some( protocol::MmapOperation::Mremap {
+                            timestamp: timestamp.into(),
+                            timestamp_wall_clock: wall_clock( &data, include_wall_clock, timestamp ),
+                            old_pointer,
+                            old_pointer_s: format!( "{:016}", old_pointer ),
+                            old_length,
+                            new_pointer,
+                            new_pointer_s: format!( "{:016}", new_pointer ),
+                            new_length,
+                            backtrace,
+                            truncated_count,
+                            backtrace_id: backtrace_id.raw(),
+                            is_may_move: remap_flags.is_may_move(),
+                            is_fixed: remap_flags.is_fixed(),
+                            is_dont_unmap: remap_flags.is_dont_unmap(),
+                            thread
+                        })
+                    },
+                    MmapOperation::Mprotect( MemoryProtect {
+                        timestamp,
+                        pointer,
+                        length,
+                        backtrace: backtrace_id,
+                        protection,
+                        thread
+                    }) => {
+                        if !filter.matches( length, None ) {
+                            return None;
+                        }
+                        let (backtrace, truncated_count) = get_frames( &data, &backtrace_format, data.get_backtrace( backtrace_id ) );
+                        Some( protocol::MmapOperation::Mprotect {
+                            timestamp: timestamp.into(),
+                            timestamp_wall_clock: wall_clock( &data, include_wall_clock, timestamp ),
+                            pointer,
+                            pointer_s: format!( "{:016}", pointer ),
+                            length,
+                            backtrace,
+                            truncated_count,
+                            backtrace_id: backtrace_id.raw(),
+                            is_readable: protection.is_readable(),
+                            is_writable: protection.is_writable(),
+                            is_executable: protection.is_executable(),
+                            thread
+                        })
+                    }
+                }
+            })
+        };
+
+        let response = protocol::ResponseMmaps {
+            operations: StreamingSerializer::new( factory )
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum MmapGroupKey {
+    Backtrace( BacktraceId ),
+    File( Option< StringId > )
+}
+
+struct MmapGroupStatistics {
+    key: MmapGroupKey,
+    map_count: u64,
+    mapped_bytes: u64,
+    unmapped_count: u64,
+    unmapped_bytes: u64,
+    live_bytes: u64,
+    min_timestamp: Timestamp,
+    max_timestamp: Timestamp
+}
+
+fn compute_mmap_groups(
+    data: &Data,
+    mmap_state: &mmap_state::MmapState,
+    filter: &protocol::MmapFilter,
+    group_by: protocol::MmapGroupBy,
+    sort_by: protocol::MmapGroupsSortBy,
+    order: protocol::Order
+) -> Vec< MmapGroupStatistics > {
+    let mut groups: HashMap< MmapGroupKey, MmapGroupStatistics > = HashMap::new();
+    for mapping in &mmap_state.mappings {
+        if !filter.matches( mapping.length, mapping.file_path( data ) ) {
+            continue;
+        }
+
+        let key = match group_by {
+            protocol::MmapGroupBy::Backtrace => MmapGroupKey::Backtrace( mapping.backtrace ),
+            protocol::MmapGroupBy::File => MmapGroupKey::File( mapping.mmap( data ).file_path )
+        };
+
+        let group = groups.entry( key ).or_insert_with( || MmapGroupStatistics {
+            key,
+            map_count: 0,
+            mapped_bytes: 0,
+            unmapped_count: 0,
+            unmapped_bytes: 0,
+            live_bytes: 0,
+            min_timestamp: mapping.timestamp,
+            max_timestamp: mapping.timestamp
+        });
+
+        group.map_count += 1;
+        group.mapped_bytes += mapping.length;
+        group.unmapped_bytes += mapping.unmapped_length;
+        group.live_bytes += mapping.remaining_length();
+        if mapping.is_fully_unmapped() {
+            group.unmapped_count += 1;
+        }
+        group.min_timestamp = std::cmp::min( group.min_timestamp, mapping.timestamp );
+        group.max_timestamp = std::cmp::max( group.max_timestamp, mapping.timestamp );
+    }
+
+    let mut groups: Vec< _ > = groups.into_iter().map( |(_, group)| group ).collect();
+    groups.sort_by_key( |group| group.key );
+    match sort_by {
+        protocol::MmapGroupsSortBy::LiveBytes => groups.sort_by_key( |group| group.live_bytes ),
+        protocol::MmapGroupsSortBy::MappedBytes => groups.sort_by_key( |group| group.mapped_bytes ),
+        protocol::MmapGroupsSortBy::MapCount => groups.sort_by_key( |group| group.map_count ),
+        protocol::MmapGroupsSortBy::UnmappedBytes => groups.sort_by_key( |group| group.unmapped_bytes ),
+        protocol::MmapGroupsSortBy::UnmappedCount => groups.sort_by_key( |group| group.unmapped_count ),
+        protocol::MmapGroupsSortBy::MinTimestamp => groups.sort_by_key( |group| group.min_timestamp ),
+        protocol::MmapGroupsSortBy::MaxTimestamp => groups.sort_by_key( |group| group.max_timestamp )
+    }
+
+    if order == protocol::Order::Dsc {
+        groups.reverse();
+    }
+
+    groups
+}
+
+fn handler_mmap_groups( req: HttpRequest ) -> Result< HttpResponse > {
+    get_data( &req )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let filter: protocol::MmapFilter = query( &req )?;
+    let params: protocol::RequestMmapGroups = query( &req )?;
+
+    let format = get_response_format( &req )?;
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let mmap_state = state.mmap_state_cache.get_or_compute( data.id(), || mmap_state::MmapState::new( &data ) );
+        let groups = compute_mmap_groups(
+            &data,
+            &mmap_state,
+            &filter,
+            params.group_by.unwrap_or( protocol::MmapGroupBy::Backtrace ),
+            params.sort_by.unwrap_or( protocol::MmapGroupsSortBy::LiveBytes ),
+            params.order.unwrap_or( protocol::Order::Dsc )
+        );
+
+        let total_count = groups.len() as u64;
+        let groups = groups.iter()
+            .skip( params.skip.unwrap_or( 0 ) as usize )
+            .take( params.count.unwrap_or( -1_i32 as _ ) as usize )
+            .map( |group| {
+                let (backtrace_id, (backtrace, truncated_count), file_path) = match group.key {
+                    MmapGroupKey::Backtrace( backtrace_id ) => {
+                        (Some( backtrace_id.raw() ), get_frames( &data, &backtrace_format, data.get_backtrace( backtrace_id ) ), None)
+                    },
+                    MmapGroupKey::File( file_path ) => {
+                        (None, (Vec::new(), None), file_path.map( |id| data.interner().resolve( id ).unwrap() ))
+                    }
+                };
+
+                protocol::MmapGroup {
+                    backtrace_id,
+                    backtrace,
+                    file_path,
+                    truncated_count,
+                    map_count: group.map_count,
+                    mapped_bytes: group.mapped_bytes,
+                    unmapped_count: group.unmapped_count,
+                    unmapped_bytes: group.unmapped_bytes,
+                    live_bytes: group.live_bytes,
+                    min_timestamp: group.min_timestamp.into(),
+                    max_timestamp: group.max_timestamp.into()
+                }
+            })
+            .collect::< Vec< _ > >();
+
+        let response = protocol::ResponseMmapGroups { groups, total_count };
+        write_response( tx, format, &response );
+    })?;
+
+    Ok( response_with_format( format ).body( body ) )
+}
+
+fn handler_mmap_leaks( req: HttpRequest ) -> Result< HttpResponse > {
+    get_data( &req )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let filter: protocol::MmapFilter = query( &req )?;
+    let params: protocol::RequestMmapLeaks = query( &req )?;
+
+    let format = get_response_format( &req )?;
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let mmap_state = state.mmap_state_cache.get_or_compute( data.id(), || mmap_state::MmapState::new( &data ) );
+        let mut leaks: Vec< _ > = mmap_state.mappings.iter()
+            .filter( |mapping| !mapping.is_fully_unmapped() )
+            .filter( |mapping| filter.matches( mapping.length, mapping.file_path( &data ) ) )
+            .collect();
+        leaks.sort_by_key( |mapping| std::cmp::Reverse( mapping.remaining_length() ) );
+
+        let total_count = leaks.len() as u64;
+        let leaks = leaks.into_iter()
+            .skip( params.skip.unwrap_or( 0 ) as usize )
+            .take( params.count.unwrap_or( -1_i32 as _ ) as usize )
+            .map( |mapping| {
+                let mmap = mapping.mmap( &data );
+                let (backtrace, truncated_count) = get_frames( &data, &backtrace_format, data.get_backtrace( mapping.backtrace ) );
+                protocol::MmapLeak {
+                    timestamp: mapping.timestamp.into(),
+                    pointer: mapping.pointer,
+                    pointer_s: format!( "{:016}", mapping.pointer ),
+                    length: mapping.length,
+                    leaked_bytes: mapping.remaining_length(),
+                    lifetime: (mmap_state.last_timestamp - mapping.timestamp).into(),
+                    backtrace_id: mapping.backtrace.raw(),
+                    backtrace,
+                    truncated_count,
+                    is_readable: mmap.mmap_protection.is_readable(),
+                    is_writable: mmap.mmap_protection.is_writable(),
+                    is_executable: mmap.mmap_protection.is_executable(),
+                    is_shared: mmap.mmap_flags.is_shared(),
+                    is_private: mmap.mmap_flags.is_private(),
+                    is_anonymous: mmap.mmap_flags.is_anonymous(),
+                    offset: mmap.offset,
+                    file_descriptor: mmap.file_descriptor as i32,
+                    thread: mmap.thread
+                }
+            })
+            .collect::< Vec< _ > >();
+
+        let response = protocol::ResponseMmapLeaks { leaks, total_count };
+        write_response( tx, format, &response );
+    })?;
+
+    Ok( response_with_format( format ).body( body ) )
+}
+
+fn handler_mmap_regions( req: HttpRequest ) -> Result< HttpResponse > {
+    get_data( &req )?;
+    let filter: protocol::MmapFilter = query( &req )?;
+    let params: protocol::RequestMmapRegions = query( &req )?;
+
+    let format = get_response_format( &req )?;
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let mmap_state = state.mmap_state_cache.get_or_compute( data.id(), || mmap_state::MmapState::new( &data ) );
+        let regions: Vec< _ > = mmap_state.regions.iter()
+            .filter( |region| filter.matches( region.length, mmap_state.mappings[ region.mapping ].file_path( &data ) ) )
+            .collect();
+
+        let total_count = regions.len() as u64;
+        let regions = regions.into_iter()
+            .skip( params.skip.unwrap_or( 0 ) as usize )
+            .take( params.count.unwrap_or( -1_i32 as _ ) as usize )
+            .map( |region| {
+                let mapping = &mmap_state.mappings[ region.mapping ];
+                let mmap = mapping.mmap( &data );
+                protocol::MmapRegion {
+                    pointer: region.pointer,
+                    pointer_s: format!( "{:016}", region.pointer ),
+                    length: region.length,
+                    mapping_pointer: mapping.pointer,
+                    mapping_length: mapping.length,
+                    mapped_at: region.mapped_at.into(),
+                    unmapped_at: region.unmapped_at.map( |timestamp| timestamp.into() ),
+                    lifetime: (region.unmapped_at.unwrap_or( mmap_state.last_timestamp ) - region.mapped_at).into(),
+                    mmap_backtrace_id: mapping.backtrace.raw(),
+                    munmap_backtrace_id: region.unmap_backtrace.map( |backtrace| backtrace.raw() ),
+                    is_readable: mmap.mmap_protection.is_readable(),
+                    is_writable: mmap.mmap_protection.is_writable(),
+                    is_executable: mmap.mmap_protection.is_executable(),
+                    is_shared: mmap.mmap_flags.is_shared(),
+                    is_private: mmap.mmap_flags.is_private(),
+                    is_anonymous: mmap.mmap_flags.is_anonymous(),
+                    // A region which was moved with `mremap` can end up below the original map.
+                    offset: mmap.offset + region.pointer.saturating_sub( mapping.pointer ),
+                    file_descriptor: mmap.file_descriptor as i32,
+                    thread: mmap.thread
+                }
+            })
+            .collect::< Vec< _ > >();
+
+        let response = protocol::ResponseMmapRegions { regions, total_count };
+        write_response( tx, format, &response );
+    })?;
+
+    Ok( response_with_format( format ).body( body ) )
+}
+
+fn parse_backtrace_id( input: &str, backtrace_count: usize ) -> Option< BacktraceId > {
+    let backtrace_id: u32 = input.parse().ok()?;
+    if backtrace_id as usize >= backtrace_count {
+        return None;
+    }
+
+    Some( BacktraceId::new( backtrace_id ) )
+}
+
+#[test]
+fn test_parse_backtrace_id() {
+    assert_eq!( parse_backtrace_id( "0", 2 ), Some( BacktraceId::new( 0 ) ) );
+    assert_eq!( parse_backtrace_id( "1", 2 ), Some( BacktraceId::new( 1 ) ) );
+    assert_eq!( parse_backtrace_id( "2", 2 ), None );
+    assert_eq!( parse_backtrace_id( "0", 0 ), None );
+    assert_eq!( parse_backtrace_id( "-1", 2 ), None );
+    assert_eq!( parse_backtrace_id( "abc", 2 ), None );
+    assert_eq!( parse_backtrace_id( "", 2 ), None );
+}
+
+#[test]
+fn test_mmap_groups() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000 ].into() },
+        test_mmap( 1, 0x10000, 0x2000, 1 ),
+        test_mmap( 2, 0x20000, 0x1000, 1 ),
+        test_mmap( 3, 0x30000, 0x8000, 2 ),
+        test_munmap( 4, 0x11000, 0x1000 ),
+        test_munmap( 5, 0x20000, 0x1000 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_groups" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 2 );
+    let groups = &response[ "groups" ];
+    assert_eq!( groups[ 0 ][ "map_count" ], 1 );
+    assert_eq!( groups[ 0 ][ "live_bytes" ], 0x8000 );
+    assert_eq!( groups[ 0 ][ "unmapped_count" ], 0 );
+    assert_eq!( groups[ 1 ][ "map_count" ], 2 );
+    assert_eq!( groups[ 1 ][ "mapped_bytes" ], 0x3000 );
+    assert_eq!( groups[ 1 ][ "unmapped_bytes" ], 0x2000 );
+    assert_eq!( groups[ 1 ][ "unmapped_count" ], 1 );
+    assert_eq!( groups[ 1 ][ "live_bytes" ], 0x1000 );
+    assert_eq!( groups[ 1 ][ "max_timestamp" ][ "secs" ].as_u64().unwrap() - groups[ 1 ][ "min_timestamp" ][ "secs" ].as_u64().unwrap(), 1 );
+    assert!( groups[ 1 ][ "backtrace" ].is_array() );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_groups?sort_by=map_count&order=dsc&count=1" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 2 );
+    assert_eq!( response[ "groups" ].as_array().unwrap().len(), 1 );
+    assert_eq!( response[ "groups" ][ 0 ][ "map_count" ], 2 );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_groups?size_max=4096" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 1 );
+    assert_eq!( response[ "groups" ][ 0 ][ "mapped_bytes" ], 0x1000 );
+    assert_eq!( response[ "groups" ][ 0 ][ "live_bytes" ], 0 );
+}
+
+#[test]
+fn test_mmap_leaks() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_mmap( 1, 0x10000, 0x200000, 1 ),
+        test_mmap( 2, 0x400000, 0x1000, 1 ),
+        test_mmap( 3, 0x500000, 0x3000, 1 ),
+        test_munmap( 4, 0x110000, 0x100000 ),
+        test_munmap( 5, 0x400000, 0x1000 ),
+        test_munmap( 6, 0x501000, 0x1000 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_leaks" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 2 );
+    let leaks = &response[ "leaks" ];
+    assert_eq!( leaks[ 0 ][ "pointer" ], 0x10000 );
+    assert_eq!( leaks[ 0 ][ "length" ], 0x200000 );
+    assert_eq!( leaks[ 0 ][ "leaked_bytes" ], 0x100000 );
+    assert_eq!( leaks[ 0 ][ "lifetime" ][ "secs" ], 5 );
+    assert_eq!( leaks[ 0 ][ "is_readable" ], true );
+    assert_eq!( leaks[ 0 ][ "is_anonymous" ], true );
+    assert_eq!( leaks[ 0 ][ "file_descriptor" ], -1 );
+    assert_eq!( leaks[ 1 ][ "pointer" ], 0x500000 );
+    assert_eq!( leaks[ 1 ][ "leaked_bytes" ], 0x2000 );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_leaks?size_max=65536&skip=0&count=10" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 1 );
+    assert_eq!( response[ "leaks" ][ 0 ][ "pointer" ], 0x500000 );
+}
+
+#[test]
+fn test_export_mmap_flamegraph() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000 ].into() },
+        test_mmap( 1, 0x10000, 0x4000, 1 ),
+        test_mmap( 2, 0x20000, 0x1000, 1 ),
+        test_mmap( 3, 0x30000, 0x2000, 2 ),
+        test_munmap( 4, 0x10000, 0x1000 ),
+        test_munmap( 5, 0x30000, 0x2000 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    // The backtraces aren't resolved here, so we only look at the total weight of every stack.
+    let total_of = |app: &mut _, uri: &str| -> u64 {
+        let request = test::TestRequest::get().uri( uri ).to_request();
+        let body = test::read_response( app, request );
+        String::from_utf8( body.to_vec() ).unwrap().lines().map( |line| line.rsplit( ' ' ).next().unwrap().parse::< u64 >().unwrap() ).sum()
+    };
+
+    assert_eq!( total_of( &mut app, "/data/last/export/mmap_flamegraph.pl" ), 0x4000 + 0x1000 + 0x2000 );
+    assert_eq!( total_of( &mut app, "/data/last/export/mmap_flamegraph.pl?weight=live" ), 0x3000 + 0x1000 );
+    assert_eq!( total_of( &mut app, "/data/last/export/mmap_flamegraph.pl?size_max=8192" ), 0x1000 + 0x2000 );
+
+    let request = test::TestRequest::get().uri( "/data/last/export/mmap_flamegraph?weight=live" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.headers().get( "Content-Type" ).unwrap(), "image/svg+xml" );
+    assert!( response.headers().get( "Content-Disposition" ).unwrap().to_str().unwrap().contains( "-live-mmaps.svg" ) );
+    assert!( String::from_utf8( test::read_body( response ).to_vec() ).unwrap().contains( "<svg" ) );
+}
+
+#[test]
+fn test_mmap_ascii_tree() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000 ].into() },
+        test_mmap( 1, 0x10000, 0x4000, 1 ),
+        test_mmap( 2, 0x20000, 0x1000, 1 ),
+        test_mmap( 3, 0x30000, 0x2000, 2 ),
+        test_munmap( 4, 0x10000, 0x1000 ),
+        test_munmap( 5, 0x30000, 0x2000 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    // Returns the size and the count from the root of the tree.
+    let root_of = |app: &mut _, uri: &str| -> (String, String) {
+        let request = test::TestRequest::get().uri( uri ).to_request();
+        let body = String::from_utf8( test::read_response( app, request ).to_vec() ).unwrap();
+        let mut lines = body.lines();
+        assert!( lines.next().unwrap().starts_with( "SIZE" ) );
+        let root: Vec< _ > = lines.next().unwrap().split_whitespace().collect();
+        (format!( "{} {}", root[ 0 ], root[ 1 ] ), root[ 2 ].to_owned())
+    };
+
+    assert_eq!( root_of( &mut app, "/data/last/mmap_ascii_tree" ), ("28.672 KB".to_owned(), "3".to_owned()) );
+    assert_eq!( root_of( &mut app, "/data/last/mmap_ascii_tree?weight=live" ), ("16.384 KB".to_owned(), "2".to_owned()) );
+    assert_eq!( root_of( &mut app, "/data/last/mmap_ascii_tree?size_max=8192" ), ("12.288 KB".to_owned(), "2".to_owned()) );
+}
+
+#[test]
+fn test_mmap_regions() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000 ].into() },
+        test_mmap( 1, 0x10000, 0x4000, 1 ),
+        test_munmap( 3, 0x11000, 0x1000 ),
+        Event::MemoryUnmap { timestamp: Timestamp::from_secs( 4 ), pointer: 0x13000, length: 0x1000, backtrace: 2, thread: 1 }
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_regions" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 4 );
+    let regions = response[ "regions" ].as_array().unwrap();
+    let pointers: Vec< _ > = regions.iter().map( |region| region[ "pointer" ].as_u64().unwrap() ).collect();
+    assert_eq!( pointers, vec![ 0x10000, 0x11000, 0x12000, 0x13000 ] );
+
+    // The parts which have survived.
+    for &index in &[ 0, 2 ] {
+        assert_eq!( regions[ index ][ "length" ], 0x1000 );
+        assert!( regions[ index ][ "unmapped_at" ].is_null() );
+        assert!( regions[ index ][ "munmap_backtrace_id" ].is_null() );
+        assert_eq!( regions[ index ][ "lifetime" ][ "secs" ], 3 );
+    }
+
+    for region in regions {
+        assert_eq!( region[ "mapping_pointer" ], 0x10000 );
+        assert_eq!( region[ "mapping_length" ], 0x4000 );
+        assert_eq!( region[ "mmap_backtrace_id" ], regions[ 0 ][ "mmap_backtrace_id" ] );
+    }
+
+    assert_eq!( regions[ 1 ][ "length" ], 0x1000 );
+    assert_eq!( regions[ 1 ][ "lifetime" ][ "secs" ], 2 );
+    assert_eq!( regions[ 1 ][ "offset" ], 0x1000 );
+    assert_eq!( regions[ 3 ][ "length" ], 0x1000 );
+    assert_eq!( regions[ 3 ][ "lifetime" ][ "secs" ], 3 );
+    assert_eq!( regions[ 3 ][ "offset" ], 0x3000 );
+    assert_ne!( regions[ 1 ][ "munmap_backtrace_id" ], regions[ 3 ][ "munmap_backtrace_id" ] );
+}
+
+#[test]
+fn test_mmap_file_paths() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let file_mmap = |secs: u64, pointer: u64, length: u64, offset: u64| Event::MemoryMap {
+        timestamp: Timestamp::from_secs( secs ),
+        pointer,
+        length,
+        backtrace: 1,
+        requested_address: 0,
+        mmap_protection: 0x1,
+        mmap_flags: 0x2,
+        file_descriptor: 3,
+        thread: 1,
+        offset
+    };
+
+    let maps = "\
+        00010000-00012000 r--p 00000000 08:01 1234 /usr/lib/libfoo.so\n\
+        00012000-00013000 r-xp 00002000 08:01 1234 /usr/lib/libfoo.so\n\
+        00020000-00021000 r--p 00000000 08:01 5678 /var/data/table.bin\n";
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        file_mmap( 1, 0x10000, 0x3000, 0 ),
+        file_mmap( 1, 0x12000, 0x1000, 0x2000 ),
+        file_mmap( 2, 0x20000, 0x1000, 0 ),
+        // Something else was mapped here before the snapshot was taken.
+        file_mmap( 2, 0x30000, 0x1000, 0 ),
+        test_munmap( 3, 0x30000, 0x1000 ),
+        test_mmap( 3, 0x40000, 0x4000, 1 ),
+        Event::File { timestamp: Timestamp::from_secs( 4 ), path: "/proc/self/maps".into(), contents: maps.as_bytes().to_vec().into() }
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmaps" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let paths: Vec< _ > = response[ "operations" ].as_array().unwrap().iter()
+        .filter_map( |op| op.get( "mmap" ) )
+        .map( |mmap| mmap[ "file_path" ].clone() )
+        .collect();
+    assert_eq!( paths, vec![
+        serde_json::json!( "/usr/lib/libfoo.so" ),
+        serde_json::json!( "/usr/lib/libfoo.so" ),
+        serde_json::json!( "/var/data/table.bin" ),
+        serde_json::Value::Null,
+        serde_json::Value::Null
+    ]);
+
+    let request = test::TestRequest::get().uri( "/data/last/mmaps?file_regex=libfoo" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "operations" ].as_array().unwrap().len(), 2 );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_groups?group_by=file&sort_by=mapped_bytes" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 3 );
+    let groups = &response[ "groups" ];
+    assert_eq!( groups[ 0 ][ "file_path" ], serde_json::Value::Null );
+    assert_eq!( groups[ 0 ][ "mapped_bytes" ], 0x5000 );
+    assert_eq!( groups[ 0 ][ "map_count" ], 2 );
+    assert_eq!( groups[ 1 ][ "file_path" ], "/usr/lib/libfoo.so" );
+    assert_eq!( groups[ 1 ][ "mapped_bytes" ], 0x4000 );
+    assert_eq!( groups[ 1 ][ "backtrace_id" ], serde_json::Value::Null );
+    assert_eq!( groups[ 2 ][ "file_path" ], "/var/data/table.bin" );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_groups?file_regex=%5E/var/" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 1 );
+    assert_eq!( response[ "groups" ][ 0 ][ "mapped_bytes" ], 0x1000 );
+    assert_eq!( response[ "groups" ][ 0 ][ "file_path" ], serde_json::Value::Null );
+}
+
+#[test]
+fn test_mmap_remap_and_protect() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_mmap( 1, 0x10000, 0x1000, 1 ),
+        // Grown and moved.
+        Event::MemoryRemap {
+            timestamp: Timestamp::from_secs( 2 ),
+            old_pointer: 0x10000,
+            old_length: 0x1000,
+            new_pointer: 0x20000,
+            new_length: 0x3000,
+            backtrace: 1,
+            remap_flags: 0x1,
+            thread: 1
+        },
+        Event::MemoryProtect { timestamp: Timestamp::from_secs( 3 ), pointer: 0x20000, length: 0x1000, backtrace: 1, protection: 0x1, thread: 1 },
+        test_munmap( 4, 0x21000, 0x1000 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmaps" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let operations = response[ "operations" ].as_array().unwrap();
+    assert_eq!( operations.len(), 4 );
+    let mremap = operations[ 1 ][ "mremap" ].clone();
+    assert_eq!( mremap[ "old_pointer" ], 0x10000 );
+    assert_eq!( mremap[ "new_pointer" ], 0x20000 );
+    assert_eq!( mremap[ "new_length" ], 0x3000 );
+    assert_eq!( mremap[ "is_may_move" ], true );
+    assert_eq!( mremap[ "is_dont_unmap" ], false );
+    let mprotect = &operations[ 2 ][ "mprotect" ];
+    assert_eq!( mprotect[ "pointer" ], 0x20000 );
+    assert_eq!( mprotect[ "is_readable" ], true );
+    assert_eq!( mprotect[ "is_writable" ], false );
+
+    // The remap doesn't create a new map.
+    let request = test::TestRequest::get().uri( "/data/last/mmap_groups" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 1 );
+    let group = &response[ "groups" ][ 0 ];
+    assert_eq!( group[ "map_count" ], 1 );
+    assert_eq!( group[ "mapped_bytes" ], 0x3000 );
+    assert_eq!( group[ "unmapped_bytes" ], 0x1000 );
+    assert_eq!( group[ "live_bytes" ], 0x2000 );
+
+    let request = test::TestRequest::get().uri( "/data/last/mmap_regions" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let regions = response[ "regions" ].as_array().unwrap();
+    let pointers: Vec< _ > = regions.iter().map( |region| region[ "pointer" ].as_u64().unwrap() ).collect();
+    assert_eq!( pointers, vec![ 0x20000, 0x21000, 0x22000 ] );
+    for region in regions {
+        assert_eq!( region[ "mapped_at" ], mremap[ "timestamp" ] );
+    }
+}
+
+#[test]
+fn test_allocations_backing_map() {
+    use actix_web::test;
+    use common::event::{AllocBody, Event};
+
+    let alloc = |pointer: u64, flags: u32| Event::Alloc {
+        timestamp: Timestamp::from_secs( 2 ),
+        allocation: AllocBody { flags, ..test_alloc_body( pointer, 0x100, 1 ) }
+    };
+
+    let mut shared = test_mmap( 1, 0x100000, 0x10000, 1 );
+    if let Event::MemoryMap { ref mut mmap_flags, .. } = shared {
+        *mmap_flags = 0x21;
+    }
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        shared,
+        test_mmap( 1, 0x200000, 0x10000, 1 ),
+        alloc( 0x100010, 2 ),
+        alloc( 0x200010, 2 ),
+        alloc( 0x300010, 2 ),
+        alloc( 0x200200, 0 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?include_backing_map=true" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let allocations = &response[ "allocations" ];
+    assert_eq!( allocations[ 0 ][ "backing_map" ][ "id" ], 0 );
+    assert_eq!( allocations[ 0 ][ "backing_map" ][ "pointer" ], 0x100000 );
+    assert_eq!( allocations[ 0 ][ "backing_map" ][ "is_shared" ], true );
+    assert_eq!( allocations[ 1 ][ "backing_map" ][ "id" ], 1 );
+    assert_eq!( allocations[ 1 ][ "backing_map" ][ "is_shared" ], false );
+    assert_eq!( allocations[ 1 ][ "backing_map" ][ "is_private" ], true );
+    assert_eq!( allocations[ 2 ][ "backing_map" ], serde_json::Value::Null );
+    assert!( allocations[ 2 ].get( "backing_map" ).is_some() );
+    assert!( allocations[ 3 ].get( "backing_map" ).is_none() );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert!( response[ "allocations" ][ 0 ].get( "backing_map" ).is_none() );
+}
+
+#[test]
+fn test_allocations_dealloc_backtrace() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000, 0x3000 ].into() },
+        test_alloc( 1, 0x1000, 0x10, 1 ),
+        test_alloc( 1, 0x2000, 0x10, 1 ),
+        test_alloc( 1, 0x3000, 0x10, 1 ),
+        test_free( 2, 0x1000, 2 ),
+        // The backtrace of this one wasn't recorded.
+        test_free( 2, 0x2000, 0 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?include_dealloc_backtrace=true" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let allocations = &response[ "allocations" ];
+    let deallocation = &allocations[ 0 ][ "deallocation" ];
+    assert!( deallocation[ "backtrace_id" ].is_u64() );
+    assert_ne!( deallocation[ "backtrace_id" ], allocations[ 0 ][ "backtrace_id" ] );
+    assert!( deallocation[ "backtrace" ].is_array() );
+    assert!( allocations[ 1 ][ "deallocation" ].is_object() );
+    assert!( allocations[ 1 ][ "deallocation" ].get( "backtrace_id" ).is_none() );
+    assert!( allocations[ 1 ][ "deallocation" ].get( "backtrace" ).is_none() );
+    assert!( allocations[ 2 ][ "deallocation" ].is_null() );
+
+    // It's off by default.
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert!( response[ "allocations" ][ 0 ][ "deallocation" ].get( "backtrace_id" ).is_none() );
+    assert!( response[ "allocations" ][ 0 ][ "deallocation" ].get( "backtrace" ).is_none() );
+}
+
+#[test]
+fn test_allocations_totals() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 1, 0x1000, 10, 1 ),
+        test_alloc( 1, 0x2000, 20, 1 ),
+        test_alloc( 1, 0x3000, 30, 1 ),
+        test_alloc( 1, 0x4000, 40, 1 ),
+        test_free( 2, 0x1000, 1 ),
+        test_free( 2, 0x3000, 1 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    // The totals cover everything which matched, not only the returned page.
+    let request = test::TestRequest::get().uri( "/data/last/allocations?count=1" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "allocations" ].as_array().unwrap().len(), 1 );
+    assert_eq!( response[ "total_count" ], 4 );
+    assert_eq!( response[ "total_leaked_count" ], 2 );
+    assert_eq!( response[ "total_freed_count" ], 2 );
+    assert_eq!( response[ "total_size" ], 100 );
+    assert_eq!( response[ "total_leaked_size" ], 60 );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?size_min=25" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 2 );
+    assert_eq!( response[ "total_leaked_count" ], 1 );
+    assert_eq!( response[ "total_freed_count" ], 1 );
+    assert_eq!( response[ "total_size" ], 70 );
+    assert_eq!( response[ "total_leaked_size" ], 40 );
+}
+
+#[test]
+fn test_match_cache_is_shared_between_endpoints() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 1, 0x1000, 10, 1 ),
+        test_alloc( 1, 0x2000, 20, 1 ),
+        test_alloc( 1, 0x3000, 30, 1 )
+    ]);
+
+    let (state, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?size_min=20" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 2 );
+    assert_eq!( state.match_cache.hits_and_misses(), (0, 1) );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocation_groups?size_min=20" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "allocations" ][ 0 ][ "only_matched" ][ "allocated_count" ], 2 );
+    assert_eq!( state.match_cache.hits_and_misses(), (1, 1) );
+
+    // A different filter has to be evaluated on its own.
+    let request = test::TestRequest::get().uri( "/data/last/allocations?size_min=30" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 1 );
+    assert_eq!( state.match_cache.hits_and_misses(), (1, 2) );
+
+    // No filter at all matches everything, so it isn't cached.
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 3 );
+    assert_eq!( state.match_cache.hits_and_misses(), (1, 2) );
+}
+
+#[test]
+fn test_match_cache_is_invalidated_when_symbols_are_loaded() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let events = |function| vec![
+        test_string( 0, "libfoo.so" ),
+        test_string( 1, "leak_memory" ),
+        test_frame( 0x1000, function, 0xFFFFFFFF, 0xFFFFFFFF, false ),
+        Event::DecodedBacktrace { frames: vec![ 0 ].into() },
+        test_alloc( 1, 0x1000, 10, 0 )
+    ];
+
+    let id = DataId::new( 1, 2 );
+    let (state, mut app) = test_app( vec![ load_test_data( id, events( 0xFFFFFFFF ) ) ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?function_regex=leak" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 0 );
+
+    // This is what happens once the debug symbols are loaded, minus the symbolication itself.
+    let old_data = state.get_data( id ).unwrap();
+    assert!( state.replace_data( &old_data, load_test_data( id, events( 1 ) ) ) );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?function_regex=leak" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 1 );
+    assert_eq!( state.match_cache.hits_and_misses(), (0, 2) );
+}
+
+#[test]
+fn test_debug_symbols_can_be_loaded_while_the_data_is_in_use() {
+    use actix_web::test;
+
+    let id = DataId::new( 1, 2 );
+    let (state, mut app) = test_app( vec![ load_test_data( id, Vec::new() ) ] );
+
+    let in_use = state.get_data( id ).unwrap();
+    let request = test::TestRequest::post().uri( "/data/last/debug_symbols?filename=libfoo.debug" ).set_payload( "not an ELF file" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "symbolicated_frames" ], 0 );
+
+    // Nothing was symbolicated, so there was no need to replace the data.
+    assert!( Arc::ptr_eq( &in_use, &state.get_data( id ).unwrap() ) );
+
+    let request = test::TestRequest::post().uri( "/data/last/debug_symbols" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
+}
+
+#[test]
+fn test_uploaded_debug_symbols() {
+    let mut upload = UploadedDebugSymbols::create( DataId::new( 1, 2 ), Some( "../libfoo.debug" ), 4 ).unwrap();
+    assert_eq!( upload.path.file_name().unwrap(), "libfoo.debug" );
+    assert!( upload.write( b"abc" ).is_ok() );
+    assert_eq!( upload.write( b"de" ).unwrap_err().to_json()[ "error" ][ "code" ], "payload_too_large" );
+    assert_eq!( std::fs::read( &upload.path ).unwrap(), b"abc" );
+
+    let directory = upload.directory.clone();
+    drop( upload );
+    assert!( !directory.exists() );
+}
+
+#[test]
+fn test_allocations_peak_flag() {
+    use actix_web::test;
+    use common::event::Event;
+
+    // The peak is at 4s, when 0x2000 and 0x3000 are alive.
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x1000, 100, 1 ),
+        test_alloc( 2, 0x2000, 100, 1 ),
+        test_free( 3, 0x1000, 1 ),
+        test_alloc( 4, 0x3000, 150, 1 ),
+        test_free( 5, 0x3000, 1 ),
+        test_alloc( 6, 0x4000, 10, 1 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?include_peak_flag=true" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let flags: Vec< _ > = response[ "allocations" ].as_array().unwrap().iter().map( |allocation| allocation[ "alive_at_peak" ].as_bool().unwrap() ).collect();
+    assert_eq!( flags, vec![ false, true, true, false ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert!( response[ "allocations" ][ 0 ].get( "alive_at_peak" ).is_none() );
+}
+
+#[test]
+fn test_wall_clock() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let events = || vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x1000, 16, 1 ),
+        test_free( 3, 0x1000, 1 ),
+        test_mmap( 4, 0x10000, 0x1000, 1 )
+    ];
+
+    let with_wall_clock = DataId::new( 1, 2 );
+    let without_wall_clock = DataId::new( 3, 4 );
+    let mut events_with_wall_clock = vec![ Event::WallClock { timestamp: Timestamp::from_secs( 1 ), sec: 1_600_000_000, nsec: 0 } ];
+    events_with_wall_clock.extend( events() );
+
+    let (_, mut app) = test_app( vec![
+        load_test_data( with_wall_clock, events_with_wall_clock ),
+        load_test_data( without_wall_clock, events() )
+    ]);
+    let mut get = |uri: String| -> serde_json::Value {
+        test::read_response_json( &mut app, test::TestRequest::get().uri( &uri ).to_request() )
+    };
+
+    let list = get( "/list".into() );
+    let metadata = list.as_array().unwrap().iter().find( |entry| entry[ "id" ] == with_wall_clock.to_string() ).unwrap();
+    assert_eq!( metadata[ "has_wall_clock" ], true );
+    assert_eq!( metadata[ "wall_clock_start" ], "2020-09-13T12:26:40.000000Z" );
+    let metadata = list.as_array().unwrap().iter().find( |entry| entry[ "id" ] == without_wall_clock.to_string() ).unwrap();
+    assert_eq!( metadata[ "has_wall_clock" ], false );
+    assert!( metadata[ "wall_clock_start" ].is_null() );
+
+    let response = get( format!( "/data/{}/allocations?wall_clock=true", with_wall_clock ) );
+    let allocation = &response[ "allocations" ][ 0 ];
+    assert_eq!( allocation[ "timestamp_wall_clock" ], "2020-09-13T12:26:41.000000Z" );
+    assert_eq!( allocation[ "deallocation" ][ "timestamp_wall_clock" ], "2020-09-13T12:26:42.000000Z" );
+
+    let response = get( format!( "/data/{}/allocation_groups?wall_clock=true", with_wall_clock ) );
+    let group = &response[ "allocations" ][ 0 ];
+    assert_eq!( group[ "all" ][ "min_timestamp_wall_clock" ], "2020-09-13T12:26:41.000000Z" );
+    assert_eq!( group[ "only_matched" ][ "max_timestamp_wall_clock" ], "2020-09-13T12:26:41.000000Z" );
+
+    let response = get( format!( "/data/{}/mmaps?wall_clock=true", with_wall_clock ) );
+    assert_eq!( response[ "operations" ][ 0 ][ "mmap" ][ "timestamp_wall_clock" ], "2020-09-13T12:26:43.000000Z" );
+
+    let response = get( format!( "/data/{}/timeline?wall_clock=true", with_wall_clock ) );
+    let xs_wall_clock = response[ "xs_wall_clock" ].as_array().unwrap();
+    assert_eq!( xs_wall_clock.len(), response[ "xs" ].as_array().unwrap().len() );
+    assert_eq!( xs_wall_clock[ 0 ], "2020-09-13T12:26:41.000000Z" );
+
+    // The fields are there, but empty, if we don't know when the data was recorded...
+    let response = get( format!( "/data/{}/allocations?wall_clock=true", without_wall_clock ) );
+    assert!( response[ "allocations" ][ 0 ][ "timestamp_wall_clock" ].is_null() );
+    assert!( response[ "allocations" ][ 0 ][ "deallocation" ][ "timestamp_wall_clock" ].is_null() );
+    let response = get( format!( "/data/{}/timeline?wall_clock=true", without_wall_clock ) );
+    assert!( response[ "xs_wall_clock" ].is_null() );
+
+    // ...and they're not there at all if they weren't requested.
+    let response = get( format!( "/data/{}/allocations", with_wall_clock ) );
+    assert!( response[ "allocations" ][ 0 ].get( "timestamp_wall_clock" ).is_none() );
+    let response = get( format!( "/data/{}/mmaps", with_wall_clock ) );
+    assert!( response[ "operations" ][ 0 ][ "mmap" ].get( "timestamp_wall_clock" ).is_none() );
+    let response = get( format!( "/data/{}/timeline", with_wall_clock ) );
+    assert!( response.get( "xs_wall_clock" ).is_none() );
+}
+
+#[test]
+fn test_compare_timeline() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let before = DataId::new( 1, 2 );
+    let after = DataId::new( 3, 4 );
+    let (state, mut app) = test_app( vec![
+        load_test_data( before, vec![
+            Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+            test_alloc( 2, 0x1000, 100, 1 ),
+            test_free( 4, 0x1000, 1 )
+        ]),
+        load_test_data( after, vec![
+            Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+            test_alloc( 2, 0x1000, 40, 1 ),
+            test_alloc( 4, 0x2000, 20, 1 ),
+            test_alloc( 6, 0x3000, 10, 1 )
+        ])
+    ]);
+
+    let uri = format!( "/compare/timeline?a={}&b={}", before, after );
+    let response: serde_json::Value = test::read_response_json( &mut app, test::TestRequest::get().uri( &uri ).to_request() );
+    let a = response[ "a" ].as_array().unwrap();
+    let b = response[ "b" ].as_array().unwrap();
+    let delta = response[ "delta" ].as_array().unwrap();
+    assert_eq!( response[ "xs" ].as_array().unwrap().len(), a.len() );
+    assert_eq!( b.last().unwrap(), 70 );
+
+    // The delta only covers the range which both of them share.
+    let overlap = a.iter().take_while( |value| !value.is_null() ).count();
+    assert!( overlap > 0 && overlap < a.len() );
+    assert!( delta[ overlap.. ].iter().all( |value| value.is_null() ) );
+    for index in 0..overlap {
+        assert_eq!( delta[ index ].as_i64().unwrap(), b[ index ].as_i64().unwrap() - a[ index ].as_i64().unwrap() );
+    }
+
+    assert!( a.iter().any( |value| value == 100 ) );
+    assert_eq!( state.timeline_cache.hits_and_misses(), (0, 2) );
+}
+
+#[test]
+fn test_allocations_origin() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        test_string( 0, "libfoo.so" ),
+        test_string( 1, "main" ),
+        test_string( 2, "std::vector<int, std::allocator<int> >::push_back(int const&)" ),
+        test_string( 3, "/src/main.cpp" ),
+        test_string( 4, "/usr/include/vector" ),
+        test_string( 5, "malloc" ),
+        test_frame( 0x1000, 5, 0xFFFFFFFF, 0xFFFFFFFF, false ),
+        test_frame( 0x2000, 2, 4, 10, false ),
+        test_frame( 0x3000, 1, 3, 20, false ),
+        Event::DecodedBacktrace { frames: vec![ 0, 1, 2 ].into() },
+        Event::DecodedBacktrace { frames: vec![ 0 ].into() },
+        Event::DecodedBacktrace { frames: vec![ 0, 2 ].into() },
+        test_alloc( 2, 0x10000, 16, 0 ),
+        test_alloc( 2, 0x20000, 16, 1 ),
+        test_alloc( 2, 0x30000, 16, 2 ),
+        test_alloc( 2, 0x40000, 16, 0 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?include_origin=true&strip_template_args=true" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let origins: Vec< _ > = response[ "allocations" ].as_array().unwrap().iter().map( |allocation| allocation[ "origin" ].clone() ).collect();
+    assert_eq!( origins, vec![
+        serde_json::json!( "std::vector<...>::push_back(int const&) (/usr/include/vector:10)" ),
+        serde_json::Value::Null,
+        serde_json::json!( "main (/src/main.cpp:20)" ),
+        serde_json::json!( "std::vector<...>::push_back(int const&) (/usr/include/vector:10)" )
+    ]);
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert!( response[ "allocations" ][ 0 ].get( "origin" ).is_none() );
+}
+
+#[test]
+fn test_allocation() {
+    use actix_web::test;
+    use common::event::{AllocBody, Event};
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000 ].into() },
+        test_mmap( 1, 0x100000, 0x10000, 1 ),
+        test_alloc( 1, 0x1000, 0x10, 1 ),
+        Event::Realloc { timestamp: Timestamp::from_secs( 2 ), old_pointer: 0x1000, allocation: test_alloc_body( 0x2000, 0x20, 1 ) },
+        test_free( 3, 0x2000, 2 ),
+        Event::Alloc { timestamp: Timestamp::from_secs( 3 ), allocation: AllocBody { flags: 2, ..test_alloc_body( 0x100010, 0x100, 1 ) } }
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocation/1" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "id" ], 1 );
+    assert_eq!( response[ "size" ], 0x20 );
+    assert_eq!( response[ "position_in_chain" ], 1 );
+    assert!( response[ "deallocation" ].is_object() );
+    assert!( response[ "deallocation_backtrace" ].is_array() );
+    assert_ne!( response[ "deallocation_backtrace_id" ], response[ "backtrace_id" ] );
+    assert!( response.get( "backing_map" ).is_none() );
+
+    let chain_of = |response: &serde_json::Value| -> Vec< (u64, u64) > {
+        response[ "chain" ].as_array().unwrap().iter().map( |link| (link[ "id" ].as_u64().unwrap(), link[ "size" ].as_u64().unwrap()) ).collect()
+    };
+    assert_eq!( chain_of( &response ), vec![ (0, 0x10), (1, 0x20) ] );
+
+    // The whole chain is returned no matter which of its allocations was requested.
+    let request = test::TestRequest::get().uri( "/data/last/allocation/0" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( chain_of( &response ), vec![ (0, 0x10), (1, 0x20) ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocation/2" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( chain_of( &response ), vec![ (2, 0x100) ] );
+    assert_eq!( response[ "backing_map" ][ "pointer" ], 0x100000 );
+    assert!( response.get( "deallocation_backtrace" ).is_none() );
+
+    for uri in &[ "/data/last/allocation/3", "/data/last/allocation/foo" ] {
+        let request = test::TestRequest::get().uri( uri ).to_request();
+        let response = test::call_service( &mut app, request );
+        assert_eq!( response.status(), actix_web::http::StatusCode::NOT_FOUND );
+        let response: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+        assert_eq!( response[ "error" ][ "code" ], "not_found" );
+    }
+}
+
+#[test]
+fn test_resolve_pointer() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 1, 0x1000, 0x100, 1 ),
+        test_alloc( 2, 0x5000, 0x10000, 1 ),
+        test_free( 3, 0x1000, 1 ),
+        test_free( 3, 0x5000, 1 ),
+        // The same addresses were reused.
+        test_alloc( 4, 0x1000, 0x80, 1 ),
+        test_alloc( 4, 0x9000, 0x10, 1 )
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let mut resolve = |query: &str| -> Vec< (u64, i64) > {
+        let request = test::TestRequest::get().uri( &format!( "/data/last/resolve_pointer?{}", query ) ).to_request();
+        let response: serde_json::Value = test::read_response_json( &mut app, request );
+        response[ "allocations" ].as_array().unwrap().iter().map( |allocation| {
+            (allocation[ "id" ].as_u64().unwrap(), allocation[ "offset" ].as_i64().unwrap())
+        }).collect()
+    };
+
+    assert_eq!( resolve( "address=0x1010" ), vec![ (0, 0x10), (2, 0x10) ] );
+    assert_eq!( resolve( "address=0x1010&at=1000" ), vec![ (0, 0x10) ] );
+    assert_eq!( resolve( "address=0x1010&at=2000" ), vec![] );
+    assert_eq!( resolve( "address=0x1010&at=3000" ), vec![ (2, 0x10) ] );
+
+    // Only the first allocation was big enough to contain this address.
+    assert_eq!( resolve( "address=0x10c0" ), vec![ (0, 0xc0) ] );
+
+    // Pointing into the header of the allocation.
+    assert_eq!( resolve( "address=0xff8" ), vec![ (0, -8), (2, -8) ] );
+
+    // A big allocation which starts way before the address.
+    assert_eq!( resolve( "address=a000" ), vec![ (1, 0x5000) ] );
+    assert_eq!( resolve( "address=0x9008" ), vec![ (1, 0x4008), (3, 0x8) ] );
+    assert_eq!( resolve( "address=0x9008&at=3000" ), vec![ (3, 0x8) ] );
+    assert_eq!( resolve( "address=0x100000" ), vec![] );
+
+    let request = test::TestRequest::get().uri( "/data/last/resolve_pointer?address=foo" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), actix_web::http::StatusCode::BAD_REQUEST );
+}
+
+fn handler_backtrace( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let backtrace_id = req.match_info().get( "backtrace_id" ).unwrap();
+    let backtrace_id = parse_backtrace_id( backtrace_id, data.unique_backtrace_count() ).ok_or_else( || ApiError::not_found( "backtrace not found" ) )?;
+    let backtrace = data.get_backtrace( backtrace_id );
+    let backtrace_format = get_backtrace_format( &req )?;
+    let params: protocol::RequestBacktrace = query( &req )?;
+
+    let (frames, truncated_count) = get_frames( data, &backtrace_format, backtrace );
+    let stats = if params.frames_only.unwrap_or( false ) {
+        None
+    } else {
+        Some( get_global_group_data( data, backtrace_id ) )
+    };
+
+    let response = protocol::ResponseBacktrace {
+        frames,
+        truncated_count,
+        stats
+    };
+
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_backtraces( req: HttpRequest ) -> Result< HttpResponse > {
+    let backtrace_format = get_backtrace_format( &req )?;
+    let filter: protocol::BacktraceFilter = query( &req )?;
+    let filter = crate::filter::prepare_backtrace_filter( &filter )?;
+    let format = get_response_format( &req )?;
+    let body = async_data_handler( &req, move |data, tx| {
+        let mut positive_cache = HashMap::new();
+        let mut negative_cache = HashMap::new();
+        let backtrace_ids: Vec< _ > = data.all_backtraces().flat_map( |(backtrace_id, backtrace)| {
+            if !crate::filter::match_backtrace( &data, &mut positive_cache, &mut negative_cache, &filter, backtrace ) {
+                None
+            } else {
+                Some( backtrace_id )
+            }
+        }).collect();
+
+        let data = &data;
+        let backtrace_ids = &backtrace_ids;
+        let backtraces = move || {
+            let mut backtraces = BacktraceFormatter::new( data, backtrace_format.clone() );
+            backtrace_ids.iter().map( move |&backtrace_id| {
+                let stats = data.get_group_statistics( backtrace_id );
+                let (frames, truncated_count) = backtraces.get_frames( backtrace_id );
+                protocol::BacktraceEntry {
+                    backtrace_id: backtrace_id.raw(),
+                    allocation_count: stats.alloc_count,
+                    total_size: stats.alloc_size,
+                    leaked_count: stats.alloc_count - stats.free_count,
+                    frames,
+                    truncated_count
+                }
+            })
+        };
+
+        let response = protocol::ResponseBacktraces {
+            backtraces: StreamingSerializer::new( backtraces ),
+            total_count: backtrace_ids.len() as u64
+        };
+
+        write_response( tx, format, &response );
+    })?;
+
+    Ok( response_with_format( format ).body( body ) )
+}
+
+/// Returns the Jaccard similarity of two sorted and deduplicated sets.
+fn jaccard_similarity( a: &[u64], b: &[u64] ) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let total = a.len() + b.len();
+    let mut common = 0;
+    let mut a = a.iter().peekable();
+    let mut b = b.iter().peekable();
+    while let (Some( &&lhs ), Some( &&rhs )) = (a.peek(), b.peek()) {
+        if lhs < rhs {
+            a.next();
+        } else if lhs > rhs {
+            b.next();
+        } else {
+            common += 1;
+            a.next();
+            b.next();
+        }
+    }
+
+    common as f64 / (total - common) as f64
+}
+
+/// Greedily clusters the given sets; every set is assigned to the first cluster
+/// whose representative (the first set assigned to it) is at least `threshold` similar.
+fn cluster_sets( sets: &[Vec< u64 >], threshold: f64 ) -> Vec< Vec< usize > > {
+    let mut clusters: Vec< Vec< usize > > = Vec::new();
+
+    // Since the threshold is never zero only the clusters whose representatives
+    // share at least one element with a set can ever match it.
+    let mut clusters_by_element: HashMap< u64, Vec< usize > > = HashMap::new();
+    let mut candidates = Vec::new();
+    for (index, set) in sets.iter().enumerate() {
+        candidates.clear();
+        for element in set {
+            if let Some( clusters ) = clusters_by_element.get( element ) {
+                candidates.extend_from_slice( clusters );
+            }
+        }
+
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let position = candidates.iter().cloned().find( |&position| {
+            let representative = &sets[ clusters[ position ][ 0 ] ];
+            let upper_bound = min( set.len(), representative.len() ) as f64 / max( max( set.len(), representative.len() ), 1 ) as f64;
+            upper_bound >= threshold && jaccard_similarity( set, representative ) >= threshold
+        });
+
+        match position {
+            Some( position ) => clusters[ position ].push( index ),
+            None => {
+                let position = clusters.len();
+                clusters.push( vec![ index ] );
+                for &element in set {
+                    clusters_by_element.entry( element ).or_default().push( position );
+                }
+            }
+        }
+    }
+
+    clusters
+}
+
+#[test]
+fn test_jaccard_similarity() {
+    assert_eq!( jaccard_similarity( &[], &[] ), 1.0 );
+    assert_eq!( jaccard_similarity( &[1, 2, 3], &[] ), 0.0 );
+    assert_eq!( jaccard_similarity( &[1, 2, 3], &[1, 2, 3] ), 1.0 );
+    assert_eq!( jaccard_similarity( &[1, 2, 3], &[4, 5, 6] ), 0.0 );
+    assert_eq!( jaccard_similarity( &[1, 2, 3], &[2, 3, 4] ), 0.5 );
+    assert_eq!( jaccard_similarity( &[1, 2, 3, 4], &[1, 2, 3] ), 0.75 );
+}
+
+#[test]
+fn test_cluster_sets() {
+    let sets = vec![
+        vec![ 1, 2, 3, 4, 5 ],
+        vec![ 10, 11, 12 ],
+        vec![ 1, 2, 3, 4, 6 ],
+        vec![ 1, 2, 3, 4, 5, 7 ],
+        vec![ 10, 11, 13 ]
+    ];
+
+    assert_eq!( cluster_sets( &sets, 1.0 ), vec![ vec![ 0 ], vec![ 1 ], vec![ 2 ], vec![ 3 ], vec![ 4 ] ] );
+    assert_eq!( cluster_sets( &sets, 0.8 ), vec![ vec![ 0, 3 ], vec![ 1 ], vec![ 2 ], vec![ 4 ] ] );
+    assert_eq!( cluster_sets( &sets, 0.6 ), vec![ vec![ 0, 2, 3 ], vec![ 1 ], vec![ 4 ] ] );
+    assert_eq!( cluster_sets( &sets, 0.5 ), vec![ vec![ 0, 2, 3 ], vec![ 1, 4 ] ] );
+    assert_eq!( cluster_sets( &[], 0.5 ), Vec::< Vec< usize > >::new() );
+    assert_eq!( cluster_sets( &[ vec![], vec![ 1 ], vec![] ], 0.5 ), vec![ vec![ 0 ], vec![ 1 ], vec![ 2 ] ] );
+}
+
+struct BacktraceCluster {
+    members: Vec< BacktraceId >,
+    allocated_count: u64,
+    allocated_size: u64,
+    leaked_count: u64,
+    leaked_size: u64
+}
+
+/// Groups together backtraces which share most of their frames.
+///
+/// Only backtraces which were used for allocations are considered. The representative
+/// of each cluster is its backtrace with the most allocated bytes, and the clusters
+/// are sorted by the amount of bytes allocated from them.
+fn compute_backtrace_clusters( data: &Data, threshold: f64 ) -> Vec< BacktraceCluster > {
+    let mut backtrace_ids: Vec< _ > = (0..data.unique_backtrace_count() as u32)
+        .map( BacktraceId::new )
+        .filter( |&backtrace_id| data.get_group_statistics( backtrace_id ).alloc_count > 0 )
+        .collect();
+
+    backtrace_ids.par_sort_by_key( |&backtrace_id| std::cmp::Reverse( data.get_group_statistics( backtrace_id ).alloc_size ) );
+
+    let sets: Vec< Vec< u64 > > = backtrace_ids.par_iter().map( |&backtrace_id| {
+        let mut set: Vec< _ > = data.get_backtrace( backtrace_id ).map( |(_, frame)| frame.address().raw() ).collect();
+        set.sort_unstable();
+        set.dedup();
+        set
+    }).collect();
+
+    let mut clusters: Vec< _ > = cluster_sets( &sets, threshold ).into_par_iter().map( |indexes| {
+        let mut cluster = BacktraceCluster {
+            members: Vec::with_capacity( indexes.len() ),
+            allocated_count: 0,
+            allocated_size: 0,
+            leaked_count: 0,
+            leaked_size: 0
+        };
+
+        for index in indexes {
+            let backtrace_id = backtrace_ids[ index ];
+            let stats = data.get_group_statistics( backtrace_id );
+            cluster.members.push( backtrace_id );
+            cluster.allocated_count += stats.alloc_count;
+            cluster.allocated_size += stats.alloc_size;
+            cluster.leaked_count += stats.alloc_count - stats.free_count;
+            cluster.leaked_size += stats.alloc_size - stats.free_size;
+        }
+
+        cluster
+    }).collect();
+
+    clusters.par_sort_by_key( |cluster| std::cmp::Reverse( cluster.allocated_size ) );
+    clusters
+}
+
+fn handler_backtrace_clusters( req: HttpRequest ) -> Result< HttpResponse > {
+    let backtrace_format = get_backtrace_format( &req )?;
+    let params: protocol::RequestBacktraceClusters = query( &req )?;
+    let threshold = params.threshold.unwrap_or( 0.8 );
+    if !(threshold > 0.0 && threshold <= 1.0) {
+        return Err( ApiError::invalid_parameter( "threshold", "the threshold must be within the (0, 1] range" ).into() );
+    }
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let data = &data;
+        let clusters = state.backtrace_clusters_cache.get_or_compute( (data.id(), threshold.to_bits()), || {
+            compute_backtrace_clusters( data, threshold )
+        });
+
+        let skip = params.skip.unwrap_or( 0 ) as usize;
+        let count = params.count.map( |count| count as usize ).unwrap_or( usize::MAX );
+        let response = protocol::ResponseBacktraceClusters {
+            clusters: clusters.iter().skip( skip ).take( count ).map( |cluster| {
+                let representative_backtrace_id = cluster.members[ 0 ];
+                let (representative, truncated_count) = get_frames( data, &backtrace_format, data.get_backtrace( representative_backtrace_id ) );
+                protocol::BacktraceCluster {
+                    representative_backtrace_id: representative_backtrace_id.raw(),
+                    representative,
+                    truncated_count,
+                    backtrace_ids: cluster.members.iter().map( |backtrace_id| backtrace_id.raw() ).collect(),
+                    allocated_count: cluster.allocated_count,
+                    allocated_size: cluster.allocated_size,
+                    leaked_count: cluster.leaked_count,
+                    leaked_size: cluster.leaked_size
+                }
+            }).collect(),
+            total_count: clusters.len() as u64
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+fn parse_address( input: &str ) -> Option< u64 > {
+    let input = input.trim();
+    let input = if input.starts_with( "0x" ) || input.starts_with( "0X" ) {
+        &input[ 2.. ]
+    } else {
+        input
+    };
+
+    u64::from_str_radix( input, 16 ).ok()
+}
+
+#[test]
+fn test_parse_address() {
+    assert_eq!( parse_address( "0x1234" ), Some( 0x1234 ) );
+    assert_eq!( parse_address( "0XdeadBEEF" ), Some( 0xdeadbeef ) );
+    assert_eq!( parse_address( "7f00aa" ), Some( 0x7f00aa ) );
+    assert_eq!( parse_address( "" ), None );
+    assert_eq!( parse_address( "0x" ), None );
+    assert_eq!( parse_address( "xyz" ), None );
+}
+
+/// Builds a list of every (address, backtrace) pair sorted by the address.
+fn build_backtraces_by_address( data: &Data ) -> Vec< (u64, BacktraceId) > {
+    let mut list: Vec< _ > = data.all_backtraces().flat_map( |(backtrace_id, backtrace)| {
+        backtrace.map( move |(_, frame)| (frame.address().raw(), backtrace_id) )
+    }).collect();
+
+    list.par_sort_unstable();
+    list.dedup();
+    list
+}
+
+fn get_backtraces_by_address( state: &State, data: &Data ) -> Arc< Vec< (u64, BacktraceId) > > {
+    state.backtraces_by_address_cache.get_or_compute( data.id(), || build_backtraces_by_address( data ) )
+}
+
+fn handler_backtraces_containing_address( req: HttpRequest ) -> Result< HttpResponse > {
+    let address = req.match_info().get( "address" ).unwrap();
+    let address = parse_address( address ).ok_or_else( || ApiError::bad_request( "invalid address" ) )?;
+    let params: protocol::RequestContainingAddress = query( &req )?;
+    let near = params.near.unwrap_or( 0 );
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let index = get_backtraces_by_address( &state, &data );
+        let address_min = address.saturating_sub( near );
+        let address_max = address.saturating_add( near );
+        let start = index.partition_point( |&(frame_address, _)| frame_address < address_min );
+
+        let mut matched_address_by_backtrace: HashMap< BacktraceId, u64 > = HashMap::new();
+        for &(frame_address, backtrace_id) in index[ start.. ].iter().take_while( |&&(frame_address, _)| frame_address <= address_max ) {
+            let distance = |value: u64| max( value, address ) - min( value, address );
+            let matched_address = matched_address_by_backtrace.entry( backtrace_id ).or_insert( frame_address );
+            if distance( frame_address ) < distance( *matched_address ) {
+                *matched_address = frame_address;
+            }
+        }
+
+        let mut backtraces: Vec< _ > = matched_address_by_backtrace.into_iter().map( |(backtrace_id, matched_address)| {
+            let stats = data.get_group_statistics( backtrace_id );
+            protocol::BacktraceWithAddress {
+                backtrace_id: backtrace_id.raw(),
+                matched_address,
+                allocated_count: stats.alloc_count,
+                allocated_size: stats.alloc_size
+            }
+        }).collect();
+
+        backtraces.sort_by_key( |entry| entry.backtrace_id );
+
+        let response = protocol::ResponseBacktracesContainingAddress {
+            address,
+            backtraces
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+/// The address range of an allocation, along with the highest end address of every entry up to and including this one.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct AllocationRangeEntry {
+    start: u64,
+    end: u64,
+    max_end: u64,
+    id: AllocationId
+}
+
+/// Builds a list of the address ranges of every allocation sorted by their start.
+fn build_allocations_by_address( data: &Data ) -> Vec< AllocationRangeEntry > {
+    let mut list: Vec< _ > = data.allocations_with_id().map( |(id, allocation)| {
+        let range = allocation.actual_range( data );
+        AllocationRangeEntry { start: range.start, end: range.end, max_end: range.end, id }
+    }).collect();
+
+    list.par_sort_unstable_by_key( |entry| (entry.start, entry.id) );
+
+    let mut max_end = 0;
+    for entry in &mut list {
+        max_end = max( max_end, entry.end );
+        entry.max_end = max_end;
+    }
+
+    list
+}
+
+/// Returns the ids of every allocation whose range contains the given address.
+fn find_allocations_containing_address( index: &[AllocationRangeEntry], address: u64 ) -> Vec< AllocationId > {
+    let end = index.partition_point( |entry| entry.start <= address );
+    index[ ..end ].iter().rev()
+        .take_while( |entry| entry.max_end > address )
+        .filter( |entry| entry.end > address )
+        .map( |entry| entry.id )
+        .collect()
+}
+
+fn handler_resolve_pointer( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestResolvePointer = query( &req )?;
+    let address = parse_address( &params.address ).ok_or_else( || ApiError::invalid_parameter( "address", "invalid address" ) )?;
+    let at = params.at.map( |at| data.initial_timestamp() + at.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let backtrace_format = get_backtrace_format( &req )?;
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let data = &data;
+        let index = state.allocations_by_address_cache.get_or_compute( data.id(), || build_allocations_by_address( data ) );
+        let mut allocation_ids = find_allocations_containing_address( &index, address );
+
+        // The same address could have been reused any number of times, so we have to check when each allocation was alive.
+        if let Some( at ) = at {
+            allocation_ids.retain( |&id| {
+                let allocation = data.get_allocation( id );
+                allocation.timestamp <= at && allocation.deallocation.as_ref().map( |deallocation| at < deallocation.timestamp ).unwrap_or( true )
+            });
+        }
+
+        allocation_ids.sort_by_key( |&id| (data.get_allocation( id ).timestamp, id) );
+        if at.is_some() {
+            // Only one allocation can be alive at a given address at any given time; if there's more then the most recent one wins.
+            allocation_ids.drain( ..allocation_ids.len().saturating_sub( 1 ) );
+        }
+
+        let mut backtraces = BacktraceFormatter::new( data, backtrace_format );
+        let allocations = allocation_ids.into_iter().map( |id| {
+            let allocation = data.get_allocation( id );
+            protocol::ResolvedAllocation {
+                allocation: get_allocation( data, &mut backtraces, protocol::AllocationFields::all(), None, false, id, allocation ),
+                offset: address.wrapping_sub( allocation.pointer ) as i64
+            }
+        }).collect();
+
+        let response = protocol::ResponseResolvePointer {
+            address,
+            address_s: format!( "{:016X}", address ),
+            at: at.map( |at| at.into() ),
+            allocations
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+const MAXIMUM_SYMBOLIZE_ADDRESS_COUNT: usize = 4096;
+
+fn handler_symbolize( req: HttpRequest ) -> Result< HttpResponse > {
+    let params: protocol::RequestSymbolize = query( &req )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+
+    let addresses: Vec< _ > = params.addresses.split( ',' ).filter( |address| !address.trim().is_empty() ).collect();
+    if addresses.len() > MAXIMUM_SYMBOLIZE_ADDRESS_COUNT {
+        return Err( ApiError::bad_request( format!( "too many addresses; at most {} can be symbolized at a time", MAXIMUM_SYMBOLIZE_ADDRESS_COUNT ) ).into() );
+    }
+
+    let addresses = addresses.into_iter().map( |address| {
+        parse_address( address ).ok_or_else( || ApiError::invalid_parameter( "addresses", format!( "invalid address: '{}'", address ) ).into() )
+    }).collect::< Result< Vec< _ > > >()?;
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let data = &data;
+        let index = get_backtraces_by_address( &state, data );
+        let addresses = addresses.into_iter().map( |address| {
+            let start = index.partition_point( |&(frame_address, _)| frame_address < address );
+            let frames: Vec< _ > = match index.get( start ) {
+                Some( &(frame_address, backtrace_id) ) if frame_address == address => {
+                    data.get_backtrace( backtrace_id )
+                        .filter( |(_, frame)| frame.address().raw() == address )
+                        .map( |(_, frame)| get_frame( data, &backtrace_format, frame ) )
+                        .collect()
+                },
+                _ => Vec::new()
+            };
+
+            let innermost = frames.last();
+            protocol::SymbolizedAddress {
+                address,
+                address_s: format!( "{:016X}", address ),
+                library: innermost.and_then( |frame| frame.library ),
+                function: innermost.and_then( |frame| frame.function.clone() ),
+                raw_function: innermost.and_then( |frame| frame.raw_function ),
+                source: innermost.and_then( |frame| frame.source.clone() ),
+                line: innermost.and_then( |frame| frame.line ),
+                column: innermost.and_then( |frame| frame.column ),
+                frames
+            }
+        }).collect();
+
+        let response = protocol::ResponseSymbolize {
+            addresses
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+fn generate_regions< 'a, F: Fn( AllocationId, &Allocation ) -> bool + Clone + 'a >( data: &'a Data, filter: F ) -> impl Serialize + 'a {
+    let main_heap_start = data.alloc_sorted_by_address( None, None )
+        .iter()
+        .map( |&id| data.get_allocation( id ) )
+        .filter( |allocation| !allocation.is_mmaped() && allocation.in_main_arena() )
+        .map( |allocation| allocation.actual_range( data ).start )
+        .next()
+        .unwrap_or( 0 );
+
+    let main_heap_end = data.alloc_sorted_by_address( None, None )
+        .iter()
+        .map( |&id| data.get_allocation( id ) )
+        .rev()
+        .filter( |allocation| !allocation.is_mmaped() && allocation.in_main_arena() )
+        .map( |allocation| allocation.actual_range( data ).end )
+        .next()
+        .unwrap_or( 0 );
+
+    let regions = move || {
+        let filter = filter.clone();
+        data.alloc_sorted_by_address( None, None )
+            .iter()
+            .map( move |&id| (id, data.get_allocation( id )) )
+            .filter( move |(id, allocation)| filter( *id, allocation ) )
+            .map( move |(_, allocation)| allocation.actual_range( data ) )
+            .coalesce( |mut range, next_range| {
+                if next_range.start <= range.end {
+                    range.end = next_range.end;
+                    Ok( range )
+                } else {
+                    Err( (range, next_range) )
+                }
+            })
+            .map( |range| [range.start, range.end] )
+    };
+
+    protocol::ResponseRegions {
+        main_heap_start,
+        main_heap_end,
+        main_heap_start_s: format!( "{}", main_heap_start ),
+        main_heap_end_s: format!( "{}", main_heap_end ),
+        regions: StreamingSerializer::new( regions )
+    }
+}
+
+fn handler_regions( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = generate_regions( &data, |id, allocation| filter.try_match( &data, id, allocation ) );
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn handler_modules( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let response = protocol::ResponseModules {
+        modules: data.modules().iter().map( |module| {
+            protocol::ModuleInfo {
+                path: &module.path,
+                start: module.address_range.start,
+                end: module.address_range.end,
+                start_s: format!( "{:016X}", module.address_range.start ),
+                end_s: format!( "{:016X}", module.address_range.end ),
+                build_id: module.build_id.as_deref()
+            }
+        }).collect()
+    };
+
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_markers( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let params: protocol::RequestMarkers = query( &req )?;
+    let from = params.from.map( |ts| data.initial_timestamp() + ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( Timestamp::min() );
+    let to = params.to.map( |ts| data.initial_timestamp() + ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( Timestamp::max() );
+    let value = params.value;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let data = &data;
+        let matches_value = |marker_value: u32| value.map( |expected| marker_value == expected ).unwrap_or( true );
+
+        let markers = data.markers().iter()
+            .filter( |marker| matches_value( marker.value ) && marker.timestamp >= from && marker.timestamp <= to )
+            .map( |marker| {
+                let (backtrace, truncated_count) = match marker.backtrace {
+                    Some( backtrace_id ) => {
+                        let (backtrace, truncated_count) = get_frames( data, &backtrace_format, data.get_backtrace( backtrace_id ) );
+                        (Some( backtrace ), truncated_count)
+                    },
+                    None => (None, None)
+                };
+
+                protocol::Marker {
+                    timestamp: marker.timestamp.into(),
+                    thread: marker.thread,
+                    value: marker.value,
+                    backtrace_id: marker.backtrace.map( |backtrace_id| backtrace_id.raw() ),
+                    backtrace,
+                    truncated_count
+                }
+            })
+            .collect();
+
+        let allocations_per_marker = data.alloc_sorted_by_timestamp( None, None )
+            .par_iter()
+            .map( |&id| data.get_allocation( id ) )
+            .filter( |allocation| matches_value( allocation.marker ) )
+            .fold( HashMap::new, |mut allocations_per_marker: HashMap< u32, (u64, u64) >, allocation| {
+                let entry = allocations_per_marker.entry( allocation.marker ).or_insert( (0, 0) );
+                entry.0 += 1;
+                entry.1 += allocation.size;
+                allocations_per_marker
+            })
+            .reduce( HashMap::new, |mut a, b| {
+                for (value, (count, size)) in b {
+                    let entry = a.entry( value ).or_insert( (0, 0) );
+                    entry.0 += count;
+                    entry.1 += size;
+                }
+                a
+            });
+
+        let mut allocations: Vec< _ > = allocations_per_marker.into_iter().map( |(value, (allocated_count, allocated_size))| {
+            protocol::MarkerAllocations {
+                value,
+                allocated_count,
+                allocated_size
+            }
+        }).collect();
+        allocations.sort_unstable_by_key( |entry| entry.value );
+
+        let response = protocol::ResponseMarkers {
+            markers,
+            allocations
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+fn handler_mallopts( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+
+    let response: Vec< _ > = data.mallopts().iter().map( |mallopt| {
+        let (backtrace, truncated_count) = get_frames( &data, &backtrace_format, data.get_backtrace( mallopt.backtrace ) );
+
+        protocol::Mallopt {
+            timestamp: mallopt.timestamp.into(),
+            thread: mallopt.thread,
+            backtrace_id: mallopt.backtrace.raw(),
+            backtrace,
+            truncated_count,
+            raw_param: mallopt.kind.raw(),
+            param: match mallopt.kind {
+                MalloptKind::TrimThreshold  => Some( "M_TRIM_THRESHOLD" ),
+                MalloptKind::TopPad         => Some( "M_TOP_PAD" ),
+                MalloptKind::MmapThreshold  => Some( "M_MMAP_THRESHOLD" ),
+                MalloptKind::MmapMax        => Some( "M_MMAP_MAX" ),
+                MalloptKind::CheckAction    => Some( "M_CHECK_ACTION" ),
+                MalloptKind::Perturb        => Some( "M_PERTURB" ),
+                MalloptKind::ArenaTest      => Some( "M_ARENA_TEXT" ),
+                MalloptKind::ArenaMax       => Some( "M_ARENA_MAX" ),
+                MalloptKind::Other( _ )     => None
+            }.map( |value| value.into() ),
+            value: mallopt.value,
+            result: mallopt.result
+        }
+    }).collect();
+
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_export_flamegraph_pl( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter_params: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter_params, &custom_filter )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let options = FlamegraphOptions {
+        demangle: backtrace_format.demangle.map( |style| style.into() ),
+        trim_below: backtrace_format.trim_below.map( |pattern| pattern.0 ),
+        trim_runtime_prefix: backtrace_format.trim_runtime_prefix.unwrap_or( false )
+    };
+
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let disconnected = tx.disconnected_flag();
+        let result = export_as_flamegraph_pl( &data, &mut tx, &options, |id, allocation| !disconnected.is_set() && filter.try_match( &data, id, allocation ) );
+        finish_response( tx, result );
+    })?;
+
+    Ok( export_response( &req, data, ExportFormat::FlamegraphPl, &filter_params, &custom_filter ).body( body ) )
+}
+
+fn handler_export_flamegraph( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter_params: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter_params, &custom_filter )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let options = FlamegraphOptions {
+        demangle: backtrace_format.demangle.map( |style| style.into() ),
+        trim_below: backtrace_format.trim_below.map( |pattern| pattern.0 ),
+        trim_runtime_prefix: backtrace_format.trim_runtime_prefix.unwrap_or( false )
+    };
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let disconnected = tx.disconnected_flag();
+        let _ = export_as_flamegraph( &data, tx, &options, |id, allocation| !disconnected.is_set() && filter.try_match( &data, id, allocation ) );
+    })?;
+
+    Ok( export_response( &req, data, ExportFormat::Flamegraph, &filter_params, &custom_filter ).body( body ) )
+}
+
+/// The `(backtrace, timestamp, size)` of a memory map which is put in a flamegraph or a tree.
+type MmapRecord = (BacktraceId, Timestamp, u64);
+
+/// Parses which memory maps should be put in a flamegraph or a tree, and how they should be weighted.
+fn mmap_records_params( req: &HttpRequest ) -> Result< (protocol::MmapFilter, protocol::MmapWeight) > {
+    let filter: protocol::MmapFilter = query( req )?;
+    let params: protocol::RequestMmapWeight = query( req )?;
+    Ok( (filter, params.weight.unwrap_or( protocol::MmapWeight::Mapped )) )
+}
+
+/// Returns every memory map which matches the `MmapFilter`, weighted as requested.
+fn mmap_records( state: &State, data: &Data, filter: &protocol::MmapFilter, weight: protocol::MmapWeight ) -> Vec< MmapRecord > {
+    let mmap_state = state.mmap_state_cache.get_or_compute( data.id(), || mmap_state::MmapState::new( data ) );
+    mmap_state.mappings.iter()
+        .filter( |mapping| filter.matches( mapping.length, mapping.file_path( data ) ) )
+        .map( |mapping| {
+            let size = match weight {
+                protocol::MmapWeight::Mapped => mapping.length,
+                protocol::MmapWeight::Live => mapping.remaining_length()
+            };
+            (mapping.backtrace, mapping.timestamp, size)
+        })
+        .filter( |&(_, _, size)| size != 0 )
+        .collect()
+}
+
+fn handler_export_mmap_flamegraph_pl( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let (filter, weight) = mmap_records_params( &req )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let options = FlamegraphOptions {
+        demangle: backtrace_format.demangle.map( |style| style.into() ),
+        trim_below: backtrace_format.trim_below.map( |pattern| pattern.0 ),
+        trim_runtime_prefix: backtrace_format.trim_runtime_prefix.unwrap_or( false )
+    };
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let records = mmap_records( &state, &data, &filter, weight );
+        let result = export_sizes_as_flamegraph_pl( &data, &mut tx, &options, records.into_iter() );
+        finish_response( tx, result );
+    })?;
+
+    Ok( mmap_export_response( &req, data, ExportFormat::FlamegraphPl, weight ).body( body ) )
+}
+
+fn handler_export_mmap_flamegraph( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let (filter, weight) = mmap_records_params( &req )?;
+    let backtrace_format = get_backtrace_format( &req )?;
+    let options = FlamegraphOptions {
+        demangle: backtrace_format.demangle.map( |style| style.into() ),
+        trim_below: backtrace_format.trim_below.map( |pattern| pattern.0 ),
+        trim_runtime_prefix: backtrace_format.trim_runtime_prefix.unwrap_or( false )
+    };
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, tx| {
+        let records = mmap_records( &state, &data, &filter, weight );
+        export_sizes_as_flamegraph( &data, tx, &options, records.into_iter() );
+    })?;
+
+    Ok( mmap_export_response( &req, data, ExportFormat::Flamegraph, weight ).body( body ) )
+}
+
+fn handler_export_replay( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter_params: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter_params, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let disconnected = tx.disconnected_flag();
+        let result = export_as_replay( &data, &mut tx, |id, allocation| !disconnected.is_set() && filter.try_match( &data, id, allocation ) );
+        finish_response( tx, result );
+    })?;
+
+    Ok( export_response( &req, data, ExportFormat::Replay, &filter_params, &custom_filter ).body( body ) )
+}
+
+fn handler_export_heaptrack( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter_params: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter_params, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let disconnected = tx.disconnected_flag();
+        let result = export_as_heaptrack( &data, &mut tx, |id, allocation| !disconnected.is_set() && filter.try_match( &data, id, allocation ) );
+        finish_response( tx, result );
+    })?;
+
+    Ok( export_response( &req, data, ExportFormat::Heaptrack, &filter_params, &custom_filter ).body( body ) )
+}
+
+fn handler_allocation_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let tree = data.tree_by_source( |id, allocation| filter.try_match( &data, id, allocation ) );
+        let table = data.dump_tree( &tree );
+        let table = table_to_string( &table );
+        let _ = writeln!( tx, "{}", table );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+}
+
+fn handler_mmap_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
+    get_data( &req )?;
+    let (filter, weight) = mmap_records_params( &req )?;
+
+    let state = req.state().clone();
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let records = mmap_records( &state, &data, &filter, weight );
+        let tree = data.tree_by_source_of_sizes( records.into_iter() );
+        let table = data.dump_tree( &tree );
+        let table = table_to_string( &table );
+        let _ = writeln!( tx, "{}", table );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+}
+
+fn handler_collation_json< F >( req: HttpRequest, callback: F ) -> Result< HttpResponse >
+    where F: Fn( &Data ) -> BTreeMap< String, BTreeMap< u32, CountAndSize > > + Send + 'static
+{
+    use serde_json::json;
+
+    let backtrace_format = get_backtrace_format( &req )?;
+    let body = async_data_handler( &req, move |data, tx| {
+        let mut constants = callback( &data );
+        if let Some( map ) = backtrace_format.source_prefix_map {
+            let mut remapped: BTreeMap< String, BTreeMap< u32, CountAndSize > > = BTreeMap::new();
+            for (key, per_line) in constants {
+                let key = remap_source_path( &map.0, &key ).unwrap_or( key );
+                let remapped_per_line = remapped.entry( key ).or_default();
+                for (line, entry) in per_line {
+                    let remapped_entry = remapped_per_line.entry( line ).or_insert( CountAndSize { count: 0, size: 0 } );
+                    remapped_entry.count += entry.count;
+                    remapped_entry.size += entry.size;
+                }
+            }
+
+            constants = remapped;
+        }
+
+        let mut total_count = 0;
+        let mut total_size = 0;
+        let per_file: BTreeMap< _, _ > = constants.into_iter().map( |(key, per_line)| {
+            let mut whole_file_count = 0;
+            let mut whole_file_size = 0;
+            let per_line: BTreeMap< _, _ > = per_line.into_iter().map( |(line, entry)| {
+                whole_file_count += entry.count;
+                whole_file_size += entry.size;
+                total_count += entry.count;
+                total_size += entry.size;
+                let entry = json!({
+                    "count": entry.count,
+                    "size": entry.size
+                });
+                (line, entry)
+            }).collect();
+
+            let value = json!({
+                "count": whole_file_count,
+                "size": whole_file_size,
+                "per_line": per_line
+            });
+
+            (key, value)
+        }).collect();
+
+        let response = json!({
+            "count": total_count,
+            "size": total_size,
+            "per_file": per_file
+        });
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+fn handler_by_source( req: HttpRequest ) -> Result< HttpResponse > {
+    use serde_json::json;
+
+    #[derive(Copy, Clone, Default)]
+    struct Counts {
+        count: u64,
+        size: u64,
+        live_count: u64,
+        live_size: u64
+    }
+
+    impl Counts {
+        fn add( &mut self, other: &Counts ) {
+            self.count += other.count;
+            self.size += other.size;
+            self.live_count += other.live_count;
+            self.live_size += other.live_size;
+        }
+
+        fn to_json( &self ) -> serde_json::Value {
+            json!({
+                "count": self.count,
+                "size": self.size,
+                "live_count": self.live_count,
+                "live_size": self.live_size
+            })
+        }
+    }
+
+    let data = &get_data( &req )?;
+    let params: protocol::RequestBySource = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+    let filter = prepare_cached_filter( &req, data, &filter, &custom_filter )?;
+    let per_line = params.per_line.unwrap_or( false );
+    let backtrace_format = get_backtrace_format( &req )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let source_by_backtrace: Vec< _ > = data.all_backtraces().map( |(_, frames)| {
+            frames.rev().map( |(_, frame)| frame ).find_map( |frame| frame.source().map( |source| (source, frame.line().unwrap_or( 0 )) ) )
+        }).collect();
+
+        let counts = prefiltered_allocation_ids( &data, protocol::AllocSortBy::Timestamp, &filter )
+            .par_iter()
+            .fold( || HashMap::new(), |mut counts: HashMap< Option< (StringId, u32) >, Counts >, &id| {
+                let allocation = data.get_allocation( id );
+                if !filter.try_match( &data, id, allocation ) {
+                    return counts;
+                }
+
+                let entry = counts.entry( source_by_backtrace[ allocation.backtrace.raw() as usize ] ).or_default();
+                entry.count += 1;
+                entry.size += allocation.size;
+                if allocation.deallocation.is_none() {
+                    entry.live_count += 1;
+                    entry.live_size += allocation.size;
+                }
+
+                counts
+            })
+            .reduce( || HashMap::new(), |mut a, mut b| {
+                if b.len() > a.len() {
+                    std::mem::swap( &mut a, &mut b );
+                }
+
+                for (key, counts) in b {
+                    a.entry( key ).or_default().add( &counts );
+                }
+
+                a
+            });
+
+        let mut total = Counts::default();
+        let mut per_file: BTreeMap< Cow< str >, (Counts, BTreeMap< u32, Counts >) > = BTreeMap::new();
+        for (key, counts) in counts {
+            let (source, line): (Cow< str >, _) = match key {
+                Some( (source, line) ) => {
+                    let source = data.interner().resolve( source ).unwrap();
+                    let remapped = backtrace_format.source_prefix_map.as_ref().and_then( |map| remap_source_path( &map.0, source ) );
+                    (remapped.map( Cow::Owned ).unwrap_or( Cow::Borrowed( source ) ), line)
+                },
+                None => ("<unknown>".into(), 0)
+            };
+
+            total.add( &counts );
+            let entry = per_file.entry( source ).or_default();
+            entry.0.add( &counts );
+            entry.1.entry( line ).or_default().add( &counts );
+        }
+
+        let per_file: BTreeMap< _, _ > = per_file.into_iter().map( |(source, (counts, lines))| {
+            let mut value = counts.to_json();
+            if per_line {
+                let lines: BTreeMap< _, _ > = lines.into_iter().map( |(line, counts)| (line, counts.to_json()) ).collect();
+                value[ "per_line" ] = json!( lines );
+            }
+
+            (source, value)
+        }).collect();
+
+        let mut response = total.to_json();
+        response[ "per_file" ] = json!( per_file );
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+fn handler_dynamic_constants( req: HttpRequest ) -> Result< HttpResponse > {
+    handler_collation_json( req, |data| data.get_dynamic_constants() )
+}
+
+fn handler_dynamic_statics( req: HttpRequest ) -> Result< HttpResponse > {
+    handler_collation_json( req, |data| data.get_dynamic_statics() )
+}
+
+fn handler_dynamic_constants_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let table = data.get_dynamic_constants_ascii_tree();
+        let _ = writeln!( tx, "{}", table );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+}
+
+fn handler_dynamic_statics_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let table = data.get_dynamic_statics_ascii_tree();
+        let _ = writeln!( tx, "{}", table );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+}
+
+fn handler_script_files( req: HttpRequest ) -> Result< HttpResponse > {
+    let hash = req.match_info().get( "hash" ).unwrap();
+    let entry = match req.state().generated_files.lock().get( hash ) {
+        Some( entry ) => entry,
+        None => {
+            return Err( ApiError::not_found( "file not found" ).into() );
+        }
+    };
+
+    let etag = format!( "\"{}\"", entry.hash );
+    if etag::if_none_match( req.headers(), &etag ) {
+        return Ok( HttpResponse::NotModified().header( "ETag", etag ).finish() );
+    }
+
+    let length = entry.data.len() as u64;
+    if let Some( value ) = req.headers().get( "Range" ) {
+        match value.to_str().map_err( |_| () ).and_then( |value| range::parse_range( value, length ) ) {
+            Ok( Some( range ) ) => {
+                let body = entry.data[ range.start as usize..range.end as usize ].to_vec();
+                return Ok(
+                    HttpResponse::PartialContent()
+                        .content_type( entry.mime )
+                        .header( "ETag", etag )
+                        .header( "Accept-Ranges", "bytes" )
+                        .header( "Content-Range", format!( "bytes {}-{}/{}", range.start, range.end - 1, length ) )
+                        .body( body )
+                );
+            },
+            Ok( None ) => {},
+            Err( () ) => {
+                return Ok(
+                    HttpResponse::RangeNotSatisfiable()
+                        .header( "Accept-Ranges", "bytes" )
+                        .header( "Content-Range", format!( "bytes */{}", length ) )
+                        .finish()
+                );
+            }
+        }
+    }
+
+    // The whole file is already in memory, so send it in one go so that it gets a `Content-Length`.
+    Ok(
+        HttpResponse::Ok()
+            .content_type( entry.mime )
+            .header( "ETag", etag )
+            .header( "Accept-Ranges", "bytes" )
+            .body( entry.data.to_vec() )
+    )
+}
+
+/// Starts the response shared by the `GET` and `HEAD` requests to one of the exports.
+fn export_response(
+    req: &HttpRequest,
+    data: &Data,
+    format: ExportFormat,
+    filter: &protocol::AllocFilter,
+    custom_filter: &protocol::CustomFilter
+) -> actix_web::dev::HttpResponseBuilder {
+    let default_filename = || exports::default_filename( data.executable(), &data.id().to_string(), format, filter, custom_filter );
+    attachment_response( req, format, default_filename )
+}
+
+fn attachment_response( req: &HttpRequest, format: ExportFormat, default_filename: impl FnOnce() -> String ) -> actix_web::dev::HttpResponseBuilder {
+    let filename = match req.match_info().get( "filename" ) {
+        Some( filename ) => filename.to_owned(),
+        None => default_filename()
+    };
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type( format.content_type() )
+        .header( "Accept-Ranges", "none" )
+        .header( "Content-Disposition", exports::content_disposition( &filename ) );
+
+    response
+}
+
+/// Handles a `HEAD` request to one of the exports, whose size is not known without generating them.
+fn handler_export_head( req: HttpRequest, format: ExportFormat ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let custom_filter = get_custom_filter( &req )?;
+
+    Ok( export_response( &req, data, format, &filter, &custom_filter ).body( Body::None ) )
+}
+
+fn mmap_export_response( req: &HttpRequest, data: &Data, format: ExportFormat, weight: protocol::MmapWeight ) -> actix_web::dev::HttpResponseBuilder {
+    attachment_response( req, format, || exports::default_mmap_filename( data.executable(), &data.id().to_string(), format, weight ) )
+}
+
+fn handler_mmap_export_head( req: HttpRequest, format: ExportFormat ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let params: protocol::RequestMmapWeight = query( &req )?;
+
+    Ok( mmap_export_response( &req, data, format, params.weight.unwrap_or( protocol::MmapWeight::Mapped ) ).body( Body::None ) )
+}
+
+fn handler_filter_to_script( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = &get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_raw_filter( data, &filter )?;
+    let custom_filter = get_custom_filter( &req )?;
+
+    let mut prologue = String::new();
+    let code;
+
+    if let Some( custom_filter ) = custom_filter.custom_filter {
+        prologue.push_str( "fn custom_filter() {\n" );
+        for line in custom_filter.lines() {
+            prologue.push_str( "    " );
+            prologue.push_str( line );
+            prologue.push_str( "\n" );
+        }
+        prologue.push_str( "}\n\n" );
+        prologue.push_str( "let filtered = custom_filter();\n" );
+        code = filter.to_code( Some( "filtered".into() ) );
+    } else {
+        code = filter.to_code( None );
+    }
+
+    let body = serde_json::json! {{
+        "prologue": prologue,
+        "code": code
+    }};
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+/// The biggest file with debug symbols which can be uploaded.
+const MAXIMUM_DEBUG_SYMBOLS_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Uploaded debug symbols, written to a directory of their own as they're received
+/// so that their original filename can be kept; the directory is removed once they're dropped.
+struct UploadedDebugSymbols {
+    directory: PathBuf,
+    path: PathBuf,
+    file: std::fs::File,
+    size: u64,
+    size_limit: u64
+}
+
+impl UploadedDebugSymbols {
+    fn create( data_id: DataId, filename: Option< &str >, size_limit: u64 ) -> io::Result< Self > {
+        static UPLOAD_COUNTER: AtomicUsize = AtomicUsize::new( 0 );
+
+        let filename = filename
+            .and_then( |filename| std::path::Path::new( filename ).file_name().map( |filename| filename.to_owned() ) )
+            .unwrap_or_else( || "debug-symbols".into() );
+
+        let directory = std::env::temp_dir().join( format!( "bytehound-debug-symbols-{}-{}", data_id, UPLOAD_COUNTER.fetch_add( 1, Ordering::Relaxed ) ) );
+        std::fs::create_dir_all( &directory )?;
+
+        let path = directory.join( filename );
+        let file = match std::fs::File::create( &path ) {
+            Ok( file ) => file,
+            Err( error ) => {
+                let _ = std::fs::remove_dir_all( &directory );
+                return Err( error );
+            }
+        };
+
+        Ok( UploadedDebugSymbols { directory, path, file, size: 0, size_limit } )
+    }
+
+    fn write( &mut self, chunk: &[u8] ) -> std::result::Result< (), ApiError > {
+        self.size += chunk.len() as u64;
+        if self.size > self.size_limit {
+            return Err( ApiError::payload_too_large( format!( "the debug symbols are too big; at most {} bytes can be uploaded", self.size_limit ) ) );
+        }
+
+        io::Write::write_all( &mut self.file, chunk ).map_err( |error| {
+            error!( "Failed to write the uploaded debug symbols to a temporary file: {}", error );
+            ApiError::internal( "failed to write the debug symbols to a temporary file" )
+        })
+    }
+}
+
+impl Drop for UploadedDebugSymbols {
+    fn drop( &mut self ) {
+        let _ = std::fs::remove_dir_all( &self.directory );
+    }
+}
+
+/// Applies the debug symbols from the given path, either specified directly or uploaded in the request body.
+///
+/// The body is written to a temporary file as it's received so that even huge files don't have to fit in memory.
+fn handler_debug_symbols( req: HttpRequest, payload: web::Payload ) -> Box< dyn Future< Item = HttpResponse, Error = ActixWebError > > {
+    let result = get_data_id( &req ).and_then( |data_id| {
+        let params: protocol::RequestDebugSymbols = query( &req )?;
+        if req.state().is_live( data_id ) {
+            // The data is replaced every time more of it is received, which would throw away the symbols.
+            return Err( ApiError::conflict( "the data is still being received; try again once it's finished" ).into() );
+        }
+
+        Ok( (data_id, params) )
+    });
+
+    let (data_id, params) = match result {
+        Ok( result ) => result,
+        Err( error ) => return Box::new( future::err( error ) )
+    };
+
+    if let Some( path ) = params.path {
+        let path = PathBuf::from( path );
+        if !path.exists() {
+            return Box::new( future::err( ApiError::bad_request( format!( "path doesn't exist: {:?}", path ) ).into() ) );
+        }
+
+        return Box::new( future::result( apply_debug_symbols( &req, data_id, path, None ) ) );
+    }
+
+    let upload = match UploadedDebugSymbols::create( data_id, params.filename.as_deref(), MAXIMUM_DEBUG_SYMBOLS_SIZE ) {
+        Ok( upload ) => upload,
+        Err( error ) => {
+            error!( "Failed to create a temporary file for the uploaded debug symbols: {}", error );
+            return Box::new( future::err( ApiError::internal( "failed to create a temporary file" ).into() ) );
+        }
+    };
+
+    let response = payload
+        .map_err( ActixWebError::from )
+        .fold( upload, |mut upload, chunk| {
+            upload.write( &chunk ).map( |_| upload ).map_err( ActixWebError::from )
+        })
+        .and_then( move |upload| {
+            if upload.size == 0 {
+                return Err( ApiError::bad_request( "either a 'path' or a file in the request body is required" ).into() );
+            }
+
+            let path = upload.path.clone();
+            apply_debug_symbols( &req, data_id, path, Some( upload ) )
+        });
+
+    Box::new( response )
+}
+
+fn apply_debug_symbols( req: &HttpRequest, data_id: DataId, path: PathBuf, upload: Option< UploadedDebugSymbols > ) -> Result< HttpResponse > {
+    // The requests which are already using the data keep on using it as it was; the symbols are applied
+    // to a copy of it, which then replaces the original. The copy shares the memory mapped parts, if any.
+    let state = req.state().clone();
+    let body = async_data_handler( req, move |_, tx| {
+        let _guard = state.symbolication_lock.lock();
+        let data = state.get_data( data_id );
+        let symbolicated_frames = data.and_then( |data| {
+            let updates = data.symbolicate_missing_frames( &[path] );
+            if updates.is_empty() {
+                return Some( 0 );
+            }
+
+            let mut symbolicated = (*data).clone();
+            let symbolicated_frames = symbolicated.apply_symbol_updates( updates );
+
+            // The names of the frames have changed, so everything which was computed from them is stale.
+            if state.replace_data( &data, symbolicated ) {
+                Some( symbolicated_frames )
+            } else {
+                None
+            }
+        });
+
+        drop( upload );
+
+        let symbolicated_frames = match symbolicated_frames {
+            Some( symbolicated_frames ) => symbolicated_frames,
+            None => {
+                // It was unloaded in the meantime.
+                tx.abort();
+                return;
+            }
+        };
+
+        info!( "Loaded extra debug symbols for {}; {} frame(s) gained symbols", data_id, symbolicated_frames );
+        state.generated_files.lock().remove_files_for( data_id );
+
+        let response = protocol::ResponseDebugSymbols {
+            symbolicated_frames: symbolicated_frames as u64
+        };
+
+        write_json( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+}
+
+fn generated_file_to_json( state: &State, kind: &str, path: &str, entry: &GeneratedFile ) -> serde_json::Value {
+    let basename = &path[ path.rfind( "/" ).unwrap() + 1.. ];
+    serde_json::json! {{
+        "url": entry.url( &state.base_path, basename ),
+        "kind": kind,
+        "basename": basename,
+        "path": path,
+        "checksum": entry.hash,
+        "mime": entry.mime,
+        "size": entry.data.len()
+    }}
+}
+
+/// Converts everything the script has printed so far into JSON and registers any generated files.
+fn collect_script_output( state: &State, data_id: DataId, env: &Mutex< cli_core::script::VirtualEnvironment > ) -> Vec< serde_json::Value > {
+    let items = std::mem::take( &mut env.lock().output );
+    script_output_to_json( state, data_id, items )
+}
+
+fn script_output_to_json( state: &State, data_id: DataId, items: Vec< cli_core::script::ScriptOutputKind > ) -> Vec< serde_json::Value > {
+    let mut output = Vec::new();
+    for item in items {
+        match item {
+            cli_core::script::ScriptOutputKind::PrintLine( line ) => {
+                output.push( serde_json::json! {{
+                    "kind": "println",
+                    "value": line
+                }});
+            },
+            cli_core::script::ScriptOutputKind::Image { path, mime, data } => {
+                let entry = state.generated_files.lock().add_file( GeneratedFile::new( data_id, mime, data ) );
+                output.push( generated_file_to_json( state, "image", &path, &entry ) );
+            },
+            cli_core::script::ScriptOutputKind::File { path, mime, data } => {
+                let entry = state.generated_files.lock().add_file( GeneratedFile::new( data_id, mime, data ) );
+                output.push( generated_file_to_json( state, "file", &path, &entry ) );
+            },
+            cli_core::script::ScriptOutputKind::Table { table, truncated } => {
+                let mut entry = table.to_json();
+                entry[ "kind" ] = "table".into();
+                entry[ "truncated" ] = truncated.into();
+                output.push( entry );
+            }
+        }
+    }
+
+    output
+}
+
+fn script_result_to_json( error: Option< &cli_core::script::EvalError >, elapsed: Duration, output: &[serde_json::Value] ) -> serde_json::Value {
+    match error {
+        None => {
+            serde_json::json! {{
+                "status": "ok",
+                "elapsed": elapsed.as_secs_f64(),
+                "output": output
+            }}
+        },
+        Some( error ) => {
+            serde_json::json! {{
+                "status": "error",
+                "reason": error.limit.map( |limit| limit.as_str() ).unwrap_or( "script_error" ),
+                "message": error.message,
+                "line": error.line,
+                "column": error.column,
+                "output": output
+            }}
+        }
+    }
+}
+
+/// Returns the script to execute, which is either the request's body or a script from the library given by name.
+fn get_script_body( req: &HttpRequest, body: web::Bytes ) -> Result< String > {
+    let params: protocol::RequestExecuteScript = query( req )?;
+    if let Some( name ) = params.script {
+        if !body.is_empty() {
+            return Err( ApiError::bad_request( "a script can't be given both by name and in the body" ).into() );
+        }
+
+        return Ok( req.state().script_library.get( &name )? );
+    }
+
+    String::from_utf8( body.to_vec() ).map_err( |_| ApiError::bad_request( "the script is not valid UTF-8" ).into() )
+}
+
+/// Returns the filter which restricts what `allocations()` returns within a script.
+///
+/// If the request doesn't set any of the filter's parameters then there's no filter and the script sees every allocation.
+fn get_script_allocation_filter( req: &HttpRequest, data: &Arc< Data > ) -> Result< Option< AllocationFilter > > {
+    let filter: protocol::AllocFilter = query( req )?;
+    let custom_filter = get_custom_filter( req )?;
+    if filter == protocol::AllocFilter::default() && custom_filter.custom_filter.is_none() {
+        return Ok( None );
+    }
+
+    Ok( Some( prepare_cached_filter( req, data, &filter, &custom_filter )? ) )
+}
+
+fn handler_execute_script( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    check_scripts_enabled( &req )?;
+    let data = &get_data( &req )?;
+    let body = get_script_body( &req, body )?;
+    let filter = get_script_allocation_filter( &req, data )?;
+    let allocation_ids = filter.map( |filter| Arc::new( matching_allocation_ids( data, &filter ) ) );
+    let env = req.state().script_limits.new_environment();
+    let engine = req.state().script_limits.new_filtered_engine( data.clone(), allocation_ids, req.state().datasets(), env.clone() );
+    let timestamp = std::time::Instant::now();
+    let result = engine.run( &body );
+    let elapsed = timestamp.elapsed();
+    req.state().record_script_execution( &result );
+
+    let output = collect_script_output( req.state(), data.id(), &env );
+    let result = script_result_to_json( result.err().as_ref(), elapsed, &output );
+
+    Ok(
+        HttpResponse::Ok()
+        .content_type( "application/json; charset=utf-8" )
+        .body( serde_json::to_string( &result ).unwrap() )
+    )
+}
+
+/// Runs a single script on every loaded data file.
+///
+/// Each data file is handled independently, so a script which fails on one of them still runs on the rest.
+fn handler_execute_script_all( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    check_scripts_enabled( &req )?;
+    let body = get_script_body( &req, body )?;
+    let params: protocol::RequestExecuteScriptAll = query( &req )?;
+    let parallelism = params.parallelism.unwrap_or( 1 ) as usize;
+    if parallelism == 0 || parallelism > MAXIMUM_EXECUTE_SCRIPT_ALL_PARALLELISM {
+        return Err( ApiError::invalid_parameter( "parallelism", format!( "'parallelism' must be between 1 and {}", MAXIMUM_EXECUTE_SCRIPT_ALL_PARALLELISM ) ).into() );
+    }
+
+    let state = req.state().clone();
+    let body = Arc::new( body );
+    let response = async_handler( &req, move |tx| {
+        let datasets = Arc::new( state.datasets() );
+        let next_index = Arc::new( AtomicUsize::new( 0 ) );
+        let (results_tx, results_rx) = crossbeam_channel::unbounded();
+        let execute_remaining = {
+            let state = state.clone();
+            let datasets = datasets.clone();
+            move || loop {
+                let index = next_index.fetch_add( 1, Ordering::SeqCst );
+                let data = match datasets.get( index ) {
+                    Some( data ) => data,
+                    None => break
+                };
+
+                let env = state.script_limits.new_environment();
+                let engine = state.script_limits.new_engine( data.clone(), (*datasets).clone(), env.clone() );
+                let timestamp = std::time::Instant::now();
+                let result = engine.run( &body );
+                let elapsed = timestamp.elapsed();
+                state.record_script_execution( &result );
+
+                let output = collect_script_output( &state, data.id(), &env );
+                let mut result = script_result_to_json( result.err().as_ref(), elapsed, &output );
+                result[ "data_id" ] = format!( "{}", data.id() ).into();
+                let _ = results_tx.send( (index, result) );
             }
+        };
 
-            group
+        // The helpers only ever pick up the data files which nobody has started on yet, so if
+        // the pool is busy then this just runs with less parallelism than was requested.
+        for _ in 1..parallelism {
+            if state.heavy_workers.spawn( execute_remaining.clone() ).is_err() {
+                break;
+            }
         }
-    ).reduce(
-        || Group::default(),
-        |mut a, b| {
-            a.size_sum += b.size_sum;
-            a.min_size = min( a.min_size, b.min_size );
-            a.max_size = max( a.max_size, b.max_size );
-            a.min_timestamp = min( a.min_timestamp, b.min_timestamp );
-            a.max_timestamp = max( a.max_timestamp, b.max_timestamp );
-            a.allocated_count += b.allocated_count;
-            a.leaked_count += b.leaked_count;
 
-            a
+        execute_remaining();
+
+        let mut results = vec![ serde_json::Value::Null; datasets.len() ];
+        for (index, result) in results_rx.iter().take( datasets.len() ) {
+            results[ index ] = result;
         }
+
+        write_json( tx, &results );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( response ) )
+}
+
+#[cfg(test)]
+fn load_test_data( id: DataId, events: Vec< common::event::Event< 'static > > ) -> Data {
+    cli_core::Loader::load_from_stream_without_debug_info( io::Cursor::new( serialize_test_data( id, events ) ) ).unwrap()
+}
+
+#[cfg(test)]
+fn test_app( datasets: Vec< Data > ) -> (
+    Arc< State >,
+    impl actix_web::dev::Service< Request = actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = ActixWebError >
+) {
+    let state = Arc::new( State::new() );
+    for data in datasets {
+        state.add_data( data );
+    }
+
+    let app = actix_web::test::init_service( App::new().data( state.clone() ).configure( configure_routes ) );
+    (state, app)
+}
+
+#[cfg(test)]
+fn serialize_test_data( id: DataId, events: Vec< common::event::Event< 'static > > ) -> Vec< u8 > {
+    serialize_test_data_of( id, "test", events )
+}
+
+#[cfg(test)]
+fn serialize_test_data_of( id: DataId, executable: &str, events: Vec< common::event::Event< 'static > > ) -> Vec< u8 > {
+    use common::event::Event;
+    use common::speedy::Writable;
+
+    let mut fp = common::lz4_stream::Lz4Writer::new( Vec::new() );
+    Event::Header( test_header( id, executable ) ).write_to_stream( &mut fp ).unwrap();
+    for event in events {
+        event.write_to_stream( &mut fp ).unwrap();
+    }
+
+    fp.into_inner().unwrap()
+}
+
+#[cfg(test)]
+fn test_header( id: DataId, executable: &str ) -> common::event::HeaderBody {
+    common::event::HeaderBody {
+        id,
+        initial_timestamp: Timestamp::from_secs( 1 ),
+        timestamp: Timestamp::from_secs( 1 ),
+        wall_clock_secs: 0,
+        wall_clock_nsecs: 0,
+        pid: 1,
+        cmdline: Vec::new(),
+        executable: executable.as_bytes().to_vec(),
+        arch: "x86_64".into(),
+        flags: 0,
+        pointer_size: 8
+    }
+}
+
+#[cfg(test)]
+fn test_mmap( secs: u64, pointer: u64, length: u64, backtrace: u64 ) -> common::event::Event< 'static > {
+    common::event::Event::MemoryMap {
+        timestamp: Timestamp::from_secs( secs ),
+        pointer,
+        length,
+        backtrace,
+        requested_address: 0,
+        mmap_protection: 0x3,
+        mmap_flags: 0x22,
+        file_descriptor: -1_i32 as u32,
+        thread: 1,
+        offset: 0
+    }
+}
+
+#[cfg(test)]
+fn test_munmap( secs: u64, pointer: u64, length: u64 ) -> common::event::Event< 'static > {
+    common::event::Event::MemoryUnmap { timestamp: Timestamp::from_secs( secs ), pointer, length, backtrace: 1, thread: 1 }
+}
+
+#[cfg(test)]
+fn test_alloc_body( pointer: u64, size: u64, backtrace: u64 ) -> common::event::AllocBody {
+    common::event::AllocBody { pointer, size, backtrace, thread: 1, flags: 0, extra_usable_space: 0, preceding_free_space: 0 }
+}
+
+#[cfg(test)]
+fn test_alloc( secs: u64, pointer: u64, size: u64, backtrace: u64 ) -> common::event::Event< 'static > {
+    common::event::Event::Alloc { timestamp: Timestamp::from_secs( secs ), allocation: test_alloc_body( pointer, size, backtrace ) }
+}
+
+#[cfg(test)]
+fn test_free( secs: u64, pointer: u64, backtrace: u64 ) -> common::event::Event< 'static > {
+    common::event::Event::Free { timestamp: Timestamp::from_secs( secs ), pointer, backtrace, thread: 1 }
+}
+
+#[cfg(test)]
+fn test_string( id: u32, string: &'static str ) -> common::event::Event< 'static > {
+    common::event::Event::String { id, string: string.into() }
+}
+
+/// A frame from the library with the string ID 0; `0xFFFFFFFF` means that the function, the source or the line is unknown.
+#[cfg(test)]
+fn test_frame( address: u64, function: u32, source: u32, line: u32, is_inline: bool ) -> common::event::Event< 'static > {
+    common::event::Event::DecodedFrame { address, library: 0, raw_function: 0xFFFFFFFF, function, source, line, column: 0xFFFFFFFF, is_inline }
+}
+
+/// Runs the given script through `/execute_script` on the first of the given data files.
+#[cfg(test)]
+fn execute_test_script( datasets: Vec< Data >, script: &str ) -> serde_json::Value {
+    use actix_web::test;
+
+    let state = State::new();
+    let id = datasets[ 0 ].id();
+    for data in datasets {
+        state.add_data( data );
+    }
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
     );
 
-    protocol::AllocationGroupData {
-        leaked_count: group.leaked_count,
-        allocated_count: group.allocated_count,
-        size: group.size_sum,
-        min_size: group.min_size,
-        max_size: group.max_size,
-        min_timestamp: group.min_timestamp.into(),
-        min_timestamp_relative: (group.min_timestamp - data.initial_timestamp()).into(),
-        min_timestamp_relative_p: timestamp_to_fraction( data, group.min_timestamp ),
-        max_timestamp: group.max_timestamp.into(),
-        max_timestamp_relative: (group.max_timestamp - data.initial_timestamp()).into(),
-        max_timestamp_relative_p: timestamp_to_fraction( data, group.max_timestamp ),
-        interval: (group.max_timestamp - group.min_timestamp).into(),
-        graph_url: None,
-        graph_preview_url: None,
-        max_total_usage_first_seen_at: None,
-        max_total_usage_first_seen_at_relative: None,
-        max_total_usage_first_seen_at_relative_p: None,
+    let request = test::TestRequest::post()
+        .uri( &format!( "/data/{}/execute_script", id ) )
+        .set_payload( script.to_owned() )
+        .to_request();
+
+    test::read_response_json( &mut app, request )
+}
+
+#[test]
+fn test_execute_script_mallopts_and_markers() {
+    use common::event::{AllocBody, Event};
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000, 0x2000 ].into() },
+        Event::Mallopt { timestamp: Timestamp::from_secs( 2 ), backtrace: 1, thread: 7, param: -3, value: 65536, result: 1 },
+        Event::Mallopt { timestamp: Timestamp::from_secs( 3 ), backtrace: 1, thread: 8, param: 100, value: 1, result: 0 },
+        Event::MarkerEx { timestamp: Timestamp::from_secs( 4 ), backtrace: 1, thread: 9, value: 42 },
+        Event::Alloc { timestamp: Timestamp::from_secs( 5 ), allocation: AllocBody { thread: 9, ..test_alloc_body( 0x10000, 16, 1 ) } },
+        // Old-style markers get the timestamp of the last event before them.
+        Event::Marker { value: 43 }
+    ]);
+
+    let result = execute_test_script( vec![ data ], r#"
+        for mallopt in mallopts() {
+            println( "{} {} {}", mallopt.timestamp(), mallopt.thread_id(), mallopt.kind() );
+            println( "{} {} {}", mallopt.raw_kind(), mallopt.value(), mallopt.result() );
+            println( mallopt.backtrace_id() );
+        }
+        for marker in data().markers() {
+            println( "{} {} {}", marker.timestamp(), marker.thread_id(), marker.value() );
+            println( marker.backtrace_id() );
+        }
+        println( type_of( markers()[1].backtrace() ) == "()" );
+    "# );
+
+    assert_eq!( result[ "status" ], "ok", "{}", result );
+    let lines: Vec< _ > = result[ "output" ].as_array().unwrap().iter().map( |entry| entry[ "value" ].as_str().unwrap().to_owned() ).collect();
+    assert_eq!( lines, vec![
+        "1s 7 M_MMAP_THRESHOLD", "-3 65536 1", "0",
+        "2s 8 100", "100 1 0", "0",
+        "3s 9 42", "0",
+        "4s 0 43", "-1",
+        "true"
+    ]);
+}
+
+#[test]
+fn test_execute_script_maps() {
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_mmap( 2, 0x10000, 0x2000, 1 ),
+        Event::MemoryMap {
+            timestamp: Timestamp::from_secs( 3 ),
+            pointer: 0x20000,
+            length: 0x1000,
+            backtrace: 1,
+            requested_address: 0,
+            mmap_protection: 0x1,
+            mmap_flags: 0x02,
+            file_descriptor: 3,
+            thread: 1,
+            offset: 0
+        },
+        test_mmap( 4, 0x30000, 0x8000, 1 ),
+        test_munmap( 5, 0x10000, 0x2000 )
+    ]);
+
+    let result = execute_test_script( vec![ data ], r#"
+        for map in maps() {
+            println( "{} {} {}", map.timestamp(), map.kind(), map.fd() );
+            println( "{} {}", map.address(), map.length() );
+            println( "{} {}", map.protection(), "anonymous" in map.flags() );
+        }
+        println( maps().only_mmaps().len() );
+        println( maps().only_munmaps()[0].is_unmap() );
+        println( maps().only_anonymous().len() );
+        println( maps().only_not_anonymous()[0].fd() );
+        println( maps().only_larger( 0x1000 ).len() );
+        println( maps().only_smaller_or_equal( 0x1000 ).len() );
+        println( maps().only_after_at_least( 2.s() ).only_until_at_most( 3.s() ).len() );
+        graph().add( maps().only_anonymous() ).save();
+    "# );
+
+    assert_eq!( result[ "status" ], "ok", "{}", result );
+    let output = result[ "output" ].as_array().unwrap();
+    let lines: Vec< _ > = output.iter().filter_map( |entry| entry[ "value" ].as_str() ).map( |line| line.to_owned() ).collect();
+    assert_eq!( lines, vec![
+        "1s mmap -1", "65536 8192", "rw- true",
+        "2s mmap 3", "131072 4096", "r-- false",
+        "3s mmap -1", "196608 32768", "rw- true",
+        "4s munmap -1", "65536 8192", " false",
+        "3",
+        "true",
+        "2",
+        "3",
+        "3",
+        "1",
+        "2"
+    ]);
+    assert_eq!( output.last().unwrap()[ "kind" ], "image" );
+}
+
+#[test]
+fn test_markers() {
+    use actix_web::test;
+    use common::event::{AllocBody, Event};
+
+    let alloc = |secs: u64, pointer: u64, size: u64| Event::Alloc {
+        timestamp: Timestamp::from_secs( secs ),
+        allocation: AllocBody { thread: 9, ..test_alloc_body( pointer, size, 1 ) }
+    };
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000, 0x2000 ].into() },
+        alloc( 1, 0x1000, 16 ),
+        Event::MarkerEx { timestamp: Timestamp::from_secs( 2 ), backtrace: 1, thread: 9, value: 1 },
+        alloc( 3, 0x2000, 16 ),
+        alloc( 3, 0x3000, 32 ),
+        Event::MarkerEx { timestamp: Timestamp::from_secs( 4 ), backtrace: 1, thread: 9, value: 2 },
+        alloc( 5, 0x4000, 8 ),
+        // Old-style markers don't have a backtrace.
+        Event::Marker { value: 3 }
+    ]);
+
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/data/last/markers" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let markers = response[ "markers" ].as_array().unwrap();
+    assert_eq!( markers.len(), 3 );
+    assert_eq!( markers[ 0 ][ "value" ], 1 );
+    assert_eq!( markers[ 0 ][ "thread" ], 9 );
+    assert_eq!( markers[ 1 ][ "timestamp" ][ "secs" ].as_u64().unwrap() - markers[ 0 ][ "timestamp" ][ "secs" ].as_u64().unwrap(), 2 );
+    assert!( markers[ 0 ][ "backtrace_id" ].is_u64() );
+    assert!( markers[ 0 ][ "backtrace" ].is_array() );
+    assert_eq!( markers[ 2 ][ "value" ], 3 );
+    assert!( markers[ 2 ].get( "backtrace_id" ).is_none() );
+    assert!( markers[ 2 ].get( "backtrace" ).is_none() );
+    assert_eq!( response[ "allocations" ], serde_json::json!([
+        { "value": 0, "allocated_count": 1, "allocated_size": 16 },
+        { "value": 1, "allocated_count": 2, "allocated_size": 48 },
+        { "value": 2, "allocated_count": 1, "allocated_size": 8 }
+    ]));
+
+    let request = test::TestRequest::get().uri( "/data/last/markers?value=1" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "markers" ].as_array().unwrap().len(), 1 );
+    assert_eq!( response[ "markers" ][ 0 ][ "value" ], 1 );
+    assert_eq!( response[ "allocations" ], serde_json::json!([ { "value": 1, "allocated_count": 2, "allocated_size": 48 } ]) );
+
+    let request = test::TestRequest::get().uri( "/data/last/markers?from=3000&to=3500" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "markers" ].as_array().unwrap().len(), 1 );
+    assert_eq!( response[ "markers" ][ 0 ][ "value" ], 2 );
+}
+
+#[test]
+fn test_marker_with_an_unknown_backtrace() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let (_, mut app) = test_app( vec![ load_test_data( DataId::new( 1, 2 ), vec![
+        Event::MarkerEx { timestamp: Timestamp::from_secs( 2 ), backtrace: 99, thread: 9, value: 1 }
+    ])]);
+
+    let request = test::TestRequest::get().uri( "/data/last/markers" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let markers = response[ "markers" ].as_array().unwrap();
+    assert_eq!( markers.len(), 1 );
+    assert_eq!( markers[ 0 ][ "value" ], 1 );
+    assert!( markers[ 0 ].get( "backtrace" ).is_none() );
+}
+
+#[test]
+fn test_execute_script_graph_with_markers() {
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::MarkerEx { timestamp: Timestamp::from_secs( 2 ), backtrace: 1, thread: 1, value: 1 }
+    ]);
+
+    let result = execute_test_script( vec![ data ], r#"
+        let graph = graph().add( maps() );
+        for marker in markers() {
+            graph = graph.add_marker( marker.timestamp(), "marker #" + marker.value() );
+        }
+        graph.save();
+    "# );
+
+    assert_eq!( result[ "status" ], "ok", "{}", result );
+    assert_eq!( result[ "output" ][ 0 ][ "kind" ], "image" );
+
+    let data = load_test_data( DataId::new( 1, 2 ), Vec::new() );
+    let result = execute_test_script( vec![ data ], "graph().add_marker( s(1), \"foo\" ).save();" );
+    assert_eq!( result[ "status" ], "error" );
+}
+
+#[test]
+fn test_execute_script_graph_as_png() {
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 )
+    ]);
+
+    let result = execute_test_script( vec![ data ], r#"
+        graph().add( allocations() ).save_as_png( 320, 240 );
+        graph().add( allocations() ).save_as_png( "foo.png", 640, 480 );
+        graph().add( "first", allocations() ).add( "second", allocations() ).save_each_series_as_png( "series", 320, 240 );
+    "# );
+
+    assert_eq!( result[ "status" ], "ok", "{}", result );
+    assert_eq!( result[ "output" ][ 0 ][ "kind" ], "image" );
+    assert_eq!( result[ "output" ][ 0 ][ "mime" ], "image/png" );
+    assert_eq!( result[ "output" ][ 0 ][ "basename" ], "Graph #1.png" );
+    assert_eq!( result[ "output" ][ 1 ][ "mime" ], "image/png" );
+    assert_eq!( result[ "output" ][ 1 ][ "basename" ], "foo.png" );
+    assert_eq!( result[ "output" ][ 2 ][ "mime" ], "image/png" );
+    assert_eq!( result[ "output" ][ 2 ][ "basename" ], "first.png" );
+    assert_eq!( result[ "output" ][ 3 ][ "mime" ], "image/png" );
+    assert_eq!( result[ "output" ][ 3 ][ "basename" ], "second.png" );
+
+    for &(width, height) in &[(0, 240), (320, -1), (320, 50000), (50000, 50000), (4096, 4096), (1, 9_000_000)] {
+        let data = load_test_data( DataId::new( 1, 2 ), Vec::new() );
+        let result = execute_test_script( vec![ data ], &format!( "graph().add( allocations() ).save_as_png( {}, {} );", width, height ) );
+        assert_eq!( result[ "status" ], "error", "{}x{}", width, height );
     }
 }
 
-fn get_global_group_data( data: &Data, backtrace_id: BacktraceId ) -> protocol::AllocationGroupData {
-    let stats = data.get_group_statistics( backtrace_id );
+#[test]
+fn test_metrics() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    let leaked_count = stats.alloc_count - stats.free_count;
-    let allocated_count = stats.alloc_count;
-    let size_sum = stats.alloc_size;
-    let min_size = stats.min_size;
-    let max_size = stats.max_size;
-    let min_timestamp = stats.first_allocation;
-    let max_timestamp = stats.last_allocation;
+    use common::event::Event;
 
-    protocol::AllocationGroupData {
-        leaked_count,
-        allocated_count,
-        size: size_sum,
-        min_size,
-        max_size,
-        min_timestamp: min_timestamp.into(),
-        min_timestamp_relative: (min_timestamp - data.initial_timestamp()).into(),
-        min_timestamp_relative_p: timestamp_to_fraction( data, min_timestamp ),
-        max_timestamp: max_timestamp.into(),
-        max_timestamp_relative: (max_timestamp - data.initial_timestamp()).into(),
-        max_timestamp_relative_p: timestamp_to_fraction( data, max_timestamp ),
-        interval: (max_timestamp - min_timestamp).into(),
-        graph_url: None,
-        graph_preview_url: None,
-        max_total_usage_first_seen_at: Some( stats.max_total_usage_first_seen_at.into() ),
-        max_total_usage_first_seen_at_relative: Some( (stats.max_total_usage_first_seen_at - data.initial_timestamp()).into() ),
-        max_total_usage_first_seen_at_relative_p: Some( timestamp_to_fraction( data, stats.max_total_usage_first_seen_at ) ),
+    let mut state = State::new();
+    state.metrics = Some( Default::default() );
+    state.add_data( load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 )
+    ]));
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .wrap_fn( metrics::middleware )
+            .service( web::resource( "/metrics" ).route( web::get().to( handler_metrics ) ) )
+            .service( web::resource( "/data/{id}/timeline" ).route( web::get().to( handler_timeline ) ) )
+            .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
+    );
+
+    for _ in 0..2 {
+        let request = test::TestRequest::get().uri( "/data/last/timeline" ).to_request();
+        assert!( !test::read_response( &mut app, request ).is_empty() );
+    }
+
+    let request = test::TestRequest::post().uri( "/data/last/execute_script" ).set_payload( "println(1);" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
+    let request = test::TestRequest::post().uri( "/data/last/execute_script" ).set_payload( "foo(" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
+    let request = test::TestRequest::get().uri( "/nonexistent" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
+
+    let request = test::TestRequest::get().uri( "/metrics" ).to_request();
+    let output = String::from_utf8( test::read_response( &mut app, request ).to_vec() ).unwrap();
+    let expected = [
+        "bytehound_http_requests_total{method=\"GET\",route=\"/data/{id}/timeline\",status=\"200\"} 2".to_owned(),
+        "bytehound_http_requests_total{method=\"POST\",route=\"/data/{id}/execute_script\",status=\"200\"} 2".to_owned(),
+        "bytehound_http_requests_total{method=\"GET\",route=\"<unknown>\",status=\"404\"} 1".to_owned(),
+        "bytehound_http_request_duration_seconds_count{method=\"GET\",route=\"/data/{id}/timeline\"} 2".to_owned(),
+        "bytehound_script_executions_total 2".to_owned(),
+        "bytehound_script_failures_total 1".to_owned(),
+        "bytehound_cache_hits_total{cache=\"timeline\"} 1".to_owned(),
+        "bytehound_cache_misses_total{cache=\"timeline\"} 1".to_owned(),
+        format!( "bytehound_dataset_allocations{{data_id=\"{}\"}} 1", DataId::new( 1, 2 ) )
+    ];
+
+    for line in &expected {
+        assert!( output.lines().any( |output_line| output_line == line ), "missing '{}' in:\n{}", line, output );
+    }
+
+    let bytes_streamed: u64 = output.lines().find( |line| line.starts_with( "bytehound_http_response_bytes_total " ) ).unwrap()
+        .split( ' ' ).nth( 1 ).unwrap().parse().unwrap();
+    assert!( bytes_streamed > 0 );
+
+    let timeline_cache_size: u64 = output.lines().find( |line| line.starts_with( "bytehound_cache_bytes{cache=\"timeline\"} " ) ).unwrap()
+        .split( ' ' ).nth( 1 ).unwrap().parse().unwrap();
+    assert!( timeline_cache_size > 0 );
+}
+
+#[test]
+fn test_health_and_readiness() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+
+    let directory = std::env::temp_dir().join( format!( "bytehound-readiness-test-{}", std::process::id() ) );
+    std::fs::create_dir_all( &directory ).unwrap();
+    let good_path = directory.join( "good.dat" );
+    let bad_path = directory.join( "nonexistent.dat" );
+    std::fs::write( &good_path, serialize_test_data( DataId::new( 1, 2 ), Vec::new() ) ).unwrap();
+
+    let mut state = State::new();
+    state.inputs = RwLock::new( vec![ Arc::new( loading::InputFile::new( good_path.clone() ) ), Arc::new( loading::InputFile::new( bad_path.clone() ) ) ] );
+    let state = Arc::new( state );
+
+    let mut app = test::init_service(
+        App::new().data( state.clone() )
+            .service( web::resource( "/list" ).route( web::get().to( handler_list ) ) )
+            .service( web::resource( "/healthz" ).route( web::get().to( handler_healthz ) ) )
+            .service( web::resource( "/readyz" ).route( web::get().to( handler_readyz ) ) )
+            .service( web::resource( "/data/{id}/modules" ).route( web::get().to( handler_modules ) ) )
+    );
+
+    let request = test::TestRequest::get().uri( "/healthz" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
+
+    let request = test::TestRequest::get().uri( "/readyz" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::SERVICE_UNAVAILABLE );
+    let response: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+    assert_eq!( response[ "status" ], "loading" );
+    assert_eq!( response[ "files" ][ 0 ][ "status" ], "loading" );
+    assert_eq!( response[ "files" ][ 0 ][ "progress" ], 0.0 );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response.as_array().unwrap().len(), 2 );
+    assert_eq!( response[ 0 ][ "state" ], "loading" );
+    assert_eq!( response[ 0 ][ "progress" ], 0.0 );
+    assert!( response[ 0 ][ "id" ].is_null() );
+
+    // Requests for the data which is still being loaded get a 409 instead of a 404.
+    let request = test::TestRequest::get().uri( "/data/last/modules" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::CONFLICT );
+
+    loading::read_header( &state.inputs.read()[ 0 ] ).unwrap();
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ 0 ][ "id" ], format!( "{}", DataId::new( 1, 2 ) ) );
+
+    let request = test::TestRequest::get().uri( &format!( "/data/{}/modules", DataId::new( 1, 2 ) ) ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::CONFLICT );
+    let response: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+    assert_eq!( response[ "error" ][ "code" ], "data_loading" );
+    assert_eq!( response[ "error" ][ "details" ][ "path" ], good_path.to_string_lossy().as_ref() );
+    assert_eq!( response[ "error" ][ "details" ][ "progress" ], 0.0 );
+
+    let request = test::TestRequest::get().uri( &format!( "/data/{}/modules", DataId::new( 3, 4 ) ) ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
+
+    loading::load_inputs( state.clone(), true, false ).join().unwrap();
+
+    let request = test::TestRequest::get().uri( "/readyz" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::OK );
+    let response: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+    assert_eq!( response[ "status" ], "ready" );
+    assert_eq!( response[ "files" ][ 0 ][ "path" ], good_path.to_string_lossy().as_ref() );
+    assert_eq!( response[ "files" ][ 0 ][ "status" ], "loaded" );
+    assert_eq!( response[ "files" ][ 0 ][ "id" ], format!( "{}", DataId::new( 1, 2 ) ) );
+    assert_eq!( response[ "files" ][ 0 ][ "progress" ], 100.0 );
+    assert_eq!( response[ "files" ][ 1 ][ "status" ], "failed" );
+    assert!( response[ "files" ][ 1 ][ "error" ].is_string() );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response.as_array().unwrap().len(), 2 );
+    assert_eq!( response[ 0 ][ "state" ], "ready" );
+    assert_eq!( response[ 0 ][ "progress" ], 1.0 );
+    assert_eq!( response[ 0 ][ "source_kind" ], "file" );
+    assert_eq!( response[ 0 ][ "source_path" ], good_path.to_string_lossy().as_ref() );
+    assert_eq!( response[ 0 ][ "source_size" ], std::fs::metadata( &good_path ).unwrap().len() );
+    assert!( response[ 0 ][ "loaded_at" ].as_u64().unwrap() > 0 );
+    assert!( response[ 0 ][ "load_duration_secs" ].is_f64() );
+    assert!( response[ 0 ][ "approx_resident_bytes" ].is_u64() );
+    assert_eq!( response[ 1 ][ "state" ], "failed" );
+    assert_eq!( response[ 1 ][ "path" ], bad_path.to_string_lossy().as_ref() );
+
+    let request = test::TestRequest::get().uri( &format!( "/data/{}/modules", DataId::new( 1, 2 ) ) ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
+
+    let _ = std::fs::remove_dir_all( &directory );
+}
+
+#[test]
+fn test_strict_loading() {
+    let directory = std::env::temp_dir().join( format!( "bytehound-strict-loading-test-{}", std::process::id() ) );
+    std::fs::create_dir_all( &directory ).unwrap();
+    let good_path = directory.join( "good.dat" );
+    let bad_path = directory.join( "nonexistent.dat" );
+    std::fs::write( &good_path, serialize_test_data( DataId::new( 1, 2 ), Vec::new() ) ).unwrap();
+
+    let inputs = || vec![ Arc::new( loading::InputFile::new( bad_path.clone() ) ), Arc::new( loading::InputFile::new( good_path.clone() ) ) ];
+    for &load_in_parallel in &[ false, true ] {
+        let mut state = State::new();
+        state.inputs = RwLock::new( inputs() );
+        let state = Arc::new( state );
+        loading::load_inputs( state.clone(), load_in_parallel, false ).join().unwrap();
+        assert!( loading::first_failure( &state ).unwrap().to_string().contains( "nonexistent.dat" ) );
+        assert!( state.get_data( DataId::new( 1, 2 ) ).is_some() );
+
+        // In the strict mode we give up on the first failure.
+        let mut state = State::new();
+        state.inputs = RwLock::new( inputs() );
+        let state = Arc::new( state );
+        loading::load_inputs( state.clone(), load_in_parallel, true ).join().unwrap();
+        assert!( loading::first_failure( &state ).is_some() );
+        assert!( state.get_data( DataId::new( 1, 2 ) ).is_none() );
+    }
+
+    let _ = std::fs::remove_dir_all( &directory );
+}
+
+#[test]
+fn test_low_memory() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let mut events = vec![ Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() } ];
+    for index in 0..1000 {
+        events.push( test_alloc( 1 + index, 0x1000 + index * 0x100, index + 1, 1 ) );
+        if index % 2 == 0 {
+            events.push( test_free( 2 + index, 0x1000 + index * 0x100, 1 ) );
+        }
+    }
+
+    let directory = std::env::temp_dir().join( format!( "bytehound-low-memory-test-{}", std::process::id() ) );
+    std::fs::create_dir_all( &directory ).unwrap();
+    let path = directory.join( "data.dat" );
+    std::fs::write( &path, serialize_test_data( DataId::new( 1, 2 ), events ) ).unwrap();
+
+    let mut responses = Vec::new();
+    let mut memory_usage = Vec::new();
+    // The second one is loaded from the cache written by the first one, and the last two straight from the data file.
+    for &(low_memory, cache_dir) in &[ (false, Some( &directory )), (true, Some( &directory )), (false, None), (true, None) ] {
+        let mut state = State::new();
+        state.low_memory = low_memory;
+        state.cache_dir = cache_dir.cloned();
+        state.inputs = RwLock::new( vec![ Arc::new( loading::InputFile::new( path.clone() ) ) ] );
+        let state = Arc::new( state );
+        loading::load_inputs( state.clone(), false, false ).join().unwrap();
+        memory_usage.push( state.get_data( DataId::new( 1, 2 ) ).unwrap().memory_usage_estimate() );
+
+        let mut app = test::init_service( App::new().data( state.clone() ).configure( configure_routes ) );
+        for uri in &[ "/data/last/allocations?sort_by=size&order=dsc&count=10", "/data/last/allocation_groups?sort_by=all.size" ] {
+            let request = test::TestRequest::get().uri( uri ).to_request();
+            let response: serde_json::Value = test::read_response_json( &mut app, request );
+            responses.push( response );
+        }
+    }
+
+    assert!( memory_usage[ 1 ] < memory_usage[ 0 ] );
+    assert!( memory_usage[ 3 ] < memory_usage[ 2 ] );
+    for index in 1..4 {
+        assert_eq!( responses[ 0 ], responses[ index * 2 ] );
+        assert_eq!( responses[ 1 ], responses[ index * 2 + 1 ] );
+    }
+    assert_eq!( responses[ 0 ][ "total_count" ], 1000 );
+
+    let _ = std::fs::remove_dir_all( &directory );
+}
+
+#[test]
+fn test_load_progress() {
+    use actix_web::test;
+    use cli_core::{LoadPhase, LoadProgress};
+    use common::event::Event;
+
+    let events: Vec< _ > = (0..10000).map( |index| Event::Backtrace { id: index, addresses: vec![ 0x1000 + index ].into() } ).collect();
+    let bytes = serialize_test_data( DataId::new( 1, 2 ), events );
+    let mut reports: Vec< LoadProgress > = Vec::new();
+    let empty: &[PathBuf] = &[];
+    cli_core::Loader::load_from_stream_with_progress( io::Cursor::new( bytes.clone() ), empty, &cli_core::LoadOptions::default(), |progress| reports.push( progress ) ).unwrap();
+
+    let mut phases: Vec< _ > = reports.iter().map( |progress| progress.phase ).collect();
+    phases.dedup();
+    assert_eq!( phases, vec![ LoadPhase::Reading, LoadPhase::Sorting, LoadPhase::Indexing ] );
+    assert!( reports.windows( 2 ).all( |pair| pair[ 0 ].bytes_read <= pair[ 1 ].bytes_read ) );
+    assert_eq!( reports.last().unwrap().bytes_read, bytes.len() as u64 );
+
+    let directory = std::env::temp_dir().join( format!( "bytehound-load-progress-test-{}", std::process::id() ) );
+    std::fs::create_dir_all( &directory ).unwrap();
+    let path = directory.join( "data.dat" );
+    std::fs::write( &path, &bytes ).unwrap();
+
+    let mut state = State::new();
+    state.inputs = RwLock::new( vec![ Arc::new( loading::InputFile::new( path.clone() ) ) ] );
+    let state = Arc::new( state );
+    let mut app = test::init_service(
+        App::new().data( state.clone() )
+            .configure( configure_routes )
+    );
+
+    let request = test::TestRequest::get().uri( "/load_progress" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ 0 ][ "path" ], path.to_string_lossy().as_ref() );
+    assert_eq!( response[ 0 ][ "status" ], "loading" );
+    assert_eq!( response[ 0 ][ "bytes_read" ], 0 );
+    assert!( response[ 0 ][ "phase" ].is_null() );
+
+    loading::load_inputs( state.clone(), false, false ).join().unwrap();
+
+    let request = test::TestRequest::get().uri( "/load_progress" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response.as_array().unwrap().len(), 1 );
+    assert_eq!( response[ 0 ][ "status" ], "loaded" );
+    assert_eq!( response[ 0 ][ "progress" ], 100.0 );
+    assert_eq!( response[ 0 ][ "bytes_read" ], bytes.len() as u64 );
+    assert_eq!( response[ 0 ][ "size" ], bytes.len() as u64 );
+    assert!( response[ 0 ][ "phase" ].is_null() );
+
+    std::fs::remove_dir_all( &directory ).unwrap();
+}
+
+#[test]
+fn test_upload() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+
+    let (state, mut app) = test_app( Vec::new() );
+
+    let id = DataId::new( 1, 2 );
+    let upload = |body: Vec< u8 >| test::TestRequest::post().uri( "/data" ).set_payload( body ).to_request();
+    let status_and_body = |response: actix_web::dev::ServiceResponse| {
+        let status = response.status();
+        let body: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+        (status, body)
+    };
+
+    let (status, body) = status_and_body( test::call_service( &mut app, upload( serialize_test_data( id, Vec::new() ) ) ) );
+    assert_eq!( status, StatusCode::ACCEPTED );
+    assert_eq!( body[ "id" ], format!( "{}", id ) );
+
+    // Once the data is loaded it's listed on its own, and the temporary file is gone.
+    let started = Instant::now();
+    while state.get_data( id ).is_none() || !state.inputs.read().is_empty() {
+        assert!( started.elapsed() < Duration::from_secs( 10 ) );
+        thread::sleep( Duration::from_millis( 10 ) );
+    }
+
+    let upload_prefix = format!( "bytehound-upload-{}-", std::process::id() );
+    assert!( !std::fs::read_dir( std::env::temp_dir() ).unwrap().any( |entry| entry.unwrap().file_name().to_string_lossy().starts_with( &upload_prefix ) ) );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response.as_array().unwrap().len(), 1 );
+    assert_eq!( response[ 0 ][ "id" ], format!( "{}", id ) );
+    assert_eq!( response[ 0 ][ "state" ], "ready" );
+
+    let (status, body) = status_and_body( test::call_service( &mut app, upload( serialize_test_data( id, Vec::new() ) ) ) );
+    assert_eq!( status, StatusCode::CONFLICT );
+    assert_eq!( body[ "error" ][ "details" ][ "id" ], format!( "{}", id ) );
+
+    let (status, _) = status_and_body( test::call_service( &mut app, upload( b"this is not a data file".to_vec() ) ) );
+    assert_eq!( status, StatusCode::BAD_REQUEST );
+    assert!( state.inputs.read().is_empty() );
+}
+
+#[test]
+fn test_unload() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+
+    let first_id = DataId::new( 1, 2 );
+    let second_id = DataId::new( 3, 4 );
+    let (state, mut app) = test_app( vec![
+        load_test_data( first_id, Vec::new() ),
+        load_test_data( second_id, Vec::new() )
+    ]);
+
+
+    let mut call = |method: &str, uri: &str| {
+        let request = match method {
+            "GET" => test::TestRequest::get(),
+            "DELETE" => test::TestRequest::delete(),
+            _ => unreachable!()
+        };
+
+        let response = test::call_service( &mut app, request.uri( uri ).to_request() );
+        let status = response.status();
+        test::read_body( response );
+        status
+    };
+
+    assert_eq!( call( "GET", &format!( "/data/{}/allocation_groups", second_id ) ), StatusCode::OK );
+    assert!( state.allocation_group_cache.inner.lock().cache.iter().any( |(key, _)| key.data_id == second_id ) );
+
+    let data = state.get_data( second_id ).unwrap();
+    assert_eq!( call( "DELETE", "/data/last" ), StatusCode::NO_CONTENT );
+
+    // The worker which has sent the response could still be holding on to the data for a moment.
+    let started = Instant::now();
+    while Arc::strong_count( &data ) != 1 {
+        assert!( started.elapsed() < Duration::from_secs( 10 ) );
+        thread::sleep( Duration::from_millis( 10 ) );
+    }
+    assert!( !state.allocation_group_cache.inner.lock().cache.iter().any( |(key, _)| key.data_id == second_id ) );
+
+    assert_eq!( call( "GET", &format!( "/data/{}/modules", second_id ) ), StatusCode::NOT_FOUND );
+    assert_eq!( call( "DELETE", &format!( "/data/{}", second_id ) ), StatusCode::NOT_FOUND );
+    assert_eq!( state.last_id(), Some( first_id ) );
+    assert_eq!( call( "GET", "/data/last/modules" ), StatusCode::OK );
+
+    assert_eq!( call( "DELETE", &format!( "/data/{}", first_id ) ), StatusCode::NO_CONTENT );
+    assert_eq!( call( "GET", "/data/last/modules" ), StatusCode::NOT_FOUND );
+    assert!( state.datasets().is_empty() );
+}
+
+#[test]
+fn test_data_names() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+
+    let first_id = DataId::new( 1, 2 );
+    let second_id = DataId::new( 3, 4 );
+    let (state, mut app) = test_app( vec![
+        load_test_data( first_id, Vec::new() ),
+        load_test_data( second_id, Vec::new() )
+    ]);
+
+
+    let mut set_name = |id: &str, body: serde_json::Value| {
+        let request = test::TestRequest::put().uri( &format!( "/data/{}/name", id ) ).set_json( &body ).to_request();
+        let response = test::call_service( &mut app, request );
+        let status = response.status();
+        test::read_body( response );
+        status
+    };
+
+    assert_eq!( set_name( &format!( "{}", first_id ), serde_json::json!({ "name": "baseline" }) ), StatusCode::NO_CONTENT );
+    assert_eq!( set_name( "last", serde_json::json!({ "name": "baseline" }) ), StatusCode::CONFLICT );
+    assert_eq!( set_name( "last", serde_json::json!({ "name": "not valid" }) ), StatusCode::BAD_REQUEST );
+    assert_eq!( set_name( "last", serde_json::json!({ "name": "last" }) ), StatusCode::BAD_REQUEST );
+    assert_eq!( set_name( "nonexistent", serde_json::json!({ "name": "candidate" }) ), StatusCode::NOT_FOUND );
+    assert_eq!( set_name( "last", serde_json::json!({ "name": "candidate" }) ), StatusCode::NO_CONTENT );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let mut names: Vec< _ > = response.as_array().unwrap().iter().map( |entry| (entry[ "id" ].as_str().unwrap().to_owned(), entry[ "name" ].as_str().unwrap().to_owned()) ).collect();
+    names.sort();
+    assert_eq!( names, vec![ (format!( "{}", first_id ), "baseline".to_owned()), (format!( "{}", second_id ), "candidate".to_owned()) ] );
+
+    let mut call = |method: &str, uri: &str| {
+        let request = match method {
+            "GET" => test::TestRequest::get(),
+            "DELETE" => test::TestRequest::delete(),
+            _ => unreachable!()
+        };
+
+        let response = test::call_service( &mut app, request.uri( uri ).to_request() );
+        let status = response.status();
+        test::read_body( response );
+        status
+    };
+
+    assert_eq!( call( "GET", "/data/baseline/modules" ), StatusCode::OK );
+    assert_eq!( call( "DELETE", "/data/baseline/name" ), StatusCode::NO_CONTENT );
+    assert_eq!( call( "DELETE", &format!( "/data/{}/name", first_id ) ), StatusCode::NOT_FOUND );
+    assert_eq!( call( "GET", "/data/baseline/modules" ), StatusCode::NOT_FOUND );
+
+    assert_eq!( call( "DELETE", "/data/candidate" ), StatusCode::NO_CONTENT );
+    assert!( state.get_data( second_id ).is_none() );
+    assert_eq!( call( "GET", "/data/candidate/modules" ), StatusCode::NOT_FOUND );
+}
+
+#[test]
+fn test_list_summary() {
+    use actix_web::test;
+    use common::event::{AllocBody, Event};
+    use common::speedy::Writable;
+
+    let alloc = |secs: u64, pointer: u64, size: u64, thread: u32| Event::Alloc {
+        timestamp: Timestamp::from_secs( secs ),
+        allocation: AllocBody { thread, ..test_alloc_body( pointer, size, 1 ) }
+    };
+
+    let mut header = test_header( DataId::new( 1, 2 ), "test" );
+    header.pid = 1234;
+    header.cmdline = b"./test\0--foo\0".to_vec();
+
+    let mut fp = common::lz4_stream::Lz4Writer::new( Vec::new() );
+    Event::Header( header ).write_to_stream( &mut fp ).unwrap();
+    for event in [
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        alloc( 1, 0x1000, 100, 1 ),
+        alloc( 2, 0x2000, 200, 2 ),
+        Event::Free { timestamp: Timestamp::from_secs( 3 ), pointer: 0x1000, backtrace: 1, thread: 3 },
+        alloc( 4, 0x3000, 50, 1 ),
+        test_mmap( 5, 0x10000, 0x4000, 1 ),
+        test_mmap( 5, 0x20000, 0x1000, 1 ),
+        test_munmap( 6, 0x10000, 0x1000 )
+    ] {
+        event.write_to_stream( &mut fp ).unwrap();
+    }
+
+    let data = cli_core::Loader::load_from_stream_without_debug_info( io::Cursor::new( fp.into_inner().unwrap() ) ).unwrap();
+    let peak_timestamp: protocol::Timeval = data.get_allocation( data.alloc_sorted_by_timestamp( None, None )[ 1 ] ).timestamp.into();
+    let (_, mut app) = test_app( vec![ data ] );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let metadata = &response[ 0 ];
+    assert_eq!( metadata[ "peak_allocated" ], 300 );
+    assert_eq!( metadata[ "peak_timestamp" ], serde_json::to_value( peak_timestamp ).unwrap() );
+    assert_eq!( metadata[ "total_allocated" ], 350 );
+    assert_eq!( metadata[ "total_allocated_count" ], 3 );
+    assert_eq!( metadata[ "final_allocated" ], 250 );
+    assert_eq!( metadata[ "operation_count" ], 4 );
+    assert_eq!( metadata[ "thread_count" ], 3 );
+    assert_eq!( metadata[ "mmap_count" ], 2 );
+    assert_eq!( metadata[ "mmap_bytes" ], 0x5000 );
+    assert_eq!( metadata[ "munmap_count" ], 1 );
+    assert_eq!( metadata[ "munmap_bytes" ], 0x1000 );
+    assert_eq!( metadata[ "pid" ], 1234 );
+    assert_eq!( metadata[ "cmdline" ], serde_json::json!([ "./test", "--foo" ]) );
+}
+
+#[test]
+fn test_summary_is_kept_along_with_the_data() {
+    let id = DataId::new( 1, 2 );
+    let state = State::new();
+    assert!( state.get_summary( id ).is_none() );
+
+    state.add_data( load_test_data( id, vec![
+        common::event::Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_mmap( 1, 0x10000, 0x4000, 1 )
+    ] ) );
+    assert_eq!( state.get_summary( id ).unwrap().mmap_bytes, 0x4000 );
+
+    let old_data = state.get_data( id ).unwrap();
+    assert!( state.replace_data( &old_data, load_test_data( id, Vec::new() ) ) );
+    assert_eq!( state.get_summary( id ).unwrap().mmap_bytes, 0 );
+
+    assert!( state.remove_data( id ) );
+    assert!( state.get_summary( id ).is_none() );
+}
+
+#[test]
+fn test_merge() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use common::event::Event;
+
+    let first_id = DataId::new( 1, 2 );
+    let second_id = DataId::new( 3, 4 );
+    let (_, mut app) = test_app( vec![
+        load_test_data( first_id, vec![
+            Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+            test_alloc( 1, 0x10000, 10, 1 ),
+            test_alloc( 3, 0x20000, 30, 1 )
+        ]),
+        // The same backtrace has a different raw ID here, and the allocations are at the same addresses.
+        load_test_data( second_id, vec![
+            Event::Backtrace { id: 7, addresses: vec![ 0x1000 ].into() },
+            Event::Backtrace { id: 8, addresses: vec![ 0x2000 ].into() },
+            test_alloc( 2, 0x10000, 20, 7 ),
+            test_alloc( 2, 0x30000, 5, 8 )
+        ]),
+        cli_core::Loader::load_from_stream_without_debug_info( io::Cursor::new( serialize_test_data_of( DataId::new( 5, 6 ), "other", Vec::new() ) ) ).unwrap()
+    ]);
+
+    let merge = |ids: serde_json::Value| test::TestRequest::post().uri( "/data/merge" ).set_json( &serde_json::json!({ "ids": ids }) ).to_request();
+    let response = test::call_service( &mut app, merge( serde_json::json!([ format!( "{}", first_id ), "last" ]) ) );
+    assert_eq!( response.status(), StatusCode::BAD_REQUEST );
+    let body: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+    assert_eq!( body[ "error" ][ "code" ], "incompatible_data" );
+
+    let ids = serde_json::json!([ format!( "{}", first_id ), format!( "{}", second_id ) ]);
+    let response = test::call_service( &mut app, merge( ids.clone() ) );
+    assert_eq!( response.status(), StatusCode::CREATED );
+    let body: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+    assert_eq!( body[ "sources" ], ids );
+    assert_eq!( body[ "source_kind" ], "merge" );
+    assert!( body[ "source_path" ].is_null() );
+    assert_eq!( body[ "total_allocated_count" ], 4 );
+    assert_eq!( body[ "unique_backtrace_count" ], 2 );
+    let merged_id = body[ "id" ].as_str().unwrap().to_owned();
+
+    let response = test::call_service( &mut app, merge( ids.clone() ) );
+    assert_eq!( response.status(), StatusCode::CONFLICT );
+    test::read_body( response );
+
+    for ids in &[ serde_json::json!([ format!( "{}", first_id ) ]), serde_json::json!([ format!( "{}", first_id ), format!( "{}", first_id ) ]) ] {
+        let response = test::call_service( &mut app, merge( ids.clone() ) );
+        assert_eq!( response.status(), StatusCode::BAD_REQUEST );
+        test::read_body( response );
+    }
+
+    let response = test::call_service( &mut app, merge( serde_json::json!([ format!( "{}", first_id ), "00000000000000000000000000000000" ]) ) );
+    assert_eq!( response.status(), StatusCode::NOT_FOUND );
+    test::read_body( response );
+
+    let request = test::TestRequest::get().uri( &format!( "/data/{}/allocations?sort_by=timestamp&fields=size,address", merged_id ) ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let sizes: Vec< _ > = response[ "allocations" ].as_array().unwrap().iter().map( |allocation| allocation[ "size" ].as_u64().unwrap() ).collect();
+    assert_eq!( sizes, vec![ 10, 20, 5, 30 ] );
+
+    let request = test::TestRequest::get().uri( &format!( "/data/{}/allocation_groups?sort_by=all.size", merged_id ) ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 2 );
+    let mut groups: Vec< _ > = response[ "allocations" ].as_array().unwrap().iter().map( |group| {
+        (group[ "all" ][ "allocated_count" ].as_u64().unwrap(), group[ "all" ][ "size" ].as_u64().unwrap())
+    }).collect();
+    groups.sort();
+    assert_eq!( groups, vec![ (1, 5), (3, 60) ] );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let merged = response.as_array().unwrap().iter().find( |entry| entry[ "id" ] == merged_id.as_str() ).unwrap();
+    assert_eq!( merged[ "sources" ], ids );
+    assert!( response.as_array().unwrap().iter().filter( |entry| entry[ "id" ] != merged_id.as_str() ).all( |entry| entry.get( "sources" ).is_none() ) );
+}
+
+#[test]
+fn test_load_from_path() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+
+    let directory = std::env::temp_dir().join( format!( "bytehound-load-test-{}", std::process::id() ) );
+    let data_dir = directory.join( "captures" );
+    let outside_dir = directory.join( "outside" );
+    std::fs::create_dir_all( &data_dir ).unwrap();
+    std::fs::create_dir_all( &outside_dir ).unwrap();
+    std::fs::write( data_dir.join( "good.dat" ), serialize_test_data( DataId::new( 1, 2 ), Vec::new() ) ).unwrap();
+    std::fs::write( outside_dir.join( "secret.dat" ), serialize_test_data( DataId::new( 3, 4 ), Vec::new() ) ).unwrap();
+    let _ = std::os::unix::fs::symlink( outside_dir.join( "secret.dat" ), data_dir.join( "link.dat" ) );
+
+    let mut state = State::new();
+    state.data_dirs = vec![ data_dir.canonicalize().unwrap() ];
+    let state = Arc::new( state );
+    let mut app = test::init_service(
+        App::new().data( state.clone() )
+            .configure( configure_routes )
+    );
+
+    let mut load = |body: serde_json::Value| {
+        let request = test::TestRequest::post().uri( "/data/load" ).set_json( &body ).to_request();
+        let response = test::call_service( &mut app, request );
+        let status = response.status();
+        let body: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+        (status, body)
+    };
+
+    let (status, body) = load( serde_json::json!({ "path": "good.dat" }) );
+    assert_eq!( status, StatusCode::ACCEPTED );
+    assert_eq!( body[ "id" ], format!( "{}", DataId::new( 1, 2 ) ) );
+
+    let started = Instant::now();
+    while state.get_data( DataId::new( 1, 2 ) ).is_none() {
+        assert!( started.elapsed() < Duration::from_secs( 10 ) );
+        thread::sleep( Duration::from_millis( 10 ) );
+    }
+
+    let (status, _) = load( serde_json::json!({ "path": data_dir.join( "good.dat" ) }) );
+    assert_eq!( status, StatusCode::CONFLICT );
+
+    for path in &[
+        serde_json::json!( "../outside/secret.dat" ),
+        serde_json::json!( "../outside/nonexistent.dat" ),
+        serde_json::json!( outside_dir.join( "secret.dat" ) ),
+        serde_json::json!( data_dir.join( "../outside/secret.dat" ) ),
+        serde_json::json!( "link.dat" )
+    ] {
+        let (status, body) = load( serde_json::json!({ "path": path }) );
+        assert_eq!( status, StatusCode::FORBIDDEN, "{}", path );
+        assert_eq!( body[ "error" ][ "code" ], "forbidden" );
     }
+
+    let (status, _) = load( serde_json::json!({ "path": "nonexistent.dat" }) );
+    assert_eq!( status, StatusCode::NOT_FOUND );
+
+    let (status, _) = load( serde_json::json!({ "file": "good.dat" }) );
+    assert_eq!( status, StatusCode::BAD_REQUEST );
+
+    assert!( state.get_data( DataId::new( 3, 4 ) ).is_none() );
+    assert!( matches!( loading::resolve_data_path( &[], std::path::Path::new( "good.dat" ) ), Err( loading::LoadError::Disabled ) ) );
+
+    let _ = std::fs::remove_dir_all( &directory );
 }
 
-fn get_allocation_groups< 'a >(
-    state: &'a State,
-    data: &'a Arc< Data >,
-    backtrace_format: protocol::BacktraceFormat,
-    params: protocol::RequestAllocationGroups,
-    allocation_groups: Arc< AllocationGroups >
-) -> protocol::ResponseAllocationGroups< impl Serialize + 'a > {
-    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
-    let skip = params.skip.unwrap_or( 0 ) as usize;
-    let generate_graphs = params.generate_graphs.unwrap_or( false );
+#[test]
+fn test_load_range() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use common::event::Event;
+
+    // The window covers from 3.5s to 7s since the start of the profiling at 1s.
+    let events = vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 1, 0x1000, 10, 1 ),
+        test_alloc( 2, 0x2000, 20, 1 ),
+        test_free( 3, 0x2000, 1 ),
+        test_alloc( 4, 0x3000, 30, 1 ),
+        test_free( 5, 0x1000, 1 ),
+        test_alloc( 6, 0x4000, 40, 1 ),
+        test_free( 8, 0x4000, 1 ),
+        test_alloc( 9, 0x5000, 50, 1 )
+    ];
+
+    let directory = std::env::temp_dir().join( format!( "bytehound-load-range-test-{}", std::process::id() ) );
+    std::fs::create_dir_all( &directory ).unwrap();
+    std::fs::write( directory.join( "data.dat" ), serialize_test_data( DataId::new( 1, 2 ), events ) ).unwrap();
 
-    let total_count = allocation_groups.len();
-    let factory = move || {
-        let backtrace_format = backtrace_format.clone();
-        let allocations = allocation_groups.clone();
-        (0..allocations.allocations_by_backtrace.len())
-            .skip( skip )
-            .take( remaining )
-            .map( move |index| {
-                let (&backtrace_id, matched_allocation_ids) = allocations.allocations_by_backtrace.get( index );
-                let all = get_global_group_data( data, backtrace_id );
-                let mut only_matched = get_allocation_group_data( data, matched_allocation_ids.into_par_iter().map( |&allocation_id| data.get_allocation( allocation_id ) ) );
-                let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
+    let mut state = State::new();
+    state.data_dirs = vec![ directory.canonicalize().unwrap() ];
+    let state = Arc::new( state );
+    let mut app = test::init_service(
+        App::new().data( state.clone() )
+            .configure( configure_routes )
+    );
 
-                if generate_graphs {
-                    let code = format!( r#"
-                        let graph = graph()
-                            .add("Matched", allocations())
-                            .add("Global", data().allocations().only_matching_backtraces([{}]))
-                            .save()
-                            .without_axes()
-                            .without_legend()
-                            .save();
-                    "#, backtrace_id.raw() );
+    let request = test::TestRequest::post().uri( "/data/load" ).set_json( &serde_json::json!({ "path": "data.dat", "range": "..1" }) ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::ACCEPTED );
+    test::read_body( response );
 
-                    let args = cli_core::script::EngineArgs {
-                        data: Some( data.clone() ),
-                        allocation_ids: Some( Arc::new( matched_allocation_ids.to_owned() ) ),
-                        .. cli_core::script::EngineArgs::default()
-                    };
+    let request = test::TestRequest::post().uri( "/data/load" ).set_json( &serde_json::json!({ "path": "data.dat", "range": "6..2" }) ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::BAD_REQUEST );
+    test::read_body( response );
 
-                    let env = Arc::new( Mutex::new( cli_core::script::VirtualEnvironment::new() ) );
-                    let engine = cli_core::script::Engine::new( env.clone(), args );
-                    engine.run( &code ).unwrap();
-                    let mut urls = Vec::new();
-                    let files = std::mem::take( &mut env.lock().output );
-                    for file in files {
-                        match file {
-                            cli_core::script::ScriptOutputKind::Image { path, data: bytes } => {
-                                let hash = format!( "{:x}", md5::compute( &*bytes ) );
-                                let basename = path[ path.rfind( "/" ).unwrap() + 1.. ].to_owned();
-                                let url = format!( "/data/{}/script_files/{}/{}", data.id(), hash, basename );
-                                let entry = GeneratedFile {
-                                    timestamp: Instant::now(),
-                                    hash,
-                                    mime: "image/svg+xml",
-                                    data: bytes
-                                };
-
-                                let mut generated = state.generated_files.lock();
-                                generated.purge_old_if_too_big();
-                                generated.add_file( entry );
-
-                                urls.push( url );
-                            },
-                            _ => {}
-                        }
-                    }
+    let started = Instant::now();
+    while state.get_data( DataId::new( 1, 2 ) ).is_none() {
+        assert!( started.elapsed() < Duration::from_secs( 10 ) );
+        thread::sleep( Duration::from_millis( 10 ) );
+    }
 
-                    let mut urls = urls.into_iter();
-                    only_matched.graph_url = urls.next();
-                    only_matched.graph_preview_url = urls.next();
-                }
+    // Only the very first allocation was made during the first second.
+    assert_eq!( state.get_data( DataId::new( 1, 2 ) ).unwrap().allocations_with_id().count(), 1 );
+    state.remove_data( DataId::new( 1, 2 ) );
 
-                protocol::AllocationGroup {
-                    all,
-                    only_matched,
-                    backtrace_id: backtrace_id.raw(),
-                    backtrace
-                }
-            })
-    };
+    let request = test::TestRequest::post().uri( "/data/load" ).set_json( &serde_json::json!({ "path": "data.dat", "range": "2500ms..6" }) ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::ACCEPTED );
+    test::read_body( response );
 
-    let response = protocol::ResponseAllocationGroups {
-        allocations: StreamingSerializer::new( factory ),
-        total_count: total_count as _
-    };
+    let started = Instant::now();
+    while state.get_data( DataId::new( 1, 2 ) ).is_none() {
+        assert!( started.elapsed() < Duration::from_secs( 10 ) );
+        thread::sleep( Duration::from_millis( 10 ) );
+    }
 
-    response
+    let request = test::TestRequest::get().uri( "/data/last/allocations?sort_by=timestamp&fields=size,is_partially_observed,deallocation" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let allocations: Vec< _ > = response[ "allocations" ].as_array().unwrap().iter().map( |allocation| {
+        (allocation[ "size" ].as_u64().unwrap(), allocation[ "is_partially_observed" ].as_bool().unwrap(), allocation[ "deallocation" ].is_object())
+    }).collect();
+    assert_eq!( allocations, vec![ (10, true, true), (30, false, false), (40, true, true) ] );
+
+    let _ = std::fs::remove_dir_all( &directory );
 }
 
-fn handler_allocation_groups( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter_params: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter_params, &custom_filter )?;
-    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
-    let params: protocol::RequestAllocationGroups = query( &req )?;
+#[test]
+fn test_live_profiles() {
+    use actix_web::test;
+    use common::event::Event;
+    use common::speedy::Writable;
+    use std::io::Write;
 
-    let key = AllocationGroupsKey {
-        data_id: data.id(),
-        filter: filter_params,
-        custom_filter,
-        sort_by: params.sort_by.unwrap_or( protocol::AllocGroupsSortBy::MinTimestamp ),
-        order: params.order.unwrap_or( protocol::Order::Asc )
+    let state = Arc::new( State::new() );
+    let listener = std::net::TcpListener::bind( "127.0.0.1:0" ).unwrap();
+    let address = listener.local_addr().unwrap();
+    live::listen( state.clone(), listener, Duration::from_millis( 0 ) );
+
+    let mut app = test::init_service(
+        App::new().data( state.clone() )
+            .configure( configure_routes )
+    );
+
+    let id = DataId::new( 1, 2 );
+    let wait_until = |condition: &dyn Fn() -> bool| {
+        let started = Instant::now();
+        while !condition() {
+            assert!( started.elapsed() < Duration::from_secs( 10 ) );
+            thread::sleep( Duration::from_millis( 10 ) );
+        }
     };
 
-    let groups = req.state().allocation_group_cache.lock().get( &key ).cloned();
+    let allocation_count = || state.get_data( id ).map( |data| data.allocations_with_id().count() );
+
+    let mut fp = common::lz4_stream::Lz4Writer::new( std::net::TcpStream::connect( address ).unwrap() );
+    Event::Header( test_header( id, "test" ) ).write_to_stream( &mut fp ).unwrap();
+    Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() }.write_to_stream( &mut fp ).unwrap();
+    test_alloc( 2, 0x1000, 100, 1 ).write_to_stream( &mut fp ).unwrap();
+    fp.flush().unwrap();
+    wait_until( &|| allocation_count() == Some( 1 ) );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ 0 ][ "live" ], true );
+
+    let request = test::TestRequest::get().uri( "/data/last/timeline" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "allocated_count" ].as_array().unwrap().last().unwrap(), 1 );
+
+    test_alloc( 3, 0x2000, 100, 1 ).write_to_stream( &mut fp ).unwrap();
+    fp.flush().unwrap();
+    wait_until( &|| allocation_count() == Some( 2 ) );
+
+    let request = test::TestRequest::get().uri( "/data/last/timeline" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "allocated_count" ].as_array().unwrap().last().unwrap(), 2 );
+    assert!( state.is_live( id ) );
+
+    // The data stays around once the stream ends.
+    drop( fp.into_inner().unwrap() );
+    wait_until( &|| !state.is_live( id ) );
+    assert_eq!( allocation_count(), Some( 2 ) );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response.as_array().unwrap().len(), 1 );
+    assert!( response[ 0 ].get( "live" ).is_none() );
+}
 
-    fn sort_by< T, F >( data: &Data, groups: &mut AllocationGroups, order: protocol::Order, is_global: bool, callback: F )
-        where F: Fn( &protocol::AllocationGroupData ) -> T + Send + Sync,
-              T: Ord + Send + Sync
-    {
-        if is_global {
-            groups.allocations_by_backtrace.par_sort_by_key( |(&backtrace_id, _)| {
-                let group_data = get_global_group_data( data, backtrace_id );
-                callback( &group_data )
-            });
-        } else {
-            let key_for_backtrace: Vec< _ > =
-                groups.allocations_by_backtrace.par_iter().map( |(&backtrace_id, ids)| {
-                    let allocations = ids.par_iter().map( |&id| data.get_allocation( id ) );
-                    let group_data = get_allocation_group_data( data, allocations );
-                    (backtrace_id, callback( &group_data ))
-                }).collect();
+#[test]
+fn test_live_profiles_authentication_and_connection_limit() {
+    use common::event::Event;
+    use common::speedy::Writable;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
 
-            let key_for_backtrace: HashMap< _, _ > = key_for_backtrace.into_iter().collect();
-            groups.allocations_by_backtrace.par_sort_by_key( |(&backtrace_id, _)| {
-                key_for_backtrace.get( &backtrace_id ).unwrap().clone()
-            });
+    let mut state = State::new();
+    state.auth_token = Some( "secret".into() );
+    let state = Arc::new( state );
+    let listener = std::net::TcpListener::bind( "127.0.0.1:0" ).unwrap();
+    let address = listener.local_addr().unwrap();
+    live::listen( state.clone(), listener, Duration::from_millis( 0 ) );
+
+    let wait_until = |condition: &dyn Fn() -> bool| {
+        let started = Instant::now();
+        while !condition() {
+            assert!( started.elapsed() < Duration::from_secs( 10 ) );
+            thread::sleep( Duration::from_millis( 10 ) );
         }
+    };
 
-        match order {
-            protocol::Order::Asc => {},
-            protocol::Order::Dsc => {
-                groups.allocations_by_backtrace.reverse();
-            }
-        }
-    }
+    let send = |token: &str, id: DataId| {
+        let mut stream = TcpStream::connect( address ).unwrap();
+        stream.write_all( format!( "{}\n", token ).as_bytes() ).unwrap();
+        let mut fp = common::lz4_stream::Lz4Writer::new( stream );
+        Event::Header( test_header( id, "test" ) ).write_to_stream( &mut fp ).unwrap();
+        drop( fp.into_inner() );
+    };
 
-    let allocation_groups;
-    if let Some( groups ) = groups {
-        allocation_groups = groups;
-    } else {
-        let iter = prefiltered_allocation_ids( data, Default::default(), &filter )
-            .par_iter()
-            .map( |&allocation_id| (allocation_id, data.get_allocation( allocation_id )) )
-            .filter( move |(id, allocation)| filter.try_match( data, *id, allocation ) );
+    // A connection with the wrong token is ignored...
+    send( "wrong", DataId::new( 1, 2 ) );
+    // ...while the one with the right token is accepted.
+    send( "secret", DataId::new( 1, 3 ) );
+    wait_until( &|| state.get_data( DataId::new( 1, 3 ) ).is_some() );
+    assert!( state.get_data( DataId::new( 1, 2 ) ).is_none() );
+
+    // These never send anything, so they use up every connection.
+    let idle: Vec< _ > = (0..16).map( |_| TcpStream::connect( address ).unwrap() ).collect();
+    let mut rejected = TcpStream::connect( address ).unwrap();
+    rejected.set_read_timeout( Some( Duration::from_secs( 5 ) ) ).unwrap();
+    assert_eq!( rejected.read( &mut [0; 1] ).unwrap(), 0 );
+    drop( idle );
+}
 
-        let mut groups = AllocationGroups::new( iter );
-        match key.sort_by {
-            protocol::AllocGroupsSortBy::MinTimestamp => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.min_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::MaxTimestamp => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.max_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::Interval => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.interval.clone() );
-            },
-            protocol::AllocGroupsSortBy::AllocatedCount => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.allocated_count );
-            },
-            protocol::AllocGroupsSortBy::LeakedCount => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.leaked_count );
-            },
-            protocol::AllocGroupsSortBy::Size => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.size );
-            },
-            protocol::AllocGroupsSortBy::GlobalMinTimestamp => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.min_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::GlobalMaxTimestamp => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.max_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::GlobalInterval => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.interval.clone() );
-            },
-            protocol::AllocGroupsSortBy::GlobalAllocatedCount => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.allocated_count );
-            },
-            protocol::AllocGroupsSortBy::GlobalLeakedCount => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.leaked_count );
-            },
-            protocol::AllocGroupsSortBy::GlobalSize => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.size );
-            },
-            protocol::AllocGroupsSortBy::GlobalMaxTotalUsageFirstSeenAt => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.max_total_usage_first_seen_at.clone() );
-            }
-        }
+#[test]
+fn test_heavy_request_queue_limit() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use std::sync::mpsc;
+
+    let mut state = State::new();
+    state.heavy_workers = worker_pool::WorkerPool::new( 1, 1 );
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
+    let state = Arc::new( state );
 
-        allocation_groups = Arc::new( groups );
-        req.state().allocation_group_cache.lock().put( key, allocation_groups.clone() );
+    let mut app = test::init_service( App::new().data( state.clone() ).configure( configure_routes ) );
+
+    // Occupy the only worker and fill up the queue.
+    let (blocker_tx, blocker_rx) = mpsc::channel::< () >();
+    let (started_tx, started_rx) = mpsc::channel();
+    assert!( state.heavy_workers.spawn( move || {
+        started_tx.send( () ).unwrap();
+        let _ = blocker_rx.recv();
+    }).is_ok() );
+    started_rx.recv().unwrap();
+    let (queued_tx, queued_rx) = mpsc::channel();
+    assert!( state.heavy_workers.spawn( move || queued_tx.send( () ).unwrap() ).is_ok() );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::SERVICE_UNAVAILABLE );
+    assert_eq!( response.headers().get( "Retry-After" ).unwrap(), "5" );
+
+    for uri in &[
+        "/data/last/timeline_temporary",
+        "/data/last/peak",
+        "/compare/timeline?a=last&b=last",
+        "/data/last/size_histogram",
+        "/data/last/backtrace_clusters",
+        "/data/last/backtraces/containing_address/0x1000",
+        "/data/last/symbolize?addresses=0x1000",
+        "/data/last/mmap_leaks",
+        "/data/last/mmap_regions",
+        "/data/last/mmap_ascii_tree"
+    ] {
+        let request = test::TestRequest::get().uri( uri ).to_request();
+        assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::SERVICE_UNAVAILABLE, "{}", uri );
     }
 
-    let state = req.state().clone();
-    let body = async_data_handler( &req, move |data, tx| {
-        let response = get_allocation_groups( &state, &data, backtrace_format, params, allocation_groups );
-        let _ = serde_json::to_writer( tx, &response );
-    })?;
+    let request = test::TestRequest::post().uri( "/data/last/execute_script_async" ).set_payload( "println(1);" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::SERVICE_UNAVAILABLE );
+    assert!( state.script_jobs.lock().by_id.is_empty() );
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+    // The lightweight handlers don't go through the queue.
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
+
+    drop( blocker_tx );
+    queued_rx.recv().unwrap();
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 0 );
 }
 
-fn handler_raw_allocations( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let iter = data.alloc_sorted_by_timestamp( None, None ).iter().map( |&id| data.get_allocation( id ) );
+#[test]
+fn test_base_path() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    let mut output = String::new();
-    output.push_str( "[" );
+    assert_eq!( normalize_base_path( "/" ).unwrap(), "" );
+    assert_eq!( normalize_base_path( "/bytehound/" ).unwrap(), "/bytehound" );
+    assert_eq!( normalize_base_path( "/a/b" ).unwrap(), "/a/b" );
+    assert!( normalize_base_path( "bytehound" ).is_err() );
+    assert!( normalize_base_path( "/a//b" ).is_err() );
+    assert!( normalize_base_path( "/a/../b" ).is_err() );
 
-    let mut is_first = true;
-    for allocation in iter {
-        if !is_first {
-            output.push_str( "," );
-        } else {
-            is_first = false;
-        }
+    let mut state = State::new();
+    state.base_path = "/bytehound".into();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
+    let state = Arc::new( state );
 
-        output.push_str( "{\"backtrace\":[" );
-        let mut is_first = true;
-        for (_, frame) in data.get_backtrace( allocation.backtrace ) {
-            if !is_first {
-                output.push_str( "," );
-            } else {
-                is_first = false;
-            }
+    let mut app = test::init_service(
+        App::new().data( state.clone() )
+            .configure( |app| configure_routes_under( "/bytehound", app ) )
+    );
 
-            let address = frame.address().raw();
-            write!( output, "\"{:016X}\"", address ).unwrap();
-        }
-        output.push_str( "]}" );
-    }
+    let request = test::TestRequest::get().uri( "/bytehound?token=1234" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::MOVED_PERMANENTLY );
+    assert_eq!( response.headers().get( "Location" ).unwrap(), "/bytehound/?token=1234" );
 
-    output.push_str( "]" );
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( output ) )
-}
+    let request = test::TestRequest::get().uri( "/bytehound/" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
 
-fn dump_node< T: fmt::Write, K: PartialEq + Clone, V, F: Fn( &mut T, &V ) -> fmt::Result >(
-    tree: &Tree< K, V >,
-    node_id: NodeId,
-    output: &mut T,
-    printer: &mut F
-) -> fmt::Result {
-    write!( output, "{{" )?;
+    let request = test::TestRequest::get().uri( "/bytehound/list" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response.as_array().unwrap().len(), 1 );
 
-    let node = tree.get_node( node_id );
-    write!( output, "\"size\":{},", node.total_size )?;
-    write!( output, "\"count\":{},", node.total_count )?;
-    write!( output, "\"first\":{},", node.total_first_timestamp.as_secs() )?;
-    write!( output, "\"last\":{},", node.total_last_timestamp.as_secs() )?;
-    if let Some( value ) = node.value() {
-        write!( output, "\"value\":" )?;
-        printer( output, value )?;
-        write!( output, "," )?;
-    }
+    let request = test::TestRequest::get().uri( "/bytehound/config.json" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "base_path" ], "/bytehound" );
 
-    write!( output, "\"children\":[" )?;
-    for (index, &(_, child_id)) in tree.get_node( node_id ).children.iter().enumerate() {
-        if index != 0 {
-            write!( output, "," )?;
-        }
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
 
-        dump_node( tree, child_id, output, printer )?;
-    }
-    write!( output, "]" )?;
+    let output = script_output_to_json( &state, DataId::new( 1, 2 ), vec![
+        cli_core::script::ScriptOutputKind::File { path: "/foo.txt".into(), mime: "text/plain", data: Arc::new( b"foo".to_vec() ) }
+    ]);
 
-    write!( output, "}}" )?;
-    Ok(())
+    let url = output[ 0 ][ "url" ].as_str().unwrap().to_owned();
+    assert!( url.starts_with( "/bytehound/data/" ) );
+
+    let request = test::TestRequest::get().uri( &url ).to_request();
+    assert_eq!( &test::read_response( &mut app, request )[..], b"foo" );
 }
 
-fn handler_tree( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter, &custom_filter )?;
-    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+#[test]
+fn test_error_responses() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    let body = async_data_handler( &req, move |data, mut tx| {
-        let mut tree: Tree< FrameId, &Frame > = Tree::new();
-        for (allocation_id, allocation) in data.allocations_with_id() {
-            if !filter.try_match( &data, allocation_id, allocation ) {
-                continue;
-            }
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
 
-            tree.add_allocation( allocation, allocation_id, data.get_backtrace( allocation.backtrace ) );
-        }
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .wrap_fn( api_error::middleware )
+            .configure( configure_routes )
+    );
 
-        dump_node( &tree, 0, &mut tx, &mut |output, frame| {
-            let frame = get_frame( &data, &backtrace_format, frame );
-            serde_json::to_writer( output, &frame ).map_err( |_| fmt::Error )
-        }).unwrap();
-    })?;
+    let mut call = |uri: &str| {
+        let request = test::TestRequest::get().uri( uri ).to_request();
+        let response = test::call_service( &mut app, request );
+        let status = response.status();
+        assert_eq!( response.headers().get( "Content-Type" ).unwrap(), "application/json", "{}", uri );
+        let body: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+        assert!( body[ "error" ][ "message" ].is_string(), "{}", body );
+        (status, body[ "error" ].clone())
+    };
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+    let (status, error) = call( "/data/1234/allocations" );
+    assert_eq!( status, StatusCode::NOT_FOUND );
+    assert_eq!( error[ "code" ], "not_found" );
+    assert!( error.get( "field" ).is_none() );
+
+    let (status, error) = call( "/data/last/allocations?function_regex=(" );
+    assert_eq!( status, StatusCode::BAD_REQUEST );
+    assert_eq!( error[ "code" ], "invalid_parameter" );
+    assert_eq!( error[ "field" ], "function_regex" );
+
+    let (status, error) = call( "/data/last/allocations?custom_filter=this+is+not+valid" );
+    assert_eq!( status, StatusCode::BAD_REQUEST );
+    assert_eq!( error[ "code" ], "invalid_custom_filter" );
+    assert_eq!( error[ "field" ], "custom_filter" );
+
+    let (status, error) = call( "/data/last/allocations?size_min=abc" );
+    assert_eq!( status, StatusCode::BAD_REQUEST );
+    assert_eq!( error[ "code" ], "bad_request" );
+
+    let (status, error) = call( "/data/last/backtrace_clusters?threshold=2" );
+    assert_eq!( status, StatusCode::BAD_REQUEST );
+    assert_eq!( error[ "code" ], "invalid_parameter" );
+    assert_eq!( error[ "field" ], "threshold" );
+
+    // The errors which don't originate from our handlers are converted too.
+    let (status, error) = call( "/this/does/not/exist" );
+    assert_eq!( status, StatusCode::NOT_FOUND );
+    assert_eq!( error[ "code" ], "not_found" );
 }
 
-fn handler_mmaps( req: HttpRequest ) -> Result< HttpResponse > {
-    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
-    let filter: protocol::MmapFilter = query( &req )?;
-    let body = async_data_handler( &req, move |data, tx| {
-        let factory = || {
-            data.mmap_operations().iter().flat_map( |op| {
-                match *op {
-                    MmapOperation::Mmap( MemoryMap {
-                        timestamp,
-                        pointer,
-                        length,
-                        backtrace: backtrace_id,
-                        requested_address,
-                        mmap_protection,
-                        mmap_flags,
-                        file_descriptor,
-                        thread,
-                        offset
-                    }) => {
-                        if let Some( min ) = filter.size_min {
-                            if length < min {
-                                return None;
-                            }
-                        }
-                        if let Some( max ) = filter.size_max {
-                            if length > max {
-                                return None;
-                            }
-                        }
-                        let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( &data, &backtrace_format, frame ) ).collect();
-                        Some( protocol::MmapOperation::Mmap {
-                            timestamp: timestamp.into(),
-                            pointer,
-                            pointer_s: format!( "{:016}", pointer ),
-                            length,
-                            backtrace,
-                            backtrace_id: backtrace_id.raw(),
-                            requested_address,
-                            requested_address_s: format!( "{:016}", requested_address ),
-                            is_readable: mmap_protection.is_readable(),
-                            is_writable: mmap_protection.is_writable(),
-                            is_executable: mmap_protection.is_executable(),
-                            is_semaphore: mmap_protection.is_semaphore(),
-                            grows_down: mmap_protection.grows_down(),
-                            grows_up: mmap_protection.grows_up(),
-                            is_shared: mmap_flags.is_shared(),
-                            is_private: mmap_flags.is_private(),
-                            is_fixed: mmap_flags.is_fixed(),
-                            is_anonymous: mmap_flags.is_anonymous(),
-                            is_uninitialized: mmap_flags.is_uninitialized(),
-                            offset,
-                            file_descriptor: file_descriptor as i32,
-                            thread
-                        })
-                    },
-                    MmapOperation::Munmap( MemoryUnmap {
-                        timestamp,
-                        pointer,
-                        length,
-                        backtrace: backtrace_id,
-                        thread
-                    }) => {
-                        if let Some( min ) = filter.size_min {
-                            if length < min {
-                                return None;
-                            }
-                        }
-                        if let Some( max ) = filter.size_max {
-                            if length > max {
-                                return None;
-                            }
-                        }
-                        let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( &data, &backtrace_format, frame ) ).collect();
-                        Some(protocol::MmapOperation::Munmap {
-                            timestamp: timestamp.into(),
-                            pointer,
-                            pointer_s: format!( "{:016}", pointer ),
-                            length,
-                            backtrace,
-                            backtrace_id: backtrace_id.raw(),
-                            thread
-                        })
-                    }
-                }
-            })
-        };
+#[test]
+fn test_deadline_ms() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-        let response = protocol::ResponseMmaps {
-            operations: StreamingSerializer::new( factory )
-        };
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
 
-        let _ = serde_json::to_writer( tx, &response );
-    })?;
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .wrap_fn( deadline::middleware )
+            .configure( configure_routes )
+    );
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+    let mut call = |uri: &str| {
+        let request = test::TestRequest::get().uri( uri ).to_request();
+        let response = test::call_service( &mut app, request );
+        let status = response.status();
+        let body = test::read_body( response );
+        (status, body)
+    };
+
+    for uri in &[ "/data/last/allocations", "/data/last/allocation_groups", "/data/last/export/flamegraph.pl" ] {
+        let (status, body) = call( &format!( "{}?deadline_ms=0", uri ) );
+        assert_eq!( status, StatusCode::SERVICE_UNAVAILABLE, "{}", uri );
+        let body: serde_json::Value = serde_json::from_slice( &body ).unwrap();
+        assert_eq!( body[ "error" ][ "code" ], "deadline_exceeded" );
+
+        let (status, _) = call( &format!( "{}?deadline_ms=60000", uri ) );
+        assert_eq!( status, StatusCode::OK, "{}", uri );
+
+        let (status, _) = call( uri );
+        assert_eq!( status, StatusCode::OK, "{}", uri );
+    }
+
+    // Here the deadline only expires while the custom filter is already running.
+    for uri in &[ "/data/last/allocations", "/data/last/allocation_groups" ] {
+        let started = Instant::now();
+        let (status, body) = call( &format!( "{}?deadline_ms=100&custom_filter=loop%20%7B%7D", uri ) );
+        assert!( started.elapsed() >= Duration::from_millis( 100 ) );
+        assert_eq!( status, StatusCode::SERVICE_UNAVAILABLE, "{}", uri );
+        let body: serde_json::Value = serde_json::from_slice( &body ).unwrap();
+        assert_eq!( body[ "error" ][ "code" ], "deadline_exceeded" );
+    }
 }
 
-fn handler_backtrace( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let backtrace_id: u32 = req.match_info().get( "backtrace_id" ).unwrap().parse().unwrap();
-    let backtrace_id = BacktraceId::new( backtrace_id );
-    let backtrace = data.get_backtrace( backtrace_id );
-    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+#[test]
+fn test_post_parameters() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use common::event::Event;
 
-    let mut frames = Vec::new();
-    for (_, frame) in backtrace {
-        frames.push( get_frame( data, &backtrace_format, frame ) );
+    let (_, mut app) = test_app( vec![ load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 ),
+        test_alloc( 3, 0x20000, 32, 1 ),
+        test_alloc( 4, 0x30000, 64, 1 )
+    ])]);
+
+    let query = "size_max=48&custom_filter=allocations().only_larger(20)&collapse_inlined=true&count=10";
+    let body = serde_json::json! {{
+        "filter": { "size_max": 48 },
+        "custom_filter": "allocations().only_larger(20)",
+        "backtrace_format": { "collapse_inlined": true },
+        "params": { "count": 10 }
+    }};
+
+    for endpoint in &[ "allocations", "allocation_groups", "backtraces", "tree", "export/flamegraph.pl", "export/replay" ] {
+        let request = test::TestRequest::get().uri( &format!( "/data/last/{}?{}", endpoint, query ) ).to_request();
+        let response = test::call_service( &mut app, request );
+        assert_eq!( response.status(), StatusCode::OK, "{}", endpoint );
+        let expected = test::read_body( response );
+
+        let request = test::TestRequest::post().uri( &format!( "/data/last/{}", endpoint ) ).set_json( &body ).to_request();
+        let response = test::call_service( &mut app, request );
+        assert_eq!( response.status(), StatusCode::OK, "{}", endpoint );
+        assert_eq!( test::read_body( response ), expected, "{}", endpoint );
     }
 
-    let response = protocol::ResponseBacktrace {
-        frames
-    };
+    let request = test::TestRequest::post().uri( "/data/last/allocations" ).set_json( &body ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "allocations" ].as_array().unwrap().len(), 1 );
+    assert_eq!( response[ "allocations" ][ 0 ][ "size" ], 32 );
 
-    Ok( HttpResponse::Ok().json( response ) )
+    let request = test::TestRequest::post().uri( "/data/last/allocations" ).set_payload( "{" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
+
+    let request = test::TestRequest::post().uri( "/data/last/allocations" ).set_json( &serde_json::json! {{ "filter": { "size_max": "foo" } }} ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
 }
 
-fn handler_backtraces( req: HttpRequest ) -> Result< HttpResponse > {
-    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
-    let filter: protocol::BacktraceFilter = query( &req )?;
-    let filter = crate::filter::prepare_backtrace_filter( &filter )?;
-    let body = async_data_handler( &req, move |data, tx| {
-        let mut positive_cache = HashMap::new();
-        let mut negative_cache = HashMap::new();
-        let total_count = data.all_backtraces().flat_map( |(_, backtrace)| {
-            if !crate::filter::match_backtrace( &data, &mut positive_cache, &mut negative_cache, &filter, backtrace ) {
-                None
-            } else {
-                Some(())
-            }
-        }).count();
+#[test]
+fn test_batch_counts() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use common::event::Event;
+
+    let (_, mut app) = test_app( vec![ load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x1000, 10, 1 ),
+        test_alloc( 2, 0x2000, 20, 1 ),
+        test_alloc( 2, 0x3000, 100, 1 ),
+        test_alloc( 2, 0x4000, 200, 1 ),
+        test_free( 3, 0x1000, 1 ),
+        test_free( 3, 0x3000, 1 )
+    ])]);
+
+    let body = serde_json::json!([
+        { "name": "all" },
+        { "name": "big", "filter": { "size_min": 50 } },
+        { "name": "leaked", "custom_filter": "allocations().only_leaked()" },
+        { "name": "invalid_regex", "filter": { "function_regex": "(" } },
+        { "name": "invalid_script", "custom_filter": "this is not valid" }
+    ]);
+
+    let request = test::TestRequest::post().uri( "/data/last/batch_counts" ).set_json( &body ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let entries = response.as_array().unwrap();
+    assert_eq!( entries.len(), 5 );
+    assert_eq!( entries[ 0 ], serde_json::json!({ "name": "all", "matched_count": 4, "matched_size": 330, "leaked_count": 2, "leaked_size": 220 }) );
+    assert_eq!( entries[ 1 ], serde_json::json!({ "name": "big", "matched_count": 2, "matched_size": 300, "leaked_count": 1, "leaked_size": 200 }) );
+    assert_eq!( entries[ 2 ], serde_json::json!({ "name": "leaked", "matched_count": 2, "matched_size": 220, "leaked_count": 2, "leaked_size": 220 }) );
+
+    // An invalid filter doesn't fail the whole batch.
+    assert_eq!( entries[ 3 ][ "name" ], "invalid_regex" );
+    assert_eq!( entries[ 3 ][ "error" ][ "code" ], "invalid_parameter" );
+    assert!( entries[ 3 ].get( "matched_count" ).is_none() );
+    assert_eq!( entries[ 4 ][ "error" ][ "code" ], "invalid_custom_filter" );
+
+    let request = test::TestRequest::post().uri( "/data/last/batch_counts" ).set_json( &serde_json::json!({ "name": "all" }) ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
+}
 
-        let data = &data;
-        let backtraces = move || {
-            let mut positive_cache = positive_cache.clone();
-            let mut negative_cache = negative_cache.clone();
-            let backtrace_format = backtrace_format.clone();
-            let filter = filter.clone();
-            data.all_backtraces().flat_map( move |(_, backtrace)| {
-                if !crate::filter::match_backtrace( &data, &mut positive_cache, &mut negative_cache, &filter, backtrace.clone() ) {
-                    return None;
-                }
+/// Rounds every float to `f32`, since in JSON they're serialized in a way that doesn't round-trip exactly.
+#[cfg(test)]
+fn round_floats( value: serde_json::Value ) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::Number( ref number ) if number.is_f64() => serde_json::json!( number.as_f64().unwrap() as f32 ),
+        Value::Array( list ) => Value::Array( list.into_iter().map( round_floats ).collect() ),
+        Value::Object( map ) => Value::Object( map.into_iter().map( |(key, value)| (key, round_floats( value )) ).collect() ),
+        value => value
+    }
+}
 
-                let mut frames = Vec::new();
-                for (_, frame) in backtrace {
-                    frames.push( get_frame( &data, &backtrace_format, frame ) );
-                }
-                Some( frames )
-            })
-        };
+#[test]
+fn test_msgpack_responses() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use common::event::Event;
+
+    let (_, mut app) = test_app( vec![ load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 ),
+        test_alloc( 3, 0x20000, 32, 1 ),
+        test_alloc( 4, 0x30000, 64, 1 )
+    ])]);
+
+    for endpoint in &[ "allocations?count=2&skip=1", "allocation_groups", "backtraces", "tree", "tree?collapse_inlined=true" ] {
+        let request = test::TestRequest::get().uri( &format!( "/data/last/{}", endpoint ) ).to_request();
+        let response = test::call_service( &mut app, request );
+        assert_eq!( response.status(), StatusCode::OK, "{}", endpoint );
+        assert_eq!( response.headers().get( "Content-Type" ).unwrap(), "application/json" );
+        let expected: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+
+        let request = test::TestRequest::get().uri( &format!( "/data/last/{}", endpoint ) ).header( "Accept", "application/msgpack" ).to_request();
+        let response = test::call_service( &mut app, request );
+        assert_eq!( response.status(), StatusCode::OK, "{}", endpoint );
+        assert_eq!( response.headers().get( "Content-Type" ).unwrap(), "application/msgpack" );
+        let body = test::read_body( response );
+        let value: serde_json::Value = rmp_serde::from_slice( &body ).unwrap();
+        assert_eq!( round_floats( value ), round_floats( expected ), "{}", endpoint );
+
+        let separator = if endpoint.contains( '?' ) { '&' } else { '?' };
+        let request = test::TestRequest::get().uri( &format!( "/data/last/{}{}format=msgpack", endpoint, separator ) ).to_request();
+        let response = test::call_service( &mut app, request );
+        assert_eq!( test::read_body( response ), body, "{}", endpoint );
+    }
 
-        let response = protocol::ResponseBacktraces {
-            backtraces: StreamingSerializer::new( backtraces ),
-            total_count: total_count as u64
-        };
+    let request = test::TestRequest::get().uri( "/data/last/allocations?format=xml" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
+}
 
-        let _ = serde_json::to_writer( tx, &response );
-    })?;
+#[test]
+fn test_allocation_fields() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use common::event::Event;
+
+    let (_, mut app) = test_app( vec![ load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 )
+    ])]);
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?fields=id,size,backtrace_id" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( response[ "total_count" ], 1 );
+    assert_eq!( response[ "allocations" ][ 0 ], serde_json::json!({ "id": 0, "size": 16, "backtrace_id": 0 }) );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations" ).to_request();
+    let response: serde_json::Value = test::read_response_json( &mut app, request );
+    let allocation = response[ "allocations" ][ 0 ].as_object().unwrap();
+    assert!( allocation.contains_key( "backtrace" ) );
+    assert!( allocation.contains_key( "address_s" ) );
+    assert!( allocation.contains_key( "chain_length" ) );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?fields=id,foo" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::BAD_REQUEST );
+    let body: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+    let message = body[ "error" ][ "message" ].as_str().unwrap();
+    assert!( message.contains( "unknown field 'foo'" ), "{}", message );
+    assert!( message.contains( "backtrace_id" ), "{}", message );
+}
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+#[test]
+fn test_ranges_and_head_requests() {
+    use actix_web::test;
+    use actix_web::http::{Method, StatusCode};
+
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
+    let entry = state.generated_files.lock().add_file( GeneratedFile::new( DataId::new( 1, 2 ), "text/plain", Arc::new( b"0123456789".to_vec() ) ) );
+    let url = entry.url( "", "file.txt" );
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service(
+                web::resource( "/data/{id}/script_files/{hash}/{filename}" )
+                    .route( web::get().to( handler_script_files ) )
+                    .route( web::head().to( handler_script_files ) )
+            )
+            .service(
+                web::resource( "/data/{id}/export/heaptrack" )
+                    .route( web::get().to( handler_export_heaptrack ) )
+                    .route( web::head().to( |req| handler_export_head( req, ExportFormat::Heaptrack ) ) )
+            )
+    );
+
+    let request = test::TestRequest::get().uri( &url ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::OK );
+    assert_eq!( response.headers().get( "Accept-Ranges" ).unwrap(), "bytes" );
+    assert_eq!( &test::read_body( response )[..], b"0123456789" );
+
+    let request = test::TestRequest::get().uri( &url ).header( "Range", "bytes=2-4" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::PARTIAL_CONTENT );
+    assert_eq!( response.headers().get( "Content-Range" ).unwrap(), "bytes 2-4/10" );
+    assert_eq!( &test::read_body( response )[..], b"234" );
+
+    let request = test::TestRequest::get().uri( &url ).header( "Range", "bytes=-3" ).to_request();
+    assert_eq!( &test::read_response( &mut app, request )[..], b"789" );
+
+    let request = test::TestRequest::get().uri( &url ).header( "Range", "bytes=10-" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::RANGE_NOT_SATISFIABLE );
+    assert_eq!( response.headers().get( "Content-Range" ).unwrap(), "bytes */10" );
+
+    let request = test::TestRequest::get().uri( &url ).header( "Range", "bytes=foo" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::RANGE_NOT_SATISFIABLE );
+
+    let request = test::TestRequest::with_uri( "/data/last/export/heaptrack" ).method( Method::HEAD ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::OK );
+    assert_eq!( response.headers().get( "Accept-Ranges" ).unwrap(), "none" );
+    assert!( test::read_body( response ).is_empty() );
+
+    let request = test::TestRequest::with_uri( "/data/1234/export/heaptrack" ).method( Method::HEAD ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
 }
 
-fn generate_regions< 'a, F: Fn( AllocationId, &Allocation ) -> bool + Clone + 'a >( data: &'a Data, filter: F ) -> impl Serialize + 'a {
-    let main_heap_start = data.alloc_sorted_by_address( None, None )
-        .iter()
-        .map( |&id| data.get_allocation( id ) )
-        .filter( |allocation| !allocation.is_mmaped() && allocation.in_main_arena() )
-        .map( |allocation| allocation.actual_range( data ).start )
-        .next()
-        .unwrap_or( 0 );
+#[test]
+fn test_export_content_disposition() {
+    use actix_web::test;
+    use actix_web::http::{Method, StatusCode};
+
+    let (state, mut app) = test_app( vec![ load_test_data( DataId::new( 1, 2 ), Vec::new() ) ] );
+    let data = state.get_data( DataId::new( 1, 2 ) ).unwrap();
+    let executable = data.executable().rsplit( '/' ).next().unwrap().to_owned();
+
+    let request = test::TestRequest::get().uri( "/data/last/export/flamegraph.pl?lifetime=only_leaked" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::OK );
+    assert_eq!( response.headers().get( "Content-Type" ).unwrap(), "text/plain; charset=utf-8" );
+    assert_eq!(
+        response.headers().get( "Content-Disposition" ).unwrap().to_str().unwrap(),
+        exports::content_disposition( &format!( "{}-{}-leaked.folded", executable, data.id() ) )
+    );
 
-    let main_heap_end = data.alloc_sorted_by_address( None, None )
-        .iter()
-        .map( |&id| data.get_allocation( id ) )
-        .rev()
-        .filter( |allocation| !allocation.is_mmaped() && allocation.in_main_arena() )
-        .map( |allocation| allocation.actual_range( data ).end )
-        .next()
-        .unwrap_or( 0 );
+    let request = test::TestRequest::with_uri( "/data/last/export/replay" ).method( Method::HEAD ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!(
+        response.headers().get( "Content-Disposition" ).unwrap().to_str().unwrap(),
+        exports::content_disposition( &format!( "{}-{}.replay.dat", executable, data.id() ) )
+    );
 
-    let regions = move || {
-        let filter = filter.clone();
-        data.alloc_sorted_by_address( None, None )
-            .iter()
-            .map( move |&id| (id, data.get_allocation( id )) )
-            .filter( move |(id, allocation)| filter( *id, allocation ) )
-            .map( move |(_, allocation)| allocation.actual_range( data ) )
-            .coalesce( |mut range, next_range| {
-                if next_range.start <= range.end {
-                    range.end = next_range.end;
-                    Ok( range )
-                } else {
-                    Err( (range, next_range) )
-                }
-            })
-            .map( |range| [range.start, range.end] )
-    };
+    let request = test::TestRequest::get().uri( "/data/last/export/flamegraph/flame.svg" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.headers().get( "Content-Type" ).unwrap(), "image/svg+xml" );
+    assert_eq!( response.headers().get( "Content-Disposition" ).unwrap(), "attachment; filename=\"flame.svg\"" );
 
-    protocol::ResponseRegions {
-        main_heap_start,
-        main_heap_end,
-        main_heap_start_s: format!( "{}", main_heap_start ),
-        main_heap_end_s: format!( "{}", main_heap_end ),
-        regions: StreamingSerializer::new( regions )
+    // The custom filter is resolved the same way for both, so they fail the same way.
+    let request = test::TestRequest::get().uri( "/data/last/export/replay?custom_filter_name=missing" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
+    let request = test::TestRequest::with_uri( "/data/last/export/replay?custom_filter_name=missing" ).method( Method::HEAD ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
+}
+
+#[test]
+fn test_cors() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+
+    let cors_origins = |values: &[&str]| parse_cors_origins( &values.iter().map( |value| value.to_string() ).collect::< Vec< _ > >() );
+    assert_eq!( cors_origins( &[] ), Ok( CorsOrigins::Any ) );
+    assert_eq!( cors_origins( &["*"] ), Ok( CorsOrigins::Any ) );
+    assert_eq!( cors_origins( &["none"] ), Ok( CorsOrigins::None ) );
+    assert_eq!( cors_origins( &["http://localhost:1234"] ), Ok( CorsOrigins::Only( vec![ "http://localhost:1234".into() ] ) ) );
+    assert!( cors_origins( &["none", "http://localhost:1234"] ).is_err() );
+    assert!( cors_origins( &["localhost"] ).is_err() );
+    assert!( cors_origins( &["http://localhost:1234/"] ).is_err() );
+
+    for &origins in &[ &["http://localhost:1234"][..], &["none"][..], &[][..] ] {
+        let mut state = State::new();
+        state.cors_origins = cors_origins( origins ).unwrap();
+        state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
+        let cors = cors::new_cors( &state.cors_origins );
+
+        let mut app = test::init_service(
+            App::new().data( Arc::new( state ) )
+                .wrap( cors )
+                .wrap_fn( cors::middleware )
+                .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
+        );
+
+        let preflight = |origin: &str| {
+            test::TestRequest::with_uri( "/data/last/execute_script" )
+                .method( actix_web::http::Method::OPTIONS )
+                .header( "Host", "localhost:8080" )
+                .header( "Origin", origin )
+                .header( "Access-Control-Request-Method", "POST" )
+                .header( "Access-Control-Request-Headers", "authorization, content-type" )
+                .to_request()
+        };
+
+        let response = test::call_service( &mut app, preflight( "http://localhost:1234" ) );
+        if origins == &["none"] {
+            assert_eq!( response.status(), StatusCode::FORBIDDEN );
+        } else {
+            assert_eq!( response.status(), StatusCode::OK );
+            assert_eq!( response.headers().get( "Access-Control-Allow-Origin" ).unwrap(), "http://localhost:1234" );
+        }
+
+        let response = test::call_service( &mut app, preflight( "http://example.com" ) );
+        if origins.is_empty() {
+            assert_eq!( response.status(), StatusCode::OK );
+        } else {
+            assert!( response.status().is_client_error() );
+        }
+
+        // Same-origin requests always work.
+        let request = test::TestRequest::post()
+            .uri( "/data/last/execute_script" )
+            .header( "Host", "localhost:8080" )
+            .header( "Origin", "http://localhost:8080" )
+            .set_payload( "println(1);" )
+            .to_request();
+        assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
     }
 }
 
-fn handler_regions( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter, &custom_filter )?;
+#[test]
+fn test_auth() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let response = generate_regions( &data, |id, allocation| filter.try_match( &data, id, allocation ) );
-        let _ = serde_json::to_writer( tx, &response );
-    })?;
+    let mut state = State::new();
+    state.auth_token = Some( "secret".into() );
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .wrap_fn( auth::middleware )
+            .service( web::resource( "/list" ).route( web::get().to( handler_list ) ) )
+            .service( web::resource( "/" ).route( web::get().to( || HttpResponse::Ok().finish() ) ) )
+    );
+
+    let request = test::TestRequest::get().uri( "/list" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::UNAUTHORIZED );
+    let response: serde_json::Value = serde_json::from_slice( &test::read_body( response ) ).unwrap();
+    assert_eq!( response[ "error" ][ "code" ], "unauthorized" );
+
+    let request = test::TestRequest::get().uri( "/list" ).header( "Authorization", "Bearer wrong" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::UNAUTHORIZED );
+
+    let request = test::TestRequest::get().uri( "/list?token=secre" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::UNAUTHORIZED );
+
+    let request = test::TestRequest::get().uri( "/list" ).header( "Authorization", "Bearer secret" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::OK );
+    assert!( response.headers().get( "Set-Cookie" ).is_none() );
+
+    let request = test::TestRequest::get().uri( "/list?token=secret" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::OK );
+    assert!( response.headers().get( "Set-Cookie" ).unwrap().to_str().unwrap().starts_with( "bytehound_token=secret;" ) );
+
+    let request = test::TestRequest::get().uri( "/list" ).header( "Cookie", "bytehound_token=secret" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
+
+    let request = test::TestRequest::get().uri( "/" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
 }
 
-fn handler_mallopts( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+#[test]
+fn test_data_etags() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    let response: Vec< _ > = data.mallopts().iter().map( |mallopt| {
-        let mut backtrace = Vec::new();
-        for (_, frame) in data.get_backtrace( mallopt.backtrace ) {
-            backtrace.push( get_frame( &data, &backtrace_format, frame ) );
-        }
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
 
-        protocol::Mallopt {
-            timestamp: mallopt.timestamp.into(),
-            thread: mallopt.thread,
-            backtrace_id: mallopt.backtrace.raw(),
-            backtrace,
-            raw_param: mallopt.kind.raw(),
-            param: match mallopt.kind {
-                MalloptKind::TrimThreshold  => Some( "M_TRIM_THRESHOLD" ),
-                MalloptKind::TopPad         => Some( "M_TOP_PAD" ),
-                MalloptKind::MmapThreshold  => Some( "M_MMAP_THRESHOLD" ),
-                MalloptKind::MmapMax        => Some( "M_MMAP_MAX" ),
-                MalloptKind::CheckAction    => Some( "M_CHECK_ACTION" ),
-                MalloptKind::Perturb        => Some( "M_PERTURB" ),
-                MalloptKind::ArenaTest      => Some( "M_ARENA_TEXT" ),
-                MalloptKind::ArenaMax       => Some( "M_ARENA_MAX" ),
-                MalloptKind::Other( _ )     => None
-            }.map( |value| value.into() ),
-            value: mallopt.value,
-            result: mallopt.result
-        }
-    }).collect();
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .wrap_fn( etag::middleware )
+            .service( web::resource( "/data/{id}/allocations" ).route( web::get().to( handler_allocations ) ) )
+    );
 
-    Ok( HttpResponse::Ok().json( response ) )
+    let mut get_etag = |uri: &str| {
+        let request = test::TestRequest::get().uri( uri ).to_request();
+        let response = test::call_service( &mut app, request );
+        response.headers().get( "ETag" ).map( |value| value.to_str().unwrap().to_owned() )
+    };
+
+    let etag = get_etag( "/data/last/allocations?size_min=1&sort_by=size" ).unwrap();
+    assert_eq!( get_etag( &format!( "/data/{}/allocations?sort_by=size&size_min=1", DataId::new( 1, 2 ) ) ).unwrap(), etag );
+    assert_ne!( get_etag( "/data/last/allocations?size_min=2&sort_by=size" ).unwrap(), etag );
+    assert_eq!( get_etag( "/data/last/allocations?custom_filter_name=foo" ), None );
+
+    let request = test::TestRequest::get()
+        .uri( "/data/last/allocations?sort_by=size&size_min=1" )
+        .header( "If-None-Match", format!( "\"foo\", {}", etag ) )
+        .to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::NOT_MODIFIED );
+    assert_eq!( response.headers().get( "ETag" ).unwrap().to_str().unwrap(), etag );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocations?size_min=foo" ).to_request();
+    let response = test::call_service( &mut app, request );
+    assert_eq!( response.status(), StatusCode::BAD_REQUEST );
+    assert!( response.headers().get( "ETag" ).is_none() );
+
+    let request = test::TestRequest::get().uri( "/data/ffffffffffffffffffffffffffffffff/allocations" ).header( "If-None-Match", "*" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
 }
 
-fn handler_export_flamegraph_pl( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter, &custom_filter )?;
+#[test]
+fn test_execute_script_all() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
+    use common::event::Event;
+
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 )
+    ]));
+    state.add_data( load_test_data( DataId::new( 1, 3 ), Vec::new() ) );
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service( web::resource( "/execute_script_all" ).route( web::post().to( handler_execute_script_all ) ) )
+    );
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let _ = export_as_flamegraph_pl( &data, tx, |id, allocation| filter.try_match( &data, id, allocation ) );
-    })?;
+    let script = r#"
+        if allocations().len() == 0 {
+            throw "no allocations";
+        }
+        println( allocations().len() );
+        graph().add( allocations() ).save();
+    "#;
+
+    for uri in &[ "/execute_script_all", "/execute_script_all?parallelism=2" ] {
+        let request = test::TestRequest::post().uri( uri ).set_payload( script ).to_request();
+        let result: serde_json::Value = test::read_response_json( &mut app, request );
+        assert_eq!( result.as_array().unwrap().len(), 2 );
+
+        assert_eq!( result[ 0 ][ "data_id" ], format!( "{}", DataId::new( 1, 2 ) ) );
+        assert_eq!( result[ 0 ][ "status" ], "ok", "{}", result );
+        assert_eq!( result[ 0 ][ "output" ][ 0 ][ "value" ], "1" );
+        assert_eq!( result[ 0 ][ "output" ][ 1 ][ "kind" ], "image" );
+
+        assert_eq!( result[ 1 ][ "data_id" ], format!( "{}", DataId::new( 1, 3 ) ) );
+        assert_eq!( result[ 1 ][ "status" ], "error" );
+    }
 
-    Ok( HttpResponse::Ok().content_type( "application/octet-stream" ).body( body ) )
+    let request = test::TestRequest::post().uri( "/execute_script_all?parallelism=0" ).set_payload( script ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
 }
 
-fn handler_export_flamegraph( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter, &custom_filter )?;
+#[test]
+fn test_execute_script_graph_controls() {
+    use common::event::Event;
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let _ = export_as_flamegraph( &data, tx, |id, allocation| filter.try_match( &data, id, allocation ) );
-    })?;
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 2 * 1024 * 1024, 1 ),
+        test_alloc( 5, 0x20000, 4 * 1024 * 1024, 1 )
+    ]);
+
+    let args = cli_core::script::EngineArgs {
+        data: Some( Arc::new( data ) ),
+        .. cli_core::script::EngineArgs::default()
+    };
+
+    let env = Arc::new( Mutex::new( cli_core::script::VirtualEnvironment::new() ) );
+    let engine = cli_core::script::Engine::new( env.clone(), args );
+    engine.run( r#"
+        graph()
+            .add( allocations() )
+            .between( s(1), s(3) )
+            .with_log_scale()
+            .bytes_formatting()
+            .with_title( "Memory usage at startup" )
+            .save( "graph.svg" );
+
+        graph().add( allocations() ).with_range( 1024 * 1024, 4 * 1024 * 1024 ).save( "range.svg" );
+    "# ).unwrap();
+
+    let output = std::mem::take( &mut env.lock().output );
+    let svgs: Vec< _ > = output.into_iter().filter_map( |item| match item {
+        cli_core::script::ScriptOutputKind::Image { data, .. } => Some( String::from_utf8( data.to_vec() ).unwrap() ),
+        _ => None
+    }).collect();
+
+    assert_eq!( svgs.len(), 2 );
+    assert!( svgs[ 0 ].contains( "Memory usage at startup" ) );
+    assert!( svgs[ 0 ].contains( "KiB" ) );
+    assert!( svgs[ 0 ].contains( "width=\"1024\" height=\"768\"" ) );
 
-    Ok( HttpResponse::Ok().content_type( "image/svg+xml" ).body( body ) )
+    for code in &[ "graph().add( allocations() ).between( s(3), s(1) );", "graph().add( allocations() ).with_range( 10, 5 );", "graph().add( allocations() ).with_range( -1, 5 );" ] {
+        let data = load_test_data( DataId::new( 1, 2 ), Vec::new() );
+        let result = execute_test_script( vec![ data ], code );
+        assert_eq!( result[ "status" ], "error", "{}", code );
+    }
 }
 
-fn handler_export_replay( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter, &custom_filter )?;
+#[test]
+fn test_execute_script_backtrace_frames() {
+    use common::event::Event;
+
+    let data = load_test_data( DataId::new( 1, 2 ), vec![
+        test_string( 0, "libfoo.so" ),
+        test_string( 1, "main" ),
+        test_string( 2, "std::vector<int, std::allocator<int> >::push_back(int const&)" ),
+        test_string( 3, "/src/main.cpp" ),
+        test_string( 4, "/usr/include/vector" ),
+        test_frame( 0x1000, 2, 4, 10, true ),
+        test_frame( 0x2000, 1, 3, 20, false ),
+        test_frame( 0x3000, 0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, false ),
+        Event::DecodedBacktrace { frames: vec![ 0, 1, 2 ].into() },
+        test_alloc( 2, 0x10000, 16, 0 )
+    ]);
+
+    let result = execute_test_script( vec![ data ], r#"
+        let backtrace = backtrace(0);
+        println(backtrace.id());
+        for frame in backtrace.frames() {
+            if frame.function() == () {
+                println("{} {} {}", frame.address(), frame.library(), frame.is_inline());
+                println(frame.source() == () && frame.line() == ());
+            } else {
+                println("{} {} {}", frame.address(), frame.library(), frame.is_inline());
+                println("{}:{}", frame.source(), frame.line());
+                println(frame.function());
+            }
+        }
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let _ = export_as_replay( &data, tx, |id, allocation| filter.try_match( &data, id, allocation ) );
-    })?;
+        println(strip_template(backtrace.frames()[2].function()));
+        println(strip_template(backtrace.frames()[2].function(), 1));
+        println(data().backtrace(0).frames().len());
+        backtrace(1);
+    "# );
+
+    assert_eq!( result[ "status" ], "error", "{}", result );
+    assert!( result[ "message" ].as_str().unwrap().contains( "no backtrace with ID 1" ), "{}", result );
+
+    let output: Vec< _ > = result[ "output" ].as_array().unwrap().iter().map( |entry| entry[ "value" ].as_str().unwrap().to_owned() ).collect();
+    assert_eq!( output, vec![
+        "0",
+        "12288 libfoo.so false", "true",
+        "8192 libfoo.so false", "/src/main.cpp:20", "main",
+        "4096 libfoo.so true", "/usr/include/vector:10", "std::vector<int, std::allocator<int> >::push_back(int const&)",
+        "std::vector<...>::push_back(int const&)",
+        "std::vector<int, std::allocator<...> >::push_back(int const&)",
+        "3"
+    ]);
+}
 
-    Ok( HttpResponse::Ok().content_type( "application/octet-stream" ).body( body ) )
+#[test]
+fn test_execute_script_datasets() {
+    use common::event::Event;
+
+    let data_a = load_test_data( DataId::new( 0, 1 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 )
+    ]);
+
+    let data_b = load_test_data( DataId::new( 0, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        Event::Backtrace { id: 2, addresses: vec![ 0x2000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 ),
+        test_alloc( 2, 0x20000, 16, 2 ),
+        test_alloc( 2, 0x30000, 16, 2 )
+    ]);
+
+    let result = execute_test_script( vec![ data_a, data_b ], r#"
+        for data in datasets() {
+            println( "{} {} {}", data.id(), data.executable(), data.allocations().len() );
+        }
+        println( dataset( "00000000000000000000000000000002" ).allocations().len() );
+        println( data().id() );
+        dataset( "00000000000000000000000000000003" );
+    "# );
+
+    assert_eq!( result[ "status" ], "error", "{}", result );
+    assert_eq!( result[ "line" ], 7 );
+    let lines: Vec< _ > = result[ "output" ].as_array().unwrap().iter().map( |entry| entry[ "value" ].as_str().unwrap().to_owned() ).collect();
+    assert_eq!( lines, vec![
+        "00000000000000000000000000000001 test 1",
+        "00000000000000000000000000000002 test 3",
+        "3",
+        "00000000000000000000000000000001"
+    ]);
 }
 
-fn handler_export_heaptrack( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter, &custom_filter )?;
+#[test]
+fn test_disabled_scripts() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let _ = export_as_heaptrack( &data, tx, |id, allocation| filter.try_match( &data, id, allocation ) );
-    })?;
+    let mut state = State::new();
+    state.scripts_disabled = true;
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
+            .service( web::resource( "/data/{id}/execute_script_async" ).route( web::post().to( handler_execute_script_async ) ) )
+            .service( web::resource( "/data/{id}/allocation_groups" ).route( web::get().to( handler_allocation_groups ) ) )
+    );
 
-    Ok( HttpResponse::Ok().content_type( "application/octet-stream" ).body( body ) )
+    let request = test::TestRequest::post().uri( "/data/last/execute_script" ).set_payload( "println(1);" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::FORBIDDEN );
+
+    let request = test::TestRequest::post().uri( "/data/last/execute_script_async" ).set_payload( "println(1);" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::FORBIDDEN );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocation_groups?custom_filter=allocations()" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::FORBIDDEN );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocation_groups?generate_graphs=true" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::FORBIDDEN );
+
+    let request = test::TestRequest::get().uri( "/data/last/allocation_groups" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::OK );
 }
 
-fn handler_allocation_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
-    let filter = prepare_filter( &data, &filter, &custom_filter )?;
+#[test]
+fn test_execute_script_sandbox() {
+    let result = execute_test_script( vec![ load_test_data( DataId::new( 1, 2 ), Vec::new() ) ], "save_file(\"../foo.txt\", \"foo\");" );
+    assert_eq!( result[ "status" ], "error" );
+    assert_eq!( result[ "output" ], serde_json::json!( [] ) );
 
-    let body = async_data_handler( &req, move |data, mut tx| {
-        let tree = data.tree_by_source( |id, allocation| filter.try_match( &data, id, allocation ) );
-        let table = data.dump_tree( &tree );
-        let table = table_to_string( &table );
-        let _ = writeln!( tx, "{}", table );
-    })?;
+    let result = execute_test_script( vec![ load_test_data( DataId::new( 1, 2 ), Vec::new() ) ], "mkdir_p(\"foo\"); save_file(\"foo/bar.txt\", \"bar\");" );
+    assert_eq!( result[ "status" ], "ok", "{}", result );
+    assert_eq!( result[ "output" ][ 0 ][ "path" ], "/foo/bar.txt" );
+}
 
-    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+#[test]
+fn test_execute_script_with_filter() {
+    use actix_web::test;
+    use common::event::Event;
+
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), vec![
+        Event::Backtrace { id: 1, addresses: vec![ 0x1000 ].into() },
+        test_alloc( 2, 0x10000, 16, 1 ),
+        test_alloc( 2, 0x20000, 32, 1 ),
+        test_alloc( 2, 0x30000, 64, 1 )
+    ]));
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
+    );
+
+    let mut run = |query: &str| {
+        let request = test::TestRequest::post()
+            .uri( &format!( "/data/last/execute_script{}", query ) )
+            .set_payload( "println(allocations().len()); println(data().allocations().len());" )
+            .to_request();
+
+        let result: serde_json::Value = test::read_response_json( &mut app, request );
+        assert_eq!( result[ "status" ], "ok", "{}", result );
+        (result[ "output" ][ 0 ][ "value" ].clone(), result[ "output" ][ 1 ][ "value" ].clone())
+    };
+
+    assert_eq!( run( "" ), ("3".into(), "3".into()) );
+    assert_eq!( run( "?size_min=32" ), ("2".into(), "3".into()) );
+    assert_eq!( run( "?size_min=32&custom_filter=allocations().only_larger(32)" ), ("1".into(), "3".into()) );
 }
 
-fn handler_collation_json< F >( req: HttpRequest, callback: F ) -> Result< HttpResponse >
-    where F: Fn( &Data ) -> BTreeMap< String, BTreeMap< u32, CountAndSize > > + Send + 'static
-{
-    use serde_json::json;
+#[test]
+fn test_execute_script_from_library() {
+    use actix_web::test;
+    use actix_web::http::StatusCode;
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let constants = callback( &data );
-        let mut total_count = 0;
-        let mut total_size = 0;
-        let per_file: BTreeMap< _, _ > = constants.into_iter().map( |(key, per_line)| {
-            let mut whole_file_count = 0;
-            let mut whole_file_size = 0;
-            let per_line: BTreeMap< _, _ > = per_line.into_iter().map( |(line, entry)| {
-                whole_file_count += entry.count;
-                whole_file_size += entry.size;
-                total_count += entry.count;
-                total_size += entry.size;
-                let entry = json!({
-                    "count": entry.count,
-                    "size": entry.size
-                });
-                (line, entry)
-            }).collect();
+    let directory = std::env::temp_dir().join( format!( "bytehound-execute-script-from-library-test-{}", std::process::id() ) );
+    let data = load_test_data( DataId::new( 1, 2 ), Vec::new() );
+    let mut state = State::new();
+    state.script_library = ScriptLibrary::new( Some( directory.clone() ) );
+    state.add_data( data );
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
+            .service(
+                web::resource( "/scripts/{name}" )
+                    .route( web::get().to( handler_get_script ) )
+                    .route( web::put().to( handler_put_script ) )
+            )
+    );
+
+    let request = test::TestRequest::put().uri( "/scripts/hello" ).set_payload( "println(\"hello\");" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NO_CONTENT );
+
+    let request = test::TestRequest::get().uri( "/scripts/hello" ).to_request();
+    assert_eq!( test::read_response( &mut app, request ), "println(\"hello\");" );
+
+    let request = test::TestRequest::post().uri( "/data/last/execute_script?script=hello" ).to_request();
+    let result: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( result[ "output" ][ 0 ][ "value" ], "hello" );
+
+    let request = test::TestRequest::post().uri( "/data/last/execute_script?script=hello" ).set_payload( "println(1);" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
+
+    let request = test::TestRequest::post().uri( "/data/last/execute_script?script=missing" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::NOT_FOUND );
+
+    let request = test::TestRequest::put().uri( "/scripts/.hidden" ).set_payload( "" ).to_request();
+    assert_eq!( test::call_service( &mut app, request ).status(), StatusCode::BAD_REQUEST );
+
+    let _ = std::fs::remove_dir_all( &directory );
+}
+
+fn handler_check_script( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    check_scripts_enabled( &req )?;
+    let data = get_data( &req )?;
+    let body = get_script_body( &req, body )?;
+    let env = req.state().script_limits.new_environment();
+    let engine = req.state().script_limits.new_engine( data, Vec::new(), env );
+    let result = match engine.check( &body ) {
+        Ok( () ) => {
+            serde_json::json! {{
+                "status": "ok"
+            }}
+        },
+        Err( error ) => {
+            serde_json::json! {{
+                "status": "error",
+                "message": error.message,
+                "line": error.line,
+                "column": error.column
+            }}
+        }
+    };
 
-            let value = json!({
-                "count": whole_file_count,
-                "size": whole_file_size,
-                "per_line": per_line
-            });
+    Ok( HttpResponse::Ok().json( result ) )
+}
 
-            (key, value)
-        }).collect();
+#[test]
+fn test_check_script() {
+    use actix_web::test;
 
-        let response = json!({
-            "count": total_count,
-            "size": total_size,
-            "per_file": per_file
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service( web::resource( "/data/{id}/check_script" ).route( web::post().to( handler_check_script ) ) )
+    );
+
+    let request = test::TestRequest::post().uri( "/data/last/check_script" ).set_payload( "println(allocations().len());" ).to_request();
+    let result: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( result, serde_json::json! {{ "status": "ok" }} );
+
+    let request = test::TestRequest::post().uri( "/data/last/check_script" ).set_payload( "let x = 1\nprintln(x);" ).to_request();
+    let result: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( result[ "status" ], "error" );
+    assert_eq!( result[ "line" ], 2 );
+    assert!( result[ "message" ].is_string() );
+}
+
+fn handler_execute_script_async( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    check_scripts_enabled( &req )?;
+    let data = get_data( &req )?;
+    let body = get_script_body( &req, body )?;
+    let filter = get_script_allocation_filter( &req, &data )?;
+    let (env, output) = req.state().script_limits.new_streaming_environment();
+    let job = req.state().script_jobs.lock().add_job( data.id(), output );
+
+    let state = req.state().clone();
+    let job_id = job.id;
+    let result = req.state().heavy_workers.spawn( move || {
+        let allocation_ids = filter.map( |filter| Arc::new( matching_allocation_ids( &data, &filter ) ) );
+        let engine = state.script_limits.new_filtered_engine( data, allocation_ids, state.datasets(), env );
+        let result = engine.run( &body );
+        let elapsed = job.started_at.elapsed();
+        state.record_script_execution( &result );
+
+        job.collect_output( &state );
+        job.progress.lock().result = Some( ScriptJobResult {
+            elapsed,
+            finished_at: Instant::now(),
+            error: result.err()
         });
+    });
 
-        let _ = serde_json::to_writer( tx, &response );
-    })?;
+    if result.is_err() {
+        req.state().script_jobs.lock().by_id.remove( &job_id );
+        return Err( ApiError::service_unavailable( "too many scripts are already running" ).into() );
+    }
 
-    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+    Ok( HttpResponse::Ok().json( serde_json::json! {{
+        "id": job_id
+    }}))
 }
 
-fn handler_dynamic_constants( req: HttpRequest ) -> Result< HttpResponse > {
-    handler_collation_json( req, |data| data.get_dynamic_constants() )
+fn get_script_job( req: &HttpRequest ) -> Result< Arc< ScriptJob > > {
+    let id = req.match_info().get( "job_id" ).unwrap();
+    let id: u64 = id.parse().map_err( |_| ApiError::not_found( "job not found" ) )?;
+    let job = req.state().script_jobs.lock().by_id.get( &id ).cloned().ok_or_else( || ApiError::not_found( "job not found" ) )?;
+    job.collect_output( req.state() );
+    Ok( job )
 }
 
-fn handler_dynamic_statics( req: HttpRequest ) -> Result< HttpResponse > {
-    handler_collation_json( req, |data| data.get_dynamic_statics() )
+fn handler_script_job( req: HttpRequest ) -> Result< HttpResponse > {
+    let job = get_script_job( &req )?;
+    Ok( HttpResponse::Ok().json( job.to_json( true ) ) )
 }
 
-fn handler_dynamic_constants_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
-    let body = async_data_handler( &req, move |data, mut tx| {
-        let table = data.get_dynamic_constants_ascii_tree();
-        let _ = writeln!( tx, "{}", table );
-    })?;
+fn handler_script_job_output( req: HttpRequest ) -> Result< HttpResponse > {
+    let job = get_script_job( &req )?;
+    if job.progress.lock().result.is_none() {
+        return Ok( HttpResponse::Accepted().json( job.to_json( true ) ) );
+    }
 
-    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+    Ok( HttpResponse::Ok().json( job.to_json( false ) ) )
 }
 
-fn handler_dynamic_statics_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
-    let body = async_data_handler( &req, move |data, mut tx| {
-        let table = data.get_dynamic_statics_ascii_tree();
-        let _ = writeln!( tx, "{}", table );
-    })?;
+fn server_sent_event( event: &str, data: &serde_json::Value ) -> web::Bytes {
+    format!( "event: {}\ndata: {}\n\n", event, data ).into()
+}
 
-    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+/// The server-sent events with a script job's output.
+///
+/// This is polled on the server's own threads, so a client which reads
+/// the events slowly is simply polled less often and doesn't block anything.
+struct ScriptJobStream {
+    job: Arc< ScriptJob >,
+    state: StateRef,
+    position: usize,
+    pending: std::collections::VecDeque< web::Bytes >,
+    is_done: bool,
+    last_sent_at: Instant,
+    interval: tokio_timer::Interval
 }
 
-fn handler_script_files( req: HttpRequest ) -> Result< HttpResponse > {
-    let hash = req.match_info().get( "hash" ).unwrap();
-    let entry = match req.state().generated_files.lock().by_hash.get( hash ) {
-        Some( entry ) => entry.clone(),
-        None => {
-            return Err( ErrorNotFound( "file not found" ) );
+impl ScriptJobStream {
+    fn new( job: Arc< ScriptJob >, state: StateRef ) -> Self {
+        ScriptJobStream {
+            job,
+            state,
+            position: 0,
+            pending: Default::default(),
+            is_done: false,
+            last_sent_at: Instant::now(),
+            interval: tokio_timer::Interval::new_interval( SCRIPT_JOB_STREAM_POLL_INTERVAL )
         }
-    };
+    }
 
-    let (mut tx, rx) = byte_channel();
-    let rx = rx.map_err( |_| ErrorInternalServerError( "internal error" ) );
-    let rx = BodyStream::new( rx );
-    let body = Body::Message( Box::new( rx ) );
-    let mime = entry.mime;
-    thread::spawn( move || {
-        use std::io::Write;
-        tx.write_all( &entry.data ).unwrap();
-    });
+    fn queue_events( &mut self ) {
+        self.job.collect_output( &self.state );
+
+        let progress = self.job.progress.lock();
+        for item in &progress.output[ self.position.. ] {
+            self.pending.push_back( server_sent_event( "output", item ) );
+        }
 
-    Ok( HttpResponse::Ok().content_type( mime ).body( body ) )
+        self.position = progress.output.len();
+        if let Some( ref result ) = progress.result {
+            let mut value = script_result_to_json( result.error.as_ref(), result.elapsed, &[] );
+            value.as_object_mut().unwrap().remove( "output" );
+            self.pending.push_back( server_sent_event( "done", &value ) );
+            self.is_done = true;
+        }
+
+        if self.pending.is_empty() && self.last_sent_at.elapsed() >= SCRIPT_JOB_STREAM_KEEP_ALIVE_INTERVAL {
+            // This is a comment, which is ignored by the client; it's mostly here to notice when the client disconnects.
+            self.pending.push_back( web::Bytes::from_static( b": keep-alive\n\n" ) );
+        }
+    }
 }
 
-fn handler_filter_to_script( req: HttpRequest ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
-    let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_raw_filter( data, &filter )?;
-    let custom_filter: protocol::CustomFilter = query( &req )?;
+impl Stream for ScriptJobStream {
+    type Item = web::Bytes;
+    type Error = ApiError;
 
-    let mut prologue = String::new();
-    let code;
+    fn poll( &mut self ) -> futures::Poll< Option< Self::Item >, Self::Error > {
+        loop {
+            if let Some( event ) = self.pending.pop_front() {
+                self.last_sent_at = Instant::now();
+                return Ok( futures::Async::Ready( Some( event ) ) );
+            }
 
-    if let Some( custom_filter ) = custom_filter.custom_filter {
-        prologue.push_str( "fn custom_filter() {\n" );
-        for line in custom_filter.lines() {
-            prologue.push_str( "    " );
-            prologue.push_str( line );
-            prologue.push_str( "\n" );
+            if self.is_done {
+                return Ok( futures::Async::Ready( None ) );
+            }
+
+            self.queue_events();
+            if !self.pending.is_empty() {
+                continue;
+            }
+
+            match self.interval.poll() {
+                Ok( futures::Async::Ready( Some( _ ) ) ) => continue,
+                Ok( futures::Async::NotReady ) => return Ok( futures::Async::NotReady ),
+                Ok( futures::Async::Ready( None ) ) | Err( _ ) => return Err( ApiError::internal( "internal error" ) )
+            }
         }
-        prologue.push_str( "}\n\n" );
-        prologue.push_str( "let filtered = custom_filter();\n" );
-        code = filter.to_code( Some( "filtered".into() ) );
-    } else {
-        code = filter.to_code( None );
     }
+}
 
-    let body = serde_json::json! {{
-        "prologue": prologue,
-        "code": code
-    }};
+/// Streams the job's output as server-sent events; the last event carries the job's final status.
+fn handler_script_job_stream( req: HttpRequest ) -> Result< HttpResponse > {
+    let job = get_script_job( &req )?;
+    let stream = ScriptJobStream::new( job, req.state().clone() );
+    let body = Body::Message( Box::new( BodyStream::new( stream ) ) );
 
-    Ok( HttpResponse::Ok().content_type( "application/json; charset=utf-8" ).body( body ) )
+    Ok(
+        HttpResponse::Ok()
+            .content_type( "text/event-stream" )
+            .header( "Cache-Control", "no-cache" )
+            .body( body )
+    )
 }
 
-fn handler_execute_script( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
-    let data = get_data( &req )?;
+#[test]
+fn test_script_job_stream() {
+    use actix_web::test;
+
+    let state = State::new();
+    state.add_data( load_test_data( DataId::new( 1, 2 ), Vec::new() ) );
+
+    let mut app = test::init_service(
+        App::new().data( Arc::new( state ) )
+            .service( web::resource( "/data/{id}/execute_script_async" ).route( web::post().to( handler_execute_script_async ) ) )
+            .service( web::resource( "/script_jobs/{job_id}/output" ).route( web::get().to( handler_script_job_output ) ) )
+            .service( web::resource( "/script_jobs/{job_id}/stream" ).route( web::get().to( handler_script_job_stream ) ) )
+    );
+
+    let request = test::TestRequest::post().uri( "/data/last/execute_script_async" ).set_payload( "println(1); println(2); foo();" ).to_request();
+    let result: serde_json::Value = test::read_response_json( &mut app, request );
+    let id = result[ "id" ].as_u64().unwrap();
+
+    let request = test::TestRequest::get().uri( &format!( "/script_jobs/{}/stream", id ) ).to_request();
+    let body = test::read_response( &mut app, request );
     let body = String::from_utf8( body.to_vec() ).unwrap();
-    let args = cli_core::script::EngineArgs {
-        data: Some( data.clone() ),
-        .. cli_core::script::EngineArgs::default()
+    let events: Vec< _ > = body.split( "\n\n" ).filter( |event| !event.is_empty() ).collect();
+    assert_eq!( events.len(), 3, "{}", body );
+    assert_eq!( events[ 0 ], "event: output\ndata: {\"kind\":\"println\",\"value\":\"1\"}" );
+    assert_eq!( events[ 1 ], "event: output\ndata: {\"kind\":\"println\",\"value\":\"2\"}" );
+
+    let done: serde_json::Value = serde_json::from_str( events[ 2 ].strip_prefix( "event: done\ndata: " ).unwrap() ).unwrap();
+    assert_eq!( done[ "status" ], "error" );
+    assert!( done.get( "output" ).is_none() );
+
+    // Streaming the output doesn't consume it.
+    let request = test::TestRequest::get().uri( &format!( "/script_jobs/{}/output", id ) ).to_request();
+    let result: serde_json::Value = test::read_response_json( &mut app, request );
+    assert_eq!( result[ "output" ].as_array().unwrap().len(), 2 );
+}
+
+fn handler_create_script_session( req: HttpRequest ) -> Result< HttpResponse > {
+    check_scripts_enabled( &req )?;
+    let data = get_data( &req )?;
+    let state = req.state().clone();
+    let datasets = state.datasets();
+    let data_ids = datasets.iter().map( |data| data.id() ).collect();
+    let (sender, receiver) = mpsc::channel::< ScriptSessionRequest >();
+    let id = state.script_sessions.lock().add_session( data_ids, sender, state.script_session_idle_timeout )
+        .ok_or_else( || ApiError::service_unavailable( "too many script sessions" ) )?;
+
+    thread::spawn( move || {
+        let limits = state.script_limits.for_session();
+        let env = limits.new_environment();
+        let engine = limits.new_engine( data.clone(), datasets, env.clone() );
+        let mut session = cli_core::script::Session::new().with_max_memory_usage( MAXIMUM_SCRIPT_SESSION_MEMORY_USAGE );
+        while let Ok( request ) = receiver.recv() {
+            let timestamp = Instant::now();
+            let result = engine.run_in_session( &mut session, &request.code );
+            let elapsed = timestamp.elapsed();
+            state.record_script_execution( &result );
+
+            let output = collect_script_output( &state, data.id(), &env );
+            let _ = request.reply.send( script_result_to_json( result.err().as_ref(), elapsed, &output ) );
+        }
+    });
+
+    Ok( HttpResponse::Ok().json( serde_json::json! {{
+        "id": id
+    }}))
+}
+
+fn get_script_session_id( req: &HttpRequest ) -> Result< u64 > {
+    let id = req.match_info().get( "session_id" ).unwrap();
+    id.parse().map_err( |_| ApiError::not_found( "session not found" ).into() )
+}
+
+/// The snippet itself runs on the session's own thread, but waiting for it takes up
+/// one of the workers so that the sessions can't run more scripts at once than the pool allows.
+fn handler_script_session_eval( req: HttpRequest, body: web::Bytes ) -> Box< dyn Future< Item = HttpResponse, Error = ActixWebError > > {
+    let session = get_script_session_id( &req ).and_then( |id| {
+        req.state().script_sessions.lock().get( id, req.state().script_session_idle_timeout ).ok_or_else( || ApiError::not_found( "session not found" ).into() )
+    });
+
+    let session = match session {
+        Ok( session ) => session,
+        Err( error ) => return Box::new( future::err( error ) )
     };
 
-    let env = Arc::new( Mutex::new( cli_core::script::VirtualEnvironment::new() ) );
-    let engine = cli_core::script::Engine::new( env.clone(), args );
-    let timestamp = std::time::Instant::now();
-    let result = engine.run( &body );
-    let elapsed = timestamp.elapsed();
-    let data_id = data.id();
+    let body = match String::from_utf8( body.to_vec() ) {
+        Ok( body ) => body,
+        Err( _ ) => return Box::new( future::err( ApiError::bad_request( "the script is not valid UTF-8" ).into() ) )
+    };
 
-    let mut new_files = Vec::new();
-    let mut output = Vec::new();
-    for item in std::mem::take( &mut env.lock().output ) {
-        match item {
-            cli_core::script::ScriptOutputKind::PrintLine( line ) => {
-                output.push( serde_json::json! {{
-                    "kind": "println",
-                    "value": line
-                }});
-            },
-            cli_core::script::ScriptOutputKind::Image { path, data } => {
-                let hash = format!( "{:x}", md5::compute( &*data ) );
-                let basename = path[ path.rfind( "/" ).unwrap() + 1.. ].to_owned();
-                output.push( serde_json::json! {{
-                    "url": format!( "/data/{}/script_files/{}/{}", data_id, hash, basename ),
-                    "kind": "image",
-                    "basename": basename,
-                    "path": path,
-                    "checksum": hash
-                }});
+    let (tx, rx) = futures::sync::oneshot::channel();
+    let result = req.state().heavy_workers.spawn( move || {
+        let _ = tx.send( session.eval( body ).ok_or_else( || ApiError::internal( "the session has crashed" ) ) );
+    });
 
-                let entry = GeneratedFile {
-                    timestamp: Instant::now(),
-                    hash,
-                    mime: "image/svg+xml",
-                    data
-                };
+    if result.is_err() {
+        return Box::new( future::err( too_many_queued_requests() ) );
+    }
 
-                new_files.push( entry );
-            }
-        }
+    let response = rx
+        .map_err( |_| ApiError::internal( "internal error" ).into() )
+        .and_then( |result| {
+            let result = result?;
+            Ok( HttpResponse::Ok().json( result ) )
+        });
+
+    Box::new( response )
+}
+
+fn handler_delete_data( req: HttpRequest ) -> Result< HttpResponse > {
+    let id = get_data_id( &req )?;
+    if !req.state().remove_data( id ) {
+        return Err( ApiError::not_found( "data not found" ).into() );
     }
 
-    let mut generated = req.state().generated_files.lock();
-    generated.purge_old_if_too_big();
-    for entry in new_files {
-        generated.add_file( entry );
+    info!( "Unloaded {}", id );
+    Ok( HttpResponse::NoContent().finish() )
+}
+
+fn handler_set_name( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    let request: protocol::RequestName = serde_json::from_slice( &body ).map_err( |error| ApiError::bad_request( format!( "invalid request body: {}", error ) ) )?;
+    let id = get_data_id( &req )?;
+    let state = req.state();
+    state.aliases.lock().set( id, &request.name, |id| state.get_data( id ).is_some() )?;
+
+    info!( "Named {} as '{}'", id, request.name );
+    Ok( HttpResponse::NoContent().finish() )
+}
+
+fn handler_delete_name( req: HttpRequest ) -> Result< HttpResponse > {
+    let id = get_data_id( &req )?;
+    if !req.state().aliases.lock().remove( id )? {
+        return Err( ApiError::not_found( "the data has no name" ).into() );
     }
-    std::mem::drop( generated );
 
-    let result = match result {
-        Ok( _ ) => {
-            serde_json::json! {{
-                "status": "ok",
-                "elapsed": elapsed.as_secs_f64(),
-                "output": output
-            }}
-        },
-        Err( error ) => {
-            serde_json::json! {{
-                "status": "error",
-                "message": error.message,
-                "line": error.line,
-                "column": error.column,
-                "output": output
-            }}
-        }
-    };
+    Ok( HttpResponse::NoContent().finish() )
+}
 
-    Ok(
-        HttpResponse::Ok()
-        .content_type( "application/json; charset=utf-8" )
-        .header( "Access-Control-Allow-Origin", "http://localhost:1234" )
-        .body( serde_json::to_string( &result ).unwrap() )
-    )
+fn handler_delete_script_session( req: HttpRequest ) -> Result< HttpResponse > {
+    let id = get_script_session_id( &req )?;
+    req.state().script_sessions.lock().by_id.remove( &id ).ok_or_else( || ApiError::not_found( "session not found" ) )?;
+
+    Ok( HttpResponse::NoContent().finish() )
+}
+
+fn get_script_name( req: &HttpRequest ) -> &str {
+    req.match_info().get( "name" ).unwrap()
+}
+
+fn handler_list_scripts( req: HttpRequest ) -> Result< HttpResponse > {
+    let list: Vec< _ > = req.state().script_library.list()?.into_iter().map( |entry| {
+        let modified = entry.modified.duration_since( std::time::UNIX_EPOCH ).map( |duration| duration.as_secs() ).unwrap_or( 0 );
+        serde_json::json! {{
+            "name": entry.name,
+            "modified": modified,
+            "size": entry.size
+        }}
+    }).collect();
+
+    Ok( HttpResponse::Ok().json( list ) )
+}
+
+fn handler_get_script( req: HttpRequest ) -> Result< HttpResponse > {
+    let body = req.state().script_library.get( get_script_name( &req ) )?;
+    Ok( HttpResponse::Ok().content_type( "text/plain; charset=utf-8" ).body( body ) )
+}
+
+fn handler_put_script( req: HttpRequest, body: web::Bytes ) -> Result< HttpResponse > {
+    let body = String::from_utf8( body.to_vec() ).map_err( |_| ApiError::bad_request( "the script is not valid UTF-8" ) )?;
+    req.state().script_library.put( get_script_name( &req ), &body )?;
+    Ok( HttpResponse::NoContent().finish() )
+}
+
+fn handler_delete_script( req: HttpRequest ) -> Result< HttpResponse > {
+    req.state().script_library.delete( get_script_name( &req ) )?;
+    Ok( HttpResponse::NoContent().finish() )
 }
 
 fn guess_mime( path: &str ) -> &str {
@@ -1690,13 +10012,63 @@ fn guess_mime( path: &str ) -> &str {
     "application/octet-stream"
 }
 
+/// Points the absolute URLs within the web UI's assets to the given base path.
+///
+/// The web UI is always built to be served from `/`, so that what's served
+/// stays exactly the same unless a base path is actually configured.
+fn rebase_asset( path: &str, bytes: &'static [u8], base_path: &str ) -> std::borrow::Cow< 'static, [u8] > {
+    let patterns: &[&str] = if path.ends_with( ".html" ) {
+        &[ "src=\"/", "href=\"/" ]
+    } else if path.ends_with( ".css" ) {
+        &[ "url(/", "url(\"/", "url('/" ]
+    } else {
+        &[]
+    };
+
+    if base_path.is_empty() || patterns.is_empty() {
+        return bytes.into();
+    }
+
+    let mut contents = String::from_utf8_lossy( bytes ).into_owned();
+    for pattern in patterns {
+        let (prefix, slash) = pattern.split_at( pattern.len() - 1 );
+        contents = contents.replace( pattern, &format!( "{}{}{}", prefix, base_path, slash ) );
+    }
+
+    contents.into_bytes().into()
+}
+
+#[test]
+fn test_rebase_asset() {
+    let html = b"<link rel=\"stylesheet\" href=\"/index.css\"><script src=\"/index.js\"></script><a href=\"https://example.com/\">";
+    assert_eq!( &*rebase_asset( "index.html", html, "" ), &html[..] );
+    assert_eq!(
+        &*rebase_asset( "index.html", html, "/bytehound" ),
+        &b"<link rel=\"stylesheet\" href=\"/bytehound/index.css\"><script src=\"/bytehound/index.js\"></script><a href=\"https://example.com/\">"[..]
+    );
+
+    let css = b"a{background:url(/a.svg)} b{background:url(\"/b.svg\")} @font-face{src:url('/c.woff')}";
+    assert_eq!(
+        &*rebase_asset( "index.css", css, "/bytehound" ),
+        &b"a{background:url(/bytehound/a.svg)} b{background:url(\"/bytehound/b.svg\")} @font-face{src:url('/bytehound/c.woff')}"[..]
+    );
+
+    let js = b"fetch(\"/list\")";
+    assert_eq!( &*rebase_asset( "index.js", js, "/bytehound" ), &js[..] );
+}
+
 struct StaticResponse( &'static str, &'static [u8] );
 impl Responder for StaticResponse {
     type Error = actix_web::Error;
     type Future = Result< HttpResponse >;
 
-    fn respond_to( self, _: &HttpRequest ) -> Self::Future {
-        Ok( HttpResponse::Ok().content_type( guess_mime( self.0 ) ).body( self.1 ) )
+    fn respond_to( self, req: &HttpRequest ) -> Self::Future {
+        let mut response = HttpResponse::Ok();
+        response.content_type( guess_mime( self.0 ) );
+        match rebase_asset( self.0, self.1, &req.state().base_path ) {
+            std::borrow::Cow::Borrowed( bytes ) => Ok( response.body( bytes ) ),
+            std::borrow::Cow::Owned( bytes ) => Ok( response.body( bytes ) )
+        }
     }
 }
 
@@ -1728,88 +10100,399 @@ impl From< io::Error > for ServerError {
 
 impl Error for ServerError {}
 
-pub fn main( inputs: Vec< PathBuf >, debug_symbols: Vec< PathBuf >, load_in_parallel: bool, interface: &str, port: u16 ) -> Result< (), ServerError > {
-    let mut state = State::new();
+#[derive(Clone, Default)]
+pub struct ServerOptions {
+    /// Whenever to compute the most commonly requested analyses in the background right after loading.
+    pub precompute: bool,
+    /// Source path prefix rewriting rules applied to every served frame.
+    pub source_prefix_map: Vec< (String, String) >,
+    /// Directories from which source code snippets can be served.
+    pub source_roots: Vec< PathBuf >,
+    /// The maximum time a single script can run.
+    pub script_timeout: Option< Duration >,
+    /// The maximum size of everything a single script can output.
+    pub script_max_output_size: Option< usize >,
+    /// How long an unused script session is kept around.
+    pub script_session_idle_timeout: Duration,
+    /// The maximum number of rows of a single table output by a script.
+    pub script_max_table_rows: usize,
+    /// The maximum total size of the files a single script can save.
+    pub script_max_files_size: usize,
+    /// The directory where named scripts are stored; if `None` the script library is disabled.
+    pub scripts_dir: Option< PathBuf >,
+    /// Whether scripts can use absolute paths and `..` when saving files.
+    pub allow_script_fs: bool,
+    /// Whether running scripts (including custom filters and per-group graphs) is forbidden.
+    pub disable_scripts: bool,
+    /// The token which has to be given with every request, except for the static assets.
+    pub auth_token: Option< String >,
+    /// Whenever to generate a random token and print it out at startup; ignored if `auth_token` is set.
+    pub generate_auth_token: bool,
+    /// The origins from which the server can be accessed by the browser; by default every origin is allowed.
+    pub cors_origins: CorsOrigins,
+    /// Whenever to collect metrics and export them through `/metrics`.
+    pub metrics: bool,
+    /// How many threads process the heavy requests; if `None` then half of the physical cores are used.
+    pub heavy_workers: Option< usize >,
+    /// How many bytes of the files generated by scripts are kept in memory.
+    pub generated_files_cache_size: usize,
+    /// Where the files generated by scripts are written once they're evicted from memory.
+    pub generated_files_dir: Option< PathBuf >,
+    /// Whenever every request should be logged.
+    pub log_requests: bool,
+    /// The requests which take longer than this are logged as warnings.
+    pub slow_request_threshold: Option< Duration >,
+    /// The deadline of the requests which don't specify their own; if `None` they can take as long as they need.
+    pub default_deadline: Option< Duration >,
+    /// The directories from which the data files can be loaded through `/data/load`; if empty this is disabled.
+    pub data_dirs: Vec< PathBuf >,
+    /// Which part of the data files given on the command line should be loaded.
+    pub load_range: Option< LoadRange >,
+    /// The address on which to accept the data streamed from running processes; if `None` this is disabled.
+    pub listen_for_profiles: Option< String >,
+    /// The file where the names given to the data are kept; if `None` they're forgotten on restart.
+    pub aliases_file: Option< PathBuf >,
+    /// The directory where the loaded data files are cached so that they load faster the next time; if `None` they're not cached.
+    pub cache_dir: Option< PathBuf >,
+    /// How many bytes the caches in the `cache_dir` can take at most; the least recently used ones are removed first.
+    pub cache_size_limit: u64,
+    /// Whenever to refuse to start if any of the data files fails to load, instead of serving the ones which didn't.
+    pub strict: bool,
+    /// Whenever to keep the allocations of the loaded data in memory mapped files instead of in memory.
+    pub low_memory: bool,
+    /// The path under which the server is available, as returned by `normalize_base_path`.
+    pub base_path: String
+}
+
+const PRECOMPUTE_TASK_COUNT: usize = 3;
 
-    if !load_in_parallel {
-        for filename in inputs {
-            info!( "Trying to load {:?}...", filename );
-            let fp = File::open( filename )?;
-            let data = Loader::load_from_stream( fp, &debug_symbols )?;
-            state.add_data( data );
+/// Precomputes the analyses in the background once none of the workers are busy with the requests.
+fn schedule_precompute( state: &Arc< State >, id: DataId ) {
+    let state_clone = state.clone();
+    state.heavy_workers.spawn_low_priority( move || {
+        if let Some( data ) = state_clone.get_data( id ) {
+            precompute( &state_clone, &data );
         }
-    } else {
-        let handles: Vec< thread::JoinHandle< io::Result< Data > > > = inputs.iter().map( move |filename| {
-            let filename = filename.clone();
-            let debug_symbols = debug_symbols.clone();
-            thread::spawn( move || {
-                info!( "Trying to load {:?}...", filename );
-                let fp = File::open( filename )?;
-                let data = Loader::load_from_stream( fp, debug_symbols )?;
-                Ok( data )
-            })
-        }).collect();
+    });
+}
+
+fn precompute( state: &State, data: &Arc< Data > ) {
+    let progress = match state.precompute_progress.lock().get( &data.id() ).cloned() {
+        Some( progress ) => progress,
+        None => return
+    };
+
+    info!( "Precomputing the timeline for {}...", data.id() );
+    state.timeline_cache.get_or_compute( data.id(), || build_timeline( data, data.operation_ids() ) );
+    progress.fetch_add( 1, Ordering::Relaxed );
+
+    info!( "Precomputing the fragmentation timeline for {}...", data.id() );
+    state.fragmentation_timeline_cache.get_or_compute( data.id(), || get_fragmentation_timeline( data ) );
+    progress.fetch_add( 1, Ordering::Relaxed );
+
+    info!( "Precomputing the allocation groups for {}...", data.id() );
+    let filter_params: protocol::AllocFilter = serde_urlencoded::from_str( "" ).unwrap();
+    let custom_filter: protocol::CustomFilter = serde_urlencoded::from_str( "" ).unwrap();
+    if let Ok( filter ) = prepare_filter( data, &filter_params, &custom_filter, &state.script_limits ) {
+        let key = AllocationGroupsKey {
+            data_id: data.id(),
+            filter: filter_params,
+            custom_filter,
+            sort_by: protocol::AllocGroupsSortBy::MinTimestamp,
+            order: protocol::Order::Asc
+        };
+
+        state.allocation_group_cache.get_or_compute( key.clone(), || {
+            compute_allocation_groups( data, &filter, key.sort_by, key.order )
+        });
+    }
+    progress.fetch_add( 1, Ordering::Relaxed );
+
+    info!( "Finished precomputing for {}", data.id() );
+}
 
+fn configure_routes( app: &mut web::ServiceConfig ) {
+    app
+        .service( web::resource( "/list" ).route( web::get().to( handler_list ) ) )
+        .service( web::resource( "/data" ).route( web::post().to_async( handler_upload ) ) )
+        .service( web::resource( "/data/load" ).route( web::post().to( handler_load ) ) )
+        .service( web::resource( "/data/merge" ).route( web::post().to_async( handler_merge ) ) )
+        .service( web::resource( "/data/{id}" ).route( web::delete().to( handler_delete_data ) ) )
+        .service(
+            web::resource( "/data/{id}/name" )
+                .route( web::put().to( handler_set_name ) )
+                .route( web::delete().to( handler_delete_name ) )
+        )
+        .service( web::resource( "/metrics" ).route( web::get().to( handler_metrics ) ) )
+        .service( web::resource( "/healthz" ).route( web::get().to( handler_healthz ) ) )
+        .service( web::resource( "/readyz" ).route( web::get().to( handler_readyz ) ) )
+        .service( web::resource( "/load_progress" ).route( web::get().to( handler_load_progress ) ) )
+        .service( web::resource( "/config.json" ).route( web::get().to( handler_config ) ) )
+        .service( web::resource( "/compare/timeline" ).route( web::get().to( handler_compare_timeline ) ) )
+        .service( web::resource( "/data/{id}/timeline" ).route( web::get().to( handler_timeline ) ) )
+        .service( web::resource( "/data/{id}/timeline_leaked" ).route( web::get().to( handler_timeline_leaked ) ) )
+        .service( web::resource( "/data/{id}/timeline_temporary" ).route( web::get().to( handler_timeline_temporary ) ) )
+        .service( web::resource( "/data/{id}/timeline_waste" ).route( web::get().to( handler_timeline_waste ) ) )
+        .service( web::resource( "/data/{id}/timeline_rates" ).route( web::get().to( handler_timeline_rates ) ) )
+        .service( web::resource( "/data/{id}/timeline_cumulative" ).route( web::get().to( handler_timeline_cumulative ) ) )
+        .service( web::resource( "/data/{id}/timeline_by_group" ).route( web::get().to( handler_timeline_by_group ) ) )
+        .service( web::resource( "/data/{id}/timeline_by_library" ).route( web::get().to( handler_timeline_by_library ) ) )
+        .service( web::resource( "/data/{id}/peak" ).route( web::get().to( handler_peak ) ) )
+        .service( web::resource( "/data/{id}/leak_rates" ).route( web::get().to( handler_leak_rates ) ) )
+        .service( web::resource( "/data/{id}/churn" ).route( web::get().to( handler_churn ) ) )
+        .service( web::resource( "/data/{id}/threads" ).route( web::get().to( handler_threads ) ) )
+        .service( web::resource( "/data/{id}/snapshot" ).route( web::get().to( handler_snapshot ) ) )
+        .service( web::resource( "/data/{id}/survival" ).route( web::get().to( handler_survival ) ) )
+        .service( web::resource( "/data/{id}/age_distribution" ).route( web::get().to( handler_age_distribution ) ) )
+        .service( web::resource( "/data/{id}/fragmentation_timeline" ).route( web::get().to( handler_fragmentation_timeline ) ) )
+        .service(
+            web::resource( "/data/{id}/allocations" )
+                .route( web::get().to( handler_allocations ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_allocations ) ) )
+        )
+        .service( web::resource( "/data/{id}/allocation/{allocation_id}" ).route( web::get().to( handler_allocation ) ) )
+        .service(
+            web::resource( "/data/{id}/allocation_groups" )
+                .route( web::get().to( handler_allocation_groups ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_allocation_groups ) ) )
+        )
+        .service( web::resource( "/data/{id}/size_histogram" ).route( web::get().to( handler_size_histogram ) ) )
+        .service(
+            web::resource( "/data/{id}/backtraces" )
+                .route( web::get().to( handler_backtraces ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_backtraces ) ) )
+        )
+        .service( web::resource( "/data/{id}/raw_allocations" ).route( web::get().to( handler_raw_allocations ) ) )
+        .service(
+            web::resource( "/data/{id}/tree" )
+                .route( web::get().to( handler_tree ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_tree ) ) )
+        )
+        .service( web::resource( "/data/{id}/mmaps" ).route( web::get().to( handler_mmaps ) ) )
+        .service( web::resource( "/data/{id}/mmap_groups" ).route( web::get().to( handler_mmap_groups ) ) )
+        .service( web::resource( "/data/{id}/mmap_leaks" ).route( web::get().to( handler_mmap_leaks ) ) )
+        .service( web::resource( "/data/{id}/mmap_regions" ).route( web::get().to( handler_mmap_regions ) ) )
+        .service( web::resource( "/data/{id}/backtrace_clusters" ).route( web::get().to( handler_backtrace_clusters ) ) )
+        .service( web::resource( "/data/{id}/backtraces/containing_address/{address}" ).route( web::get().to( handler_backtraces_containing_address ) ) )
+        .service( web::resource( "/data/{id}/resolve_pointer" ).route( web::get().to( handler_resolve_pointer ) ) )
+        .service( web::resource( "/data/{id}/modules" ).route( web::get().to( handler_modules ) ) )
+        .service( web::resource( "/data/{id}/symbolize" ).route( web::get().to( handler_symbolize ) ) )
+        .service( web::resource( "/data/{id}/backtrace/{backtrace_id}" ).route( web::get().to( handler_backtrace ) ) )
+        .service( web::resource( "/data/{id}/regions" ).route( web::get().to( handler_regions ) ) )
+        .service( web::resource( "/data/{id}/mallopts" ).route( web::get().to( handler_mallopts ) ) )
+        .service( web::resource( "/data/{id}/markers" ).route( web::get().to( handler_markers ) ) )
+        .service( web::resource( "/data/{id}/batch_counts" ).route( web::post().to( handler_batch_counts ) ) )
+        .service(
+            web::resource( "/data/{id}/export/flamegraph" )
+                .route( web::get().to( handler_export_flamegraph ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_flamegraph ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::Flamegraph ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/flamegraph/{filename}" )
+                .route( web::get().to( handler_export_flamegraph ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_flamegraph ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::Flamegraph ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/flamegraph.pl" )
+                .route( web::get().to( handler_export_flamegraph_pl ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_flamegraph_pl ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::FlamegraphPl ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/flamegraph.pl/{filename}" )
+                .route( web::get().to( handler_export_flamegraph_pl ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_flamegraph_pl ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::FlamegraphPl ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/mmap_flamegraph" )
+                .route( web::get().to( handler_export_mmap_flamegraph ) )
+                .route( web::head().to( |req| handler_mmap_export_head( req, ExportFormat::Flamegraph ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/mmap_flamegraph/{filename}" )
+                .route( web::get().to( handler_export_mmap_flamegraph ) )
+                .route( web::head().to( |req| handler_mmap_export_head( req, ExportFormat::Flamegraph ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/mmap_flamegraph.pl" )
+                .route( web::get().to( handler_export_mmap_flamegraph_pl ) )
+                .route( web::head().to( |req| handler_mmap_export_head( req, ExportFormat::FlamegraphPl ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/mmap_flamegraph.pl/{filename}" )
+                .route( web::get().to( handler_export_mmap_flamegraph_pl ) )
+                .route( web::head().to( |req| handler_mmap_export_head( req, ExportFormat::FlamegraphPl ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/heaptrack" )
+                .route( web::get().to( handler_export_heaptrack ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_heaptrack ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::Heaptrack ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/heaptrack/{filename}" )
+                .route( web::get().to( handler_export_heaptrack ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_heaptrack ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::Heaptrack ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/replay" )
+                .route( web::get().to( handler_export_replay ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_replay ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::Replay ) ) )
+        )
+        .service(
+            web::resource( "/data/{id}/export/replay/{filename}" )
+                .route( web::get().to( handler_export_replay ) )
+                .route( web::post().to( |req: HttpRequest, body: web::Bytes| post_handler( req, body, handler_export_replay ) ) )
+                .route( web::head().to( |req| handler_export_head( req, ExportFormat::Replay ) ) )
+        )
+        .service( web::resource( "/data/{id}/allocation_ascii_tree" ).route( web::get().to( handler_allocation_ascii_tree ) ) )
+        .service( web::resource( "/data/{id}/mmap_ascii_tree" ).route( web::get().to( handler_mmap_ascii_tree ) ) )
+        .service( web::resource( "/data/{id}/by_source" ).route( web::get().to( handler_by_source ) ) )
+        .service( web::resource( "/data/{id}/dynamic_constants" ).route( web::get().to( handler_dynamic_constants ) ) )
+        .service( web::resource( "/data/{id}/dynamic_constants/{filename}" ).route( web::get().to( handler_dynamic_constants ) ) )
+        .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree" ).route( web::get().to( handler_dynamic_constants_ascii_tree ) ) )
+        .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree/{filename}" ).route( web::get().to( handler_dynamic_constants_ascii_tree ) ) )
+        .service( web::resource( "/data/{id}/dynamic_statics" ).route( web::get().to( handler_dynamic_statics ) ) )
+        .service( web::resource( "/data/{id}/dynamic_statics/{filename}" ).route( web::get().to( handler_dynamic_statics ) ) )
+        .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree" ).route( web::get().to( handler_dynamic_statics_ascii_tree ) ) )
+        .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree/{filename}" ).route( web::get().to( handler_dynamic_statics_ascii_tree ) ) )
+        .service( web::resource( "/data/{id}/debug_symbols" ).route( web::post().to_async( handler_debug_symbols ) ) )
+        .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
+        .service( web::resource( "/data/{id}/execute_script_async" ).route( web::post().to( handler_execute_script_async ) ) )
+        .service( web::resource( "/data/{id}/check_script" ).route( web::post().to( handler_check_script ) ) )
+        .service( web::resource( "/execute_script_all" ).route( web::post().to( handler_execute_script_all ) ) )
+        .service( web::resource( "/script_jobs/{job_id}" ).route( web::get().to( handler_script_job ) ) )
+        .service( web::resource( "/script_jobs/{job_id}/output" ).route( web::get().to( handler_script_job_output ) ) )
+        .service( web::resource( "/script_jobs/{job_id}/stream" ).route( web::get().to( handler_script_job_stream ) ) )
+        .service( web::resource( "/data/{id}/script_session" ).route( web::post().to( handler_create_script_session ) ) )
+        .service(
+            web::resource( "/script_session/{session_id}" )
+                .route( web::delete().to( handler_delete_script_session ) )
+        )
+        .service( web::resource( "/script_session/{session_id}/eval" ).route( web::post().to_async( handler_script_session_eval ) ) )
+        .service( web::resource( "/scripts" ).route( web::get().to( handler_list_scripts ) ) )
+        .service(
+            web::resource( "/scripts/{name}" )
+                .data( web::PayloadConfig::new( MAXIMUM_SCRIPT_SIZE ) )
+                .route( web::get().to( handler_get_script ) )
+                .route( web::put().to( handler_put_script ) )
+                .route( web::delete().to( handler_delete_script ) )
+        )
+        .service(
+            web::resource( "/data/{id}/script_files/{hash}/{filename}" )
+                .route( web::get().to( handler_script_files ) )
+                .route( web::head().to( handler_script_files ) )
+        )
+        .service( web::resource( "/data/{id}/filter_to_script" ).route( web::get().to( handler_filter_to_script ) ) )
+    ;
 
-        for handle in handles {
-            let data = handle.join().unwrap()?;
-            state.add_data( data );
+    for (key, bytes) in WEBUI_ASSETS {
+        app.service( web::resource( &format!( "/{}", key ) ).route( web::get().to( move || StaticResponse( key, bytes ) ) ) );
+        if *key == "index.html" {
+            app.service( web::resource( "/" ).route( web::get().to( move || StaticResponse( key, bytes ) ) ) );
         }
     }
+}
+
+fn configure_routes_under( base_path: &str, app: &mut web::ServiceConfig ) {
+    if base_path.is_empty() {
+        configure_routes( app );
+        return;
+    }
+
+    let redirect_base_path = base_path.to_owned();
+    app
+        .service( web::resource( base_path ).route( web::get().to( move |req: HttpRequest| redirect_to_base_path( &redirect_base_path, req ) ) ) )
+        .service( web::scope( base_path ).configure( configure_routes ) );
+}
+
+pub fn main( inputs: Vec< PathBuf >, debug_symbols: Vec< PathBuf >, load_in_parallel: bool, interface: &str, port: u16, options: ServerOptions ) -> Result< (), ServerError > {
+    let mut state = State::new();
+    state.source_prefix_map = options.source_prefix_map.clone();
+    state.source_files = Arc::new( SourceFiles::new( &options.source_roots ) );
+    state.script_limits = ScriptLimits {
+        timeout: options.script_timeout,
+        max_output_size: options.script_max_output_size,
+        max_collection_size: None,
+        max_table_rows: Some( options.script_max_table_rows ),
+        max_files_size: Some( options.script_max_files_size ),
+        allow_fs: options.allow_script_fs,
+        deadline: Deadline::none()
+    };
+    state.scripts_disabled = options.disable_scripts;
+    state.auth_token = options.auth_token.clone();
+    state.cors_origins = options.cors_origins.clone();
+    if options.metrics {
+        state.metrics = Some( Default::default() );
+    }
+    state.base_path = options.base_path.clone();
+    state.log_requests = options.log_requests;
+    state.slow_request_threshold = options.slow_request_threshold;
+    state.default_deadline = options.default_deadline;
+    state.generated_files = Mutex::new( GeneratedFilesCollection::new( options.generated_files_cache_size, options.generated_files_dir.clone() ) );
+    if let Some( heavy_workers ) = options.heavy_workers {
+        state.heavy_workers = worker_pool::WorkerPool::new( heavy_workers, MAXIMUM_QUEUED_HEAVY_REQUESTS );
+    }
+    if state.auth_token.is_none() && options.generate_auth_token {
+        let token = auth::generate_token()?;
+        println!( "Authentication token: {}", token );
+        println!( "Open http://{}:{}/?token={} to access the web UI", interface, port, token );
+        state.auth_token = Some( token );
+    }
+    state.script_session_idle_timeout = options.script_session_idle_timeout;
+    state.script_library = ScriptLibrary::new( options.scripts_dir.clone() );
+    state.aliases = Mutex::new( aliases::Aliases::new( options.aliases_file.clone() )? );
+
+    state.inputs = RwLock::new( inputs.into_iter().map( |path| Arc::new( loading::InputFile::new( path ).with_range( options.load_range ) ) ).collect() );
+    state.debug_symbols = debug_symbols;
+    state.precompute = options.precompute;
+    state.cache_dir = options.cache_dir.clone();
+    state.cache_size_limit = options.cache_size_limit;
+    state.low_memory = options.low_memory;
+    state.data_dirs = options.data_dirs.iter().map( |dir| dir.canonicalize() ).collect::< Result< _, _ > >()?;
 
     for (key, bytes) in WEBUI_ASSETS {
         debug!( "Static asset: '{}', length = {}", key, bytes.len() );
     }
 
+    // The data files are loaded in the background so that the server can report
+    // its progress through `/readyz` in the meantime. The precomputation is then
+    // queued on the worker pool, where it only runs when there are no requests waiting.
     let state = Arc::new( state );
+    let loader = loading::load_inputs( state.clone(), load_in_parallel, options.strict );
+    if options.strict {
+        // Nothing is served until we know that every file was loaded.
+        let _ = loader.join();
+        if let Some( error ) = loading::first_failure( &state ) {
+            return Err( error.into() );
+        }
+    }
+
+    if let Some( ref address ) = options.listen_for_profiles {
+        let listener = std::net::TcpListener::bind( address ).map_err( |error| {
+            io::Error::new( error.kind(), format!( "failed to listen for live profiles on {}: {}", address, error ) )
+        })?;
+        info!( "Listening for live profiles on {}", address );
+        live::listen( state.clone(), listener, live::SNAPSHOT_INTERVAL );
+    }
+
     let sys = actix::System::new( "server" );
     actix_web::HttpServer::new( move || {
         App::new().data( state.clone() )
-            .wrap( Cors::new() )
-            .configure( |app| {
-                app
-                    .service( web::resource( "/list" ).route( web::get().to( handler_list ) ) )
-                    .service( web::resource( "/data/{id}/timeline" ).route( web::get().to( handler_timeline ) ) )
-                    .service( web::resource( "/data/{id}/timeline_leaked" ).route( web::get().to( handler_timeline_leaked ) ) )
-                    .service( web::resource( "/data/{id}/fragmentation_timeline" ).route( web::get().to( handler_fragmentation_timeline ) ) )
-                    .service( web::resource( "/data/{id}/allocations" ).route( web::get().to( handler_allocations ) ) )
-                    .service( web::resource( "/data/{id}/allocation_groups" ).route( web::get().to( handler_allocation_groups ) ) )
-                    .service( web::resource( "/data/{id}/backtraces" ).route( web::get().to( handler_backtraces ) ) )
-                    .service( web::resource( "/data/{id}/raw_allocations" ).route( web::get().to( handler_raw_allocations ) ) )
-                    .service( web::resource( "/data/{id}/tree" ).route( web::get().to( handler_tree ) ) )
-                    .service( web::resource( "/data/{id}/mmaps" ).route( web::get().to( handler_mmaps ) ) )
-                    .service( web::resource( "/data/{id}/backtrace/{backtrace_id}" ).route( web::get().to( handler_backtrace ) ) )
-                    .service( web::resource( "/data/{id}/regions" ).route( web::get().to( handler_regions ) ) )
-                    .service( web::resource( "/data/{id}/mallopts" ).route( web::get().to( handler_mallopts ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph" ).route( web::get().to( handler_export_flamegraph ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph/{filename}" ).route( web::get().to( handler_export_flamegraph ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph.pl" ).route( web::get().to( handler_export_flamegraph_pl ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph.pl/{filename}" ).route( web::get().to( handler_export_flamegraph_pl ) ) )
-                    .service( web::resource( "/data/{id}/export/heaptrack" ).route( web::get().to( handler_export_heaptrack ) ) )
-                    .service( web::resource( "/data/{id}/export/heaptrack/{filename}" ).route( web::get().to( handler_export_heaptrack ) ) )
-                    .service( web::resource( "/data/{id}/export/replay" ).route( web::get().to( handler_export_replay ) ) )
-                    .service( web::resource( "/data/{id}/export/replay/{filename}" ).route( web::get().to( handler_export_replay ) ) )
-                    .service( web::resource( "/data/{id}/allocation_ascii_tree" ).route( web::get().to( handler_allocation_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants" ).route( web::get().to( handler_dynamic_constants ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants/{filename}" ).route( web::get().to( handler_dynamic_constants ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree" ).route( web::get().to( handler_dynamic_constants_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree/{filename}" ).route( web::get().to( handler_dynamic_constants_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics" ).route( web::get().to( handler_dynamic_statics ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics/{filename}" ).route( web::get().to( handler_dynamic_statics ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree" ).route( web::get().to( handler_dynamic_statics_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree/{filename}" ).route( web::get().to( handler_dynamic_statics_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/execute_script" ).route( web::post().to( handler_execute_script ) ) )
-                    .service( web::resource( "/data/{id}/script_files/{hash}/{filename}" ).route( web::get().to( handler_script_files ) ) )
-                    .service( web::resource( "/data/{id}/filter_to_script" ).route( web::get().to( handler_filter_to_script ) ) )
-                ;
-
-                for (key, bytes) in WEBUI_ASSETS {
-                    app.service( web::resource( &format!( "/{}", key ) ).route( web::get().to( move || StaticResponse( key, bytes ) ) ) );
-                    if *key == "index.html" {
-                        app.service( web::resource( "/" ).route( web::get().to( move || StaticResponse( key, bytes ) ) ) );
-                    }
-                }
-            })
+            .wrap_fn( deadline::middleware )
+            .wrap_fn( etag::middleware )
+            .wrap_fn( auth::middleware )
+            .wrap( cors::new_cors( &state.cors_origins ) )
+            .wrap_fn( cors::middleware )
+            .wrap_fn( api_error::middleware )
+            .wrap_fn( metrics::middleware )
+            .wrap_fn( request_log::middleware )
+            .configure( |app| configure_routes_under( &state.base_path, app ) )
     }).bind( &format!( "{}:{}", interface, port ) ).map_err( |err| ServerError::BindFailed( err ) )?
         .shutdown_timeout( 1 )
         .start();
@@ -0,0 +1,147 @@
+use std::io::{self, Read};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cli_core::Loader;
+
+use crate::State;
+use crate::loading::{Provenance, Source};
+
+/// How often the data which is still being received is made available by default.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs( 2 );
+
+/// How many profiles can be received at the same time; any further connections are closed right away.
+const MAXIMUM_CONNECTIONS: usize = 16;
+
+/// How long the authentication token can take to arrive.
+const AUTH_TIMEOUT: Duration = Duration::from_secs( 10 );
+
+/// The longest line with the authentication token which is accepted.
+const MAXIMUM_AUTH_LINE_LENGTH: usize = 256;
+
+struct ConnectionGuard( Arc< AtomicUsize > );
+
+impl Drop for ConnectionGuard {
+    fn drop( &mut self ) {
+        self.0.fetch_sub( 1, Ordering::SeqCst );
+    }
+}
+
+/// Accepts the data streamed by running processes, making what was received so far available
+/// as it comes in; once a stream ends its data becomes a normal, static one.
+///
+/// Each connection is expected to send the contents of a data file, exactly as it'd be written to the disk.
+/// If the server requires an authentication token then it has to be sent first, on a line of its own.
+pub fn listen( state: Arc< State >, listener: TcpListener, snapshot_interval: Duration ) -> thread::JoinHandle< () > {
+    let connections = Arc::new( AtomicUsize::new( 0 ) );
+    thread::spawn( move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok( stream ) => stream,
+                Err( error ) => {
+                    warn!( "Failed to accept a live profile: {}", error );
+                    continue;
+                }
+            };
+
+            if connections.fetch_add( 1, Ordering::SeqCst ) >= MAXIMUM_CONNECTIONS {
+                connections.fetch_sub( 1, Ordering::SeqCst );
+                warn!( "Rejected a live profile: already receiving {} of them", MAXIMUM_CONNECTIONS );
+                continue;
+            }
+
+            let guard = ConnectionGuard( connections.clone() );
+            let state = state.clone();
+            thread::spawn( move || {
+                let _guard = guard;
+                receive( state, stream, snapshot_interval );
+            });
+        }
+    })
+}
+
+/// Reads the line with the authentication token, one byte at a time so that nothing after it is consumed.
+fn read_token( stream: &mut TcpStream ) -> io::Result< String > {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    loop {
+        stream.read_exact( &mut byte )?;
+        if byte[ 0 ] == b'\n' {
+            break;
+        }
+
+        if line.len() >= MAXIMUM_AUTH_LINE_LENGTH {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, "the authentication token is too long" ) );
+        }
+
+        line.push( byte[ 0 ] );
+    }
+
+    let line = String::from_utf8( line ).map_err( |_| io::Error::new( io::ErrorKind::InvalidData, "the authentication token is not valid UTF-8" ) )?;
+    Ok( line.trim().to_owned() )
+}
+
+fn authenticate( state: &State, stream: &mut TcpStream ) -> io::Result< () > {
+    let expected = match state.auth_token {
+        Some( ref token ) => token,
+        None => return Ok(())
+    };
+
+    stream.set_read_timeout( Some( AUTH_TIMEOUT ) )?;
+    let token = read_token( stream )?;
+    stream.set_read_timeout( None )?;
+
+    if !crate::auth::tokens_equal( &token, expected ) {
+        return Err( io::Error::new( io::ErrorKind::PermissionDenied, "invalid authentication token" ) );
+    }
+
+    Ok(())
+}
+
+fn receive( state: Arc< State >, mut stream: TcpStream, snapshot_interval: Duration ) {
+    let address = stream.peer_addr().map( |address| address.to_string() ).unwrap_or_else( |_| "unknown".into() );
+    if let Err( error ) = authenticate( &state, &mut stream ) {
+        warn!( "Rejected a live profile from {}: {}", address, error );
+        return;
+    }
+
+    info!( "Receiving a live profile from {}...", address );
+
+    let started = Instant::now();
+    let snapshot_state = state.clone();
+    let snapshot_address = address.clone();
+    let result = Loader::load_from_live_stream( stream, &state.debug_symbols, snapshot_interval, move |data| {
+        let id = data.id();
+        if !snapshot_state.update_live_data( data, false ) {
+            return Err( io::Error::new( io::ErrorKind::AlreadyExists, format!( "{} is already loaded", id ) ) );
+        }
+
+        snapshot_state.provenance.lock().insert( id, Provenance::new( Source::Live( snapshot_address.clone() ), None, started ) );
+
+        Ok(())
+    });
+
+    let data = match result {
+        Ok( data ) => data,
+        Err( error ) => {
+            error!( "Failed to receive a live profile from {}: {}", address, error );
+            return;
+        }
+    };
+
+    let id = data.id();
+    if state.precompute {
+        state.precompute_progress.lock().insert( id, Default::default() );
+    }
+
+    state.provenance.lock().insert( id, Provenance::new( Source::Live( address.clone() ), None, started ) );
+    state.update_live_data( data, true );
+    info!( "Finished receiving {} from {}", id, address );
+
+    if state.precompute {
+        crate::schedule_precompute( &state, id );
+    }
+}
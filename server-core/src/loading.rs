@@ -0,0 +1,499 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use cli_core::{Data, Loader, LoadOptions, LoadPhase, LoadRange, parse_events};
+use common::event::DataId;
+use parking_lot::Mutex;
+
+use crate::State;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LoadStatus {
+    Loading,
+    Loaded( DataId ),
+    Failed( String )
+}
+
+/// What we know about a file from its header before it's fully loaded.
+#[derive(Clone)]
+pub struct InputHeader {
+    pub id: DataId,
+    pub executable: String
+}
+
+/// Where the data came from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    File( PathBuf ),
+    Upload,
+    Merge,
+    /// Received from a running process with the given address.
+    Live( String )
+}
+
+impl Source {
+    pub fn kind( &self ) -> &'static str {
+        match *self {
+            Source::File( .. ) => "file",
+            Source::Upload => "upload",
+            Source::Merge => "merge",
+            Source::Live( .. ) => "live"
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Provenance {
+    pub source: Source,
+    /// The size of the data file, if there was one.
+    pub source_size: Option< u64 >,
+    /// When the data became available.
+    pub loaded_at: SystemTime,
+    pub load_duration: Duration
+}
+
+impl Provenance {
+    pub fn new( source: Source, source_size: Option< u64 >, started: Instant ) -> Self {
+        Provenance {
+            source,
+            source_size,
+            loaded_at: SystemTime::now(),
+            load_duration: started.elapsed()
+        }
+    }
+}
+
+/// A single data file which was given to the server, either on the command line or through an upload.
+pub struct InputFile {
+    pub path: PathBuf,
+    /// Which part of the file should be loaded; if `None` it's loaded whole.
+    pub range: Option< LoadRange >,
+    /// Whether the file was uploaded, in which case it's deleted right after it's loaded.
+    pub is_upload: bool,
+    /// Whether the file was loaded on request instead of being given on the command line,
+    /// in which case it's only kept in the list of the inputs while it's loading or if it has failed to load.
+    pub is_on_request: bool,
+    size: AtomicU64,
+    bytes_read: AtomicU64,
+    phase: Mutex< Option< LoadPhase > >,
+    header: Mutex< Option< InputHeader > >,
+    status: Mutex< LoadStatus >
+}
+
+impl InputFile {
+    pub fn new( path: PathBuf ) -> Self {
+        InputFile {
+            path,
+            range: None,
+            is_upload: false,
+            is_on_request: false,
+            size: AtomicU64::new( 0 ),
+            bytes_read: AtomicU64::new( 0 ),
+            phase: Mutex::new( None ),
+            header: Mutex::new( None ),
+            status: Mutex::new( LoadStatus::Loading )
+        }
+    }
+
+    pub fn with_range( mut self, range: Option< LoadRange > ) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn status( &self ) -> LoadStatus {
+        self.status.lock().clone()
+    }
+
+    pub fn header( &self ) -> Option< InputHeader > {
+        self.header.lock().clone()
+    }
+
+    /// Returns the stage of loading the file is in, if it's still being loaded.
+    pub fn phase( &self ) -> Option< LoadPhase > {
+        if *self.status.lock() != LoadStatus::Loading {
+            return None;
+        }
+
+        *self.phase.lock()
+    }
+
+    /// Checks whether this is the file with the given ID and it's still being loaded.
+    pub fn is_loading( &self, id: DataId ) -> bool {
+        *self.status.lock() == LoadStatus::Loading && self.header.lock().as_ref().map( |header| header.id == id ).unwrap_or( false )
+    }
+
+    /// Returns how much of the file was already loaded, in percent.
+    pub fn progress( &self ) -> f64 {
+        if *self.status.lock() != LoadStatus::Loading {
+            return 100.0;
+        }
+
+        let size = self.size.load( Ordering::Relaxed );
+        if size == 0 {
+            return 0.0;
+        }
+
+        let bytes_read = self.bytes_read.load( Ordering::Relaxed );
+        (bytes_read as f64 / size as f64 * 100.0).min( 100.0 )
+    }
+
+    pub fn to_json( &self ) -> serde_json::Value {
+        let mut value = serde_json::json! {{
+            "path": self.path.to_string_lossy(),
+            "progress": self.progress(),
+            "bytes_read": self.bytes_read.load( Ordering::Relaxed ),
+            "size": self.size.load( Ordering::Relaxed )
+        }};
+
+        if let Some( phase ) = self.phase() {
+            value[ "phase" ] = phase.name().into();
+        }
+
+        if let Some( header ) = self.header() {
+            value[ "id" ] = format!( "{}", header.id ).into();
+            value[ "executable" ] = header.executable.into();
+        }
+
+        match self.status() {
+            LoadStatus::Loading => {
+                value[ "status" ] = "loading".into();
+            },
+            LoadStatus::Loaded( id ) => {
+                value[ "status" ] = "loaded".into();
+                value[ "id" ] = format!( "{}", id ).into();
+            },
+            LoadStatus::Failed( error ) => {
+                value[ "status" ] = "failed".into();
+                value[ "error" ] = error.into();
+            }
+        }
+
+        value
+    }
+}
+
+/// How often, in percent, the progress of reading a data file is logged.
+const LOG_INTERVAL: u64 = 5;
+
+/// Reads only the header so that the file can be identified while it's being loaded.
+pub fn read_header( input: &InputFile ) -> io::Result< () > {
+    let (header, _) = parse_events( File::open( &input.path )? )?;
+    *input.header.lock() = Some( InputHeader {
+        id: header.id,
+        executable: String::from_utf8_lossy( &header.executable ).into_owned()
+    });
+
+    Ok(())
+}
+
+fn load( state: &State, input: &InputFile ) -> io::Result< (Data, Provenance) > {
+    let started = Instant::now();
+
+    // The allocations are written straight into a memory mapped file as they're loaded,
+    // so they never have to be all in memory at the same time.
+    let allocations_directory = if state.low_memory {
+        Some( state.cache_dir.clone().unwrap_or_else( std::env::temp_dir ) )
+    } else {
+        None
+    };
+
+    // Only the whole files are cached; there's no point in caching the uploads since they're deleted right away.
+    let data = match state.cache_dir {
+        Some( ref cache_dir ) if !input.is_upload && input.range.is_none() => {
+            cli_core::load_with_cache( cache_dir, state.cache_size_limit, &input.path, &state.debug_symbols, allocations_directory.as_deref(), || {
+                load_uncached( input, &state.debug_symbols, allocations_directory.clone() )
+            })?
+        },
+        _ => load_uncached( input, &state.debug_symbols, allocations_directory )?
+    };
+
+    let source = if input.is_upload { Source::Upload } else { Source::File( input.path.clone() ) };
+    let source_size = fs::metadata( &input.path ).map( |metadata| metadata.len() ).ok();
+    Ok( (data, Provenance::new( source, source_size, started )) )
+}
+
+fn load_uncached( input: &InputFile, debug_symbols: &[PathBuf], allocations_directory: Option< PathBuf > ) -> io::Result< Data > {
+    info!( "Trying to load {:?}...", input.path );
+    let fp = File::open( &input.path )?;
+    let size = fp.metadata()?.len();
+    input.size.store( size, Ordering::Relaxed );
+
+    let options = LoadOptions {
+        range: input.range,
+        allocations_directory
+    };
+
+    let mut last_logged = 0;
+    Loader::load_from_stream_with_progress( fp, debug_symbols, &options, |progress| {
+        input.bytes_read.store( progress.bytes_read, Ordering::Relaxed );
+
+        let mut phase = input.phase.lock();
+        if *phase != Some( progress.phase ) {
+            *phase = Some( progress.phase );
+            if progress.phase != LoadPhase::Reading {
+                info!( "Loading {:?}: {}...", input.path, progress.phase.name() );
+            }
+        }
+
+        if progress.phase == LoadPhase::Reading && size != 0 {
+            let percent = progress.bytes_read.saturating_mul( 100 ) / size;
+            if percent >= last_logged + LOG_INTERVAL {
+                last_logged = percent - percent % LOG_INTERVAL;
+                info!( "Loading {:?}: {}% read", input.path, last_logged );
+            }
+        }
+    })
+}
+
+/// Returns `false` if the file has failed to load.
+fn finish( state: &State, input: &InputFile, result: io::Result< (Data, Provenance) > ) -> bool {
+    match result {
+        Ok( (data, provenance) ) => {
+            let id = data.id();
+            if state.precompute {
+                state.precompute_progress.lock().insert( id, Default::default() );
+            }
+
+            state.provenance.lock().insert( id, provenance );
+            state.add_data( data );
+            *input.status.lock() = LoadStatus::Loaded( id );
+            true
+        },
+        Err( error ) => {
+            error!( "Failed to load {:?}: {}", input.path, error );
+            *input.status.lock() = LoadStatus::Failed( error.to_string() );
+            false
+        }
+    }
+}
+
+/// Returns the error of the first input file which has failed to load, if any.
+pub fn first_failure( state: &State ) -> Option< io::Error > {
+    state.inputs.read().iter().find_map( |input| match input.status() {
+        LoadStatus::Failed( error ) => Some( io::Error::new( io::ErrorKind::Other, format!( "failed to load {:?}: {}", input.path, error ) ) ),
+        _ => None
+    })
+}
+
+/// Loads every input file in the background, making each data file available as soon as it's loaded.
+///
+/// A file which fails to load doesn't prevent the other ones from being loaded,
+/// unless `strict` is set, in which case we give up as soon as any of them fails.
+pub fn load_inputs( state: Arc< State >, load_in_parallel: bool, strict: bool ) -> thread::JoinHandle< () > {
+    thread::spawn( move || {
+        let inputs = state.inputs.read().clone();
+
+        // This is cheap, and lets us tell which data files are still being loaded right away.
+        for input in inputs.iter() {
+            if let Err( error ) = read_header( input ) {
+                // This will be reported once we actually try to load it.
+                debug!( "Failed to read the header of {:?}: {}", input.path, error );
+            }
+        }
+
+        if !load_in_parallel {
+            for input in inputs.iter() {
+                let result = load( &state, input );
+                if !finish( &state, input, result ) && strict {
+                    return;
+                }
+            }
+        } else {
+            let handles: Vec< _ > = inputs.iter().map( |input| {
+                let input = input.clone();
+                let state = state.clone();
+                thread::spawn( move || load( &state, &input ) )
+            }).collect();
+
+            // The data files are added in the order they were given so that `last` always refers to the same one.
+            for (input, handle) in inputs.iter().zip( handles ) {
+                let result = handle.join().unwrap_or_else( |_| Err( io::Error::new( io::ErrorKind::Other, "the loader has panicked" ) ) );
+                if !finish( &state, input, result ) && strict {
+                    return;
+                }
+            }
+        }
+
+        if state.precompute {
+            for data in state.datasets() {
+                crate::schedule_precompute( &state, data.id() );
+            }
+        }
+    })
+}
+
+/// A temporary file to which a data file is written while it's being uploaded.
+///
+/// It's deleted when dropped, so nothing is left behind if the upload is interrupted.
+pub struct Upload {
+    path: PathBuf,
+    file: File
+}
+
+impl Upload {
+    pub fn create() -> io::Result< Self > {
+        static COUNTER: AtomicUsize = AtomicUsize::new( 0 );
+        let filename = format!( "bytehound-upload-{}-{}.dat", std::process::id(), COUNTER.fetch_add( 1, Ordering::Relaxed ) );
+        let path = std::env::temp_dir().join( filename );
+        let file = OpenOptions::new().write( true ).create_new( true ).open( &path )?;
+        Ok( Upload { path, file } )
+    }
+
+    pub fn write( &mut self, chunk: &[u8] ) -> io::Result< () > {
+        self.file.write_all( chunk )
+    }
+}
+
+impl Drop for Upload {
+    fn drop( &mut self ) {
+        let _ = fs::remove_file( &self.path );
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    InvalidFile( io::Error ),
+    AlreadyLoaded( DataId ),
+    /// No data directories were configured, so nothing can be loaded by path.
+    Disabled,
+    NotFound,
+    /// The path is outside of the data directories.
+    Forbidden
+}
+
+/// How many of the files which were loaded on request and have failed to load are kept around so that their errors can be reported.
+const MAXIMUM_FAILED_REQUESTS: usize = 16;
+
+/// Forgets the oldest files which were loaded on request and have failed to load, making room for one more.
+fn forget_old_failures( inputs: &mut Vec< Arc< InputFile > > ) {
+    let is_failed_request = |input: &InputFile| input.is_on_request && matches!( input.status(), LoadStatus::Failed( _ ) );
+    let failed_count = inputs.iter().filter( |input| is_failed_request( input ) ).count();
+    let mut excess = (failed_count + 1).saturating_sub( MAXIMUM_FAILED_REQUESTS );
+    inputs.retain( |input| {
+        if excess > 0 && is_failed_request( input ) {
+            excess -= 1;
+            false
+        } else {
+            true
+        }
+    });
+}
+
+#[test]
+fn test_forget_old_failures() {
+    let new_input = |index: usize, status: LoadStatus, is_on_request: bool| {
+        let mut input = InputFile::new( PathBuf::from( format!( "{}.dat", index ) ) );
+        input.is_on_request = is_on_request;
+        *input.status.lock() = status;
+        Arc::new( input )
+    };
+
+    let mut inputs = vec![ new_input( 0, LoadStatus::Failed( "error".into() ), false ) ];
+    for index in 1..20 {
+        forget_old_failures( &mut inputs );
+        inputs.push( new_input( index, LoadStatus::Failed( "error".into() ), true ) );
+    }
+
+    forget_old_failures( &mut inputs );
+    inputs.push( new_input( 20, LoadStatus::Loading, true ) );
+
+    // The files given on the command line are always kept.
+    let paths: Vec< _ > = inputs.iter().map( |input| input.path.to_str().unwrap().to_owned() ).collect();
+    let mut expected = vec![ "0.dat".to_owned() ];
+    expected.extend( (5..21).map( |index| format!( "{}.dat", index ) ) );
+    assert_eq!( paths, expected );
+}
+
+/// Starts loading a data file in the background.
+///
+/// Only the header is read right away, so that the data files which are
+/// already loaded (or are being loaded) can be rejected.
+fn start_loading( state: Arc< State >, mut input: InputFile, upload: Option< Upload > ) -> Result< DataId, LoadError > {
+    input.is_on_request = true;
+    let input = Arc::new( input );
+    read_header( &input ).map_err( LoadError::InvalidFile )?;
+    let id = input.header().unwrap().id;
+
+    {
+        let mut inputs = state.inputs.write();
+        if state.data.read().contains_key( &id ) || inputs.iter().any( |input| input.is_loading( id ) ) {
+            return Err( LoadError::AlreadyLoaded( id ) );
+        }
+
+        forget_old_failures( &mut inputs );
+        inputs.push( input.clone() );
+    }
+
+    thread::spawn( move || {
+        let result = load( &state, &input );
+
+        // The uploaded file isn't needed anymore once it's loaded.
+        drop( upload );
+
+        if !finish( &state, &input, result ) {
+            return;
+        }
+
+        // The data is listed on its own now.
+        state.inputs.write().retain( |other| !Arc::ptr_eq( other, &input ) );
+        if state.precompute {
+            crate::schedule_precompute( &state, id );
+        }
+    });
+
+    Ok( id )
+}
+
+pub fn load_upload( state: Arc< State >, mut upload: Upload ) -> Result< DataId, LoadError > {
+    upload.file.flush().map_err( LoadError::InvalidFile )?;
+
+    let mut input = InputFile::new( upload.path.clone() );
+    input.is_upload = true;
+    let id = start_loading( state, input, Some( upload ) )?;
+    info!( "Received an upload of {}", id );
+    Ok( id )
+}
+
+/// Starts loading a data file which was already checked with `resolve_data_path`.
+pub fn load_file( state: Arc< State >, path: PathBuf, range: Option< LoadRange > ) -> Result< DataId, LoadError > {
+    info!( "Loading {:?} on request...", path );
+    start_loading( state, InputFile::new( path ).with_range( range ), None )
+}
+
+/// Resolves a path to a data file given by a client, making sure that it's inside one of the `data_dirs`.
+///
+/// The `data_dirs` must already be canonicalized. Relative paths are relative to the first data directory
+/// in which they exist. Symbolic links are resolved before checking, so they can't be used to escape
+/// the data directories; `..` is always rejected, so the existence of the files outside of
+/// the data directories can't be probed through it either.
+pub fn resolve_data_path( data_dirs: &[PathBuf], path: &Path ) -> Result< PathBuf, LoadError > {
+    if data_dirs.is_empty() {
+        return Err( LoadError::Disabled );
+    }
+
+    if path.components().any( |component| component == Component::ParentDir ) {
+        return Err( LoadError::Forbidden );
+    }
+
+    let canonical_path = if path.is_absolute() {
+        if !data_dirs.iter().any( |dir| path.starts_with( dir ) ) {
+            return Err( LoadError::Forbidden );
+        }
+
+        path.canonicalize().ok()
+    } else {
+        data_dirs.iter().filter_map( |dir| dir.join( path ).canonicalize().ok() ).next()
+    };
+
+    let canonical_path = canonical_path.ok_or( LoadError::NotFound )?;
+    if !data_dirs.iter().any( |dir| canonical_path.starts_with( dir ) ) {
+        return Err( LoadError::Forbidden );
+    }
+
+    Ok( canonical_path )
+}
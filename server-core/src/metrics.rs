@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::{Error, HttpRequest};
+use actix_web::body::{Body, BodySize, MessageBody};
+use actix_web::dev::{ResponseBody, Service, ServiceRequest, ServiceResponse};
+use bytes::Bytes;
+use futures::{Async, Poll};
+use futures::future::{Either, Future};
+use parking_lot::Mutex;
+
+use crate::StateRef;
+
+/// The upper bounds of the request latency histogram's buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[ 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0 ];
+
+#[derive(Default)]
+struct RouteMetrics {
+    requests_by_status: BTreeMap< u16, u64 >,
+    latency_buckets: Vec< u64 >,
+    latency_sum: f64,
+    latency_count: u64
+}
+
+/// Server-wide counters exported through `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    routes: Mutex< BTreeMap< (String, String), RouteMetrics > >,
+    bytes_streamed: AtomicU64,
+    script_executions: AtomicU64,
+    script_failures: AtomicU64,
+    cancelled_requests: AtomicU64
+}
+
+impl Metrics {
+    fn record_request( &self, method: &str, route: String, status: u16, elapsed: Duration ) {
+        let elapsed = elapsed.as_secs_f64();
+        let mut routes = self.routes.lock();
+        let metrics = routes.entry( (method.to_owned(), route) ).or_default();
+        *metrics.requests_by_status.entry( status ).or_insert( 0 ) += 1;
+        if metrics.latency_buckets.is_empty() {
+            metrics.latency_buckets = vec![ 0; LATENCY_BUCKETS.len() ];
+        }
+
+        for (count, &bound) in metrics.latency_buckets.iter_mut().zip( LATENCY_BUCKETS ) {
+            if elapsed <= bound {
+                *count += 1;
+            }
+        }
+
+        metrics.latency_sum += elapsed;
+        metrics.latency_count += 1;
+    }
+
+    pub fn record_script_execution( &self, failed: bool ) {
+        self.script_executions.fetch_add( 1, Ordering::Relaxed );
+        if failed {
+            self.script_failures.fetch_add( 1, Ordering::Relaxed );
+        }
+    }
+
+    pub fn record_cancelled_request( &self ) {
+        self.cancelled_requests.fetch_add( 1, Ordering::Relaxed );
+    }
+
+    pub fn write_to( &self, output: &mut String ) {
+        let routes = self.routes.lock();
+
+        write_header( output, "bytehound_http_requests_total", "counter", "The number of handled HTTP requests." );
+        for ((method, route), metrics) in routes.iter() {
+            for (status, count) in &metrics.requests_by_status {
+                writeln!( output, "bytehound_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}", method, escape( route ), status, count ).unwrap();
+            }
+        }
+
+        write_header( output, "bytehound_http_request_duration_seconds", "histogram", "How long it took to start sending the response." );
+        for ((method, route), metrics) in routes.iter() {
+            let labels = format!( "method=\"{}\",route=\"{}\"", method, escape( route ) );
+            for (count, bound) in metrics.latency_buckets.iter().zip( LATENCY_BUCKETS ) {
+                writeln!( output, "bytehound_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count ).unwrap();
+            }
+            writeln!( output, "bytehound_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, metrics.latency_count ).unwrap();
+            writeln!( output, "bytehound_http_request_duration_seconds_sum{{{}}} {}", labels, metrics.latency_sum ).unwrap();
+            writeln!( output, "bytehound_http_request_duration_seconds_count{{{}}} {}", labels, metrics.latency_count ).unwrap();
+        }
+
+        write_metric( output, "bytehound_http_response_bytes_total", "counter", "The number of bytes sent in response bodies.", self.bytes_streamed.load( Ordering::Relaxed ) );
+        write_metric( output, "bytehound_script_executions_total", "counter", "The number of executed scripts.", self.script_executions.load( Ordering::Relaxed ) );
+        write_metric( output, "bytehound_script_failures_total", "counter", "The number of executed scripts which have failed.", self.script_failures.load( Ordering::Relaxed ) );
+        write_metric( output, "bytehound_cancelled_requests_total", "counter", "The number of requests whose client has disconnected before the whole response was sent.", self.cancelled_requests.load( Ordering::Relaxed ) );
+    }
+}
+
+pub fn escape( value: &str ) -> String {
+    value.replace( '\\', "\\\\" ).replace( '"', "\\\"" ).replace( '\n', "\\n" )
+}
+
+pub fn write_header( output: &mut String, name: &str, kind: &str, help: &str ) {
+    writeln!( output, "# HELP {} {}", name, help ).unwrap();
+    writeln!( output, "# TYPE {} {}", name, kind ).unwrap();
+}
+
+pub fn write_metric( output: &mut String, name: &str, kind: &str, help: &str, value: u64 ) {
+    write_header( output, name, kind, help );
+    writeln!( output, "{} {}", name, value ).unwrap();
+}
+
+/// Returns the pattern of the route which has handled the request, e.g. `/data/{id}/timeline`,
+/// so that requests for different data files or backtraces are counted together.
+fn route_of( req: &HttpRequest, status: u16 ) -> String {
+    let params: Vec< _ > = req.match_info().iter().collect();
+    if params.is_empty() && status == 404 {
+        // Don't create a new time series for every nonexistent path someone requests.
+        return "<unknown>".into();
+    }
+
+    req.path().split( '/' ).map( |segment| {
+        match params.iter().find( |(_, value)| *value == segment && !segment.is_empty() ) {
+            Some( (name, _) ) => format!( "{{{}}}", name ),
+            None => segment.to_owned()
+        }
+    }).collect::< Vec< _ > >().join( "/" )
+}
+
+/// Counts the bytes of the response's body as they're being sent.
+struct CountingBody {
+    body: ResponseBody< Body >,
+    state: StateRef
+}
+
+impl MessageBody for CountingBody {
+    fn size( &self ) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next( &mut self ) -> Poll< Option< Bytes >, Error > {
+        let result = self.body.poll_next();
+        if let Ok( Async::Ready( Some( ref bytes ) ) ) = result {
+            if let Some( ref metrics ) = self.state.metrics {
+                metrics.bytes_streamed.fetch_add( bytes.len() as u64, Ordering::Relaxed );
+            }
+        }
+
+        result
+    }
+}
+
+/// A middleware which records every request's route, status, latency and the size of its response.
+pub fn middleware< S >( req: ServiceRequest, service: &mut S ) -> impl Future< Item = ServiceResponse< Body >, Error = Error >
+    where S: Service< Request = ServiceRequest, Response = ServiceResponse< Body >, Error = Error >
+{
+    let state = match req.app_data::< StateRef >() {
+        Some( ref state ) if state.metrics.is_some() => state.get_ref().clone(),
+        _ => return Either::A( service.call( req ) )
+    };
+
+    let timestamp = Instant::now();
+    Either::B( service.call( req ).map( move |response| {
+        let status = response.status().as_u16();
+        let route = route_of( response.request(), status );
+        let method = response.request().method().as_str().to_owned();
+        state.metrics.as_ref().unwrap().record_request( &method, route, status, timestamp.elapsed() );
+
+        response.map_body( move |_, body| {
+            ResponseBody::Body( Body::Message( Box::new( CountingBody { body, state } ) ) )
+        })
+    }))
+}
@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use cli_core::{BacktraceId, Data, MemoryMap, MemoryRemap, MemoryUnmap, MmapOperation, Timestamp};
+
+/// A memory map created by a single `mmap` call, along with what has happened to it afterwards.
+#[derive(Clone, Debug)]
+pub struct Mapping {
+    /// The index of the `mmap` in `Data::mmap_operations`.
+    pub index: usize,
+    pub timestamp: Timestamp,
+    pub pointer: u64,
+    /// How much was mapped, including whatever was added to it with `mremap`.
+    pub length: u64,
+    pub backtrace: BacktraceId,
+    /// How much of it was unmapped; it could have been unmapped piece by piece.
+    pub unmapped_length: u64,
+    /// When the last part of it was unmapped, if it was unmapped completely.
+    pub unmapped_at: Option< Timestamp >
+}
+
+impl Mapping {
+    pub fn remaining_length( &self ) -> u64 {
+        self.length - self.unmapped_length
+    }
+
+    pub fn is_fully_unmapped( &self ) -> bool {
+        self.unmapped_at.is_some()
+    }
+
+    /// Returns the `mmap` which has created this map.
+    pub fn mmap< 'a >( &self, data: &'a Data ) -> &'a MemoryMap {
+        match data.mmap_operations()[ self.index ] {
+            MmapOperation::Mmap( ref mmap ) => mmap,
+            _ => unreachable!()
+        }
+    }
+
+    /// Returns the path of the mapped file, if it's known.
+    pub fn file_path< 'a >( &self, data: &'a Data ) -> Option< &'a str > {
+        self.mmap( data ).file_path.map( |id| data.interner().resolve( id ).unwrap() )
+    }
+}
+
+/// A part of a memory map which was unmapped at once, or which was never unmapped.
+///
+/// A map which was unmapped piece by piece is split into multiple regions.
+#[derive(Clone, Debug)]
+pub struct Region {
+    /// The index of the map in `MmapState::mappings`.
+    pub mapping: usize,
+    pub pointer: u64,
+    pub length: u64,
+    /// When it was mapped at this address; only differs from when the map was created if it was moved with `mremap`.
+    pub mapped_at: Timestamp,
+    pub unmapped_at: Option< Timestamp >,
+    /// The backtrace of the `munmap`, or of the `mmap` which has mapped something else over this region.
+    pub unmap_backtrace: Option< BacktraceId >
+}
+
+/// Pairs every `munmap` back to the `mmap`s whose address ranges it has covered.
+///
+/// Mapping a new region over an existing one implicitly unmaps the old one, and is treated as such.
+/// The `munmap`s of regions which weren't mapped while we were profiling are ignored.
+///
+/// An `mremap` doesn't create a new map; the map which was remapped is moved, grown or shrunk instead.
+pub struct MmapState {
+    pub mappings: Vec< Mapping >,
+    /// Sorted by when they were mapped.
+    pub regions: Vec< Region >,
+    /// When the profiling has ended; the maps don't count towards the data's `last_timestamp`.
+    pub last_timestamp: Timestamp,
+    /// The indexes of the regions, sorted by their addresses.
+    by_address: Vec< usize >,
+    max_region_length: u64
+}
+
+impl MmapState {
+    pub fn new( data: &Data ) -> Self {
+        let mut builder = Builder::default();
+        let mut last_timestamp = data.last_timestamp();
+        for (index, op) in data.mmap_operations().iter().enumerate() {
+            last_timestamp = std::cmp::max( last_timestamp, op.timestamp() );
+            match *op {
+                MmapOperation::Mmap( MemoryMap { timestamp, pointer, length, backtrace, .. } ) => {
+                    builder.map( index, timestamp, pointer, length, backtrace );
+                },
+                MmapOperation::Munmap( MemoryUnmap { timestamp, pointer, length, backtrace, .. } ) => {
+                    builder.unmap( pointer..pointer.saturating_add( length ), timestamp, backtrace );
+                },
+                MmapOperation::Mremap( MemoryRemap { timestamp, old_pointer, old_length, new_pointer, new_length, backtrace, remap_flags, .. } ) => {
+                    let old_range = old_pointer..old_pointer.saturating_add( old_length );
+                    let new_range = new_pointer..new_pointer.saturating_add( new_length );
+                    builder.remap( old_range, new_range, remap_flags.is_dont_unmap(), timestamp, backtrace );
+                },
+                MmapOperation::Mprotect( .. ) => {}
+            }
+        }
+
+        Self::from_builder( builder, last_timestamp )
+    }
+
+    fn from_builder( mut builder: Builder, last_timestamp: Timestamp ) -> Self {
+        let regions = builder.regions();
+        let mut by_address: Vec< _ > = (0..regions.len()).collect();
+        by_address.sort_by_key( |&index| regions[ index ].pointer );
+        let max_region_length = regions.iter().map( |region| region.length ).max().unwrap_or( 0 );
+
+        MmapState { mappings: builder.mappings, regions, last_timestamp, by_address, max_region_length }
+    }
+
+    /// Returns roughly how much memory this occupies.
+    pub fn size_in_bytes( &self ) -> usize {
+        self.mappings.capacity() * std::mem::size_of::< Mapping >() +
+        self.regions.capacity() * std::mem::size_of::< Region >() +
+        self.by_address.capacity() * std::mem::size_of::< usize >()
+    }
+
+    /// Finds the region which was mapped over the whole given address range at the given time.
+    pub fn find_region( &self, range: Range< u64 >, timestamp: Timestamp ) -> Option< &Region > {
+        let end = self.by_address.partition_point( |&index| self.regions[ index ].pointer <= range.start );
+        self.by_address[ ..end ].iter().rev()
+            .map( |&index| &self.regions[ index ] )
+            .take_while( |region| range.start - region.pointer < self.max_region_length )
+            .find( |region| {
+                region.pointer + region.length >= range.end &&
+                region.mapped_at <= timestamp &&
+                region.unmapped_at.map( |unmapped_at| timestamp < unmapped_at ).unwrap_or( true )
+            })
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    mappings: Vec< Mapping >,
+    /// The currently mapped address ranges, keyed by their start, along with the index of the mapping
+    /// they're from and when they were mapped.
+    live: BTreeMap< u64, (u64, usize, Timestamp) >,
+    unmapped: Vec< Region >
+}
+
+impl Builder {
+    fn map( &mut self, index: usize, timestamp: Timestamp, pointer: u64, length: u64, backtrace: BacktraceId ) {
+        if length == 0 {
+            return;
+        }
+
+        let range = pointer..pointer.saturating_add( length );
+        self.unmap( range.clone(), timestamp, backtrace );
+        self.live.insert( range.start, (range.end, self.mappings.len(), timestamp) );
+        self.mappings.push( Mapping {
+            index,
+            timestamp,
+            pointer,
+            length: range.end - range.start,
+            backtrace,
+            unmapped_length: 0,
+            unmapped_at: None
+        });
+    }
+
+    fn unmap( &mut self, range: Range< u64 >, timestamp: Timestamp, backtrace: BacktraceId ) {
+        for (pointer, length, index, mapped_at) in self.take_live( range ) {
+            self.unmap_region( index, pointer, length, mapped_at, timestamp, backtrace );
+        }
+    }
+
+    fn unmap_region( &mut self, index: usize, pointer: u64, length: u64, mapped_at: Timestamp, timestamp: Timestamp, backtrace: BacktraceId ) {
+        self.unmapped.push( Region {
+            mapping: index,
+            pointer,
+            length,
+            mapped_at,
+            unmapped_at: Some( timestamp ),
+            unmap_backtrace: Some( backtrace )
+        });
+
+        let mapping = &mut self.mappings[ index ];
+        mapping.unmapped_length += length;
+        if mapping.unmapped_length == mapping.length {
+            mapping.unmapped_at = Some( timestamp );
+        }
+    }
+
+    /// Removes the given address range from the live ranges, and returns the parts which were removed.
+    fn take_live( &mut self, range: Range< u64 > ) -> Vec< (u64, u64, usize, Timestamp) > {
+        if range.start >= range.end {
+            return Vec::new();
+        }
+
+        // The ranges never overlap, so only the one right before the start can extend into the given range.
+        let first = self.live.range( ..range.start ).next_back().map( |(&start, _)| start ).unwrap_or( range.start );
+        let overlapping: Vec< _ > = self.live.range( first..range.end )
+            .filter( |&(_, &(end, ..))| end > range.start )
+            .map( |(&start, &(end, index, mapped_at))| (start, end, index, mapped_at) )
+            .collect();
+
+        overlapping.into_iter().map( |(start, end, index, mapped_at)| {
+            self.live.remove( &start );
+            if start < range.start {
+                self.live.insert( start, (range.start, index, mapped_at) );
+            }
+            if end > range.end {
+                self.live.insert( range.end, (end, index, mapped_at) );
+            }
+
+            let pointer = start.max( range.start );
+            (pointer, end.min( range.end ) - pointer, index, mapped_at)
+        }).collect()
+    }
+
+    /// Moves and/or resizes the map which was mapped at the start of the old range.
+    fn remap( &mut self, old_range: Range< u64 >, new_range: Range< u64 >, dont_unmap: bool, timestamp: Timestamp, backtrace: BacktraceId ) {
+        let index = self.live.range( ..=old_range.start ).next_back()
+            .filter( |&(_, &(end, ..))| end > old_range.start )
+            .map( |(_, &(_, index, _))| index );
+
+        let index = match index {
+            Some( index ) if new_range.start < new_range.end => index,
+            _ => {
+                // We don't know what was remapped, so all we can do is to get rid of whatever was there before.
+                if !dont_unmap {
+                    self.unmap( old_range, timestamp, backtrace );
+                }
+                self.unmap( new_range, timestamp, backtrace );
+                return;
+            }
+        };
+
+        let old_length = if dont_unmap {
+            // The old range is still mapped, so the new range is effectively added to the map.
+            0
+        } else {
+            let mut old_length = 0;
+            for (pointer, length, region_index, mapped_at) in self.take_live( old_range.clone() ) {
+                if region_index == index {
+                    old_length += length;
+                } else {
+                    self.unmap_region( region_index, pointer, length, mapped_at, timestamp, backtrace );
+                }
+            }
+            old_length
+        };
+
+        self.unmap( new_range.clone(), timestamp, backtrace );
+
+        let new_length = new_range.end - new_range.start;
+        if new_length > old_length {
+            self.mappings[ index ].length += new_length - old_length;
+        } else if new_length < old_length {
+            // The map was shrunk, so the end of it was effectively unmapped.
+            let mapped_at = self.mappings[ index ].timestamp;
+            self.unmap_region( index, old_range.start + new_length, old_length - new_length, mapped_at, timestamp, backtrace );
+        }
+
+        let mapped_at = if new_range.start == old_range.start && !dont_unmap { self.mappings[ index ].timestamp } else { timestamp };
+        self.live.insert( new_range.start, (new_range.end, index, mapped_at) );
+    }
+
+    /// Returns every region, including the ones which are still mapped.
+    fn regions( &mut self ) -> Vec< Region > {
+        let mut regions = std::mem::take( &mut self.unmapped );
+        regions.extend( self.live.iter().map( |(&start, &(end, index, mapped_at))| Region {
+            mapping: index,
+            pointer: start,
+            length: end - start,
+            mapped_at,
+            unmapped_at: None,
+            unmap_backtrace: None
+        }));
+
+        regions.sort_by_key( |region| (region.mapping, region.pointer) );
+        regions
+    }
+}
+
+#[cfg(test)]
+fn test_mmap_state_of( ops: &[(bool, u64, u64)] ) -> Vec< (u64, Option< Timestamp >) > {
+    test_remaining_lengths( &test_mmap_builder_of( ops ) )
+}
+
+#[cfg(test)]
+fn test_remaining_lengths( builder: &Builder ) -> Vec< (u64, Option< Timestamp >) > {
+    builder.mappings.iter().map( |mapping| (mapping.remaining_length(), mapping.unmapped_at) ).collect()
+}
+
+#[cfg(test)]
+fn test_mmap_builder_of( ops: &[(bool, u64, u64)] ) -> Builder {
+    let mut builder = Builder::default();
+    for (index, &(is_mmap, pointer, length)) in ops.iter().enumerate() {
+        let timestamp = Timestamp::from_secs( index as u64 );
+        if is_mmap {
+            builder.map( index, timestamp, pointer, length, BacktraceId::new( 0 ) );
+        } else {
+            builder.unmap( pointer..pointer + length, timestamp, BacktraceId::new( 0 ) );
+        }
+    }
+
+    builder
+}
+
+#[test]
+fn test_mmap_state() {
+    let at = |secs| Some( Timestamp::from_secs( secs ) );
+
+    // Unmapped in one go.
+    assert_eq!( test_mmap_state_of( &[ (true, 0x1000, 0x2000), (false, 0x1000, 0x2000) ] ), vec![ (0, at( 1 )) ] );
+
+    // Only half of it was unmapped.
+    assert_eq!( test_mmap_state_of( &[ (true, 0x1000, 0x2000), (false, 0x2000, 0x1000) ] ), vec![ (0x1000, None) ] );
+
+    // Unmapped from the middle and then the rest of it.
+    assert_eq!(
+        test_mmap_state_of( &[ (true, 0x1000, 0x3000), (false, 0x2000, 0x1000), (false, 0x1000, 0x1000), (false, 0x3000, 0x1000) ] ),
+        vec![ (0, at( 3 )) ]
+    );
+
+    // A single munmap spanning multiple maps, and an unmap of something we've never seen.
+    assert_eq!(
+        test_mmap_state_of( &[ (true, 0x1000, 0x1000), (true, 0x2000, 0x1000), (true, 0x4000, 0x1000), (false, 0x0, 0x3800), (false, 0x8000, 0x1000) ] ),
+        vec![ (0, at( 3 )), (0, at( 3 )), (0x1000, None) ]
+    );
+
+    // Mapped over an existing map.
+    assert_eq!( test_mmap_state_of( &[ (true, 0x1000, 0x2000), (true, 0x2000, 0x2000) ] ), vec![ (0x1000, None), (0x2000, None) ] );
+}
+
+#[test]
+fn test_mmap_regions() {
+    let regions_of = |ops: &[(bool, u64, u64)]| -> Vec< (usize, u64, u64, Option< Timestamp >) > {
+        test_mmap_builder_of( ops ).regions().into_iter().map( |region| (region.mapping, region.pointer, region.length, region.unmapped_at) ).collect()
+    };
+
+    let at = |secs| Some( Timestamp::from_secs( secs ) );
+    assert_eq!( regions_of( &[ (true, 0x1000, 0x2000), (false, 0x1000, 0x2000) ] ), vec![ (0, 0x1000, 0x2000, at( 1 )) ] );
+
+    // The part which was unmapped and the part which survived.
+    assert_eq!(
+        regions_of( &[ (true, 0x1000, 0x2000), (false, 0x2000, 0x1000) ] ),
+        vec![ (0, 0x1000, 0x1000, None), (0, 0x2000, 0x1000, at( 1 )) ]
+    );
+
+    // Punched a hole in the middle, and then mapped something else over the rest of it.
+    assert_eq!(
+        regions_of( &[ (true, 0x1000, 0x3000), (false, 0x2000, 0x1000), (true, 0x1000, 0x1000) ] ),
+        vec![ (0, 0x1000, 0x1000, at( 2 )), (0, 0x2000, 0x1000, at( 1 )), (0, 0x3000, 0x1000, None), (1, 0x1000, 0x1000, None) ]
+    );
+}
+
+#[test]
+fn test_find_region() {
+    let builder = test_mmap_builder_of( &[ (true, 0x1000, 0x3000), (false, 0x2000, 0x1000), (true, 0x2000, 0x1000), (true, 0x8000, 0x1000) ] );
+    let state = MmapState::from_builder( builder, Timestamp::from_secs( 10 ) );
+    let find = |range: Range< u64 >, secs: u64| state.find_region( range, Timestamp::from_secs( secs ) ).map( |region| (region.mapping, region.pointer) );
+
+    assert_eq!( find( 0x1000..0x1100, 0 ), Some( (0, 0x1000) ) );
+    assert_eq!( find( 0x3f00..0x4000, 5 ), Some( (0, 0x3000) ) );
+
+    // The hole which was punched in the first map and then filled by the second one.
+    assert_eq!( find( 0x2000..0x2100, 0 ), Some( (0, 0x2000) ) );
+    assert_eq!( find( 0x2000..0x2100, 1 ), None );
+    assert_eq!( find( 0x2000..0x2100, 2 ), Some( (1, 0x2000) ) );
+
+    // Not covered by a single region.
+    assert_eq!( find( 0x1f00..0x2100, 5 ), None );
+    assert_eq!( find( 0x3f00..0x4100, 5 ), None );
+    assert_eq!( find( 0x8000..0x8100, 2 ), None );
+    assert_eq!( find( 0x8000..0x8100, 3 ), Some( (2, 0x8000) ) );
+    assert_eq!( find( 0x500..0x600, 5 ), None );
+}
+
+#[test]
+fn test_mmap_remap() {
+    let at = |secs| Some( Timestamp::from_secs( secs ) );
+    let regions_of = |mut builder: Builder| -> Vec< (usize, u64, u64, Option< Timestamp >) > {
+        builder.regions().into_iter().map( |region| (region.mapping, region.pointer, region.length, region.unmapped_at) ).collect()
+    };
+
+    // Grown in place.
+    let mut builder = test_mmap_builder_of( &[ (true, 0x1000, 0x1000) ] );
+    builder.remap( 0x1000..0x2000, 0x1000..0x3000, false, Timestamp::from_secs( 1 ), BacktraceId::new( 0 ) );
+    assert_eq!( builder.mappings[ 0 ].length, 0x2000 );
+    assert_eq!( regions_of( builder ), vec![ (0, 0x1000, 0x2000, None) ] );
+
+    // Shrunk in place.
+    let mut builder = test_mmap_builder_of( &[ (true, 0x1000, 0x2000) ] );
+    builder.remap( 0x1000..0x3000, 0x1000..0x1800, false, Timestamp::from_secs( 1 ), BacktraceId::new( 0 ) );
+    assert_eq!( builder.mappings[ 0 ].remaining_length(), 0x800 );
+    assert_eq!( regions_of( builder ), vec![ (0, 0x1000, 0x800, None), (0, 0x1800, 0x1800, at( 1 )) ] );
+
+    // Moved over another map, which gets implicitly unmapped.
+    let mut builder = test_mmap_builder_of( &[ (true, 0x1000, 0x1000), (true, 0x8000, 0x1000) ] );
+    builder.remap( 0x1000..0x2000, 0x8000..0xa000, false, Timestamp::from_secs( 2 ), BacktraceId::new( 0 ) );
+    assert_eq!( test_remaining_lengths( &builder ), vec![ (0x2000, None), (0, at( 2 )) ] );
+    assert_eq!( regions_of( builder ), vec![ (0, 0x8000, 0x2000, None), (1, 0x8000, 0x1000, at( 2 )) ] );
+
+    // Moved with MREMAP_DONTUNMAP; the old range is still mapped.
+    let mut builder = test_mmap_builder_of( &[ (true, 0x1000, 0x1000) ] );
+    builder.remap( 0x1000..0x2000, 0x8000..0x9000, true, Timestamp::from_secs( 1 ), BacktraceId::new( 0 ) );
+    assert_eq!( regions_of( builder ), vec![ (0, 0x1000, 0x1000, None), (0, 0x8000, 0x1000, None) ] );
+
+    // Something we've never seen was moved over an existing map.
+    let mut builder = test_mmap_builder_of( &[ (true, 0x1000, 0x1000) ] );
+    builder.remap( 0x5000..0x6000, 0x1000..0x2000, false, Timestamp::from_secs( 1 ), BacktraceId::new( 0 ) );
+    assert_eq!( test_remaining_lengths( &builder ), vec![ (0, at( 1 )) ] );
+}
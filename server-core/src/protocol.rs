@@ -2,10 +2,14 @@ use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::str::FromStr;
 use std::fmt;
+use std::sync::Arc;
 
 use serde::Serialize;
 use cli_core::Timestamp;
 
+use crate::response_format::ResponseFormat;
+use crate::source_files::SourceFiles;
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Debug, Hash)]
 #[serde(transparent)]
 pub struct Secs( u64 );
@@ -62,6 +66,13 @@ impl From< Timestamp > for Timeval {
     }
 }
 
+impl From< &Timeval > for Timestamp {
+    #[inline]
+    fn from( value: &Timeval ) -> Self {
+        Timestamp::from_timespec( value.secs.0, value.fract_nsecs.0 as u64 )
+    }
+}
+
 #[derive(Serialize)]
 pub struct ResponseMetadata {
     pub id: String,
@@ -69,13 +80,62 @@ pub struct ResponseMetadata {
     pub architecture: String,
     pub final_allocated: u64,
     pub final_allocated_count: u64,
+    pub total_allocated: u64,
+    pub total_allocated_count: u64,
+    pub total_freed: u64,
+    pub total_freed_count: u64,
     pub runtime: Timeval,
     pub unique_backtrace_count: u64,
     pub maximum_backtrace_depth: u32,
-    pub timestamp: Timeval
+    pub timestamp: Timeval,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precompute: Option< PrecomputeProgress >,
+    /// The IDs of the data from which this one was merged.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec< String >,
+    /// Where the data came from: `file`, `upload`, `merge` or `live`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_kind: Option< &'static str >,
+    /// The path of the data file, or the address of the process for the live data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_path: Option< String >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_size: Option< u64 >,
+    /// When the data became available, in seconds since the UNIX epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loaded_at: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_duration_secs: Option< f64 >,
+    /// A rough estimate of how much memory the data takes, not counting what was computed from it.
+    pub approx_resident_bytes: u64,
+    /// The highest amount of memory which was allocated at any one time, and when that happened.
+    pub peak_allocated: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peak_timestamp: Option< Timeval >,
+    pub operation_count: u64,
+    /// How many distinct threads allocated, deallocated or mapped memory.
+    pub thread_count: u64,
+    pub mmap_count: u64,
+    pub mmap_bytes: u64,
+    pub munmap_count: u64,
+    pub munmap_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option< u32 >,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cmdline: Vec< String >,
+    /// Whether the data knows when it was recorded; if not then every `*_wall_clock` field is `null`.
+    pub has_wall_clock: bool,
+    /// When the profiling started, as an ISO 8601 date in UTC.
+    pub wall_clock_start: Option< String >
 }
 
 #[derive(Serialize)]
+pub struct PrecomputeProgress {
+    pub completed: u32,
+    pub total: u32
+}
+
+#[derive(Clone, Serialize)]
 pub struct ResponseTimeline {
     pub xs: Vec< u64 >,
     pub size_delta: Vec< i64 >,
@@ -83,7 +143,86 @@ pub struct ResponseTimeline {
     pub allocated_size: Vec< u64 >,
     pub allocated_count: Vec< u64 >,
     pub allocations: Vec< u32 >,
-    pub deallocations: Vec< u32 >
+    pub deallocations: Vec< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markers: Option< Vec< TimelineMarker > >,
+    /// The `xs` as ISO 8601 dates in UTC; only present when it was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xs_wall_clock: Option< Option< Vec< String > > >
+}
+
+#[derive(Clone, Serialize)]
+pub enum TimelineMarkerKind {
+    #[serde(rename = "global_peak")]
+    GlobalPeak,
+    #[serde(rename = "group_peak")]
+    GroupPeak
+}
+
+#[derive(Clone, Serialize)]
+pub struct TimelineMarker {
+    pub kind: TimelineMarkerKind,
+    pub x: u64,
+    pub value: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace_id: Option< u32 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseTimelineTemporary {
+    pub max_lifetime_ms: Option< u64 >,
+    pub bucket_duration_us: u64,
+    #[serde(flatten)]
+    pub timeline: ResponseTimeline
+}
+
+#[derive(Serialize)]
+pub struct TimelineSeries {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace_id: Option< u32 >,
+    pub label: String,
+    pub allocated_size: Vec< u64 >,
+    pub allocated_count: Vec< u64 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseStackedTimeline {
+    pub xs: Vec< u64 >,
+    pub series: Vec< TimelineSeries >
+}
+
+#[derive(Serialize)]
+pub struct ResponseCompareTimeline {
+    pub xs: Vec< u64 >,
+    pub a: Vec< Option< u64 > >,
+    pub b: Vec< Option< u64 > >,
+    pub delta: Vec< Option< i64 > >
+}
+
+#[derive(Serialize)]
+pub struct ResponseTimelineWaste {
+    pub xs: Vec< u64 >,
+    pub allocated_size: Vec< u64 >,
+    pub extra_usable_space: Vec< u64 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseTimelineRates {
+    pub xs: Vec< u64 >,
+    pub allocated_size: Vec< u64 >,
+    pub freed_size: Vec< u64 >,
+    pub net_size: Vec< i64 >,
+    pub allocations: Vec< u64 >,
+    pub deallocations: Vec< u64 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseTimelineCumulative {
+    pub xs: Vec< u64 >,
+    pub allocated_size: Vec< u64 >,
+    pub allocated_count: Vec< u64 >,
+    pub freed_size: Vec< u64 >,
+    pub freed_count: Vec< u64 >
 }
 
 #[derive(Serialize)]
@@ -92,7 +231,13 @@ pub struct ResponseFragmentationTimeline {
     pub fragmentation: Vec< u64 >
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
+pub struct SourceSnippet {
+    pub first_line: u32,
+    pub lines: Vec< String >
+}
+
+#[derive(Clone, Serialize)]
 pub struct Frame< 'a > {
     pub address: u64,
     pub address_s: String,
@@ -100,51 +245,302 @@ pub struct Frame< 'a > {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub library: Option< &'a str >,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub library_offset: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_id: Option< &'a str >,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub function: Option< Cow< 'a, str > >,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub raw_function: Option< &'a str >,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub source: Option< &'a str >,
+    pub source: Option< Cow< 'a, str > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_original: Option< &'a str >,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub line: Option< u32 >,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub column: Option< u32 >,
-    pub is_inline: bool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_snippet: Option< SourceSnippet >,
+    pub is_inline: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inlined_count: Option< u32 >
 }
 
 #[derive(Serialize)]
-pub struct ResponseBacktrace< 'a > {
+pub struct ResponseDebugSymbols {
+    pub symbolicated_frames: u64
+}
+
+#[derive(Serialize)]
+pub struct SymbolizedAddress< 'a > {
+    pub address: u64,
+    pub address_s: String,
+    pub library: Option< &'a str >,
+    pub function: Option< Cow< 'a, str > >,
+    pub raw_function: Option< &'a str >,
+    pub source: Option< Cow< 'a, str > >,
+    pub line: Option< u32 >,
+    pub column: Option< u32 >,
     pub frames: Vec< Frame< 'a > >
 }
 
 #[derive(Serialize)]
-pub struct Deallocation {
-    pub timestamp: Timeval,
-    pub thread: u32
+pub struct ResponseSymbolize< 'a > {
+    pub addresses: Vec< SymbolizedAddress< 'a > >
 }
 
 #[derive(Serialize)]
-pub struct Allocation< 'a > {
-    pub id: u64,
-    pub address: u64,
-    pub address_s: String,
+pub struct ResponseBacktrace< 'a > {
+    pub frames: Vec< Frame< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option< AllocationGroupData >
+}
+
+#[derive(Serialize)]
+pub struct Deallocation< 'a > {
     pub timestamp: Timeval,
-    pub timestamp_relative: Timeval,
-    pub timestamp_relative_p: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_wall_clock: Option< Option< String > >,
     pub thread: u32,
-    pub size: u64,
-    pub backtrace_id: u32,
+    /// Only present when it was requested and the backtrace of the deallocation was recorded.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub deallocation: Option< Deallocation >,
-    pub backtrace: Vec< Frame< 'a > >,
-    pub is_mmaped: bool,
-    pub is_jemalloc: bool,
-    pub in_main_arena: bool,
-    pub extra_space: u32,
+    pub backtrace_id: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option< Vec< Frame< 'a > > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >
+}
+
+/// A single allocation; only the fields which were requested through `fields` are set.
+#[derive(Serialize, Default)]
+pub struct Allocation< 'a > {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address_s: Option< String >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option< Timeval >,
+    /// The `timestamp` as an ISO 8601 date in UTC, or `null` if the wall clock time is unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_wall_clock: Option< Option< String > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_relative: Option< Timeval >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_relative_p: Option< f32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace_id: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deallocation: Option< Deallocation< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option< Vec< Frame< 'a > > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_mmaped: Option< bool >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_jemalloc: Option< bool >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_main_arena: Option< bool >,
+    /// Whether the allocation was made before, or freed after, the part of the data which was loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_partially_observed: Option< bool >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_space: Option< u32 >,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub chain_lifetime: Option< Timeval >,
-    pub position_in_chain: u32,
-    pub chain_length: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position_in_chain: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_length: Option< u32 >,
+    /// The memory map in which an mmaped allocation lives, or `null` if it's unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backing_map: Option< Option< BackingMap< 'a > > >,
+    /// The innermost frame of the backtrace which isn't a part of the allocator, as `function (file:line)`,
+    /// or `null` if there's no such frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option< Option< String > >,
+    /// Whether the allocation was alive when the memory usage was at its highest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alive_at_peak: Option< bool >
+}
+
+/// A single allocation along with the details which don't fit into the list of allocations.
+#[derive(Serialize)]
+pub struct ResponseAllocation< 'a > {
+    #[serde(flatten)]
+    pub allocation: Allocation< 'a >,
+    /// Only present if the allocation was deallocated and the backtrace of the deallocation was recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deallocation_backtrace_id: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deallocation_backtrace: Option< Vec< Frame< 'a > > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deallocation_truncated_count: Option< u32 >,
+    /// Every allocation in the allocation's realloc chain, in order, including the allocation itself.
+    pub chain: Vec< ChainLink >
+}
+
+#[derive(Serialize)]
+pub struct ChainLink {
+    pub id: u64,
+    pub address: u64,
+    pub address_s: String,
+    pub size: u64,
+    pub timestamp: Timeval
+}
+
+/// The memory map which backs an allocation.
+#[derive(Serialize)]
+pub struct BackingMap< 'a > {
+    /// The index of the `mmap` in `/data/<id>/mmaps`.
+    pub id: u64,
+    pub pointer: u64,
+    pub pointer_s: String,
+    pub length: u64,
+    pub timestamp: Timeval,
+    pub is_readable: bool,
+    pub is_writable: bool,
+    pub is_executable: bool,
+    pub is_shared: bool,
+    pub is_private: bool,
+    pub is_anonymous: bool,
+    pub file_path: Option< &'a str >
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AllocationField {
+    Id,
+    Address,
+    AddressS,
+    Timestamp,
+    TimestampRelative,
+    TimestampRelativeP,
+    Thread,
+    Size,
+    BacktraceId,
+    Deallocation,
+    Backtrace,
+    TruncatedCount,
+    IsMmaped,
+    IsJemalloc,
+    InMainArena,
+    IsPartiallyObserved,
+    ExtraSpace,
+    ChainLifetime,
+    PositionInChain,
+    ChainLength
+}
+
+const ALLOCATION_FIELDS: &[(&str, AllocationField)] = &[
+    ("id", AllocationField::Id),
+    ("address", AllocationField::Address),
+    ("address_s", AllocationField::AddressS),
+    ("timestamp", AllocationField::Timestamp),
+    ("timestamp_relative", AllocationField::TimestampRelative),
+    ("timestamp_relative_p", AllocationField::TimestampRelativeP),
+    ("thread", AllocationField::Thread),
+    ("size", AllocationField::Size),
+    ("backtrace_id", AllocationField::BacktraceId),
+    ("deallocation", AllocationField::Deallocation),
+    ("backtrace", AllocationField::Backtrace),
+    ("truncated_count", AllocationField::TruncatedCount),
+    ("is_mmaped", AllocationField::IsMmaped),
+    ("is_jemalloc", AllocationField::IsJemalloc),
+    ("in_main_arena", AllocationField::InMainArena),
+    ("is_partially_observed", AllocationField::IsPartiallyObserved),
+    ("extra_space", AllocationField::ExtraSpace),
+    ("chain_lifetime", AllocationField::ChainLifetime),
+    ("position_in_chain", AllocationField::PositionInChain),
+    ("chain_length", AllocationField::ChainLength)
+];
+
+/// The set of the fields of an `Allocation` which should be returned.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AllocationFields( u32 );
+
+impl AllocationFields {
+    pub fn all() -> Self {
+        AllocationFields( (1 << ALLOCATION_FIELDS.len()) - 1 )
+    }
+
+    pub fn contains( self, field: AllocationField ) -> bool {
+        self.0 & (1 << field as u32) != 0
+    }
+
+    /// Calls `callback` to generate the field only if it was requested.
+    pub fn select< T >( self, field: AllocationField, callback: impl FnOnce() -> T ) -> Option< T > {
+        if self.contains( field ) {
+            Some( callback() )
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for AllocationFields {
+    type Err = String;
+
+    fn from_str( string: &str ) -> Result< Self, Self::Err > {
+        let mut mask = 0;
+        for name in string.split( ',' ).map( |name| name.trim() ).filter( |name| !name.is_empty() ) {
+            let field = ALLOCATION_FIELDS.iter().find( |&&(field_name, _)| field_name == name ).map( |&(_, field)| field );
+            match field {
+                Some( field ) => mask |= 1 << field as u32,
+                None => {
+                    let valid: Vec< _ > = ALLOCATION_FIELDS.iter().map( |&(field_name, _)| field_name ).collect();
+                    return Err( format!( "unknown field '{}'; the valid fields are: {}", name, valid.join( ", " ) ) );
+                }
+            }
+        }
+
+        Ok( AllocationFields( mask ) )
+    }
+}
+
+#[test]
+fn test_parse_allocation_fields() {
+    let fields: AllocationFields = "id, size,backtrace_id,".parse().unwrap();
+    assert!( fields.contains( AllocationField::Id ) );
+    assert!( fields.contains( AllocationField::Size ) );
+    assert!( fields.contains( AllocationField::BacktraceId ) );
+    assert!( !fields.contains( AllocationField::Backtrace ) );
+    assert_eq!( fields.select( AllocationField::Size, || 1 ), Some( 1 ) );
+    assert_eq!( fields.select( AllocationField::Thread, || 1 ), None );
+
+    assert!( AllocationFields::all().contains( AllocationField::ChainLength ) );
+    assert!( "id,foo".parse::< AllocationFields >().unwrap_err().contains( "the valid fields are: id, address," ) );
+}
+
+impl< 'de > serde::Deserialize< 'de > for AllocationFields {
+    fn deserialize< D >( deserializer: D ) -> Result< Self, D::Error >
+        where D: serde::Deserializer< 'de >
+    {
+        struct Visitor;
+        impl< 'de > serde::de::Visitor< 'de > for Visitor {
+            type Value = AllocationFields;
+
+            fn expecting( &self, formatter: &mut fmt::Formatter ) -> fmt::Result {
+                write!( formatter, "comma separated list of field names" )
+            }
+
+            fn visit_str< E >( self, value: &str ) -> Result< Self::Value, E >
+                where E: serde::de::Error
+            {
+                value.parse().map_err( E::custom )
+            }
+        }
+
+        deserializer.deserialize_any( Visitor )
+    }
 }
 
 #[derive(Serialize)]
@@ -166,6 +562,10 @@ pub struct AllocationGroupData {
     pub max_total_usage_first_seen_at: Option< Timeval >,
     pub max_total_usage_first_seen_at_relative: Option< Timeval >,
     pub max_total_usage_first_seen_at_relative_p: Option< f32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_timestamp_wall_clock: Option< Option< String > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_timestamp_wall_clock: Option< Option< String > >
 }
 
 #[derive(Serialize)]
@@ -173,7 +573,37 @@ pub struct AllocationGroup< 'a > {
     pub all: AllocationGroupData,
     pub only_matched: AllocationGroupData,
     pub backtrace_id: u32,
-    pub backtrace: Vec< Frame< 'a > >
+    pub backtrace: Vec< Frame< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >
+}
+
+#[derive(Serialize)]
+pub struct Marker< 'a > {
+    pub timestamp: Timeval,
+    pub thread: u32,
+    pub value: u32,
+    /// Only present if the backtrace of the marker was recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace_id: Option< u32 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option< Vec< Frame< 'a > > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >
+}
+
+/// How many allocations were made while the given marker was set.
+#[derive(Serialize)]
+pub struct MarkerAllocations {
+    pub value: u32,
+    pub allocated_count: u64,
+    pub allocated_size: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseMarkers< 'a > {
+    pub markers: Vec< Marker< 'a > >,
+    pub allocations: Vec< MarkerAllocations >
 }
 
 #[derive(Serialize)]
@@ -182,6 +612,8 @@ pub struct Mallopt< 'a > {
     pub thread: u32,
     pub backtrace_id: u32,
     pub backtrace: Vec< Frame< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >,
     pub raw_param: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub param: Option< String >,
@@ -194,11 +626,15 @@ pub enum MmapOperation< 'a > {
     #[serde(rename = "mmap")]
     Mmap {
         timestamp: Timeval,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp_wall_clock: Option< Option< String > >,
         pointer: u64,
         pointer_s: String,
         length: u64,
         backtrace_id: u32,
         backtrace: Vec< Frame< 'a > >,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        truncated_count: Option< u32 >,
         requested_address: u64,
         requested_address_s: String,
         is_readable: bool,
@@ -214,50 +650,405 @@ pub enum MmapOperation< 'a > {
         is_uninitialized: bool,
         offset: u64,
         file_descriptor: i32,
+        file_path: Option< &'a str >,
         thread: u32
     },
     #[serde(rename = "munmap")]
     Munmap {
         timestamp: Timeval,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp_wall_clock: Option< Option< String > >,
+        pointer: u64,
+        pointer_s: String,
+        length: u64,
+        backtrace_id: u32,
+        backtrace: Vec< Frame< 'a > >,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        truncated_count: Option< u32 >,
+        thread: u32
+    },
+    #[serde(rename = "mremap")]
+    Mremap {
+        timestamp: Timeval,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp_wall_clock: Option< Option< String > >,
+        old_pointer: u64,
+        old_pointer_s: String,
+        old_length: u64,
+        new_pointer: u64,
+        new_pointer_s: String,
+        new_length: u64,
+        backtrace_id: u32,
+        backtrace: Vec< Frame< 'a > >,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        truncated_count: Option< u32 >,
+        is_may_move: bool,
+        is_fixed: bool,
+        is_dont_unmap: bool,
+        thread: u32
+    },
+    #[serde(rename = "mprotect")]
+    Mprotect {
+        timestamp: Timeval,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp_wall_clock: Option< Option< String > >,
         pointer: u64,
         pointer_s: String,
         length: u64,
         backtrace_id: u32,
         backtrace: Vec< Frame< 'a > >,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        truncated_count: Option< u32 >,
+        is_readable: bool,
+        is_writable: bool,
+        is_executable: bool,
         thread: u32
     }
 }
 
-#[derive(Serialize)]
-pub struct ResponseAllocations< T: Serialize > {
-    pub allocations: T,
-    pub total_count: u64
+#[derive(Serialize)]
+pub struct ResponseAllocations< T: Serialize > {
+    pub allocations: T,
+    pub total_count: u64,
+    pub total_leaked_count: u64,
+    pub total_freed_count: u64,
+    pub total_size: u64,
+    pub total_leaked_size: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseAllocationGroups< T: Serialize > {
+    pub allocations: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseMmaps< T: Serialize > {
+    pub operations: T
+}
+
+#[derive(Serialize)]
+pub struct MmapGroup< 'a > {
+    /// Only present when the maps are grouped by their backtraces.
+    pub backtrace_id: Option< u32 >,
+    pub backtrace: Vec< Frame< 'a > >,
+    /// The path of the mapped file when the maps are grouped by files; `null` for the maps
+    /// which aren't file-backed, or whose files are unknown.
+    pub file_path: Option< &'a str >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >,
+    pub map_count: u64,
+    pub mapped_bytes: u64,
+    /// How many of the maps were completely unmapped.
+    pub unmapped_count: u64,
+    pub unmapped_bytes: u64,
+    /// How much of the maps was never unmapped.
+    pub live_bytes: u64,
+    pub min_timestamp: Timeval,
+    pub max_timestamp: Timeval
+}
+
+/// A memory map which wasn't completely unmapped by the end of the profiling.
+#[derive(Serialize)]
+pub struct MmapLeak< 'a > {
+    pub timestamp: Timeval,
+    pub pointer: u64,
+    pub pointer_s: String,
+    pub length: u64,
+    /// How much of it was never unmapped.
+    pub leaked_bytes: u64,
+    /// How long it was alive until the end of the profiling.
+    pub lifetime: Timeval,
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >,
+    pub is_readable: bool,
+    pub is_writable: bool,
+    pub is_executable: bool,
+    pub is_shared: bool,
+    pub is_private: bool,
+    pub is_anonymous: bool,
+    pub offset: u64,
+    pub file_descriptor: i32,
+    pub thread: u32
+}
+
+/// A part of a memory map from when it was mapped until it was unmapped.
+///
+/// A map which was unmapped piece by piece is split into multiple regions.
+#[derive(Serialize)]
+pub struct MmapRegion {
+    pub pointer: u64,
+    pub pointer_s: String,
+    pub length: u64,
+    /// The whole map this region is a part of.
+    pub mapping_pointer: u64,
+    pub mapping_length: u64,
+    pub mapped_at: Timeval,
+    pub unmapped_at: Option< Timeval >,
+    /// How long it was mapped; if it was never unmapped then until the end of the profiling.
+    pub lifetime: Timeval,
+    pub mmap_backtrace_id: u32,
+    /// The backtrace of the `munmap`, or of the `mmap` which has mapped something else over this region.
+    pub munmap_backtrace_id: Option< u32 >,
+    pub is_readable: bool,
+    pub is_writable: bool,
+    pub is_executable: bool,
+    pub is_shared: bool,
+    pub is_private: bool,
+    pub is_anonymous: bool,
+    pub offset: u64,
+    pub file_descriptor: i32,
+    pub thread: u32
+}
+
+#[derive(Serialize)]
+pub struct ResponseMmapRegions< T: Serialize > {
+    pub regions: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseMmapLeaks< T: Serialize > {
+    pub leaks: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseMmapGroups< T: Serialize > {
+    pub groups: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct SizeHistogramBucket {
+    pub size_min: u64,
+    pub size_max: Option< u64 >,
+    pub count: u64,
+    pub live_count: u64,
+    pub bytes: u64,
+    pub live_bytes: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseSizeHistogram {
+    pub at: Option< Timeval >,
+    pub buckets: Vec< SizeHistogramBucket >
+}
+
+#[derive(Serialize)]
+pub struct LiveGroup {
+    pub backtrace_id: u32,
+    pub label: String,
+    pub live_size: u64,
+    pub live_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponsePeak {
+    pub timestamp: Timeval,
+    pub timestamp_relative: Timeval,
+    pub allocated_size: u64,
+    pub allocated_count: u64,
+    pub groups: Vec< LiveGroup >
+}
+
+#[derive(Serialize)]
+pub struct LeakRate {
+    pub backtrace_id: u32,
+    pub label: String,
+    pub slope: f64,
+    pub r_squared: f64,
+    pub final_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projected_size: Option< f64 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseLeakRates {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_at: Option< Timeval >,
+    pub groups: Vec< LeakRate >
+}
+
+#[derive(Serialize)]
+pub struct ChurnGroup {
+    pub backtrace_id: u32,
+    pub label: String,
+    pub pairs: u64,
+    pub bytes: u64,
+    pub mean_lifetime: Timeval
+}
+
+#[derive(Serialize)]
+pub struct ResponseChurn {
+    pub groups: Vec< ChurnGroup >,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ThreadStatistics {
+    pub thread: u32,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub leaked_size: u64,
+    pub peak_size: u64,
+    pub first_activity: Timeval,
+    pub last_activity: Timeval,
+    pub cross_thread_frees: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseThreads {
+    pub threads: Vec< ThreadStatistics >
+}
+
+#[derive(Serialize)]
+pub struct ResponseSnapshot {
+    pub timestamp: Timeval,
+    pub timestamp_relative: Timeval,
+    pub allocated_size: u64,
+    pub allocated_count: u64,
+    pub groups: Vec< LiveGroup >
+}
+
+#[derive(Serialize)]
+pub struct ResponseSurvival {
+    pub durations_us: Vec< u64 >,
+    pub survival_by_count: Vec< f64 >,
+    pub survival_by_size: Vec< f64 >,
+    pub total_count: u64,
+    pub total_size: u64,
+    pub leaked_count: u64,
+    pub leaked_size: u64
+}
+
+#[derive(Serialize)]
+pub struct AgeBucket {
+    pub age_min_us: u64,
+    pub age_max_us: Option< u64 >,
+    pub count: u64,
+    pub size: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseAgeDistribution {
+    pub at: Timeval,
+    pub at_relative: Timeval,
+    pub buckets: Vec< AgeBucket >
+}
+
+#[derive(Serialize)]
+pub struct ResponseRegions< T: Serialize > {
+    pub main_heap_start: u64,
+    pub main_heap_end: u64,
+    pub main_heap_start_s: String,
+    pub main_heap_end_s: String,
+    pub regions: T
+}
+
+#[derive(Serialize)]
+pub struct BacktraceEntry< 'a > {
+    pub backtrace_id: u32,
+    pub allocation_count: u64,
+    pub total_size: u64,
+    pub leaked_count: u64,
+    pub frames: Vec< Frame< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >
+}
+
+#[derive(Serialize)]
+pub struct ModuleInfo< 'a > {
+    pub path: &'a str,
+    pub start: u64,
+    pub end: u64,
+    pub start_s: String,
+    pub end_s: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_id: Option< &'a str >
+}
+
+#[derive(Serialize)]
+pub struct ResponseModules< 'a > {
+    pub modules: Vec< ModuleInfo< 'a > >
+}
+
+#[derive(Serialize)]
+pub struct ResponseBacktraces< T: Serialize > {
+    pub backtraces: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct BacktraceCluster< 'a > {
+    pub representative_backtrace_id: u32,
+    pub representative: Vec< Frame< 'a > >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated_count: Option< u32 >,
+    pub backtrace_ids: Vec< u32 >,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub leaked_count: u64,
+    pub leaked_size: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseBacktraceClusters< 'a > {
+    pub clusters: Vec< BacktraceCluster< 'a > >,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct BacktraceWithAddress {
+    pub backtrace_id: u32,
+    pub matched_address: u64,
+    pub allocated_count: u64,
+    pub allocated_size: u64
+}
+
+#[derive(Clone, Serialize, Default)]
+pub struct BatchCounts {
+    pub matched_count: u64,
+    pub matched_size: u64,
+    pub leaked_count: u64,
+    pub leaked_size: u64
 }
 
+/// The counts for one of the filters of a `batch_counts` request; if its filter was invalid then only the `error` is set.
 #[derive(Serialize)]
-pub struct ResponseAllocationGroups< T: Serialize > {
-    pub allocations: T,
-    pub total_count: u64
+pub struct BatchCountsEntry {
+    pub name: String,
+    #[serde(flatten)]
+    pub counts: Option< BatchCounts >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option< serde_json::Value >
 }
 
+/// The allocations which contained a given address; ordered by the time they were allocated.
 #[derive(Serialize)]
-pub struct ResponseMmaps< T: Serialize > {
-    pub operations: T
+pub struct ResponseResolvePointer< 'a > {
+    pub address: u64,
+    pub address_s: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at: Option< Timeval >,
+    /// When `at` was given then there's at most one allocation here, the one which was alive at that time.
+    pub allocations: Vec< ResolvedAllocation< 'a > >
 }
 
 #[derive(Serialize)]
-pub struct ResponseRegions< T: Serialize > {
-    pub main_heap_start: u64,
-    pub main_heap_end: u64,
-    pub main_heap_start_s: String,
-    pub main_heap_end_s: String,
-    pub regions: T
+pub struct ResolvedAllocation< 'a > {
+    #[serde(flatten)]
+    pub allocation: Allocation< 'a >,
+    /// How far into the allocation the address is; negative if it points into the allocator's header.
+    pub offset: i64
 }
 
 #[derive(Serialize)]
-pub struct ResponseBacktraces< T: Serialize > {
-    pub backtraces: T,
-    pub total_count: u64
+pub struct ResponseBacktracesContainingAddress {
+    pub address: u64,
+    pub backtraces: Vec< BacktraceWithAddress >
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
@@ -310,6 +1101,24 @@ pub enum AllocSortBy {
     Size
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum MmapGroupsSortBy {
+    #[serde(rename = "live_bytes")]
+    LiveBytes,
+    #[serde(rename = "mapped_bytes")]
+    MappedBytes,
+    #[serde(rename = "map_count")]
+    MapCount,
+    #[serde(rename = "unmapped_bytes")]
+    UnmappedBytes,
+    #[serde(rename = "unmapped_count")]
+    UnmappedCount,
+    #[serde(rename = "min_timestamp")]
+    MinTimestamp,
+    #[serde(rename = "max_timestamp")]
+    MaxTimestamp
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
 pub enum AllocGroupsSortBy {
     #[serde(rename = "only_matched.min_timestamp")]
@@ -556,6 +1365,54 @@ impl< 'de, K > serde::Deserialize< 'de > for TimestampFilter< K > where K: Timev
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum TimestampOrPeak {
+    Timestamp( TimestampFilter< OffsetMin > ),
+    Peak
+}
+
+impl< 'de > serde::Deserialize< 'de > for TimestampOrPeak {
+    fn deserialize< D >( deserializer: D ) -> Result< Self, D::Error >
+        where D: serde::Deserializer< 'de >
+    {
+        struct Visitor;
+        impl< 'de > serde::de::Visitor< 'de > for Visitor {
+            type Value = TimestampOrPeak;
+
+            fn expecting( &self, formatter: &mut fmt::Formatter ) -> fmt::Result {
+                write!( formatter, "timestamp, percentage or 'peak'" )
+            }
+
+            fn visit_str< E >( self, value: &str ) -> Result< Self::Value, E >
+                where E: serde::de::Error
+            {
+                use serde::de::IntoDeserializer;
+
+                if value == "peak" {
+                    Ok( TimestampOrPeak::Peak )
+                } else {
+                    let timestamp = serde::Deserialize::deserialize( value.into_deserializer() )?;
+                    Ok( TimestampOrPeak::Timestamp( timestamp ) )
+                }
+            }
+        }
+
+        deserializer.deserialize_any( Visitor )
+    }
+}
+
+#[test]
+fn test_parse_timestamp_or_peak() {
+    fn parse( string: &str ) -> TimestampOrPeak {
+        serde_urlencoded::from_str::< RequestAgeDistribution >( string ).unwrap().at
+    }
+
+    assert_eq!( parse( "at=peak" ), TimestampOrPeak::Peak );
+    assert_eq!( parse( "at=50%" ), TimestampOrPeak::Timestamp( TimestampFilter::Percent( 50 ) ) );
+    assert_eq!( parse( "at=1000" ), TimestampOrPeak::Timestamp( TimestampFilter::Absolute( MSecs( 1000 ), PhantomData ) ) );
+    assert!( serde_urlencoded::from_str::< RequestAgeDistribution >( "at=xyz" ).is_err() );
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
 pub enum NumberOrPercentage {
     Absolute( u32 ),
@@ -573,6 +1430,64 @@ impl NumberOrPercentage {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct RegexPattern( pub regex::Regex );
+
+impl< 'de > serde::Deserialize< 'de > for RegexPattern {
+    fn deserialize< D >( deserializer: D ) -> Result< Self, D::Error >
+        where D: serde::Deserializer< 'de >
+    {
+        struct Visitor;
+        impl< 'de > serde::de::Visitor< 'de > for Visitor {
+            type Value = RegexPattern;
+
+            fn expecting( &self, formatter: &mut fmt::Formatter ) -> fmt::Result {
+                write!( formatter, "regex" )
+            }
+
+            fn visit_str< E >( self, value: &str ) -> Result< Self::Value, E >
+                where E: serde::de::Error
+            {
+                let regex = regex::Regex::new( value ).map_err( |_| E::custom( "not a valid regex" ) )?;
+                Ok( RegexPattern( regex ) )
+            }
+        }
+
+        deserializer.deserialize_any( Visitor )
+    }
+}
+
+/// A list of `from=>to` source path prefix rewriting rules separated by commas.
+#[derive(Clone, Default, Debug)]
+pub struct SourcePrefixMap( pub Vec< (String, String) > );
+
+impl< 'de > serde::Deserialize< 'de > for SourcePrefixMap {
+    fn deserialize< D >( deserializer: D ) -> Result< Self, D::Error >
+        where D: serde::Deserializer< 'de >
+    {
+        struct Visitor;
+        impl< 'de > serde::de::Visitor< 'de > for Visitor {
+            type Value = SourcePrefixMap;
+
+            fn expecting( &self, formatter: &mut fmt::Formatter ) -> fmt::Result {
+                write!( formatter, "source prefix map" )
+            }
+
+            fn visit_str< E >( self, value: &str ) -> Result< Self::Value, E >
+                where E: serde::de::Error
+            {
+                let rules = value.split( ',' ).filter( |rule| !rule.is_empty() ).map( |rule| {
+                    crate::parse_source_prefix_rule( rule ).ok_or_else( || E::custom( "not a valid source prefix rule" ) )
+                }).collect::< Result< _, _ > >()?;
+
+                Ok( SourcePrefixMap( rules ) )
+            }
+        }
+
+        deserializer.deserialize_any( Visitor )
+    }
+}
+
 impl< 'de > serde::Deserialize< 'de > for NumberOrPercentage {
     fn deserialize< D >( deserializer: D ) -> Result< Self, D::Error >
         where D: serde::Deserializer< 'de >
@@ -602,18 +1517,123 @@ impl< 'de > serde::Deserialize< 'de > for NumberOrPercentage {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct NumberList( pub Vec< u64 > );
+
+impl FromStr for NumberList {
+    type Err = std::num::ParseIntError;
+
+    fn from_str( string: &str ) -> Result< Self, Self::Err > {
+        let list = string.split( ',' )
+            .map( |value| value.trim() )
+            .filter( |value| !value.is_empty() )
+            .map( |value| value.parse() )
+            .collect::< Result< Vec< u64 >, _ > >()?;
+
+        Ok( NumberList( list ) )
+    }
+}
+
+#[test]
+fn test_parse_number_list() {
+    fn assert( string: &str, expected: &[u64] ) {
+        let list: NumberList = string.parse().unwrap();
+        assert_eq!( list.0, expected );
+    }
+
+    assert( "", &[] );
+    assert( "1", &[1] );
+    assert( "0,64,4096", &[0, 64, 4096] );
+    assert( "0, 64 ,4096,", &[0, 64, 4096] );
+    assert!( "1,a".parse::< NumberList >().is_err() );
+}
+
+impl< 'de > serde::Deserialize< 'de > for NumberList {
+    fn deserialize< D >( deserializer: D ) -> Result< Self, D::Error >
+        where D: serde::Deserializer< 'de >
+    {
+        struct Visitor;
+        impl< 'de > serde::de::Visitor< 'de > for Visitor {
+            type Value = NumberList;
+
+            fn expecting( &self, formatter: &mut fmt::Formatter ) -> fmt::Result {
+                write!( formatter, "comma separated list of numbers" )
+            }
+
+            fn visit_str< E >( self, value: &str ) -> Result< Self::Value, E >
+                where E: serde::de::Error
+            {
+                value.parse().map_err( |_| E::custom( "not a valid list of numbers" ) )
+            }
+        }
+
+        deserializer.deserialize_any( Visitor )
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
 pub struct MmapFilter {
     pub size_min: Option< u64 >,
     pub size_max: Option< u64 >,
+    /// Only matches the file-backed maps whose path matches this regex.
+    pub file_regex: Option< RegexPattern >
+}
+
+impl MmapFilter {
+    pub fn matches( &self, length: u64, file_path: Option< &str > ) -> bool {
+        if self.size_min.map( |min| length < min ).unwrap_or( false ) || self.size_max.map( |max| length > max ).unwrap_or( false ) {
+            return false;
+        }
+
+        match (self.file_regex.as_ref(), file_path) {
+            (None, _) => true,
+            (Some( regex ), Some( file_path )) => regex.0.is_match( file_path ),
+            (Some( _ ), None) => false
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
 pub struct CustomFilter {
-    pub custom_filter: Option< String >
+    pub custom_filter: Option< String >,
+    pub custom_filter_name: Option< String >
 }
 
-#[derive(Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+#[derive(Deserialize, Debug)]
+pub struct RequestFormat {
+    pub format: Option< ResponseFormat >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestLoad {
+    pub path: std::path::PathBuf,
+    /// Which part of the file should be loaded, e.g. `10m..20m`.
+    #[serde(default, deserialize_with = "deserialize_load_range")]
+    pub range: Option< cli_core::LoadRange >
+}
+
+fn deserialize_load_range< 'de, D >( deserializer: D ) -> Result< Option< cli_core::LoadRange >, D::Error > where D: serde::Deserializer< 'de > {
+    let value: Option< String > = serde::Deserialize::deserialize( deserializer )?;
+    value.map( |value| value.parse().map_err( serde::de::Error::custom ) ).transpose()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestName {
+    pub name: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestMerge {
+    pub ids: Vec< String >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestDeadline {
+    /// How many milliseconds the request can take before it's aborted.
+    pub deadline_ms: Option< u64 >
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize, Debug, Hash, Default)]
 pub struct AllocFilter {
     pub from: Option< TimestampFilter< OffsetMin > >,
     pub to: Option< TimestampFilter< OffsetMax > >,
@@ -653,6 +1673,13 @@ pub struct AllocFilter {
     pub chain_lifetime_max: Option< Interval >,
 }
 
+#[derive(Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub struct TimelineWindow {
+    pub resolution: Option< u32 >,
+    pub window_start: Option< TimestampFilter< OffsetMin > >,
+    pub window_end: Option< TimestampFilter< OffsetMax > >
+}
+
 #[derive(Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
 pub struct BacktraceFilter {
     pub backtrace_depth_min: Option< u32 >,
@@ -663,20 +1690,106 @@ pub struct BacktraceFilter {
     pub negative_source_regex: Option< String >,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum Demangle {
+    #[serde(rename = "full")]
+    Full,
+    #[serde(rename = "short")]
+    Short,
+    #[serde(rename = "none")]
+    None
+}
+
+/// Which end of a backtrace is kept when it's truncated.
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug)]
+pub enum FramesFrom {
+    #[serde(rename = "leaf")]
+    Leaf,
+    #[serde(rename = "root")]
+    Root
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct BacktraceFormat {
-    pub strip_template_args: Option< bool >
+    pub strip_template_args: Option< bool >,
+    pub template_depth: Option< u32 >,
+    pub demangle: Option< Demangle >,
+    pub collapse_inlined: Option< bool >,
+    pub trim_below: Option< RegexPattern >,
+    pub trim_runtime_prefix: Option< bool >,
+    pub source_prefix_map: Option< SourcePrefixMap >,
+    pub include_source_snippet: Option< bool >,
+    pub source_snippet_context: Option< u32 >,
+    pub max_frames: Option< u32 >,
+    pub frames_from: Option< FramesFrom >,
+    #[serde(skip)]
+    pub source_files: Option< Arc< SourceFiles > >
 }
 
 #[derive(Deserialize, Debug)]
 pub struct RequestAllocations {
     pub skip: Option< u64 >,
     pub count: Option< u32 >,
+    /// If set then only these fields are returned for each allocation.
+    pub fields: Option< AllocationFields >,
+    /// Whether to look up the memory maps in which the mmaped allocations live.
+    pub include_backing_map: Option< bool >,
+    /// Whether to resolve the backtraces of the deallocations.
+    pub include_dealloc_backtrace: Option< bool >,
+    /// Whether to summarize where every allocation came from in a single line.
+    pub include_origin: Option< bool >,
+    /// Whether to flag the allocations which were alive at the peak memory usage.
+    pub include_peak_flag: Option< bool >,
 
     pub sort_by: Option< AllocSortBy >,
     pub order: Option< Order >
 }
 
+#[derive(Deserialize, Debug)]
+pub struct RequestMmapRegions {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug)]
+pub enum MmapWeight {
+    /// Every map is weighted by how much was originally mapped.
+    #[serde(rename = "mapped")]
+    Mapped,
+    /// Every map is weighted by how much of it was never unmapped.
+    #[serde(rename = "live")]
+    Live
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestMmapWeight {
+    pub weight: Option< MmapWeight >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestMmapLeaks {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug)]
+pub enum MmapGroupBy {
+    #[serde(rename = "backtrace")]
+    Backtrace,
+    #[serde(rename = "file")]
+    File
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestMmapGroups {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+    pub group_by: Option< MmapGroupBy >,
+
+    pub sort_by: Option< MmapGroupsSortBy >,
+    pub order: Option< Order >
+}
+
 #[derive(Deserialize, Debug)]
 pub struct RequestAllocationGroups {
     pub skip: Option< u64 >,
@@ -687,3 +1800,135 @@ pub struct RequestAllocationGroups {
 
     pub generate_graphs: Option< bool >
 }
+
+#[derive(Deserialize, Debug)]
+pub struct RequestTimelineTemporary {
+    pub max_lifetime_ms: Option< u64 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestSizeHistogram {
+    pub buckets: Option< NumberList >,
+    pub at: Option< TimestampFilter< OffsetMin > >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestStackedTimeline {
+    pub top: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestPeak {
+    pub top: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestLeakRates {
+    pub top: Option< u32 >,
+    pub samples: Option< u32 >,
+    pub project_at: Option< Interval >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestCompare {
+    pub a: String,
+    pub b: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestChurn {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+    pub min_pairs: Option< u64 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestSnapshot {
+    pub at: TimestampFilter< OffsetMin >,
+    pub top: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestAgeDistribution {
+    pub at: TimestampOrPeak
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestWallClock {
+    /// Whether to also render the timestamps as ISO 8601 dates in UTC.
+    pub wall_clock: Option< bool >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestTimelineMarkers {
+    pub include_markers: Option< bool >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestBySource {
+    pub per_line: Option< bool >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestContainingAddress {
+    pub near: Option< u64 >
+}
+
+/// One of the filters of a `batch_counts` request, in the same format as in the body of the other POST requests.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RequestBatchCountsEntry {
+    pub name: String,
+    #[serde(default)]
+    pub filter: serde_json::Map< String, serde_json::Value >,
+    #[serde(default)]
+    pub custom_filter: Option< String >,
+    #[serde(default)]
+    pub custom_filter_name: Option< String >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestMarkers {
+    pub value: Option< u32 >,
+    pub from: Option< TimestampFilter< OffsetMin > >,
+    pub to: Option< TimestampFilter< OffsetMax > >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestResolvePointer {
+    pub address: String,
+    pub at: Option< TimestampFilter< OffsetMin > >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestSymbolize {
+    pub addresses: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestExecuteScript {
+    pub script: Option< String >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestExecuteScriptAll {
+    pub parallelism: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestDebugSymbols {
+    pub path: Option< String >,
+    pub filename: Option< String >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestBacktraceClusters {
+    pub threshold: Option< f64 >,
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestBacktrace {
+    pub frames_only: Option< bool >
+}
@@ -0,0 +1,68 @@
+use std::ops::Range;
+
+/// Parses the value of a `Range` header for a resource of the given `length`.
+///
+/// Returns `Ok( None )` if the whole resource should be sent, which is the case for multiple ranges
+/// since those are not supported, and an error if the range is malformed or can't be satisfied.
+pub fn parse_range( value: &str, length: u64 ) -> Result< Option< Range< u64 > >, () > {
+    let value = value.trim();
+    if !value.starts_with( "bytes=" ) {
+        return Err(());
+    }
+
+    let value = &value[ "bytes=".len().. ];
+    if value.contains( ',' ) {
+        return Ok( None );
+    }
+
+    let mut iter = value.splitn( 2, '-' );
+    let start = iter.next().ok_or(())?.trim();
+    let end = iter.next().ok_or(())?.trim();
+
+    let range = if start.is_empty() {
+        // A suffix range, e.g. `bytes=-500` means the last 500 bytes.
+        let suffix: u64 = end.parse().map_err( |_| () )?;
+        if suffix == 0 {
+            return Err(());
+        }
+
+        length.saturating_sub( suffix )..length
+    } else {
+        let start: u64 = start.parse().map_err( |_| () )?;
+        let end = if end.is_empty() {
+            length
+        } else {
+            let end: u64 = end.parse().map_err( |_| () )?;
+            if end < start {
+                return Err(());
+            }
+
+            std::cmp::min( end.saturating_add( 1 ), length )
+        };
+
+        start..end
+    };
+
+    if range.start >= length {
+        return Err(());
+    }
+
+    Ok( Some( range ) )
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!( parse_range( "bytes=0-0", 10 ), Ok( Some( 0..1 ) ) );
+    assert_eq!( parse_range( "bytes=2-5", 10 ), Ok( Some( 2..6 ) ) );
+    assert_eq!( parse_range( "bytes=2-", 10 ), Ok( Some( 2..10 ) ) );
+    assert_eq!( parse_range( "bytes=2-100", 10 ), Ok( Some( 2..10 ) ) );
+    assert_eq!( parse_range( "bytes=-3", 10 ), Ok( Some( 7..10 ) ) );
+    assert_eq!( parse_range( "bytes=-30", 10 ), Ok( Some( 0..10 ) ) );
+    assert_eq!( parse_range( "bytes=0-1,3-4", 10 ), Ok( None ) );
+    assert_eq!( parse_range( "bytes=10-", 10 ), Err(()) );
+    assert_eq!( parse_range( "bytes=5-2", 10 ), Err(()) );
+    assert_eq!( parse_range( "bytes=-0", 10 ), Err(()) );
+    assert_eq!( parse_range( "bytes=a-b", 10 ), Err(()) );
+    assert_eq!( parse_range( "items=0-1", 10 ), Err(()) );
+    assert_eq!( parse_range( "bytes=0-0", 0 ), Err(()) );
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use actix_web::Error;
+use actix_web::body::{Body, BodySize, MessageBody};
+use actix_web::dev::{ResponseBody, Service, ServiceRequest, ServiceResponse};
+use bytes::Bytes;
+use futures::{Async, Poll};
+use futures::future::{Either, Future};
+use log::Level;
+
+use crate::StateRef;
+
+/// Query parameters whose values must never end up in the logs.
+const SENSITIVE_PARAMETERS: &[&str] = &[ "token" ];
+
+/// Returns the query string with the values of the sensitive parameters redacted.
+fn sanitize_query( query: &str ) -> String {
+    let params: Vec< (String, String) > = match serde_urlencoded::from_str( query ) {
+        Ok( params ) => params,
+        Err( _ ) => return "<invalid>".into()
+    };
+
+    let params: Vec< _ > = params.into_iter().map( |(key, value)| {
+        if SENSITIVE_PARAMETERS.contains( &key.as_str() ) {
+            (key, "REDACTED".to_owned())
+        } else {
+            (key, value)
+        }
+    }).collect();
+
+    serde_urlencoded::to_string( &params ).unwrap_or_default()
+}
+
+struct RequestInfo {
+    method: String,
+    path: String,
+    status: u16,
+    timestamp: Instant,
+    level: Level,
+    slow_request_threshold: Option< Duration >
+}
+
+impl RequestInfo {
+    fn log( &self, bytes: u64, finished: bool ) {
+        let elapsed = self.timestamp.elapsed();
+        let is_slow = self.slow_request_threshold.map( |threshold| elapsed >= threshold ).unwrap_or( false );
+        let level = if is_slow { Level::Warn } else { self.level };
+        log!(
+            level,
+            "{} {} {} {} bytes in {:.3}s{}{}",
+            self.method,
+            self.path,
+            self.status,
+            bytes,
+            elapsed.as_secs_f64(),
+            if finished { "" } else { " (the client has disconnected)" },
+            if is_slow { " (slow request)" } else { "" }
+        );
+    }
+}
+
+/// Logs the request once its whole body was sent, so that the timing also covers the streamed responses.
+struct LoggingBody {
+    body: ResponseBody< Body >,
+    info: RequestInfo,
+    bytes: u64,
+    finished: bool
+}
+
+impl MessageBody for LoggingBody {
+    fn size( &self ) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next( &mut self ) -> Poll< Option< Bytes >, Error > {
+        let result = self.body.poll_next();
+        match result {
+            Ok( Async::Ready( Some( ref bytes ) ) ) => self.bytes += bytes.len() as u64,
+            Ok( Async::Ready( None ) ) => self.finished = true,
+            _ => {}
+        }
+
+        result
+    }
+}
+
+impl Drop for LoggingBody {
+    fn drop( &mut self ) {
+        let finished = self.finished || match self.body.size() {
+            BodySize::None | BodySize::Empty => true,
+            BodySize::Sized( 0 ) | BodySize::Sized64( 0 ) => true,
+            _ => false
+        };
+
+        self.info.log( self.bytes, finished );
+    }
+}
+
+/// A middleware which logs every request along with how long it took and how big its response was.
+///
+/// The requests are logged at the `info` level if `--log-requests` was given, and at the `debug` level otherwise;
+/// the requests which are slower than the configured threshold are always logged as warnings.
+pub fn middleware< S >( req: ServiceRequest, service: &mut S ) -> impl Future< Item = ServiceResponse< Body >, Error = Error >
+    where S: Service< Request = ServiceRequest, Response = ServiceResponse< Body >, Error = Error >
+{
+    let (level, slow_request_threshold) = match req.app_data::< StateRef >() {
+        Some( state ) => (if state.log_requests { Level::Info } else { Level::Debug }, state.slow_request_threshold),
+        None => return Either::A( service.call( req ) )
+    };
+
+    if !log_enabled!( level ) && slow_request_threshold.is_none() {
+        return Either::A( service.call( req ) );
+    }
+
+    let timestamp = Instant::now();
+    let method = req.method().as_str().to_owned();
+    let path = if req.query_string().is_empty() {
+        req.path().to_owned()
+    } else {
+        format!( "{}?{}", req.path(), sanitize_query( req.query_string() ) )
+    };
+
+    Either::B( service.call( req ).map( move |response| {
+        let info = RequestInfo {
+            method,
+            path,
+            status: response.status().as_u16(),
+            timestamp,
+            level,
+            slow_request_threshold
+        };
+
+        response.map_body( move |_, body| {
+            ResponseBody::Body( Body::Message( Box::new( LoggingBody { body, info, bytes: 0, finished: false } ) ) )
+        })
+    }))
+}
+
+#[test]
+fn test_sanitize_query() {
+    assert_eq!( sanitize_query( "foo=1&token=secret&bar=a+b" ), "foo=1&token=REDACTED&bar=a+b" );
+    assert_eq!( sanitize_query( "" ), "" );
+}
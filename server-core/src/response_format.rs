@@ -0,0 +1,71 @@
+use std::io;
+
+use serde::Serialize;
+
+/// The format in which the data endpoints serialize their responses.
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug)]
+pub enum ResponseFormat {
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack
+}
+
+impl Default for ResponseFormat {
+    fn default() -> Self {
+        ResponseFormat::Json
+    }
+}
+
+impl ResponseFormat {
+    pub fn content_type( self ) -> &'static str {
+        match self {
+            ResponseFormat::Json => "application/json",
+            ResponseFormat::MessagePack => "application/msgpack"
+        }
+    }
+
+    /// Picks the format based on the `Accept` header; JSON is used unless MessagePack is explicitly asked for.
+    pub fn from_accept( accept: &str ) -> Self {
+        let is_msgpack = accept.split( ',' )
+            .filter_map( |item| {
+                let mut parts = item.split( ';' );
+                let media_type = parts.next()?.trim();
+                let is_rejected = parts.any( |param| {
+                    let mut param = param.splitn( 2, '=' );
+                    param.next().map( |key| key.trim() ) == Some( "q" ) &&
+                        param.next().and_then( |value| value.trim().parse::< f32 >().ok() ) == Some( 0.0 )
+                });
+
+                if is_rejected { None } else { Some( media_type ) }
+            })
+            .any( |media_type| media_type.eq_ignore_ascii_case( "application/msgpack" ) || media_type.eq_ignore_ascii_case( "application/x-msgpack" ) );
+
+        if is_msgpack {
+            ResponseFormat::MessagePack
+        } else {
+            ResponseFormat::Json
+        }
+    }
+
+    pub fn write< W: io::Write, T: Serialize >( self, output: W, value: &T ) -> Result< (), String > {
+        match self {
+            ResponseFormat::Json => serde_json::to_writer( output, value ).map_err( |error| error.to_string() ),
+            ResponseFormat::MessagePack => {
+                // The structs are serialized as maps so that the fields are named exactly as they are in JSON.
+                let mut output = output;
+                rmp_serde::encode::write_named( &mut output, value ).map_err( |error| error.to_string() )
+            }
+        }
+    }
+}
+
+#[test]
+fn test_response_format_from_accept() {
+    assert_eq!( ResponseFormat::from_accept( "" ), ResponseFormat::Json );
+    assert_eq!( ResponseFormat::from_accept( "*/*" ), ResponseFormat::Json );
+    assert_eq!( ResponseFormat::from_accept( "application/json" ), ResponseFormat::Json );
+    assert_eq!( ResponseFormat::from_accept( "application/msgpack" ), ResponseFormat::MessagePack );
+    assert_eq!( ResponseFormat::from_accept( "application/json;q=0.5, application/x-msgpack" ), ResponseFormat::MessagePack );
+    assert_eq!( ResponseFormat::from_accept( "application/msgpack; q=0" ), ResponseFormat::Json );
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Scripts bigger than this can't be stored.
+pub const MAXIMUM_SCRIPT_SIZE: usize = 1024 * 1024;
+
+const MAXIMUM_SCRIPT_NAME_LENGTH: usize = 128;
+const SCRIPT_EXTENSION: &str = ".rhai";
+
+#[derive(Debug)]
+pub enum ScriptLibraryError {
+    Disabled,
+    InvalidName,
+    TooLarge,
+    NotFound,
+    Io( io::Error )
+}
+
+impl From< io::Error > for ScriptLibraryError {
+    fn from( error: io::Error ) -> Self {
+        if error.kind() == io::ErrorKind::NotFound {
+            ScriptLibraryError::NotFound
+        } else {
+            ScriptLibraryError::Io( error )
+        }
+    }
+}
+
+pub struct ScriptEntry {
+    pub name: String,
+    pub modified: SystemTime,
+    pub size: u64
+}
+
+/// Stores named scripts as flat files within a single directory.
+#[derive(Debug, Default)]
+pub struct ScriptLibrary {
+    directory: Option< PathBuf >
+}
+
+/// Only allows names which are safe to use as a filename on every platform.
+fn is_valid_name( name: &str ) -> bool {
+    !name.is_empty() &&
+    name.len() <= MAXIMUM_SCRIPT_NAME_LENGTH &&
+    !name.starts_with( '.' ) &&
+    name.chars().all( |ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-' || ch == '.' )
+}
+
+impl ScriptLibrary {
+    /// If there's no directory then every operation fails with `ScriptLibraryError::Disabled`.
+    pub fn new( directory: Option< PathBuf > ) -> Self {
+        ScriptLibrary { directory }
+    }
+
+    fn directory( &self ) -> Result< &PathBuf, ScriptLibraryError > {
+        self.directory.as_ref().ok_or( ScriptLibraryError::Disabled )
+    }
+
+    fn path( &self, name: &str ) -> Result< PathBuf, ScriptLibraryError > {
+        let directory = self.directory()?;
+        if !is_valid_name( name ) {
+            return Err( ScriptLibraryError::InvalidName );
+        }
+
+        Ok( directory.join( format!( "{}{}", name, SCRIPT_EXTENSION ) ) )
+    }
+
+    /// Returns every stored script, sorted by name.
+    pub fn list( &self ) -> Result< Vec< ScriptEntry >, ScriptLibraryError > {
+        let directory = self.directory()?;
+        let iter = match fs::read_dir( directory ) {
+            Ok( iter ) => iter,
+            Err( ref error ) if error.kind() == io::ErrorKind::NotFound => return Ok( Vec::new() ),
+            Err( error ) => return Err( ScriptLibraryError::Io( error ) )
+        };
+
+        let mut output = Vec::new();
+        for entry in iter {
+            let entry = entry?;
+            let filename = entry.file_name();
+            let name = match filename.to_str().and_then( |filename| filename.strip_suffix( SCRIPT_EXTENSION ) ) {
+                Some( name ) if is_valid_name( name ) => name,
+                _ => continue
+            };
+
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            output.push( ScriptEntry {
+                name: name.to_owned(),
+                modified: metadata.modified()?,
+                size: metadata.len()
+            });
+        }
+
+        output.sort_by( |lhs, rhs| lhs.name.cmp( &rhs.name ) );
+        Ok( output )
+    }
+
+    pub fn get( &self, name: &str ) -> Result< String, ScriptLibraryError > {
+        let path = self.path( name )?;
+        Ok( fs::read_to_string( path )? )
+    }
+
+    pub fn put( &self, name: &str, body: &str ) -> Result< (), ScriptLibraryError > {
+        let path = self.path( name )?;
+        if body.len() > MAXIMUM_SCRIPT_SIZE {
+            return Err( ScriptLibraryError::TooLarge );
+        }
+
+        fs::create_dir_all( self.directory()? ).map_err( ScriptLibraryError::Io )?;
+
+        // Write to a temporary file first so that a concurrent `get` never sees a partially written script.
+        let tmp_path = self.directory()?.join( format!( ".{}{}.tmp", name, SCRIPT_EXTENSION ) );
+        fs::write( &tmp_path, body ).map_err( ScriptLibraryError::Io )?;
+        fs::rename( &tmp_path, &path ).map_err( ScriptLibraryError::Io )?;
+
+        Ok(())
+    }
+
+    pub fn delete( &self, name: &str ) -> Result< (), ScriptLibraryError > {
+        let path = self.path( name )?;
+        fs::remove_file( path )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_script_library() {
+    let directory = std::env::temp_dir().join( format!( "bytehound-script-library-test-{}", std::process::id() ) );
+    let library = ScriptLibrary::new( Some( directory.join( "scripts" ) ) );
+
+    assert!( library.list().unwrap().is_empty() );
+    assert!( matches!( library.get( "foo" ), Err( ScriptLibraryError::NotFound ) ) );
+
+    library.put( "foo", "println(1);" ).unwrap();
+    library.put( "bar.v2", "println(2);" ).unwrap();
+    library.put( "foo", "println(3);" ).unwrap();
+    assert_eq!( library.get( "foo" ).unwrap(), "println(3);" );
+
+    let names: Vec< _ > = library.list().unwrap().into_iter().map( |entry| (entry.name, entry.size) ).collect();
+    assert_eq!( names, vec![ ("bar.v2".to_owned(), 11), ("foo".to_owned(), 11) ] );
+
+    for &name in &["", ".hidden", "../foo", "foo/bar", "foo bar", &"a".repeat( MAXIMUM_SCRIPT_NAME_LENGTH + 1 )] {
+        assert!( matches!( library.put( name, "" ), Err( ScriptLibraryError::InvalidName ) ), "{:?}", name );
+    }
+
+    assert!( matches!( library.put( "big", &" ".repeat( MAXIMUM_SCRIPT_SIZE + 1 ) ), Err( ScriptLibraryError::TooLarge ) ) );
+
+    library.delete( "foo" ).unwrap();
+    assert!( matches!( library.delete( "foo" ), Err( ScriptLibraryError::NotFound ) ) );
+    assert_eq!( library.list().unwrap().len(), 1 );
+
+    assert!( matches!( ScriptLibrary::new( None ).list(), Err( ScriptLibraryError::Disabled ) ) );
+
+    fs::remove_dir_all( &directory ).unwrap();
+}
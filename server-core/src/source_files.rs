@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use lru::LruCache;
+use parking_lot::Mutex;
+
+/// Files bigger than this are never read.
+const MAXIMUM_SOURCE_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Reads source files from a fixed set of allowed directories and caches their contents.
+pub struct SourceFiles {
+    roots: Vec< PathBuf >,
+    cache: Mutex< LruCache< (PathBuf, SystemTime), Arc< Vec< String > > > >
+}
+
+impl std::fmt::Debug for SourceFiles {
+    fn fmt( &self, formatter: &mut std::fmt::Formatter ) -> std::fmt::Result {
+        formatter.debug_struct( "SourceFiles" ).field( "roots", &self.roots ).finish()
+    }
+}
+
+impl SourceFiles {
+    /// Roots which don't exist are ignored.
+    pub fn new( roots: &[PathBuf] ) -> Self {
+        let roots = roots.iter().filter_map( |root| {
+            match fs::canonicalize( root ) {
+                Ok( root ) => Some( root ),
+                Err( error ) => {
+                    warn!( "Ignoring source root {:?}: {}", root, error );
+                    None
+                }
+            }
+        }).collect();
+
+        SourceFiles {
+            roots,
+            cache: Mutex::new( LruCache::new( 256 ) )
+        }
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Returns the lines of the given file, or `None` if it can't be read or lies outside of the allowed roots.
+    pub fn get_lines( &self, path: &Path ) -> Option< Arc< Vec< String > > > {
+        let path = fs::canonicalize( path ).ok()?;
+        if !self.roots.iter().any( |root| path.starts_with( root ) ) {
+            return None;
+        }
+
+        let metadata = fs::metadata( &path ).ok()?;
+        if !metadata.is_file() || metadata.len() > MAXIMUM_SOURCE_FILE_SIZE {
+            return None;
+        }
+
+        let key = (path, metadata.modified().ok()?);
+        if let Some( lines ) = self.cache.lock().get( &key ) {
+            return Some( lines.clone() );
+        }
+
+        let contents = fs::read( &key.0 ).ok()?;
+        let lines: Vec< _ > = String::from_utf8_lossy( &contents ).lines().map( |line| line.to_owned() ).collect();
+        let lines = Arc::new( lines );
+        self.cache.lock().put( key, lines.clone() );
+
+        Some( lines )
+    }
+
+    /// Returns up to `context` lines around the given 1-based line number, along with the number of the first returned line.
+    pub fn get_snippet( &self, path: &Path, line: u32, context: u32 ) -> Option< (u32, Vec< String >) > {
+        let lines = self.get_lines( path )?;
+        let index = (line as usize).checked_sub( 1 )?;
+        if index >= lines.len() {
+            return None;
+        }
+
+        let start = index.saturating_sub( context as usize );
+        let end = std::cmp::min( index + context as usize + 1, lines.len() );
+        Some( (start as u32 + 1, lines[ start..end ].to_vec()) )
+    }
+}
+
+#[test]
+fn test_source_files() {
+    let directory = std::env::temp_dir().join( format!( "bytehound-source-files-test-{}", std::process::id() ) );
+    let root = directory.join( "root" );
+    let outside = directory.join( "outside" );
+    fs::create_dir_all( &root ).unwrap();
+    fs::create_dir_all( &outside ).unwrap();
+    fs::write( root.join( "main.rs" ), "1\n2\n3\n4\n5\n6\n" ).unwrap();
+    fs::write( outside.join( "secret.rs" ), "secret\n" ).unwrap();
+
+    let files = SourceFiles::new( &[root.clone(), directory.join( "nonexistent" )] );
+    assert_eq!( files.get_snippet( &root.join( "main.rs" ), 3, 1 ), Some( (2, vec![ "2".to_owned(), "3".to_owned(), "4".to_owned() ]) ) );
+    assert_eq!( files.get_snippet( &root.join( "main.rs" ), 1, 2 ), Some( (1, vec![ "1".to_owned(), "2".to_owned(), "3".to_owned() ]) ) );
+    assert_eq!( files.get_snippet( &root.join( "main.rs" ), 6, 1 ).map( |(first, lines)| (first, lines.len()) ), Some( (5, 2) ) );
+    assert_eq!( files.get_snippet( &root.join( "main.rs" ), 0, 1 ), None );
+    assert_eq!( files.get_snippet( &root.join( "main.rs" ), 7, 1 ), None );
+    assert_eq!( files.get_snippet( &root.join( "missing.rs" ), 1, 1 ), None );
+    assert_eq!( files.get_snippet( &outside.join( "secret.rs" ), 1, 1 ), None );
+    assert_eq!( files.get_snippet( &root.join( "../outside/secret.rs" ), 1, 1 ), None );
+    assert_eq!( files.get_snippet( &root, 1, 1 ), None );
+
+    fs::remove_dir_all( &directory ).unwrap();
+}
@@ -1,18 +1,50 @@
 use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::VecDeque;
 
 use futures;
 
+/// How many values can be sent before the sender has to wait for the receiver.
+const CAPACITY: usize = 16;
+
 struct Inner< T > {
     buffer: VecDeque< T >,
     task: Option< futures::task::Task >,
     sender_closed: bool,
-    receiver_closed: bool
+    receiver_closed: bool,
+    aborted: bool,
+    disconnected: Arc< AtomicBool >
+}
+
+/// Gets set when the receiver is dropped before receiving everything,
+/// e.g. when the client has disconnected in the middle of a response.
+#[derive(Clone)]
+pub struct DisconnectedFlag( Arc< AtomicBool > );
+
+impl DisconnectedFlag {
+    pub fn is_set( &self ) -> bool {
+        self.0.load( Ordering::Relaxed )
+    }
 }
 
 pub struct Sender< T >( Arc< (Condvar, Mutex< Inner< T > >) > );
 
 impl< T > Sender< T > {
+    pub fn disconnected_flag( &self ) -> DisconnectedFlag {
+        DisconnectedFlag( (self.0).1.lock().unwrap().disconnected.clone() )
+    }
+
+    /// Marks the stream as incomplete, so that the receiver will end with an error
+    /// instead of ending normally once everything that was sent so far is received.
+    pub fn abort( &mut self ) {
+        (self.0).1.lock().unwrap().aborted = true;
+    }
+
+    /// Returns whether `send` would have to wait for the receiver.
+    pub fn is_full( &self ) -> bool {
+        (self.0).1.lock().unwrap().buffer.len() >= CAPACITY
+    }
+
     pub fn send( &mut self, value: T ) -> Result< (), () > {
         let mut inner = (self.0).1.lock().unwrap();
         if inner.receiver_closed {
@@ -20,7 +52,7 @@ impl< T > Sender< T > {
             return Err(());
         }
 
-        while inner.buffer.len() >= 16 {
+        while inner.buffer.len() >= CAPACITY {
             inner = (self.0).0.wait( inner ).unwrap();
             if inner.receiver_closed {
                 inner.buffer.clear();
@@ -42,6 +74,10 @@ impl< T > Drop for Sender< T > {
     fn drop( &mut self ) {
         let mut inner = (self.0).1.lock().unwrap();
         inner.sender_closed = true;
+        if std::thread::panicking() {
+            inner.aborted = true;
+        }
+
         if let Some( ref mut task ) = inner.task {
             task.notify();
         }
@@ -63,6 +99,10 @@ impl< T > futures::Stream for Receiver< T > {
             },
             None => {
                 if inner.sender_closed {
+                    if inner.aborted {
+                        return Err(());
+                    }
+
                     return Ok( futures::Async::Ready( None ) );
                 }
 
@@ -77,6 +117,9 @@ impl< T > Drop for Receiver< T > {
     fn drop( &mut self ) {
         let mut inner = (self.0).1.lock().unwrap();
         inner.receiver_closed = true;
+        if !inner.sender_closed || !inner.buffer.is_empty() {
+            inner.disconnected.store( true, Ordering::Relaxed );
+        }
         (self.0).0.notify_all();
     }
 }
@@ -86,7 +129,9 @@ pub fn streaming_channel< T >() -> (Sender< T >, Receiver< T >) {
         buffer: VecDeque::new(),
         task: None,
         sender_closed: false,
-        receiver_closed: false
+        receiver_closed: false,
+        aborted: false,
+        disconnected: Arc::new( AtomicBool::new( false ) )
     };
 
     let condvar = Condvar::new();
@@ -26,7 +26,15 @@ impl< F, R, T > Serialize for StreamingSerializer< F, R, T >
         use serde::ser::SerializeSeq;
 
         let iter = (self.callback)();
-        let mut seq = serializer.serialize_seq( None )?;
+        let length = match iter.size_hint() {
+            (lower, Some( upper )) if lower == upper => Some( lower ),
+            // The binary formats need to know the length upfront, otherwise the whole
+            // sequence has to be buffered in memory, so just count the elements first.
+            _ if !serializer.is_human_readable() => Some( (self.callback)().count() ),
+            _ => None
+        };
+
+        let mut seq = serializer.serialize_seq( length )?;
         for element in iter {
             seq.serialize_element( &element )?;
         }
@@ -0,0 +1,309 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use crossbeam_channel::{Receiver, Select, Sender, TryRecvError, TrySendError};
+use parking_lot::Mutex;
+
+type Job = Box< dyn FnOnce() + Send + 'static >;
+
+/// How many workers can be blocked on sending their responses to slow clients
+/// after being replaced by new ones through `release_current_worker`.
+const MAXIMUM_RELEASED_WORKERS: usize = 64;
+
+#[derive(Default)]
+struct Stats {
+    queue_wait_micros: AtomicU64,
+    processed: AtomicU64,
+    rejected: AtomicU64,
+    is_running_low_priority_job: AtomicBool
+}
+
+/// What's shared by every worker of a single pool.
+struct Shared {
+    rx: Receiver< (Instant, Job) >,
+    low_priority_rx: Receiver< Job >,
+    stats: Stats,
+    released_workers: AtomicUsize,
+    next_worker_index: AtomicUsize
+}
+
+thread_local! {
+    /// Set on the pool's threads; the flag is set once the worker was released.
+    static CURRENT_WORKER: RefCell< Option< (Arc< Shared >, bool) > > = RefCell::new( None );
+}
+
+/// A fixed number of threads which process the expensive requests,
+/// so that a lot of concurrent requests can't grind the whole machine to a halt.
+pub struct WorkerPool {
+    tx: Sender< (Instant, Job) >,
+    low_priority_tx: Sender< Job >,
+    worker_count: usize,
+    started_workers: Mutex< usize >,
+    shared: Arc< Shared >
+}
+
+pub struct QueueFull;
+
+/// By default only half of the cores are used for the requests themselves
+/// since the heavier analyses are also parallelized internally.
+pub fn default_worker_count() -> usize {
+    std::cmp::max( num_cpus::get_physical() / 2, 1 )
+}
+
+impl WorkerPool {
+    pub fn new( worker_count: usize, maximum_queued: usize ) -> Self {
+        let (tx, rx) = crossbeam_channel::bounded( maximum_queued );
+        let (low_priority_tx, low_priority_rx) = crossbeam_channel::unbounded();
+        WorkerPool {
+            tx,
+            low_priority_tx,
+            worker_count: std::cmp::max( worker_count, 1 ),
+            started_workers: Mutex::new( 0 ),
+            shared: Arc::new( Shared {
+                rx,
+                low_priority_rx,
+                stats: Default::default(),
+                released_workers: AtomicUsize::new( 0 ),
+                next_worker_index: AtomicUsize::new( 0 )
+            })
+        }
+    }
+
+    pub fn worker_count( &self ) -> usize {
+        self.worker_count
+    }
+
+    /// Queues the job for execution, or returns an error if too many jobs are already waiting.
+    pub fn spawn< F: FnOnce() + Send + 'static >( &self, job: F ) -> Result< (), QueueFull > {
+        self.start_workers();
+        match self.tx.try_send( (Instant::now(), Box::new( job )) ) {
+            Ok( () ) => Ok(()),
+            Err( TrySendError::Full( _ ) ) | Err( TrySendError::Disconnected( _ ) ) => {
+                self.shared.stats.rejected.fetch_add( 1, Ordering::Relaxed );
+                Err( QueueFull )
+            }
+        }
+    }
+
+    /// Queues a background job which is only picked up when there are no requests waiting.
+    ///
+    /// At most one such job runs at a time, so the other workers stay available for the requests.
+    pub fn spawn_low_priority< F: FnOnce() + Send + 'static >( &self, job: F ) {
+        self.start_workers();
+        let _ = self.low_priority_tx.send( Box::new( job ) );
+    }
+
+    /// The workers are only started once they're actually needed.
+    fn start_workers( &self ) {
+        let mut started_workers = self.started_workers.lock();
+        while *started_workers < self.worker_count {
+            start_worker( &self.shared );
+            *started_workers += 1;
+        }
+    }
+
+    pub fn queued_count( &self ) -> usize {
+        self.shared.rx.len()
+    }
+
+    /// Returns the number of jobs which were picked up by the workers so far
+    /// and how long, in total, they had to wait in the queue.
+    pub fn queue_wait( &self ) -> (u64, f64) {
+        let processed = self.shared.stats.processed.load( Ordering::Relaxed );
+        let wait = self.shared.stats.queue_wait_micros.load( Ordering::Relaxed ) as f64 / 1_000_000.0;
+        (processed, wait)
+    }
+
+    pub fn rejected_count( &self ) -> u64 {
+        self.shared.stats.rejected.load( Ordering::Relaxed )
+    }
+
+    /// Returns the number of workers which were released and are still finishing their jobs.
+    pub fn released_count( &self ) -> usize {
+        self.shared.released_workers.load( Ordering::Relaxed )
+    }
+}
+
+fn start_worker( shared: &Arc< Shared > ) {
+    let shared = shared.clone();
+    let index = shared.next_worker_index.fetch_add( 1, Ordering::Relaxed );
+    thread::Builder::new().name( format!( "worker-{}", index ) ).spawn( move || {
+        CURRENT_WORKER.with( |current| *current.borrow_mut() = Some( (shared.clone(), false) ) );
+        work( &shared );
+    }).expect( "failed to spawn a worker thread" );
+}
+
+/// Called by a job which is about to wait for something other than the CPU, e.g. for a slow
+/// client to receive its response; the job's worker is replaced by a new one, and exits
+/// once the job finishes.
+///
+/// Does nothing if not called from one of the workers, or if too many workers were already released.
+pub fn release_current_worker() {
+    CURRENT_WORKER.with( |current| {
+        let mut current = current.borrow_mut();
+        let (shared, is_released) = match *current {
+            Some( (ref shared, ref mut is_released) ) if !*is_released => (shared, is_released),
+            _ => return
+        };
+
+        let released_workers = shared.released_workers.fetch_add( 1, Ordering::SeqCst );
+        if released_workers >= MAXIMUM_RELEASED_WORKERS {
+            shared.released_workers.fetch_sub( 1, Ordering::SeqCst );
+            return;
+        }
+
+        *is_released = true;
+        start_worker( shared );
+    });
+}
+
+fn is_current_worker_released() -> bool {
+    CURRENT_WORKER.with( |current| current.borrow().as_ref().map( |&(_, is_released)| is_released ).unwrap_or( false ) )
+}
+
+fn work( shared: &Shared ) {
+    let rx = &shared.rx;
+    let low_priority_rx = &shared.low_priority_rx;
+    let stats = &shared.stats;
+    while !is_current_worker_released() {
+        match rx.try_recv() {
+            Ok( (queued_at, job) ) => {
+                stats.queue_wait_micros.fetch_add( queued_at.elapsed().as_micros() as u64, Ordering::Relaxed );
+                stats.processed.fetch_add( 1, Ordering::Relaxed );
+                job();
+                continue;
+            },
+            Err( TryRecvError::Disconnected ) => return,
+            Err( TryRecvError::Empty ) => {}
+        }
+
+        let can_run_low_priority_job = !stats.is_running_low_priority_job.swap( true, Ordering::Acquire );
+        if can_run_low_priority_job {
+            let job = low_priority_rx.try_recv().ok();
+            let found_job = job.is_some();
+            if let Some( job ) = job {
+                job();
+            }
+
+            stats.is_running_low_priority_job.store( false, Ordering::Release );
+            if found_job {
+                continue;
+            }
+        }
+
+        // Wait until there's something new to pick up; it might end up being picked up
+        // by another worker, but in that case we'll just end up waiting again.
+        //
+        // The worker which is running the current low priority job picks up the next one.
+        let mut select = Select::new();
+        select.recv( rx );
+        if can_run_low_priority_job {
+            select.recv( low_priority_rx );
+        }
+        select.ready();
+    }
+
+    shared.released_workers.fetch_sub( 1, Ordering::SeqCst );
+}
+
+#[test]
+fn test_worker_pool() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let pool = WorkerPool::new( 1, 1 );
+    let (blocker_tx, blocker_rx) = mpsc::channel::< () >();
+    let (done_tx, done_rx) = mpsc::channel();
+
+    // Keep the only worker busy.
+    let (started_tx, started_rx) = mpsc::channel();
+    assert!( pool.spawn( move || {
+        started_tx.send( () ).unwrap();
+        let _ = blocker_rx.recv();
+    }).is_ok() );
+    started_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap();
+
+    let done_tx_clone = done_tx.clone();
+    assert!( pool.spawn( move || done_tx_clone.send( 1 ).unwrap() ).is_ok() );
+    assert_eq!( pool.queued_count(), 1 );
+    assert!( pool.spawn( move || done_tx.send( 2 ).unwrap() ).is_err() );
+    assert_eq!( pool.rejected_count(), 1 );
+
+    drop( blocker_tx );
+    assert_eq!( done_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap(), 1 );
+    assert!( done_rx.recv_timeout( Duration::from_millis( 100 ) ).is_err() );
+    assert_eq!( pool.queue_wait().0, 2 );
+}
+
+#[test]
+fn test_worker_pool_low_priority_jobs() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let pool = WorkerPool::new( 2, 4 );
+    let running = Arc::new( AtomicUsize::new( 0 ) );
+    let maximum_running = Arc::new( AtomicUsize::new( 0 ) );
+    let (done_tx, done_rx) = mpsc::channel();
+    for index in 0..3 {
+        let running = running.clone();
+        let maximum_running = maximum_running.clone();
+        let done_tx = done_tx.clone();
+        pool.spawn_low_priority( move || {
+            let count = running.fetch_add( 1, Ordering::SeqCst ) + 1;
+            maximum_running.fetch_max( count, Ordering::SeqCst );
+            thread::sleep( Duration::from_millis( 100 ) );
+            running.fetch_sub( 1, Ordering::SeqCst );
+            done_tx.send( index ).unwrap();
+        });
+    }
+
+    // The other worker is still free to process the requests.
+    assert!( pool.spawn( move || done_tx.send( 100 ).unwrap() ).is_ok() );
+    assert_eq!( done_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap(), 100 );
+
+    let mut finished: Vec< _ > = (0..3).map( |_| done_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap() ).collect();
+    finished.sort();
+    assert_eq!( finished, vec![ 0, 1, 2 ] );
+    assert_eq!( maximum_running.load( Ordering::SeqCst ), 1 );
+}
+
+#[test]
+fn test_worker_pool_release_current_worker() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let pool = WorkerPool::new( 1, 4 );
+    let (blocker_tx, blocker_rx) = mpsc::channel::< () >();
+    let (done_tx, done_rx) = mpsc::channel();
+
+    // Doesn't do anything outside of the pool.
+    release_current_worker();
+
+    let done_tx_clone = done_tx.clone();
+    assert!( pool.spawn( move || {
+        release_current_worker();
+        release_current_worker();
+        let _ = blocker_rx.recv();
+        done_tx_clone.send( 1 ).unwrap();
+    }).is_ok() );
+
+    // The only worker is busy, but it was replaced with a new one.
+    assert!( pool.spawn( move || done_tx.send( 2 ).unwrap() ).is_ok() );
+    assert_eq!( done_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap(), 2 );
+    assert_eq!( pool.released_count(), 1 );
+
+    drop( blocker_tx );
+    assert_eq!( done_rx.recv_timeout( Duration::from_secs( 5 ) ).unwrap(), 1 );
+    for _ in 0..100 {
+        if pool.released_count() == 0 {
+            break;
+        }
+
+        thread::sleep( Duration::from_millis( 10 ) );
+    }
+    assert_eq!( pool.released_count(), 0 );
+}
@@ -694,13 +694,15 @@ class Control extends React.Component {
             data_url.search = "?" + create_query( q ).toString();
             fullDataUrl = data_url.toString();
 
-            data_url.pathname = "/data/" + this.props.id + "/export/heaptrack/heaptrack.dat";
+            // Keep whatever base path the server is running under.
+            const base_path = data_url.pathname.replace( /\/data\/.*$/, "" );
+            data_url.pathname = base_path + "/data/" + this.props.id + "/export/heaptrack/heaptrack.dat";
             heaptrackUrl = data_url.toString();
 
-            data_url.pathname = "/data/" + this.props.id + "/allocation_ascii_tree";
+            data_url.pathname = base_path + "/data/" + this.props.id + "/allocation_ascii_tree";
             treeUrl = data_url.toString();
 
-            data_url.pathname = "/data/" + this.props.id + "/export/flamegraph/flame.svg";
+            data_url.pathname = base_path + "/data/" + this.props.id + "/export/flamegraph/flame.svg";
             flamegraphUrl = data_url.toString();
         }
 
@@ -1309,12 +1311,12 @@ export default class PageDataAllocations extends React.Component {
 
                         const q = _.omit( extract_query( this.props.location.search ), "count", "skip", "group_allocations", "sort_by", "order" );
                         q.backtraces = row.original.backtrace_id;
-                        const url = "/#" + this.props.location.pathname + "?" + create_query( q ).toString();
+                        const url = "#" + this.props.location.pathname + "?" + create_query( q ).toString();
 
                         let graph = "";
                         if( row.original.only_matched && row.original.only_matched.graph_url ) {
-                            const url_preview = (this.props.sourceUrl || "") + row.original.only_matched.graph_preview_url;
-                            const url_full = (this.props.sourceUrl || "") + row.original.only_matched.graph_url;
+                            const url_preview = new URL( row.original.only_matched.graph_preview_url, this.props.sourceUrl || window.location.origin ).toString();
+                            const url_full = new URL( row.original.only_matched.graph_url, this.props.sourceUrl || window.location.origin ).toString();
                             graph = (
                                 <a href={url_full} target="_blank">
                                     <img src={url_preview} style={{maxHeight: "15rem"}} />
@@ -1365,7 +1367,14 @@ export default class PageDataAllocations extends React.Component {
         fetch( data_url )
             .then( response => {
                 if( response.status !== 200 ) {
-                    return response.text().then( error => Promise.reject( error ) );
+                    return response.text().then( text => {
+                        let error = text;
+                        try {
+                            error = JSON.parse( text ).error.message;
+                        } catch( _ ) {}
+
+                        return Promise.reject( error );
+                    });
                 }
 
                 return response.json();
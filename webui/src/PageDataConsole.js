@@ -5,6 +5,63 @@ import { Button } from "reactstrap";
 import { Link } from "react-router-dom";
 import Feather from "./Feather.js";
 
+class ScriptTable extends React.Component {
+    state = {
+        sortBy: null,
+        ascending: true
+    }
+
+    sortBy( index ) {
+        if( this.state.sortBy === index ) {
+            this.setState({ ascending: !this.state.ascending });
+        } else {
+            this.setState({ sortBy: index, ascending: true });
+        }
+    }
+
+    render() {
+        const table = this.props.table;
+        let rows = table.rows;
+        if( this.state.sortBy !== null ) {
+            rows = _.sortBy( rows, row => row[ this.state.sortBy ] );
+            if( !this.state.ascending ) {
+                rows = _.reverse( rows );
+            }
+        }
+
+        const header = table.columns.map( (column, index) => {
+            let arrow = "";
+            if( this.state.sortBy === index ) {
+                arrow = this.state.ascending ? " \u25B2" : " \u25BC";
+            }
+
+            return (
+                <th key={index} onClick={() => this.sortBy( index )} style={{cursor: "pointer"}}>
+                    {column.name}{arrow}
+                </th>
+            );
+        });
+
+        return (
+            <div className="script-table">
+                <table className="table table-sm table-striped">
+                    <thead>
+                        <tr>{header}</tr>
+                    </thead>
+                    <tbody>
+                        {rows.map( (row, row_index) => (
+                            <tr key={row_index}>
+                                {row.map( (cell, cell_index) => <td key={cell_index}>{cell}</td> )}
+                            </tr>
+                        ))}
+                    </tbody>
+                </table>
+                {table.truncated ? <div className="message">The table was truncated to {table.rows.length} rows.</div> : ""}
+            </div>
+        );
+    }
+}
+
 export default class PageDataConsole extends React.Component {
     state = {
         code: "graph()\n  .add(\"Leaked\", allocations().only_leaked())\n  .add(\"All\", allocations())\n  .save();",
@@ -46,7 +103,7 @@ export default class PageDataConsole extends React.Component {
                         </div>
                     );
                 } else if( entry.kind === "image" ) {
-                    const url = (this.props.sourceUrl || "") + entry.url;
+                    const url = new URL( entry.url, this.props.sourceUrl || window.location.origin ).toString();
                     const key = "file-" + entry.checksum;
                     list.push(
                         <div key={key} className="script-file">
@@ -58,6 +115,19 @@ export default class PageDataConsole extends React.Component {
                             </a>
                         </div>
                     );
+                } else if( entry.kind === "file" ) {
+                    const url = new URL( entry.url, this.props.sourceUrl || window.location.origin ).toString();
+                    const key = "file-" + entry.checksum;
+                    list.push(
+                        <div key={key} className="script-file">
+                            <a href={url} download={entry.basename}>
+                                <Feather name="download" /> {entry.path}
+                            </a> ({entry.size} bytes)
+                        </div>
+                    );
+                } else if( entry.kind === "table" ) {
+                    const key = "table-" + counter;
+                    list.push( <ScriptTable key={key} table={entry} /> );
                 }
             }
 
@@ -11,12 +11,20 @@ export default class PageDataList extends React.Component {
         this.updateDatasetList();
     }
 
+    componentWillUnmount() {
+        clearTimeout( this.refreshTimer );
+    }
+
     render() {
         const columns = [
             {
                 id: "timestamp",
                 Header: "Timestamp",
                 Cell: cell => {
+                    if( !cell.original.timestamp ) {
+                        return "";
+                    }
+
                     return fmt_date_unix( cell.original.timestamp.secs );
                 },
                 maxWidth: 200
@@ -29,6 +37,10 @@ export default class PageDataList extends React.Component {
                 id: "runtime",
                 Header: "Runtime",
                 Cell: cell => {
+                    if( !cell.original.runtime ) {
+                        return "";
+                    }
+
                     return fmt_uptime( cell.original.runtime.secs );
                 },
                 maxWidth: 150
@@ -36,6 +48,10 @@ export default class PageDataList extends React.Component {
             {
                 Header: "Allocated",
                 Cell: cell => {
+                    if( cell.value === undefined ) {
+                        return "";
+                    }
+
                     return fmt_size( cell.value ) + "B";
                 },
                 accessor: "final_allocated",
@@ -44,6 +60,10 @@ export default class PageDataList extends React.Component {
             {
                 Header: "Allocated count",
                 Cell: cell => {
+                    if( cell.value === undefined ) {
+                        return "";
+                    }
+
                     return fmt_size( cell.value );
                 },
                 accessor: "final_allocated_count",
@@ -57,6 +77,12 @@ export default class PageDataList extends React.Component {
             {
                 Header: "...",
                 Cell: row => {
+                    if( row.original.state === "loading" ) {
+                        return "Loading... (" + Math.floor( row.original.progress * 100 ) + "%)";
+                    } else if( row.original.state === "failed" ) {
+                        return <span title={row.original.error}>Failed to load</span>;
+                    }
+
                     return (
                         <Link to={"/overview/" + row.original.id}>Open</Link>
                     );
@@ -88,7 +114,8 @@ export default class PageDataList extends React.Component {
     preprocess( data ) {
         return data.map( in_row => {
             let row = {...in_row};
-            row.executable = row.executable.match( /[^/]+$/ )[ 0 ]
+            const executable = row.executable || row.path;
+            row.executable = executable.match( /[^/]+$/ )[ 0 ]
             return row;
         });
     }
@@ -96,6 +123,13 @@ export default class PageDataList extends React.Component {
     updateDatasetList() {
         fetch( this.props.sourceUrl + "/list" )
             .then( response => response.json() )
-            .then( list => this.setState( { datasets: list } ) );
+            .then( list => {
+                this.setState( { datasets: list } );
+
+                // Keep refreshing the list until everything's loaded.
+                if( list.some( entry => entry.state === "loading" ) ) {
+                    this.refreshTimer = setTimeout( () => this.updateDatasetList(), 1000 );
+                }
+            });
     }
 }
@@ -19,7 +19,9 @@ let sourceUrl;
 if( module.hot ) {
     sourceUrl = "http://localhost:8080";
 } else {
-    sourceUrl = window.location.origin;
+    // The server might be running under a base path, e.g. behind a reverse proxy.
+    const base_path = window.location.pathname.replace( /\/(index\.html)?$/, "" );
+    sourceUrl = window.location.origin + base_path;
 }
 
 ReactDOM.render(